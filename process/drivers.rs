@@ -8,12 +8,18 @@
 
 use std::{
     borrow::Borrow,
+    collections::HashSet,
     fmt::Debug,
+    num::NonZeroUsize,
     process::{ExitStatus, Output},
-    sync::{Mutex, RwLock},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        LazyLock, Mutex, RwLock,
+    },
     time::Duration,
 };
 
+use blue_build_utils::{constants::BB_CONTAINERIZED, os_family::OsFamily};
 use bon::{bon, Builder};
 use cached::proc_macro::cached;
 use clap::Args;
@@ -22,80 +28,173 @@ use indicatif::{ProgressBar, ProgressStyle};
 use log::{info, trace, warn};
 use miette::{miette, IntoDiagnostic, Result};
 use oci_distribution::Reference;
-use once_cell::sync::Lazy;
 use opts::{
-    BuildOpts, BuildTagPushOpts, CheckKeyPairOpts, GenerateImageNameOpts, GenerateKeyPairOpts,
-    GenerateTagsOpts, GetMetadataOpts, PushOpts, RunOpts, SignOpts, TagOpts, VerifyOpts,
+    BuildContext, BuildOpts, BuildStageOpts, BuildTagPushOpts, CheckKeyPairOpts,
+    GenerateImageNameOpts, GenerateKeyPairOpts, GenerateTagsOpts, GetMetadataOpts, PushOpts,
+    RunOpts, SignOpts, TagOpts, VerifyOpts,
 };
 use types::{
-    BuildDriverType, CiDriverType, DetermineDriver, ImageMetadata, InspectDriverType, Platform,
-    RunDriverType, SigningDriverType,
+    BuildDriverType, CiDriverType, DetermineDriver, DriverSelector, DriverValueParser,
+    ImageMetadata, InspectDriverType, Platform, RunDriverType, SigningDriverType,
 };
 use uuid::Uuid;
 
-use crate::logging::Logger;
+use crate::{logging::Logger, summary::BuildSummary};
+
+use self::external_driver::ExternalDriverKind;
 
 pub use self::{
     buildah_driver::BuildahDriver, cosign_driver::CosignDriver, docker_driver::DockerDriver,
-    github_driver::GithubDriver, gitlab_driver::GitlabDriver, local_driver::LocalDriver,
-    podman_driver::PodmanDriver, skopeo_driver::SkopeoDriver, traits::*,
+    external_driver::ExternalDriver, github_driver::GithubDriver, gitlab_driver::GitlabDriver,
+    local_driver::LocalDriver, oci_client_driver::OciClientDriver, podman_driver::PodmanDriver,
+    skopeo_driver::SkopeoDriver, traits::*,
 };
+#[cfg(feature = "test")]
+pub use mock_driver::{MockCall, MockDriver, MockResults};
 #[cfg(feature = "sigstore")]
 pub use sigstore_driver::SigstoreDriver;
 
 mod buildah_driver;
+mod containerized;
 mod cosign_driver;
 mod docker_driver;
+pub mod dyn_traits;
+mod external_driver;
 mod functions;
 mod github_driver;
 mod gitlab_driver;
+#[cfg(feature = "rechunk")]
+mod layer_merge;
 mod local_driver;
+mod local_inspect;
+#[cfg(feature = "test")]
+mod mock_driver;
+mod oci_client_driver;
 pub mod opts;
+mod os_release;
 mod podman_driver;
 #[cfg(feature = "sigstore")]
 mod sigstore_driver;
 mod skopeo_driver;
+mod tool_paths;
+mod tools;
 mod traits;
 pub mod types;
-
-static INIT: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
-static SELECTED_BUILD_DRIVER: Lazy<RwLock<Option<BuildDriverType>>> =
-    Lazy::new(|| RwLock::new(None));
-static SELECTED_INSPECT_DRIVER: Lazy<RwLock<Option<InspectDriverType>>> =
-    Lazy::new(|| RwLock::new(None));
-static SELECTED_RUN_DRIVER: Lazy<RwLock<Option<RunDriverType>>> = Lazy::new(|| RwLock::new(None));
-static SELECTED_SIGNING_DRIVER: Lazy<RwLock<Option<SigningDriverType>>> =
-    Lazy::new(|| RwLock::new(None));
-static SELECTED_CI_DRIVER: Lazy<RwLock<Option<CiDriverType>>> = Lazy::new(|| RwLock::new(None));
+mod version_cache;
+
+static INIT: LazyLock<Mutex<bool>> = LazyLock::new(|| Mutex::new(false));
+static SELECTED_BUILD_DRIVER: LazyLock<RwLock<Option<BuildDriverType>>> =
+    LazyLock::new(|| RwLock::new(None));
+static SELECTED_INSPECT_DRIVER: LazyLock<RwLock<Option<InspectDriverType>>> =
+    LazyLock::new(|| RwLock::new(None));
+/// The inspect drivers that failed to inspect an image in a registry.
+static INSPECT_FAILURES: LazyLock<Mutex<HashSet<(InspectDriverType, String)>>> =
+    LazyLock::new(|| Mutex::new(HashSet::new()));
+static SELECTED_RUN_DRIVER: LazyLock<RwLock<Option<RunDriverType>>> =
+    LazyLock::new(|| RwLock::new(None));
+static SELECTED_SIGNING_DRIVER: LazyLock<RwLock<Option<SigningDriverType>>> =
+    LazyLock::new(|| RwLock::new(None));
+static SELECTED_CI_DRIVER: LazyLock<RwLock<Option<CiDriverType>>> =
+    LazyLock::new(|| RwLock::new(None));
+/// Whether images are only inspected in local storage.
+static OFFLINE: AtomicBool = AtomicBool::new(false);
+/// Whether privileged helper images may run without being verified.
+static ALLOW_UNVERIFIED_TOOLS: AtomicBool = AtomicBool::new(false);
+/// Whether this process is running in a container.
+static CONTAINERIZED: AtomicBool = AtomicBool::new(false);
 
 /// UUID used to mark the current builds
-static BUILD_ID: Lazy<Uuid> = Lazy::new(Uuid::new_v4);
+static BUILD_ID: LazyLock<Uuid> = LazyLock::new(Uuid::new_v4);
 
 /// Args for selecting the various drivers to use for runtime.
 ///
 /// If the args are left uninitialized, the program will determine
 /// the best one available.
-#[derive(Default, Clone, Copy, Debug, Builder, Args)]
+#[allow(clippy::struct_field_names)]
+#[derive(Default, Clone, Debug, Builder, Args)]
 pub struct DriverArgs {
     /// Select which driver to use to build
-    /// your image.
-    #[arg(short = 'B', long)]
-    build_driver: Option<BuildDriverType>,
+    /// your image, or `external:<name>` to
+    /// run `bb-driver-<name>`.
+    #[arg(short = 'B', long, value_parser = DriverValueParser::<BuildDriverType>::new())]
+    #[builder(into)]
+    build_driver: Option<DriverSelector<BuildDriverType>>,
 
     /// Select which driver to use to inspect
-    /// images.
-    #[arg(short = 'I', long)]
-    inspect_driver: Option<InspectDriverType>,
+    /// images, or `external:<name>` to
+    /// run `bb-driver-<name>`.
+    #[arg(short = 'I', long, value_parser = DriverValueParser::<InspectDriverType>::new())]
+    #[builder(into)]
+    inspect_driver: Option<DriverSelector<InspectDriverType>>,
 
     /// Select which driver to use to sign
-    /// images.
-    #[arg(short = 'S', long)]
-    signing_driver: Option<SigningDriverType>,
+    /// images, or `external:<name>` to
+    /// run `bb-driver-<name>`.
+    #[arg(short = 'S', long, value_parser = DriverValueParser::<SigningDriverType>::new())]
+    #[builder(into)]
+    signing_driver: Option<DriverSelector<SigningDriverType>>,
 
     /// Select which driver to use to run
     /// containers.
     #[arg(short = 'R', long)]
     run_driver: Option<RunDriverType>,
+
+    /// The CI driver, which is detected
+    /// from the environment when it isn't set.
+    #[arg(skip)]
+    ci_driver: Option<CiDriverType>,
+
+    /// Whether bluebuild is running in a container,
+    /// like in CI. This is detected by default, and
+    /// containers outside of CI, like toolbox, aren't
+    /// counted unless this is set.
+    ///
+    /// In a container, podman and buildah use the `vfs` storage
+    /// driver when fuse-overlayfs isn't available and `chroot`
+    /// isolation, unless `STORAGE_DRIVER` or `BUILDAH_ISOLATION`
+    /// are set. Docker is only selected if a daemon can be reached,
+    /// and a mounted podman socket is used for `DOCKER_HOST`.
+    #[arg(
+        long,
+        env = BB_CONTAINERIZED,
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true",
+        value_name = "BOOL"
+    )]
+    containerized: Option<bool>,
+}
+
+impl DriverArgs {
+    #[must_use]
+    pub const fn build_driver(&self) -> Option<&DriverSelector<BuildDriverType>> {
+        self.build_driver.as_ref()
+    }
+
+    #[must_use]
+    pub const fn inspect_driver(&self) -> Option<&DriverSelector<InspectDriverType>> {
+        self.inspect_driver.as_ref()
+    }
+
+    #[must_use]
+    pub const fn signing_driver(&self) -> Option<&DriverSelector<SigningDriverType>> {
+        self.signing_driver.as_ref()
+    }
+
+    #[must_use]
+    pub const fn run_driver(&self) -> Option<RunDriverType> {
+        self.run_driver
+    }
+
+    #[must_use]
+    pub const fn ci_driver(&self) -> Option<CiDriverType> {
+        self.ci_driver
+    }
+
+    #[must_use]
+    pub const fn containerized(&self) -> Option<bool> {
+        self.containerized
+    }
 }
 
 macro_rules! impl_driver_type {
@@ -118,17 +217,6 @@ macro_rules! impl_driver_init {
             }
         }
     };
-    (@ default => $cache:ident; $($tail:tt)*) => {
-        {
-            let mut driver = $cache.write().expect("Should lock");
-
-            impl_driver_init!(@ $($tail)*);
-
-            *driver = Some(driver.determine_driver());
-            ::log::trace!("Driver set {driver:?}");
-            drop(driver);
-        }
-    };
     (@ $driver:expr => $cache:ident; $($tail:tt)*) => {
         {
             let mut driver = $cache.write().expect("Should lock");
@@ -157,16 +245,87 @@ impl Driver {
     pub fn init(mut args: DriverArgs) {
         trace!("Driver::init()");
 
+        let mut build_driver = args
+            .build_driver
+            .or_else(|| {
+                ExternalDriver::name_from_env(ExternalDriverKind::Build)
+                    .map(DriverSelector::External)
+            })
+            .map(DriverSelector::select);
+        let mut inspect_driver = args
+            .inspect_driver
+            .or_else(|| {
+                ExternalDriver::name_from_env(ExternalDriverKind::Inspect)
+                    .map(DriverSelector::External)
+            })
+            .map(DriverSelector::select);
+        let mut signing_driver = args
+            .signing_driver
+            .or_else(|| {
+                ExternalDriver::name_from_env(ExternalDriverKind::Signing)
+                    .map(DriverSelector::External)
+            })
+            .map(DriverSelector::select);
+
+        let containerized = args.containerized.unwrap_or_else(|| {
+            containerized::detect(args.ci_driver.unwrap_or_else(CiDriverType::detect))
+        });
+        CONTAINERIZED.store(containerized, Ordering::Relaxed);
+        if containerized {
+            containerized::configure();
+        }
+
         impl_driver_init! {
             INIT;
-            args.build_driver => SELECTED_BUILD_DRIVER;
-            args.inspect_driver => SELECTED_INSPECT_DRIVER;
+            build_driver => SELECTED_BUILD_DRIVER;
+            inspect_driver => SELECTED_INSPECT_DRIVER;
             args.run_driver => SELECTED_RUN_DRIVER;
-            args.signing_driver => SELECTED_SIGNING_DRIVER;
-            default => SELECTED_CI_DRIVER;
+            signing_driver => SELECTED_SIGNING_DRIVER;
+            args.ci_driver => SELECTED_CI_DRIVER;
         }
     }
 
+    /// Sets whether to stay off the network. Images are then
+    /// inspected in the local storage of the run driver
+    /// instead of in their registry.
+    pub fn set_offline(offline: bool) {
+        trace!("Driver::set_offline({offline})");
+        OFFLINE.store(offline, Ordering::Relaxed);
+    }
+
+    /// Sets the number of builders that docker spreads builds
+    /// over, so that recipes building at the same time don't
+    /// contend for one builder.
+    pub fn set_builders(builders: NonZeroUsize) {
+        trace!("Driver::set_builders({builders})");
+        DockerDriver::set_builders(builders);
+    }
+
+    /// Whether to stay off the network.
+    #[must_use]
+    pub fn is_offline() -> bool {
+        OFFLINE.load(Ordering::Relaxed)
+    }
+
+    /// Sets whether the helper images that are run with
+    /// privileges may run without a valid signature.
+    pub fn set_allow_unverified_tools(allow: bool) {
+        trace!("Driver::set_allow_unverified_tools({allow})");
+        ALLOW_UNVERIFIED_TOOLS.store(allow, Ordering::Relaxed);
+    }
+
+    /// Whether privileged helper images may run without being verified.
+    #[must_use]
+    pub fn allows_unverified_tools() -> bool {
+        ALLOW_UNVERIFIED_TOOLS.load(Ordering::Relaxed)
+    }
+
+    /// Whether this process is running in a container.
+    #[must_use]
+    pub fn is_containerized() -> bool {
+        CONTAINERIZED.load(Ordering::Relaxed)
+    }
+
     /// Gets the current build's UUID
     #[must_use]
     pub fn get_build_id() -> Uuid {
@@ -222,12 +381,59 @@ impl Driver {
         })
         .or_else(|err| {
             warn!("Unable to get version via image inspection due to error:\n{err:?}");
+            if Self::is_offline() {
+                return Err(err);
+            }
+            crate::block_on(os_release::fetch_os_version(oci_ref, platform))
+        })
+        .or_else(|err| {
+            warn!("Unable to get version from the image's layers due to error:\n{err:?}");
             get_version_run_image(oci_ref)
         })?;
         trace!("os_version: {os_version}");
         Ok(os_version)
     }
 
+    /// Retrieve the family of the OS of an image, like
+    /// Fedora or Debian, from its `os-release` file.
+    ///
+    /// Falls back to Fedora when the family can't be
+    /// found, since that's what images used to be assumed to be.
+    #[builder]
+    pub fn get_os_family(
+        /// The OCI image reference.
+        oci_ref: &Reference,
+        /// The platform of the image to read the `os-release` file of.
+        #[builder(default)]
+        platform: Platform,
+    ) -> OsFamily {
+        trace!("Driver::get_os_family({oci_ref:#?})");
+
+        #[cfg(test)]
+        {
+            let _ = (oci_ref, platform); // silence lint
+
+            if true {
+                return OsFamily::Fedora;
+            }
+        }
+
+        let os_family = if Self::is_offline() {
+            get_os_family_run_image(oci_ref)
+        } else {
+            crate::block_on(os_release::fetch_os_family(oci_ref, platform)).or_else(|err| {
+                warn!("Unable to get the OS family from the image's layers due to error:\n{err:?}");
+                get_os_family_run_image(oci_ref)
+            })
+        }
+        .unwrap_or_else(|err| {
+            warn!("Unable to get the OS family of {oci_ref}, assuming Fedora:\n{err:?}");
+            OsFamily::Fedora
+        });
+        trace!("os_family: {os_family}");
+        os_family
+    }
+
     pub fn get_build_driver() -> BuildDriverType {
         impl_driver_type!(SELECTED_BUILD_DRIVER)
     }
@@ -244,8 +450,16 @@ impl Driver {
         impl_driver_type!(SELECTED_RUN_DRIVER)
     }
 
+    /// The CI driver, which is detected from the environment
+    /// when the drivers haven't been initialized.
+    ///
+    /// # Panics
+    /// Will panic if the lock is poisoned.
     pub fn get_ci_driver() -> CiDriverType {
-        impl_driver_type!(SELECTED_CI_DRIVER)
+        // The CI driver only depends on the environment, so commands
+        // that don't initialize the drivers can still report to CI
+        let driver = *SELECTED_CI_DRIVER.read().expect("Should read");
+        driver.unwrap_or_else(CiDriverType::detect)
     }
 }
 
@@ -279,7 +493,7 @@ fn get_version_run_image(oci_ref: &Reference) -> Result<u64> {
                 "-c",
                 r#"awk -F= '/^VERSION_ID=/ {gsub(/"/, "", $2); print $2}' /usr/lib/os-release"#,
             ])
-            .pull(true)
+            .pull(!Driver::is_offline())
             .remove(true)
             .build(),
     )?;
@@ -293,12 +507,42 @@ fn get_version_run_image(oci_ref: &Reference) -> Result<u64> {
         .into_diagnostic()
 }
 
+#[cached(
+    result = true,
+    key = "String",
+    convert = r#"{ oci_ref.to_string() }"#,
+    sync_writes = true
+)]
+fn get_os_family_run_image(oci_ref: &Reference) -> Result<OsFamily> {
+    warn!("Running the image to read its os-release file...");
+
+    let output = Driver::run_output(
+        &RunOpts::builder()
+            .image(oci_ref.to_string())
+            .args(bon::vec![
+                "/bin/sh",
+                "-c",
+                "cat /usr/lib/os-release || cat /etc/os-release",
+            ])
+            .pull(!Driver::is_offline())
+            .remove(true)
+            .build(),
+    )?;
+
+    Ok(OsFamily::from_os_release(&String::from_utf8_lossy(
+        &output.stdout,
+    )))
+}
+
 macro_rules! impl_build_driver {
     ($func:ident($($args:expr),*)) => {
         match Self::get_build_driver() {
             BuildDriverType::Buildah => BuildahDriver::$func($($args,)*),
             BuildDriverType::Podman => PodmanDriver::$func($($args,)*),
             BuildDriverType::Docker => DockerDriver::$func($($args,)*),
+            BuildDriverType::External => ExternalDriver::$func($($args,)*),
+            #[cfg(feature = "test")]
+            BuildDriverType::Mock => MockDriver::$func($($args,)*),
         }
     };
 }
@@ -308,6 +552,14 @@ impl BuildDriver for Driver {
         impl_build_driver!(build(opts))
     }
 
+    fn build_stage(opts: &BuildStageOpts) -> Result<BuildContext> {
+        impl_build_driver!(build_stage(opts))
+    }
+
+    fn remove_stage(context: &BuildContext) -> Result<()> {
+        impl_build_driver!(remove_stage(context))
+    }
+
     fn tag(opts: &TagOpts) -> Result<()> {
         impl_build_driver!(tag(opts))
     }
@@ -320,6 +572,22 @@ impl BuildDriver for Driver {
         impl_build_driver!(login())
     }
 
+    fn storage_dir() -> Result<Option<std::path::PathBuf>> {
+        impl_build_driver!(storage_dir())
+    }
+
+    fn storage_transport() -> Option<&'static str> {
+        impl_build_driver!(storage_transport())
+    }
+
+    fn list_local_images() -> Result<Vec<types::LocalImage>> {
+        impl_build_driver!(list_local_images())
+    }
+
+    fn remove_local_images(images: &[String]) -> Result<()> {
+        impl_build_driver!(remove_local_images(images))
+    }
+
     #[cfg(feature = "prune")]
     fn prune(opts: &opts::PruneOpts) -> Result<()> {
         impl_build_driver!(prune(opts))
@@ -337,6 +605,10 @@ macro_rules! impl_signing_driver {
 
             #[cfg(feature = "sigstore")]
             SigningDriverType::Sigstore => SigstoreDriver::$func($($args,)*),
+
+            SigningDriverType::External => ExternalDriver::$func($($args,)*),
+            #[cfg(feature = "test")]
+            SigningDriverType::Mock => MockDriver::$func($($args,)*),
         }
     };
 }
@@ -363,27 +635,88 @@ impl SigningDriver for Driver {
     }
 }
 
-macro_rules! impl_inspect_driver {
-    ($func:ident($($args:expr),*)) => {
-        match Self::get_inspect_driver() {
-            InspectDriverType::Skopeo => SkopeoDriver::$func($($args,)*),
-            InspectDriverType::Podman => PodmanDriver::$func($($args,)*),
-            InspectDriverType::Docker => DockerDriver::$func($($args,)*),
+impl InspectDriver for Driver {
+    async fn get_metadata_async(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+        if Self::is_offline() {
+            return Self::get_local_metadata_async(opts).await;
         }
-    };
-}
 
-impl InspectDriver for Driver {
-    fn get_metadata(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
-        impl_inspect_driver!(get_metadata(opts))
+        let selected = Self::get_inspect_driver();
+        let registry = opts.image.resolve_registry().to_owned();
+        let mut last_err = None;
+
+        for driver in inspect_chain(selected, &registry) {
+            let result = match driver {
+                InspectDriverType::OciClient => OciClientDriver::get_metadata_async(opts).await,
+                InspectDriverType::Skopeo => SkopeoDriver::get_metadata_async(opts).await,
+                InspectDriverType::Podman => PodmanDriver::get_metadata_async(opts).await,
+                InspectDriverType::Docker => DockerDriver::get_metadata_async(opts).await,
+                InspectDriverType::External => ExternalDriver::get_metadata_async(opts).await,
+                #[cfg(feature = "test")]
+                InspectDriverType::Mock => MockDriver::get_metadata_async(opts).await,
+            };
+
+            match result {
+                Ok(metadata) => {
+                    if driver != selected {
+                        warn!(
+                            "Inspected {} with the {driver} inspect driver instead of {selected}",
+                            opts.image.to_string().bold()
+                        );
+                    }
+                    return Ok(metadata);
+                }
+                Err(err) => {
+                    warn!(
+                        "Failed to inspect {} with the {driver} inspect driver:\n{err:?}",
+                        opts.image.to_string().bold()
+                    );
+                    INSPECT_FAILURES
+                        .lock()
+                        .expect("Should lock")
+                        .insert((driver, registry.clone()));
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err
+            .unwrap_or_else(|| miette!("No inspect driver is available"))
+            .wrap_err(format!(
+                "Unable to inspect {} with any inspect driver",
+                opts.image
+            )))
     }
 }
 
+/// The inspect drivers to try for an image in the `registry`,
+/// starting with the `selected` one.
+///
+/// Drivers that already failed for the registry are tried last
+/// since they likely can't reach it, like when they don't have
+/// credentials for it.
+fn inspect_chain(selected: InspectDriverType, registry: &str) -> Vec<InspectDriverType> {
+    let failures = INSPECT_FAILURES.lock().expect("Should lock");
+    let mut chain = std::iter::once(selected)
+        .chain(
+            InspectDriverType::available()
+                .iter()
+                .copied()
+                .filter(|driver| *driver != selected),
+        )
+        .collect::<Vec<_>>();
+    chain.sort_by_key(|driver| failures.contains(&(*driver, registry.to_owned())));
+    trace!("inspect_chain({selected}, {registry}): {chain:?}");
+    chain
+}
+
 macro_rules! impl_run_driver {
     ($func:ident($($args:expr),*)) => {
         match Self::get_run_driver() {
             RunDriverType::Docker => DockerDriver::$func($($args,)*),
             RunDriverType::Podman => PodmanDriver::$func($($args,)*),
+            #[cfg(feature = "test")]
+            RunDriverType::Mock => MockDriver::$func($($args,)*),
         }
     };
 }
@@ -396,6 +729,15 @@ impl RunDriver for Driver {
     fn run_output(opts: &RunOpts) -> Result<Output> {
         impl_run_driver!(run_output(opts))
     }
+
+    async fn get_local_metadata_async(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+        match Self::get_run_driver() {
+            RunDriverType::Docker => DockerDriver::get_local_metadata_async(opts).await,
+            RunDriverType::Podman => PodmanDriver::get_local_metadata_async(opts).await,
+            #[cfg(feature = "test")]
+            RunDriverType::Mock => MockDriver::get_local_metadata_async(opts).await,
+        }
+    }
 }
 
 macro_rules! impl_ci_driver {
@@ -404,6 +746,8 @@ macro_rules! impl_ci_driver {
             CiDriverType::Local => LocalDriver::$func($($args,)*),
             CiDriverType::Gitlab => GitlabDriver::$func($($args,)*),
             CiDriverType::Github => GithubDriver::$func($($args,)*),
+            #[cfg(feature = "test")]
+            CiDriverType::Mock => MockDriver::$func($($args,)*),
         }
     };
 }
@@ -421,6 +765,10 @@ impl CiDriver for Driver {
         impl_ci_driver!(oidc_provider())
     }
 
+    fn signs_keylessly() -> bool {
+        impl_ci_driver!(signs_keylessly())
+    }
+
     fn generate_tags(opts: &GenerateTagsOpts) -> Result<Vec<String>> {
         impl_ci_driver!(generate_tags(opts))
     }
@@ -433,6 +781,14 @@ impl CiDriver for Driver {
         impl_ci_driver!(get_registry())
     }
 
+    fn get_commit_sha() -> Option<String> {
+        impl_ci_driver!(get_commit_sha())
+    }
+
+    fn get_branch() -> Option<String> {
+        impl_ci_driver!(get_branch())
+    }
+
     fn generate_image_name<'a, O>(opts: O) -> Result<Reference>
     where
         O: Borrow<GenerateImageNameOpts<'a>>,
@@ -443,6 +799,10 @@ impl CiDriver for Driver {
     fn default_ci_file_path() -> std::path::PathBuf {
         impl_ci_driver!(default_ci_file_path())
     }
+
+    fn report_summary(summary: &BuildSummary) -> Result<()> {
+        impl_ci_driver!(report_summary(summary))
+    }
 }
 
 #[cfg(feature = "rechunk")]
@@ -474,21 +834,45 @@ impl ContainerMountDriver for Driver {
 
 #[cfg(feature = "rechunk")]
 impl OciCopy for Driver {
-    fn copy_oci_dir(
-        oci_dir: &self::types::OciDir,
-        registry: &oci_distribution::Reference,
-    ) -> Result<()> {
-        SkopeoDriver::copy_oci_dir(oci_dir, registry)
+    fn copy_oci_dir(opts: &opts::CopyOciDirOpts) -> Result<()> {
+        SkopeoDriver::copy_oci_dir(opts)
+    }
+
+    fn copy_image(source: &str, dest: &str) -> Result<()> {
+        SkopeoDriver::copy_image(source, dest)
     }
 }
 
+#[cfg(feature = "rechunk")]
+impl LayerMergeDriver for Driver {}
+
 #[cfg(feature = "rechunk")]
 impl RechunkDriver for Driver {
     fn rechunk(opts: &opts::RechunkOpts) -> Result<Vec<String>> {
         PodmanDriver::rechunk(opts)
     }
 
+    fn hhd_rechunk(
+        _helper_image: &str,
+        _raw_image: &Reference,
+        _ostree_cache_id: &str,
+        _temp_dir_str: &str,
+        _opts: &opts::RechunkOpts<'_>,
+    ) -> Result<()> {
+        unimplemented!("Use the `rechunk` function instead");
+    }
+
+    fn build_chunked_oci(
+        _raw_image: &Reference,
+        _ostree_cache_id: &str,
+        _temp_dir_str: &str,
+        _opts: &opts::RechunkOpts<'_>,
+    ) -> Result<()> {
+        unimplemented!("Use the `rechunk` function instead");
+    }
+
     fn prune_image(
+        _rechunk_image: &str,
         _mount: &types::MountId,
         _container: &types::ContainerId,
         _raw_image: &Reference,
@@ -498,6 +882,7 @@ impl RechunkDriver for Driver {
     }
 
     fn create_ostree_commit(
+        _rechunk_image: &str,
         _mount: &types::MountId,
         _ostree_cache_id: &str,
         _container: &types::ContainerId,
@@ -508,6 +893,7 @@ impl RechunkDriver for Driver {
     }
 
     fn rechunk_image(
+        _rechunk_image: &str,
         _ostree_cache_id: &str,
         _temp_dir_str: &str,
         _current_dir: &str,
@@ -516,3 +902,91 @@ impl RechunkDriver for Driver {
         unimplemented!("Use the `rechunk` function instead");
     }
 }
+
+#[cfg(test)]
+mod test {
+    use clap::{Args, Command, FromArgMatches};
+
+    use super::{
+        external_driver::{ExternalDriver, ExternalDriverKind},
+        inspect_chain,
+        types::{BuildDriverType, DriverSelector, InspectDriverType},
+        DriverArgs, INSPECT_FAILURES,
+    };
+
+    fn parse(args: &[&str]) -> Result<DriverArgs, clap::Error> {
+        let matches = DriverArgs::augment_args(Command::new("test")).try_get_matches_from(args)?;
+        DriverArgs::from_arg_matches(&matches)
+    }
+
+    #[test]
+    fn external_driver_flags() {
+        let args = parse(&[
+            "test",
+            "--build-driver",
+            "external:buildkit",
+            "--signing-driver",
+            "external:vault",
+        ])
+        .unwrap();
+        assert_eq!(
+            args.build_driver()
+                .and_then(DriverSelector::selector)
+                .as_deref(),
+            Some("external:buildkit")
+        );
+        assert!(matches!(
+            args.build_driver()
+                .map(DriverSelector::<BuildDriverType>::driver_type),
+            Some(BuildDriverType::External)
+        ));
+        assert_eq!(
+            args.signing_driver()
+                .and_then(DriverSelector::selector)
+                .as_deref(),
+            Some("external:vault")
+        );
+        // The names are only selected when the drivers are initialized
+        assert_eq!(
+            ExternalDriver::selected_name(ExternalDriverKind::Build),
+            None
+        );
+
+        let args = parse(&["test", "--build-driver", "podman"]).unwrap();
+        assert!(matches!(
+            args.build_driver(),
+            Some(DriverSelector::Driver(BuildDriverType::Podman))
+        ));
+        assert_eq!(
+            args.build_driver()
+                .and_then(DriverSelector::selector)
+                .as_deref(),
+            Some("podman")
+        );
+
+        assert!(parse(&["test", "--build-driver", "external:"]).is_err());
+        assert!(parse(&["test", "--build-driver", "external"]).is_err());
+        assert!(parse(&["test", "--build-driver", "kaniko"]).is_err());
+    }
+
+    #[test]
+    fn failed_inspect_drivers_are_tried_last() {
+        let registry = "failed-inspect.example.com";
+
+        let chain = inspect_chain(InspectDriverType::Skopeo, registry);
+        assert_eq!(chain[0], InspectDriverType::Skopeo);
+        assert!(chain.contains(&InspectDriverType::OciClient));
+
+        INSPECT_FAILURES
+            .lock()
+            .unwrap()
+            .insert((InspectDriverType::Skopeo, registry.to_owned()));
+
+        let chain = inspect_chain(InspectDriverType::Skopeo, registry);
+        assert_eq!(chain.last(), Some(&InspectDriverType::Skopeo));
+        assert_eq!(
+            inspect_chain(InspectDriverType::Skopeo, "other.example.com")[0],
+            InspectDriverType::Skopeo
+        );
+    }
+}
@@ -6,12 +6,13 @@ use log::{debug, error, info, trace};
 use miette::{bail, miette, IntoDiagnostic, Result};
 use semver::Version;
 use serde::Deserialize;
+use uuid::Uuid;
 
 use crate::{drivers::types::Platform, logging::CommandLogging};
 
 use super::{
-    opts::{BuildOpts, PushOpts, TagOpts},
-    BuildDriver, DriverVersion,
+    opts::{BuildContext, BuildOpts, BuildStageOpts, PushOpts, TagOpts},
+    version_cache, BuildDriver, DriverVersion,
 };
 
 #[derive(Debug, Deserialize)]
@@ -35,17 +36,19 @@ impl DriverVersion for BuildahDriver {
     fn version() -> Result<Version> {
         trace!("BuildahDriver::version()");
 
-        trace!("buildah version --json");
-        let output = cmd!("buildah", "version", "--json")
-            .output()
-            .into_diagnostic()?;
+        version_cache::cached_version("buildah", || {
+            trace!("buildah version --json");
+            let output = cmd!("buildah", "version", "--json")
+                .output()
+                .into_diagnostic()?;
 
-        let version_json: BuildahVersionJson = serde_json::from_slice(&output.stdout)
-            .inspect_err(|e| error!("{e}: {}", String::from_utf8_lossy(&output.stdout)))
-            .into_diagnostic()?;
-        trace!("{version_json:#?}");
+            let version_json: BuildahVersionJson = serde_json::from_slice(&output.stdout)
+                .inspect_err(|e| error!("{e}: {}", String::from_utf8_lossy(&output.stdout)))
+                .into_diagnostic()?;
+            trace!("{version_json:#?}");
 
-        Ok(version_json.version)
+            Ok(version_json.version)
+        })
     }
 }
 
@@ -60,12 +63,22 @@ impl BuildDriver for BuildahDriver {
                 "--platform",
                 opts.platform.to_string(),
             ],
-            "--pull=true",
+            if opts.pull => "--pull=true",
+            if !opts.pull => "--pull=never",
+            if opts.host_network => "--net=host",
             format!("--layers={}", !opts.squash),
+            for secret in &opts.secrets => ["--secret", secret.arg()],
+            if let Some(target) = opts.target.as_deref() => ["--target", target],
+            for context in &opts.build_contexts => ["--build-context", context.arg()],
+            for repo in &opts.cache.from => ["--cache-from", repo],
+            if let Some(repo) = opts.cache.to.as_deref() => ["--cache-to", repo],
+            if !opts.proxy => "--http-proxy=false",
+            for arg in &opts.extra_args => arg.arg(),
             "-f",
             &*opts.containerfile,
             "-t",
             &*opts.image,
+            ".",
         );
 
         trace!("{command:?}");
@@ -81,6 +94,50 @@ impl BuildDriver for BuildahDriver {
         Ok(())
     }
 
+    fn build_stage(opts: &BuildStageOpts) -> Result<BuildContext> {
+        trace!("BuildahDriver::build_stage({opts:#?})");
+
+        let image = format!(
+            "localhost/bluebuild-stage-{}:{}",
+            opts.stage.to_lowercase(),
+            Uuid::new_v4()
+        );
+        Self::build(
+            &BuildOpts::builder()
+                .image(&image)
+                .containerfile(&*opts.containerfile)
+                .platform(opts.platform)
+                .secrets(opts.secrets.clone())
+                .target(&*opts.stage)
+                .build_contexts(opts.build_contexts.clone())
+                .cache(opts.cache.clone())
+                .proxy(opts.proxy)
+                .pull(opts.pull)
+                .extra_args(opts.extra_args.clone())
+                .build(),
+        )?;
+
+        Ok(BuildContext {
+            name: opts.stage.to_string(),
+            location: format!("container-image://{image}"),
+        })
+    }
+
+    fn remove_stage(context: &BuildContext) -> Result<()> {
+        trace!("BuildahDriver::remove_stage({context:#?})");
+
+        let image = context.location.trim_start_matches("container-image://");
+        let output = cmd!("buildah", "rmi", image).output().into_diagnostic()?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to remove the image {image}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
     fn tag(opts: &TagOpts) -> Result<()> {
         trace!("BuildahDriver::tag({opts:#?})");
 
@@ -114,6 +171,7 @@ impl BuildDriver for BuildahDriver {
                 "--compression-format={}",
                 opts.compression_type.unwrap_or_default()
             ),
+            for arg in &opts.extra_args => arg.arg(),
             &image_str,
         );
 
@@ -130,14 +188,26 @@ impl BuildDriver for BuildahDriver {
         Ok(())
     }
 
+    fn storage_dir() -> Result<Option<std::path::PathBuf>> {
+        super::functions::storage_dir("buildah", "{{.store.GraphRoot}}")
+    }
+
+    fn storage_transport() -> Option<&'static str> {
+        Some("containers-storage:")
+    }
+
+    fn remove_local_images(images: &[String]) -> Result<()> {
+        super::functions::remove_local_images("buildah", images)
+    }
+
     fn login() -> Result<()> {
         trace!("BuildahDriver::login()");
 
-        if let Some(Credentials {
+        for Credentials {
             registry,
             username,
             password,
-        }) = Credentials::get()
+        } in &Credentials::all()
         {
             let mut command = cmd!(
                 "buildah",
@@ -168,7 +238,10 @@ impl BuildDriver for BuildahDriver {
 
             if !output.status.success() {
                 let err_out = String::from_utf8_lossy(&output.stderr);
-                bail!("Failed to login for buildah:\n{}", err_out.trim());
+                bail!(
+                    "Failed to login to {registry} for buildah:\n{}",
+                    err_out.trim()
+                );
             }
             debug!("Logged into {registry}");
         }
@@ -0,0 +1,74 @@
+//! Support for running bluebuild inside of a container,
+//! like the images that are used in CI.
+//!
+//! Podman and buildah can't use their defaults in most containers,
+//! so their storage driver and isolation are set in the environment
+//! of the commands that are run unless the user already set them.
+//! Docker is only used if a daemon can be reached through a socket.
+
+use std::{env, path::Path};
+
+use blue_build_utils::{
+    constants::{BUILDAH_ISOLATION, DOCKER_HOST, STORAGE_DRIVER},
+    tools,
+};
+use log::{debug, info, trace};
+
+use crate::{drivers::types::CiDriverType, preflight};
+
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+const PODMAN_SOCKET: &str = "/run/podman/podman.sock";
+
+/// Whether this process is running in a container of a CI job.
+///
+/// Podman creates `/run/.containerenv` and docker creates
+/// `/.dockerenv`. Systemd sets `container` for nspawn and others.
+/// Containers outside of CI, like toolbox and distrobox, can use
+/// the defaults of the container runtimes, so they aren't counted.
+pub(super) fn detect(ci: CiDriverType) -> bool {
+    let in_container = Path::new("/run/.containerenv").exists()
+        || Path::new("/.dockerenv").exists()
+        || env::var_os("container").is_some_and(|container| !container.is_empty());
+    let containerized = in_container && !matches!(ci, CiDriverType::Local);
+    debug!("Running in a container: {in_container}, in CI: {containerized}");
+    containerized
+}
+
+/// Whether a docker daemon can be reached.
+pub(super) fn docker_reachable() -> bool {
+    tools::env_value(DOCKER_HOST).is_some_and(|host| !host.is_empty())
+        || Path::new(DOCKER_SOCKET).exists()
+}
+
+/// Sets up the environment of the container runtimes for
+/// running in a container. Settings that the user made
+/// in the environment are kept.
+///
+/// This must run before any commands
+/// of the container runtimes are created.
+pub(super) fn configure() {
+    trace!("containerized::configure()");
+
+    // Overlays can't be mounted on the overlay root of most
+    // containers, so vfs is the only driver that always works
+    if !preflight::has_fuse_overlayfs() {
+        set_default_env(STORAGE_DRIVER, "vfs");
+    }
+
+    // Building in a user namespace needs privileges that
+    // containers don't have, chroot works without them
+    set_default_env(BUILDAH_ISOLATION, "chroot");
+
+    // A mounted podman socket can be used in place of docker's
+    if !docker_reachable() && Path::new(PODMAN_SOCKET).exists() {
+        set_default_env(DOCKER_HOST, &format!("unix://{PODMAN_SOCKET}"));
+    }
+}
+
+fn set_default_env(key: &'static str, value: &str) {
+    if tools::set_env(key, value) {
+        info!("Running in a container, setting {key}={value}");
+    } else {
+        debug!("Keeping {key} from the environment");
+    }
+}
@@ -80,11 +80,11 @@ impl SigningDriver for CosignDriver {
     fn signing_login() -> Result<()> {
         trace!("CosignDriver::signing_login()");
 
-        if let Some(Credentials {
+        for Credentials {
             registry,
             username,
             password,
-        }) = Credentials::get()
+        } in &Credentials::all()
         {
             let mut command = cmd!(
                 "cosign",
@@ -114,7 +114,10 @@ impl SigningDriver for CosignDriver {
 
             if !output.status.success() {
                 let err_out = String::from_utf8_lossy(&output.stderr);
-                bail!("Failed to login for cosign:\n{}", err_out.trim());
+                bail!(
+                    "Failed to login to {registry} for cosign:\n{}",
+                    err_out.trim()
+                );
             }
             debug!("Logged into {registry}");
         }
@@ -161,7 +164,7 @@ impl SigningDriver for CosignDriver {
                         "--certificate-oidc-issuer",
                         issuer as &str,
                     ),
-                };
+                }
             },
             opts.image.to_string(),
         );
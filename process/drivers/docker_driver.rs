@@ -1,37 +1,42 @@
 use std::{
     env,
     io::Write,
+    num::NonZeroUsize,
     path::Path,
     process::{Command, ExitStatus, Stdio},
-    sync::Mutex,
+    sync::{LazyLock, Mutex},
 };
 
 use blue_build_utils::{
     cmd,
     constants::{BB_BUILDKIT_CACHE_GHA, CONTAINER_FILE, DOCKER_HOST, GITHUB_ACTIONS},
     credentials::Credentials,
-    string_vec,
+    string_vec, tools,
 };
 use cached::proc_macro::cached;
 use colored::Colorize;
 use log::{debug, info, trace, warn};
 use miette::{bail, miette, IntoDiagnostic, Result};
-use once_cell::sync::Lazy;
 use semver::Version;
 use serde::Deserialize;
 use tempfile::TempDir;
+use uuid::Uuid;
 
+mod builder_pool;
 mod metadata;
 
 use crate::{
     drivers::{
+        functions::with_built_stages,
         opts::{
-            BuildOpts, BuildTagPushOpts, GetMetadataOpts, PushOpts, RunOpts, RunOptsEnv,
-            RunOptsVolume, TagOpts,
+            proxy_build_args, BuildCache, BuildContext, BuildOpts, BuildStageOpts,
+            BuildTagPushOpts, GetMetadataOpts, PushOpts, RunOpts, RunOptsEnv, RunOptsVolume,
+            TagOpts,
         },
         traits::{BuildDriver, DriverVersion, InspectDriver, RunDriver},
         types::ImageMetadata,
         types::Platform,
+        version_cache,
     },
     logging::CommandLogging,
     signal_handler::{add_cid, remove_cid, ContainerRuntime, ContainerSignalId},
@@ -49,20 +54,38 @@ struct DockerVersionJson {
     pub client: DockerVerisonJsonClient,
 }
 
-static DOCKER_SETUP: Lazy<Mutex<bool>> = Lazy::new(|| Mutex::new(false));
+/// Whether the `bluebuild` builder is used, once it's been set up.
+static DOCKER_SETUP: LazyLock<Mutex<Option<bool>>> = LazyLock::new(|| Mutex::new(None));
 
 #[derive(Debug)]
 pub struct DockerDriver;
 
 impl DockerDriver {
-    fn setup() -> Result<()> {
+    /// Sets up the builder for buildx and returns
+    /// whether the `bluebuild` builder is used.
+    ///
+    /// Docker's own builder is used when docker keeps its images in
+    /// the containerd image store, since it can then build images for
+    /// any platform and load them straight into the store.
+    fn setup() -> Result<bool> {
         trace!("DockerDriver::setup()");
 
+        if tools::env_value(DOCKER_HOST).is_some_and(|dh| !dh.is_empty()) {
+            return Ok(false);
+        }
+
         let mut lock = DOCKER_SETUP.lock().expect("Should lock");
 
-        if *lock {
+        if let Some(use_builder) = *lock {
+            drop(lock);
+            return Ok(use_builder);
+        }
+
+        if Self::uses_containerd_store() {
+            debug!("Docker uses the containerd image store, building with the default builder");
+            *lock = Some(false);
             drop(lock);
-            return Ok(());
+            return Ok(false);
         }
 
         trace!("docker buildx ls --format={}", "{{.Name}}");
@@ -78,27 +101,38 @@ impl DockerDriver {
 
         trace!("{ls_out}");
 
-        if !ls_out.lines().any(|line| line == "bluebuild") {
-            trace!("docker buildx create --bootstrap --driver=docker-container --name=bluebuild");
-            let create_out = cmd!(
-                "docker",
-                "buildx",
-                "create",
-                "--bootstrap",
-                "--driver=docker-container",
-                "--name=bluebuild",
-            )
-            .output()
-            .into_diagnostic()?;
+        builder_pool::sync(ls_out.lines())?;
 
-            if !create_out.status.success() {
-                bail!("{}", String::from_utf8_lossy(&create_out.stderr));
-            }
+        *lock = Some(true);
+        drop(lock);
+        Ok(true)
+    }
+
+    /// Leases a builder of the pool for a build, or
+    /// `None` if docker's own builder is used.
+    fn lease_builder() -> Result<Option<builder_pool::BuilderLease>> {
+        if !Self::setup()? {
+            return Ok(None);
         }
+        builder_pool::lease().map(Some)
+    }
 
-        *lock = true;
-        drop(lock);
-        Ok(())
+    /// Sets the number of builders that the builds
+    /// of images and stages are spread over.
+    pub(super) fn set_builders(builders: NonZeroUsize) {
+        builder_pool::set_size(builders);
+    }
+
+    /// Whether docker keeps its images in the containerd image store.
+    fn uses_containerd_store() -> bool {
+        trace!("docker info --format={}", "{{json .DriverStatus}}");
+        cmd!("docker", "info", "--format={{json .DriverStatus}}")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .is_some_and(|output| {
+                String::from_utf8_lossy(&output.stdout).contains("io.containerd.snapshotter")
+            })
     }
 }
 
@@ -108,14 +142,16 @@ impl DriverVersion for DockerDriver {
     const VERSION_REQ: &'static str = ">=23";
 
     fn version() -> Result<Version> {
-        let output = cmd!("docker", "version", "-f", "json")
-            .output()
-            .into_diagnostic()?;
+        version_cache::cached_version("docker", || {
+            let output = cmd!("docker", "version", "-f", "json")
+                .output()
+                .into_diagnostic()?;
 
-        let version_json: DockerVersionJson =
-            serde_json::from_slice(&output.stdout).into_diagnostic()?;
+            let version_json: DockerVersionJson =
+                serde_json::from_slice(&output.stdout).into_diagnostic()?;
 
-        Ok(version_json.client.version)
+            Ok(version_json.client.version)
+        })
     }
 }
 
@@ -135,6 +171,11 @@ impl BuildDriver for DockerDriver {
                 "--platform",
                 opts.platform.to_string(),
             ],
+            for secret in &opts.secrets => ["--secret", secret.arg()],
+            if let Some(target) = opts.target.as_deref() => ["--target", target],
+            for context in &opts.build_contexts => ["--build-context", context.arg()],
+            for var in proxy_build_args(opts.proxy) => ["--build-arg", var],
+            for arg in &opts.extra_args => arg.arg(),
             "-t",
             &*opts.image,
             "-f",
@@ -152,6 +193,85 @@ impl BuildDriver for DockerDriver {
         Ok(())
     }
 
+    fn build_stage(opts: &BuildStageOpts) -> Result<BuildContext> {
+        trace!("DockerDriver::build_stage({opts:#?})");
+
+        // The bluebuild builder runs in a container and can't
+        // see local images, so the stage is exported as an OCI layout
+        let layout = opts
+            .tempdir
+            .map_or_else(env::temp_dir, Path::to_path_buf)
+            .join(format!(
+                "bluebuild-stage-{}-{}",
+                opts.stage.to_lowercase(),
+                Uuid::new_v4()
+            ));
+
+        let builder = Self::lease_builder()?;
+        let command = cmd!(
+            "docker",
+            "buildx",
+            if let Some(builder) = &builder => format!("--builder={builder}"),
+            "build",
+            if !matches!(opts.platform, Platform::Native) => [
+                "--platform",
+                opts.platform.to_string(),
+            ],
+            for secret in &opts.secrets => ["--secret", secret.arg()],
+            for context in &opts.build_contexts => ["--build-context", context.arg()],
+            for cache_args(&opts.cache),
+            for var in proxy_build_args(opts.proxy) => ["--build-arg", var],
+            for arg in &opts.extra_args => arg.arg(),
+            "--target",
+            &*opts.stage,
+            "--output",
+            format!("type=oci,dest={},tar=false", layout.display()),
+            "-f",
+            &*opts.containerfile,
+            ".",
+        );
+
+        trace!("{command:?}");
+        if !command
+            .build_status(&*opts.stage, "Building Stage")
+            .into_diagnostic()?
+            .success()
+        {
+            let _ = std::fs::remove_dir_all(&layout);
+            bail!("Failed to build stage {}", opts.stage);
+        }
+
+        // The layout isn't tagged, so it's referenced by its digest
+        let digest = std::fs::read(layout.join("index.json"))
+            .into_diagnostic()
+            .and_then(|index| serde_json::from_slice::<serde_json::Value>(&index).into_diagnostic())
+            .ok()
+            .and_then(|index| {
+                index
+                    .pointer("/manifests/0/digest")
+                    .and_then(serde_json::Value::as_str)
+                    .map(ToOwned::to_owned)
+            })
+            .ok_or_else(|| miette!("Failed to read the digest of stage {}", opts.stage))?;
+
+        Ok(BuildContext {
+            name: opts.stage.to_string(),
+            location: format!("oci-layout://{}@{digest}", layout.display()),
+        })
+    }
+
+    fn remove_stage(context: &BuildContext) -> Result<()> {
+        trace!("DockerDriver::remove_stage({context:#?})");
+
+        let layout = context
+            .location
+            .trim_start_matches("oci-layout://")
+            .split('@')
+            .next()
+            .unwrap_or_default();
+        std::fs::remove_dir_all(layout).into_diagnostic()
+    }
+
     fn tag(opts: &TagOpts) -> Result<()> {
         trace!("DockerDriver::tag({opts:#?})");
 
@@ -176,9 +296,14 @@ impl BuildDriver for DockerDriver {
         let image_str = opts.image.to_string();
 
         trace!("docker push {}", opts.image);
-        let status = cmd!("docker", "push", &image_str)
-            .status()
-            .into_diagnostic()?;
+        let status = cmd!(
+            "docker",
+            "push",
+            for arg in &opts.extra_args => arg.arg(),
+            &image_str,
+        )
+        .status()
+        .into_diagnostic()?;
 
         if status.success() {
             info!("Successfully pushed {}!", image_str.bold().green());
@@ -188,14 +313,30 @@ impl BuildDriver for DockerDriver {
         Ok(())
     }
 
+    fn storage_dir() -> Result<Option<std::path::PathBuf>> {
+        super::functions::storage_dir("docker", "{{.DockerRootDir}}")
+    }
+
+    fn storage_transport() -> Option<&'static str> {
+        Some("docker-daemon:")
+    }
+
+    fn list_local_images() -> Result<Vec<super::types::LocalImage>> {
+        super::functions::list_local_images("docker")
+    }
+
+    fn remove_local_images(images: &[String]) -> Result<()> {
+        super::functions::remove_local_images("docker", images)
+    }
+
     fn login() -> Result<()> {
         trace!("DockerDriver::login()");
 
-        if let Some(Credentials {
+        for Credentials {
             registry,
             username,
             password,
-        }) = Credentials::get()
+        } in &Credentials::all()
         {
             let mut command = cmd!(
                 "docker",
@@ -225,7 +366,10 @@ impl BuildDriver for DockerDriver {
 
             if !output.status.success() {
                 let err_out = String::from_utf8_lossy(&output.stderr);
-                bail!("Failed to login for docker:\n{}", err_out.trim());
+                bail!(
+                    "Failed to login to {registry} for docker:\n{}",
+                    err_out.trim()
+                );
             }
             debug!("Logged into {registry}");
         }
@@ -259,9 +403,8 @@ impl BuildDriver for DockerDriver {
                         "prune",
                         "--force",
                         |command|? {
-                            if !env::var(DOCKER_HOST).is_ok_and(|dh| !dh.is_empty()) {
-                                Self::setup()?;
-                                cmd!(command, "--builder=bluebuild");
+                            if Self::setup()? {
+                                cmd!(command, format!("--builder={}", builder_pool::BUILDER_NAME));
                             }
                         },
                         if opts.all => "--all",
@@ -293,31 +436,27 @@ impl BuildDriver for DockerDriver {
             warn!("Squash is deprecated for docker so this build will not squash");
         }
 
+        if opts.push && !opts.push_extra_args.is_empty() {
+            warn!("Docker pushes the image as part of the build, so the push options are ignored");
+        }
+
+        let builder = Self::lease_builder()?;
         let mut command = cmd!(
             "docker",
             "buildx",
-            |command|? {
-                if !env::var(DOCKER_HOST).is_ok_and(|dh| !dh.is_empty()) {
-                    Self::setup()?;
-                    cmd!(command, "--builder=bluebuild");
-                }
-            },
+            if let Some(builder) = &builder => format!("--builder={builder}"),
             "build",
-            "--pull",
+            if opts.pull => "--pull",
             if !matches!(opts.platform, Platform::Native) => [
                 "--platform",
                 opts.platform.to_string(),
             ],
             "-f",
             &*opts.containerfile,
-            // https://github.com/moby/buildkit?tab=readme-ov-file#github-actions-cache-experimental
-            if env::var(BB_BUILDKIT_CACHE_GHA)
-                .map_or_else(|_| false, |e| e == "true") => [
-                    "--cache-from",
-                    "type=gha",
-                    "--cache-to",
-                    "type=gha",
-                ],
+            for secret in &opts.secrets => ["--secret", secret.arg()],
+            for cache_args(&opts.cache),
+            for var in proxy_build_args(opts.proxy) => ["--build-arg", var],
+            for arg in &opts.build_extra_args => arg.arg(),
         );
 
         let final_images = match (opts.image, opts.archive_path.as_deref()) {
@@ -372,49 +511,58 @@ impl BuildDriver for DockerDriver {
         };
         let display_image = final_images.first().unwrap(); // There will always be at least one image
 
-        cmd!(command, ".");
+        with_built_stages::<Self, _>(opts, |build_contexts| {
+            cmd!(
+                command,
+                for context in build_contexts => ["--build-context", context.arg()],
+                ".",
+            );
+
+            trace!("{command:?}");
+            if !command
+                .build_status(display_image, "Building Image")
+                .into_diagnostic()?
+                .success()
+            {
+                bail!("Failed to build image {}", display_image);
+            }
 
-        trace!("{command:?}");
-        if command
-            .build_status(display_image, "Building Image")
-            .into_diagnostic()?
-            .success()
-        {
             if opts.push {
                 info!("Successfully built and pushed image {}", display_image);
             } else {
                 info!("Successfully built image {}", display_image);
             }
-        } else {
-            bail!("Failed to build image {}", display_image);
-        }
+            Ok(())
+        })?;
         Ok(final_images)
     }
 }
 
 impl InspectDriver for DockerDriver {
-    fn get_metadata(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
-        get_metadata_cache(opts)
+    async fn get_metadata_async(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+        if opts.fresh {
+            get_metadata_cache_prime_cache(opts).await
+        } else {
+            get_metadata_cache(opts).await
+        }
     }
 }
 
 #[cached(
     result = true,
     key = "String",
-    convert = r#"{ format!("{}-{}", opts.image, opts.platform)}"#,
-    sync_writes = true
+    convert = r#"{ format!("{}-{}", opts.image, opts.platform)}"#
 )]
-fn get_metadata_cache(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
+async fn get_metadata_cache(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
     trace!("DockerDriver::get_metadata({opts:#?})");
     let image_str = opts.image.to_string();
 
-    let mut command = cmd!(
+    let command = cmd!(
         "docker",
         "buildx",
         |command|? {
-            if !env::var(DOCKER_HOST).is_ok_and(|dh| !dh.is_empty()) {
-                DockerDriver::setup()?;
-                cmd!(command, "--builder=bluebuild");
+            if DockerDriver::setup()? {
+                cmd!(command, format!("--builder={}", builder_pool::BUILDER_NAME));
             }
         },
         "imagetools",
@@ -425,7 +573,10 @@ fn get_metadata_cache(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
     );
     trace!("{command:?}");
 
-    let output = command.output().into_diagnostic()?;
+    let output = tokio::process::Command::from(command)
+        .output()
+        .await
+        .into_diagnostic()?;
 
     if output.status.success() {
         info!("Successfully inspected image {}!", image_str.bold().green());
@@ -476,6 +627,26 @@ impl RunDriver for DockerDriver {
     }
 }
 
+/// The cache args for buildx. Buildx keeps the
+/// registry cache in the `cache` tag of the repos.
+fn cache_args(cache: &BuildCache) -> Vec<String> {
+    let mut args = Vec::new();
+
+    // https://github.com/moby/buildkit?tab=readme-ov-file#github-actions-cache-experimental
+    if env::var(BB_BUILDKIT_CACHE_GHA).is_ok_and(|e| e == "true") {
+        args.extend(["--cache-from", "type=gha", "--cache-to", "type=gha"].map(ToOwned::to_owned));
+    }
+    for repo in &cache.from {
+        args.push("--cache-from".to_owned());
+        args.push(format!("type=registry,ref={repo}:cache"));
+    }
+    if let Some(repo) = &cache.to {
+        args.push("--cache-to".to_owned());
+        args.push(format!("type=registry,ref={repo}:cache,mode=max"));
+    }
+    args
+}
+
 fn docker_run(opts: &RunOpts, cid_file: &Path) -> Command {
     let command = cmd!(
         "docker",
@@ -486,6 +657,10 @@ fn docker_run(opts: &RunOpts, cid_file: &Path) -> Command {
         if opts.remove => "--rm",
         if opts.pull => "--pull=always",
         if let Some(user) = opts.user.as_ref() => format!("--user={user}"),
+        if let Some(workdir) = opts.workdir.as_ref() => format!("--workdir={workdir}"),
+        for port in &opts.ports => ["--publish", port.arg()],
+        for device in &opts.devices => ["--device", &**device],
+        for mount in &opts.mounts => ["--mount", mount.arg()],
         for RunOptsVolume { path_or_vol_name, container_path } in opts.volumes.iter() => [
             "--volume",
             format!("{path_or_vol_name}:{container_path}"),
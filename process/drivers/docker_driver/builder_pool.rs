@@ -0,0 +1,317 @@
+//! A pool of buildx builders, so that recipes that build at the
+//! same time don't all contend for the one `bluebuild` builder.
+//!
+//! The first builder is `bluebuild` and the rest are `bluebuild-<n>`.
+//! Builders are created the first time they're leased and are kept
+//! between runs so that their layer caches can be reused.
+//!
+//! A leased builder has a shared lock on its lock file in the runtime
+//! dir, so a run with a smaller pool only removes the builders that
+//! no other run is using.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Display,
+    fs::{self, File, OpenOptions},
+    num::NonZeroUsize,
+    os::unix::fs::OpenOptionsExt,
+    path::Path,
+    sync::{LazyLock, Mutex},
+};
+
+use blue_build_utils::cmd;
+use log::{debug, trace, warn};
+use miette::{bail, IntoDiagnostic, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg, OFlag},
+};
+
+/// The name of the first builder of the pool.
+pub const BUILDER_NAME: &str = "bluebuild";
+
+const LOCKS_DIR: &str = "builders";
+
+static POOL: LazyLock<Mutex<BuilderPool>> = LazyLock::new(|| Mutex::new(BuilderPool::default()));
+
+/// Sets the number of builders in the pool.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn set_size(size: NonZeroUsize) {
+    trace!("builder_pool::set_size({size})");
+    POOL.lock().expect("Should lock POOL").size = size;
+}
+
+/// Records the builders that exist, creates the first builder if
+/// it doesn't exist, and removes the builders of the pool that are
+/// beyond its size, which were left idle by earlier runs with more
+/// builders. Builders that are leased by another run are kept.
+///
+/// # Errors
+/// Will error if the first builder can't be created.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn sync<'a>(existing: impl IntoIterator<Item = &'a str>) -> Result<()> {
+    let mut pool = POOL.lock().expect("Should lock POOL");
+
+    for name in existing {
+        let Some(index) = builder_index(name) else {
+            continue;
+        };
+
+        if index < pool.size.get() {
+            pool.created.insert(index);
+        } else {
+            let Some(_lock) = lock(name, FlockArg::LockExclusiveNonblock) else {
+                debug!("Keeping the builder {name}, which may be in use");
+                continue;
+            };
+            debug!("Removing the idle builder {name}");
+            match cmd!("docker", "buildx", "rm", name).output() {
+                Ok(output) if output.status.success() => {}
+                Ok(output) => warn!(
+                    "Failed to remove the builder {name}: {}",
+                    String::from_utf8_lossy(&output.stderr).trim()
+                ),
+                Err(e) => warn!("Failed to remove the builder {name}: {e}"),
+            }
+        }
+    }
+
+    if pool.created.insert(0) {
+        if let Err(e) = create(BUILDER_NAME) {
+            pool.created.remove(&0);
+            return Err(e);
+        }
+    }
+    drop(pool);
+    Ok(())
+}
+
+/// Leases a builder, creating it if needed. The builder is
+/// returned to the pool when the lease is dropped.
+///
+/// # Errors
+/// Will error if the builder can't be created.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn lease() -> Result<BuilderLease> {
+    let mut pool = POOL.lock().expect("Should lock POOL");
+    let index = pool.pick();
+
+    // The lock is held while creating so that the
+    // builder isn't created twice
+    if pool.created.insert(index) {
+        if let Err(e) = create(&builder_name(index)) {
+            pool.created.remove(&index);
+            pool.release(index);
+            return Err(e);
+        }
+    }
+    drop(pool);
+
+    let name = builder_name(index);
+    let lock = lock(&name, FlockArg::LockSharedNonblock);
+    if lock.is_none() {
+        warn!("Failed to lock the builder {name}, another run may remove it while it's used");
+    }
+
+    let lease = BuilderLease { index, _lock: lock };
+    trace!("Leased the builder {lease}");
+    Ok(lease)
+}
+
+/// Locks the lock file of the builder named `name`, or
+/// returns `None` if it's locked by another run or
+/// can't be locked.
+fn lock(name: &str, arg: FlockArg) -> Option<Flock<File>> {
+    let dir = blue_build_utils::runtime_dir()?.join(LOCKS_DIR);
+    lock_in(&dir, name, arg)
+}
+
+fn lock_in(dir: &Path, name: &str, arg: FlockArg) -> Option<Flock<File>> {
+    if let Err(e) = fs::create_dir_all(dir) {
+        debug!("Failed to create {}: {e}", dir.display());
+        return None;
+    }
+
+    let path = dir.join(format!("{name}.lock"));
+    let file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .custom_flags(OFlag::O_NOFOLLOW.bits())
+        .open(&path)
+        .inspect_err(|e| debug!("Failed to open {}: {e}", path.display()))
+        .ok()?;
+
+    match Flock::lock(file, arg) {
+        Ok(lock) => Some(lock),
+        Err((_, Errno::EWOULDBLOCK)) => None,
+        Err((_, e)) => {
+            debug!("Failed to lock {}: {e}", path.display());
+            None
+        }
+    }
+}
+
+fn create(name: &str) -> Result<()> {
+    trace!("docker buildx create --bootstrap --driver=docker-container --name={name}");
+    let output = cmd!(
+        "docker",
+        "buildx",
+        "create",
+        "--bootstrap",
+        "--driver=docker-container",
+        format!("--name={name}"),
+    )
+    .output()
+    .into_diagnostic()?;
+
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr));
+    }
+    Ok(())
+}
+
+fn builder_name(index: usize) -> String {
+    match index {
+        0 => BUILDER_NAME.to_owned(),
+        index => format!("{BUILDER_NAME}-{index}"),
+    }
+}
+
+/// The index of the builder of the pool named `name`.
+fn builder_index(name: &str) -> Option<usize> {
+    if name == BUILDER_NAME {
+        return Some(0);
+    }
+
+    name.strip_prefix(BUILDER_NAME)?
+        .strip_prefix('-')?
+        .parse()
+        .ok()
+        .filter(|index| *index > 0)
+}
+
+#[derive(Debug)]
+struct BuilderPool {
+    size: NonZeroUsize,
+    created: BTreeSet<usize>,
+    leases: Vec<usize>,
+    next: usize,
+}
+
+impl Default for BuilderPool {
+    fn default() -> Self {
+        Self {
+            size: NonZeroUsize::MIN,
+            created: BTreeSet::new(),
+            leases: Vec::new(),
+            next: 0,
+        }
+    }
+}
+
+impl BuilderPool {
+    /// Picks the builder with the fewest leases, going round-robin
+    /// from the builder after the one picked last.
+    fn pick(&mut self) -> usize {
+        let size = self.size.get();
+        self.leases.resize(size, 0);
+
+        let index = (0..size)
+            .map(|offset| (self.next + offset) % size)
+            .min_by_key(|index| self.leases[*index])
+            .unwrap_or_default();
+
+        self.leases[index] += 1;
+        self.next = (index + 1) % size;
+        index
+    }
+
+    fn release(&mut self, index: usize) {
+        if let Some(leases) = self.leases.get_mut(index) {
+            *leases = leases.saturating_sub(1);
+        }
+    }
+}
+
+/// A builder of the pool that is in use.
+#[derive(Debug)]
+pub struct BuilderLease {
+    index: usize,
+    _lock: Option<Flock<File>>,
+}
+
+impl Display for BuilderLease {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&builder_name(self.index))
+    }
+}
+
+impl Drop for BuilderLease {
+    fn drop(&mut self) {
+        if let Ok(mut pool) = POOL.lock() {
+            pool.release(self.index);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::num::NonZeroUsize;
+
+    use rstest::rstest;
+
+    use nix::fcntl::FlockArg;
+
+    use super::{builder_index, lock_in, BuilderPool};
+
+    #[rstest]
+    #[case("bluebuild", Some(0))]
+    #[case("bluebuild-2", Some(2))]
+    #[case("bluebuild-0", None)]
+    #[case("bluebuild-stage", None)]
+    #[case("default", None)]
+    fn index(#[case] name: &str, #[case] expected: Option<usize>) {
+        assert_eq!(builder_index(name), expected);
+    }
+
+    #[test]
+    fn leased_builders_are_kept() {
+        let dir = tempfile::TempDir::new().unwrap();
+
+        let first = lock_in(dir.path(), "bluebuild-2", FlockArg::LockSharedNonblock).unwrap();
+        let second = lock_in(dir.path(), "bluebuild-2", FlockArg::LockSharedNonblock).unwrap();
+        assert!(lock_in(dir.path(), "bluebuild-2", FlockArg::LockExclusiveNonblock).is_none());
+
+        drop(first);
+        assert!(lock_in(dir.path(), "bluebuild-2", FlockArg::LockExclusiveNonblock).is_none());
+        drop(second);
+        assert!(lock_in(dir.path(), "bluebuild-2", FlockArg::LockExclusiveNonblock).is_some());
+    }
+
+    #[test]
+    fn picks_round_robin() {
+        let mut pool = BuilderPool {
+            size: NonZeroUsize::new(3).unwrap(),
+            ..BuilderPool::default()
+        };
+
+        assert_eq!([pool.pick(), pool.pick(), pool.pick()], [0, 1, 2]);
+
+        // Every builder has a lease, so it goes around again
+        assert_eq!(pool.pick(), 0);
+
+        // Builder 0 is idle again, so it's picked before builder 1
+        pool.release(0);
+        pool.release(0);
+        assert_eq!(pool.pick(), 0);
+        assert_eq!(pool.pick(), 1);
+    }
+}
@@ -3,7 +3,9 @@ use std::collections::HashMap;
 use miette::{bail, Report};
 use serde::Deserialize;
 
-use crate::drivers::types::{ImageMetadata, Platform};
+use crate::drivers::types::{
+    ImageConfig, ImageHistory, ImageLayer, ImageMetadata, ManifestLayer, Platform,
+};
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct Metadata {
@@ -28,11 +30,26 @@ pub struct Manifest {
 
     #[serde(default)]
     manifests: Vec<PlatformManifest>,
+
+    #[serde(default)]
+    layers: Vec<ManifestLayer>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct MetadataPlatformImage {
     config: Config,
+
+    #[serde(default)]
+    history: Vec<ImageHistory>,
+
+    #[serde(default)]
+    rootfs: RootFs,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+pub struct RootFs {
+    #[serde(default)]
+    diff_ids: Vec<String>,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -46,6 +63,9 @@ pub enum MetadataImage {
 #[serde(rename_all = "PascalCase")]
 pub struct Config {
     labels: HashMap<String, serde_json::Value>,
+
+    #[serde(flatten)]
+    run: ImageConfig,
 }
 
 impl TryFrom<(Metadata, Platform)> for ImageMetadata {
@@ -56,6 +76,15 @@ impl TryFrom<(Metadata, Platform)> for ImageMetadata {
             MetadataImage::Single(image) => Ok(Self {
                 labels: image.config.labels,
                 digest: metadata.manifest.digest,
+                config: image.config.run,
+                layers: metadata
+                    .manifest
+                    .layers
+                    .into_iter()
+                    .map(ImageLayer::from)
+                    .collect(),
+                history: image.history,
+                size: None,
             }),
             MetadataImage::Multi(mut platforms) => {
                 let Some(image) = platforms.remove(&platform.to_string()) else {
@@ -69,11 +98,67 @@ impl TryFrom<(Metadata, Platform)> for ImageMetadata {
                 else {
                     bail!("Manifest does not exist for {platform}");
                 };
+                // The manifest of the platform isn't part of the
+                // output, so the layers are the uncompressed ones
                 Ok(Self {
                     labels: image.config.labels,
                     digest: manifest.digest,
+                    config: image.config.run,
+                    layers: image
+                        .rootfs
+                        .diff_ids
+                        .into_iter()
+                        .map(|digest| ImageLayer { digest, size: None })
+                        .collect(),
+                    history: image.history,
+                    size: None,
                 })
             }
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::drivers::types::{ImageLayer, ImageMetadata, Platform};
+
+    use super::Metadata;
+
+    #[test]
+    fn single_platform_metadata() {
+        let metadata: Metadata = serde_json::from_value(serde_json::json!({
+            "manifest": {
+                "digest": "sha256:abc",
+                "layers": [
+                    { "mediaType": "application/vnd.oci.image.layer.v1.tar+gzip", "digest": "sha256:layer", "size": 42 }
+                ]
+            },
+            "image": {
+                "config": {
+                    "Labels": { "org.opencontainers.image.version": "40" },
+                    "Cmd": ["/bin/bash"],
+                    "Env": ["PATH=/usr/bin"]
+                },
+                "history": [
+                    { "created": "2024-01-01T00:00:00Z", "created_by": "RUN echo", "empty_layer": true }
+                ]
+            }
+        }))
+        .unwrap();
+
+        let metadata = ImageMetadata::try_from((metadata, Platform::Native)).unwrap();
+        assert_eq!(metadata.digest, "sha256:abc");
+        assert_eq!(metadata.config.cmd, Some(vec!["/bin/bash".to_owned()]));
+        assert_eq!(metadata.config.entrypoint, None);
+        assert_eq!(
+            metadata.layers,
+            [ImageLayer {
+                digest: "sha256:layer".into(),
+                size: Some(42)
+            }]
+        );
+        assert_eq!(metadata.history.len(), 1);
+        assert!(metadata.history[0].empty_layer);
+        assert_eq!(metadata.history[0].created_by.as_deref(), Some("RUN echo"));
+    }
+}
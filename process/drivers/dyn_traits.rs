@@ -0,0 +1,477 @@
+//! Object-safe versions of the driver traits.
+//!
+//! The driver traits are implemented with associated functions so
+//! that the drivers can be selected without allocating, and only this
+//! crate can implement them. Each of them has a `Dyn` counterpart here
+//! that takes `&self` and is implemented for every driver, including
+//! [`Driver`](super::Driver), so other crates can hold drivers as trait
+//! objects and compose them.
+//!
+//! ```
+//! use blue_build_process_management::drivers::{
+//!     dyn_traits::DynBuildDriver,
+//!     opts::{BuildContext, BuildOpts, BuildStageOpts, BuildTagPushOpts, PushOpts, TagOpts},
+//!     Driver,
+//! };
+//! use miette::Result;
+//!
+//! /// Logs how long builds take.
+//! struct Timed<D>(D);
+//!
+//! impl<D: DynBuildDriver> DynBuildDriver for Timed<D> {
+//!     fn build(&self, opts: &BuildOpts) -> Result<()> {
+//!         self.0.build(opts)
+//!     }
+//!
+//!     fn build_stage(&self, opts: &BuildStageOpts) -> Result<BuildContext> {
+//!         self.0.build_stage(opts)
+//!     }
+//!
+//!     fn remove_stage(&self, context: &BuildContext) -> Result<()> {
+//!         self.0.remove_stage(context)
+//!     }
+//!
+//!     fn tag(&self, opts: &TagOpts) -> Result<()> {
+//!         self.0.tag(opts)
+//!     }
+//!
+//!     fn push(&self, opts: &PushOpts) -> Result<()> {
+//!         self.0.push(opts)
+//!     }
+//!
+//!     fn login(&self) -> Result<()> {
+//!         self.0.login()
+//!     }
+//!
+//!     fn build_tag_push(&self, opts: &BuildTagPushOpts) -> Result<Vec<String>> {
+//!         let start = std::time::Instant::now();
+//!         let result = self.0.build_tag_push(opts);
+//!         println!("Built {:?} in {:?}", opts.image, start.elapsed());
+//!         result
+//!     }
+//! }
+//!
+//! let driver: Box<dyn DynBuildDriver> = Box::new(Timed(Driver));
+//! ```
+
+use std::{
+    path::PathBuf,
+    process::{ExitStatus, Output},
+};
+
+use miette::Result;
+use oci_distribution::Reference;
+
+use crate::summary::BuildSummary;
+
+use super::{
+    opts::{
+        BuildContext, BuildOpts, BuildStageOpts, BuildTagPushOpts, CheckKeyPairOpts,
+        GenerateImageNameOpts, GenerateKeyPairOpts, GenerateTagsOpts, GetMetadataOpts, PushOpts,
+        RunOpts, SignOpts, SignVerifyOpts, TagOpts, VerifyOpts,
+    },
+    types::ImageMetadata,
+    BuildDriver, CiDriver, InspectDriver, RunDriver, SigningDriver,
+};
+
+/// The object-safe version of [`BuildDriver`].
+pub trait DynBuildDriver: Send + Sync {
+    /// See [`BuildDriver::build`].
+    ///
+    /// # Errors
+    /// Will error if the build fails.
+    fn build(&self, opts: &BuildOpts) -> Result<()>;
+
+    /// See [`BuildDriver::build_stage`].
+    ///
+    /// # Errors
+    /// Will error if the build fails.
+    fn build_stage(&self, opts: &BuildStageOpts) -> Result<BuildContext>;
+
+    /// See [`BuildDriver::remove_stage`].
+    ///
+    /// # Errors
+    /// Will error if the stage can't be removed.
+    fn remove_stage(&self, context: &BuildContext) -> Result<()>;
+
+    /// See [`BuildDriver::tag`].
+    ///
+    /// # Errors
+    /// Will error if the tagging fails.
+    fn tag(&self, opts: &TagOpts) -> Result<()>;
+
+    /// See [`BuildDriver::push`].
+    ///
+    /// # Errors
+    /// Will error if the push fails.
+    fn push(&self, opts: &PushOpts) -> Result<()>;
+
+    /// See [`BuildDriver::login`].
+    ///
+    /// # Errors
+    /// Will error if login fails.
+    fn login(&self) -> Result<()>;
+
+    /// See [`BuildDriver::storage_dir`].
+    ///
+    /// # Errors
+    /// Will error if the driver fails to report its storage.
+    fn storage_dir(&self) -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// See [`BuildDriver::prune`].
+    ///
+    /// # Errors
+    /// Will error if the driver fails to prune.
+    #[cfg(feature = "prune")]
+    fn prune(&self, _opts: &super::opts::PruneOpts) -> Result<()> {
+        Ok(())
+    }
+
+    /// See [`BuildDriver::build_tag_push`].
+    ///
+    /// The drivers of this crate do the build, tags, and pushes
+    /// themselves, so a wrapper that passes this on to one of them
+    /// doesn't see the calls of the other methods.
+    ///
+    /// # Errors
+    /// Will error if the build, tagging, or pushing fails.
+    fn build_tag_push(&self, opts: &BuildTagPushOpts) -> Result<Vec<String>>;
+}
+
+impl<T: BuildDriver + Send + Sync> DynBuildDriver for T {
+    fn build(&self, opts: &BuildOpts) -> Result<()> {
+        T::build(opts)
+    }
+
+    fn build_stage(&self, opts: &BuildStageOpts) -> Result<BuildContext> {
+        T::build_stage(opts)
+    }
+
+    fn remove_stage(&self, context: &BuildContext) -> Result<()> {
+        T::remove_stage(context)
+    }
+
+    fn tag(&self, opts: &TagOpts) -> Result<()> {
+        T::tag(opts)
+    }
+
+    fn push(&self, opts: &PushOpts) -> Result<()> {
+        T::push(opts)
+    }
+
+    fn login(&self) -> Result<()> {
+        T::login()
+    }
+
+    fn storage_dir(&self) -> Result<Option<PathBuf>> {
+        T::storage_dir()
+    }
+
+    #[cfg(feature = "prune")]
+    fn prune(&self, opts: &super::opts::PruneOpts) -> Result<()> {
+        T::prune(opts)
+    }
+
+    fn build_tag_push(&self, opts: &BuildTagPushOpts) -> Result<Vec<String>> {
+        T::build_tag_push(opts)
+    }
+}
+
+/// The object-safe version of [`InspectDriver`].
+pub trait DynInspectDriver: Send + Sync {
+    /// See [`InspectDriver::get_metadata`].
+    ///
+    /// # Errors
+    /// Will error if the metadata can't be retrieved.
+    fn get_metadata(&self, opts: &GetMetadataOpts) -> Result<ImageMetadata>;
+}
+
+impl<T: InspectDriver + Send + Sync> DynInspectDriver for T {
+    fn get_metadata(&self, opts: &GetMetadataOpts) -> Result<ImageMetadata> {
+        T::get_metadata(opts)
+    }
+}
+
+/// The object-safe version of [`RunDriver`].
+pub trait DynRunDriver: Send + Sync {
+    /// See [`RunDriver::run`].
+    ///
+    /// # Errors
+    /// Will error if the container can't be run.
+    fn run(&self, opts: &RunOpts) -> Result<ExitStatus>;
+
+    /// See [`RunDriver::run_output`].
+    ///
+    /// # Errors
+    /// Will error if the container can't be run.
+    fn run_output(&self, opts: &RunOpts) -> Result<Output>;
+}
+
+impl<T: RunDriver + Send + Sync> DynRunDriver for T {
+    fn run(&self, opts: &RunOpts) -> Result<ExitStatus> {
+        T::run(opts)
+    }
+
+    fn run_output(&self, opts: &RunOpts) -> Result<Output> {
+        T::run_output(opts)
+    }
+}
+
+/// The object-safe version of [`SigningDriver`].
+pub trait DynSigningDriver: Send + Sync {
+    /// See [`SigningDriver::generate_key_pair`].
+    ///
+    /// # Errors
+    /// Will error if the key pair can't be generated.
+    fn generate_key_pair(&self, opts: &GenerateKeyPairOpts) -> Result<()>;
+
+    /// See [`SigningDriver::check_signing_files`].
+    ///
+    /// # Errors
+    /// Will error if the signing files aren't valid.
+    fn check_signing_files(&self, opts: &CheckKeyPairOpts) -> Result<()>;
+
+    /// See [`SigningDriver::sign`].
+    ///
+    /// # Errors
+    /// Will error if signing fails.
+    fn sign(&self, opts: &SignOpts) -> Result<()>;
+
+    /// See [`SigningDriver::verify`].
+    ///
+    /// # Errors
+    /// Will error if verification fails.
+    fn verify(&self, opts: &VerifyOpts) -> Result<()>;
+
+    /// See [`SigningDriver::sign_and_verify`].
+    ///
+    /// # Errors
+    /// Will error if signing or verification fails.
+    fn sign_and_verify(&self, opts: &SignVerifyOpts) -> Result<()>;
+
+    /// See [`SigningDriver::signing_login`].
+    ///
+    /// # Errors
+    /// Will error if login fails.
+    fn signing_login(&self) -> Result<()>;
+}
+
+impl<T: SigningDriver + Send + Sync> DynSigningDriver for T {
+    fn generate_key_pair(&self, opts: &GenerateKeyPairOpts) -> Result<()> {
+        T::generate_key_pair(opts)
+    }
+
+    fn check_signing_files(&self, opts: &CheckKeyPairOpts) -> Result<()> {
+        T::check_signing_files(opts)
+    }
+
+    fn sign(&self, opts: &SignOpts) -> Result<()> {
+        T::sign(opts)
+    }
+
+    fn verify(&self, opts: &VerifyOpts) -> Result<()> {
+        T::verify(opts)
+    }
+
+    fn sign_and_verify(&self, opts: &SignVerifyOpts) -> Result<()> {
+        T::sign_and_verify(opts)
+    }
+
+    fn signing_login(&self) -> Result<()> {
+        T::signing_login()
+    }
+}
+
+/// The object-safe version of [`CiDriver`].
+pub trait DynCiDriver: Send + Sync {
+    /// See [`CiDriver::on_default_branch`].
+    fn on_default_branch(&self) -> bool;
+
+    /// See [`CiDriver::keyless_cert_identity`].
+    ///
+    /// # Errors
+    /// Will error if the environment variables aren't set.
+    fn keyless_cert_identity(&self) -> Result<String>;
+
+    /// See [`CiDriver::oidc_provider`].
+    ///
+    /// # Errors
+    /// Will error if the environment variables aren't set.
+    fn oidc_provider(&self) -> Result<String>;
+
+    /// See [`CiDriver::generate_tags`].
+    ///
+    /// # Errors
+    /// Will error if the environment variables aren't set.
+    fn generate_tags(&self, opts: &GenerateTagsOpts) -> Result<Vec<String>>;
+
+    /// See [`CiDriver::generate_image_name`].
+    ///
+    /// # Errors
+    /// Will error if the environment variables aren't set.
+    fn generate_image_name(&self, opts: &GenerateImageNameOpts) -> Result<Reference>;
+
+    /// See [`CiDriver::get_repo_url`].
+    ///
+    /// # Errors
+    /// Will error if the environment variables aren't set.
+    fn get_repo_url(&self) -> Result<String>;
+
+    /// See [`CiDriver::get_registry`].
+    ///
+    /// # Errors
+    /// Will error if the environment variables aren't set.
+    fn get_registry(&self) -> Result<String>;
+
+    /// See [`CiDriver::get_commit_sha`].
+    fn get_commit_sha(&self) -> Option<String>;
+
+    /// See [`CiDriver::get_branch`].
+    fn get_branch(&self) -> Option<String>;
+
+    /// See [`CiDriver::default_ci_file_path`].
+    fn default_ci_file_path(&self) -> PathBuf;
+
+    /// See [`CiDriver::report_summary`].
+    ///
+    /// # Errors
+    /// Will error if the summary can't be written.
+    fn report_summary(&self, _summary: &BuildSummary) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<T: CiDriver + Send + Sync> DynCiDriver for T {
+    fn on_default_branch(&self) -> bool {
+        T::on_default_branch()
+    }
+
+    fn keyless_cert_identity(&self) -> Result<String> {
+        T::keyless_cert_identity()
+    }
+
+    fn oidc_provider(&self) -> Result<String> {
+        T::oidc_provider()
+    }
+
+    fn generate_tags(&self, opts: &GenerateTagsOpts) -> Result<Vec<String>> {
+        T::generate_tags(opts)
+    }
+
+    fn generate_image_name(&self, opts: &GenerateImageNameOpts) -> Result<Reference> {
+        T::generate_image_name(opts)
+    }
+
+    fn get_repo_url(&self) -> Result<String> {
+        T::get_repo_url()
+    }
+
+    fn get_registry(&self) -> Result<String> {
+        T::get_registry()
+    }
+
+    fn get_commit_sha(&self) -> Option<String> {
+        T::get_commit_sha()
+    }
+
+    fn get_branch(&self) -> Option<String> {
+        T::get_branch()
+    }
+
+    fn default_ci_file_path(&self) -> PathBuf {
+        T::default_ci_file_path()
+    }
+
+    fn report_summary(&self, summary: &BuildSummary) -> Result<()> {
+        T::report_summary(summary)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use miette::Result;
+
+    use crate::drivers::{opts::GenerateImageNameOpts, LocalDriver};
+
+    use super::DynCiDriver;
+
+    /// Counts the calls for the registry of the wrapped driver.
+    struct Counted<'a> {
+        inner: &'a dyn DynCiDriver,
+        calls: AtomicUsize,
+    }
+
+    impl DynCiDriver for Counted<'_> {
+        fn on_default_branch(&self) -> bool {
+            self.inner.on_default_branch()
+        }
+
+        fn keyless_cert_identity(&self) -> Result<String> {
+            self.inner.keyless_cert_identity()
+        }
+
+        fn oidc_provider(&self) -> Result<String> {
+            self.inner.oidc_provider()
+        }
+
+        fn generate_tags(
+            &self,
+            opts: &crate::drivers::opts::GenerateTagsOpts,
+        ) -> Result<Vec<String>> {
+            self.inner.generate_tags(opts)
+        }
+
+        fn generate_image_name(
+            &self,
+            opts: &GenerateImageNameOpts,
+        ) -> Result<oci_distribution::Reference> {
+            self.inner.generate_image_name(opts)
+        }
+
+        fn get_repo_url(&self) -> Result<String> {
+            self.inner.get_repo_url()
+        }
+
+        fn get_registry(&self) -> Result<String> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            self.inner.get_registry()
+        }
+
+        fn get_commit_sha(&self) -> Option<String> {
+            self.inner.get_commit_sha()
+        }
+
+        fn get_branch(&self) -> Option<String> {
+            self.inner.get_branch()
+        }
+
+        fn default_ci_file_path(&self) -> std::path::PathBuf {
+            self.inner.default_ci_file_path()
+        }
+    }
+
+    #[test]
+    fn wraps_driver() {
+        let driver = Counted {
+            inner: &LocalDriver,
+            calls: AtomicUsize::new(0),
+        };
+        let drivers: Vec<&dyn DynCiDriver> = vec![&LocalDriver, &driver];
+
+        for driver in drivers {
+            assert_eq!(driver.get_registry().unwrap(), "localhost");
+            assert_eq!(
+                driver
+                    .generate_image_name(&GenerateImageNameOpts::builder().name("test").build())
+                    .unwrap()
+                    .to_string(),
+                "localhost/test:latest"
+            );
+        }
+        assert_eq!(driver.calls.load(Ordering::SeqCst), 1);
+    }
+}
@@ -0,0 +1,496 @@
+//! Drivers that are implemented by an external executable.
+//!
+//! Setting `--build-driver`, `--inspect-driver`, or `--signing-driver`,
+//! or their env vars `BB_BUILD_DRIVER`, `BB_INSPECT_DRIVER`, and
+//! `BB_SIGNING_DRIVER`, to `external:<name>` runs the executable
+//! `bb-driver-<name>` from the `PATH` for every operation of that driver. This allows builders that
+//! `bluebuild` doesn't support to be used without changing `bluebuild`.
+//!
+//! # Protocol
+//!
+//! The executable is run once for each operation. The request is
+//! written to its stdin as a single JSON object and then stdin is closed:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "driver": "build",
+//!   "operation": "tag",
+//!   "opts": {
+//!     "src_image": "ghcr.io/octocat/my-image:latest",
+//!     "dest_image": "ghcr.io/octocat/my-image:40"
+//!   }
+//! }
+//! ```
+//!
+//! The executable writes a single JSON object to stdout, which is
+//! either `{"result": <value>}` when the operation succeeds or
+//! `{"error": "<message>"}` when it fails. Exiting with a non-zero
+//! status also fails the operation. Anything written to stderr is
+//! shown to the user.
+//!
+//! These are the operations of each driver, the fields of their
+//! `opts`, and their `result`:
+//!
+//! | Driver    | Operation             | `opts`                                                                                                                                        | `result`               |
+//! |-----------|-----------------------|-----------------------------------------------------------------------------------------------------------------------------------------------|------------------------|
+//! | `build`   | `build`               | `image`, `containerfile`, `platform`, `squash`, `host_network`, `target`, `secrets`, `build_contexts`, `cache`, `proxy`, `pull`, `extra_args` | `null`                 |
+//! | `build`   | `build_stage`         | `stage`, `containerfile`, `platform`, `secrets`, `build_contexts`, `cache`, `proxy`, `pull`, `extra_args`                                     | `{"name", "location"}` |
+//! | `build`   | `remove_stage`        | `name`, `location`                                                                                                                            | `null`                 |
+//! | `build`   | `tag`                 | `src_image`, `dest_image`                                                                                                                     | `null`                 |
+//! | `build`   | `push`                | `image`, `compression`, `extra_args`                                                                                                          | `null`                 |
+//! | `build`   | `login`               |                                                                                                                                               | `null`                 |
+//! | `build`   | `prune`               | `all`, `volumes`                                                                                                                              | `null`                 |
+//! | `inspect` | `get_metadata`        | `image`, `platform`                                                                                                                           | image metadata         |
+//! | `signing` | `generate_key_pair`   | `dir`                                                                                                                                         | `null`                 |
+//! | `signing` | `check_signing_files` | `dir`                                                                                                                                         | `null`                 |
+//! | `signing` | `sign`                | `image`, `key`, `dir`                                                                                                                         | `null`                 |
+//! | `signing` | `verify`              | `image`, and either `key` or `issuer` and `identity`                                                                                          | `null`                 |
+//! | `signing` | `signing_login`       |                                                                                                                                               | `null`                 |
+//!
+//! A `secret` is `{"id", "env"}` or `{"id", "file"}`, a build
+//! context is `{"name", "location"}`, and `cache` is `{"from": [..],
+//! "to"}`. The platform is `linux/amd64` or `linux/arm64`. When
+//! `proxy` is set, the proxy env vars of the host, like `HTTPS_PROXY`,
+//! are passed into the build. When `pull` isn't set, the images
+//! used by the build must already be in local storage. The
+//! `extra_args` are raw args for the underlying tool, like
+//! `--ulimit=nofile=4096`, that the driver can pass on or ignore.
+//!
+//! The image metadata is `{"digest", "labels", "config", "layers",
+//! "history"}`, where only the `digest` is required. The `config`
+//! has the `Entrypoint`, `Cmd`, and `Env` of the OCI image config,
+//! the `layers` are `{"digest", "size"}`, and the `history` is the
+//! `history` of the OCI image config.
+
+use std::{
+    collections::HashMap,
+    env,
+    io::Write,
+    process::Stdio,
+    sync::{LazyLock, RwLock},
+};
+
+use blue_build_utils::{
+    cmd,
+    constants::{BB_BUILD_DRIVER, BB_INSPECT_DRIVER, BB_SIGNING_DRIVER},
+};
+use log::{debug, trace};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+
+use super::{
+    opts::{
+        BuildCache, BuildContext, BuildOpts, BuildSecret, BuildSecretSource, BuildStageOpts,
+        CheckKeyPairOpts, ExtraArg, GenerateKeyPairOpts, GetMetadataOpts, PushOpts, SignOpts,
+        TagOpts, VerifyOpts, VerifyType,
+    },
+    types::{ImageConfig, ImageHistory, ImageLayer, ImageMetadata},
+    BuildDriver, InspectDriver, SigningDriver,
+};
+
+/// The version of the protocol, which changes
+/// when a request can't be read by older drivers.
+const PROTOCOL_VERSION: u32 = 1;
+pub(super) const EXTERNAL_PREFIX: &str = "external:";
+const EXECUTABLE_PREFIX: &str = "bb-driver-";
+
+/// The names of the selected external drivers.
+static SELECTED_NAMES: LazyLock<RwLock<HashMap<ExternalDriverKind, String>>> =
+    LazyLock::new(|| RwLock::new(HashMap::new()));
+
+/// The kinds of drivers that can be external.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub(super) enum ExternalDriverKind {
+    Build,
+    Inspect,
+    Signing,
+}
+
+impl ExternalDriverKind {
+    const fn name(self) -> &'static str {
+        match self {
+            Self::Build => "build",
+            Self::Inspect => "inspect",
+            Self::Signing => "signing",
+        }
+    }
+
+    const fn env_var(self) -> &'static str {
+        match self {
+            Self::Build => BB_BUILD_DRIVER,
+            Self::Inspect => BB_INSPECT_DRIVER,
+            Self::Signing => BB_SIGNING_DRIVER,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ExternalDriver;
+
+/// The response of an external driver.
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "snake_case")]
+enum Response<T> {
+    Result(T),
+    Error(String),
+}
+
+#[derive(Deserialize, Debug)]
+struct ExternalMetadata {
+    digest: String,
+
+    #[serde(default)]
+    labels: HashMap<String, Value>,
+
+    #[serde(default)]
+    config: ImageConfig,
+
+    #[serde(default)]
+    layers: Vec<ExternalLayer>,
+
+    #[serde(default)]
+    history: Vec<ImageHistory>,
+}
+
+#[derive(Deserialize, Debug)]
+struct ExternalLayer {
+    digest: String,
+
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+impl ExternalDriver {
+    /// The name of the external driver that is set in the
+    /// env var of the `kind` of driver with `external:<name>`.
+    pub(super) fn name_from_env(kind: ExternalDriverKind) -> Option<String> {
+        env::var(kind.env_var())
+            .ok()
+            .and_then(|value| value.strip_prefix(EXTERNAL_PREFIX).map(ToOwned::to_owned))
+            .filter(|name| !name.is_empty())
+    }
+
+    /// Selects the external driver `name` for the `kind` of driver.
+    pub(super) fn select(kind: ExternalDriverKind, name: impl Into<String>) {
+        let name = name.into();
+        debug!(
+            "Using external {} driver {EXECUTABLE_PREFIX}{name}",
+            kind.name()
+        );
+        SELECTED_NAMES
+            .write()
+            .expect("Should lock")
+            .insert(kind, name);
+    }
+
+    /// The name of the selected external driver of the `kind`.
+    pub(super) fn selected_name(kind: ExternalDriverKind) -> Option<String> {
+        SELECTED_NAMES
+            .read()
+            .expect("Should lock")
+            .get(&kind)
+            .cloned()
+    }
+
+    /// Runs an operation of the external driver of the `kind`.
+    fn call<T>(kind: ExternalDriverKind, operation: &str, opts: &Value) -> Result<T>
+    where
+        T: DeserializeOwned,
+    {
+        let name = Self::selected_name(kind)
+            .ok_or_else(|| miette!("No external {} driver is selected", kind.name()))?;
+        let executable = format!("{EXECUTABLE_PREFIX}{name}");
+
+        let request = json!({
+            "version": PROTOCOL_VERSION,
+            "driver": kind.name(),
+            "operation": operation,
+            "opts": opts,
+        });
+        trace!("{executable}: {request}");
+
+        let mut child = cmd!(
+            &executable,
+            stdin = Stdio::piped(),
+            stdout = Stdio::piped(),
+            stderr = Stdio::inherit(),
+        )
+        .spawn()
+        .into_diagnostic()
+        .with_context(|| format!("Failed to run the external driver {executable}"))?;
+
+        if let Some(mut stdin) = child.stdin.take() {
+            stdin
+                .write_all(request.to_string().as_bytes())
+                .into_diagnostic()?;
+        }
+        let output = child.wait_with_output().into_diagnostic()?;
+
+        parse_response(&output.stdout, output.status.success())
+            .with_context(|| format!("The external driver {executable} failed to {operation}"))
+    }
+}
+
+fn parse_response<T>(stdout: &[u8], success: bool) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    match serde_json::from_slice::<Response<T>>(stdout) {
+        Ok(Response::Error(message)) => bail!("{message}"),
+        _ if !success => bail!("Exited with an error"),
+        Ok(Response::Result(result)) => Ok(result),
+        Err(e) => Err(e)
+            .into_diagnostic()
+            .context("Failed to read the response"),
+    }
+}
+
+fn secrets_json(secrets: &[BuildSecret]) -> Value {
+    secrets
+        .iter()
+        .map(|secret| match &secret.source {
+            BuildSecretSource::Env(var) => json!({ "id": secret.id, "env": var }),
+            BuildSecretSource::File(path) => json!({ "id": secret.id, "file": path }),
+        })
+        .collect()
+}
+
+fn cache_json(cache: &BuildCache) -> Value {
+    json!({ "from": cache.from, "to": cache.to })
+}
+
+fn extra_args_json(args: &[ExtraArg]) -> Value {
+    args.iter().map(ExtraArg::arg).collect()
+}
+
+pub(super) fn build_context_json(context: &BuildContext) -> Value {
+    json!({ "name": context.name, "location": context.location })
+}
+
+// The `opts` of the operations, which the mock driver also records
+
+pub(super) fn build_json(opts: &BuildOpts) -> Value {
+    json!({
+        "image": opts.image,
+        "containerfile": opts.containerfile,
+        "platform": opts.platform.to_string(),
+        "squash": opts.squash,
+        "host_network": opts.host_network,
+        "target": opts.target,
+        "secrets": secrets_json(&opts.secrets),
+        "build_contexts": opts
+            .build_contexts
+            .iter()
+            .map(build_context_json)
+            .collect::<Value>(),
+        "cache": cache_json(&opts.cache),
+        "proxy": opts.proxy,
+        "pull": opts.pull,
+        "extra_args": extra_args_json(&opts.extra_args),
+    })
+}
+
+pub(super) fn build_stage_json(opts: &BuildStageOpts) -> Value {
+    json!({
+        "stage": opts.stage,
+        "containerfile": opts.containerfile,
+        "platform": opts.platform.to_string(),
+        "secrets": secrets_json(&opts.secrets),
+        "build_contexts": opts
+            .build_contexts
+            .iter()
+            .map(build_context_json)
+            .collect::<Value>(),
+        "cache": cache_json(&opts.cache),
+        "proxy": opts.proxy,
+        "pull": opts.pull,
+        "extra_args": extra_args_json(&opts.extra_args),
+    })
+}
+
+pub(super) fn tag_json(opts: &TagOpts) -> Value {
+    json!({
+        "src_image": opts.src_image.to_string(),
+        "dest_image": opts.dest_image.to_string(),
+    })
+}
+
+pub(super) fn push_json(opts: &PushOpts) -> Value {
+    json!({
+        "image": opts.image.to_string(),
+        "compression": opts.compression_type.map(|c| c.to_string()),
+        "extra_args": extra_args_json(&opts.extra_args),
+    })
+}
+
+pub(super) fn get_metadata_json(opts: &GetMetadataOpts) -> Value {
+    json!({
+        "image": opts.image.to_string(),
+        "platform": opts.platform.to_string(),
+    })
+}
+
+pub(super) fn sign_json(opts: &SignOpts) -> Value {
+    json!({
+        "image": opts.image.to_string(),
+        "key": opts.key,
+        "dir": opts.dir,
+    })
+}
+
+pub(super) fn verify_json(opts: &VerifyOpts) -> Value {
+    match &opts.verify_type {
+        VerifyType::File(key) => json!({
+            "image": opts.image.to_string(),
+            "key": key,
+        }),
+        VerifyType::Keyless { issuer, identity } => json!({
+            "image": opts.image.to_string(),
+            "issuer": issuer,
+            "identity": identity,
+        }),
+    }
+}
+
+impl BuildDriver for ExternalDriver {
+    fn build(opts: &BuildOpts) -> Result<()> {
+        Self::call(ExternalDriverKind::Build, "build", &build_json(opts))
+    }
+
+    fn build_stage(opts: &BuildStageOpts) -> Result<BuildContext> {
+        #[derive(Deserialize)]
+        struct StageContext {
+            name: String,
+            location: String,
+        }
+
+        let context: StageContext = Self::call(
+            ExternalDriverKind::Build,
+            "build_stage",
+            &build_stage_json(opts),
+        )?;
+
+        Ok(BuildContext {
+            name: context.name,
+            location: context.location,
+        })
+    }
+
+    fn remove_stage(context: &BuildContext) -> Result<()> {
+        Self::call(
+            ExternalDriverKind::Build,
+            "remove_stage",
+            &build_context_json(context),
+        )
+    }
+
+    fn tag(opts: &TagOpts) -> Result<()> {
+        Self::call(ExternalDriverKind::Build, "tag", &tag_json(opts))
+    }
+
+    fn push(opts: &PushOpts) -> Result<()> {
+        Self::call(ExternalDriverKind::Build, "push", &push_json(opts))
+    }
+
+    fn login() -> Result<()> {
+        Self::call(ExternalDriverKind::Build, "login", &json!({}))
+    }
+
+    #[cfg(feature = "prune")]
+    fn prune(opts: &super::opts::PruneOpts) -> Result<()> {
+        Self::call(
+            ExternalDriverKind::Build,
+            "prune",
+            &json!({ "all": opts.all, "volumes": opts.volumes }),
+        )
+    }
+}
+
+impl InspectDriver for ExternalDriver {
+    async fn get_metadata_async(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+        let request = get_metadata_json(opts);
+
+        let metadata: ExternalMetadata = crate::spawn_blocking(move || {
+            Self::call(ExternalDriverKind::Inspect, "get_metadata", &request)
+        })
+        .await?;
+
+        Ok(ImageMetadata {
+            labels: metadata.labels,
+            digest: metadata.digest,
+            config: metadata.config,
+            layers: metadata
+                .layers
+                .into_iter()
+                .map(|layer| ImageLayer {
+                    digest: layer.digest,
+                    size: layer.size,
+                })
+                .collect(),
+            history: metadata.history,
+            size: None,
+        })
+    }
+}
+
+impl SigningDriver for ExternalDriver {
+    fn generate_key_pair(opts: &GenerateKeyPairOpts) -> Result<()> {
+        Self::call(
+            ExternalDriverKind::Signing,
+            "generate_key_pair",
+            &json!({ "dir": opts.dir }),
+        )
+    }
+
+    fn check_signing_files(opts: &CheckKeyPairOpts) -> Result<()> {
+        Self::call(
+            ExternalDriverKind::Signing,
+            "check_signing_files",
+            &json!({ "dir": opts.dir }),
+        )
+    }
+
+    fn sign(opts: &SignOpts) -> Result<()> {
+        Self::call(ExternalDriverKind::Signing, "sign", &sign_json(opts))
+    }
+
+    fn verify(opts: &VerifyOpts) -> Result<()> {
+        Self::call(ExternalDriverKind::Signing, "verify", &verify_json(opts))
+    }
+
+    fn signing_login() -> Result<()> {
+        Self::call(ExternalDriverKind::Signing, "signing_login", &json!({}))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+    use serde_json::Value;
+
+    use super::parse_response;
+
+    #[rstest]
+    #[case(br#"{"result": null}"#, true, Some(Value::Null))]
+    #[case(br#"{"result": {"name": "stage"}}"#, true, Some(serde_json::json!({"name": "stage"})))]
+    #[case(br#"{"result": null}"#, false, None)]
+    #[case(br#"{"error": "no such image"}"#, true, None)]
+    #[case(br#"{"error": "no such image"}"#, false, None)]
+    #[case(b"", false, None)]
+    #[case(b"not json", true, None)]
+    fn read_response(
+        #[case] stdout: &[u8],
+        #[case] success: bool,
+        #[case] expected: Option<Value>,
+    ) {
+        assert_eq!(parse_response::<Value>(stdout, success).ok(), expected);
+    }
+
+    #[test]
+    fn error_message() {
+        let err = parse_response::<Value>(br#"{"error": "no such image"}"#, true).unwrap_err();
+        assert_eq!(err.to_string(), "no such image");
+    }
+}
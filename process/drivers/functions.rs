@@ -1,12 +1,24 @@
-use std::{env, path::Path};
+use std::{
+    env,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
+};
 
 use blue_build_utils::{
+    cmd,
     constants::{BB_PRIVATE_KEY, COSIGN_PRIVATE_KEY, COSIGN_PRIV_PATH, COSIGN_PUB_PATH},
     string,
 };
-use miette::{bail, Result};
+use log::{debug, info, trace, warn};
+use miette::{bail, IntoDiagnostic, Result};
 
-use super::opts::PrivateKey;
+use super::{
+    opts::{BuildContext, BuildStageOpts, BuildTagPushOpts, PrivateKey},
+    types::LocalImage,
+    BuildDriver,
+};
 
 pub(super) fn get_private_key<P>(path: P) -> Result<PrivateKey>
 where
@@ -50,3 +62,307 @@ where
         },
     )
 }
+
+/// Runs `f` on each item with at most `jobs` running at once.
+///
+/// The results are returned in the same order as the items.
+/// Every item is attempted even if some fail, then the
+/// first error is returned.
+pub(super) fn run_concurrently<T, V, F>(items: &[T], jobs: NonZeroUsize, f: F) -> Result<Vec<V>>
+where
+    T: Sync,
+    V: Send,
+    F: Fn(&T) -> Result<V> + Sync,
+{
+    let next = AtomicUsize::new(0);
+    let workers = jobs.get().min(items.len());
+
+    let mut results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            break results;
+                        };
+                        results.push((index, f(item)));
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("Worker thread should not panic"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(index, _)| *index);
+
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Runs `f` on the first item on its own and then on
+/// the rest of the items with at most `jobs` at once.
+///
+/// This is how the tags of an image are pushed. The tags
+/// share all of their blobs, so once the first push has
+/// uploaded them the rest only need to upload their manifests.
+pub(super) fn run_first_then_concurrently<T, F>(items: &[T], jobs: NonZeroUsize, f: F) -> Result<()>
+where
+    T: Sync,
+    F: Fn(&T) -> Result<()> + Sync,
+{
+    let Some((first, rest)) = items.split_first() else {
+        return Ok(());
+    };
+    f(first)?;
+    run_concurrently(rest, jobs, f)?;
+    Ok(())
+}
+
+/// Builds the stages of `opts` at the same time and runs `build`
+/// with the build contexts of `opts` and the ones that replace
+/// the stages. The stages are removed again once `build` is done.
+pub(super) fn with_built_stages<T, V>(
+    opts: &BuildTagPushOpts,
+    build: impl FnOnce(&[BuildContext]) -> Result<V>,
+) -> Result<V>
+where
+    T: BuildDriver + ?Sized,
+{
+    let Some(containerfile) = opts
+        .stages_containerfile
+        .as_deref()
+        .filter(|_| !opts.stages.is_empty())
+    else {
+        return build(&opts.build_contexts);
+    };
+
+    info!(
+        "Building stages {}, {} at a time",
+        opts.stages.join(", "),
+        opts.stage_jobs
+    );
+    let results = run_concurrently(&opts.stages, opts.stage_jobs, |stage| {
+        Ok(T::build_stage(
+            &BuildStageOpts::builder()
+                .stage(&**stage)
+                .containerfile(containerfile)
+                .platform(opts.platform)
+                .secrets(opts.secrets.clone())
+                .build_contexts(opts.build_contexts.clone())
+                .cache(opts.cache.clone())
+                .proxy(opts.proxy)
+                .pull(opts.pull)
+                .extra_args(opts.build_extra_args.clone())
+                .maybe_tempdir(opts.stages_tempdir)
+                .build(),
+        ))
+    })?;
+
+    let mut contexts = Vec::with_capacity(results.len());
+    let mut error = None;
+    for result in results {
+        match result {
+            Ok(context) => contexts.push(context),
+            Err(e) => {
+                error.get_or_insert(e);
+            }
+        }
+    }
+
+    let result = error.map_or_else(
+        || build(&[opts.build_contexts.as_slice(), &contexts].concat()),
+        Err,
+    );
+
+    for context in &contexts {
+        if let Err(e) = T::remove_stage(context) {
+            warn!("Failed to remove stage {}: {e:?}", context.name);
+        }
+    }
+    result
+}
+
+/// Gets the directory that a container tool keeps its images in
+/// from its `info` command, formatted with a Go template.
+/// Lists every image in the local storage of `tool`,
+/// including the intermediate images.
+pub(super) fn list_local_images(tool: &str) -> Result<Vec<LocalImage>> {
+    let output = {
+        let mut c = cmd!(tool);
+        c.args(["images", "--all", "--quiet", "--no-trunc"]);
+        trace!("{c:?}");
+        c
+    }
+    .output()
+    .into_diagnostic()?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to list the images of {tool}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    // An image is listed once for each of its tags
+    let mut ids = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(ToOwned::to_owned)
+        .collect::<Vec<_>>();
+    ids.sort();
+    ids.dedup();
+
+    if ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let output = {
+        let mut c = cmd!(tool);
+        c.args(["image", "inspect"]).args(&ids);
+        trace!("{c:?}");
+        c
+    }
+    .output()
+    .into_diagnostic()?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to inspect the images of {tool}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    serde_json::from_slice(&output.stdout).into_diagnostic()
+}
+
+/// Removes images from the local storage of `tool`
+/// by their tags, or by their IDs if they have no tags.
+pub(super) fn remove_local_images(tool: &str, images: &[String]) -> Result<()> {
+    let output = {
+        let mut c = cmd!(tool);
+        c.arg("rmi").args(images);
+        trace!("{c:?}");
+        c
+    }
+    .output()
+    .into_diagnostic()?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to remove {}: {}",
+            images.join(", "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+pub(super) fn storage_dir(tool: &str, format: &str) -> Result<Option<PathBuf>> {
+    let output = {
+        let mut c = cmd!(tool);
+        c.args(["info", "--format", format]);
+        trace!("{c:?}");
+        c
+    }
+    .output()
+    .into_diagnostic()?;
+
+    if !output.status.success() {
+        bail!(
+            "Failed to get the storage of {tool}: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    // Docker Desktop and podman machine keep their storage in a
+    // VM, so the directory they report isn't on this host
+    let dir = PathBuf::from(String::from_utf8_lossy(&output.stdout).trim());
+    if dir.as_os_str().is_empty() || !dir.exists() {
+        debug!("The storage of {tool} isn't on this host");
+        return Ok(None);
+    }
+    Ok(Some(dir))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        num::NonZeroUsize,
+        sync::atomic::{AtomicUsize, Ordering},
+        thread,
+        time::Duration,
+    };
+
+    use miette::bail;
+
+    use super::{run_concurrently, run_first_then_concurrently};
+
+    #[test]
+    fn concurrent_results_in_order() {
+        let running = AtomicUsize::new(0);
+        let max_running = AtomicUsize::new(0);
+
+        let results = run_concurrently(&[1, 2, 3, 4, 5, 6], NonZeroUsize::new(2).unwrap(), |i| {
+            let now = running.fetch_add(1, Ordering::SeqCst) + 1;
+            max_running.fetch_max(now, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(i * 10)
+        })
+        .unwrap();
+
+        assert_eq!(results, [10, 20, 30, 40, 50, 60]);
+        assert_eq!(max_running.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn concurrent_attempts_all() {
+        let attempts = AtomicUsize::new(0);
+
+        let result = run_concurrently(&[1, 2, 3], NonZeroUsize::new(3).unwrap(), |&i| {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            if i == 2 {
+                bail!("Failed {i}");
+            }
+            Ok(i)
+        });
+
+        assert_eq!(result.unwrap_err().to_string(), "Failed 2");
+        assert_eq!(attempts.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn first_runs_alone() {
+        let running = AtomicUsize::new(0);
+        let first_overlapped = AtomicUsize::new(0);
+
+        run_first_then_concurrently(&[1, 2, 3, 4], NonZeroUsize::new(3).unwrap(), |&i| {
+            running.fetch_add(1, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(10));
+            if i == 1 {
+                first_overlapped.store(running.load(Ordering::SeqCst) - 1, Ordering::SeqCst);
+            }
+            running.fetch_sub(1, Ordering::SeqCst);
+            Ok(())
+        })
+        .unwrap();
+
+        assert_eq!(first_overlapped.load(Ordering::SeqCst), 0);
+
+        let attempts = AtomicUsize::new(0);
+        let result = run_first_then_concurrently(&[1, 2, 3], NonZeroUsize::MIN, |&i| {
+            attempts.fetch_add(1, Ordering::SeqCst);
+            if i == 1 {
+                bail!("Failed {i}");
+            }
+            Ok(())
+        });
+        assert_eq!(result.unwrap_err().to_string(), "Failed 1");
+        assert_eq!(attempts.load(Ordering::SeqCst), 1);
+    }
+}
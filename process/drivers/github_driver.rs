@@ -1,14 +1,16 @@
-use std::path::PathBuf;
+use std::{fs::OpenOptions, io::Write, path::PathBuf};
 
 use blue_build_utils::{
     constants::{
-        GITHUB_EVENT_NAME, GITHUB_EVENT_PATH, GITHUB_REF_NAME, GITHUB_SHA, GITHUB_TOKEN_ISSUER_URL,
-        GITHUB_WORKFLOW_REF, PR_EVENT_NUMBER,
+        GITHUB_EVENT_NAME, GITHUB_EVENT_PATH, GITHUB_HEAD_REF, GITHUB_OUTPUT, GITHUB_REF_NAME,
+        GITHUB_SHA, GITHUB_STEP_SUMMARY, GITHUB_TOKEN_ISSUER_URL, GITHUB_WORKFLOW_REF,
+        PR_EVENT_NUMBER,
     },
     string_vec,
 };
 use event::Event;
-use log::trace;
+use log::{debug, trace};
+use miette::{Context, IntoDiagnostic};
 
 #[cfg(not(test))]
 use blue_build_utils::get_env_var;
@@ -16,6 +18,8 @@ use blue_build_utils::get_env_var;
 #[cfg(test)]
 use blue_build_utils::test_utils::get_env_var;
 
+use crate::summary::{BuildSummary, ImageSummary};
+
 use super::{opts::GenerateTagsOpts, CiDriver, Driver};
 
 mod event;
@@ -36,6 +40,10 @@ impl CiDriver for GithubDriver {
         Ok(GITHUB_TOKEN_ISSUER_URL.to_string())
     }
 
+    fn signs_keylessly() -> bool {
+        true
+    }
+
     fn generate_tags(opts: &GenerateTagsOpts) -> miette::Result<Vec<String>> {
         const PR_EVENT: &str = "pull_request";
         let timestamp = blue_build_utils::get_tag_timestamp();
@@ -133,9 +141,66 @@ impl CiDriver for GithubDriver {
         .to_lowercase())
     }
 
+    fn get_commit_sha() -> Option<String> {
+        get_env_var(GITHUB_SHA).ok()
+    }
+
+    fn get_branch() -> Option<String> {
+        // The ref of a pull request is its merge ref
+        // instead of the branch that it's from
+        get_env_var(GITHUB_HEAD_REF)
+            .ok()
+            .filter(|head_ref| !head_ref.is_empty())
+            .or_else(|| get_env_var(GITHUB_REF_NAME).ok())
+    }
+
     fn default_ci_file_path() -> PathBuf {
         PathBuf::from(".github/workflows/build.yml")
     }
+
+    fn report_summary(summary: &BuildSummary) -> miette::Result<()> {
+        if let Ok(path) = get_env_var(GITHUB_STEP_SUMMARY) {
+            debug!("Writing the build summary to {path}");
+            append(&path, &summary.to_markdown())?;
+        }
+
+        if let Ok(path) = get_env_var(GITHUB_OUTPUT) {
+            debug!("Writing the build outputs to {path}");
+            append(&path, &outputs(summary)?)?;
+        }
+        Ok(())
+    }
+}
+
+/// The outputs of the step for the workflow, which are the
+/// built images at each of their tags as a JSON array and the
+/// digest of the first pushed image.
+fn outputs(summary: &BuildSummary) -> miette::Result<String> {
+    let images = serde_json::to_string(
+        &summary
+            .images
+            .iter()
+            .flat_map(ImageSummary::refs)
+            .collect::<Vec<_>>(),
+    )
+    .into_diagnostic()?;
+    let digest = summary
+        .images
+        .iter()
+        .find_map(|image| image.digest.as_deref())
+        .unwrap_or_default();
+
+    Ok(format!("images={images}\ndigest={digest}\n"))
+}
+
+fn append(path: &str, contents: &str) -> miette::Result<()> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .and_then(|mut file| file.write_all(contents.as_bytes()))
+        .into_diagnostic()
+        .with_context(|| format!("Failed to write to {path}"))
 }
 
 #[cfg(test)]
@@ -144,7 +209,8 @@ mod test {
 
     use blue_build_utils::{
         constants::{
-            GITHUB_EVENT_NAME, GITHUB_EVENT_PATH, GITHUB_REF_NAME, GITHUB_SHA, PR_EVENT_NUMBER,
+            GITHUB_EVENT_NAME, GITHUB_EVENT_PATH, GITHUB_HEAD_REF, GITHUB_REF_NAME, GITHUB_SHA,
+            PR_EVENT_NUMBER,
         },
         string_vec,
         test_utils::set_env_var,
@@ -154,10 +220,11 @@ mod test {
 
     use crate::{
         drivers::{opts::GenerateTagsOpts, types::Platform, CiDriver},
+        summary::{BuildSummary, ImageSummary},
         test::{TEST_TAG_1, TEST_TAG_2, TIMESTAMP},
     };
 
-    use super::GithubDriver;
+    use super::{outputs, GithubDriver};
 
     const COMMIT_SHA: &str = "1234567";
     const BR_REF_NAME: &str = "test";
@@ -193,6 +260,18 @@ mod test {
         set_env_var(GITHUB_SHA, "1234567890");
     }
 
+    #[test]
+    fn get_branch() {
+        setup_branch();
+        set_env_var(GITHUB_HEAD_REF, "");
+        assert_eq!(GithubDriver::get_branch().as_deref(), Some(BR_REF_NAME));
+
+        setup_pr_branch();
+        set_env_var(GITHUB_REF_NAME, "12/merge");
+        set_env_var(GITHUB_HEAD_REF, BR_REF_NAME);
+        assert_eq!(GithubDriver::get_branch().as_deref(), Some(BR_REF_NAME));
+    }
+
     #[test]
     fn get_registry() {
         setup_default_branch();
@@ -302,4 +381,32 @@ mod test {
 
         assert_eq!(tags, expected);
     }
+
+    #[test]
+    fn step_outputs() {
+        let summary = BuildSummary {
+            success: true,
+            images: vec![
+                ImageSummary::builder()
+                    .recipe("recipes/recipe.yml")
+                    .image("ghcr.io/octocat/test")
+                    .tags(string_vec!["latest", "41"])
+                    .digest("sha256:abc".into())
+                    .pushed(true)
+                    .build(),
+                ImageSummary::builder()
+                    .recipe("recipes/other.yml")
+                    .image("ghcr.io/octocat/other")
+                    .tags(string_vec!["latest"])
+                    .build(),
+            ],
+            validations: Vec::new(),
+            warnings: Vec::new(),
+        };
+
+        assert_eq!(
+            outputs(&summary).unwrap(),
+            "images=[\"ghcr.io/octocat/test:latest\",\"ghcr.io/octocat/test:41\",\"ghcr.io/octocat/other:latest\"]\ndigest=sha256:abc\n"
+        );
+    }
 }
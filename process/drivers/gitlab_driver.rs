@@ -1,14 +1,16 @@
-use std::path::PathBuf;
+use std::{fmt::Write as _, fs, path::PathBuf};
 
 use blue_build_utils::{
     constants::{
-        CI_COMMIT_REF_NAME, CI_COMMIT_SHORT_SHA, CI_DEFAULT_BRANCH, CI_MERGE_REQUEST_IID,
-        CI_PIPELINE_SOURCE, CI_PROJECT_NAME, CI_PROJECT_NAMESPACE, CI_PROJECT_URL, CI_REGISTRY,
-        CI_SERVER_HOST, CI_SERVER_PROTOCOL,
+        CI_COMMIT_REF_NAME, CI_COMMIT_SHA, CI_COMMIT_SHORT_SHA, CI_DEFAULT_BRANCH,
+        CI_MERGE_REQUEST_IID, CI_PIPELINE_SOURCE, CI_PROJECT_NAME, CI_PROJECT_NAMESPACE,
+        CI_PROJECT_URL, CI_REGISTRY, CI_SERVER_HOST, CI_SERVER_PROTOCOL, GITLAB_DOTENV_REPORT,
+        GITLAB_JUNIT_REPORT,
     },
-    string_vec,
+    escape_xml, string_vec,
 };
-use log::trace;
+use log::{debug, trace};
+use miette::{Context, IntoDiagnostic};
 
 #[cfg(not(test))]
 use blue_build_utils::get_env_var;
@@ -16,7 +18,7 @@ use blue_build_utils::get_env_var;
 #[cfg(test)]
 use blue_build_utils::test_utils::get_env_var;
 
-use crate::drivers::Driver;
+use crate::{drivers::Driver, summary::BuildSummary};
 
 use super::{opts::GenerateTagsOpts, CiDriver};
 
@@ -45,6 +47,10 @@ impl CiDriver for GitlabDriver {
         ))
     }
 
+    fn signs_keylessly() -> bool {
+        true
+    }
+
     fn generate_tags(opts: &GenerateTagsOpts) -> miette::Result<Vec<String>> {
         const MR_EVENT: &str = "merge_request_event";
         let os_version = Driver::get_os_version()
@@ -143,9 +149,100 @@ impl CiDriver for GitlabDriver {
         .to_lowercase())
     }
 
+    fn get_commit_sha() -> Option<String> {
+        get_env_var(CI_COMMIT_SHA).ok()
+    }
+
+    fn get_branch() -> Option<String> {
+        get_env_var(CI_COMMIT_REF_NAME).ok()
+    }
+
     fn default_ci_file_path() -> PathBuf {
         PathBuf::from(".gitlab-ci.yml")
     }
+
+    fn report_summary(summary: &BuildSummary) -> miette::Result<()> {
+        if !summary.validations.is_empty() {
+            debug!("Writing the validation report to {GITLAB_JUNIT_REPORT}");
+            write_report(GITLAB_JUNIT_REPORT, &junit_report(summary))?;
+        }
+
+        if !summary.images.is_empty() {
+            debug!("Writing the build variables to {GITLAB_DOTENV_REPORT}");
+            write_report(GITLAB_DOTENV_REPORT, &dotenv_report(summary))?;
+        }
+        Ok(())
+    }
+}
+
+/// The validation of each recipe as a test case of a JUnit
+/// report, which GitLab shows in the merge request.
+fn junit_report(summary: &BuildSummary) -> String {
+    let failures = summary
+        .validations
+        .iter()
+        .filter(|validation| !validation.errors.is_empty())
+        .count();
+    let tests = summary.validations.len();
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    _ = writeln!(
+        out,
+        "<testsuites name=\"bluebuild\" tests=\"{tests}\" failures=\"{failures}\">"
+    );
+    _ = writeln!(
+        out,
+        "  <testsuite name=\"validate\" tests=\"{tests}\" failures=\"{failures}\">"
+    );
+
+    for validation in &summary.validations {
+        let recipe = escape_xml(&validation.recipe);
+
+        if validation.errors.is_empty() {
+            _ = writeln!(
+                out,
+                "    <testcase classname=\"validate\" name=\"{recipe}\" />"
+            );
+        } else {
+            _ = writeln!(
+                out,
+                "    <testcase classname=\"validate\" name=\"{recipe}\">"
+            );
+            _ = writeln!(
+                out,
+                "      <failure message=\"Recipe {recipe} failed to validate\">{}</failure>",
+                escape_xml(&validation.errors.join("\n\n"))
+            );
+            out.push_str("    </testcase>\n");
+        }
+    }
+
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+/// The variables for a dotenv report, which passes them
+/// to the jobs that come after the build.
+fn dotenv_report(summary: &BuildSummary) -> String {
+    let mut out = String::new();
+
+    if let Some(image) = summary.images.iter().find_map(|image| image.refs().next()) {
+        _ = writeln!(out, "IMAGE={image}");
+    }
+    if let Some(digest) = summary
+        .images
+        .iter()
+        .find_map(|image| image.digest.as_deref())
+    {
+        _ = writeln!(out, "DIGEST={digest}");
+    }
+    out
+}
+
+fn write_report(path: &str, contents: &str) -> miette::Result<()> {
+    fs::write(path, contents)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to write {path}"))
 }
 
 #[cfg(test)]
@@ -166,10 +263,11 @@ mod test {
 
     use crate::{
         drivers::{opts::GenerateTagsOpts, CiDriver},
+        summary::{BuildSummary, ImageSummary, ValidationSummary},
         test::{TEST_TAG_1, TEST_TAG_2, TIMESTAMP},
     };
 
-    use super::GitlabDriver;
+    use super::{dotenv_report, junit_report, GitlabDriver};
 
     const COMMIT_SHA: &str = "1234567";
     const BR_REF_NAME: &str = "test";
@@ -310,4 +408,58 @@ mod test {
 
         assert_eq!(tags, expected);
     }
+
+    #[test]
+    fn junit() {
+        let summary = BuildSummary {
+            success: false,
+            validations: vec![
+                ValidationSummary {
+                    recipe: "recipes/recipe.yml".into(),
+                    errors: Vec::new(),
+                },
+                ValidationSummary {
+                    recipe: "recipes/broken.yml".into(),
+                    errors: string_vec!["`modules` is missing", "<bad> & \"worse\""],
+                },
+            ],
+            ..BuildSummary::default()
+        };
+
+        assert_eq!(
+            junit_report(&summary),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="bluebuild" tests="2" failures="1">
+  <testsuite name="validate" tests="2" failures="1">
+    <testcase classname="validate" name="recipes/recipe.yml" />
+    <testcase classname="validate" name="recipes/broken.yml">
+      <failure message="Recipe recipes/broken.yml failed to validate">`modules` is missing
+
+&lt;bad&gt; &amp; &quot;worse&quot;</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+
+    #[test]
+    fn dotenv() {
+        let summary = BuildSummary {
+            success: true,
+            images: vec![ImageSummary::builder()
+                .recipe("recipes/recipe.yml")
+                .image("registry.gitlab.com/octocat/test")
+                .tags(string_vec!["latest", "41"])
+                .digest("sha256:abc".into())
+                .pushed(true)
+                .build()],
+            ..BuildSummary::default()
+        };
+
+        assert_eq!(
+            dotenv_report(&summary),
+            "IMAGE=registry.gitlab.com/octocat/test:latest\nDIGEST=sha256:abc\n"
+        );
+    }
 }
@@ -0,0 +1,832 @@
+//! Merges small adjacent layers of an image in an OCI directory
+//! so that the image fits into a budget of layers.
+//!
+//! Some registries and clients behave badly with images that
+//! have a hundred or more layers. Layers that are at least as
+//! big as the keep size are never merged since they're usually
+//! shared with the base image and other images.
+
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufRead, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use flate2::{read::MultiGzDecoder, write::GzEncoder, Compression};
+use log::{debug, trace, warn};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+
+use super::os_release::{entry_path, parse_size, BLOCK_SIZE};
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+const LAYER_GZIP_MEDIA_TYPE: &str = "application/vnd.oci.image.layer.v1.tar+gzip";
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Merges the layers of the image in the OCI directory
+/// at `dir` until it has at most `max_layers` layers.
+///
+/// Returns the number of layers the image has afterwards.
+///
+/// # Errors
+/// Will error if the image in the OCI directory
+/// can't be read or the merged image can't be written.
+pub(super) fn merge_oci_dir(dir: &Path, max_layers: usize, keep_size: u64) -> Result<usize> {
+    let index_path = dir.join("index.json");
+    let mut index = read_json(&index_path)?;
+    let mut manifest = read_json(&blob_path(dir, &index["manifests"][0])?)?;
+    let mut config = read_json(&blob_path(dir, &manifest["config"])?)?;
+
+    let layers = manifest["layers"].as_array().cloned().unwrap_or_default();
+    let diff_ids = config["rootfs"]["diff_ids"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    if layers.len() != diff_ids.len() {
+        bail!(
+            "The image in {} has {} layers but {} diff IDs",
+            dir.display(),
+            layers.len(),
+            diff_ids.len()
+        );
+    }
+
+    let sizes = layers
+        .iter()
+        .map(|layer| layer["size"].as_u64().unwrap_or_default())
+        .collect::<Vec<_>>();
+    let groups = plan_groups(&sizes, max_layers, keep_size);
+    if groups.len() > max_layers {
+        warn!(
+            "Only able to merge the image down to {} layers, the other layers are bigger than the keep size",
+            groups.len()
+        );
+    }
+    if groups.len() == layers.len() {
+        return Ok(layers.len());
+    }
+
+    let mut new_layers = Vec::with_capacity(groups.len());
+    let mut new_diff_ids = Vec::with_capacity(groups.len());
+    let mut folded = vec![false; layers.len()];
+    let mut merged_blobs = Vec::new();
+
+    for group in groups {
+        let merged = if group.len() > 1 {
+            let paths = group
+                .clone()
+                .map(|i| blob_path(dir, &layers[i]))
+                .collect::<Result<Vec<_>>>()?;
+            let merged = merge_layer_files(dir, &paths)?;
+            if merged.is_some() {
+                merged_blobs.extend(paths);
+            }
+            merged
+        } else {
+            None
+        };
+
+        if let Some((layer, diff_id)) = merged {
+            debug!("Merged layers {group:?} into {}", layer["digest"]);
+            folded[group.start + 1..group.end].fill(true);
+            new_layers.push(layer);
+            new_diff_ids.push(diff_id);
+        } else {
+            new_layers.extend_from_slice(&layers[group.clone()]);
+            new_diff_ids.extend_from_slice(&diff_ids[group]);
+        }
+    }
+
+    fold_history(&mut config, &folded);
+    config["rootfs"]["diff_ids"] = Value::Array(new_diff_ids);
+    let (config_digest, config_size) = write_json_blob(dir, &config)?;
+    manifest["config"]["digest"] = json!(config_digest);
+    manifest["config"]["size"] = json!(config_size);
+
+    let layer_count = new_layers.len();
+    manifest["layers"] = Value::Array(new_layers);
+    let (manifest_digest, manifest_size) = write_json_blob(dir, &manifest)?;
+    index["manifests"][0]["digest"] = json!(manifest_digest);
+    index["manifests"][0]["size"] = json!(manifest_size);
+    fs::write(&index_path, serde_json::to_vec(&index).into_diagnostic()?)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to write {}", index_path.display()))?;
+
+    // The merged layers aren't referenced anymore, so they'd only take up space
+    for path in merged_blobs {
+        if let Err(e) = fs::remove_file(&path) {
+            trace!("Failed to remove {}: {e}", path.display());
+        }
+    }
+
+    Ok(layer_count)
+}
+
+/// Plans which adjacent layers to merge by repeatedly merging the
+/// pair of neighbours that is the smallest when put together.
+///
+/// Layers that are at least `keep_size` are never merged, so
+/// there can be more than `max_layers` groups when there are
+/// too many big layers.
+fn plan_groups(sizes: &[u64], max_layers: usize, keep_size: u64) -> Vec<Range<usize>> {
+    let size = |group: &Range<usize>| sizes[group.clone()].iter().sum::<u64>();
+    let mergeable = |group: &Range<usize>| sizes[group.clone()].iter().all(|&s| s < keep_size);
+
+    let mut groups = (0..sizes.len()).map(|i| i..i + 1).collect::<Vec<_>>();
+    while groups.len() > max_layers.max(1) {
+        let Some(i) = groups
+            .windows(2)
+            .enumerate()
+            .filter(|(_, pair)| mergeable(&pair[0]) && mergeable(&pair[1]))
+            .min_by_key(|(_, pair)| size(&pair[0]) + size(&pair[1]))
+            .map(|(i, _)| i)
+        else {
+            break;
+        };
+
+        let next = groups.remove(i + 1);
+        groups[i].end = next.end;
+    }
+    groups
+}
+
+/// Marks the history entries of the layers that were
+/// folded into the layer below them as empty layers.
+///
+/// The history is left alone if it doesn't line up with the layers.
+fn fold_history(config: &mut Value, folded: &[bool]) {
+    let Some(history) = config["history"].as_array_mut() else {
+        return;
+    };
+    let is_empty = |entry: &Value| entry["empty_layer"].as_bool().unwrap_or_default();
+
+    if history.iter().filter(|entry| !is_empty(entry)).count() != folded.len() {
+        debug!("The history doesn't line up with the layers, leaving it as is");
+        return;
+    }
+
+    for (entry, &folded) in history
+        .iter_mut()
+        .filter(|entry| !is_empty(entry))
+        .zip(folded)
+    {
+        if folded {
+            entry["empty_layer"] = json!(true);
+        }
+    }
+}
+
+/// Merges the layer blobs at `paths` into a new gzip compressed
+/// blob, returning its descriptor and its diff ID.
+///
+/// Returns `None` if the layers can't be merged without
+/// breaking a hard link.
+fn merge_layer_files(dir: &Path, paths: &[PathBuf]) -> Result<Option<(Value, Value)>> {
+    let mut temp = tempfile::NamedTempFile::new_in(dir).into_diagnostic()?;
+    let (diff_id, digest, size) = {
+        let mut tar = HashWriter::new(GzEncoder::new(
+            HashWriter::new(BufWriter::new(temp.as_file_mut())),
+            Compression::default(),
+        ));
+        if !merge_tars(|i| open_layer(&paths[i]), paths.len(), &mut tar)? {
+            debug!("Not merging layers that would break a hard link");
+            return Ok(None);
+        }
+
+        let (gzip, diff_id, _) = tar.finish();
+        let (file, digest, size) = gzip.finish().into_diagnostic()?.finish();
+        file.into_inner()
+            .map_err(io::IntoInnerError::into_error)
+            .into_diagnostic()?;
+        (diff_id, digest, size)
+    };
+
+    temp.persist(dir.join("blobs/sha256").join(&digest))
+        .into_diagnostic()?;
+
+    Ok(Some((
+        json!({
+            "mediaType": LAYER_GZIP_MEDIA_TYPE,
+            "digest": format!("sha256:{digest}"),
+            "size": size,
+        }),
+        json!(format!("sha256:{diff_id}")),
+    )))
+}
+
+/// Writes the entries of the tar archives that `open` returns into
+/// one archive, leaving out what the later archives replace or remove.
+///
+/// Returns `false` without writing anything if an entry that a
+/// hard link points to would have to be left out.
+fn merge_tars<R: Read>(
+    open: impl Fn(usize) -> Result<R>,
+    count: usize,
+    out: &mut impl Write,
+) -> Result<bool> {
+    let layers = (0..count)
+        .map(|i| {
+            let mut reader = TarReader(open(i)?);
+            let mut entries = Vec::new();
+            while let Some(entry) = reader.next_entry()? {
+                reader.copy_data(&entry, &mut io::sink())?;
+                entries.push(EntryMeta::from(entry));
+            }
+            Ok(entries)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let Some(plan) = plan_entries(&layers) else {
+        return Ok(false);
+    };
+
+    for (i, keep) in plan.iter().enumerate() {
+        let mut reader = TarReader(open(i)?);
+        for keep in keep {
+            let entry = reader
+                .next_entry()?
+                .ok_or_else(|| miette!("Layer {i} changed while it was merged"))?;
+
+            if *keep == Keep::No {
+                reader.copy_data(&entry, &mut io::sink())?;
+                continue;
+            }
+
+            out.write_all(&entry.header).into_diagnostic()?;
+            reader.copy_data(&entry, out)?;
+            if *keep == Keep::WithOpaque {
+                out.write_all(&file_header(&format!("{}/{OPAQUE_WHITEOUT}", entry.path)))
+                    .into_diagnostic()?;
+            }
+        }
+    }
+    out.write_all(&[0; BLOCK_SIZE * 2]).into_diagnostic()?;
+
+    Ok(true)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Keep {
+    No,
+    Yes,
+
+    /// Keep the directory and add an opaque whiteout in it, since it
+    /// replaces a directory that an earlier layer in the group removed.
+    WithOpaque,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    Dir,
+    HardLink,
+    Other,
+}
+
+#[derive(Debug)]
+struct EntryMeta {
+    path: String,
+    kind: EntryKind,
+    link: String,
+}
+
+impl From<TarEntry> for EntryMeta {
+    fn from(entry: TarEntry) -> Self {
+        Self {
+            path: entry.path,
+            kind: entry.kind,
+            link: entry.link,
+        }
+    }
+}
+
+/// Decides which entries of the layers, from the bottom layer
+/// up, make it into the merged layer.
+///
+/// An entry is left out when a later layer has an entry at the same
+/// path, replaces one of its parents with something other than a
+/// directory, or removes it or one of its parents with a whiteout.
+/// Whiteouts are kept for the layers below the merged layer unless
+/// a later layer adds the path back.
+fn plan_entries(layers: &[Vec<EntryMeta>]) -> Option<Vec<Vec<Keep>>> {
+    let link_targets = layers
+        .iter()
+        .flatten()
+        .filter(|entry| entry.kind == EntryKind::HardLink)
+        .map(|entry| &*entry.link)
+        .collect::<HashSet<_>>();
+
+    let mut plan = layers
+        .iter()
+        .map(|entries| vec![Keep::Yes; entries.len()])
+        .collect::<Vec<_>>();
+
+    // What the layers above the current one did
+    let mut latest: HashMap<&str, (usize, usize, EntryKind)> = HashMap::new();
+    let mut removed: HashSet<String> = HashSet::new();
+    let mut opaque: HashSet<&str> = HashSet::new();
+
+    for (layer, entries) in layers.iter().enumerate().rev() {
+        for (index, entry) in entries.iter().enumerate() {
+            let path = &*entry.path;
+            let replaced = match whiteout_target(path) {
+                Some(Whiteout::Opaque(_)) => covered(path, &removed, &opaque),
+                Some(Whiteout::Path(target)) => {
+                    supersede(&mut plan, latest.get(&*target), EntryKind::Other)
+                        || covered(&target, &removed, &opaque)
+                }
+                None => {
+                    supersede(&mut plan, latest.get(path), entry.kind)
+                        || covered(path, &removed, &opaque)
+                }
+            };
+
+            if replaced {
+                if link_targets.contains(path) {
+                    return None;
+                }
+                plan[layer][index] = Keep::No;
+            }
+        }
+
+        for (index, entry) in entries.iter().enumerate() {
+            let path = &*entry.path;
+            match whiteout_target(path) {
+                Some(Whiteout::Opaque(dir)) => {
+                    opaque.insert(dir);
+                }
+                Some(Whiteout::Path(target)) => {
+                    removed.insert(target);
+                }
+                None => {
+                    if entry.kind != EntryKind::Dir {
+                        removed.insert(path.to_owned());
+                    }
+                    if plan[layer][index] != Keep::No {
+                        latest.insert(path, (layer, index, entry.kind));
+                    }
+                }
+            }
+        }
+    }
+
+    Some(plan)
+}
+
+/// Whether an entry of `kind` is superseded by a later entry
+/// at the same path. A later directory gets an opaque whiteout
+/// when it replaces something other than a directory, since that
+/// removed whatever was in the directory before.
+fn supersede(
+    plan: &mut [Vec<Keep>],
+    superseded_by: Option<&(usize, usize, EntryKind)>,
+    kind: EntryKind,
+) -> bool {
+    let Some(&(layer, index, later_kind)) = superseded_by else {
+        return false;
+    };
+    if later_kind == EntryKind::Dir && kind != EntryKind::Dir {
+        plan[layer][index] = Keep::WithOpaque;
+    }
+    true
+}
+
+/// Whether `path` or one of its parents was removed,
+/// or one of its parents was made opaque.
+fn covered(path: &str, removed: &HashSet<String>, opaque: &HashSet<&str>) -> bool {
+    removed.contains(path)
+        || opaque.contains("")
+        || path
+            .match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .any(|dir| removed.contains(dir) || opaque.contains(dir))
+}
+
+enum Whiteout<'a> {
+    /// Hides everything in the directory from the layers below.
+    Opaque(&'a str),
+
+    /// Removes the path from the layers below.
+    Path(String),
+}
+
+fn whiteout_target(path: &str) -> Option<Whiteout<'_>> {
+    let (dir, name) = path.rsplit_once('/').unwrap_or(("", path));
+    if name == OPAQUE_WHITEOUT {
+        return Some(Whiteout::Opaque(dir));
+    }
+
+    let name = name.strip_prefix(WHITEOUT_PREFIX)?;
+    Some(Whiteout::Path(if dir.is_empty() {
+        name.to_owned()
+    } else {
+        format!("{dir}/{name}")
+    }))
+}
+
+struct TarEntry {
+    path: String,
+    kind: EntryKind,
+    link: String,
+
+    /// The header block of the entry, along with any
+    /// extended headers and their data before it.
+    header: Vec<u8>,
+
+    /// The size of the data after the header, padded to whole blocks.
+    data_len: u64,
+}
+
+struct TarReader<R>(R);
+
+impl<R: Read> TarReader<R> {
+    /// Reads the headers of the next entry. Its data
+    /// has to be read with [`Self::copy_data`] next.
+    fn next_entry(&mut self) -> Result<Option<TarEntry>> {
+        let mut header = Vec::new();
+        let (mut path, mut link) = (None, None);
+
+        loop {
+            let mut block = [0; BLOCK_SIZE];
+            match self.0.read_exact(&mut block) {
+                Ok(()) => {}
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e).into_diagnostic(),
+            }
+            if block.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+            header.extend_from_slice(&block);
+
+            let size = parse_size(&block[124..136]);
+            let data_len = size.div_ceil(BLOCK_SIZE as u64) * BLOCK_SIZE as u64;
+
+            let typeflag = block[156];
+            if !matches!(typeflag, b'x' | b'L' | b'K') {
+                return Ok(Some(TarEntry {
+                    path: normalize(&path.unwrap_or_else(|| entry_path(&block))),
+                    kind: match typeflag {
+                        b'5' => EntryKind::Dir,
+                        b'1' => EntryKind::HardLink,
+                        _ => EntryKind::Other,
+                    },
+                    link: normalize(&link.unwrap_or_else(|| c_string(&block[157..257]))),
+                    header,
+                    data_len,
+                }));
+            }
+
+            let start = header.len();
+            header.resize(start + usize::try_from(data_len).into_diagnostic()?, 0);
+            self.0.read_exact(&mut header[start..]).into_diagnostic()?;
+            let data = &header[start..start + usize::try_from(size).into_diagnostic()?];
+
+            match typeflag {
+                b'x' => {
+                    for (key, value) in pax_records(data) {
+                        match &*key {
+                            "path" => path = Some(value),
+                            "linkpath" => link = Some(value),
+                            _ => {}
+                        }
+                    }
+                }
+                b'L' => path = Some(c_string(data)),
+                _ => link = Some(c_string(data)),
+            }
+        }
+    }
+
+    fn copy_data(&mut self, entry: &TarEntry, out: &mut impl Write) -> Result<()> {
+        io::copy(&mut (&mut self.0).take(entry.data_len), out).into_diagnostic()?;
+        Ok(())
+    }
+}
+
+fn normalize(path: &str) -> String {
+    path.trim_start_matches("./")
+        .trim_start_matches('/')
+        .trim_end_matches('/')
+        .to_owned()
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..end]).into_owned()
+}
+
+/// Parses the `<length> <key>=<value>\n` records of a PAX header.
+fn pax_records(mut data: &[u8]) -> Vec<(String, String)> {
+    let mut records = Vec::new();
+
+    while let Some(space) = data.iter().position(|&b| b == b' ') {
+        let Some(len) = std::str::from_utf8(&data[..space])
+            .ok()
+            .and_then(|len| len.parse::<usize>().ok())
+            .filter(|&len| len > space + 1 && len <= data.len())
+        else {
+            break;
+        };
+
+        let record = &data[space + 1..len - 1];
+        if let Some(eq) = record.iter().position(|&b| b == b'=') {
+            records.push((c_string(&record[..eq]), c_string(&record[eq + 1..])));
+        }
+        data = &data[len..];
+    }
+    records
+}
+
+/// The header of an empty file, with a PAX
+/// header before it if the path is too long.
+fn file_header(path: &str) -> Vec<u8> {
+    if path.len() <= 100 {
+        return ustar_header(path, b'0', 0).to_vec();
+    }
+
+    let record = format!(" path={path}\n");
+    let mut len = record.len();
+    while len.to_string().len() + record.len() != len {
+        len = len.to_string().len() + record.len();
+    }
+    let mut data = format!("{len}{record}").into_bytes();
+    let size = data.len();
+    data.resize(size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+
+    let mut header = ustar_header("././@PaxHeader", b'x', size).to_vec();
+    header.extend(data);
+    header.extend(ustar_header(OPAQUE_WHITEOUT, b'0', 0));
+    header
+}
+
+fn ustar_header(name: &str, typeflag: u8, size: usize) -> [u8; BLOCK_SIZE] {
+    let mut header = [0; BLOCK_SIZE];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    header[100..108].copy_from_slice(b"0000644\0");
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    header[124..136].copy_from_slice(format!("{size:011o}\0").as_bytes());
+    header[136..148].copy_from_slice(b"00000000000\0");
+    header[148..156].copy_from_slice(b"        ");
+    header[156] = typeflag;
+    header[257..263].copy_from_slice(b"ustar\0");
+    header[263..265].copy_from_slice(b"00");
+
+    let checksum = header.iter().map(|&b| u32::from(b)).sum::<u32>();
+    header[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+    header
+}
+
+fn open_layer(path: &Path) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(
+        File::open(path)
+            .into_diagnostic()
+            .wrap_err_with(|| format!("Failed to open layer {}", path.display()))?,
+    );
+    let magic = reader.fill_buf().into_diagnostic()?;
+
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(BufReader::new(MultiGzDecoder::new(reader))))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        bail!(
+            "Layer {} is zstd compressed, which can't be merged",
+            path.display()
+        );
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+fn blob_path(dir: &Path, descriptor: &Value) -> Result<PathBuf> {
+    let digest = descriptor["digest"]
+        .as_str()
+        .ok_or_else(|| miette!("Missing digest in {descriptor}"))?;
+    let (algorithm, hash) = digest
+        .split_once(':')
+        .ok_or_else(|| miette!("Invalid digest {digest}"))?;
+    Ok(dir.join("blobs").join(algorithm).join(hash))
+}
+
+fn read_json(path: &Path) -> Result<Value> {
+    serde_json::from_slice(&fs::read(path).into_diagnostic()?)
+        .into_diagnostic()
+        .wrap_err_with(|| format!("Failed to parse {}", path.display()))
+}
+
+fn write_json_blob(dir: &Path, value: &Value) -> Result<(String, usize)> {
+    let blob = serde_json::to_vec(value).into_diagnostic()?;
+    let digest = format!("{:x}", Sha256::digest(&blob));
+    fs::write(dir.join("blobs/sha256").join(&digest), &blob).into_diagnostic()?;
+    Ok((format!("sha256:{digest}"), blob.len()))
+}
+
+/// Hashes and counts everything that's written through it.
+struct HashWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    len: u64,
+}
+
+impl<W: Write> HashWriter<W> {
+    fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            len: 0,
+        }
+    }
+
+    /// Returns the inner writer with the hex
+    /// digest and length of what was written.
+    fn finish(self) -> (W, String, u64) {
+        (
+            self.inner,
+            format!("{:x}", self.hasher.finalize()),
+            self.len,
+        )
+    }
+}
+
+impl<W: Write> Write for HashWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.len += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Cursor;
+
+    use rstest::rstest;
+
+    use super::{merge_tars, plan_groups, TarReader, BLOCK_SIZE};
+
+    const MIB: u64 = 1024 * 1024;
+
+    fn tar_entry(path: &str, typeflag: u8, link: &str, contents: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; BLOCK_SIZE];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        header[124..136].copy_from_slice(format!("{:011o}\0", contents.len()).as_bytes());
+        header[156] = typeflag;
+        header[157..157 + link.len()].copy_from_slice(link.as_bytes());
+        header[257..263].copy_from_slice(b"ustar\0");
+
+        let mut entry = header;
+        entry.extend_from_slice(contents);
+        entry.resize(entry.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        entry
+    }
+
+    fn file(path: &str, contents: &str) -> Vec<u8> {
+        tar_entry(path, b'0', "", contents.as_bytes())
+    }
+
+    fn dir(path: &str) -> Vec<u8> {
+        tar_entry(path, b'5', "", &[])
+    }
+
+    fn tar(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut tar = entries.concat();
+        tar.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+        tar
+    }
+
+    /// Merges the layers and lists the paths
+    /// in the merged layer with their contents.
+    fn merge(layers: &[Vec<u8>]) -> Option<Vec<(String, String)>> {
+        let mut merged = Vec::new();
+        if !merge_tars(|i| Ok(Cursor::new(&layers[i])), layers.len(), &mut merged).unwrap() {
+            return None;
+        }
+
+        let mut reader = TarReader(Cursor::new(merged));
+        let mut entries = Vec::new();
+        while let Some(entry) = reader.next_entry().unwrap() {
+            let mut data = Vec::new();
+            reader.copy_data(&entry, &mut data).unwrap();
+            let contents = String::from_utf8(data).unwrap();
+            entries.push((entry.path, contents.trim_end_matches('\0').to_owned()));
+        }
+        Some(entries)
+    }
+
+    fn entries(expected: &[(&str, &str)]) -> Vec<(String, String)> {
+        expected
+            .iter()
+            .map(|(path, contents)| ((*path).to_owned(), (*contents).to_owned()))
+            .collect()
+    }
+
+    #[rstest]
+    #[case(&[MIB; 4], 4, &[0..1, 1..2, 2..3, 3..4])]
+    #[case(&[MIB, 2 * MIB, MIB, 5 * MIB], 2, &[0..3, 3..4])]
+    #[case(&[100 * MIB, MIB, MIB, 100 * MIB, MIB], 3, &[0..1, 1..3, 3..4, 4..5])]
+    #[case(&[100 * MIB, MIB, 100 * MIB, MIB, 100 * MIB], 2, &[0..1, 1..2, 2..3, 3..4, 4..5])]
+    #[case(&[MIB, MIB, 100 * MIB], 1, &[0..2, 2..3])]
+    fn groups(
+        #[case] sizes: &[u64],
+        #[case] max_layers: usize,
+        #[case] expected: &[std::ops::Range<usize>],
+    ) {
+        assert_eq!(plan_groups(sizes, max_layers, 64 * MIB), expected);
+    }
+
+    #[test]
+    fn later_layers_win() {
+        let merged = merge(&[
+            tar(&[dir("etc/"), file("etc/a", "old"), file("etc/b", "b")]),
+            tar(&[file("./etc/a", "new"), file("etc/.wh.b", "")]),
+        ]);
+
+        // The whiteout still has to remove `etc/b` from the layers below
+        assert_eq!(
+            merged,
+            Some(entries(&[("etc", ""), ("etc/a", "new"), ("etc/.wh.b", "")]))
+        );
+    }
+
+    #[test]
+    fn whiteouts_of_lower_layers_are_kept() {
+        let merged = merge(&[
+            tar(&[file("etc/.wh.c", ""), file("var/.wh..wh..opq", "")]),
+            tar(&[file("var/d", "d")]),
+        ]);
+
+        assert_eq!(
+            merged,
+            Some(entries(&[
+                ("etc/.wh.c", ""),
+                ("var/.wh..wh..opq", ""),
+                ("var/d", "d")
+            ]))
+        );
+    }
+
+    #[test]
+    fn opaque_dirs_hide_earlier_contents() {
+        let merged = merge(&[
+            tar(&[dir("var/"), file("var/old", "old")]),
+            tar(&[file("var/.wh..wh..opq", ""), file("var/new", "new")]),
+        ]);
+
+        assert_eq!(
+            merged,
+            Some(entries(&[
+                ("var", ""),
+                ("var/.wh..wh..opq", ""),
+                ("var/new", "new")
+            ]))
+        );
+    }
+
+    #[test]
+    fn readded_dirs_become_opaque() {
+        let merged = merge(&[
+            tar(&[file(".wh.opt", ""), file("usr/x", "file")]),
+            tar(&[dir("opt/"), file("opt/n", "n"), dir("usr/x/")]),
+        ]);
+
+        assert_eq!(
+            merged,
+            Some(entries(&[
+                ("opt", ""),
+                ("opt/.wh..wh..opq", ""),
+                ("opt/n", "n"),
+                ("usr/x", ""),
+                ("usr/x/.wh..wh..opq", ""),
+            ]))
+        );
+    }
+
+    #[test]
+    fn hard_link_targets_are_not_dropped() {
+        let merged = merge(&[
+            tar(&[file("bin/a", "a"), tar_entry("bin/b", b'1', "bin/a", &[])]),
+            tar(&[file("bin/a", "new")]),
+        ]);
+
+        assert_eq!(merged, None);
+    }
+
+    #[test]
+    fn long_opaque_whiteouts() {
+        let path = format!("{}/.wh..wh..opq", "a".repeat(120));
+        let mut layer = super::file_header(&path);
+        layer.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+
+        let mut reader = TarReader(Cursor::new(layer));
+        assert_eq!(reader.next_entry().unwrap().unwrap().path, path);
+        assert!(reader.next_entry().unwrap().is_none());
+    }
+}
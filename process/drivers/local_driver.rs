@@ -28,7 +28,7 @@ impl CiDriver for LocalDriver {
             .platform(opts.platform)
             .call()?;
         let timestamp = blue_build_utils::get_tag_timestamp();
-        let short_sha = commit_sha();
+        let short_sha = commit_sha(true);
 
         Ok(opts.alt_tags.as_ref().map_or_else(
             || {
@@ -75,13 +75,38 @@ impl CiDriver for LocalDriver {
         Ok(String::from("localhost"))
     }
 
+    fn get_commit_sha() -> Option<String> {
+        trace!("LocalDriver::get_commit_sha()");
+        commit_sha(false)
+    }
+
+    fn get_branch() -> Option<String> {
+        trace!("LocalDriver::get_branch()");
+        let output = cmd!("git", "rev-parse", "--abbrev-ref", "HEAD")
+            .output()
+            .ok()?;
+
+        // A detached HEAD isn't on a branch
+        String::from_utf8(output.stdout)
+            .ok()
+            .map(|branch| branch.trim().to_owned())
+            .filter(|branch| output.status.success() && branch != "HEAD")
+    }
+
     fn default_ci_file_path() -> PathBuf {
         unimplemented!()
     }
 }
 
-fn commit_sha() -> Option<String> {
-    let output = cmd!("git", "rev-parse", "--short", "HEAD").output().ok()?;
+fn commit_sha(short: bool) -> Option<String> {
+    let output = cmd!(
+        "git",
+        "rev-parse",
+        if short => "--short",
+        "HEAD"
+    )
+    .output()
+    .ok()?;
 
     if output.status.success() {
         String::from_utf8(output.stdout)
@@ -0,0 +1,162 @@
+//! Inspection of images in the local storage of the run driver.
+//!
+//! This is used instead of the inspect drivers when building
+//! offline, so images are never looked up in their registry.
+
+use std::collections::HashMap;
+
+use blue_build_utils::cmd;
+use colored::Colorize;
+use log::{debug, trace};
+use miette::{bail, miette, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+use serde::Deserialize;
+
+use super::{
+    opts::GetMetadataOpts,
+    types::{ImageConfig, ImageLayer, ImageMetadata},
+    Driver,
+};
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct LocalImageMetadata {
+    /// Only podman has the digest of the image itself.
+    #[serde(default)]
+    digest: Option<String>,
+
+    #[serde(default)]
+    repo_digests: Option<Vec<String>>,
+
+    #[serde(default)]
+    config: LocalImageConfig,
+
+    #[serde(default, rename = "RootFS")]
+    root_fs: LocalRootFs,
+
+    #[serde(default)]
+    size: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+struct LocalImageConfig {
+    #[serde(default)]
+    labels: Option<HashMap<String, serde_json::Value>>,
+
+    #[serde(flatten)]
+    run: ImageConfig,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+struct LocalRootFs {
+    #[serde(default)]
+    layers: Vec<String>,
+}
+
+impl LocalImageMetadata {
+    /// The digest of the image, preferring the
+    /// repo digest of the repo that was inspected.
+    fn digest_of(&self, image: &Reference) -> Option<String> {
+        if let Some(digest) = image.digest() {
+            return Some(digest.to_owned());
+        }
+
+        self.repo_digests
+            .iter()
+            .flatten()
+            .filter_map(|repo_digest| repo_digest.parse::<Reference>().ok())
+            .find(|repo_digest| {
+                repo_digest.resolve_registry() == image.resolve_registry()
+                    && repo_digest.repository() == image.repository()
+            })
+            .and_then(|repo_digest| repo_digest.digest().map(ToOwned::to_owned))
+            .or_else(|| self.digest.clone())
+    }
+}
+
+pub(super) async fn get_metadata(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+    trace!("local_inspect::get_metadata({opts:#?})");
+
+    let image_str = opts.image.to_string();
+    let command = cmd!(
+        String::from(Driver::get_run_driver()),
+        "image",
+        "inspect",
+        &image_str,
+    );
+    trace!("{command:?}");
+
+    let output = tokio::process::Command::from(command)
+        .output()
+        .await
+        .into_diagnostic()?;
+
+    if !output.status.success() {
+        bail!(
+            help = "Pull the image before building offline",
+            "The image {} isn't in local storage",
+            image_str.bold().red()
+        );
+    }
+    debug!("Inspected local image {}", image_str.bold().green());
+
+    let metadata = serde_json::from_slice::<Vec<LocalImageMetadata>>(&output.stdout)
+        .into_diagnostic()?
+        .into_iter()
+        .next()
+        .ok_or_else(|| miette!("No metadata for the local image {image_str}"))?;
+    trace!("{metadata:#?}");
+
+    Ok(ImageMetadata {
+        digest: metadata
+            .digest_of(opts.image)
+            .ok_or_else(|| miette!("The local image {image_str} doesn't have a digest"))?,
+        labels: metadata.config.labels.unwrap_or_default(),
+        config: metadata.config.run,
+        layers: metadata
+            .root_fs
+            .layers
+            .into_iter()
+            .map(|digest| ImageLayer { digest, size: None })
+            .collect(),
+        history: Vec::new(),
+        size: metadata.size,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use oci_distribution::Reference;
+
+    use super::LocalImageMetadata;
+
+    #[test]
+    fn digest_of_repo() {
+        let metadata: Vec<LocalImageMetadata> = serde_json::from_str(
+            r#"[{
+                "Digest": "sha256:1111111111111111111111111111111111111111111111111111111111111111",
+                "RepoDigests": [
+                    "quay.io/fedora/fedora@sha256:2222222222222222222222222222222222222222222222222222222222222222",
+                    "ghcr.io/ublue-os/silverblue-main@sha256:3333333333333333333333333333333333333333333333333333333333333333"
+                ],
+                "Config": {
+                    "Labels": { "org.opencontainers.image.version": "41.20241029" }
+                }
+            }]"#,
+        )
+        .unwrap();
+        let image: Reference = "ghcr.io/ublue-os/silverblue-main:41".parse().unwrap();
+        let other: Reference = "ghcr.io/ublue-os/bazzite:stable".parse().unwrap();
+
+        assert_eq!(
+            metadata[0].digest_of(&image).unwrap(),
+            "sha256:3333333333333333333333333333333333333333333333333333333333333333"
+        );
+        assert_eq!(
+            metadata[0].digest_of(&other).unwrap(),
+            "sha256:1111111111111111111111111111111111111111111111111111111111111111"
+        );
+    }
+}
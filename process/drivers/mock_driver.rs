@@ -0,0 +1,496 @@
+//! A driver that doesn't run anything, for testing code that uses
+//! the drivers without docker, podman, or a registry.
+//!
+//! The mock driver records every operation that it's asked to do and
+//! returns the canned results that it was given. It is selected for
+//! every kind of driver by initializing the drivers with
+//! [`MockDriver::args`]:
+//!
+//! ```
+//! use blue_build_process_management::drivers::{
+//!     opts::TagOpts, BuildDriver, Driver, MockDriver, MockResults,
+//! };
+//!
+//! MockDriver::set_results(MockResults::builder().tags(vec!["41".into()]).build());
+//! Driver::init(MockDriver::args());
+//!
+//! let image = "ghcr.io/octocat/my-image".parse().unwrap();
+//! let dest = "ghcr.io/octocat/my-image:41".parse().unwrap();
+//! Driver::tag(&TagOpts::builder().src_image(&image).dest_image(&dest).build()).unwrap();
+//!
+//! let calls = MockDriver::take_calls();
+//! assert_eq!(calls[0].operation, "tag");
+//! assert_eq!(calls[0].opts["dest_image"], "ghcr.io/octocat/my-image:41");
+//! ```
+//!
+//! Like the real inspect drivers, the metadata of an image is cached
+//! once it's inspected, unless it's inspected with `fresh`.
+//!
+//! The calls and results are shared by the whole process, so tests
+//! that use the mock driver at the same time see each other's calls.
+
+use std::{
+    collections::{HashMap, HashSet},
+    future::{self, Future},
+    os::unix::process::ExitStatusExt,
+    path::PathBuf,
+    process::{ExitStatus, Output},
+    sync::{LazyLock, Mutex},
+};
+
+use blue_build_utils::constants::IMAGE_VERSION_LABEL;
+use bon::Builder;
+use log::trace;
+use miette::{bail, IntoDiagnostic, Result};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use super::{
+    external_driver::{
+        build_context_json, build_json, build_stage_json, get_metadata_json, push_json, sign_json,
+        tag_json, verify_json,
+    },
+    opts::{
+        BuildContext, BuildOpts, BuildStageOpts, CheckKeyPairOpts, GenerateKeyPairOpts,
+        GenerateTagsOpts, GetMetadataOpts, PushOpts, RunOpts, SignOpts, TagOpts, VerifyOpts,
+    },
+    types::{
+        BuildDriverType, CiDriverType, ImageMetadata, InspectDriverType, RunDriverType,
+        SigningDriverType,
+    },
+    BuildDriver, CiDriver, DriverArgs, InspectDriver, RunDriver, SigningDriver,
+};
+use crate::summary::BuildSummary;
+
+static STATE: LazyLock<Mutex<MockState>> = LazyLock::new(|| Mutex::new(MockState::default()));
+
+#[derive(Debug, Default)]
+struct MockState {
+    results: MockResults,
+    calls: Vec<MockCall>,
+
+    /// The metadata that was inspected, by image and platform.
+    inspected: HashMap<String, ImageMetadata>,
+}
+
+/// An operation that the mock driver was asked to do.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MockCall {
+    /// The kind of driver, which is `build`, `inspect`,
+    /// `run`, `signing`, or `ci`.
+    pub driver: &'static str,
+
+    /// The name of the operation, like `build` or `get_metadata`.
+    pub operation: &'static str,
+
+    /// The options of the operation. These are the same
+    /// `opts` that are sent to an external driver.
+    pub opts: Value,
+}
+
+/// The results that the mock driver returns.
+#[derive(Debug, Clone, Builder)]
+pub struct MockResults {
+    /// The metadata of images that aren't in `images`.
+    ///
+    /// The default has an empty digest and the version 41.
+    #[builder(default = default_metadata())]
+    pub metadata: ImageMetadata,
+
+    /// The metadata of images by their reference,
+    /// like `ghcr.io/ublue-os/silverblue-main:41`.
+    #[builder(default)]
+    pub images: HashMap<String, ImageMetadata>,
+
+    /// The metadata that images have once they're pushed,
+    /// by their reference. Pushing one of them replaces
+    /// its metadata in `images`.
+    #[builder(default)]
+    pub pushed: HashMap<String, ImageMetadata>,
+
+    /// What running a container prints to stdout.
+    #[builder(default, into)]
+    pub run_stdout: String,
+
+    /// The tags that are generated for an image.
+    #[builder(default = vec![String::from("latest")])]
+    pub tags: Vec<String>,
+
+    #[builder(default = String::from("localhost"), into)]
+    pub registry: String,
+
+    #[builder(default, into)]
+    pub repo_url: String,
+
+    #[builder(into)]
+    pub commit_sha: Option<String>,
+
+    #[builder(into)]
+    pub branch: Option<String>,
+
+    #[builder(default)]
+    pub on_default_branch: bool,
+
+    /// The operations that fail, like `push` or `sign`.
+    #[builder(default)]
+    pub failures: HashSet<&'static str>,
+}
+
+impl Default for MockResults {
+    fn default() -> Self {
+        Self::builder().build()
+    }
+}
+
+fn default_metadata() -> ImageMetadata {
+    ImageMetadata {
+        labels: HashMap::from([(IMAGE_VERSION_LABEL.to_owned(), json!("41"))]),
+        digest: format!("sha256:{}", "0".repeat(64)),
+        ..ImageMetadata::default()
+    }
+}
+
+#[derive(Debug)]
+pub struct MockDriver;
+
+impl MockDriver {
+    /// The args that select the mock driver for every kind of driver.
+    #[must_use]
+    pub fn args() -> DriverArgs {
+        DriverArgs::builder()
+            .build_driver(BuildDriverType::Mock)
+            .inspect_driver(InspectDriverType::Mock)
+            .signing_driver(SigningDriverType::Mock)
+            .run_driver(RunDriverType::Mock)
+            .ci_driver(CiDriverType::Mock)
+            .containerized(false)
+            .build()
+    }
+
+    /// Sets the results that the mock driver returns
+    /// and forgets the metadata that was cached.
+    ///
+    /// # Panics
+    /// Will panic if the mutex cannot be locked.
+    pub fn set_results(results: MockResults) {
+        let mut state = STATE.lock().expect("Should lock");
+        state.results = results;
+        state.inspected.clear();
+    }
+
+    /// The operations that were done so far.
+    ///
+    /// # Panics
+    /// Will panic if the mutex cannot be locked.
+    #[must_use]
+    pub fn calls() -> Vec<MockCall> {
+        STATE.lock().expect("Should lock").calls.clone()
+    }
+
+    /// Returns the operations that were done so far and forgets them.
+    ///
+    /// # Panics
+    /// Will panic if the mutex cannot be locked.
+    #[must_use]
+    pub fn take_calls() -> Vec<MockCall> {
+        std::mem::take(&mut STATE.lock().expect("Should lock").calls)
+    }
+
+    /// Records an operation and returns its result
+    /// from the results, or an error if it fails.
+    fn call<T>(
+        driver: &'static str,
+        operation: &'static str,
+        opts: Value,
+        result: impl FnOnce(&MockResults) -> T,
+    ) -> Result<T> {
+        trace!("MockDriver::call({driver}, {operation}, {opts})");
+
+        let mut state = STATE.lock().expect("Should lock");
+        state.calls.push(MockCall {
+            driver,
+            operation,
+            opts,
+        });
+        if state.results.failures.contains(operation) {
+            bail!("The mock {driver} driver failed to {operation}");
+        }
+        Ok(result(&state.results))
+    }
+}
+
+impl BuildDriver for MockDriver {
+    fn build(opts: &BuildOpts) -> Result<()> {
+        Self::call("build", "build", build_json(opts), |_| ())
+    }
+
+    fn build_stage(opts: &BuildStageOpts) -> Result<BuildContext> {
+        Self::call("build", "build_stage", build_stage_json(opts), |_| {
+            BuildContext {
+                name: opts.stage.to_string(),
+                location: format!("mock/{}", opts.stage),
+            }
+        })
+    }
+
+    fn remove_stage(context: &BuildContext) -> Result<()> {
+        Self::call("build", "remove_stage", build_context_json(context), |_| ())
+    }
+
+    fn tag(opts: &TagOpts) -> Result<()> {
+        Self::call("build", "tag", tag_json(opts), |_| ())
+    }
+
+    fn push(opts: &PushOpts) -> Result<()> {
+        Self::call("build", "push", push_json(opts), |_| ())?;
+
+        let image = opts.image.to_string();
+        let mut state = STATE.lock().expect("Should lock");
+        if let Some(metadata) = state.results.pushed.get(&image).cloned() {
+            state.results.images.insert(image, metadata);
+        }
+        drop(state);
+        Ok(())
+    }
+
+    fn login() -> Result<()> {
+        Self::call("build", "login", json!({}), |_| ())
+    }
+
+    #[cfg(feature = "prune")]
+    fn prune(opts: &super::opts::PruneOpts) -> Result<()> {
+        Self::call(
+            "build",
+            "prune",
+            json!({ "all": opts.all, "volumes": opts.volumes }),
+            |_| (),
+        )
+    }
+}
+
+impl InspectDriver for MockDriver {
+    fn get_metadata_async(
+        opts: &GetMetadataOpts<'_>,
+    ) -> impl Future<Output = Result<ImageMetadata>> + Send {
+        let image = opts.image.to_string();
+        let key = format!("{image}-{}", opts.platform);
+        let fresh = opts.fresh;
+
+        future::ready(
+            Self::call(
+                "inspect",
+                "get_metadata",
+                get_metadata_json(opts),
+                |results| {
+                    results
+                        .images
+                        .get(&image)
+                        .unwrap_or(&results.metadata)
+                        .clone()
+                },
+            )
+            .map(|metadata| {
+                let mut state = STATE.lock().expect("Should lock");
+                if fresh {
+                    state.inspected.insert(key, metadata.clone());
+                    metadata
+                } else {
+                    state.inspected.entry(key).or_insert(metadata).clone()
+                }
+            }),
+        )
+    }
+}
+
+impl RunDriver for MockDriver {
+    fn run(opts: &RunOpts) -> Result<ExitStatus> {
+        Self::run_output(opts).map(|output| output.status)
+    }
+
+    fn run_output(opts: &RunOpts) -> Result<Output> {
+        Self::call(
+            "run",
+            "run",
+            json!({
+                "image": opts.image,
+                "args": opts.args,
+                "env": opts
+                    .env_vars
+                    .iter()
+                    .map(|env| format!("{}={}", env.key, env.value))
+                    .collect::<Vec<_>>(),
+                "privileged": opts.privileged,
+                "pull": opts.pull,
+                "remove": opts.remove,
+            }),
+            |results| Output {
+                status: ExitStatus::from_raw(0),
+                stdout: results.run_stdout.clone().into_bytes(),
+                stderr: Vec::new(),
+            },
+        )
+    }
+
+    /// Has every image the inspect driver has, even offline.
+    fn get_local_metadata_async(
+        opts: &GetMetadataOpts<'_>,
+    ) -> impl Future<Output = Result<ImageMetadata>> + Send {
+        Self::get_metadata_async(opts)
+    }
+}
+
+impl SigningDriver for MockDriver {
+    fn generate_key_pair(opts: &GenerateKeyPairOpts) -> Result<()> {
+        Self::call(
+            "signing",
+            "generate_key_pair",
+            json!({ "dir": opts.dir }),
+            |_| (),
+        )
+    }
+
+    fn check_signing_files(opts: &CheckKeyPairOpts) -> Result<()> {
+        Self::call(
+            "signing",
+            "check_signing_files",
+            json!({ "dir": opts.dir }),
+            |_| (),
+        )
+    }
+
+    fn sign(opts: &SignOpts) -> Result<()> {
+        Self::call("signing", "sign", sign_json(opts), |_| ())
+    }
+
+    fn verify(opts: &VerifyOpts) -> Result<()> {
+        Self::call("signing", "verify", verify_json(opts), |_| ())
+    }
+
+    fn signing_login() -> Result<()> {
+        Self::call("signing", "signing_login", json!({}), |_| ())
+    }
+}
+
+impl CiDriver for MockDriver {
+    fn on_default_branch() -> bool {
+        Self::call("ci", "on_default_branch", json!({}), |results| {
+            results.on_default_branch
+        })
+        .unwrap_or_default()
+    }
+
+    fn keyless_cert_identity() -> Result<String> {
+        Self::call("ci", "keyless_cert_identity", json!({}), |results| {
+            format!(
+                "{}//.github/workflows/build.yml@refs/heads/main",
+                results.repo_url
+            )
+        })
+    }
+
+    fn oidc_provider() -> Result<String> {
+        Self::call("ci", "oidc_provider", json!({}), |_| {
+            String::from("https://token.actions.githubusercontent.com")
+        })
+    }
+
+    /// Signs keylessly like the CI drivers.
+    fn signs_keylessly() -> bool {
+        true
+    }
+
+    fn generate_tags(opts: &GenerateTagsOpts) -> Result<Vec<String>> {
+        Self::call(
+            "ci",
+            "generate_tags",
+            json!({
+                "oci_ref": opts.oci_ref.to_string(),
+                "alt_tags": opts.alt_tags,
+                "platform": opts.platform.to_string(),
+            }),
+            |results| results.tags.clone(),
+        )
+    }
+
+    fn get_repo_url() -> Result<String> {
+        Self::call("ci", "get_repo_url", json!({}), |results| {
+            results.repo_url.clone()
+        })
+    }
+
+    fn get_registry() -> Result<String> {
+        Self::call("ci", "get_registry", json!({}), |results| {
+            results.registry.clone()
+        })
+    }
+
+    fn get_commit_sha() -> Option<String> {
+        Self::call("ci", "get_commit_sha", json!({}), |results| {
+            results.commit_sha.clone()
+        })
+        .ok()
+        .flatten()
+    }
+
+    fn get_branch() -> Option<String> {
+        Self::call("ci", "get_branch", json!({}), |results| {
+            results.branch.clone()
+        })
+        .ok()
+        .flatten()
+    }
+
+    fn default_ci_file_path() -> PathBuf {
+        PathBuf::from(".github/workflows/build.yml")
+    }
+
+    fn report_summary(summary: &BuildSummary) -> Result<()> {
+        Self::call(
+            "ci",
+            "report_summary",
+            serde_json::to_value(summary).into_diagnostic()?,
+            |_| (),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::HashSet, path::Path};
+
+    use oci_distribution::Reference;
+
+    use super::{MockDriver, MockResults};
+    use crate::drivers::{opts::BuildTagPushOpts, BuildDriver};
+
+    #[test]
+    fn records_calls() {
+        MockDriver::set_results(
+            MockResults::builder()
+                .failures(HashSet::from(["push"]))
+                .build(),
+        );
+        let image: Reference = "ghcr.io/octocat/test".parse().unwrap();
+
+        let result = MockDriver::build_tag_push(
+            &BuildTagPushOpts::builder()
+                .image(&image)
+                .containerfile(Path::new("Containerfile"))
+                .tags(vec!["41".into()])
+                .push(true)
+                .build(),
+        );
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "The mock build driver failed to push"
+        );
+        let calls = MockDriver::take_calls();
+        assert_eq!(
+            calls.iter().map(|call| call.operation).collect::<Vec<_>>(),
+            ["build", "tag", "push"]
+        );
+        assert_eq!(calls[0].opts["image"], "ghcr.io/octocat/test:latest");
+        assert_eq!(calls[1].opts["dest_image"], "ghcr.io/octocat/test:41");
+    }
+}
@@ -0,0 +1,108 @@
+//! Inspection of images straight from their registry.
+//!
+//! This doesn't need any container tools to be installed, but only
+//! knows about the credentials given to `bluebuild` itself.
+
+use std::{collections::HashMap, time::Duration};
+
+use cached::proc_macro::cached;
+use colored::Colorize;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, trace};
+use miette::{IntoDiagnostic, Result};
+use oci_distribution::{client::ClientConfig, Client};
+use serde::Deserialize;
+
+use crate::logging::Logger;
+
+use super::{
+    opts::GetMetadataOpts,
+    os_release::{registry_auth, resolve_platform},
+    types::{ImageConfig, ImageHistory, ImageLayer, ImageMetadata},
+    InspectDriver,
+};
+
+#[derive(Debug)]
+pub struct OciClientDriver;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+struct OciConfig {
+    #[serde(default)]
+    config: OciRunConfig,
+
+    #[serde(default)]
+    history: Vec<ImageHistory>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+struct OciRunConfig {
+    #[serde(default)]
+    labels: Option<HashMap<String, serde_json::Value>>,
+
+    #[serde(flatten)]
+    run: ImageConfig,
+}
+
+impl InspectDriver for OciClientDriver {
+    async fn get_metadata_async(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+        if opts.fresh {
+            get_metadata_cache_prime_cache(opts).await
+        } else {
+            get_metadata_cache(opts).await
+        }
+    }
+}
+
+#[cached(
+    result = true,
+    key = "String",
+    convert = r#"{ format!("{}-{}", opts.image, opts.platform)}"#
+)]
+async fn get_metadata_cache(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+    trace!("OciClientDriver::get_metadata({opts:#?})");
+
+    let image_str = opts.image.to_string();
+    let platform = opts.platform;
+
+    let progress = Logger::multi_progress().add(
+        ProgressBar::new_spinner()
+            .with_style(ProgressStyle::default_spinner())
+            .with_message(format!("Inspecting metadata for {}", image_str.bold())),
+    );
+    progress.enable_steady_tick(Duration::from_millis(100));
+
+    let client = Client::new(ClientConfig {
+        platform_resolver: Some(Box::new(move |entries| resolve_platform(entries, platform))),
+        ..Default::default()
+    });
+    let result = client
+        .pull_manifest_and_config(opts.image, &registry_auth(opts.image))
+        .await
+        .into_diagnostic();
+
+    progress.finish_and_clear();
+    Logger::multi_progress().remove(&progress);
+
+    let (manifest, digest, config) = result?;
+    debug!("Successfully inspected image {}!", image_str.bold().green());
+
+    let config: OciConfig = serde_json::from_str(&config).into_diagnostic()?;
+
+    Ok(ImageMetadata {
+        labels: config.config.labels.unwrap_or_default(),
+        digest,
+        config: config.config.run,
+        layers: manifest
+            .layers
+            .into_iter()
+            .map(|layer| ImageLayer {
+                digest: layer.digest,
+                size: u64::try_from(layer.size).ok(),
+            })
+            .collect(),
+        history: config.history,
+        size: None,
+    })
+    .inspect(|metadata| trace!("{metadata:#?}"))
+}
@@ -1,13 +1,25 @@
-use std::{borrow::Cow, path::Path};
+use std::{
+    borrow::Cow,
+    env,
+    fmt::Display,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
 
 use bon::Builder;
+use miette::bail;
 use oci_distribution::Reference;
 
 use crate::drivers::types::Platform;
 
 use super::CompressionType;
 
+/// The maximum number of tags that are pushed at once by default.
+pub const DEFAULT_PUSH_JOBS: NonZeroUsize = NonZeroUsize::new(4).unwrap();
+
 /// Options for building
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone, Builder)]
 pub struct BuildOpts<'scope> {
     #[builder(into)]
@@ -24,6 +36,222 @@ pub struct BuildOpts<'scope> {
 
     #[builder(default)]
     pub host_network: bool,
+
+    /// Secrets that `RUN` instructions can mount.
+    #[builder(default, into)]
+    pub secrets: Vec<BuildSecret>,
+
+    /// Only build up to this stage of the Containerfile.
+    #[builder(into)]
+    pub target: Option<Cow<'scope, str>>,
+
+    /// Named contexts that the Containerfile copies from, like
+    /// stages that were built separately and replace the stages
+    /// of the same name.
+    #[builder(default, into)]
+    pub build_contexts: Vec<BuildContext>,
+
+    /// Registry repos to use as a layer cache.
+    #[builder(default)]
+    pub cache: BuildCache,
+
+    /// Pass the proxy env vars, like `HTTPS_PROXY`, into the build.
+    #[builder(default = true)]
+    pub proxy: bool,
+
+    /// Pull the images used by the build. When not set,
+    /// the images must already be in local storage.
+    #[builder(default = true)]
+    pub pull: bool,
+
+    /// Raw options appended to the build command.
+    #[builder(default, into)]
+    pub extra_args: Vec<ExtraArg>,
+}
+
+/// The proxy env vars that are passed into builds. These are
+/// predefined build args, so they don't need to be declared with
+/// `ARG` and aren't stored in the history of the image.
+pub const PROXY_BUILD_ARGS: [&str; 8] = [
+    "HTTP_PROXY",
+    "HTTPS_PROXY",
+    "NO_PROXY",
+    "ALL_PROXY",
+    "http_proxy",
+    "https_proxy",
+    "no_proxy",
+    "all_proxy",
+];
+
+/// The proxy env vars that are set, which
+/// are passed into builds when `proxy` is set.
+pub fn proxy_build_args(proxy: bool) -> impl Iterator<Item = &'static str> {
+    PROXY_BUILD_ARGS
+        .into_iter()
+        .filter(move |var| proxy && env::var_os(var).is_some())
+}
+
+/// A named context that is given to a build with
+/// `--build-context <name>=<location>`, like a stage
+/// that was built on its own.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildContext {
+    pub name: String,
+    pub location: String,
+}
+
+impl BuildContext {
+    /// The value for the `--build-context` arg of the build.
+    #[must_use]
+    pub fn arg(&self) -> String {
+        format!("{}={}", self.name, self.location)
+    }
+}
+
+/// Options for building a single stage of a Containerfile.
+#[derive(Debug, Clone, Builder)]
+pub struct BuildStageOpts<'scope> {
+    /// The name of the stage to build.
+    #[builder(into)]
+    pub stage: Cow<'scope, str>,
+
+    #[builder(into)]
+    pub containerfile: Cow<'scope, Path>,
+
+    #[builder(default)]
+    pub platform: Platform,
+
+    /// Secrets that `RUN` instructions can mount.
+    #[builder(default, into)]
+    pub secrets: Vec<BuildSecret>,
+
+    /// Named contexts that the Containerfile copies from.
+    #[builder(default, into)]
+    pub build_contexts: Vec<BuildContext>,
+
+    /// Registry repos to use as a layer cache.
+    #[builder(default)]
+    pub cache: BuildCache,
+
+    /// Pass the proxy env vars, like `HTTPS_PROXY`, into the build.
+    #[builder(default = true)]
+    pub proxy: bool,
+
+    /// Pull the images used by the build. When not set,
+    /// the images must already be in local storage.
+    #[builder(default = true)]
+    pub pull: bool,
+
+    /// Raw options appended to the build command.
+    #[builder(default, into)]
+    pub extra_args: Vec<ExtraArg>,
+
+    /// The directory that a stage is exported to when the
+    /// driver can't keep it in local storage.
+    ///
+    /// Defaults to the system temp directory.
+    pub tempdir: Option<&'scope Path>,
+}
+
+/// Registry repos that cached layers are pulled from and
+/// pushed to, so that builds on fresh machines can reuse
+/// the layers of earlier builds.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BuildCache {
+    /// The repos to pull cached layers from.
+    pub from: Vec<String>,
+
+    /// The repo to push the layers of the build to.
+    pub to: Option<String>,
+}
+
+/// A secret that `RUN` instructions can mount
+/// with `--mount=type=secret,id=<id>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BuildSecret {
+    pub id: String,
+    pub source: BuildSecretSource,
+}
+
+/// Where the value of a secret is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildSecretSource {
+    Env(String),
+    File(PathBuf),
+}
+
+impl BuildSecret {
+    /// The value for the `--secret` arg of the build.
+    #[must_use]
+    pub fn arg(&self) -> String {
+        match &self.source {
+            BuildSecretSource::Env(var) => format!("id={},env={var}", self.id),
+            BuildSecretSource::File(path) => format!("id={},src={}", self.id, path.display()),
+        }
+    }
+}
+
+/// An option of the underlying tool that isn't modeled by
+/// the other opts, given as `key=value` or `key` and passed
+/// to the tool as `--key=value` or `--key`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraArg {
+    pub key: String,
+    pub value: Option<String>,
+}
+
+/// Keys that have these in their name have their
+/// value redacted when the option is displayed.
+const SENSITIVE_KEYS: [&str; 6] = ["auth", "cred", "key", "pass", "secret", "token"];
+
+impl ExtraArg {
+    /// The arg that is passed to the tool.
+    #[must_use]
+    pub fn arg(&self) -> String {
+        self.value.as_ref().map_or_else(
+            || format!("--{}", self.key),
+            |value| format!("--{}={value}", self.key),
+        )
+    }
+
+    /// Whether the value shouldn't be logged.
+    #[must_use]
+    pub fn is_sensitive(&self) -> bool {
+        let key = self.key.to_lowercase();
+        SENSITIVE_KEYS
+            .iter()
+            .any(|sensitive| key.contains(sensitive))
+    }
+}
+
+impl FromStr for ExtraArg {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (key, value) = s
+            .split_once('=')
+            .map_or((s, None), |(key, value)| (key, Some(value.to_owned())));
+        let key = key.trim_start_matches('-');
+
+        if key.is_empty() || key.contains(char::is_whitespace) {
+            bail!("Expected an option like `key=value` or `key`, got `{s}`");
+        }
+
+        Ok(Self {
+            key: key.to_owned(),
+            value,
+        })
+    }
+}
+
+/// Displays the arg with the value redacted if it's sensitive.
+impl Display for ExtraArg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(_) if self.is_sensitive() => write!(f, "--{}=<redacted>", self.key),
+            _ => f.write_str(&self.arg()),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Builder)]
@@ -36,6 +264,10 @@ pub struct TagOpts<'scope> {
 pub struct PushOpts<'scope> {
     pub image: &'scope Reference,
     pub compression_type: Option<CompressionType>,
+
+    /// Raw options appended to the push command.
+    #[builder(default, into)]
+    pub extra_args: Vec<ExtraArg>,
 }
 
 #[derive(Debug, Clone, Builder)]
@@ -88,6 +320,12 @@ pub struct BuildTagPushOpts<'scope> {
     #[builder(default)]
     pub compression: CompressionType,
 
+    /// The maximum number of tags to push at once.
+    ///
+    /// Defaults to 4.
+    #[builder(default = DEFAULT_PUSH_JOBS)]
+    pub push_jobs: NonZeroUsize,
+
     /// Run all steps in a single layer.
     #[builder(default)]
     pub squash: bool,
@@ -95,4 +333,90 @@ pub struct BuildTagPushOpts<'scope> {
     /// The platform to build the image on.
     #[builder(default)]
     pub platform: Platform,
+
+    /// Secrets that `RUN` instructions can mount.
+    #[builder(default, into)]
+    pub secrets: Vec<BuildSecret>,
+
+    /// Named contexts that the Containerfiles copy from,
+    /// like the rendered templated files.
+    #[builder(default, into)]
+    pub build_contexts: Vec<BuildContext>,
+
+    /// Stages to build as their own images before the image.
+    ///
+    /// The stages are built from `stages_containerfile` at the same
+    /// time and replace the stages of the same name in `containerfile`.
+    #[builder(default, into)]
+    pub stages: Vec<Cow<'scope, str>>,
+
+    /// The Containerfile that defines `stages`.
+    #[builder(into)]
+    pub stages_containerfile: Option<Cow<'scope, Path>>,
+
+    /// The maximum number of stages to build at once.
+    ///
+    /// Defaults to 1.
+    #[builder(default = NonZeroUsize::MIN)]
+    pub stage_jobs: NonZeroUsize,
+
+    /// The directory that the stages are exported to when
+    /// the driver can't keep them in local storage.
+    ///
+    /// Defaults to the system temp directory.
+    pub stages_tempdir: Option<&'scope Path>,
+
+    /// Registry repos to use as a layer cache.
+    #[builder(default)]
+    pub cache: BuildCache,
+
+    /// Pass the proxy env vars, like `HTTPS_PROXY`, into the build.
+    #[builder(default = true)]
+    pub proxy: bool,
+
+    /// Pull the images used by the build. When not set,
+    /// the images must already be in local storage.
+    #[builder(default = true)]
+    pub pull: bool,
+
+    /// Raw options appended to the build command.
+    #[builder(default, into)]
+    pub build_extra_args: Vec<ExtraArg>,
+
+    /// Raw options appended to the push command.
+    #[builder(default, into)]
+    pub push_extra_args: Vec<ExtraArg>,
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use super::ExtraArg;
+
+    #[rstest]
+    #[case("ulimit=nofile=4096", "--ulimit=nofile=4096", "--ulimit=nofile=4096")]
+    #[case("--tls-verify=false", "--tls-verify=false", "--tls-verify=false")]
+    #[case("no-cache", "--no-cache", "--no-cache")]
+    #[case(
+        "registry-token=abc",
+        "--registry-token=abc",
+        "--registry-token=<redacted>"
+    )]
+    #[case("creds=user:pass", "--creds=user:pass", "--creds=<redacted>")]
+    fn extra_arg(#[case] input: &str, #[case] arg: &str, #[case] display: &str) {
+        let extra_arg: ExtraArg = input.parse().unwrap();
+
+        assert_eq!(extra_arg.arg(), arg);
+        assert_eq!(extra_arg.to_string(), display);
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("=value")]
+    #[case("--")]
+    #[case("no cache")]
+    fn invalid_extra_arg(#[case] input: &str) {
+        assert!(input.parse::<ExtraArg>().is_err());
+    }
 }
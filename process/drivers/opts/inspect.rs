@@ -11,4 +11,10 @@ pub struct GetMetadataOpts<'scope> {
 
     #[builder(default)]
     pub platform: Platform,
+
+    /// Inspects the image again instead of using the metadata
+    /// cached for it, for images that may have changed since,
+    /// like one that was just pushed.
+    #[builder(default)]
+    pub fresh: bool,
 }
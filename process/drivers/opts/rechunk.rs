@@ -1,10 +1,12 @@
-use std::{borrow::Cow, path::Path};
+use std::{borrow::Cow, collections::BTreeMap, num::NonZeroUsize, path::Path};
 
 use bon::Builder;
+use clap::ValueEnum;
+use oci_distribution::Reference;
 
-use crate::drivers::types::Platform;
+use crate::drivers::types::{OciDir, Platform};
 
-use super::CompressionType;
+use super::{BuildContext, BuildSecret, CompressionType, ExtraArg, DEFAULT_PUSH_JOBS};
 
 #[derive(Debug, Clone, Builder)]
 #[builder(on(Cow<'_, str>, into))]
@@ -17,11 +19,11 @@ pub struct RechunkOpts<'scope> {
     #[builder(default)]
     pub platform: Platform,
     pub version: Cow<'scope, str>,
-    pub name: Cow<'scope, str>,
-    pub description: Cow<'scope, str>,
-    pub base_digest: Cow<'scope, str>,
-    pub base_image: Cow<'scope, str>,
-    pub repo: Cow<'scope, str>,
+
+    /// The labels to set on the chunked image,
+    /// other than the build ID.
+    #[builder(default)]
+    pub labels: BTreeMap<String, String>,
 
     /// The list of tags for the image being built.
     #[builder(default, into)]
@@ -44,8 +46,151 @@ pub struct RechunkOpts<'scope> {
     /// The compression type to use when pushing.
     #[builder(default)]
     pub compression: CompressionType,
+
+    /// The maximum number of tags to push at once.
+    ///
+    /// Defaults to 4.
+    #[builder(default = DEFAULT_PUSH_JOBS)]
+    pub push_jobs: NonZeroUsize,
+
+    /// The number of layers to upload at once for each tag.
+    ///
+    /// Uses the default of the copy tool if not set.
+    pub push_concurrency: Option<NonZeroUsize>,
     pub tempdir: Option<&'scope Path>,
 
+    /// The tool that splits the image into chunks.
+    #[builder(default)]
+    pub rechunker: Rechunker,
+
+    /// The most layers the chunked image can have.
+    ///
+    /// Uses the default of the rechunker if not set.
+    pub max_layers: Option<NonZeroUsize>,
+
+    /// Use a fresh plan instead of the plan of the previous image.
+    ///
+    /// Only [`Rechunker::Hhd`] reuses the plan of the previous image,
+    /// the other rechunkers always start fresh.
     #[builder(default)]
     pub clear_plan: bool,
+
+    /// Secrets that `RUN` instructions can mount.
+    #[builder(default, into)]
+    pub secrets: Vec<BuildSecret>,
+
+    /// Named contexts that the Containerfile copies from.
+    #[builder(default, into)]
+    pub build_contexts: Vec<BuildContext>,
+
+    /// Raw options appended to the build command.
+    #[builder(default, into)]
+    pub build_extra_args: Vec<ExtraArg>,
+
+    /// Raw options appended to the copy command that pushes.
+    #[builder(default, into)]
+    pub push_extra_args: Vec<ExtraArg>,
+}
+
+/// The tools that can split an image into chunks.
+///
+/// Every rechunker starts from the same squashed build of the
+/// image and writes an OCI directory that is pushed the same way.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub enum Rechunker {
+    /// The scripts of `ghcr.io/hhd-dev/rechunk`, which
+    /// keep the layers stable between versions of the image.
+    #[default]
+    Hhd,
+
+    /// `rpm-ostree compose build-chunked-oci`, run from the image
+    /// itself, so the image needs a version of rpm-ostree that has it.
+    RpmOstree,
+}
+
+impl std::fmt::Display for Rechunker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Hhd => "hhd",
+            Self::RpmOstree => "rpm-ostree",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Builder)]
+pub struct MergeLayersOpts<'scope> {
+    /// The built image in local storage.
+    pub image: &'scope Reference,
+
+    /// The most layers the image can have after merging.
+    pub max_layers: NonZeroUsize,
+
+    /// Layers at least this many bytes are never merged.
+    pub keep_size: u64,
+
+    /// The list of tags for the image being built.
+    #[builder(default, into)]
+    pub tags: Vec<Cow<'scope, str>>,
+
+    /// Enable pushing the image.
+    #[builder(default)]
+    pub push: bool,
+
+    /// Number of times to retry pushing.
+    ///
+    /// Defaults to 1.
+    #[builder(default = 1)]
+    pub retry_count: u8,
+
+    /// The maximum number of tags to push at once.
+    ///
+    /// Defaults to 4.
+    #[builder(default = DEFAULT_PUSH_JOBS)]
+    pub push_jobs: NonZeroUsize,
+    pub tempdir: Option<&'scope Path>,
+
+    /// Raw options appended to the copy command that pushes.
+    #[builder(default, into)]
+    pub push_extra_args: Vec<ExtraArg>,
+}
+
+/// The manifest format to push an OCI directory with.
+#[derive(Debug, Copy, Clone, ValueEnum)]
+pub enum ManifestFormat {
+    Oci,
+    V2s2,
+}
+
+impl std::fmt::Display for ManifestFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Oci => "oci",
+            Self::V2s2 => "v2s2",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Builder)]
+pub struct CopyOciDirOpts<'scope> {
+    pub oci_dir: &'scope OciDir,
+    pub registry: &'scope Reference,
+
+    /// Compute the digests of the layers before uploading
+    /// them so that layers already in the registry are skipped.
+    #[builder(default)]
+    pub precompute_digests: bool,
+
+    /// The manifest format to push with.
+    ///
+    /// Keeps the format of the source if not set.
+    pub format: Option<ManifestFormat>,
+
+    /// The number of layers to upload at once.
+    ///
+    /// Uses the default of the copy tool if not set.
+    pub concurrency: Option<NonZeroUsize>,
+
+    /// Raw options appended to the copy command.
+    #[builder(default, into)]
+    pub extra_args: Vec<ExtraArg>,
 }
@@ -19,6 +19,22 @@ pub struct RunOpts<'scope> {
     #[builder(into)]
     pub user: Option<Cow<'scope, str>>,
 
+    /// The directory to run the command in.
+    #[builder(into)]
+    pub workdir: Option<Cow<'scope, str>>,
+
+    /// Ports of the container to publish on the host.
+    #[builder(default, into)]
+    pub ports: Vec<RunOptsPort>,
+
+    /// Devices of the host to add to the container.
+    #[builder(default, into)]
+    pub devices: Vec<Cow<'scope, str>>,
+
+    /// Mounts that aren't plain volumes.
+    #[builder(default, into)]
+    pub mounts: Vec<RunOptsMount<'scope>>,
+
     #[builder(default)]
     pub privileged: bool,
 
@@ -52,6 +68,59 @@ macro_rules! run_volumes {
     };
 }
 
+/// A port of the container that is published on the host.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Builder)]
+pub struct RunOptsPort {
+    /// The port on the host, or `None` to
+    /// let the engine pick a free port.
+    pub host_port: Option<u16>,
+
+    pub container_port: u16,
+}
+
+impl RunOptsPort {
+    /// The value for the `--publish` arg of the run.
+    #[must_use]
+    pub fn arg(&self) -> String {
+        self.host_port.map_or_else(
+            || self.container_port.to_string(),
+            |host_port| format!("{host_port}:{}", self.container_port),
+        )
+    }
+}
+
+/// A mount that can't be expressed as a volume.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunOptsMount<'scope> {
+    /// An empty in-memory filesystem.
+    Tmpfs { container_path: Cow<'scope, str> },
+
+    /// A file or directory of the host.
+    Bind {
+        host_path: Cow<'scope, str>,
+        container_path: Cow<'scope, str>,
+        read_only: bool,
+    },
+}
+
+impl RunOptsMount<'_> {
+    /// The value for the `--mount` arg of the run.
+    #[must_use]
+    pub fn arg(&self) -> String {
+        match self {
+            Self::Tmpfs { container_path } => format!("type=tmpfs,destination={container_path}"),
+            Self::Bind {
+                host_path,
+                container_path,
+                read_only,
+            } => format!(
+                "type=bind,source={host_path},destination={container_path}{}",
+                if *read_only { ",readonly" } else { "" }
+            ),
+        }
+    }
+}
+
 #[derive(Debug, Clone, Builder)]
 pub struct RunOptsEnv<'scope> {
     #[builder(into)]
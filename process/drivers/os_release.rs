@@ -0,0 +1,497 @@
+//! Reads the `os-release` file straight out of an image's layers.
+//!
+//! This lets us find the OS version of an image that doesn't have
+//! a version label without having to pull and run the whole image.
+
+use std::{io::Write, time::Duration};
+
+use blue_build_utils::{credentials::Credentials, os_family::OsFamily};
+use cached::proc_macro::cached;
+use colored::Colorize;
+use flate2::write::GzDecoder;
+use futures_util::StreamExt;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, trace};
+use miette::{miette, IntoDiagnostic, Result};
+use oci_distribution::{
+    client::ClientConfig,
+    manifest::{
+        ImageIndexEntry, OciDescriptor, IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE,
+        IMAGE_DOCKER_LAYER_TAR_MEDIA_TYPE, IMAGE_LAYER_GZIP_MEDIA_TYPE, IMAGE_LAYER_MEDIA_TYPE,
+    },
+    secrets::RegistryAuth,
+    Client, Reference,
+};
+
+use crate::logging::Logger;
+
+use super::types::Platform;
+
+pub(super) const BLOCK_SIZE: usize = 512;
+const OS_RELEASE_PATHS: [&str; 2] = ["usr/lib/os-release", "etc/os-release"];
+
+/// Gets the major version from the `VERSION_ID` in the
+/// `os-release` file of the image.
+///
+/// # Errors
+/// Will error if the `os-release` file can't be
+/// read or it doesn't have a version.
+pub async fn fetch_os_version(image: &Reference, platform: Platform) -> Result<u64> {
+    trace!("fetch_os_version({image}, {platform})");
+
+    parse_version_id(&fetch_os_release(image, platform).await?).ok_or_else(|| {
+        miette!(
+            "Failed to parse VERSION_ID from the os-release of {}",
+            image.to_string().bold()
+        )
+    })
+}
+
+/// Gets the family of the OS from the `ID` and
+/// `ID_LIKE` in the `os-release` file of the image.
+///
+/// # Errors
+/// Will error if the `os-release` file can't be read.
+pub async fn fetch_os_family(image: &Reference, platform: Platform) -> Result<OsFamily> {
+    trace!("fetch_os_family({image}, {platform})");
+
+    Ok(OsFamily::from_os_release(
+        &fetch_os_release(image, platform).await?,
+    ))
+}
+
+/// Reads the `os-release` file of the image.
+///
+/// The layers are read from the top down and the download
+/// stops as soon as the file is found. Only uncompressed and
+/// gzip compressed layers can be read.
+#[cached(
+    result = true,
+    key = "String",
+    convert = r#"{ format!("{image}-{platform}") }"#
+)]
+async fn fetch_os_release(image: &Reference, platform: Platform) -> Result<String> {
+    let progress = Logger::multi_progress().add(
+        ProgressBar::new_spinner()
+            .with_style(ProgressStyle::default_spinner())
+            .with_message(format!(
+                "Reading os-release from the layers of {}",
+                image.to_string().bold()
+            )),
+    );
+    progress.enable_steady_tick(Duration::from_millis(100));
+
+    let result = read_os_release(image, platform)
+        .await
+        .map(|contents| String::from_utf8_lossy(&contents).into_owned());
+
+    progress.finish_and_clear();
+    Logger::multi_progress().remove(&progress);
+
+    result
+}
+
+async fn read_os_release(image: &Reference, platform: Platform) -> Result<Vec<u8>> {
+    let client = Client::new(ClientConfig {
+        platform_resolver: Some(Box::new(move |entries| resolve_platform(entries, platform))),
+        ..Default::default()
+    });
+
+    let (manifest, _) = client
+        .pull_image_manifest(image, &registry_auth(image))
+        .await
+        .into_diagnostic()?;
+
+    for layer in manifest.layers.iter().rev() {
+        let Some(mut scanner) = LayerScanner::new(&layer.media_type) else {
+            debug!(
+                "Skipping layer {} with unsupported media type {}",
+                layer.digest, layer.media_type
+            );
+            continue;
+        };
+
+        if let Some(contents) = scan_layer(&client, image, layer, &mut scanner).await? {
+            return Ok(contents);
+        }
+    }
+
+    Err(miette!(
+        "None of the readable layers of {} contain an os-release file",
+        image.to_string().bold()
+    ))
+}
+
+async fn scan_layer(
+    client: &Client,
+    image: &Reference,
+    layer: &OciDescriptor,
+    scanner: &mut LayerScanner,
+) -> Result<Option<Vec<u8>>> {
+    trace!("Scanning layer {} of {image}", layer.digest);
+
+    let mut stream = client
+        .pull_blob_stream(image, layer)
+        .await
+        .into_diagnostic()?;
+
+    while let Some(chunk) = stream.next().await {
+        scanner
+            .write_all(&chunk.into_diagnostic()?)
+            .into_diagnostic()?;
+
+        if scanner.tar().is_done() {
+            return Ok(scanner.tar_mut().take_os_release());
+        }
+    }
+    scanner.finish().into_diagnostic()?;
+
+    Ok(scanner.tar_mut().take_os_release())
+}
+
+pub(super) fn resolve_platform(entries: &[ImageIndexEntry], platform: Platform) -> Option<String> {
+    let platform = platform.to_string();
+    let (os, arch) = platform.split_once('/')?;
+
+    entries
+        .iter()
+        .find(|entry| {
+            entry
+                .platform
+                .as_ref()
+                .is_some_and(|p| p.os == os && p.architecture == arch)
+        })
+        .map(|entry| entry.digest.clone())
+}
+
+pub(super) fn registry_auth(image: &Reference) -> RegistryAuth {
+    Credentials::for_registry(image.registry()).map_or(RegistryAuth::Anonymous, |creds| {
+        RegistryAuth::Basic(creds.username, creds.password)
+    })
+}
+
+/// Gets the major version out of the `VERSION_ID`
+/// field of an `os-release` file.
+fn parse_version_id(os_release: &str) -> Option<u64> {
+    os_release
+        .lines()
+        .find_map(|line| line.trim().strip_prefix("VERSION_ID="))
+        .map(|value| value.trim().trim_matches(|c| c == '"' || c == '\''))
+        .and_then(|value| value.split('.').next())
+        .and_then(|major| major.parse().ok())
+}
+
+enum LayerScanner {
+    Tar(TarScanner),
+    Gzip(GzDecoder<TarScanner>),
+}
+
+impl LayerScanner {
+    fn new(media_type: &str) -> Option<Self> {
+        match media_type {
+            IMAGE_LAYER_MEDIA_TYPE | IMAGE_DOCKER_LAYER_TAR_MEDIA_TYPE => {
+                Some(Self::Tar(TarScanner::default()))
+            }
+            IMAGE_LAYER_GZIP_MEDIA_TYPE | IMAGE_DOCKER_LAYER_GZIP_MEDIA_TYPE => {
+                Some(Self::Gzip(GzDecoder::new(TarScanner::default())))
+            }
+            _ => None,
+        }
+    }
+
+    fn tar(&self) -> &TarScanner {
+        match self {
+            Self::Tar(tar) => tar,
+            Self::Gzip(gzip) => gzip.get_ref(),
+        }
+    }
+
+    /// Writes out any data that's still buffered
+    /// by the decompressor.
+    fn finish(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Tar(_) => Ok(()),
+            Self::Gzip(gzip) => gzip.try_finish(),
+        }
+    }
+
+    fn tar_mut(&mut self) -> &mut TarScanner {
+        match self {
+            Self::Tar(tar) => tar,
+            Self::Gzip(gzip) => gzip.get_mut(),
+        }
+    }
+}
+
+impl Write for LayerScanner {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            Self::Tar(tar) => tar.write(buf),
+            Self::Gzip(gzip) => gzip.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            Self::Tar(tar) => tar.flush(),
+            Self::Gzip(gzip) => gzip.flush(),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+enum TarState {
+    #[default]
+    Header,
+    Skip(u64),
+    Capture {
+        remaining: u64,
+        padding: u64,
+        preferred: bool,
+    },
+    Done,
+}
+
+/// Finds the `os-release` file in a tar archive
+/// as it's written without buffering the archive.
+#[derive(Debug, Default)]
+struct TarScanner {
+    state: TarState,
+    header: Vec<u8>,
+    contents: Vec<u8>,
+    os_release: Option<Vec<u8>>,
+}
+
+impl TarScanner {
+    /// Whether the rest of the archive can be skipped.
+    const fn is_done(&self) -> bool {
+        matches!(self.state, TarState::Done)
+    }
+
+    const fn take_os_release(&mut self) -> Option<Vec<u8>> {
+        self.os_release.take()
+    }
+
+    fn read_header(&mut self) {
+        let header = &self.header;
+
+        if header.iter().all(|&b| b == 0) {
+            self.state = TarState::Done;
+            return;
+        }
+
+        let size = parse_size(&header[124..136]);
+        let padding = (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64;
+        let is_file = matches!(header[156], b'0' | 0);
+        let path = entry_path(header);
+
+        self.state = match OS_RELEASE_PATHS.iter().position(|p| *p == path) {
+            Some(index) if is_file => {
+                trace!("Found {path} in layer");
+                TarState::Capture {
+                    remaining: size,
+                    padding,
+                    preferred: index == 0,
+                }
+            }
+            _ => TarState::Skip(size + padding),
+        };
+        self.header.clear();
+    }
+}
+
+impl Write for TarScanner {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut data = buf;
+
+        while !data.is_empty() {
+            match &mut self.state {
+                TarState::Done => break,
+                TarState::Header => {
+                    let take = data.len().min(BLOCK_SIZE - self.header.len());
+                    self.header.extend_from_slice(&data[..take]);
+                    data = &data[take..];
+
+                    if self.header.len() == BLOCK_SIZE {
+                        self.read_header();
+                    }
+                }
+                TarState::Skip(remaining) => {
+                    let take = data
+                        .len()
+                        .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                    *remaining -= take as u64;
+                    data = &data[take..];
+
+                    if *remaining == 0 {
+                        self.state = TarState::Header;
+                    }
+                }
+                TarState::Capture {
+                    remaining,
+                    padding,
+                    preferred,
+                } => {
+                    let take = data
+                        .len()
+                        .min(usize::try_from(*remaining).unwrap_or(usize::MAX));
+                    self.contents.extend_from_slice(&data[..take]);
+                    *remaining -= take as u64;
+                    data = &data[take..];
+
+                    if *remaining == 0 {
+                        let preferred = *preferred;
+                        self.os_release = Some(std::mem::take(&mut self.contents));
+                        self.state = if preferred {
+                            TarState::Done
+                        } else {
+                            TarState::Skip(*padding)
+                        };
+                    }
+                }
+            }
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Gets the path of an entry without any leading `./` or `/`
+/// and joined with the `ustar` prefix if there is one.
+pub(super) fn entry_path(header: &[u8]) -> String {
+    let field = |bytes: &[u8]| {
+        let end = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).into_owned()
+    };
+
+    let name = field(&header[..100]);
+    let path = if &header[257..262] == b"ustar" {
+        match field(&header[345..500]) {
+            prefix if prefix.is_empty() => name,
+            prefix => format!("{prefix}/{name}"),
+        }
+    } else {
+        name
+    };
+
+    path.trim_start_matches("./")
+        .trim_start_matches('/')
+        .to_owned()
+}
+
+/// Parses the size field, which is either octal
+/// or base-256 for large files.
+pub(super) fn parse_size(field: &[u8]) -> u64 {
+    if field[0] & 0x80 == 0 {
+        let octal = String::from_utf8_lossy(field);
+        u64::from_str_radix(octal.trim_matches(|c: char| c == '\0' || c == ' '), 8).unwrap_or(0)
+    } else {
+        field[1..]
+            .iter()
+            .fold(u64::from(field[0] & 0x7f), |size, &b| {
+                (size << 8) | u64::from(b)
+            })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+
+    use flate2::{write::GzEncoder, Compression};
+    use rstest::rstest;
+
+    use super::{parse_version_id, LayerScanner, TarScanner, BLOCK_SIZE};
+
+    fn tar_entry(path: &str, typeflag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut header = vec![0; BLOCK_SIZE];
+        header[..path.len()].copy_from_slice(path.as_bytes());
+        let size = format!("{:011o}\0", contents.len());
+        header[124..136].copy_from_slice(size.as_bytes());
+        header[156] = typeflag;
+        header[257..263].copy_from_slice(b"ustar\0");
+
+        let mut entry = header;
+        entry.extend_from_slice(contents);
+        entry.resize(entry.len().div_ceil(BLOCK_SIZE) * BLOCK_SIZE, 0);
+        entry
+    }
+
+    fn tar(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut tar = entries.concat();
+        tar.extend_from_slice(&[0; BLOCK_SIZE * 2]);
+        tar
+    }
+
+    fn scan(scanner: &mut LayerScanner, data: &[u8]) -> Option<Vec<u8>> {
+        // Write in small uneven chunks to cover
+        // headers that are split across writes
+        for chunk in data.chunks(100) {
+            scanner.write_all(chunk).unwrap();
+        }
+        scanner.finish().unwrap();
+        scanner.tar_mut().take_os_release()
+    }
+
+    #[test]
+    fn scan_tar_layer() {
+        let data = tar(&[
+            tar_entry("./etc/hostname", b'0', &[b'a'; 700]),
+            tar_entry("./etc/os-release", b'2', b""),
+            tar_entry(
+                "./usr/lib/os-release",
+                b'0',
+                b"NAME=Fedora\nVERSION_ID=41\n",
+            ),
+            tar_entry("./usr/lib/other", b'0', b"other"),
+        ]);
+
+        let mut scanner = LayerScanner::Tar(TarScanner::default());
+        assert_eq!(
+            scan(&mut scanner, &data).as_deref(),
+            Some(&b"NAME=Fedora\nVERSION_ID=41\n"[..])
+        );
+        assert!(scanner.tar().is_done());
+    }
+
+    #[test]
+    fn scan_gzip_layer() {
+        let data = tar(&[
+            tar_entry("usr/bin/bash", b'0', &[b'b'; 2000]),
+            tar_entry("etc/os-release", b'0', b"VERSION_ID=\"40\"\n"),
+        ]);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        let data = encoder.finish().unwrap();
+
+        let mut scanner = LayerScanner::new(super::IMAGE_LAYER_GZIP_MEDIA_TYPE).unwrap();
+        assert_eq!(
+            scan(&mut scanner, &data).as_deref(),
+            Some(&b"VERSION_ID=\"40\"\n"[..])
+        );
+    }
+
+    #[test]
+    fn scan_layer_without_os_release() {
+        let data = tar(&[tar_entry("usr/lib/other", b'0', b"other")]);
+
+        let mut scanner = LayerScanner::Tar(TarScanner::default());
+        assert_eq!(scan(&mut scanner, &data), None);
+        assert!(scanner.tar().is_done());
+    }
+
+    #[rstest]
+    #[case("NAME=Fedora\nVERSION_ID=41\n", Some(41))]
+    #[case("VERSION_ID=\"40\"", Some(40))]
+    #[case("VERSION_ID='9.4'", Some(9))]
+    #[case("ID=debian\nVERSION_ID=\"12\"\n", Some(12))]
+    #[case("ID=arch\n", None)]
+    #[case("VERSION_ID=rawhide", None)]
+    fn version_id(#[case] os_release: &str, #[case] expected: Option<u64>) {
+        assert_eq!(parse_version_id(os_release), expected);
+    }
+}
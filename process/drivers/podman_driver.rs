@@ -16,12 +16,16 @@ use oci_distribution::Reference;
 use semver::Version;
 use serde::Deserialize;
 use tempfile::TempDir;
+use uuid::Uuid;
 
 use crate::{
     drivers::{
-        opts::{BuildOpts, GetMetadataOpts, PushOpts, RunOpts, RunOptsEnv, RunOptsVolume, TagOpts},
-        types::{ImageMetadata, Platform},
-        BuildDriver, DriverVersion, InspectDriver, RunDriver,
+        opts::{
+            BuildContext, BuildOpts, BuildStageOpts, GetMetadataOpts, PushOpts, RunOpts,
+            RunOptsEnv, RunOptsVolume, TagOpts,
+        },
+        types::{ImageConfig, ImageHistory, ImageLayer, ImageMetadata, Platform},
+        version_cache, BuildDriver, DriverVersion, InspectDriver, RunDriver,
     },
     logging::{CommandLogging, Logger},
     signal_handler::{add_cid, remove_cid, ContainerRuntime, ContainerSignalId},
@@ -38,6 +42,22 @@ use super::{
 struct PodmanImageMetadata {
     labels: HashMap<String, serde_json::Value>,
     repo_digests: Vec<String>,
+
+    #[serde(default)]
+    config: ImageConfig,
+
+    #[serde(default)]
+    history: Vec<ImageHistory>,
+
+    #[serde(default, rename = "RootFS")]
+    root_fs: PodmanRootFs,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+struct PodmanRootFs {
+    #[serde(default)]
+    layers: Vec<String>,
 }
 
 impl TryFrom<Vec<PodmanImageMetadata>> for ImageMetadata {
@@ -82,6 +102,15 @@ impl TryFrom<Vec<PodmanImageMetadata>> for ImageMetadata {
         Ok(Self {
             labels: value.labels,
             digest,
+            config: value.config,
+            layers: value
+                .root_fs
+                .layers
+                .into_iter()
+                .map(|digest| ImageLayer { digest, size: None })
+                .collect(),
+            history: value.history,
+            size: None,
         })
     }
 }
@@ -116,17 +145,19 @@ impl DriverVersion for PodmanDriver {
     fn version() -> Result<Version> {
         trace!("PodmanDriver::version()");
 
-        trace!("podman version -f json");
-        let output = cmd!("podman", "version", "-f", "json")
-            .output()
-            .into_diagnostic()?;
+        version_cache::cached_version("podman", || {
+            trace!("podman version -f json");
+            let output = cmd!("podman", "version", "-f", "json")
+                .output()
+                .into_diagnostic()?;
 
-        let version_json: PodmanVersionJson = serde_json::from_slice(&output.stdout)
-            .inspect_err(|e| error!("{e}: {}", String::from_utf8_lossy(&output.stdout)))
-            .into_diagnostic()?;
-        trace!("{version_json:#?}");
+            let version_json: PodmanVersionJson = serde_json::from_slice(&output.stdout)
+                .inspect_err(|e| error!("{e}: {}", String::from_utf8_lossy(&output.stdout)))
+                .into_diagnostic()?;
+            trace!("{version_json:#?}");
 
-        Ok(version_json.client.version)
+            Ok(version_json.client.version)
+        })
     }
 }
 
@@ -141,9 +172,17 @@ impl BuildDriver for PodmanDriver {
                 "--platform",
                 opts.platform.to_string(),
             ],
-            "--pull=true",
+            if opts.pull => "--pull=true",
+            if !opts.pull => "--pull=never",
             if opts.host_network => "--net=host",
             format!("--layers={}", !opts.squash),
+            for secret in &opts.secrets => ["--secret", secret.arg()],
+            if let Some(target) = opts.target.as_deref() => ["--target", target],
+            for context in &opts.build_contexts => ["--build-context", context.arg()],
+            for repo in &opts.cache.from => ["--cache-from", repo],
+            if let Some(repo) = opts.cache.to.as_deref() => ["--cache-to", repo],
+            if !opts.proxy => "--http-proxy=false",
+            for arg in &opts.extra_args => arg.arg(),
             "-f",
             &*opts.containerfile,
             "-t",
@@ -164,6 +203,50 @@ impl BuildDriver for PodmanDriver {
         Ok(())
     }
 
+    fn build_stage(opts: &BuildStageOpts) -> Result<BuildContext> {
+        trace!("PodmanDriver::build_stage({opts:#?})");
+
+        let image = format!(
+            "localhost/bluebuild-stage-{}:{}",
+            opts.stage.to_lowercase(),
+            Uuid::new_v4()
+        );
+        Self::build(
+            &BuildOpts::builder()
+                .image(&image)
+                .containerfile(&*opts.containerfile)
+                .platform(opts.platform)
+                .secrets(opts.secrets.clone())
+                .target(&*opts.stage)
+                .build_contexts(opts.build_contexts.clone())
+                .cache(opts.cache.clone())
+                .proxy(opts.proxy)
+                .pull(opts.pull)
+                .extra_args(opts.extra_args.clone())
+                .build(),
+        )?;
+
+        Ok(BuildContext {
+            name: opts.stage.to_string(),
+            location: format!("container-image://{image}"),
+        })
+    }
+
+    fn remove_stage(context: &BuildContext) -> Result<()> {
+        trace!("PodmanDriver::remove_stage({context:#?})");
+
+        let image = context.location.trim_start_matches("container-image://");
+        let output = cmd!("podman", "rmi", image).output().into_diagnostic()?;
+
+        if !output.status.success() {
+            bail!(
+                "Failed to remove the image {image}: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        Ok(())
+    }
+
     fn tag(opts: &TagOpts) -> Result<()> {
         trace!("PodmanDriver::tag({opts:#?})");
 
@@ -194,6 +277,7 @@ impl BuildDriver for PodmanDriver {
                 "--compression-format={}",
                 opts.compression_type.unwrap_or_default()
             ),
+            for arg in &opts.extra_args => arg.arg(),
             &image_str,
         );
 
@@ -210,14 +294,30 @@ impl BuildDriver for PodmanDriver {
         Ok(())
     }
 
+    fn storage_dir() -> Result<Option<std::path::PathBuf>> {
+        super::functions::storage_dir("podman", "{{.Store.GraphRoot}}")
+    }
+
+    fn storage_transport() -> Option<&'static str> {
+        Some("containers-storage:")
+    }
+
+    fn list_local_images() -> Result<Vec<super::types::LocalImage>> {
+        super::functions::list_local_images("podman")
+    }
+
+    fn remove_local_images(images: &[String]) -> Result<()> {
+        super::functions::remove_local_images("podman", images)
+    }
+
     fn login() -> Result<()> {
         trace!("PodmanDriver::login()");
 
-        if let Some(Credentials {
+        for Credentials {
             registry,
             username,
             password,
-        }) = Credentials::get()
+        } in &Credentials::all()
         {
             let mut command = cmd!(
                 "podman",
@@ -248,7 +348,10 @@ impl BuildDriver for PodmanDriver {
 
             if !output.status.success() {
                 let err_out = String::from_utf8_lossy(&output.stderr);
-                bail!("Failed to login for podman:\n{}", err_out.trim());
+                bail!(
+                    "Failed to login to {registry} for podman:\n{}",
+                    err_out.trim()
+                );
             }
             debug!("Logged into {registry}");
         }
@@ -279,18 +382,21 @@ impl BuildDriver for PodmanDriver {
 }
 
 impl InspectDriver for PodmanDriver {
-    fn get_metadata(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
-        get_metadata_cache(opts)
+    async fn get_metadata_async(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+        if opts.fresh {
+            get_metadata_cache_prime_cache(opts).await
+        } else {
+            get_metadata_cache(opts).await
+        }
     }
 }
 
 #[cached(
     result = true,
     key = "String",
-    convert = r#"{ format!("{}-{}", opts.image, opts.platform)}"#,
-    sync_writes = true
+    convert = r#"{ format!("{}-{}", opts.image, opts.platform)}"#
 )]
-fn get_metadata_cache(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
+async fn get_metadata_cache(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
     trace!("PodmanDriver::get_metadata({opts:#?})");
 
     let image_str = opts.image.to_string();
@@ -305,7 +411,7 @@ fn get_metadata_cache(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
     );
     progress.enable_steady_tick(Duration::from_millis(100));
 
-    let mut command = cmd!(
+    let command = cmd!(
         "podman",
         "pull",
         if !matches!(opts.platform, Platform::Native) => [
@@ -316,16 +422,22 @@ fn get_metadata_cache(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
     );
     trace!("{command:?}");
 
-    let output = command.output().into_diagnostic()?;
+    let output = tokio::process::Command::from(command)
+        .output()
+        .await
+        .into_diagnostic()?;
 
     if !output.status.success() {
         bail!("Failed to pull {} for inspection!", image_str.bold().red());
     }
 
-    let mut command = cmd!("podman", "image", "inspect", "--format=json", &image_str);
+    let command = cmd!("podman", "image", "inspect", "--format=json", &image_str);
     trace!("{command:?}");
 
-    let output = command.output().into_diagnostic()?;
+    let output = tokio::process::Command::from(command)
+        .output()
+        .await
+        .into_diagnostic()?;
 
     progress.finish_and_clear();
     Logger::multi_progress().remove(&progress);
@@ -452,14 +564,16 @@ impl RunDriver for PodmanDriver {
     fn run(opts: &RunOpts) -> Result<ExitStatus> {
         trace!("PodmanDriver::run({opts:#?})");
 
-        if !nix::unistd::Uid::effective().is_root() {
-            bail!("You must be root to run privileged podman!");
-        }
+        check_privileges(opts)?;
 
         let cid_path = TempDir::new().into_diagnostic()?;
         let cid_file = cid_path.path().join("cid");
 
-        let cid = ContainerSignalId::new(&cid_file, ContainerRuntime::Podman, opts.privileged);
+        let cid = ContainerSignalId::new(
+            &cid_file,
+            ContainerRuntime::Podman,
+            opts.privileged && !is_remote(),
+        );
 
         add_cid(&cid);
 
@@ -475,14 +589,16 @@ impl RunDriver for PodmanDriver {
     fn run_output(opts: &RunOpts) -> Result<std::process::Output> {
         trace!("PodmanDriver::run_output({opts:#?})");
 
-        if !nix::unistd::Uid::effective().is_root() {
-            bail!("You must be root to run privileged podman!");
-        }
+        check_privileges(opts)?;
 
         let cid_path = TempDir::new().into_diagnostic()?;
         let cid_file = cid_path.path().join("cid");
 
-        let cid = ContainerSignalId::new(&cid_file, ContainerRuntime::Podman, opts.privileged);
+        let cid = ContainerSignalId::new(
+            &cid_file,
+            ContainerRuntime::Podman,
+            opts.privileged && !is_remote(),
+        );
 
         add_cid(&cid);
 
@@ -494,6 +610,26 @@ impl RunDriver for PodmanDriver {
     }
 }
 
+/// Whether podman runs containers on another machine, like the VM
+/// of `podman machine` on macOS and Windows. The privileges
+/// of this host don't matter then.
+#[cached]
+fn is_remote() -> bool {
+    trace!("podman info --format {{{{.Host.ServiceIsRemote}}}}");
+    cmd!("podman", "info", "--format", "{{.Host.ServiceIsRemote}}")
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .is_some_and(|output| String::from_utf8_lossy(&output.stdout).trim() == "true")
+}
+
+fn check_privileges(opts: &RunOpts) -> Result<()> {
+    if opts.privileged && !nix::unistd::Uid::effective().is_root() && !is_remote() {
+        bail!("You must be root to run privileged podman!");
+    }
+    Ok(())
+}
+
 fn podman_run(opts: &RunOpts, cid_file: &Path) -> Command {
     let command = cmd!(
         "podman",
@@ -506,6 +642,10 @@ fn podman_run(opts: &RunOpts, cid_file: &Path) -> Command {
         if opts.remove => "--rm",
         if opts.pull => "--pull=always",
         if let Some(user) = opts.user.as_ref() => format!("--user={user}"),
+        if let Some(workdir) = opts.workdir.as_ref() => format!("--workdir={workdir}"),
+        for port in &opts.ports => ["--publish", port.arg()],
+        for device in &opts.devices => ["--device", &**device],
+        for mount in &opts.mounts => ["--mount", mount.arg()],
         for RunOptsVolume { path_or_vol_name, container_path } in opts.volumes.iter() => [
             "--volume",
             format!("{path_or_vol_name}:{container_path}"),
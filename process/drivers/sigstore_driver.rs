@@ -1,9 +1,6 @@
 use std::{fs, path::Path};
 
-use crate::{
-    drivers::opts::{PrivateKeyContents, VerifyType},
-    ASYNC_RUNTIME,
-};
+use crate::drivers::opts::{PrivateKeyContents, VerifyType};
 
 use super::{
     functions::get_private_key,
@@ -18,6 +15,7 @@ use blue_build_utils::{
 use colored::Colorize;
 use log::{debug, trace};
 use miette::{bail, miette, Context, IntoDiagnostic};
+use oci_distribution::Reference;
 use sigstore::{
     cosign::{
         constraint::PrivateKeySigner,
@@ -27,9 +25,12 @@ use sigstore::{
     crypto::{signing_key::SigStoreKeyPair, SigningScheme},
     errors::SigstoreVerifyConstraintsError,
     registry::{Auth, OciReference},
+    trust::sigstore::SigstoreTrustRoot,
 };
 use zeroize::Zeroizing;
 
+mod keyless;
+
 pub struct SigstoreDriver;
 
 impl SigningDriver for SigstoreDriver {
@@ -119,29 +120,21 @@ impl SigningDriver for SigstoreDriver {
         let mut client = ClientBuilder::default().build().into_diagnostic()?;
         let image_digest: OciReference = opts.image.to_string().parse().into_diagnostic()?;
 
-        let signing_scheme = SigningScheme::default();
-        let key: Zeroizing<Vec<u8>> = get_private_key(path)?.contents()?;
-        debug!("Retrieved private key");
-
-        let signer = PrivateKeySigner::new_with_signer(
-            SigStoreKeyPair::from_encrypted_pem(&key, b"")
-                .into_diagnostic()?
-                .to_sigstore_signer(&signing_scheme)
-                .into_diagnostic()?,
-        );
-        debug!("Created signer");
-
         let Credentials {
             registry: _,
             username,
             password,
-        } = Credentials::get().ok_or_else(|| miette!("Credentials are required for signing"))?;
-        let auth = Auth::Basic(username.clone(), password.clone());
+        } = Credentials::for_registry(image_digest.registry()).ok_or_else(|| {
+            miette!(
+                "Credentials for {} are required for signing",
+                image_digest.registry()
+            )
+        })?;
+        let auth = Auth::Basic(username, password);
         debug!("Credentials retrieved");
 
         let (cosign_signature_image, source_image_digest) = retry(2, 5, || {
-            ASYNC_RUNTIME
-                .block_on(client.triangulate(&image_digest, &auth))
+            crate::block_on(client.triangulate(&image_digest, &auth))
                 .into_diagnostic()
                 .with_context(|| format!("Failed to triangulate image {image_digest}"))
         })?;
@@ -150,6 +143,28 @@ impl SigningDriver for SigstoreDriver {
 
         let mut signature_layer =
             SignatureLayer::new_unsigned(&image_digest, &source_image_digest).into_diagnostic()?;
+
+        if opts.key.is_none() {
+            return crate::block_on(sign_keyless(
+                &auth,
+                &cosign_signature_image,
+                signature_layer,
+            ))
+            .with_context(|| format!("Failed to sign image {image_digest}"));
+        }
+
+        let signing_scheme = SigningScheme::default();
+        let key: Zeroizing<Vec<u8>> = get_private_key(path)?.contents()?;
+        debug!("Retrieved private key");
+
+        let signer = PrivateKeySigner::new_with_signer(
+            SigStoreKeyPair::from_encrypted_pem(&key, b"")
+                .into_diagnostic()?
+                .to_sigstore_signer(&signing_scheme)
+                .into_diagnostic()?,
+        );
+        debug!("Created signer");
+
         signer
             .add_constraint(&mut signature_layer)
             .into_diagnostic()?;
@@ -157,19 +172,18 @@ impl SigningDriver for SigstoreDriver {
 
         debug!("Pushing signature");
         retry(2, 5, || {
-            ASYNC_RUNTIME
-                .block_on(client.push_signature(
-                    None,
-                    &auth,
-                    &cosign_signature_image,
-                    vec![signature_layer.clone()],
-                ))
-                .into_diagnostic()
-                .with_context(|| {
-                    format!(
+            crate::block_on(client.push_signature(
+                None,
+                &auth,
+                &cosign_signature_image,
+                vec![signature_layer.clone()],
+            ))
+            .into_diagnostic()
+            .with_context(|| {
+                format!(
                     "Failed to push signature {cosign_signature_image} for image {image_digest}"
                 )
-                })
+            })
         })?;
         debug!("Successfully pushed signature");
 
@@ -177,46 +191,63 @@ impl SigningDriver for SigstoreDriver {
     }
 
     fn verify(opts: &VerifyOpts) -> miette::Result<()> {
-        let mut client = ClientBuilder::default().build().into_diagnostic()?;
-
         let image_digest: OciReference = opts.image.to_string().parse().into_diagnostic()?;
         trace!("{image_digest:?}");
 
-        let signing_scheme = SigningScheme::default();
-
-        let pub_key = fs::read_to_string(match &opts.verify_type {
-            VerifyType::File(path) => path,
-            VerifyType::Keyless { .. } => {
-                todo!("Keyless currently not supported for sigstore driver")
+        let (mut client, verification_constraints): (_, VerificationConstraintVec) = match &opts
+            .verify_type
+        {
+            VerifyType::File(path) => {
+                let pub_key = fs::read_to_string(path)
+                    .into_diagnostic()
+                    .with_context(|| {
+                        format!("Failed to open public key file {}", path.display())
+                    })?;
+                debug!("Retrieved public key from {}", path.display());
+                trace!("{pub_key}");
+
+                let verifier =
+                    PublicKeyVerifier::new(pub_key.as_bytes(), &SigningScheme::default())
+                        .into_diagnostic()?;
+
+                (
+                    ClientBuilder::default().build().into_diagnostic()?,
+                    vec![Box::new(verifier)],
+                )
             }
-        })
-        .into_diagnostic()
-        .with_context(|| format!("Failed to open public key file {COSIGN_PUB_PATH}"))?;
-        debug!("Retrieved public key from {COSIGN_PUB_PATH}");
-        trace!("{pub_key}");
-
-        let verifier =
-            PublicKeyVerifier::new(pub_key.as_bytes(), &signing_scheme).into_diagnostic()?;
-        let verification_constraints: VerificationConstraintVec = vec![Box::new(verifier)];
+            VerifyType::Keyless { issuer, identity } => {
+                debug!("Fetching the Sigstore trust root");
+                let trust_root = crate::block_on(SigstoreTrustRoot::new(None))
+                    .into_diagnostic()
+                    .context("Failed to fetch the Sigstore trust root")?;
+
+                (
+                    ClientBuilder::default()
+                        .with_trust_repository(&trust_root)
+                        .into_diagnostic()?
+                        .build()
+                        .into_diagnostic()?,
+                    vec![Box::new(keyless::IdentityVerifier::new(issuer, identity)?)],
+                )
+            }
+        };
 
         debug!("Triangulating image");
         let auth = Auth::Anonymous;
         let (cosign_signature_image, source_image_digest) = retry(2, 5, || {
-            ASYNC_RUNTIME
-                .block_on(client.triangulate(&image_digest, &auth))
+            crate::block_on(client.triangulate(&image_digest, &auth))
                 .into_diagnostic()
                 .with_context(|| format!("Failed to triangulate image {image_digest}"))
         })?;
         trace!("{cosign_signature_image}, {source_image_digest}");
 
         let trusted_layers = retry(2, 5, || {
-            ASYNC_RUNTIME
-                .block_on(client.trusted_signature_layers(
-                    &auth,
-                    &source_image_digest,
-                    &cosign_signature_image,
-                ))
-                .into_diagnostic()
+            crate::block_on(client.trusted_signature_layers(
+                &auth,
+                &source_image_digest,
+                &cosign_signature_image,
+            ))
+            .into_diagnostic()
         })?;
 
         sigstore::cosign::verify_constraints(&trusted_layers, verification_constraints.iter())
@@ -234,6 +265,50 @@ impl SigningDriver for SigstoreDriver {
     }
 }
 
+/// Signs with a certificate from Fulcio, records the
+/// signature in Rekor, and pushes it to the registry.
+async fn sign_keyless(
+    auth: &Auth,
+    cosign_signature_image: &OciReference,
+    mut signature_layer: SignatureLayer,
+) -> miette::Result<()> {
+    let token = keyless::ambient_id_token().await?;
+    let (signer, certificate) = keyless::request_certificate(&token).await?;
+
+    PrivateKeySigner::new_with_signer(signer)
+        .add_constraint(&mut signature_layer)
+        .into_diagnostic()?;
+    let signature = signature_layer
+        .signature
+        .as_deref()
+        .ok_or_else(|| miette!("Failed to sign the signature layer"))?;
+    debug!("Created signing layer");
+
+    let bundle = keyless::upload_to_rekor(
+        &signature_layer.raw_data,
+        signature,
+        &certificate.certificate,
+    )
+    .await?;
+
+    debug!("Pushing signature");
+    let target: Reference = cosign_signature_image
+        .to_string()
+        .parse()
+        .into_diagnostic()?;
+    keyless::push_signature(
+        &auth.into(),
+        &target,
+        &signature_layer,
+        &certificate,
+        &bundle,
+    )
+    .await?;
+    debug!("Successfully pushed signature");
+
+    Ok(())
+}
+
 #[cfg(test)]
 mod test {
     use std::{fs, path::Path};
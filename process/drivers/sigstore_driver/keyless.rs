@@ -0,0 +1,596 @@
+//! Keyless signing and verification using Fulcio and Rekor.
+//!
+//! This follows the same flow as `cosign sign` without a key.
+//! An OIDC token from the CI environment is exchanged with Fulcio
+//! for a short lived certificate, the signature is recorded in
+//! Rekor, and the certificate and Rekor bundle are pushed as
+//! annotations on the signature so that both `cosign verify`
+//! and the sigstore driver can check them.
+
+use std::{collections::HashMap, env, fmt::Write as _, str::FromStr};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use blue_build_utils::constants::{
+    ACTIONS_ID_TOKEN_REQUEST_TOKEN, ACTIONS_ID_TOKEN_REQUEST_URL, SIGSTORE_ID_TOKEN,
+};
+use log::{debug, trace};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use oci_distribution::{
+    client::{Config, ImageLayer},
+    manifest::{OciImageManifest, OCI_IMAGE_MEDIA_TYPE},
+    secrets::RegistryAuth,
+    Client, Reference,
+};
+use openidconnect::core::CoreIdToken;
+use regex::Regex;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use sigstore::{
+    cosign::{
+        bundle::{Bundle, Payload},
+        signature_layers::CertificateSubject,
+        verification_constraint::VerificationConstraint,
+        SignatureLayer,
+    },
+    crypto::{signing_key::SigStoreSigner, Signature, SigningScheme},
+    fulcio::{FulcioClient, TokenProvider, FULCIO_ROOT},
+    rekor::models::{hashedrekord, Hashedrekord, ProposedEntry},
+};
+
+const REKOR_URL: &str = "https://rekor.sigstore.dev";
+const SIMPLE_SIGNING_MEDIA_TYPE: &str = "application/vnd.dev.cosign.simplesigning.v1+json";
+const SIGNATURE_ANNOTATION: &str = "dev.cosignproject.cosign/signature";
+const CERTIFICATE_ANNOTATION: &str = "dev.sigstore.cosign/certificate";
+const CHAIN_ANNOTATION: &str = "dev.sigstore.cosign/chain";
+const BUNDLE_ANNOTATION: &str = "dev.sigstore.cosign/bundle";
+const PEM_END: &str = "-----END CERTIFICATE-----";
+
+/// The certificate Fulcio issued for a signer.
+pub struct SigningCertificate {
+    pub certificate: String,
+    pub chain: String,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    value: String,
+}
+
+/// Gets an OIDC token for Fulcio from the environment.
+///
+/// The `SIGSTORE_ID_TOKEN` env var is used if it's set, which
+/// is how GitLab provides the token. Otherwise the token is
+/// requested from GitHub Actions.
+///
+/// # Errors
+/// Will error if no token is available or the request fails.
+pub async fn ambient_id_token() -> Result<String> {
+    if let Ok(token) = env::var(SIGSTORE_ID_TOKEN) {
+        debug!("Using OIDC token from {SIGSTORE_ID_TOKEN}");
+        return Ok(token);
+    }
+
+    let (Ok(url), Ok(request_token)) = (
+        env::var(ACTIONS_ID_TOKEN_REQUEST_URL),
+        env::var(ACTIONS_ID_TOKEN_REQUEST_TOKEN),
+    ) else {
+        bail!(
+            help = format!(
+                "Set {SIGSTORE_ID_TOKEN} or give the GitHub workflow the `id-token: write` permission"
+            ),
+            "No OIDC token is available for keyless signing"
+        );
+    };
+
+    debug!("Requesting OIDC token from GitHub Actions");
+    let response: TokenResponse = reqwest::Client::new()
+        .get(url)
+        .query(&[("audience", "sigstore")])
+        .bearer_auth(request_token)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .into_diagnostic()
+        .context("Failed to request OIDC token from GitHub Actions")?
+        .json()
+        .await
+        .into_diagnostic()?;
+
+    Ok(response.value)
+}
+
+/// Gets the claim from the token that Fulcio expects
+/// to be signed to prove possession of the key.
+///
+/// This is the `email` claim if there is one
+/// and the `sub` claim otherwise.
+fn token_challenge(token: &str) -> Result<String> {
+    let claims = token
+        .split('.')
+        .nth(1)
+        .ok_or_else(|| miette!("The OIDC token is not a JWT"))?;
+    let claims: serde_json::Value = serde_json::from_slice(
+        &URL_SAFE_NO_PAD
+            .decode(claims.trim_end_matches('='))
+            .into_diagnostic()
+            .context("Failed to decode OIDC token claims")?,
+    )
+    .into_diagnostic()?;
+
+    claims
+        .get("email")
+        .or_else(|| claims.get("sub"))
+        .and_then(serde_json::Value::as_str)
+        .map(ToOwned::to_owned)
+        .ok_or_else(|| miette!("The OIDC token doesn't have an email or sub claim"))
+}
+
+/// Creates a new key pair and gets a certificate
+/// for it from Fulcio using the OIDC `token`.
+///
+/// # Errors
+/// Will error if the token is invalid or Fulcio rejects it.
+pub async fn request_certificate(token: &str) -> Result<(SigStoreSigner, SigningCertificate)> {
+    let challenge = token_challenge(token)?;
+    trace!("OIDC token challenge: {challenge}");
+
+    let id_token = CoreIdToken::from_str(token)
+        .into_diagnostic()
+        .context("Failed to parse OIDC token")?;
+
+    let (signer, chain) = FulcioClient::new(
+        reqwest::Url::parse(FULCIO_ROOT).into_diagnostic()?,
+        TokenProvider::Static((id_token, challenge)),
+    )
+    .request_cert(SigningScheme::default())
+    .await
+    .into_diagnostic()
+    .context("Failed to get signing certificate from Fulcio")?;
+    debug!("Received signing certificate from Fulcio");
+
+    let (certificate, chain) = split_chain(&chain.to_string());
+
+    Ok((signer, SigningCertificate { certificate, chain }))
+}
+
+/// Splits a PEM certificate chain into the leaf
+/// certificate and the rest of the chain.
+fn split_chain(chain: &str) -> (String, String) {
+    chain.find(PEM_END).map_or_else(
+        || (chain.to_owned(), String::new()),
+        |end| {
+            let (leaf, rest) = chain.split_at(end + PEM_END.len());
+            (format!("{}\n", leaf.trim()), rest.trim_start().to_owned())
+        },
+    )
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RekorEntry {
+    body: String,
+    integrated_time: i64,
+    #[serde(rename = "logID")]
+    log_id: String,
+    log_index: i64,
+    verification: RekorVerification,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RekorVerification {
+    signed_entry_timestamp: String,
+}
+
+/// Records the signature of `payload` in Rekor and returns
+/// the bundle that proves it was recorded.
+///
+/// # Errors
+/// Will error if Rekor rejects the entry.
+pub async fn upload_to_rekor(payload: &[u8], signature: &str, certificate: &str) -> Result<Bundle> {
+    let entry = ProposedEntry::Hashedrekord {
+        api_version: "0.0.1".into(),
+        spec: hashedrekord::Spec::new(
+            hashedrekord::Signature::new(
+                signature.to_owned(),
+                hashedrekord::PublicKey::new(
+                    base64::engine::general_purpose::STANDARD.encode(certificate),
+                ),
+            ),
+            hashedrekord::Data::new(hashedrekord::Hash::new(
+                hashedrekord::AlgorithmKind::sha256,
+                sha256_hex(payload),
+            )),
+        ),
+    };
+
+    debug!("Uploading signature to Rekor");
+    let response = reqwest::Client::new()
+        .post(format!("{REKOR_URL}/api/v1/log/entries"))
+        .json(&entry)
+        .send()
+        .await
+        .and_then(reqwest::Response::error_for_status)
+        .into_diagnostic()
+        .context("Failed to upload signature to Rekor")?
+        .text()
+        .await
+        .into_diagnostic()?;
+
+    rekor_bundle(&response)
+}
+
+fn sha256_hex(payload: &[u8]) -> String {
+    Sha256::digest(payload)
+        .iter()
+        .fold(String::new(), |mut hash, byte| {
+            let _ = write!(hash, "{byte:02x}");
+            hash
+        })
+}
+
+fn rekor_bundle(response: &str) -> Result<Bundle> {
+    let entries: HashMap<String, RekorEntry> = serde_json::from_str(response)
+        .into_diagnostic()
+        .context("Failed to parse Rekor response")?;
+    let (uuid, entry) = entries
+        .into_iter()
+        .next()
+        .ok_or_else(|| miette!("Rekor didn't return a log entry"))?;
+    debug!("Signature recorded in Rekor with UUID {uuid}");
+
+    Ok(Bundle {
+        signed_entry_timestamp: entry.verification.signed_entry_timestamp,
+        payload: Payload {
+            body: entry.body,
+            integrated_time: entry.integrated_time,
+            log_index: entry.log_index,
+            log_id: entry.log_id,
+        },
+    })
+}
+
+/// Pushes a keyless signature to `target`
+/// the same way that `cosign` does.
+///
+/// # Errors
+/// Will error if the signature can't be pushed.
+pub async fn push_signature(
+    auth: &RegistryAuth,
+    target: &Reference,
+    layer: &SignatureLayer,
+    certificate: &SigningCertificate,
+    bundle: &Bundle,
+) -> Result<()> {
+    let signature = layer
+        .signature
+        .clone()
+        .ok_or_else(|| miette!("The signature layer hasn't been signed"))?;
+
+    let mut annotations = HashMap::from([
+        (SIGNATURE_ANNOTATION.to_owned(), signature),
+        (
+            CERTIFICATE_ANNOTATION.to_owned(),
+            certificate.certificate.clone(),
+        ),
+        (
+            BUNDLE_ANNOTATION.to_owned(),
+            serde_json::to_string(bundle).into_diagnostic()?,
+        ),
+    ]);
+    if !certificate.chain.is_empty() {
+        annotations.insert(CHAIN_ANNOTATION.to_owned(), certificate.chain.clone());
+    }
+
+    let layers = [ImageLayer::new(
+        layer.raw_data.clone(),
+        SIMPLE_SIGNING_MEDIA_TYPE.into(),
+        Some(annotations),
+    )];
+    let config = Config::oci_v1(b"{}".to_vec(), None);
+    let mut manifest = OciImageManifest::build(&layers, &config, None);
+    manifest.media_type = Some(OCI_IMAGE_MEDIA_TYPE.to_owned());
+
+    Client::default()
+        .push(target, &layers, config, auth, Some(manifest))
+        .await
+        .into_diagnostic()
+        .with_context(|| format!("Failed to push signature to {target}"))?;
+
+    Ok(())
+}
+
+/// Checks that a signature was made with a
+/// certificate for the expected identity.
+///
+/// The signature has to verify against the key in the
+/// certificate and has to be the one recorded in the
+/// Rekor bundle. The sigstore client only checks that the
+/// certificate and bundle themselves are trusted.
+///
+/// The `identity` is a regex like cosign's
+/// `--certificate-identity-regexp`.
+#[derive(Debug)]
+pub struct IdentityVerifier {
+    pub issuer: String,
+    pub identity: Regex,
+}
+
+impl IdentityVerifier {
+    /// # Errors
+    /// Will error if `identity` isn't a valid regex.
+    pub fn new(issuer: &str, identity: &str) -> Result<Self> {
+        Ok(Self {
+            issuer: issuer.to_owned(),
+            identity: Regex::new(identity).into_diagnostic()?,
+        })
+    }
+
+    fn matches(&self, issuer: Option<&str>, subject: &CertificateSubject) -> bool {
+        let (CertificateSubject::Email(subject) | CertificateSubject::Uri(subject)) = subject;
+        issuer == Some(self.issuer.as_str()) && self.identity.is_match(subject)
+    }
+}
+
+impl VerificationConstraint for IdentityVerifier {
+    fn verify(&self, signature_layer: &SignatureLayer) -> sigstore::errors::Result<bool> {
+        let (Some(cert), Some(signature)) = (
+            signature_layer.certificate_signature.as_ref(),
+            signature_layer.signature.as_deref(),
+        ) else {
+            return Ok(false);
+        };
+
+        if !self.matches(cert.issuer.as_deref(), &cert.subject) {
+            return Ok(false);
+        }
+
+        if let Err(e) = cert.verification_key.verify_signature(
+            Signature::Base64Encoded(signature.as_bytes()),
+            &signature_layer.raw_data,
+        ) {
+            debug!("Signature doesn't verify against its certificate: {e}");
+            return Ok(false);
+        }
+
+        Ok(signature_layer
+            .bundle
+            .as_ref()
+            .is_some_and(|bundle| bundle_matches(bundle, signature, &signature_layer.raw_data)))
+    }
+}
+
+/// Checks that the Rekor entry in `bundle`
+/// records `signature` over `payload`.
+fn bundle_matches(bundle: &Bundle, signature: &str, payload: &[u8]) -> bool {
+    let Some(entry) = base64::engine::general_purpose::STANDARD
+        .decode(&bundle.payload.body)
+        .ok()
+        .and_then(|body| serde_json::from_slice::<Hashedrekord>(&body).ok())
+    else {
+        debug!("Rekor bundle doesn't contain a hashedrekord entry");
+        return false;
+    };
+
+    let matches = entry.spec.signature.content == signature
+        && entry.spec.data.hash.algorithm == hashedrekord::AlgorithmKind::sha256
+        && entry.spec.data.hash.value == sha256_hex(payload);
+    if !matches {
+        debug!("Rekor bundle is for a different signature");
+    }
+    matches
+}
+
+#[cfg(test)]
+mod test {
+    use base64::{
+        engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+        Engine,
+    };
+    use rstest::rstest;
+    use sigstore::{
+        cosign::{
+            bundle::{Bundle, Payload},
+            constraint::PrivateKeySigner,
+            signature_layers::{CertificateSignature, CertificateSubject},
+            verification_constraint::VerificationConstraint,
+            Constraint, SignatureLayer,
+        },
+        crypto::{CosignVerificationKey, SigningScheme},
+        registry::OciReference,
+    };
+
+    use super::{rekor_bundle, sha256_hex, split_chain, token_challenge, IdentityVerifier};
+
+    const ISSUER: &str = "https://token.actions.githubusercontent.com";
+    const IDENTITY: &str =
+        "https://github.com/org/repo/.github/workflows/build.yml@refs/heads/main";
+
+    fn jwt(claims: &str) -> String {
+        format!(
+            "{}.{}.signature",
+            URL_SAFE_NO_PAD.encode(r#"{"alg":"RS256"}"#),
+            URL_SAFE_NO_PAD.encode(claims)
+        )
+    }
+
+    #[rstest]
+    #[case(
+        r#"{"sub":"repo:org/repo:ref:refs/heads/main"}"#,
+        "repo:org/repo:ref:refs/heads/main"
+    )]
+    #[case(r#"{"sub":"1234","email":"user@example.com"}"#, "user@example.com")]
+    fn challenge(#[case] claims: &str, #[case] expected: &str) {
+        assert_eq!(token_challenge(&jwt(claims)).unwrap(), expected);
+    }
+
+    #[test]
+    fn challenge_requires_claim() {
+        assert!(token_challenge(&jwt(r#"{"aud":"sigstore"}"#)).is_err());
+        assert!(token_challenge("not-a-jwt").is_err());
+    }
+
+    #[test]
+    fn split_certificate_chain() {
+        let chain = "-----BEGIN CERTIFICATE-----\nleaf\n-----END CERTIFICATE-----\n\
+                     -----BEGIN CERTIFICATE-----\nroot\n-----END CERTIFICATE-----\n";
+
+        assert_eq!(
+            split_chain(chain),
+            (
+                "-----BEGIN CERTIFICATE-----\nleaf\n-----END CERTIFICATE-----\n".to_owned(),
+                "-----BEGIN CERTIFICATE-----\nroot\n-----END CERTIFICATE-----\n".to_owned()
+            )
+        );
+    }
+
+    #[test]
+    fn bundle_from_rekor_response() {
+        let bundle = rekor_bundle(
+            r#"{
+              "24296fb24b8ad77a": {
+                "body": "eyJhcGlWZXJzaW9uIjoiMC4wLjEifQ==",
+                "integratedTime": 1634714179,
+                "logID": "c0d23d6ad406973f",
+                "logIndex": 783606,
+                "verification": { "signedEntryTimestamp": "MEUCIDx9" }
+              }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(bundle.signed_entry_timestamp, "MEUCIDx9");
+        assert_eq!(bundle.payload.log_index, 783_606);
+        assert_eq!(bundle.payload.log_id, "c0d23d6ad406973f");
+    }
+
+    #[test]
+    fn identity_matches() {
+        let verifier = IdentityVerifier::new(
+            "https://token.actions.githubusercontent.com",
+            "org/repo/.github/workflows/build.yml@refs/heads/main",
+        )
+        .unwrap();
+        let subject = CertificateSubject::Uri(
+            "https://github.com/org/repo/.github/workflows/build.yml@refs/heads/main".into(),
+        );
+
+        assert!(verifier.matches(
+            Some("https://token.actions.githubusercontent.com"),
+            &subject
+        ));
+        assert!(!verifier.matches(Some("https://gitlab.com"), &subject));
+        assert!(!verifier.matches(
+            Some("https://token.actions.githubusercontent.com"),
+            &CertificateSubject::Uri("https://github.com/other/repo".into())
+        ));
+    }
+
+    /// Creates a layer signed with a new key, a certificate
+    /// for that key, and a Rekor bundle for the signature.
+    fn signed_layer() -> SignatureLayer {
+        let image: OciReference = "ghcr.io/org/repo@sha256:f1143ec2786e13d7d3335dbb498528438d910648469d3f39647e1cde6914da8d"
+            .parse()
+            .unwrap();
+        let mut layer = SignatureLayer::new_unsigned(&image, image.digest().unwrap()).unwrap();
+
+        let signer = SigningScheme::default().create_signer().unwrap();
+        let verification_key = CosignVerificationKey::from_sigstore_signer(&signer).unwrap();
+        PrivateKeySigner::new_with_signer(signer)
+            .add_constraint(&mut layer)
+            .unwrap();
+
+        layer.certificate_signature = Some(CertificateSignature {
+            verification_key,
+            subject: CertificateSubject::Uri(IDENTITY.into()),
+            issuer: Some(ISSUER.into()),
+            github_workflow_trigger: None,
+            github_workflow_sha: None,
+            github_workflow_name: None,
+            github_workflow_repository: None,
+            github_workflow_ref: None,
+        });
+        layer.bundle = Some(bundle_for(
+            layer.signature.as_deref().unwrap(),
+            &sha256_hex(&layer.raw_data),
+        ));
+        layer
+    }
+
+    fn bundle_for(signature: &str, hash: &str) -> Bundle {
+        let body = serde_json::json!({
+            "apiVersion": "0.0.1",
+            "kind": "hashedrekord",
+            "spec": {
+                "data": { "hash": { "algorithm": "sha256", "value": hash } },
+                "signature": { "content": signature, "publicKey": { "content": "" } }
+            }
+        });
+
+        Bundle {
+            signed_entry_timestamp: String::new(),
+            payload: Payload {
+                body: STANDARD.encode(body.to_string()),
+                integrated_time: 0,
+                log_index: 0,
+                log_id: String::new(),
+            },
+        }
+    }
+
+    #[test]
+    fn verify_signed_layer() {
+        let verifier = IdentityVerifier::new(ISSUER, "org/repo/").unwrap();
+
+        assert!(verifier.verify(&signed_layer()).unwrap());
+    }
+
+    #[test]
+    fn verify_rejects_tampered_signature() {
+        let verifier = IdentityVerifier::new(ISSUER, "org/repo/").unwrap();
+        let mut layer = signed_layer();
+        let mut signature = STANDARD
+            .decode(layer.signature.as_deref().unwrap())
+            .unwrap();
+        let last = signature.len() - 1;
+        signature[last] ^= 1;
+        let signature = STANDARD.encode(signature);
+        layer.bundle = Some(bundle_for(&signature, &sha256_hex(&layer.raw_data)));
+        layer.signature = Some(signature);
+
+        assert!(!verifier.verify(&layer).unwrap());
+    }
+
+    #[test]
+    fn verify_rejects_tampered_payload() {
+        let verifier = IdentityVerifier::new(ISSUER, "org/repo/").unwrap();
+        let mut layer = signed_layer();
+        layer.raw_data.extend_from_slice(b" ");
+        layer.bundle = Some(bundle_for(
+            layer.signature.as_deref().unwrap(),
+            &sha256_hex(&layer.raw_data),
+        ));
+
+        assert!(!verifier.verify(&layer).unwrap());
+    }
+
+    #[test]
+    fn verify_rejects_mismatched_bundle() {
+        let verifier = IdentityVerifier::new(ISSUER, "org/repo/").unwrap();
+
+        let mut layer = signed_layer();
+        layer.bundle = Some(bundle_for("c2lnbmF0dXJl", &sha256_hex(&layer.raw_data)));
+        assert!(!verifier.verify(&layer).unwrap());
+
+        let mut layer = signed_layer();
+        layer.bundle = Some(bundle_for(
+            layer.signature.as_deref().unwrap(),
+            &sha256_hex(b"other"),
+        ));
+        assert!(!verifier.verify(&layer).unwrap());
+
+        let mut layer = signed_layer();
+        layer.bundle = None;
+        assert!(!verifier.verify(&layer).unwrap());
+    }
+}
@@ -1,4 +1,4 @@
-use std::{process::Stdio, time::Duration};
+use std::{collections::HashMap, process::Stdio, time::Duration};
 
 use blue_build_utils::cmd;
 use cached::proc_macro::cached;
@@ -6,27 +6,52 @@ use colored::Colorize;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, trace};
 use miette::{bail, IntoDiagnostic, Result};
+use serde::Deserialize;
 
 use crate::{drivers::types::Platform, logging::Logger};
 
-use super::{opts::GetMetadataOpts, types::ImageMetadata, InspectDriver};
+use super::{
+    opts::GetMetadataOpts,
+    types::{ImageLayer, ImageMetadata, OciImageConfig},
+    InspectDriver,
+};
 
 #[derive(Debug)]
 pub struct SkopeoDriver;
 
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct SkopeoInspect {
+    labels: HashMap<String, serde_json::Value>,
+    digest: String,
+
+    #[serde(default)]
+    layers_data: Option<Vec<SkopeoLayer>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "PascalCase")]
+struct SkopeoLayer {
+    digest: String,
+    size: u64,
+}
+
 impl InspectDriver for SkopeoDriver {
-    fn get_metadata(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
-        get_metadata_cache(opts)
+    async fn get_metadata_async(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
+        if opts.fresh {
+            get_metadata_cache_prime_cache(opts).await
+        } else {
+            get_metadata_cache(opts).await
+        }
     }
 }
 
 #[cached(
     result = true,
     key = "String",
-    convert = r#"{ format!("{}-{}", opts.image, opts.platform)}"#,
-    sync_writes = true
+    convert = r#"{ format!("{}-{}", opts.image, opts.platform)}"#
 )]
-fn get_metadata_cache(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
+async fn get_metadata_cache(opts: &GetMetadataOpts<'_>) -> Result<ImageMetadata> {
     trace!("SkopeoDriver::get_metadata({opts:#?})");
 
     let image_str = opts.image.to_string();
@@ -38,41 +63,79 @@ fn get_metadata_cache(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
     );
     progress.enable_steady_tick(Duration::from_millis(100));
 
-    let mut command = cmd!(
-        "skopeo",
-        if !matches!(opts.platform, Platform::Native) => [
-            "--override-arch",
-            opts.platform.arch(),
-        ],
-        "inspect",
-        format!("docker://{image_str}"),
-        stderr = Stdio::inherit(),
-    );
-    trace!("{command:?}");
-
-    let output = command.output().into_diagnostic()?;
+    let inspect = |config: bool| {
+        let command = cmd!(
+            "skopeo",
+            if !matches!(opts.platform, Platform::Native) => [
+                "--override-arch",
+                opts.platform.arch(),
+            ],
+            "inspect",
+            if config => "--config",
+            format!("docker://{image_str}"),
+            stderr = Stdio::inherit(),
+        );
+        trace!("{command:?}");
+        tokio::process::Command::from(command).output()
+    };
+
+    let (output, config_output) =
+        tokio::try_join!(inspect(false), inspect(true)).into_diagnostic()?;
 
     progress.finish_and_clear();
     Logger::multi_progress().remove(&progress);
 
-    if output.status.success() {
+    if output.status.success() && config_output.status.success() {
         debug!("Successfully inspected image {}!", image_str.bold().green());
     } else {
         bail!("Failed to inspect image {}", image_str.bold().red());
     }
-    serde_json::from_slice(&output.stdout).into_diagnostic()
+
+    let inspect: SkopeoInspect = serde_json::from_slice(&output.stdout).into_diagnostic()?;
+    let config: OciImageConfig = serde_json::from_slice(&config_output.stdout).into_diagnostic()?;
+
+    Ok(ImageMetadata {
+        labels: inspect.labels,
+        digest: inspect.digest,
+        config: config.config,
+        layers: inspect
+            .layers_data
+            .unwrap_or_default()
+            .into_iter()
+            .map(|layer| ImageLayer {
+                digest: layer.digest,
+                size: Some(layer.size),
+            })
+            .collect(),
+        history: config.history,
+        size: None,
+    })
+    .inspect(|metadata| trace!("{metadata:#?}"))
 }
 
 #[cfg(feature = "rechunk")]
 impl super::OciCopy for SkopeoDriver {
-    fn copy_oci_dir(
-        oci_dir: &super::types::OciDir,
-        registry: &oci_distribution::Reference,
-    ) -> Result<()> {
+    fn copy_oci_dir(opts: &super::opts::CopyOciDirOpts) -> Result<()> {
         use crate::logging::CommandLogging;
 
+        let super::opts::CopyOciDirOpts {
+            oci_dir, registry, ..
+        } = opts;
+
         let status = {
-            let c = cmd!("skopeo", "copy", oci_dir, format!("docker://{registry}"),);
+            let c = cmd!(
+                "skopeo",
+                "copy",
+                if opts.precompute_digests => "--dest-precompute-digests",
+                if let Some(format) = opts.format => ["--format", format.to_string()],
+                if let Some(concurrency) = opts.concurrency => [
+                    "--image-parallel-copies",
+                    concurrency.to_string(),
+                ],
+                for arg in &opts.extra_args => arg.arg(),
+                oci_dir,
+                format!("docker://{registry}"),
+            );
             trace!("{c:?}");
             c
         }
@@ -85,4 +148,22 @@ impl super::OciCopy for SkopeoDriver {
 
         Ok(())
     }
+
+    fn copy_image(source: &str, dest: &str) -> Result<()> {
+        use crate::logging::CommandLogging;
+
+        let status = {
+            let c = cmd!("skopeo", "copy", source, dest);
+            trace!("{c:?}");
+            c
+        }
+        .build_status(dest, format!("Copying {source} to"))
+        .into_diagnostic()?;
+
+        if !status.success() {
+            bail!("Failed to copy {source} to {dest}");
+        }
+
+        Ok(())
+    }
 }
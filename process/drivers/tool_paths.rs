@@ -0,0 +1,83 @@
+//! Checks the tools that are set to a path with `BB_<TOOL>_BIN`
+//! or the config files, so that a wrong path fails at startup
+//! instead of in the middle of a build.
+
+use std::path::Path;
+
+use blue_build_utils::tools;
+use colored::Colorize;
+use log::{debug, trace};
+use miette::{bail, Result};
+
+use super::{BuildahDriver, DockerDriver, Driver, DriverVersion, PodmanDriver};
+
+impl Driver {
+    /// Checks that every tool that is set to a path is executable,
+    /// and that the build drivers are a supported version.
+    ///
+    /// # Errors
+    /// Will error if a tool can't be run or isn't a supported version.
+    pub fn check_tool_paths() -> Result<()> {
+        trace!("Driver::check_tool_paths()");
+
+        for (tool, path) in tools::overrides() {
+            debug!("Using {} for {tool}", path.display());
+            check_tool_path(tool, &path)?;
+        }
+        Ok(())
+    }
+}
+
+fn check_tool_path(tool: &str, path: &Path) -> Result<()> {
+    if which::which(path).is_err() {
+        bail!(
+            help = format!(
+                "Set {} or `{tool}` in the `tools` of the config to the path of the binary",
+                tools::env_var(tool)
+            ),
+            "The path {} of {tool} isn't an executable",
+            path.display().to_string().bold().red()
+        );
+    }
+
+    match tool {
+        "docker" => check_version::<DockerDriver>(tool, path),
+        "podman" => check_version::<PodmanDriver>(tool, path),
+        "buildah" => check_version::<BuildahDriver>(tool, path),
+        _ => Ok(()),
+    }
+}
+
+fn check_version<T: DriverVersion>(tool: &str, path: &Path) -> Result<()> {
+    let version = match T::version() {
+        Ok(version) => version,
+        Err(e) => bail!(
+            "Failed to get the version of {tool} at {}: {e}",
+            path.display()
+        ),
+    };
+
+    if !T::is_supported_version() {
+        bail!(
+            "The version {version} of {tool} at {} isn't supported, {} is required",
+            path.display(),
+            T::VERSION_REQ
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use super::check_tool_path;
+
+    #[test]
+    fn missing_tool() {
+        let err = check_tool_path("cosign", Path::new("/nonexistent/cosign")).unwrap_err();
+
+        assert!(err.to_string().contains("/nonexistent/cosign"));
+        assert!(check_tool_path("bootc", Path::new("/bin/sh")).is_ok());
+    }
+}
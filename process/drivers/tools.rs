@@ -0,0 +1,265 @@
+//! Verification of the images that bluebuild copies into a build,
+//! like the build scripts, the modules, and cosign.
+//!
+//! Images published by a known workflow have their signature
+//! verified against its identity. Every image is checked against
+//! the digest recorded in the lockfile of the recipe when it has one.
+//!
+//! Helper images that are run with `--privileged`, like the rechunk
+//! image and the ISO installer, must always have a valid signature
+//! unless unverified tools are allowed.
+
+use blue_build_utils::constants::{BLUE_BUILD_IMAGE_REF, GITHUB_TOKEN_ISSUER_URL};
+use cached::proc_macro::cached;
+use colored::Colorize;
+use log::{debug, info, trace, warn};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+
+use crate::summary;
+
+use super::{
+    opts::{GetMetadataOpts, VerifyOpts, VerifyType},
+    Driver, InspectDriver, SigningDriver,
+};
+
+const VERIFY_HELP: &str =
+    "Run with `--lock` to record the new digest, or `--no-verify-tools` to skip verification";
+const HELPER_HELP: &str =
+    "Run with `--insecure-allow-unverified-tools` to run it anyway, which gives it root on this machine";
+
+/// The workflow that signs the images of a repository.
+#[derive(Debug, Clone, Copy)]
+struct ToolSigner {
+    /// The repository, which also covers the repositories under it.
+    repo: &'static str,
+    issuer: &'static str,
+    identity: &'static str,
+}
+
+const TOOL_SIGNERS: [ToolSigner; 5] = [
+    ToolSigner {
+        repo: BLUE_BUILD_IMAGE_REF,
+        issuer: GITHUB_TOKEN_ISSUER_URL,
+        identity: "^https://github.com/blue-build/cli/.github/workflows/",
+    },
+    ToolSigner {
+        repo: "ghcr.io/blue-build/modules",
+        issuer: GITHUB_TOKEN_ISSUER_URL,
+        identity: "^https://github.com/blue-build/modules/.github/workflows/",
+    },
+    ToolSigner {
+        repo: "ghcr.io/sigstore/cosign/cosign",
+        issuer: "https://accounts.google.com",
+        identity: "^keyless@projectsigstore.iam.gserviceaccount.com$",
+    },
+    ToolSigner {
+        repo: "ghcr.io/hhd-dev/rechunk",
+        issuer: GITHUB_TOKEN_ISSUER_URL,
+        identity: "^https://github.com/hhd-dev/rechunk/.github/workflows/",
+    },
+    ToolSigner {
+        repo: "ghcr.io/jasonn3/build-container-installer",
+        issuer: GITHUB_TOKEN_ISSUER_URL,
+        identity: "^https://github.com/JasonN3/build-container-installer/.github/workflows/",
+    },
+];
+
+fn find_signer(image: &Reference) -> Option<ToolSigner> {
+    let repo = format!("{}/{}", image.resolve_registry(), image.repository());
+
+    TOOL_SIGNERS.into_iter().find(|signer| {
+        repo == signer.repo
+            || repo
+                .strip_prefix(signer.repo)
+                .is_some_and(|rest| rest.starts_with('/'))
+    })
+}
+
+impl Driver {
+    /// Verifies an image that gets copied into a build and returns its digest.
+    ///
+    /// If `locked_digest` is given, the image must still resolve to it
+    /// and isn't verified again. Otherwise the signature of the image
+    /// is verified if it's published by a known workflow.
+    ///
+    /// # Errors
+    /// Will error if the digest doesn't match the lockfile
+    /// or the signature of the image isn't valid.
+    pub fn verify_tool_image(image: &Reference, locked_digest: Option<&str>) -> Result<String> {
+        trace!("Driver::verify_tool_image({image}, {locked_digest:?})");
+        verify_tool_image(image, locked_digest)
+    }
+
+    /// Verifies a helper image that is run with privileges and
+    /// returns it pinned to the digest that was verified, so
+    /// that the image that runs is the one that was verified.
+    ///
+    /// # Errors
+    /// Will error if the image has no known signer, can't be
+    /// verified offline, or its signature isn't valid, unless
+    /// unverified tools are allowed.
+    pub fn verify_helper_image(image: &Reference) -> Result<Reference> {
+        trace!("Driver::verify_helper_image({image})");
+
+        if Self::allows_unverified_tools() {
+            warn!(
+                "Running the helper image {} without verifying it",
+                image.to_string().bold()
+            );
+            summary::record_warning(format!(
+                "The privileged helper image `{image}` wasn't verified"
+            ));
+            return Ok(image.clone());
+        }
+
+        verify_helper_image(image)
+    }
+}
+
+#[cached(result = true, key = "String", convert = r#"{ image.to_string() }"#)]
+fn verify_helper_image(image: &Reference) -> Result<Reference> {
+    let Some(signer) = find_signer(image) else {
+        bail!(
+            help = HELPER_HELP,
+            "The helper image {} has no known signer, so it can't be verified",
+            image.to_string().bold().red()
+        );
+    };
+
+    if Driver::is_offline() {
+        bail!(
+            help = HELPER_HELP,
+            "The helper image {} can't be verified offline",
+            image.to_string().bold().red()
+        );
+    }
+
+    let digest = Driver::get_metadata(&GetMetadataOpts::builder().image(image).build())?.digest;
+    let pinned: Reference = format!(
+        "{}/{}@{digest}",
+        image.resolve_registry(),
+        image.repository()
+    )
+    .parse()
+    .into_diagnostic()?;
+
+    info!("Verifying the signature of {}", image.to_string().bold());
+    if let Err(e) = Driver::verify(
+        &VerifyOpts::builder()
+            .image(&pinned)
+            .verify_type(VerifyType::Keyless {
+                issuer: signer.issuer.into(),
+                identity: signer.identity.into(),
+            })
+            .build(),
+    ) {
+        bail!(
+            help = HELPER_HELP,
+            "Refusing to run the helper image {} with privileges, its signature isn't valid:\n{e}",
+            image.to_string().bold().red()
+        );
+    }
+
+    Ok(pinned)
+}
+
+#[cached(
+    result = true,
+    key = "String",
+    convert = r#"{ format!("{image}{locked_digest:?}") }"#,
+    sync_writes = true
+)]
+fn verify_tool_image(image: &Reference, locked_digest: Option<&str>) -> Result<String> {
+    let digest = Driver::get_metadata(&GetMetadataOpts::builder().image(image).build())?.digest;
+
+    if let Some(locked_digest) = locked_digest {
+        if digest != locked_digest {
+            bail!(
+                help = VERIFY_HELP,
+                "The image {} resolved to {digest}, but the lockfile has {locked_digest}",
+                image.to_string().bold().red()
+            );
+        }
+        debug!("The image {image} matches the lockfile");
+        return Ok(digest);
+    }
+
+    let Some(signer) = find_signer(image) else {
+        debug!("No known signer for {image}, only recording its digest");
+        return Ok(digest);
+    };
+
+    if Driver::is_offline() {
+        warn!(
+            "The image {} isn't in the lockfile and can't be verified offline",
+            image.to_string().bold()
+        );
+        summary::record_warning(format!(
+            "The image `{image}` isn't in the lockfile and wasn't verified"
+        ));
+        return Ok(digest);
+    }
+
+    info!("Verifying the signature of {}", image.to_string().bold());
+    let signed_image: Reference = format!(
+        "{}/{}@{digest}",
+        image.resolve_registry(),
+        image.repository()
+    )
+    .parse()
+    .into_diagnostic()?;
+
+    Driver::verify(
+        &VerifyOpts::builder()
+            .image(&signed_image)
+            .verify_type(VerifyType::Keyless {
+                issuer: signer.issuer.into(),
+                identity: signer.identity.into(),
+            })
+            .build(),
+    )
+    .with_context(|| {
+        format!("Failed to verify the signature of {image}, use `--no-verify-tools` to skip it")
+    })?;
+
+    Ok(digest)
+}
+
+#[cfg(test)]
+mod test {
+    use oci_distribution::Reference;
+    use rstest::rstest;
+
+    use super::find_signer;
+
+    #[rstest]
+    #[case(
+        "ghcr.io/blue-build/cli:latest-installer",
+        Some("ghcr.io/blue-build/cli")
+    )]
+    #[case(
+        "ghcr.io/blue-build/cli/build-scripts:main",
+        Some("ghcr.io/blue-build/cli")
+    )]
+    #[case("ghcr.io/blue-build/cli-fork:latest", None)]
+    #[case(
+        "ghcr.io/blue-build/modules:latest",
+        Some("ghcr.io/blue-build/modules")
+    )]
+    #[case(
+        "ghcr.io/sigstore/cosign/cosign:v2.4.1",
+        Some("ghcr.io/sigstore/cosign/cosign")
+    )]
+    #[case("ghcr.io/hhd-dev/rechunk:v1.0.1", Some("ghcr.io/hhd-dev/rechunk"))]
+    #[case(
+        "ghcr.io/jasonn3/build-container-installer:latest",
+        Some("ghcr.io/jasonn3/build-container-installer")
+    )]
+    #[case("ghcr.io/octocat/modules:latest", None)]
+    fn signer_of_image(#[case] image: &str, #[case] expected: Option<&str>) {
+        let image: Reference = image.parse().unwrap();
+
+        assert_eq!(find_signer(&image).map(|signer| signer.repo), expected);
+    }
+}
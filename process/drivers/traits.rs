@@ -1,5 +1,6 @@
 use std::{
-    borrow::Borrow,
+    borrow::{Borrow, Cow},
+    future::Future,
     path::PathBuf,
     process::{ExitStatus, Output},
 };
@@ -10,31 +11,45 @@ use miette::{bail, Context, IntoDiagnostic, Result};
 use oci_distribution::Reference;
 use semver::{Version, VersionReq};
 
-use crate::drivers::{functions::get_private_key, types::CiDriverType, Driver};
+use crate::{
+    drivers::{
+        functions::{get_private_key, run_first_then_concurrently, with_built_stages},
+        local_inspect,
+        types::CiDriverType,
+        Driver,
+    },
+    summary::BuildSummary,
+};
 
+#[cfg(feature = "test")]
+use super::mock_driver::MockDriver;
 #[cfg(feature = "sigstore")]
 use super::sigstore_driver::SigstoreDriver;
 use super::{
     buildah_driver::BuildahDriver,
     cosign_driver::CosignDriver,
     docker_driver::DockerDriver,
+    external_driver::ExternalDriver,
     github_driver::GithubDriver,
     gitlab_driver::GitlabDriver,
     local_driver::LocalDriver,
+    oci_client_driver::OciClientDriver,
     opts::{
-        BuildOpts, BuildTagPushOpts, CheckKeyPairOpts, GenerateImageNameOpts, GenerateKeyPairOpts,
-        GenerateTagsOpts, GetMetadataOpts, PushOpts, RunOpts, SignOpts, SignVerifyOpts, TagOpts,
-        VerifyOpts, VerifyType,
+        BuildContext, BuildOpts, BuildStageOpts, BuildTagPushOpts, CheckKeyPairOpts,
+        GenerateImageNameOpts, GenerateKeyPairOpts, GenerateTagsOpts, GetMetadataOpts, PushOpts,
+        RunOpts, SignOpts, SignVerifyOpts, TagOpts, VerifyOpts, VerifyType,
     },
     podman_driver::PodmanDriver,
     skopeo_driver::SkopeoDriver,
-    types::ImageMetadata,
+    types::{ImageMetadata, LocalImage},
 };
 #[cfg(feature = "rechunk")]
 use super::{
-    opts::RechunkOpts,
+    opts::{MergeLayersOpts, RechunkOpts, Rechunker},
     types::{ContainerId, MountId},
 };
+#[cfg(feature = "rechunk")]
+use crate::signal_handler::{CleanupGuard, CleanupItem, ContainerRuntime};
 
 trait PrivateDriver {}
 
@@ -56,12 +71,17 @@ impl_private_driver!(
     LocalDriver,
     CosignDriver,
     SkopeoDriver,
+    OciClientDriver,
+    ExternalDriver,
     CiDriverType,
 );
 
 #[cfg(feature = "sigstore")]
 impl_private_driver!(SigstoreDriver);
 
+#[cfg(feature = "test")]
+impl_private_driver!(MockDriver);
+
 /// Trait for retrieving version of a driver.
 #[allow(private_bounds)]
 pub trait DriverVersion: PrivateDriver {
@@ -93,6 +113,19 @@ pub trait BuildDriver: PrivateDriver {
     /// Will error if the build fails.
     fn build(opts: &BuildOpts) -> Result<()>;
 
+    /// Builds a single stage of a Containerfile on its own so
+    /// that another build can use it in place of the stage.
+    ///
+    /// # Errors
+    /// Will error if the build fails.
+    fn build_stage(opts: &BuildStageOpts) -> Result<BuildContext>;
+
+    /// Removes a stage that was built with [`BuildDriver::build_stage`].
+    ///
+    /// # Errors
+    /// Will error if the stage can't be removed.
+    fn remove_stage(context: &BuildContext) -> Result<()>;
+
     /// Runs the tag logic for the driver.
     ///
     /// # Errors
@@ -111,6 +144,42 @@ pub trait BuildDriver: PrivateDriver {
     /// Will error if login fails.
     fn login() -> Result<()>;
 
+    /// The directory the driver keeps its images and containers
+    /// in, or `None` if the driver doesn't report it.
+    ///
+    /// # Errors
+    /// Will error if the driver fails to report its storage.
+    fn storage_dir() -> Result<Option<PathBuf>> {
+        Ok(None)
+    }
+
+    /// The skopeo transport of the local storage the driver keeps
+    /// its images in, like `containers-storage:`, or `None` if
+    /// skopeo can't read its images.
+    #[must_use]
+    fn storage_transport() -> Option<&'static str> {
+        None
+    }
+
+    /// Lists every image in the local storage of the
+    /// driver, including the intermediate images.
+    ///
+    /// # Errors
+    /// Will error if the driver can't list its images.
+    fn list_local_images() -> Result<Vec<LocalImage>> {
+        bail!("Listing the local images isn't supported by this build driver")
+    }
+
+    /// Removes images from the local storage of the driver
+    /// by their tags, or by their IDs if they have no tags.
+    ///
+    /// # Errors
+    /// Will error if an image can't be removed.
+    fn remove_local_images(images: &[String]) -> Result<()> {
+        let _ = images;
+        bail!("Removing local images isn't supported by this build driver")
+    }
+
     /// Runs prune commands for the driver.
     ///
     /// # Errors
@@ -134,21 +203,29 @@ pub trait BuildDriver: PrivateDriver {
             (None, None) => bail!("Need either the image or archive path set"),
         };
 
-        let build_opts = BuildOpts::builder()
-            .image(&full_image)
-            .containerfile(opts.containerfile.as_ref())
-            .platform(opts.platform)
-            .squash(opts.squash)
-            .build();
-
-        info!("Building image {full_image}");
-        Self::build(&build_opts)?;
+        with_built_stages::<Self, _>(opts, |build_contexts| {
+            let build_opts = BuildOpts::builder()
+                .image(&full_image)
+                .containerfile(opts.containerfile.as_ref())
+                .platform(opts.platform)
+                .squash(opts.squash)
+                .secrets(opts.secrets.clone())
+                .build_contexts(build_contexts)
+                .cache(opts.cache.clone())
+                .proxy(opts.proxy)
+                .pull(opts.pull)
+                .extra_args(opts.build_extra_args.clone())
+                .build();
+
+            info!("Building image {full_image}");
+            Self::build(&build_opts)
+        })?;
 
         let image_list: Vec<String> = if !opts.tags.is_empty() && opts.archive_path.is_none() {
             let image = opts.image.unwrap();
             debug!("Tagging all images");
 
-            let mut image_list = Vec::with_capacity(opts.tags.len());
+            let mut tagged_images = Vec::with_capacity(opts.tags.len());
 
             for tag in &opts.tags {
                 debug!("Tagging {} with {tag}", &full_image);
@@ -163,33 +240,49 @@ pub trait BuildDriver: PrivateDriver {
                     .build();
 
                 Self::tag(&tag_opts)?;
-                image_list.push(tagged_image.to_string());
-
-                if opts.push {
-                    let retry_count = if opts.retry_push { opts.retry_count } else { 0 };
-
-                    debug!("Pushing all images");
-                    // Push images with retries (1s delay between retries)
-                    blue_build_utils::retry(retry_count, 5, || {
-                        debug!("Pushing image {tagged_image}");
-
-                        let push_opts = PushOpts::builder()
-                            .image(&tagged_image)
-                            .compression_type(opts.compression)
-                            .build();
+                tagged_images.push(tagged_image);
+            }
 
-                        Self::push(&push_opts)
-                    })?;
-                }
+            if opts.push {
+                Self::push_tagged(&tagged_images, opts)?;
             }
 
-            image_list
+            tagged_images.iter().map(ToString::to_string).collect()
         } else {
             string_vec![&full_image]
         };
 
         Ok(image_list)
     }
+
+    /// Pushes the tagged images of a build with the push
+    /// options of `opts`, like after the image was tested.
+    ///
+    /// # Errors
+    /// Will error if pushing any of the images fails.
+    fn push_tagged(tagged_images: &[Reference], opts: &BuildTagPushOpts) -> Result<()> {
+        let retry_count = if opts.retry_push { opts.retry_count } else { 0 };
+
+        debug!(
+            "Pushing the first image, then the rest {} at a time",
+            opts.push_jobs
+        );
+        run_first_then_concurrently(tagged_images, opts.push_jobs, |tagged_image| {
+            // Push images with retries (5s delay between retries)
+            blue_build_utils::retry(retry_count, 5, || {
+                debug!("Pushing image {tagged_image}");
+
+                let push_opts = PushOpts::builder()
+                    .image(tagged_image)
+                    .compression_type(opts.compression)
+                    .extra_args(opts.push_extra_args.clone())
+                    .build();
+
+                Self::push(&push_opts)
+            })
+        })?;
+        Ok(())
+    }
 }
 
 /// Allows agnostic inspection of images.
@@ -199,7 +292,18 @@ pub trait InspectDriver: PrivateDriver {
     ///
     /// # Errors
     /// Will error if it is unable to get the labels.
-    fn get_metadata(opts: &GetMetadataOpts) -> Result<ImageMetadata>;
+    fn get_metadata(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
+        crate::block_on(Self::get_metadata_async(opts))
+    }
+
+    /// Gets the metadata on an image tag without
+    /// blocking the current thread.
+    ///
+    /// # Errors
+    /// Will error if it is unable to get the labels.
+    fn get_metadata_async(
+        opts: &GetMetadataOpts,
+    ) -> impl Future<Output = Result<ImageMetadata>> + Send;
 }
 
 /// Allows agnostic running of containers.
@@ -216,6 +320,26 @@ pub trait RunDriver: PrivateDriver {
     /// # Errors
     /// Will error if there is an issue running the container.
     fn run_output(opts: &RunOpts) -> Result<Output>;
+
+    /// Gets the metadata on an image in the local storage
+    /// of the run driver. This is used when building offline.
+    ///
+    /// # Errors
+    /// Will error if the image isn't in local storage.
+    fn get_local_metadata(opts: &GetMetadataOpts) -> Result<ImageMetadata> {
+        crate::block_on(Self::get_local_metadata_async(opts))
+    }
+
+    /// Gets the metadata on an image in the local storage
+    /// of the run driver without blocking the current thread.
+    ///
+    /// # Errors
+    /// Will error if the image isn't in local storage.
+    fn get_local_metadata_async(
+        opts: &GetMetadataOpts,
+    ) -> impl Future<Output = Result<ImageMetadata>> + Send {
+        local_inspect::get_metadata(opts)
+    }
 }
 
 #[allow(private_bounds)]
@@ -260,10 +384,79 @@ pub(super) trait ContainerMountDriver: PrivateDriver {
 
 #[cfg(feature = "rechunk")]
 pub(super) trait OciCopy {
-    fn copy_oci_dir(
-        oci_dir: &super::types::OciDir,
-        registry: &oci_distribution::Reference,
-    ) -> Result<()>;
+    fn copy_oci_dir(opts: &super::opts::CopyOciDirOpts) -> Result<()>;
+
+    /// Copies an image between two transports, like
+    /// `containers-storage:` and `oci:`.
+    fn copy_image(source: &str, dest: &str) -> Result<()>;
+}
+
+#[allow(private_bounds)]
+#[cfg(feature = "rechunk")]
+pub trait LayerMergeDriver: OciCopy {
+    /// Merges the smallest adjacent layers of a built image until
+    /// it has at most `max_layers` layers, then pushes it or
+    /// replaces the local tags with the merged image.
+    ///
+    /// # Errors
+    /// Will error if the image can't be exported,
+    /// merged, or copied to its tags.
+    fn merge_layers(opts: &MergeLayersOpts) -> Result<Vec<String>> {
+        let Some(transport) = Driver::storage_transport() else {
+            bail!("Merging layers isn't supported by this build driver");
+        };
+
+        let temp_dir = if let Some(dir) = opts.tempdir {
+            tempfile::TempDir::new_in(dir).into_diagnostic()?
+        } else {
+            tempfile::TempDir::new().into_diagnostic()?
+        };
+        let _temp_dir_cleanup = CleanupGuard::new(CleanupItem::Dir(temp_dir.path().to_owned()));
+        let oci_path = temp_dir.path().join("image");
+
+        Self::copy_image(
+            &format!("{transport}{}", opts.image),
+            &format!("oci:{}", oci_path.display()),
+        )?;
+        let layers =
+            super::layer_merge::merge_oci_dir(&oci_path, opts.max_layers.get(), opts.keep_size)?;
+        info!("Merged the layers of {} down to {layers}", opts.image);
+
+        let oci_dir = &super::types::OciDir::try_from(oci_path)?;
+        let tagged_images = opts
+            .tags
+            .iter()
+            .map(|tag| {
+                Reference::with_tag(
+                    opts.image.registry().to_string(),
+                    opts.image.repository().to_string(),
+                    tag.to_string(),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        if opts.push {
+            run_first_then_concurrently(&tagged_images, opts.push_jobs, |tagged_image| {
+                blue_build_utils::retry(opts.retry_count, 5, || {
+                    debug!("Pushing image {tagged_image}");
+
+                    Driver::copy_oci_dir(
+                        &super::opts::CopyOciDirOpts::builder()
+                            .oci_dir(oci_dir)
+                            .registry(tagged_image)
+                            .extra_args(opts.push_extra_args.clone())
+                            .build(),
+                    )
+                })
+            })?;
+        } else {
+            for tagged_image in &tagged_images {
+                Self::copy_image(&oci_dir.to_string(), &format!("{transport}{tagged_image}"))?;
+            }
+        }
+
+        Ok(tagged_images.into_iter().map(Into::into).collect())
+    }
 }
 
 #[allow(private_bounds)]
@@ -273,14 +466,31 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
 
     /// Perform a rechunk build of a recipe.
     ///
+    /// The image is built and squashed, split into chunks by
+    /// the rechunker in [`RechunkOpts::rechunker`], and then
+    /// pushed from the OCI directory that the rechunker wrote.
+    ///
     /// # Errors
     /// Will error if the rechunk process fails.
     fn rechunk(opts: &RechunkOpts) -> Result<Vec<String>> {
+        // The helper image is verified before building
+        // so that a bad signature doesn't waste a build
+        let helper_image = match opts.rechunker {
+            Rechunker::Hhd => Some(
+                Driver::verify_helper_image(
+                    &Reference::try_from(blue_build_utils::helper_images::helper_image(
+                        "rechunk",
+                        Self::RECHUNK_IMAGE,
+                    ))
+                    .into_diagnostic()?,
+                )?
+                .to_string(),
+            ),
+            Rechunker::RpmOstree => None,
+        };
         let ostree_cache_id = &uuid::Uuid::new_v4().to_string();
         let raw_image =
             &Reference::try_from(format!("localhost/{ostree_cache_id}/raw-rechunk")).unwrap();
-        let current_dir = &std::env::current_dir().into_diagnostic()?;
-        let current_dir = &*current_dir.to_string_lossy();
         let full_image = Reference::try_from(opts.tags.first().map_or_else(
             || opts.image.to_string(),
             |tag| format!("{}:{tag}", opts.image),
@@ -294,46 +504,195 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
                 .platform(opts.platform)
                 .squash(true)
                 .host_network(true)
+                .secrets(opts.secrets.clone())
+                .build_contexts(opts.build_contexts.clone())
+                .extra_args(opts.build_extra_args.clone())
                 .build(),
         )?;
 
-        let container = &Self::create_container(raw_image)?;
-        let mount = &Self::mount_container(container)?;
-
-        Self::prune_image(mount, container, raw_image, opts)?;
-        Self::create_ostree_commit(mount, ostree_cache_id, container, raw_image, opts)?;
-
         let temp_dir = if let Some(dir) = opts.tempdir {
             tempfile::TempDir::new_in(dir).into_diagnostic()?
         } else {
             tempfile::TempDir::new().into_diagnostic()?
         };
+        let _temp_dir_cleanup = CleanupGuard::new(CleanupItem::Dir(temp_dir.path().to_owned()));
         let temp_dir_str = &*temp_dir.path().to_string_lossy();
 
-        Self::rechunk_image(ostree_cache_id, temp_dir_str, current_dir, opts)?;
-
-        let mut image_list = Vec::with_capacity(opts.tags.len());
+        info!("Rechunking {} with {}", opts.image, opts.rechunker);
+        match helper_image {
+            Some(helper_image) => Self::hhd_rechunk(
+                &helper_image,
+                raw_image,
+                ostree_cache_id,
+                temp_dir_str,
+                opts,
+            )?,
+            None => Self::build_chunked_oci(raw_image, ostree_cache_id, temp_dir_str, opts)?,
+        }
 
-        if opts.push {
-            let oci_dir = &super::types::OciDir::try_from(temp_dir.path().join(ostree_cache_id))?;
+        if !opts.push {
+            return Ok(Vec::new());
+        }
 
-            for tag in &opts.tags {
-                let tagged_image = Reference::with_tag(
+        let oci_dir = &super::types::OciDir::try_from(temp_dir.path().join(ostree_cache_id))?;
+        let tagged_images = opts
+            .tags
+            .iter()
+            .map(|tag| {
+                Reference::with_tag(
                     full_image.registry().to_string(),
                     full_image.repository().to_string(),
                     tag.to_string(),
-                );
+                )
+            })
+            .collect::<Vec<_>>();
+
+        run_first_then_concurrently(&tagged_images, opts.push_jobs, |tagged_image| {
+            blue_build_utils::retry(opts.retry_count, 5, || {
+                debug!("Pushing image {tagged_image}");
+
+                Driver::copy_oci_dir(
+                    &super::opts::CopyOciDirOpts::builder()
+                        .oci_dir(oci_dir)
+                        .registry(tagged_image)
+                        .maybe_concurrency(opts.push_concurrency)
+                        .extra_args(opts.push_extra_args.clone())
+                        .build(),
+                )
+            })
+        })?;
 
-                blue_build_utils::retry(opts.retry_count, 5, || {
-                    debug!("Pushing image {tagged_image}");
+        Ok(tagged_images.into_iter().map(Into::into).collect())
+    }
 
-                    Driver::copy_oci_dir(oci_dir, &tagged_image)
-                })?;
-                image_list.push(tagged_image.into());
-            }
+    /// Chunks the raw image with the scripts of `helper_image`
+    /// into the OCI directory `<temp_dir_str>/<ostree_cache_id>`.
+    ///
+    /// # Errors
+    /// Will error if any of the rechunk steps fail.
+    fn hhd_rechunk(
+        helper_image: &str,
+        raw_image: &Reference,
+        ostree_cache_id: &str,
+        temp_dir_str: &str,
+        opts: &RechunkOpts<'_>,
+    ) -> Result<()> {
+        let current_dir = &std::env::current_dir().into_diagnostic()?;
+        let current_dir = &*current_dir.to_string_lossy();
+
+        // Rechunking is only supported by podman, which runs as root
+        let image_cleanup = CleanupGuard::new(CleanupItem::Image {
+            name: raw_image.to_string(),
+            container_runtime: ContainerRuntime::Podman,
+            requires_sudo: false,
+        });
+        let container = &Self::create_container(raw_image)?;
+        let container_cleanup = CleanupGuard::new(CleanupItem::Container {
+            container_id: container.to_string(),
+            container_runtime: ContainerRuntime::Podman,
+            requires_sudo: false,
+        });
+        let mount = &Self::mount_container(container)?;
+        let mount_cleanup = CleanupGuard::new(CleanupItem::Mount {
+            container_id: container.to_string(),
+            container_runtime: ContainerRuntime::Podman,
+            requires_sudo: false,
+        });
+
+        Self::prune_image(helper_image, mount, container, raw_image, opts)?;
+
+        let volume_cleanup = CleanupGuard::new(CleanupItem::Volume {
+            name: ostree_cache_id.to_owned(),
+            container_runtime: ContainerRuntime::Podman,
+            requires_sudo: false,
+        });
+        Self::create_ostree_commit(
+            helper_image,
+            mount,
+            ostree_cache_id,
+            container,
+            raw_image,
+            opts,
+        )?;
+
+        // The ostree commit step removes these, so they
+        // shouldn't be removed again if the build is killed
+        drop(mount_cleanup);
+        drop(container_cleanup);
+        drop(image_cleanup);
+
+        Self::rechunk_image(
+            helper_image,
+            ostree_cache_id,
+            temp_dir_str,
+            current_dir,
+            opts,
+        )?;
+        drop(volume_cleanup);
+        Ok(())
+    }
+
+    /// Chunks the raw image with `rpm-ostree compose build-chunked-oci`
+    /// into the OCI directory `<temp_dir_str>/<ostree_cache_id>`.
+    ///
+    /// The raw image runs the command itself, reading itself from
+    /// the container storage of root. The labels of the chunked image
+    /// are the labels that the Containerfile set on the raw image.
+    ///
+    /// # Errors
+    /// Will error if rpm-ostree fails or the image doesn't have it.
+    fn build_chunked_oci(
+        raw_image: &Reference,
+        ostree_cache_id: &str,
+        temp_dir_str: &str,
+        opts: &RechunkOpts<'_>,
+    ) -> Result<()> {
+        const CONTAINER_STORAGE: &str = "/var/lib/containers";
+
+        let _image_cleanup = CleanupGuard::new(CleanupItem::Image {
+            name: raw_image.to_string(),
+            container_runtime: ContainerRuntime::Podman,
+            requires_sudo: false,
+        });
+
+        let mut args: Vec<Cow<'_, str>> = bon::vec![
+            "rpm-ostree",
+            "compose",
+            "build-chunked-oci",
+            "--bootc",
+            "--format-version=1",
+            format!("--from={raw_image}"),
+            format!("--output=oci:/workspace/{ostree_cache_id}"),
+        ];
+        if let Some(max_layers) = opts.max_layers {
+            args.push(format!("--max-layers={max_layers}").into());
         }
 
-        Ok(image_list)
+        let status = Self::run(
+            &RunOpts::builder()
+                .image(raw_image.to_string())
+                .remove(true)
+                .user("0:0")
+                .privileged(true)
+                .volumes(crate::run_volumes! {
+                    CONTAINER_STORAGE => CONTAINER_STORAGE,
+                    temp_dir_str => "/workspace",
+                })
+                .args(args)
+                .build(),
+        );
+        Self::remove_image(raw_image)?;
+
+        if !status?.success() {
+            bail!(
+                help =
+                    "The image needs a version of rpm-ostree that has `compose build-chunked-oci`",
+                "Failed to run rpm-ostree rechunking for {}",
+                &opts.image
+            );
+        }
+
+        Ok(())
     }
 
     /// Step 1 of the rechunk process that prunes excess files.
@@ -341,6 +700,7 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
     /// # Errors
     /// Will error if the prune process fails.
     fn prune_image(
+        rechunk_image: &str,
         mount: &MountId,
         container: &ContainerId,
         raw_image: &Reference,
@@ -348,7 +708,7 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
     ) -> Result<(), miette::Error> {
         let status = Self::run(
             &RunOpts::builder()
-                .image(Self::RECHUNK_IMAGE)
+                .image(rechunk_image)
                 .remove(true)
                 .user("0:0")
                 .privileged(true)
@@ -377,6 +737,7 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
     /// # Errors
     /// Will error if the ostree commit process fails.
     fn create_ostree_commit(
+        rechunk_image: &str,
         mount: &MountId,
         ostree_cache_id: &str,
         container: &ContainerId,
@@ -385,7 +746,7 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
     ) -> Result<()> {
         let status = Self::run(
             &RunOpts::builder()
-                .image(Self::RECHUNK_IMAGE)
+                .image(rechunk_image)
                 .remove(true)
                 .user("0:0")
                 .privileged(true)
@@ -406,6 +767,7 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
         Self::remove_image(raw_image)?;
 
         if !status.success() {
+            Self::remove_volume(ostree_cache_id)?;
             bail!("Failed to run Ostree create step for {}", &opts.image);
         }
 
@@ -417,49 +779,41 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
     /// # Errors
     /// Will error if the chunk process fails.
     fn rechunk_image(
+        rechunk_image: &str,
         ostree_cache_id: &str,
         temp_dir_str: &str,
         current_dir: &str,
         opts: &RechunkOpts<'_>,
     ) -> Result<()> {
         let status = Self::run(
-        &RunOpts::builder()
-            .image(Self::RECHUNK_IMAGE)
-            .remove(true)
-            .user("0:0")
-            .privileged(true)
-            .volumes(crate::run_volumes! {
-                ostree_cache_id => "/var/ostree",
-                temp_dir_str => "/workspace",
-                current_dir => "/var/git"
-            })
-            .env_vars(crate::run_envs! {
-                "REPO" => "/var/ostree/repo",
-                "PREV_REF" => &*opts.image,
-                "OUT_NAME" => ostree_cache_id,
-                "CLEAR_PLAN" => if opts.clear_plan { "true" } else { "" },
-                "VERSION" => format!("{}", opts.version),
-                "OUT_REF" => format!("oci:{ostree_cache_id}"),
-                "GIT_DIR" => "/var/git",
-                "LABELS" => format!(
-                    "{}\n{}\n{}\n{}\n{}\n{}\n{}\n{}",
-                    format_args!("{}={}", blue_build_utils::constants::BUILD_ID_LABEL, Driver::get_build_id()),
-                    format_args!("org.opencontainers.image.title={}", &opts.name),
-                    format_args!("org.opencontainers.image.description={}", &opts.description),
-                    format_args!("org.opencontainers.image.source={}", &opts.repo),
-                    format_args!("org.opencontainers.image.base.digest={}", &opts.base_digest),
-                    format_args!("org.opencontainers.image.base.name={}", &opts.base_image),
-                    "org.opencontainers.image.created=<timestamp>",
-                    "io.artifacthub.package.readme-url=https://raw.githubusercontent.com/blue-build/cli/main/README.md",
-                )
-            })
-            .args(bon::vec!["/sources/rechunk/3_chunk.sh"])
-            .build(),
-        )?;
+            &RunOpts::builder()
+                .image(rechunk_image)
+                .remove(true)
+                .user("0:0")
+                .privileged(true)
+                .volumes(crate::run_volumes! {
+                    ostree_cache_id => "/var/ostree",
+                    temp_dir_str => "/workspace",
+                    current_dir => "/var/git"
+                })
+                .env_vars(crate::run_envs! {
+                    "REPO" => "/var/ostree/repo",
+                    "PREV_REF" => &*opts.image,
+                    "OUT_NAME" => ostree_cache_id,
+                    "CLEAR_PLAN" => if opts.clear_plan { "true" } else { "" },
+                    "VERSION" => format!("{}", opts.version),
+                    "OUT_REF" => format!("oci:{ostree_cache_id}"),
+                    "GIT_DIR" => "/var/git",
+                    "LABELS" => rechunk_labels(opts),
+                    "MAX_LAYERS" => opts.max_layers.map(|max| max.to_string()).unwrap_or_default(),
+                })
+                .args(bon::vec!["/sources/rechunk/3_chunk.sh"])
+                .build(),
+        );
 
         Self::remove_volume(ostree_cache_id)?;
 
-        if !status.success() {
+        if !status?.success() {
             bail!("Failed to run rechunking for {}", &opts.image);
         }
 
@@ -467,6 +821,28 @@ pub trait RechunkDriver: RunDriver + BuildDriver + ContainerMountDriver {
     }
 }
 
+/// The labels of a chunked image, one `key=value` per line.
+/// The rechunk script fills in `<timestamp>`.
+#[cfg(feature = "rechunk")]
+fn rechunk_labels(opts: &RechunkOpts) -> String {
+    use std::fmt::Write as _;
+
+    const CREATED: &str = "org.opencontainers.image.created";
+
+    let mut labels = format!(
+        "{}={}",
+        blue_build_utils::constants::BUILD_ID_LABEL,
+        Driver::get_build_id()
+    );
+    for (key, value) in &opts.labels {
+        _ = write!(labels, "\n{key}={value}");
+    }
+    if !opts.labels.contains_key(CREATED) {
+        _ = write!(labels, "\n{CREATED}=<timestamp>");
+    }
+    labels
+}
+
 /// Allows agnostic management of signature keys.
 #[allow(private_bounds)]
 pub trait SigningDriver: PrivateDriver {
@@ -515,6 +891,7 @@ pub trait SigningDriver: PrivateDriver {
             &GetMetadataOpts::builder()
                 .image(opts.image)
                 .platform(opts.platform)
+                .fresh(true)
                 .build(),
         )?
         .digest;
@@ -526,7 +903,9 @@ pub trait SigningDriver: PrivateDriver {
         .parse()
         .into_diagnostic()?;
 
-        let (sign_opts, verify_opts) = match (Driver::get_ci_driver(), get_private_key(&path)) {
+        let keyless = Driver::signs_keylessly();
+
+        let (sign_opts, verify_opts) = match (keyless, get_private_key(&path)) {
             // Cosign public/private key pair
             (_, Ok(priv_key)) => (
                 SignOpts::builder()
@@ -539,8 +918,8 @@ pub trait SigningDriver: PrivateDriver {
                     .verify_type(VerifyType::File(path.join(COSIGN_PUB_PATH).into()))
                     .build(),
             ),
-            // Gitlab keyless
-            (CiDriverType::Github | CiDriverType::Gitlab, _) => (
+            // Github and Gitlab keyless
+            (true, _) => (
                 SignOpts::builder().dir(&path).image(&image_digest).build(),
                 VerifyOpts::builder()
                     .image(opts.image)
@@ -590,6 +969,13 @@ pub trait CiDriver: PrivateDriver {
     /// Will error if the environment variables aren't set.
     fn oidc_provider() -> Result<String>;
 
+    /// Whether images are signed keylessly with the identity
+    /// of the CI job when there isn't a private key.
+    #[must_use]
+    fn signs_keylessly() -> bool {
+        false
+    }
+
     /// Generate a list of tags based on the OS version.
     ///
     /// ## CI
@@ -672,5 +1058,22 @@ pub trait CiDriver: PrivateDriver {
     /// Will error if the environment variables aren't set.
     fn get_registry() -> Result<String>;
 
+    /// Get the full sha of the commit being built,
+    /// if it can be found.
+    fn get_commit_sha() -> Option<String>;
+
+    /// Get the name of the branch being built,
+    /// if it can be found.
+    fn get_branch() -> Option<String>;
+
     fn default_ci_file_path() -> PathBuf;
+
+    /// Reports the summary of a build to the CI system,
+    /// like the step summary of a GitHub workflow.
+    ///
+    /// # Errors
+    /// Will error if the summary can't be written.
+    fn report_summary(_summary: &BuildSummary) -> Result<()> {
+        Ok(())
+    }
 }
@@ -1,45 +1,253 @@
-use std::{collections::HashMap, env};
+use std::{
+    collections::{BTreeMap, HashMap},
+    env,
+    ffi::OsStr,
+    marker::PhantomData,
+    sync::LazyLock,
+};
 
 use blue_build_utils::constants::{GITHUB_ACTIONS, GITLAB_CI, IMAGE_VERSION_LABEL};
-use clap::ValueEnum;
+use clap::{
+    builder::{EnumValueParser, PossibleValue, TypedValueParser},
+    error::ErrorKind,
+    Arg, Command, ValueEnum,
+};
 use log::trace;
 use serde::Deserialize;
 use serde_json::Value;
 
 use crate::drivers::{
-    buildah_driver::BuildahDriver, docker_driver::DockerDriver, podman_driver::PodmanDriver,
-    DriverVersion,
+    buildah_driver::BuildahDriver,
+    containerized,
+    docker_driver::DockerDriver,
+    external_driver::{ExternalDriver, ExternalDriverKind, EXTERNAL_PREFIX},
+    podman_driver::PodmanDriver,
+    Driver, DriverVersion,
 };
 
 pub(super) trait DetermineDriver<T> {
     fn determine_driver(&mut self) -> T;
 }
 
-#[derive(Debug, Clone, Copy, ValueEnum)]
+/// The driver types that can select an external driver.
+pub(super) trait ExternalDriverType:
+    ValueEnum + Clone + Copy + Send + Sync + 'static
+{
+    const KIND: ExternalDriverKind;
+    const EXTERNAL: Self;
+}
+
+/// A driver that is selected with a flag, which is either one of
+/// the drivers of `T` or the external driver `external:<name>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DriverSelector<T> {
+    Driver(T),
+    External(String),
+}
+
+impl<T> From<T> for DriverSelector<T> {
+    fn from(driver: T) -> Self {
+        Self::Driver(driver)
+    }
+}
+
+impl<T: ValueEnum> DriverSelector<T> {
+    /// The value that selects the driver, like
+    /// `podman` or `external:<name>`.
+    #[must_use]
+    pub fn selector(&self) -> Option<String> {
+        match self {
+            Self::Driver(driver) => driver
+                .to_possible_value()
+                .map(|value| value.get_name().to_owned()),
+            Self::External(name) => Some(format!("{EXTERNAL_PREFIX}{name}")),
+        }
+    }
+}
+
+#[allow(private_bounds)]
+impl<T: ExternalDriverType> DriverSelector<T> {
+    /// Selects the external driver if it is one
+    /// and returns the type of the driver.
+    pub(super) fn select(self) -> T {
+        match self {
+            Self::Driver(driver) => driver,
+            Self::External(name) => {
+                ExternalDriver::select(T::KIND, name);
+                T::EXTERNAL
+            }
+        }
+    }
+}
+
+/// Parses the drivers of `T` and `external:<name>`,
+/// which selects the external driver `name`.
+#[derive(Debug, Clone, Copy)]
+pub(super) struct DriverValueParser<T>(PhantomData<T>);
+
+impl<T> DriverValueParser<T> {
+    pub(super) const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: ExternalDriverType> TypedValueParser for DriverValueParser<T> {
+    type Value = DriverSelector<T>;
+
+    fn parse_ref(
+        &self,
+        cmd: &Command,
+        arg: Option<&Arg>,
+        value: &OsStr,
+    ) -> Result<Self::Value, clap::Error> {
+        let Some(name) = value
+            .to_str()
+            .and_then(|value| value.strip_prefix(EXTERNAL_PREFIX))
+        else {
+            return EnumValueParser::<T>::new()
+                .parse_ref(cmd, arg, value)
+                .map(DriverSelector::Driver);
+        };
+
+        if name.is_empty() {
+            return Err(clap::Error::raw(
+                ErrorKind::InvalidValue,
+                format!("The name of the driver is missing from `{EXTERNAL_PREFIX}<name>`\n"),
+            )
+            .with_cmd(cmd));
+        }
+        Ok(DriverSelector::External(name.to_owned()))
+    }
+
+    fn possible_values(&self) -> Option<Box<dyn Iterator<Item = PossibleValue> + '_>> {
+        Some(Box::new(
+            T::value_variants()
+                .iter()
+                .filter_map(ValueEnum::to_possible_value),
+        ))
+    }
+}
+
+macro_rules! impl_external_driver_type {
+    ($($type:ty => $kind:ident),* $(,)?) => {
+        $(
+            impl ExternalDriverType for $type {
+                const KIND: ExternalDriverKind = ExternalDriverKind::$kind;
+                const EXTERNAL: Self = Self::External;
+            }
+
+            impl DriverSelector<$type> {
+                /// The type of the selected driver.
+                #[must_use]
+                pub const fn driver_type(&self) -> $type {
+                    match self {
+                        Self::Driver(driver) => *driver,
+                        Self::External(_) => <$type>::External,
+                    }
+                }
+
+            }
+        )*
+    };
+}
+
+impl_external_driver_type! {
+    BuildDriverType => Build,
+    InspectDriverType => Inspect,
+    SigningDriverType => Signing,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum, PartialEq, Eq, Hash)]
 pub enum InspectDriverType {
+    OciClient,
     Skopeo,
     Podman,
     Docker,
+
+    /// Selected with `--inspect-driver external:<name>`
+    /// or `BB_INSPECT_DRIVER=external:<name>`.
+    #[value(skip)]
+    External,
+
+    /// Selected with [`MockDriver::args`](super::MockDriver::args).
+    #[cfg(feature = "test")]
+    #[value(skip)]
+    Mock,
+}
+
+impl InspectDriverType {
+    /// Finds the first inspect driver that can be used.
+    #[must_use]
+    pub fn detect() -> Option<Self> {
+        Self::available().first().copied()
+    }
+
+    /// The inspect drivers that can be used, in the order
+    /// they're tried when inspecting an image fails.
+    ///
+    /// The OCI client is always available since it's built in.
+    /// The others need their command to be installed.
+    #[must_use]
+    pub fn available() -> &'static [Self] {
+        static AVAILABLE: LazyLock<Vec<InspectDriverType>> = LazyLock::new(|| {
+            let available = [
+                InspectDriverType::OciClient,
+                InspectDriverType::Skopeo,
+                InspectDriverType::Docker,
+                InspectDriverType::Podman,
+            ]
+            .into_iter()
+            .filter(|driver| {
+                driver
+                    .command()
+                    .is_none_or(|command| blue_build_utils::check_command_exists(command).is_ok())
+            })
+            .collect();
+            trace!("Available inspect drivers: {available:?}");
+            available
+        });
+
+        &AVAILABLE
+    }
+
+    /// The command the driver needs.
+    const fn command(self) -> Option<&'static str> {
+        match self {
+            Self::OciClient | Self::External => None,
+            #[cfg(feature = "test")]
+            Self::Mock => None,
+            Self::Skopeo => Some("skopeo"),
+            Self::Podman => Some("podman"),
+            Self::Docker => Some("docker"),
+        }
+    }
+}
+
+impl std::fmt::Display for InspectDriverType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match *self {
+            Self::OciClient => "oci-client",
+            Self::Skopeo => "skopeo",
+            Self::Podman => "podman",
+            Self::Docker => "docker",
+            Self::External => "external",
+            #[cfg(feature = "test")]
+            Self::Mock => "mock",
+        })
+    }
 }
 
 impl DetermineDriver<InspectDriverType> for Option<InspectDriverType> {
     fn determine_driver(&mut self) -> InspectDriverType {
-        *self.get_or_insert(
-            match (
-                blue_build_utils::check_command_exists("skopeo"),
-                blue_build_utils::check_command_exists("docker"),
-                blue_build_utils::check_command_exists("podman"),
-            ) {
-                (Ok(_skopeo), _, _) => InspectDriverType::Skopeo,
-                (_, Ok(_docker), _) => InspectDriverType::Docker,
-                (_, _, Ok(_podman)) => InspectDriverType::Podman,
-                _ => panic!(
+        *self.get_or_insert_with(|| {
+            InspectDriverType::detect().unwrap_or_else(|| {
+                panic!(
                     "{}{}",
                     "Could not determine inspection strategy. ",
                     "You need either skopeo, docker, or podman",
-                ),
-            },
-        )
+                )
+            })
+        })
     }
 }
 
@@ -48,26 +256,55 @@ pub enum BuildDriverType {
     Buildah,
     Podman,
     Docker,
+
+    /// Selected with `--build-driver external:<name>`
+    /// or `BB_BUILD_DRIVER=external:<name>`.
+    #[value(skip)]
+    External,
+
+    /// Selected with [`MockDriver::args`](super::MockDriver::args).
+    #[cfg(feature = "test")]
+    #[value(skip)]
+    Mock,
+}
+
+impl BuildDriverType {
+    /// Finds the first build driver that is installed
+    /// with a supported version.
+    ///
+    /// In a container, docker is only used if its daemon can be
+    /// reached, and buildah is preferred over podman since it
+    /// needs the fewest privileges.
+    #[must_use]
+    pub fn detect() -> Option<Self> {
+        let containerized = Driver::is_containerized();
+
+        match (
+            blue_build_utils::check_command_exists("docker"),
+            blue_build_utils::check_command_exists("podman"),
+            blue_build_utils::check_command_exists("buildah"),
+        ) {
+            (Ok(_docker), _, _)
+                if (!containerized || containerized::docker_reachable())
+                    && DockerDriver::is_supported_version() =>
+            {
+                Some(Self::Docker)
+            }
+            (_, _, Ok(_buildah)) if containerized && BuildahDriver::is_supported_version() => {
+                Some(Self::Buildah)
+            }
+            (_, Ok(_podman), _) if PodmanDriver::is_supported_version() => Some(Self::Podman),
+            (_, _, Ok(_buildah)) if BuildahDriver::is_supported_version() => Some(Self::Buildah),
+            _ => None,
+        }
+    }
 }
 
 impl DetermineDriver<BuildDriverType> for Option<BuildDriverType> {
     fn determine_driver(&mut self) -> BuildDriverType {
-        *self.get_or_insert(
-            match (
-                blue_build_utils::check_command_exists("docker"),
-                blue_build_utils::check_command_exists("podman"),
-                blue_build_utils::check_command_exists("buildah"),
-            ) {
-                (Ok(_docker), _, _) if DockerDriver::is_supported_version() => {
-                    BuildDriverType::Docker
-                }
-                (_, Ok(_podman), _) if PodmanDriver::is_supported_version() => {
-                    BuildDriverType::Podman
-                }
-                (_, _, Ok(_buildah)) if BuildahDriver::is_supported_version() => {
-                    BuildDriverType::Buildah
-                }
-                _ => panic!(
+        *self.get_or_insert_with(|| {
+            BuildDriverType::detect().unwrap_or_else(|| {
+                panic!(
                     "{}{}{}{}",
                     "Could not determine strategy, ",
                     format_args!("need either docker version {}, ", DockerDriver::VERSION_REQ,),
@@ -76,9 +313,9 @@ impl DetermineDriver<BuildDriverType> for Option<BuildDriverType> {
                         "or buildah version {} to continue",
                         BuildahDriver::VERSION_REQ,
                     ),
-                ),
-            },
-        )
+                )
+            })
+        })
     }
 }
 
@@ -87,6 +324,33 @@ pub enum SigningDriverType {
     Cosign,
     #[cfg(feature = "sigstore")]
     Sigstore,
+
+    /// Selected with `--signing-driver external:<name>`
+    /// or `BB_SIGNING_DRIVER=external:<name>`.
+    #[value(skip)]
+    External,
+
+    /// Selected with [`MockDriver::args`](super::MockDriver::args).
+    #[cfg(feature = "test")]
+    #[value(skip)]
+    Mock,
+}
+
+impl SigningDriverType {
+    /// Prefers the native sigstore driver when it's built in
+    /// so that signing doesn't need the `cosign` binary.
+    #[must_use]
+    pub const fn detect() -> Self {
+        #[cfg(feature = "sigstore")]
+        {
+            Self::Sigstore
+        }
+
+        #[cfg(not(feature = "sigstore"))]
+        {
+            Self::Cosign
+        }
+    }
 }
 
 impl DetermineDriver<SigningDriverType> for Option<SigningDriverType> {
@@ -95,15 +359,17 @@ impl DetermineDriver<SigningDriverType> for Option<SigningDriverType> {
 
         #[cfg(feature = "sigstore")]
         {
-            *self.get_or_insert(
-                blue_build_utils::check_command_exists("cosign")
-                    .map_or(SigningDriverType::Sigstore, |()| SigningDriverType::Cosign),
-            )
+            *self.get_or_insert_with(SigningDriverType::detect)
         }
 
         #[cfg(not(feature = "sigstore"))]
         {
-            SigningDriverType::Cosign
+            match self {
+                Some(SigningDriverType::External) => SigningDriverType::External,
+                #[cfg(feature = "test")]
+                Some(SigningDriverType::Mock) => SigningDriverType::Mock,
+                _ => SigningDriverType::detect(),
+            }
         }
     }
 }
@@ -112,6 +378,11 @@ impl DetermineDriver<SigningDriverType> for Option<SigningDriverType> {
 pub enum RunDriverType {
     Podman,
     Docker,
+
+    /// Selected with [`MockDriver::args`](super::MockDriver::args).
+    #[cfg(feature = "test")]
+    #[value(skip)]
+    Mock,
 }
 
 impl From<RunDriverType> for String {
@@ -119,6 +390,24 @@ impl From<RunDriverType> for String {
         match value {
             RunDriverType::Podman => "podman".to_string(),
             RunDriverType::Docker => "docker".to_string(),
+            #[cfg(feature = "test")]
+            RunDriverType::Mock => "mock".to_string(),
+        }
+    }
+}
+
+impl RunDriverType {
+    /// Finds the first run driver that is installed
+    /// with a supported version.
+    #[must_use]
+    pub fn detect() -> Option<Self> {
+        match (
+            blue_build_utils::check_command_exists("docker"),
+            blue_build_utils::check_command_exists("podman"),
+        ) {
+            (Ok(_docker), _) if DockerDriver::is_supported_version() => Some(Self::Docker),
+            (_, Ok(_podman)) if PodmanDriver::is_supported_version() => Some(Self::Podman),
+            _ => None,
         }
     }
 }
@@ -127,14 +416,9 @@ impl DetermineDriver<RunDriverType> for Option<RunDriverType> {
     fn determine_driver(&mut self) -> RunDriverType {
         trace!("RunDriver::determine_driver()");
 
-        *self.get_or_insert(
-            match (
-                blue_build_utils::check_command_exists("docker"),
-                blue_build_utils::check_command_exists("podman"),
-            ) {
-                (Ok(_docker), _) if DockerDriver::is_supported_version() => RunDriverType::Docker,
-                (_, Ok(_podman)) if PodmanDriver::is_supported_version() => RunDriverType::Podman,
-                _ => panic!(
+        *self.get_or_insert_with(|| {
+            RunDriverType::detect().unwrap_or_else(|| {
+                panic!(
                     "{}{}{}{}",
                     "Could not determine strategy, ",
                     format_args!("need either docker version {}, ", DockerDriver::VERSION_REQ),
@@ -143,9 +427,9 @@ impl DetermineDriver<RunDriverType> for Option<RunDriverType> {
                         "or buildah version {} to continue",
                         BuildahDriver::VERSION_REQ
                     ),
-                ),
-            },
-        )
+                )
+            })
+        })
     }
 }
 
@@ -154,19 +438,30 @@ pub enum CiDriverType {
     Local,
     Gitlab,
     Github,
+
+    /// Selected with [`MockDriver::args`](super::MockDriver::args).
+    #[cfg(feature = "test")]
+    #[value(skip)]
+    Mock,
+}
+
+impl CiDriverType {
+    /// Determines the CI platform from the environment.
+    #[must_use]
+    pub fn detect() -> Self {
+        match (env::var(GITLAB_CI).ok(), env::var(GITHUB_ACTIONS).ok()) {
+            (Some(_gitlab_ci), None) => Self::Gitlab,
+            (None, Some(_github_actions)) => Self::Github,
+            _ => Self::Local,
+        }
+    }
 }
 
 impl DetermineDriver<CiDriverType> for Option<CiDriverType> {
     fn determine_driver(&mut self) -> CiDriverType {
         trace!("CiDriverType::determine_driver()");
 
-        *self.get_or_insert(
-            match (env::var(GITLAB_CI).ok(), env::var(GITHUB_ACTIONS).ok()) {
-                (Some(_gitlab_ci), None) => CiDriverType::Gitlab,
-                (None, Some(_github_actions)) => CiDriverType::Github,
-                _ => CiDriverType::Local,
-            },
-        )
+        *self.get_or_insert_with(CiDriverType::detect)
     }
 }
 
@@ -216,33 +511,203 @@ impl std::fmt::Display for Platform {
     }
 }
 
-#[derive(Deserialize, Debug, Clone)]
-#[serde(rename_all = "PascalCase")]
+#[derive(Debug, Clone, Default)]
 pub struct ImageMetadata {
     pub labels: HashMap<String, Value>,
     pub digest: String,
+
+    /// The config that containers of the image run with.
+    pub config: ImageConfig,
+
+    /// The layers of the image, starting with the base layer.
+    pub layers: Vec<ImageLayer>,
+
+    /// How the image was created, starting with the oldest step.
+    pub history: Vec<ImageHistory>,
+
+    /// The size of the image in local storage, which is
+    /// only known when the image is inspected locally.
+    pub size: Option<u64>,
+}
+
+/// The parts of an image's config that
+/// describe how its containers are run.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(rename_all = "PascalCase")]
+pub struct ImageConfig {
+    #[serde(default)]
+    pub entrypoint: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub cmd: Option<Vec<String>>,
+
+    #[serde(default)]
+    pub env: Option<Vec<String>>,
+}
+
+/// A layer of an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageLayer {
+    /// The digest of the layer. This is the digest of the
+    /// compressed layer when the size is known, otherwise
+    /// it's the digest of the uncompressed layer.
+    pub digest: String,
+
+    /// The size of the compressed layer, if the inspect driver knows it.
+    pub size: Option<u64>,
+}
+
+/// A step in the history of an image.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ImageHistory {
+    #[serde(default)]
+    pub created: Option<String>,
+
+    #[serde(default)]
+    pub created_by: Option<String>,
+
+    #[serde(default)]
+    pub comment: Option<String>,
+
+    /// Whether the step didn't create a layer.
+    #[serde(default)]
+    pub empty_layer: bool,
+}
+
+/// An image in the local storage of a build driver.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(from = "InspectedImage")]
+pub struct LocalImage {
+    pub id: String,
+
+    /// The tags of the image, which is empty for dangling images.
+    pub tags: Vec<String>,
+
+    /// The size of the image in bytes, including the
+    /// layers that it shares with other images.
+    pub size: u64,
+
+    pub created: Option<String>,
+
+    /// The ID of the image that it was built on top of,
+    /// if the build driver keeps track of it.
+    pub parent: Option<String>,
+
+    pub labels: BTreeMap<String, String>,
+}
+
+/// The output of `podman image inspect` and `docker image inspect`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedImage {
+    id: String,
+
+    #[serde(default)]
+    repo_tags: Option<Vec<String>>,
+
+    #[serde(default)]
+    size: u64,
+
+    #[serde(default)]
+    created: Option<String>,
+
+    #[serde(default)]
+    parent: Option<String>,
+
+    #[serde(default)]
+    config: Option<InspectedImageConfig>,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(rename_all = "PascalCase")]
+struct InspectedImageConfig {
+    #[serde(default)]
+    labels: Option<BTreeMap<String, String>>,
+}
+
+impl From<InspectedImage> for LocalImage {
+    fn from(image: InspectedImage) -> Self {
+        Self {
+            id: image.id,
+            tags: image.repo_tags.unwrap_or_default(),
+            size: image.size,
+            created: image.created,
+            parent: image.parent.filter(|parent| !parent.is_empty()),
+            labels: image
+                .config
+                .and_then(|config| config.labels)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+/// The parts of an OCI image config that aren't labels.
+#[derive(Deserialize, Debug, Clone, Default)]
+pub(super) struct OciImageConfig {
+    #[serde(default)]
+    pub config: ImageConfig,
+
+    #[serde(default)]
+    pub history: Vec<ImageHistory>,
+}
+
+/// A layer in an image manifest.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub(super) struct ManifestLayer {
+    pub digest: String,
+    pub size: u64,
+}
+
+impl From<ManifestLayer> for ImageLayer {
+    fn from(layer: ManifestLayer) -> Self {
+        Self {
+            digest: layer.digest,
+            size: Some(layer.size),
+        }
+    }
 }
 
 impl ImageMetadata {
+    /// Gets the major OS version from the image's labels.
+    ///
+    /// The version labels are checked first and then the
+    /// kernel release in `ostree.linux` is used, which
+    /// ends in the distro version like `fc41` or `el9`.
     #[must_use]
     pub fn get_version(&self) -> Option<u64> {
-        Some(
-            self.labels
-                .get(IMAGE_VERSION_LABEL)?
-                .as_str()
-                .and_then(|v| lenient_semver::parse(v).ok())?
-                .major,
-        )
+        let label = |name: &str| self.labels.get(name).and_then(Value::as_str);
+
+        [IMAGE_VERSION_LABEL, "redhat.version-id", "version"]
+            .into_iter()
+            .filter_map(label)
+            .find_map(|v| lenient_semver::parse(v).ok())
+            .map(|v| v.major)
+            .or_else(|| label("ostree.linux").and_then(kernel_release_version))
     }
 }
 
+/// Gets the distro version out of a kernel
+/// release like `6.11.3-300.fc41.x86_64`.
+fn kernel_release_version(release: &str) -> Option<u64> {
+    release.split(['.', '-']).find_map(|part| {
+        let version = part
+            .strip_prefix("fc")
+            .or_else(|| part.strip_prefix("el"))?;
+        let end = version
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(version.len());
+        version[..end].parse().ok()
+    })
+}
+
 #[cfg(feature = "rechunk")]
 pub struct ContainerId(pub(super) String);
 
 #[cfg(feature = "rechunk")]
 impl std::fmt::Display for ContainerId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.0)
+        write!(f, "{}", self.0)
     }
 }
 
@@ -259,7 +724,7 @@ pub struct MountId(pub(super) String);
 #[cfg(feature = "rechunk")]
 impl std::fmt::Display for MountId {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.0)
+        write!(f, "{}", self.0)
     }
 }
 
@@ -278,12 +743,13 @@ impl<'a> From<&'a MountId> for std::borrow::Cow<'a, str> {
 }
 
 #[cfg(feature = "rechunk")]
+#[derive(Debug)]
 pub struct OciDir(String);
 
 #[cfg(feature = "rechunk")]
 impl std::fmt::Display for OciDir {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", &self.0)
+        write!(f, "{}", self.0)
     }
 }
 
@@ -306,3 +772,64 @@ impl TryFrom<std::path::PathBuf> for OciDir {
         Ok(Self(format!("oci:{}", value.display())))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use rstest::rstest;
+    use serde_json::Value;
+
+    use super::{ImageMetadata, LocalImage};
+
+    #[rstest]
+    #[case(&[("org.opencontainers.image.version", "41.20241015.0")], Some(41))]
+    #[case(&[("redhat.version-id", "9.4")], Some(9))]
+    #[case(&[("version", "latest"), ("ostree.linux", "6.11.3-300.fc41.x86_64")], Some(41))]
+    #[case(&[("ostree.linux", "5.14.0-427.el9_4.x86_64")], Some(9))]
+    #[case(&[("ostree.linux", "6.11.3-arch1-1")], None)]
+    #[case(&[], None)]
+    fn version_from_labels(#[case] labels: &[(&str, &str)], #[case] expected: Option<u64>) {
+        let metadata = ImageMetadata {
+            labels: labels
+                .iter()
+                .map(|(k, v)| ((*k).to_owned(), Value::from(*v)))
+                .collect::<HashMap<_, _>>(),
+            digest: String::new(),
+            ..Default::default()
+        };
+
+        assert_eq!(metadata.get_version(), expected);
+    }
+
+    #[test]
+    fn local_images_from_inspect() {
+        let images: Vec<LocalImage> = serde_json::from_str(
+            r#"[
+                {
+                    "Id": "sha256:1f2e",
+                    "RepoTags": ["localhost/test:latest"],
+                    "Parent": "",
+                    "Created": "2024-10-15T12:00:00Z",
+                    "Size": 2048,
+                    "Config": { "Labels": { "org.blue-build.build-id": "1234" } }
+                },
+                {
+                    "Id": "3c4d",
+                    "RepoTags": null,
+                    "Parent": "1f2e",
+                    "Size": 1024,
+                    "Config": { "Labels": null }
+                }
+            ]"#,
+        )
+        .unwrap();
+
+        assert_eq!(images[0].tags, ["localhost/test:latest"]);
+        assert_eq!(images[0].parent, None);
+        assert_eq!(images[0].labels["org.blue-build.build-id"], "1234");
+        assert_eq!(images[1].tags, Vec::<String>::new());
+        assert_eq!(images[1].parent.as_deref(), Some("1f2e"));
+        assert!(images[1].labels.is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! On disk cache of the versions of the driver binaries.
+//!
+//! Checking a version means running a command like `docker version`,
+//! which can take a while when the daemon is slow to respond. The
+//! result is kept for a short time and thrown out as soon as the
+//! binary on the `PATH`, or the binary that it's set to, changes.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use blue_build_utils::constants::DRIVER_VERSIONS_CACHE_FILE;
+use log::{debug, trace};
+use miette::{IntoDiagnostic, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+const TTL: Duration = Duration::from_hours(1);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Entry {
+    path: PathBuf,
+    modified: u64,
+    checked: u64,
+    version: Version,
+}
+
+/// Gets the version of `program` from the cache, or by
+/// calling `detect` and caching the result.
+///
+/// The cache is skipped entirely if the binary
+/// can't be found or there is no cache directory.
+///
+/// # Errors
+/// Will error if `detect` is called and fails.
+pub fn cached_version<F>(program: &str, detect: F) -> Result<Version>
+where
+    F: FnOnce() -> Result<Version>,
+{
+    let (Some(dir), Ok(binary)) = (
+        blue_build_utils::cache_dir(),
+        which::which(blue_build_utils::tools::tool_path(program)),
+    ) else {
+        return detect();
+    };
+
+    cached_version_in(
+        &dir.join(DRIVER_VERSIONS_CACHE_FILE),
+        program,
+        &binary,
+        SystemTime::now(),
+        detect,
+    )
+}
+
+fn cached_version_in<F>(
+    cache_file: &Path,
+    program: &str,
+    binary: &Path,
+    now: SystemTime,
+    detect: F,
+) -> Result<Version>
+where
+    F: FnOnce() -> Result<Version>,
+{
+    let Some(modified) = fs::metadata(binary)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .map(unix_secs)
+    else {
+        return detect();
+    };
+    let now = unix_secs(now);
+
+    let mut entries = read_entries(cache_file);
+    if let Some(entry) = entries.get(program).filter(|entry| {
+        entry.path == binary
+            && entry.modified == modified
+            && now.saturating_sub(entry.checked) < TTL.as_secs()
+    }) {
+        debug!("Using cached {program} version {}", entry.version);
+        return Ok(entry.version.clone());
+    }
+
+    let version = detect()?;
+    entries.insert(
+        program.to_owned(),
+        Entry {
+            path: binary.to_path_buf(),
+            modified,
+            checked: now,
+            version: version.clone(),
+        },
+    );
+
+    if let Err(e) = write_entries(cache_file, &entries) {
+        debug!("Failed to write driver version cache: {e}");
+    }
+
+    Ok(version)
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn read_entries(cache_file: &Path) -> HashMap<String, Entry> {
+    trace!("version_cache::read_entries({})", cache_file.display());
+
+    fs::read(cache_file)
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+fn write_entries(cache_file: &Path, entries: &HashMap<String, Entry>) -> Result<()> {
+    trace!("version_cache::write_entries({})", cache_file.display());
+
+    let dir = cache_file.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(dir).into_diagnostic()?;
+
+    // Write to a temporary file first so that another
+    // invocation never reads a partially written cache
+    let tmp = dir.join(format!(".{DRIVER_VERSIONS_CACHE_FILE}.{}", Uuid::new_v4()));
+    fs::write(&tmp, serde_json::to_vec(entries).into_diagnostic()?).into_diagnostic()?;
+    fs::rename(&tmp, cache_file).into_diagnostic()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        cell::Cell,
+        fs::{self, File},
+        time::{Duration, SystemTime},
+    };
+
+    use semver::Version;
+    use tempfile::TempDir;
+
+    use super::{cached_version_in, TTL};
+
+    #[test]
+    fn cache_until_expired_or_changed() {
+        let dir = TempDir::new().unwrap();
+        let cache_file = dir.path().join("cache/driver-versions.json");
+        let binary = dir.path().join("docker");
+        fs::write(&binary, "").unwrap();
+
+        let calls = Cell::new(0);
+        let detect = || {
+            calls.set(calls.get() + 1);
+            Ok(Version::new(27, calls.get(), 0))
+        };
+        let now = SystemTime::now();
+
+        assert_eq!(
+            cached_version_in(&cache_file, "docker", &binary, now, detect).unwrap(),
+            Version::new(27, 1, 0)
+        );
+        assert_eq!(
+            cached_version_in(&cache_file, "docker", &binary, now, detect).unwrap(),
+            Version::new(27, 1, 0)
+        );
+        assert_eq!(calls.get(), 1);
+
+        // Expired entries are detected again
+        let later = now + TTL;
+        assert_eq!(
+            cached_version_in(&cache_file, "docker", &binary, later, detect).unwrap(),
+            Version::new(27, 2, 0)
+        );
+        assert_eq!(calls.get(), 2);
+
+        // A changed binary is detected again
+        File::options()
+            .write(true)
+            .open(&binary)
+            .unwrap()
+            .set_modified(now - Duration::from_secs(60))
+            .unwrap();
+        assert_eq!(
+            cached_version_in(&cache_file, "docker", &binary, later, detect).unwrap(),
+            Version::new(27, 3, 0)
+        );
+        assert_eq!(calls.get(), 3);
+
+        // Other programs have their own entries
+        assert_eq!(
+            cached_version_in(&cache_file, "podman", &binary, later, detect).unwrap(),
+            Version::new(27, 4, 0)
+        );
+        assert_eq!(
+            cached_version_in(&cache_file, "docker", &binary, later, detect).unwrap(),
+            Version::new(27, 3, 0)
+        );
+    }
+}
@@ -0,0 +1,381 @@
+//! Tracks the temporary files and directories that a run
+//! generates, so that the ones left behind by a run that
+//! crashed can be removed by a later run.
+//!
+//! Each run keeps a manifest of what it generated in
+//! `~/.cache/bluebuild/generated/`. The manifest records the boot
+//! and the PID namespace of the run, since the cache can be shared
+//! with other hosts or containers whose processes can't be seen.
+//! A manifest is only stale once its process is gone from the same
+//! boot and PID namespace, manifests of other hosts are left alone.
+//!
+//! Only paths with a name that starts with `.tmp`, which are the
+//! temp dirs and staging dirs of bluebuild, and the Containerfiles
+//! in them are removed, so a corrupted manifest can't remove
+//! anything else.
+
+use std::{
+    collections::BTreeSet,
+    fs,
+    io::ErrorKind,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    process,
+    sync::{LazyLock, Mutex},
+};
+
+use blue_build_utils::constants::CONTAINER_FILE;
+use log::{debug, trace, warn};
+use miette::{Context, IntoDiagnostic, Result};
+use nix::{errno::Errno, sys::signal::kill, unistd::Pid};
+use serde::{Deserialize, Serialize};
+
+const MANIFEST_DIR: &str = "generated";
+const GENERATED_PREFIX: &str = ".tmp";
+
+static GENERATED: LazyLock<Mutex<BTreeSet<PathBuf>>> =
+    LazyLock::new(|| Mutex::new(BTreeSet::new()));
+
+/// The boot and PID namespace of this run.
+static ORIGIN: LazyLock<Origin> = LazyLock::new(Origin::current);
+
+/// Where a run ran, which decides whether
+/// its process can be checked from here.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Origin {
+    /// The ID of the boot of the host, which
+    /// differs between hosts and reboots.
+    boot_id: String,
+
+    /// The inode of the PID namespace of the run.
+    pid_ns: u64,
+}
+
+impl Origin {
+    fn current() -> Self {
+        Self {
+            boot_id: fs::read_to_string("/proc/sys/kernel/random/boot_id")
+                .map(|id| id.trim().to_owned())
+                .unwrap_or_default(),
+            pid_ns: fs::metadata("/proc/self/ns/pid").map_or(0, |ns| ns.ino()),
+        }
+    }
+
+    /// Whether the processes of this origin can be checked,
+    /// which needs both the boot and the namespace.
+    const fn is_known(&self) -> bool {
+        !self.boot_id.is_empty() && self.pid_ns != 0
+    }
+}
+
+/// What a run generated.
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    #[serde(flatten)]
+    origin: Origin,
+    pid: u32,
+    paths: BTreeSet<PathBuf>,
+}
+
+impl Manifest {
+    /// A manifest is stale once its process is gone, which can only
+    /// be told for a run of the same boot and PID namespace.
+    fn is_stale(&self, origin: &Origin) -> bool {
+        origin.is_known() && self.origin == *origin && !is_running(self.pid)
+    }
+}
+
+fn manifest_dir() -> Option<PathBuf> {
+    blue_build_utils::cache_dir().map(|dir| dir.join(MANIFEST_DIR))
+}
+
+/// Adds a path to the manifest of this run.
+///
+/// Tracking is best effort, a manifest that can't
+/// be written only means that nothing is cleaned up.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn track(path: &Path) {
+    let Ok(path) = std::path::absolute(path) else {
+        return;
+    };
+    let mut generated = GENERATED.lock().expect("Should lock GENERATED");
+
+    if generated.insert(path) {
+        write_manifest(&generated);
+    }
+}
+
+/// Removes a path, and the paths inside of it, from the
+/// manifest of this run once it has been cleaned up.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn untrack(path: &Path) {
+    let Ok(path) = std::path::absolute(path) else {
+        return;
+    };
+    let mut generated = GENERATED.lock().expect("Should lock GENERATED");
+
+    let len = generated.len();
+    generated.retain(|tracked| !tracked.starts_with(&path));
+    if generated.len() != len {
+        write_manifest(&generated);
+    }
+}
+
+/// The manifest of a run, which is named after its
+/// origin so that runs of other hosts don't collide.
+fn manifest_path(dir: &Path, origin: &Origin, pid: u32) -> PathBuf {
+    let boot_id = if origin.boot_id.is_empty() {
+        "unknown"
+    } else {
+        &origin.boot_id
+    };
+    dir.join(format!("{boot_id}-{}-{pid}.json", origin.pid_ns))
+}
+
+fn write_manifest(generated: &BTreeSet<PathBuf>) {
+    let Some(dir) = manifest_dir() else {
+        return;
+    };
+    let manifest = manifest_path(&dir, &ORIGIN, process::id());
+
+    let result = if generated.is_empty() {
+        match fs::remove_file(&manifest) {
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    } else {
+        // Write to a temporary file first so that a crash
+        // never leaves a partially written manifest
+        let tmp = dir.join(format!(".{}.json", process::id()));
+        let contents = serde_json::to_vec(&Manifest {
+            origin: ORIGIN.clone(),
+            pid: process::id(),
+            paths: generated.clone(),
+        })
+        .unwrap_or_default();
+        fs::create_dir_all(&dir)
+            .and_then(|()| fs::write(&tmp, contents))
+            .and_then(|()| fs::rename(&tmp, &manifest))
+    };
+
+    if let Err(e) = result {
+        debug!("Failed to write {}: {e}", manifest.display());
+    }
+}
+
+/// Removes what the runs that are no longer running left behind.
+///
+/// Returns the paths that were removed, or that would
+/// be removed if `dry_run` is true.
+///
+/// # Errors
+/// Will error if the manifests can't be read.
+pub fn clean_stale(dry_run: bool) -> Result<Vec<PathBuf>> {
+    manifest_dir().map_or_else(
+        || Ok(Vec::new()),
+        |dir| clean_stale_in(&dir, &ORIGIN, dry_run),
+    )
+}
+
+fn clean_stale_in(dir: &Path, origin: &Origin, dry_run: bool) -> Result<Vec<PathBuf>> {
+    trace!("generated::clean_stale_in({}, {dry_run})", dir.display());
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            return Err(e)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to read {}", dir.display()))
+        }
+    };
+
+    let mut removed = Vec::new();
+    for entry in entries {
+        let manifest = entry.into_diagnostic()?.path();
+        // Manifests that are being written start with a `.`
+        if manifest
+            .file_name()
+            .is_none_or(|name| name.as_encoded_bytes().starts_with(b"."))
+        {
+            continue;
+        }
+
+        let Some(contents) = fs::read(&manifest)
+            .ok()
+            .and_then(|contents| serde_json::from_slice::<Manifest>(&contents).ok())
+        else {
+            debug!("Skipping the unreadable manifest {}", manifest.display());
+            continue;
+        };
+        if !contents.is_stale(origin) {
+            continue;
+        }
+
+        for path in contents.paths {
+            if !is_generated(&path) {
+                warn!(
+                    "Not removing {}, bluebuild didn't generate it",
+                    path.display()
+                );
+                continue;
+            }
+
+            let result = match fs::symlink_metadata(&path) {
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Ok(_) if dry_run => Ok(()),
+                Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(&path),
+                Ok(_) => fs::remove_file(&path),
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(()) => removed.push(path),
+                Err(e) => warn!("Failed to remove {}: {e}", path.display()),
+            }
+        }
+
+        if !dry_run {
+            let _ = fs::remove_file(&manifest);
+        }
+    }
+    Ok(removed)
+}
+
+/// A process that can't be signaled because it belongs
+/// to another user is still running.
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = i32::try_from(pid) else {
+        return false;
+    };
+    pid == process::id().cast_signed() || kill(Pid::from_raw(pid), None) != Err(Errno::ESRCH)
+}
+
+/// Whether the path is a temp dir or staging dir that
+/// bluebuild generated, or a Containerfile in one.
+fn is_generated(path: &Path) -> bool {
+    let has_prefix = |path: &Path, prefix: &str| {
+        path.file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with(prefix))
+    };
+    path.is_absolute()
+        && (has_prefix(path, GENERATED_PREFIX)
+            || (has_prefix(path, CONTAINER_FILE)
+                && path
+                    .parent()
+                    .is_some_and(|parent| has_prefix(parent, GENERATED_PREFIX))))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeSet, fs, path::PathBuf, process};
+
+    use tempfile::TempDir;
+
+    use super::{clean_stale_in, manifest_path, Manifest, Origin};
+
+    fn origin(boot_id: &str) -> Origin {
+        Origin {
+            boot_id: boot_id.into(),
+            pid_ns: 4_026_531_836,
+        }
+    }
+
+    fn write(dir: &std::path::Path, origin: &Origin, pid: u32, paths: &[&PathBuf]) -> PathBuf {
+        let manifest = manifest_path(dir, origin, pid);
+        fs::write(
+            &manifest,
+            serde_json::to_vec(&Manifest {
+                origin: origin.clone(),
+                pid,
+                paths: paths
+                    .iter()
+                    .map(|path| (*path).clone())
+                    .collect::<BTreeSet<_>>(),
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        manifest
+    }
+
+    #[test]
+    fn cleans_stale_manifests() {
+        let dir = TempDir::new().unwrap();
+        let manifests = dir.path().join("generated");
+        fs::create_dir(&manifests).unwrap();
+
+        let leftover = dir.path().join(".tmpAbC123");
+        let not_generated = dir.path().join("recipes");
+        let running = dir.path().join(".tmp-running");
+        let other_host = dir.path().join(".tmp-other-host");
+        for path in [&leftover, &not_generated, &running, &other_host] {
+            fs::create_dir(path).unwrap();
+            fs::write(path.join("Containerfile"), "FROM scratch").unwrap();
+        }
+        let containerfile = leftover.join("Containerfile.AbCdEfGhIjK");
+        fs::write(&containerfile, "FROM scratch").unwrap();
+
+        // Pids are never this high, so the process isn't running
+        let here = origin("boot");
+        let stale = write(
+            &manifests,
+            &here,
+            i32::MAX.cast_unsigned(),
+            &[
+                &containerfile,
+                &leftover,
+                &not_generated,
+                &dir.path().join(".tmp-gone"),
+            ],
+        );
+        write(&manifests, &here, process::id(), &[&running]);
+        let elsewhere = write(
+            &manifests,
+            &origin("other-boot"),
+            i32::MAX.cast_unsigned(),
+            &[&other_host],
+        );
+        fs::write(manifests.join("1234.json"), "[]").unwrap();
+
+        assert_eq!(
+            clean_stale_in(&manifests, &here, true).unwrap(),
+            [leftover.clone(), containerfile.clone()]
+        );
+        assert!(leftover.exists());
+        assert!(stale.exists());
+
+        // The Containerfile is removed with its dir
+        assert_eq!(
+            clean_stale_in(&manifests, &here, false).unwrap(),
+            std::slice::from_ref(&leftover)
+        );
+        assert!(!containerfile.exists());
+        assert!(!leftover.exists());
+        assert!(!stale.exists());
+        assert!(not_generated.exists());
+        assert!(running.exists());
+        assert!(other_host.exists());
+        assert!(elsewhere.exists());
+
+        // Nothing is removed when the origin of this run isn't known
+        let unknown = Origin {
+            boot_id: String::new(),
+            pid_ns: 0,
+        };
+        write(
+            &manifests,
+            &unknown,
+            i32::MAX.cast_unsigned(),
+            &[&other_host],
+        );
+        assert_eq!(
+            clean_stale_in(&manifests, &unknown, false).unwrap(),
+            [] as [PathBuf; 0]
+        );
+    }
+}
@@ -5,13 +5,14 @@ use std::{
     io::{BufRead, BufReader, Result, Write as IoWrite},
     path::{Path, PathBuf},
     process::{Command, ExitStatus, Stdio},
-    sync::Mutex,
+    sync::{LazyLock, Mutex},
     thread,
     time::Duration,
 };
 
 use bon::Builder;
 use chrono::Local;
+use clap::ValueEnum;
 use colored::{control::ShouldColorize, ColoredString, Colorize};
 use indicatif::{MultiProgress, ProgressBar};
 use indicatif_log_bridge::LogWrapper;
@@ -27,15 +28,20 @@ use log4rs::{
         },
     },
     config::{Appender, Root},
-    encode::{pattern::PatternEncoder, Encode, Write},
+    encode::{json::JsonEncoder, pattern::PatternEncoder, Encode, Write},
     Config, Logger as L4RSLogger,
 };
 use nu_ansi_term::Color;
-use once_cell::sync::Lazy;
 use private::Private;
 use rand::Rng;
 
-use crate::signal_handler::{add_pid, remove_pid};
+use crate::{
+    logging::boot_progress::BootProgress,
+    metrics,
+    signal_handler::{add_pid, remove_pid},
+};
+
+mod boot_progress;
 
 mod private {
     pub trait Private {}
@@ -43,20 +49,33 @@ mod private {
 
 impl Private for Command {}
 
-static MULTI_PROGRESS: Lazy<MultiProgress> = Lazy::new(MultiProgress::new);
-static LOG_DIR: Lazy<Mutex<PathBuf>> = Lazy::new(|| Mutex::new(PathBuf::new()));
+static MULTI_PROGRESS: LazyLock<MultiProgress> = LazyLock::new(MultiProgress::new);
+static LOG_DIR: LazyLock<Mutex<PathBuf>> = LazyLock::new(|| Mutex::new(PathBuf::new()));
+
+/// The format of the logs printed to the terminal.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    /// Colored logs meant to be read by a person.
+    #[default]
+    Pretty,
+
+    /// One JSON object per line.
+    Json,
+}
 
 #[derive(Debug, Clone)]
 pub struct Logger {
     modules: Vec<(String, LevelFilter)>,
     level: LevelFilter,
     log_dir: Option<PathBuf>,
+    format: LogFormat,
 }
 
 impl Logger {
     const TRIGGER_FILE_SIZE: u64 = 10 * 1024;
     const ARCHIVE_FILENAME_PATTERN: &'static str = "bluebuild.{}.log";
-    const LOG_FILENAME: &'static str = "bluebuild.log";
+    /// The name of the log file of the current run.
+    pub const LOG_FILENAME: &'static str = "bluebuild.log";
     const LOG_FILE_COUNT: u32 = 4;
 
     #[must_use]
@@ -76,11 +95,16 @@ impl Logger {
         self
     }
 
-    pub fn filter_level(&mut self, filter_level: LevelFilter) -> &mut Self {
+    pub const fn filter_level(&mut self, filter_level: LevelFilter) -> &mut Self {
         self.level = filter_level;
         self
     }
 
+    pub const fn log_format(&mut self, format: LogFormat) -> &mut Self {
+        self.format = format;
+        self
+    }
+
     pub fn log_out_dir<P>(&mut self, path: Option<P>) -> &mut Self
     where
         P: AsRef<Path>,
@@ -108,14 +132,19 @@ impl Logger {
         let log_archive_pattern =
             format!("{}/{}", log_dir.display(), Self::ARCHIVE_FILENAME_PATTERN);
 
-        let stderr = ConsoleAppender::builder()
-            .encoder(Box::new(
+        let encoder: Box<dyn Encode> = match self.format {
+            LogFormat::Pretty => Box::new(
                 CustomPatternEncoder::builder()
                     .filter_modules(self.modules.clone())
                     .build(),
-            ))
+            ),
+            LogFormat::Json => Box::new(JsonEncoder::new()),
+        };
+        let stderr = ConsoleAppender::builder()
+            .encoder(encoder)
             .target(log4rs::append::console::Target::Stderr)
-            .tty_only(true)
+            // JSON logs are meant to be collected by other tools
+            .tty_only(self.format == LogFormat::Pretty)
             .build();
 
         let file = RollingFileAppender::builder()
@@ -154,6 +183,15 @@ impl Logger {
     pub fn multi_progress() -> MultiProgress {
         MULTI_PROGRESS.clone()
     }
+
+    /// The directory that logs are written to.
+    ///
+    /// # Panics
+    /// Will panic if the mutex cannot be locked.
+    #[must_use]
+    pub fn log_dir() -> PathBuf {
+        LOG_DIR.lock().expect("Should lock LOG_DIR").clone()
+    }
 }
 
 impl Default for Logger {
@@ -162,6 +200,7 @@ impl Default for Logger {
             modules: vec![],
             level: LevelFilter::Info,
             log_dir: None,
+            format: LogFormat::default(),
         }
     }
 }
@@ -204,6 +243,18 @@ pub trait CommandLogging: Private {
     where
         S: AsRef<str>,
         D: Into<Cow<'static, str>>;
+
+    /// Shows the progress of an `rpm-ostree` or `bootc` command
+    /// that switches or upgrades the system on a progress bar.
+    /// Only the lines that aren't about the progress are printed,
+    /// but every line is logged.
+    ///
+    /// # Errors
+    /// Will error if there was an issue executing the process.
+    fn boot_status<T, U>(self, image_ref: T, message: U) -> Result<ExitStatus>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>;
 }
 
 impl CommandLogging for Command {
@@ -250,6 +301,7 @@ impl CommandLogging for Command {
                 let mp = Logger::multi_progress();
                 reader.lines().for_each(|line| {
                     if let Ok(l) = line {
+                        metrics::record_build_line(&l);
                         let text =
                             format!("{log_prefix} {l}", log_prefix = log_header(&short_name));
                         if mp.is_hidden() {
@@ -336,6 +388,79 @@ impl CommandLogging for Command {
         }
         inner(self, header.as_ref(), message.into())
     }
+
+    fn boot_status<T, U>(self, image_ref: T, message: U) -> Result<ExitStatus>
+    where
+        T: AsRef<str>,
+        U: AsRef<str>,
+    {
+        fn inner(mut command: Command, image_ref: &str, message: &str) -> Result<ExitStatus> {
+            let ansi_color = gen_random_ansi_color();
+            let name = color_str(image_ref, ansi_color);
+            let short_name = color_str(shorten_name(image_ref), ansi_color);
+            let (reader, writer) = os_pipe::pipe()?;
+
+            command
+                .stdout(writer.try_clone()?)
+                .stderr(writer)
+                .stdin(Stdio::piped());
+
+            let progress = Logger::multi_progress()
+                .add(ProgressBar::new_spinner().with_message(format!("{message} {name}")));
+            progress.enable_steady_tick(Duration::from_millis(100));
+
+            let mut child = command.spawn()?;
+
+            let child_pid = child.id();
+            add_pid(child_pid);
+
+            // We drop the `Command` to prevent blocking on writer
+            // https://docs.rs/os_pipe/latest/os_pipe/#examples
+            drop(command);
+
+            let reader = BufReader::new(reader);
+            let log_file_path = {
+                let lock = LOG_DIR.lock().expect("Should lock LOG_DIR");
+                lock.join(format!("{}.log", image_ref.replace(['/', ':', '.'], "_")))
+            };
+            let log_file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(log_file_path.as_path())?;
+
+            let mut boot_progress = BootProgress::new(progress.clone(), message.to_owned());
+            let output = thread::spawn(move || {
+                let mp = Logger::multi_progress();
+                reader.lines().map_while(Result::ok).for_each(|l| {
+                    if !boot_progress.update(&l) {
+                        let text =
+                            format!("{log_prefix} {l}", log_prefix = log_header(&short_name));
+                        if mp.is_hidden() {
+                            eprintln!("{text}");
+                        } else {
+                            mp.println(text).unwrap();
+                        }
+                    }
+                    if let Err(e) = writeln!(&log_file, "{l}") {
+                        warn!(
+                            "Failed to write to log for {}: {e:?}",
+                            log_file_path.display()
+                        );
+                    }
+                });
+            });
+
+            let status = child.wait()?;
+            remove_pid(child_pid);
+            let _ = output.join();
+
+            progress.finish();
+            Logger::multi_progress().remove(&progress);
+
+            Ok(status)
+        }
+        inner(self, image_ref.as_ref(), message.as_ref())
+    }
 }
 
 #[derive(Debug, Builder)]
@@ -438,9 +563,8 @@ where
     // Split the reference by colon to separate the tag or digest
     let mut parts = text.split(':');
 
-    let path = match parts.next() {
-        None => return text.to_string(),
-        Some(path) => path,
+    let Some(path) = parts.next() else {
+        return text.to_string();
     };
     let tag = parts.next();
 
@@ -0,0 +1,174 @@
+//! Reads the progress of `rpm-ostree` and `bootc` out of the
+//! lines they print while switching or upgrading the system.
+
+use std::collections::HashSet;
+
+use indicatif::{ProgressBar, ProgressStyle};
+
+/// The style of the progress bar once the number of layers is known.
+#[allow(clippy::literal_string_with_formatting_args)]
+const LAYERS_TEMPLATE: &str = "{spinner} {msg} [{bar:30}] {pos}/{len} layers";
+
+/// A change in the progress of a switch or upgrade.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) enum BootProgressEvent {
+    /// More layers need to be fetched. `rpm-ostree` prints
+    /// this once for the ostree chunks and once for the
+    /// custom layers of the image.
+    LayersNeeded(u64),
+
+    /// A layer is being fetched.
+    Fetching { layer: String, percent: Option<u8> },
+
+    /// A new step started, like staging the deployment.
+    Step(String),
+}
+
+impl BootProgressEvent {
+    /// Reads the progress out of a line of output.
+    ///
+    /// Returns `None` for lines that aren't about the progress.
+    pub(super) fn parse(line: &str) -> Option<Self> {
+        let line = line.trim();
+        let lower = line.to_lowercase();
+
+        if let Some((_, needed)) = lower.split_once("layers needed:") {
+            return needed
+                .split_whitespace()
+                .next()
+                .and_then(|count| count.parse().ok())
+                .map(Self::LayersNeeded);
+        }
+
+        if lower.starts_with("fetching") && (lower.contains("layer") || lower.contains("chunk")) {
+            let layer = line
+                .split_whitespace()
+                .skip_while(|word| {
+                    let word = word.to_lowercase();
+                    !(word.starts_with("layer") || word.starts_with("chunk"))
+                })
+                .nth(1)?
+                .trim_end_matches(['.', ':'])
+                .to_owned();
+            let percent = line
+                .split_whitespace()
+                .find_map(|word| word.strip_suffix('%')?.parse().ok());
+
+            return Some(Self::Fetching { layer, percent });
+        }
+
+        [
+            "pulling manifest",
+            "importing",
+            "staging deployment",
+            "pruning",
+        ]
+        .iter()
+        .any(|step| lower.starts_with(step))
+        .then(|| {
+            Self::Step(
+                line.split("...")
+                    .next()
+                    .unwrap_or(line)
+                    .trim_end_matches(':')
+                    .to_owned(),
+            )
+        })
+    }
+}
+
+/// Shows the progress of a switch or upgrade on a progress bar.
+pub(super) struct BootProgress {
+    progress: ProgressBar,
+    message: String,
+    fetched: HashSet<String>,
+}
+
+impl BootProgress {
+    pub(super) fn new(progress: ProgressBar, message: String) -> Self {
+        Self {
+            progress,
+            message,
+            fetched: HashSet::new(),
+        }
+    }
+
+    /// Updates the progress bar with the progress of the line.
+    ///
+    /// Returns `false` if the line isn't about the progress.
+    pub(super) fn update(&mut self, line: &str) -> bool {
+        let Some(event) = BootProgressEvent::parse(line) else {
+            return false;
+        };
+
+        match event {
+            BootProgressEvent::LayersNeeded(count) => {
+                if self.progress.length().is_none_or(|length| length == 0) {
+                    self.progress.set_style(
+                        ProgressStyle::with_template(LAYERS_TEMPLATE)
+                            .expect("Should be a valid template")
+                            .progress_chars("=> "),
+                    );
+                    self.progress.set_length(0);
+                }
+                self.progress.inc_length(count);
+            }
+            BootProgressEvent::Fetching { layer, percent } => {
+                if self.fetched.insert(layer.clone()) {
+                    self.progress.set_position(self.fetched.len() as u64);
+                }
+                self.progress.set_message(percent.map_or_else(
+                    || format!("{} {layer}", self.message),
+                    |percent| format!("{} {layer} {percent}%", self.message),
+                ));
+            }
+            BootProgressEvent::Step(step) => self.progress.set_message(step),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use super::BootProgressEvent;
+
+    #[rstest]
+    #[case(
+        "Ostree chunk layers needed: 51 (1.2 GB)",
+        Some(BootProgressEvent::LayersNeeded(51))
+    )]
+    #[case(
+        "Custom layers needed: 1 (300.4 MB)",
+        Some(BootProgressEvent::LayersNeeded(1))
+    )]
+    #[case(
+        "layers already present: 10; layers needed: 55 (1.3 GB)",
+        Some(BootProgressEvent::LayersNeeded(55))
+    )]
+    #[case(
+        "Fetching ostree chunk sha256:2ae1c9b2cd5c (24.2 MB)...done",
+        Some(BootProgressEvent::Fetching { layer: "sha256:2ae1c9b2cd5c".into(), percent: None })
+    )]
+    #[case(
+        "Fetching layer sha256:0b9f5ee1b1a5 (300.4 MB)... 45%",
+        Some(BootProgressEvent::Fetching { layer: "sha256:0b9f5ee1b1a5".into(), percent: Some(45) })
+    )]
+    #[case("Staging deployment...done", Some(BootProgressEvent::Step("Staging deployment".into())))]
+    #[case(
+        "Pulling manifest: ostree-unverified-image:oci-archive:/etc/bluebuild/test.tar.gz",
+        Some(BootProgressEvent::Step(
+            "Pulling manifest: ostree-unverified-image:oci-archive:/etc/bluebuild/test.tar.gz".into()
+        ))
+    )]
+    #[case("Added:", None)]
+    #[case("  htop-3.3.0-1.fc41.x86_64", None)]
+    #[case(
+        "Changes queued for next boot. Run \"systemctl reboot\" to start a reboot",
+        None
+    )]
+    fn parse_progress(#[case] line: &str, #[case] expected: Option<BootProgressEvent>) {
+        assert_eq!(BootProgressEvent::parse(line), expected);
+    }
+}
@@ -0,0 +1,303 @@
+//! Collects metrics about a build so that they can be exported
+//! to a Prometheus pushgateway or a node-exporter textfile collector.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::Path,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use bon::builder;
+use log::{debug, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+
+const METRICS_JOB: &str = "bluebuild";
+
+static METRICS: LazyLock<Mutex<BuildMetrics>> =
+    LazyLock::new(|| Mutex::new(BuildMetrics::default()));
+
+#[derive(Debug, Default)]
+struct BuildMetrics {
+    phases: BTreeMap<(String, String), Duration>,
+    image_sizes: BTreeMap<(String, String), u64>,
+    cache_hits: u64,
+    cache_steps: u64,
+}
+
+/// Runs `f` and records how long it took as the
+/// duration of `phase` for the given recipe.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn time_phase<T, F>(recipe: &str, phase: &str, f: F) -> T
+where
+    F: FnOnce() -> T,
+{
+    let start = Instant::now();
+    let result = f();
+    record_phase(recipe, phase, start.elapsed());
+    result
+}
+
+/// Records the duration of `phase` for the given recipe.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn record_phase(recipe: &str, phase: &str, elapsed: Duration) {
+    trace!("Phase {phase} for {recipe} took {elapsed:?}");
+    *METRICS
+        .lock()
+        .expect("Should lock METRICS")
+        .phases
+        .entry((recipe.into(), phase.into()))
+        .or_default() += elapsed;
+}
+
+/// Records the size in bytes of a built image.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn record_image_size(recipe: &str, image: &str, size: u64) {
+    METRICS
+        .lock()
+        .expect("Should lock METRICS")
+        .image_sizes
+        .insert((recipe.into(), image.into()), size);
+}
+
+/// Inspects a line of build output for build steps
+/// and whether or not they were pulled from cache.
+pub(crate) fn record_build_line(line: &str) {
+    let (step, hit) = parse_build_line(line);
+
+    if step || hit {
+        let mut metrics = METRICS.lock().expect("Should lock METRICS");
+        metrics.cache_steps += u64::from(step);
+        metrics.cache_hits += u64::from(hit);
+    }
+}
+
+/// Returns whether the line starts a build step and
+/// whether the line reports a cache hit.
+fn parse_build_line(line: &str) -> (bool, bool) {
+    let line = line.trim_start();
+
+    // podman/buildah print `STEP n/m: ...` and `--> Using cache <id>`,
+    // docker buildx prints `#n [stage n/m] ...` and `#n CACHED`
+    line.strip_prefix('#').map_or_else(
+        || {
+            (
+                line.starts_with("STEP "),
+                line.starts_with("--> Using cache"),
+            )
+        },
+        |line| {
+            let line = line.trim_start_matches(|c: char| c.is_ascii_digit());
+            (line.starts_with(" ["), line == " CACHED")
+        },
+    )
+}
+
+/// Renders all collected metrics in the Prometheus text exposition format.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+#[must_use]
+pub fn render(success: bool) -> String {
+    METRICS.lock().expect("Should lock METRICS").render(success)
+}
+
+impl BuildMetrics {
+    fn render(&self, success: bool) -> String {
+        let mut out = String::new();
+
+        write_metric(
+            &mut out,
+            "bluebuild_build_success gauge Whether the last build succeeded (1) or failed (0).",
+            [(String::new(), u8::from(success).to_string())],
+        );
+        write_metric(
+            &mut out,
+            "bluebuild_build_last_run_timestamp_seconds gauge Unix timestamp of the end of the last build.",
+            [(
+                String::new(),
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_secs()
+                    .to_string(),
+            )],
+        );
+        write_metric(
+            &mut out,
+            "bluebuild_build_phase_duration_seconds gauge Time spent in each phase of the build.",
+            self.phases.iter().map(|((recipe, phase), duration)| {
+                (
+                    format!(
+                        "recipe=\"{}\",phase=\"{}\"",
+                        escape_label(recipe),
+                        escape_label(phase)
+                    ),
+                    format!("{:.3}", duration.as_secs_f64()),
+                )
+            }),
+        );
+        write_metric(
+            &mut out,
+            "bluebuild_build_image_size_bytes gauge Size of each built image.",
+            self.image_sizes.iter().map(|((recipe, image), size)| {
+                (
+                    format!(
+                        "recipe=\"{}\",image=\"{}\"",
+                        escape_label(recipe),
+                        escape_label(image)
+                    ),
+                    size.to_string(),
+                )
+            }),
+        );
+        write_metric(
+            &mut out,
+            "bluebuild_build_cache_steps_total counter Number of build steps executed.",
+            [(String::new(), self.cache_steps.to_string())],
+        );
+        write_metric(
+            &mut out,
+            "bluebuild_build_cache_hits_total counter Number of build steps that were pulled from cache.",
+            [(String::new(), self.cache_hits.to_string())],
+        );
+        #[allow(clippy::cast_precision_loss)]
+        write_metric(
+            &mut out,
+            "bluebuild_build_cache_hit_ratio gauge Ratio of build steps that were pulled from cache.",
+            [(
+                String::new(),
+                if self.cache_steps == 0 {
+                    String::from("0")
+                } else {
+                    format!("{:.3}", self.cache_hits as f64 / self.cache_steps as f64)
+                },
+            )],
+        );
+        write_metric(
+            &mut out,
+            "bluebuild_build_retries_total counter Number of retried operations during the build.",
+            [(String::new(), blue_build_utils::retry_count().to_string())],
+        );
+
+        out
+    }
+}
+
+/// Writes the `HELP` and `TYPE` header for a metric followed by its samples.
+///
+/// The `description` is in the format of `<name> <type> <help text>`.
+fn write_metric<I>(out: &mut String, description: &str, samples: I)
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut parts = description.splitn(3, ' ');
+    let (name, kind, help) = (
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+        parts.next().unwrap_or_default(),
+    );
+
+    let _ = writeln!(out, "# HELP {name} {help}");
+    let _ = writeln!(out, "# TYPE {name} {kind}");
+    for (labels, value) in samples {
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name} {value}");
+        } else {
+            let _ = writeln!(out, "{name}{{{labels}}} {value}");
+        }
+    }
+}
+
+/// Exports the collected metrics to a textfile
+/// and/or a Prometheus pushgateway.
+///
+/// # Errors
+/// Will error if the textfile cannot be written or
+/// the pushgateway rejects the metrics.
+#[builder]
+pub fn export(textfile: Option<&Path>, pushgateway: Option<&str>, success: bool) -> Result<()> {
+    let metrics = render(success);
+
+    if let Some(textfile) = textfile {
+        debug!("Writing metrics to {}", textfile.display());
+
+        // Write to a temp file first so the collector never reads a partial file
+        let tmp_file = textfile.with_extension("tmp");
+        fs::write(&tmp_file, &metrics)
+            .and_then(|()| fs::rename(&tmp_file, textfile))
+            .into_diagnostic()
+            .with_context(|| format!("Failed to write metrics to {}", textfile.display()))?;
+    }
+
+    if let Some(pushgateway) = pushgateway {
+        let hostname = nix::unistd::gethostname()
+            .into_diagnostic()?
+            .to_string_lossy()
+            .into_owned();
+        let url = format!(
+            "{}/metrics/job/{METRICS_JOB}/instance/{hostname}",
+            pushgateway.trim_end_matches('/')
+        );
+        debug!("Pushing metrics to {url}");
+
+        let response = reqwest::blocking::Client::new()
+            .put(&url)
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(metrics)
+            .send()
+            .into_diagnostic()
+            .with_context(|| format!("Failed to push metrics to {url}"))?;
+
+        if !response.status().is_success() {
+            bail!("Pushgateway at {url} responded with {}", response.status());
+        }
+    }
+
+    Ok(())
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', r"\\")
+        .replace('"', "\\\"")
+        .replace('\n', r"\n")
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use super::{escape_label, parse_build_line, render};
+
+    #[rstest]
+    #[case("STEP 1/4: FROM ghcr.io/ublue-os/silverblue-main:41", (true, false))]
+    #[case("--> Using cache 2f1a9c0e", (false, true))]
+    #[case("#7 [stage-1 2/5] RUN echo test", (true, false))]
+    #[case("#7 CACHED", (false, true))]
+    #[case("#7 DONE 0.3s", (false, false))]
+    #[case("Writing manifest to image destination", (false, false))]
+    fn build_lines(#[case] line: &str, #[case] expected: (bool, bool)) {
+        assert_eq!(parse_build_line(line), expected);
+    }
+
+    #[test]
+    fn escape_labels() {
+        assert_eq!(escape_label(r#"a"b\c"#), r#"a\"b\\c"#);
+    }
+
+    #[test]
+    fn render_success() {
+        let out = render(true);
+        assert!(out.contains("bluebuild_build_success 1\n"));
+        assert!(out.contains("# TYPE bluebuild_build_retries_total counter\n"));
+    }
+}
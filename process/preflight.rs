@@ -0,0 +1,382 @@
+//! Checks that run before a build starts, so that a build fails
+//! early instead of running out of space halfway through or on a
+//! system that isn't set up for rootless containers.
+
+use std::{
+    collections::BTreeMap,
+    fmt, fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use blue_build_utils::check_command_exists;
+use bon::Builder;
+use log::{debug, trace, warn};
+use miette::{bail, IntoDiagnostic, Result};
+use nix::{
+    sys::statvfs::statvfs,
+    unistd::{Uid, User},
+};
+
+/// A size in bytes, like `20G` or `512MiB`.
+///
+/// Units are powers of 1024, the same as podman and docker use.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(pub u64);
+
+const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+
+/// The fewest subordinate ids that rootless containers need,
+/// which covers the users that most images create.
+const MIN_SUBIDS: u64 = 65536;
+
+/// The first kernel that supports overlay mounts in a user namespace.
+const NATIVE_OVERLAY_KERNEL: (u32, u32) = (5, 13);
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s
+            .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .unwrap_or(s.len());
+        let (number, unit) = s.split_at(split);
+
+        let number: f64 = number
+            .parse()
+            .map_err(|_| format!("{s} doesn't start with a number"))?;
+        let exponent = match unit.trim().to_ascii_lowercase().as_str() {
+            "" | "b" => 0,
+            "k" | "kb" | "kib" => 1,
+            "m" | "mb" | "mib" => 2,
+            "g" | "gb" | "gib" => 3,
+            "t" | "tb" | "tib" => 4,
+            unit => return Err(format!("Unknown unit {unit}, expected B, K, M, G, or T")),
+        };
+
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        Ok(Self((number * 1024_f64.powi(exponent)) as u64))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        #[allow(clippy::cast_precision_loss)]
+        let mut size = self.0 as f64;
+        let mut unit = 0;
+
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+
+        if unit == 0 {
+            write!(f, "{} {}", self.0, UNITS[unit])
+        } else {
+            write!(f, "{size:.1} {}", UNITS[unit])
+        }
+    }
+}
+
+/// Space that a build needs on the filesystem of a path.
+#[derive(Debug, Clone, Builder)]
+pub struct SpaceNeeded {
+    #[builder(into)]
+    pub path: PathBuf,
+
+    pub required: ByteSize,
+
+    /// What the space is for, like `container storage`.
+    #[builder(into)]
+    pub purpose: String,
+}
+
+/// The path, or its nearest ancestor that exists,
+/// since the path may be a directory that a build creates later.
+fn existing_ancestor(path: &Path) -> &Path {
+    path.ancestors()
+        .find(|ancestor| ancestor.exists())
+        .unwrap_or(path)
+}
+
+/// Gets the space that's free for unprivileged
+/// users on the filesystem of the path.
+///
+/// # Errors
+/// Will error if the filesystem can't be read.
+pub fn free_space(path: &Path) -> Result<ByteSize> {
+    let stat = statvfs(existing_ancestor(path)).into_diagnostic()?;
+
+    #[allow(clippy::useless_conversion)]
+    Ok(ByteSize(
+        u64::from(stat.blocks_available()) * u64::from(stat.fragment_size()),
+    ))
+}
+
+/// Makes sure the filesystem of the path has at least `required` free.
+///
+/// # Errors
+/// Will error if there isn't enough free space.
+pub fn check_free_space(path: &Path, required: ByteSize) -> Result<()> {
+    trace!(
+        "preflight::check_free_space({}, {required})",
+        path.display()
+    );
+
+    check_space_needed(&[SpaceNeeded::builder()
+        .path(path)
+        .required(required)
+        .purpose("the build")
+        .build()])
+}
+
+/// Makes sure every filesystem has the space that's needed on it.
+///
+/// Space needed on paths that share a filesystem is added up,
+/// like when container storage and the tempdir are on the same disk.
+///
+/// # Errors
+/// Will error if a filesystem doesn't have enough free space.
+pub fn check_space_needed(needed: &[SpaceNeeded]) -> Result<()> {
+    trace!("preflight::check_space_needed({needed:#?})");
+
+    let mut filesystems: BTreeMap<u64, Vec<&SpaceNeeded>> = BTreeMap::new();
+    for space in needed {
+        let device = fs::metadata(existing_ancestor(&space.path))
+            .into_diagnostic()?
+            .dev();
+        filesystems.entry(device).or_default().push(space);
+    }
+
+    for spaces in filesystems.into_values() {
+        let path = &spaces[0].path;
+        let required = ByteSize(spaces.iter().map(|space| space.required.0).sum());
+        let free = free_space(path)?;
+        debug!("{free} is free in {}", path.display());
+
+        if free < required {
+            let purposes = spaces
+                .iter()
+                .map(|space| format!("{} for {}", space.required, space.purpose))
+                .collect::<Vec<_>>()
+                .join(", ");
+            bail!(
+                help = "Free up space, use `--tempdir` to build on a bigger disk, or use `--skip-preflight` if the estimate is wrong",
+                "Only {free} is free in {}, but the build needs about {required} ({purposes})",
+                path.display()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Makes sure the system is set up for rootless podman or buildah,
+/// which is where many first builds fail.
+///
+/// This does nothing when running as root or off of Linux.
+///
+/// # Errors
+/// Will error with the steps to fix the system if the user doesn't
+/// have enough subordinate ids or there's no way to mount overlays.
+pub fn check_rootless(tool: &str) -> Result<()> {
+    trace!("preflight::check_rootless({tool})");
+
+    // Off of Linux, podman and buildah run in a VM that is set up for them
+    let uid = Uid::current();
+    if uid.is_root() || !cfg!(target_os = "linux") {
+        return Ok(());
+    }
+    let user = User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map_or_else(|| uid.to_string(), |user| user.name);
+
+    let mut problems = Vec::new();
+    let mut fixes = Vec::new();
+
+    // The ids can also come from sssd or another NSS service
+    if !subids_from_nss() {
+        for file in ["/etc/subuid", "/etc/subgid"] {
+            let count = fs::read_to_string(file)
+                .map(|contents| subid_count(&contents, &user, uid.as_raw()))
+                .unwrap_or_default();
+            debug!("{user} has {count} ids in {file}");
+
+            if count < MIN_SUBIDS {
+                problems.push(format!(
+                    "{file} gives {user} {count} ids, but at least {MIN_SUBIDS} are needed"
+                ));
+            }
+        }
+        if !problems.is_empty() {
+            fixes.push(format!(
+                "Run `sudo usermod --add-subuids 100000-165535 --add-subgids 100000-165535 {user}` and then `{tool} system migrate`"
+            ));
+        }
+    }
+
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap_or_default();
+    if !supports_native_overlay(&release) && !has_fuse_overlayfs() {
+        problems.push(format!(
+            "The kernel {} can't mount overlays rootless and fuse-overlayfs isn't installed",
+            release.trim()
+        ));
+        fixes.push("Install fuse-overlayfs, or update to a kernel newer than 5.13".into());
+    }
+
+    if !Path::new("/sys/fs/cgroup/cgroup.controllers").exists() {
+        warn!(
+            "The system uses cgroups v1, so {tool} can't limit the resources of rootless containers. Boot with `systemd.unified_cgroup_hierarchy=1` to use cgroups v2"
+        );
+    }
+
+    if !problems.is_empty() {
+        fixes.push("Use `--skip-preflight` if the system is set up in another way".into());
+        bail!(
+            help = fixes.join("\n"),
+            "The system isn't set up for rootless {tool}:\n{}",
+            problems
+                .iter()
+                .map(|problem| format!("  - {problem}"))
+                .collect::<Vec<_>>()
+                .join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// Whether overlays can be mounted with fuse-overlayfs.
+#[must_use]
+pub fn has_fuse_overlayfs() -> bool {
+    Path::new("/dev/fuse").exists() && check_command_exists("fuse-overlayfs").is_ok()
+}
+
+/// Whether subordinate ids are looked up with NSS instead of the files.
+fn subids_from_nss() -> bool {
+    fs::read_to_string("/etc/nsswitch.conf").is_ok_and(|contents| {
+        contents
+            .lines()
+            .any(|line| line.trim_start().starts_with("subid:"))
+    })
+}
+
+/// The number of subordinate ids that a `/etc/subuid`
+/// or `/etc/subgid` file gives the user.
+fn subid_count(contents: &str, user: &str, uid: u32) -> u64 {
+    let uid = uid.to_string();
+
+    contents
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split(':');
+            let owner = fields.next()?;
+            let _start = fields.next()?;
+            let count: u64 = fields.next()?.parse().ok()?;
+
+            (owner == user || owner == uid).then_some(count)
+        })
+        .sum()
+}
+
+/// Whether the kernel release, like `6.11.4-301.fc41.x86_64`,
+/// supports overlay mounts in a user namespace.
+fn supports_native_overlay(release: &str) -> bool {
+    let mut version = release
+        .trim()
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|part| part.parse::<u32>().ok());
+
+    match (version.next().flatten(), version.next().flatten()) {
+        (Some(major), Some(minor)) => (major, minor) >= NATIVE_OVERLAY_KERNEL,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use rstest::rstest;
+
+    use super::{
+        check_free_space, check_space_needed, subid_count, supports_native_overlay, ByteSize,
+        SpaceNeeded,
+    };
+
+    #[rstest]
+    #[case("512", 512)]
+    #[case("512B", 512)]
+    #[case("4K", 4 * 1024)]
+    #[case("20G", 20 * 1024 * 1024 * 1024)]
+    #[case("20GiB", 20 * 1024 * 1024 * 1024)]
+    #[case("1.5 GB", 3 * 512 * 1024 * 1024)]
+    #[case("2t", 2 * 1024 * 1024 * 1024 * 1024)]
+    fn parse_size(#[case] size: &str, #[case] expected: u64) {
+        assert_eq!(size.parse::<ByteSize>().unwrap(), ByteSize(expected));
+    }
+
+    #[rstest]
+    #[case("")]
+    #[case("G")]
+    #[case("20X")]
+    fn parse_invalid_size(#[case] size: &str) {
+        assert!(size.parse::<ByteSize>().is_err());
+    }
+
+    #[rstest]
+    #[case(ByteSize(512), "512 B")]
+    #[case(ByteSize(20 * 1024 * 1024 * 1024), "20.0 GiB")]
+    #[case(ByteSize(3 * 512 * 1024), "1.5 MiB")]
+    fn display_size(#[case] size: ByteSize, #[case] expected: &str) {
+        assert_eq!(size.to_string(), expected);
+    }
+
+    #[test]
+    fn free_space() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("does-not-exist");
+
+        assert!(check_free_space(&path, ByteSize(0)).is_ok());
+        assert!(check_free_space(&path, ByteSize(u64::MAX)).is_err());
+    }
+
+    #[test]
+    fn space_needed_on_same_filesystem_adds_up() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let needed = |purpose: &str| {
+            SpaceNeeded::builder()
+                .path(path)
+                .required(ByteSize(super::free_space(path).unwrap().0 / 4 * 3))
+                .purpose(purpose)
+                .build()
+        };
+
+        assert!(check_space_needed(&[needed("storage")]).is_ok());
+        assert!(check_space_needed(&[needed("storage"), needed("tempdir")]).is_err());
+    }
+
+    #[rstest]
+    #[case("octocat:100000:65536\n", 65536)]
+    #[case("1000:100000:65536\n", 65536)]
+    #[case("octocat:100000:30000\noctocat:200000:40000\n", 70000)]
+    #[case("other:100000:65536\n", 0)]
+    #[case("", 0)]
+    fn subids_of_user(#[case] contents: &str, #[case] expected: u64) {
+        assert_eq!(subid_count(contents, "octocat", 1000), expected);
+    }
+
+    #[rstest]
+    #[case("6.11.4-301.fc41.x86_64", true)]
+    #[case("5.13.0", true)]
+    #[case("5.4.0-150-generic", false)]
+    #[case("4.18.0-553.el8_10.x86_64", false)]
+    #[case("", false)]
+    fn native_overlay(#[case] release: &str, #[case] expected: bool) {
+        assert_eq!(supports_native_overlay(release), expected);
+    }
+}
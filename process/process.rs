@@ -2,23 +2,54 @@
 //! by this tool. It contains drivers for running, building, inspecting, and signing
 //! images that interface with tools like docker or podman.
 
-#[cfg(any(feature = "sigstore", feature = "validate"))]
-use once_cell::sync::Lazy;
-#[cfg(any(feature = "sigstore", feature = "validate"))]
-use tokio::runtime::Runtime;
+use std::{future::Future, sync::LazyLock};
+
+use miette::{IntoDiagnostic, Result};
+use tokio::runtime::{Handle, Runtime, RuntimeFlavor};
 
 pub mod drivers;
+pub mod generated;
 pub mod logging;
+pub mod metrics;
+pub mod preflight;
 pub mod signal_handler;
+pub mod summary;
 
-#[cfg(any(feature = "sigstore", feature = "validate"))]
-pub static ASYNC_RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+/// The runtime shared by all async operations of the drivers.
+pub static ASYNC_RUNTIME: LazyLock<Runtime> = LazyLock::new(|| {
     tokio::runtime::Builder::new_multi_thread()
         .enable_all()
         .build()
         .unwrap()
 });
 
+/// Runs a future to completion on the shared runtime.
+///
+/// This is safe to call from a task that is already running
+/// on a multi-threaded runtime. The worker thread is handed
+/// off to the runtime's other tasks while this blocks.
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    match Handle::try_current() {
+        Ok(handle) if handle.runtime_flavor() == RuntimeFlavor::MultiThread => {
+            tokio::task::block_in_place(|| handle.block_on(future))
+        }
+        _ => ASYNC_RUNTIME.block_on(future),
+    }
+}
+
+/// Runs blocking work on the shared runtime's
+/// blocking thread pool.
+///
+/// # Errors
+/// Will error if `f` errors or panics.
+pub async fn spawn_blocking<T, F>(f: F) -> Result<T>
+where
+    F: FnOnce() -> Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    ASYNC_RUNTIME.spawn_blocking(f).await.into_diagnostic()?
+}
+
 #[cfg(test)]
 pub(crate) mod test {
     use std::sync::LazyLock;
@@ -27,4 +58,21 @@ pub(crate) mod test {
     pub const TEST_TAG_2: &str = "test-tag-2";
 
     pub static TIMESTAMP: LazyLock<String> = LazyLock::new(blue_build_utils::get_tag_timestamp);
+
+    #[test]
+    fn block_on_inside_runtime() {
+        let value = super::ASYNC_RUNTIME
+            .block_on(async { tokio::spawn(async { super::block_on(async { 1 }) }).await })
+            .unwrap();
+
+        assert_eq!(value, 1);
+    }
+
+    #[test]
+    fn spawn_blocking_errors() {
+        let result: miette::Result<()> =
+            super::block_on(super::spawn_blocking(|| miette::bail!("Failed")));
+
+        assert_eq!(result.unwrap_err().to_string(), "Failed");
+    }
 }
@@ -1,19 +1,18 @@
 use std::{
     fs,
     path::PathBuf,
-    process,
-    sync::{atomic::AtomicBool, Arc, Mutex},
+    process::{self, ExitStatus},
+    sync::{atomic::AtomicBool, Arc, LazyLock, Mutex},
     thread,
 };
 
-use blue_build_utils::cmd;
+use blue_build_utils::{cmd, tools::tool_path};
 use log::{debug, error, trace, warn};
 use nix::{
     libc::{SIGABRT, SIGCONT, SIGHUP, SIGTSTP},
     sys::signal::{kill, Signal},
     unistd::Pid,
 };
-use once_cell::sync::Lazy;
 use signal_hook::{
     consts::TERM_SIGNALS,
     flag,
@@ -21,7 +20,7 @@ use signal_hook::{
     low_level,
 };
 
-use crate::logging::Logger;
+use crate::{generated, logging::Logger};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ContainerSignalId {
@@ -59,9 +58,153 @@ impl ContainerSignalId {
     }
 }
 
-static PID_LIST: Lazy<Arc<Mutex<Vec<i32>>>> = Lazy::new(|| Arc::new(Mutex::new(vec![])));
-static CID_LIST: Lazy<Arc<Mutex<Vec<ContainerSignalId>>>> =
-    Lazy::new(|| Arc::new(Mutex::new(vec![])));
+/// Something a build creates that is removed
+/// when the program receives a kill signal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CleanupItem {
+    /// A directory, like a temp dir.
+    Dir(PathBuf),
+
+    /// A volume of a container runtime.
+    Volume {
+        name: String,
+        container_runtime: ContainerRuntime,
+        requires_sudo: bool,
+    },
+
+    /// The mount of a container's filesystem,
+    /// which is unmounted by the container ID.
+    Mount {
+        container_id: String,
+        container_runtime: ContainerRuntime,
+        requires_sudo: bool,
+    },
+
+    /// A container that was created but isn't running.
+    Container {
+        container_id: String,
+        container_runtime: ContainerRuntime,
+        requires_sudo: bool,
+    },
+
+    /// An image that was only built for an intermediate step.
+    Image {
+        name: String,
+        container_runtime: ContainerRuntime,
+        requires_sudo: bool,
+    },
+}
+
+impl CleanupItem {
+    fn remove(&self) {
+        let (container_runtime, requires_sudo, args) = match self {
+            Self::Dir(path) => {
+                debug!("Removing directory {}", path.display());
+
+                if let Err(e) = fs::remove_dir_all(path) {
+                    error!("Failed to remove directory {}: Error {e}", path.display());
+                }
+                return;
+            }
+            Self::Volume {
+                name,
+                container_runtime,
+                requires_sudo,
+            } => (
+                container_runtime,
+                requires_sudo,
+                vec!["volume", "rm", "-f", name.as_str()],
+            ),
+            Self::Mount {
+                container_id,
+                container_runtime,
+                requires_sudo,
+            } => (
+                container_runtime,
+                requires_sudo,
+                vec!["unmount", "-f", container_id.as_str()],
+            ),
+            Self::Container {
+                container_id,
+                container_runtime,
+                requires_sudo,
+            } => (
+                container_runtime,
+                requires_sudo,
+                vec!["container", "rm", "-f", container_id.as_str()],
+            ),
+            Self::Image {
+                name,
+                container_runtime,
+                requires_sudo,
+            } => (
+                container_runtime,
+                requires_sudo,
+                vec!["image", "rm", "-f", name.as_str()],
+            ),
+        };
+        debug!("Running {container_runtime} {}", args.join(" "));
+
+        match runtime_status(container_runtime, *requires_sudo, &args) {
+            Ok(status) if status.success() => {}
+            Ok(status) => error!(
+                "Failed to run {container_runtime} {}: {status}",
+                args.join(" ")
+            ),
+            Err(e) => error!(
+                "Failed to run {container_runtime} {}: Error {e}",
+                args.join(" ")
+            ),
+        }
+    }
+}
+
+fn runtime_status(
+    container_runtime: &ContainerRuntime,
+    requires_sudo: bool,
+    args: &[&str],
+) -> std::io::Result<ExitStatus> {
+    if requires_sudo {
+        cmd!("sudo", tool_path(container_runtime.to_string()), for args).status()
+    } else {
+        cmd!(container_runtime.to_string(), for args).status()
+    }
+}
+
+/// Keeps an item in the cleanup list while it's alive.
+///
+/// The owner of the item is still responsible for removing
+/// it, this only covers the program being killed. Directories
+/// are also tracked in the manifest of [`generated`] in case
+/// the program crashes.
+#[derive(Debug)]
+pub struct CleanupGuard(CleanupItem);
+
+impl CleanupGuard {
+    #[must_use]
+    pub fn new(item: CleanupItem) -> Self {
+        if let CleanupItem::Dir(path) = &item {
+            generated::track(path);
+        }
+        add_cleanup(&item);
+        Self(item)
+    }
+}
+
+impl Drop for CleanupGuard {
+    fn drop(&mut self) {
+        remove_cleanup(&self.0);
+        if let CleanupItem::Dir(path) = &self.0 {
+            generated::untrack(path);
+        }
+    }
+}
+
+static PID_LIST: LazyLock<Arc<Mutex<Vec<i32>>>> = LazyLock::new(|| Arc::new(Mutex::new(vec![])));
+static CID_LIST: LazyLock<Arc<Mutex<Vec<ContainerSignalId>>>> =
+    LazyLock::new(|| Arc::new(Mutex::new(vec![])));
+static CLEANUP_LIST: LazyLock<Arc<Mutex<Vec<CleanupItem>>>> =
+    LazyLock::new(|| Arc::new(Mutex::new(vec![])));
 
 /// Initialize Ctrl-C handler. This should be done at the start
 /// of a binary.
@@ -121,7 +264,13 @@ where
                         debug!("Killing container {id}");
 
                         let status = if cid.requires_sudo {
-                            cmd!("sudo", cid.container_runtime.to_string(), "stop", id).status()
+                            cmd!(
+                                "sudo",
+                                tool_path(cid.container_runtime.to_string()),
+                                "stop",
+                                id
+                            )
+                            .status()
                         } else {
                             cmd!(cid.container_runtime.to_string(), "stop", id).status()
                         };
@@ -133,6 +282,8 @@ where
                 });
                 drop(cid_list);
 
+                cleanup();
+
                 exit_unwind(1);
             }
             SIGTSTP => {
@@ -169,6 +320,16 @@ fn exit_unwind(code: i32) {
     std::panic::resume_unwind(Box::new(ExitCode { code }));
 }
 
+/// Removes the items in the cleanup list, newest first, since
+/// later items can depend on earlier ones.
+fn cleanup() {
+    let cleanup_list = CLEANUP_LIST.clone();
+    let cleanup_list = cleanup_list.lock().expect("Should lock mutex");
+
+    cleanup_list.iter().rev().for_each(CleanupItem::remove);
+    drop(cleanup_list);
+}
+
 fn send_signal_processes(sig: i32) {
     let pid_list = PID_LIST.clone();
     let pid_list = pid_list.lock().expect("Should lock mutex");
@@ -245,3 +406,28 @@ pub fn remove_cid(cid: &ContainerSignalId) {
         cid_list.swap_remove(index);
     }
 }
+
+/// Add an item to the list to remove when the
+/// program recieves a kill signal.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn add_cleanup(item: &CleanupItem) {
+    let mut cleanup_list = CLEANUP_LIST.lock().expect("Should lock cleanup_list");
+
+    if !cleanup_list.contains(item) {
+        cleanup_list.push(item.clone());
+    }
+}
+
+/// Remove an item from the list of items to remove.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn remove_cleanup(item: &CleanupItem) {
+    let mut cleanup_list = CLEANUP_LIST.lock().expect("Should lock cleanup_list");
+
+    if let Some(index) = cleanup_list.iter().position(|val| *val == *item) {
+        cleanup_list.remove(index);
+    }
+}
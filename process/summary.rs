@@ -0,0 +1,275 @@
+//! Collects what a build produced, like the images and their
+//! digests and the results of validating the recipes, so that
+//! a CI driver can report it to the CI system.
+
+use std::{
+    fmt::Write as _,
+    sync::{LazyLock, Mutex},
+};
+
+use bon::Builder;
+use log::trace;
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::preflight::ByteSize;
+
+static SUMMARY: LazyLock<Mutex<BuildSummary>> =
+    LazyLock::new(|| Mutex::new(BuildSummary::default()));
+
+/// Everything that was built, and the warnings that came up.
+#[derive(Debug, Default, Clone, Serialize, JsonSchema)]
+pub struct BuildSummary {
+    pub success: bool,
+    pub images: Vec<ImageSummary>,
+    pub validations: Vec<ValidationSummary>,
+    pub warnings: Vec<String>,
+}
+
+/// The result of validating a recipe.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ValidationSummary {
+    /// The path of the recipe.
+    pub recipe: String,
+
+    /// The rendered errors, which are empty if the recipe is valid.
+    pub errors: Vec<String>,
+}
+
+/// An image that was built from a recipe.
+#[derive(Debug, Clone, Serialize, JsonSchema, Builder)]
+pub struct ImageSummary {
+    /// The path of the recipe.
+    #[builder(into)]
+    pub recipe: String,
+
+    /// The name of the image without a tag.
+    #[builder(into)]
+    pub image: String,
+
+    #[builder(default)]
+    pub tags: Vec<String>,
+
+    /// The digest of the image in the registry,
+    /// which is only known once it's pushed.
+    pub digest: Option<String>,
+
+    /// The size of the image in bytes.
+    pub size: Option<u64>,
+
+    #[builder(default)]
+    pub pushed: bool,
+
+    #[builder(default)]
+    pub signed: bool,
+}
+
+impl ImageSummary {
+    /// The image at each of its tags.
+    pub fn refs(&self) -> impl Iterator<Item = String> + '_ {
+        self.tags.iter().map(|tag| format!("{}:{tag}", self.image))
+    }
+}
+
+/// Records an image that was built.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn record_image(image: ImageSummary) {
+    trace!("summary::record_image({image:?})");
+    SUMMARY
+        .lock()
+        .expect("Should lock SUMMARY")
+        .images
+        .push(image);
+}
+
+/// Records the result of validating a recipe. Colors
+/// are removed from the errors.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn record_validation<S: Into<String>>(recipe: S, errors: &[String]) {
+    let validation = ValidationSummary {
+        recipe: recipe.into(),
+        errors: errors.iter().map(|err| strip_ansi(err)).collect(),
+    };
+    trace!("summary::record_validation({validation:?})");
+
+    let mut summary = SUMMARY.lock().expect("Should lock SUMMARY");
+    summary
+        .validations
+        .retain(|other| other.recipe != validation.recipe);
+    summary.validations.push(validation);
+}
+
+/// Records a warning to show in the summary.
+/// A warning is only recorded once, even when
+/// it comes up for every recipe.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+pub fn record_warning<S: Into<String>>(warning: S) {
+    let warning = warning.into();
+    let mut summary = SUMMARY.lock().expect("Should lock SUMMARY");
+
+    if !summary.warnings.contains(&warning) {
+        summary.warnings.push(warning);
+    }
+}
+
+/// Returns the summary of everything recorded so far.
+///
+/// # Panics
+/// Will panic if the mutex cannot be locked.
+#[must_use]
+pub fn get(success: bool) -> BuildSummary {
+    BuildSummary {
+        success,
+        ..SUMMARY.lock().expect("Should lock SUMMARY").clone()
+    }
+}
+
+impl BuildSummary {
+    /// Renders the summary as Markdown.
+    #[must_use]
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+
+        _ = writeln!(
+            out,
+            "## BlueBuild {}\n",
+            if self.success {
+                "build succeeded :white_check_mark:"
+            } else {
+                "build failed :x:"
+            }
+        );
+
+        if self.images.is_empty() {
+            out.push_str("No images were built.\n");
+        } else {
+            out.push_str("| Recipe | Image | Tags | Digest | Size | Signed |\n");
+            out.push_str("| --- | --- | --- | --- | --- | --- |\n");
+
+            for image in &self.images {
+                _ = writeln!(
+                    out,
+                    "| `{}` | `{}` | {} | {} | {} | {} |",
+                    image.recipe,
+                    image.image,
+                    image
+                        .tags
+                        .iter()
+                        .map(|tag| format!("`{tag}`"))
+                        .collect::<Vec<_>>()
+                        .join(" "),
+                    image
+                        .digest
+                        .as_deref()
+                        .map_or_else(|| String::from("-"), |digest| format!("`{digest}`")),
+                    image
+                        .size
+                        .map_or_else(|| String::from("-"), |size| ByteSize(size).to_string()),
+                    match (image.pushed, image.signed) {
+                        (false, _) => "not pushed",
+                        (true, false) => ":x:",
+                        (true, true) => ":white_check_mark:",
+                    },
+                );
+            }
+        }
+
+        let invalid = self
+            .validations
+            .iter()
+            .filter(|validation| !validation.errors.is_empty())
+            .collect::<Vec<_>>();
+        if !invalid.is_empty() {
+            out.push_str("\n### Validation errors\n");
+            for validation in invalid {
+                _ = writeln!(out, "\n`{}`\n\n```", validation.recipe);
+                for err in &validation.errors {
+                    _ = writeln!(out, "{err}");
+                }
+                out.push_str("```\n");
+            }
+        }
+
+        if !self.warnings.is_empty() {
+            out.push_str("\n### Warnings\n\n");
+            for warning in &self.warnings {
+                _ = writeln!(out, "- {warning}");
+            }
+        }
+
+        out
+    }
+}
+
+/// Removes the escape sequences that color text in a terminal.
+#[must_use]
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars();
+
+    while let Some(c) = chars.next() {
+        if c == '\u{1b}' {
+            if chars.next() == Some('[') {
+                chars.by_ref().find(|c| ('@'..='~').contains(c));
+            }
+        } else {
+            out.push(c);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{strip_ansi, BuildSummary, ImageSummary};
+
+    #[test]
+    fn markdown() {
+        let summary = BuildSummary {
+            success: true,
+            images: vec![
+                ImageSummary::builder()
+                    .recipe("recipes/recipe.yml")
+                    .image("ghcr.io/octocat/test")
+                    .tags(vec!["latest".into(), "41".into()])
+                    .digest("sha256:abc".into())
+                    .size(3 * 1024 * 1024 * 1024)
+                    .pushed(true)
+                    .signed(true)
+                    .build(),
+                ImageSummary::builder()
+                    .recipe("recipes/local.yml")
+                    .image("localhost/local")
+                    .tags(vec!["latest".into()])
+                    .build(),
+            ],
+            validations: Vec::new(),
+            warnings: vec!["Skipping validation".into()],
+        };
+
+        assert_eq!(
+            summary.to_markdown(),
+            "## BlueBuild build succeeded :white_check_mark:\n\n\
+            | Recipe | Image | Tags | Digest | Size | Signed |\n\
+            | --- | --- | --- | --- | --- | --- |\n\
+            | `recipes/recipe.yml` | `ghcr.io/octocat/test` | `latest` `41` | `sha256:abc` | 3.0 GiB | :white_check_mark: |\n\
+            | `recipes/local.yml` | `localhost/local` | `latest` | - | - | not pushed |\n\
+            \n### Warnings\n\n\
+            - Skipping validation\n"
+        );
+    }
+
+    #[test]
+    fn strips_colors() {
+        assert_eq!(
+            strip_ansi("File \u{1b}[1;3mrecipe.yml\u{1b}[0m is invalid"),
+            "File recipe.yml is invalid"
+        );
+    }
+}
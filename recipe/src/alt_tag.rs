@@ -0,0 +1,93 @@
+use std::{borrow::Cow, collections::BTreeMap, env};
+
+use serde::{Deserialize, Serialize};
+
+/// A tag to add to the image instead of `latest`.
+///
+/// This can either be just the tag, which is always added:
+/// ```yaml
+/// alt-tags:
+///   - latest
+/// ```
+/// or a map with the conditions that the build must match
+/// for the tag to be added:
+/// ```yaml
+/// alt-tags:
+///   - tag: gts
+///     if:
+///       branch: main
+///       platform: linux/amd64
+///       env:
+///         FEDORA_VERSION: "40"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum AltTag<'a> {
+    Tag(Cow<'a, str>),
+    Conditional {
+        tag: Cow<'a, str>,
+
+        #[serde(rename = "if")]
+        condition: TagCondition<'a>,
+    },
+}
+
+/// The conditions of an alt tag. Every condition
+/// that is set must match for the tag to be added.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TagCondition<'a> {
+    /// The branch that is being built.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<Cow<'a, str>>,
+
+    /// The platform that is being built, like
+    /// `linux/amd64` or just the arch `amd64`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform: Option<Cow<'a, str>>,
+
+    /// Environment variables and the values they must be set to.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub env: BTreeMap<String, String>,
+}
+
+impl AltTag<'_> {
+    /// The tag to add to the image.
+    #[must_use]
+    pub fn tag(&self) -> &str {
+        match self {
+            Self::Tag(tag) | Self::Conditional { tag, .. } => tag,
+        }
+    }
+
+    /// Whether the tag is added for the build of the
+    /// `branch`, if it's known, for the `platform`.
+    #[must_use]
+    pub fn matches(&self, branch: Option<&str>, platform: &str) -> bool {
+        match self {
+            Self::Tag(_) => true,
+            Self::Conditional { condition, .. } => condition.matches(branch, platform),
+        }
+    }
+}
+
+impl TagCondition<'_> {
+    fn matches(&self, branch: Option<&str>, platform: &str) -> bool {
+        let branch_matches = self
+            .branch
+            .as_deref()
+            .is_none_or(|expected| branch == Some(expected));
+        let platform_matches = self.platform.as_deref().is_none_or(|expected| {
+            platform == expected
+                || platform
+                    .split_once('/')
+                    .is_some_and(|(_, arch)| arch == expected)
+        });
+        let env_matches = self
+            .env
+            .iter()
+            .all(|(var, expected)| env::var(var).is_ok_and(|value| value == *expected));
+
+        branch_matches && platform_matches && env_matches
+    }
+}
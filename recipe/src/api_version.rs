@@ -0,0 +1,251 @@
+use std::{collections::BTreeMap, fmt::Display};
+
+use colored::Colorize;
+use miette::{bail, miette, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
+
+use crate::substitute_expressions;
+
+/// The key that sets the version of the recipe format.
+pub const API_VERSION_KEY: &str = "apiVersion";
+
+/// The key of the variables of a v2 recipe.
+const VARS_KEY: &str = "vars";
+
+/// The keys of a v2 recipe.
+const V2_KEYS: &[&str] = &[
+    API_VERSION_KEY,
+    "name",
+    "description",
+    "base-image",
+    "blue-build-tag",
+    "alt-tags",
+    VARS_KEY,
+    "secrets",
+    "labels",
+    "mok",
+    "hooks",
+    "tests",
+    "stages",
+    "modules",
+];
+
+/// The keys that are planned for v2 recipes but aren't
+/// supported yet, which are rejected instead of ignored.
+const UNSUPPORTED_KEYS: &[&str] = &["profiles", "matrix"];
+
+/// The namespace of the variables in expressions.
+pub const VARS_NAMESPACE: &str = "vars";
+
+/// The keys that v1 recipes accept which were
+/// replaced in v2, with the key that replaced them.
+pub const V1_KEYS: &[(&str, &str)] = &[
+    ("base_image", "base-image"),
+    ("image_version", "base-image"),
+    ("image-version", "base-image"),
+    ("blue_build_tag", "blue-build-tag"),
+    ("alt_tags", "alt-tags"),
+];
+
+/// The version of the recipe format.
+///
+/// A recipe without an `apiVersion` is a v1 recipe. v1 recipes
+/// keep building while they're deprecated, and are converted
+/// to v2 with `bluebuild migrate`. Compared to v1, a v2 recipe:
+/// - sets `apiVersion: v2`
+/// - only uses kebab-case keys
+/// - sets the tag of the base image in `base-image`
+///   instead of in `image-version`
+/// - can set `vars` that are used in the strings of
+///   the recipe as `${{ vars.NAME }}`
+/// - rejects keys that it doesn't know, like `profiles`
+///   and `matrix`, which aren't supported yet
+///
+/// ```yaml
+/// apiVersion: v2
+/// name: weird-os
+/// description: This is my personal OS image.
+/// vars:
+///   FEDORA_VERSION: 41
+/// base-image: ghcr.io/ublue-os/silverblue-main:${{ vars.FEDORA_VERSION }}
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiVersion {
+    #[default]
+    V1,
+    V2,
+}
+
+impl ApiVersion {
+    /// The version that `bluebuild migrate` converts recipes to.
+    pub const LATEST: Self = Self::V2;
+
+    /// The version of a recipe, which is v1 if it isn't set.
+    ///
+    /// # Errors
+    /// Will error if the version isn't known.
+    pub fn of(recipe: &Value) -> Result<Self> {
+        recipe.get(API_VERSION_KEY).map_or(Ok(Self::V1), |version| {
+            serde_yaml::from_value(version.clone()).map_err(|_| {
+                miette!(
+                    help = "Use `apiVersion: v2`, or remove it for a v1 recipe",
+                    "The recipe has the unknown {API_VERSION_KEY} {}",
+                    serde_yaml::to_string(version)
+                        .unwrap_or_default()
+                        .trim()
+                        .bold()
+                )
+            })
+        })
+    }
+
+    #[must_use]
+    pub const fn is_v1(self) -> bool {
+        matches!(self, Self::V1)
+    }
+}
+
+impl Display for ApiVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::V1 => "v1",
+            Self::V2 => "v2",
+        })
+    }
+}
+
+/// Converts a v2 recipe to the keys that a recipe is deserialized
+/// from, which are the keys of a v1 recipe. The converted recipe
+/// is also what the schema of v1 recipes checks.
+///
+/// # Errors
+/// Will error if the recipe uses a key that was replaced
+/// in v2, a key that isn't supported, or a variable that
+/// isn't set.
+pub fn from_v2(recipe: Value) -> Result<Value> {
+    let Value::Mapping(mut recipe) = recipe else {
+        bail!("The recipe must be a map of keys to values");
+    };
+    recipe.remove(API_VERSION_KEY);
+
+    if let Some((key, replacement)) = V1_KEYS.iter().find(|(key, _)| recipe.contains_key(*key)) {
+        bail!(
+            help = "Run `bluebuild migrate` to convert the rest of the recipe to v2",
+            "The key {} isn't used in v2 recipes, use {} instead",
+            key.bold(),
+            replacement.bold()
+        );
+    }
+    for key in recipe.keys() {
+        let Some(key) = key.as_str() else {
+            bail!("The keys of the recipe must be strings");
+        };
+        if UNSUPPORTED_KEYS.contains(&key) {
+            bail!(
+                help = "Write a recipe for each variant of the image instead",
+                "The key {} isn't supported yet",
+                key.bold()
+            );
+        }
+        if !V2_KEYS.contains(&key) {
+            bail!(
+                help = format!("The keys of v2 recipes are {}", V2_KEYS.join(", ")),
+                "The key {} isn't used in v2 recipes",
+                key.bold()
+            );
+        }
+    }
+
+    let vars = vars(recipe.remove(VARS_KEY))?;
+    let mut recipe = Value::Mapping(recipe);
+    substitute_vars(&mut recipe, &expression_values(&vars))?;
+    let Value::Mapping(mut recipe) = recipe else {
+        unreachable!("The recipe is still a map");
+    };
+
+    // The variables are kept for the files that are templated
+    recipe.insert(
+        VARS_KEY.into(),
+        serde_yaml::to_value(&vars).into_diagnostic()?,
+    );
+
+    let base_image = match recipe.remove("base-image") {
+        Some(Value::String(base_image)) => base_image,
+        Some(_) => bail!("The {} must be a string", "base-image".bold()),
+        None => bail!("The recipe is missing the {}", "base-image".bold()),
+    };
+    let (image, tag) = split_tag(&base_image);
+    recipe.insert("base-image".into(), image.into());
+    recipe.insert("image-version".into(), tag.into());
+
+    Ok(Value::Mapping(recipe))
+}
+
+/// Splits the tag off of an image, which is `latest` if it isn't set.
+#[must_use]
+pub fn split_tag(image: &str) -> (&str, &str) {
+    let name_start = image.rfind('/').map_or(0, |i| i + 1);
+    image[name_start..]
+        .rfind(':')
+        .map_or((image, "latest"), |i| {
+            (&image[..name_start + i], &image[name_start + i + 1..])
+        })
+}
+
+fn vars(vars: Option<Value>) -> Result<BTreeMap<String, String>> {
+    let vars = match vars {
+        Some(Value::Mapping(vars)) => vars,
+        Some(Value::Null) | None => return Ok(BTreeMap::new()),
+        Some(_) => bail!("The {} must be a map of names to values", VARS_KEY.bold()),
+    };
+
+    vars.into_iter()
+        .map(|(name, value)| {
+            let value = match value {
+                Value::String(value) => value,
+                Value::Number(_) | Value::Bool(_) => serde_yaml::to_string(&value)
+                    .into_diagnostic()?
+                    .trim()
+                    .to_owned(),
+                _ => bail!(
+                    "The variable {} must be a string, number, or boolean",
+                    serde_yaml::to_string(&name).unwrap_or_default().trim()
+                ),
+            };
+            let Value::String(name) = name else {
+                bail!("The names of the {} must be strings", VARS_KEY.bold());
+            };
+            Ok((name, value))
+        })
+        .collect()
+}
+
+/// The variables keyed by their expression, like `vars.NAME`.
+#[must_use]
+pub fn expression_values(vars: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    vars.iter()
+        .map(|(name, value)| (format!("{VARS_NAMESPACE}.{name}"), value.clone()))
+        .collect()
+}
+
+/// Replaces the `${{ vars.NAME }}` in the strings of the recipe.
+fn substitute_vars(value: &mut Value, vars: &BTreeMap<String, String>) -> Result<()> {
+    match value {
+        Value::String(string) => *string = substitute_expressions(string, &[VARS_NAMESPACE], vars)?,
+        Value::Sequence(values) => {
+            for value in values {
+                substitute_vars(value, vars)?;
+            }
+        }
+        Value::Mapping(values) => {
+            for (_, value) in values.iter_mut() {
+                substitute_vars(value, vars)?;
+            }
+        }
+        Value::Tagged(tagged) => substitute_vars(&mut tagged.value, vars)?,
+        Value::Null | Value::Bool(_) | Value::Number(_) => {}
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+use std::{borrow::Cow, fmt::Display};
+
+use serde::{Deserialize, Serialize};
+
+use crate::Recipe;
+
+/// A directory that is kept between builds
+/// with a `--mount=type=cache` mount.
+///
+/// This can either be just the path:
+/// ```yaml
+/// cache-mounts:
+///   - /var/cache/dnf
+/// ```
+/// or a map with the `id` and `sharing` mode:
+/// ```yaml
+/// cache-mounts:
+///   - path: /root/.cargo/registry
+///     id: cargo-registry
+///     sharing: shared
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum CacheMount<'a> {
+    Path(Cow<'a, str>),
+    Full {
+        path: Cow<'a, str>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        id: Option<Cow<'a, str>>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sharing: Option<CacheSharing>,
+    },
+}
+
+/// How a cache mount is shared between builds running at the same time.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CacheSharing {
+    #[default]
+    Locked,
+    Shared,
+    Private,
+}
+
+impl Display for CacheSharing {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Locked => "locked",
+            Self::Shared => "shared",
+            Self::Private => "private",
+        })
+    }
+}
+
+impl CacheMount<'_> {
+    /// The directory in the container to mount the cache on.
+    #[must_use]
+    pub fn path(&self) -> &str {
+        match self {
+            Self::Path(path) | Self::Full { path, .. } => path,
+        }
+    }
+
+    #[must_use]
+    pub fn sharing(&self) -> CacheSharing {
+        match self {
+            Self::Path(_) => CacheSharing::default(),
+            Self::Full { sharing, .. } => sharing.unwrap_or_default(),
+        }
+    }
+
+    /// The ID of the cache.
+    ///
+    /// An `id` set by the user is used as is so that a cache can be
+    /// shared between recipes. Otherwise the ID is made from the path
+    /// and the recipe so that it stays the same between builds of
+    /// the recipe, and is shared by every module that mounts the path.
+    #[must_use]
+    pub fn id(&self, recipe: &Recipe) -> String {
+        if let Self::Full { id: Some(id), .. } = self {
+            return id.to_string();
+        }
+
+        let path = self
+            .path()
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase();
+        format!("{path}-{}-{}", recipe.name, recipe.image_version)
+    }
+}
@@ -0,0 +1,49 @@
+use std::collections::BTreeMap;
+
+use colored::Colorize;
+use miette::{bail, Result};
+
+const EXPRESSION_START: &str = "${{";
+const EXPRESSION_END: &str = "}}";
+
+/// Replaces the `${{ namespace.name }}` expressions in a string
+/// with their value in `values`, which is keyed by `namespace.name`.
+///
+/// Only the expressions of the `namespaces` are replaced. Others,
+/// like the ones of GitHub Actions in a script, are left as they are.
+///
+/// # Errors
+/// Will error if an expression of one of the
+/// `namespaces` doesn't have a value.
+pub fn substitute_expressions(
+    string: &str,
+    namespaces: &[&str],
+    values: &BTreeMap<String, String>,
+) -> Result<String> {
+    let mut substituted = String::with_capacity(string.len());
+    let mut rest = string;
+
+    while let Some(start) = rest.find(EXPRESSION_START) {
+        let Some(end) = rest[start..].find(EXPRESSION_END).map(|end| start + end) else {
+            break;
+        };
+        let expression = rest[start + EXPRESSION_START.len()..end].trim();
+
+        substituted.push_str(&rest[..start]);
+        let is_known = expression
+            .split_once('.')
+            .is_some_and(|(namespace, _)| namespaces.contains(&namespace));
+        if is_known {
+            let Some(value) = values.get(expression) else {
+                bail!("The expression {} doesn't have a value", expression.bold());
+            };
+            substituted.push_str(value);
+        } else {
+            substituted.push_str(&rest[start..end + EXPRESSION_END.len()]);
+        }
+        rest = &rest[end + EXPRESSION_END.len()..];
+    }
+    substituted.push_str(rest);
+
+    Ok(substituted)
+}
@@ -0,0 +1,106 @@
+use std::fmt::Display;
+
+use blue_build_utils::constants::GIT_MODULES_PATH;
+use miette::{bail, Result};
+
+const GIT_SOURCE_PREFIX: &str = "git+";
+
+/// A module source in a git repo.
+///
+/// These are written like
+/// `git+https://github.com/org/modules.git#ref=v1.2.0&path=modules/foo`.
+/// The `ref` defaults to the default branch of the repo and
+/// the `path` defaults to `modules/<module type>`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GitSource<'a> {
+    pub url: &'a str,
+    pub git_ref: Option<&'a str>,
+    pub path: Option<&'a str>,
+}
+
+impl<'a> GitSource<'a> {
+    /// Parses a module `source`.
+    ///
+    /// Returns `None` if the source isn't a git source.
+    ///
+    /// # Errors
+    /// Will error if the source has an empty URL or unknown options.
+    pub fn parse(source: &'a str) -> Result<Option<Self>> {
+        let Some(source) = source.strip_prefix(GIT_SOURCE_PREFIX) else {
+            return Ok(None);
+        };
+        let (url, options) = source.split_once('#').unwrap_or((source, ""));
+
+        if url.is_empty() {
+            bail!("The git module source {GIT_SOURCE_PREFIX}{source} doesn't have a URL");
+        }
+
+        let mut git_source = Self {
+            url,
+            git_ref: None,
+            path: None,
+        };
+
+        for option in options.split('&').filter(|option| !option.is_empty()) {
+            match option.split_once('=') {
+                Some(("ref", git_ref)) if !git_ref.is_empty() => git_source.git_ref = Some(git_ref),
+                Some(("path", path)) if !path.is_empty() => {
+                    git_source.path = Some(path.trim_matches('/'));
+                }
+                _ => bail!(
+                    "Unknown option {option} in git module source {GIT_SOURCE_PREFIX}{source}, expected `ref=` or `path=`"
+                ),
+            }
+        }
+
+        Ok(Some(git_source))
+    }
+
+    /// The path of the module's directory in the repo.
+    #[must_use]
+    pub fn module_path(&self, module_type: &str) -> String {
+        self.path
+            .map_or_else(|| format!("modules/{module_type}"), ToOwned::to_owned)
+    }
+
+    /// The key the module's files are stored under in
+    /// the build context. This includes the `ref`, so it
+    /// changes when the module is pinned to a new commit.
+    #[must_use]
+    pub fn key(&self, module_type: &str) -> String {
+        let repo = self
+            .url
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+
+        format!("{repo}-{module_type}-{}", self.git_ref.unwrap_or("head"))
+            .split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-")
+            .to_lowercase()
+    }
+
+    /// The directory in the build context that
+    /// holds the module's files.
+    #[must_use]
+    pub fn context_dir(&self, module_type: &str) -> String {
+        format!("{GIT_MODULES_PATH}/{}", self.key(module_type))
+    }
+}
+
+impl Display for GitSource<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{GIT_SOURCE_PREFIX}{}", self.url)?;
+
+        match (self.git_ref, self.path) {
+            (Some(git_ref), Some(path)) => write!(f, "#ref={git_ref}&path={path}"),
+            (Some(git_ref), None) => write!(f, "#ref={git_ref}"),
+            (None, Some(path)) => write!(f, "#path={path}"),
+            (None, None) => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+
+/// Commands that `bluebuild build` runs on the host during the
+/// build of the image, like notifying a chat or deploying the image.
+///
+/// The hooks of a recipe only run when the build is given
+/// `--allow-recipe-hooks`. Each command is run with `sh -c` from
+/// the root of the repo, with env vars like `BB_IMAGE` and
+/// `BB_TAGS` that describe the build:
+/// ```yaml
+/// hooks:
+///   pre-build:
+///     - ./scripts/check-mirrors.sh
+///   post-push:
+///     - skopeo copy "docker://$BB_IMAGE@$BB_DIGEST" "docker://mirror.example.com/os:latest"
+///   on-failure:
+///     - ./scripts/page-oncall.sh "$BB_RECIPE"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Hooks {
+    /// Run before the image is built.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pre_build: Vec<String>,
+
+    /// Run after the image is built and tagged.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_build: Vec<String>,
+
+    /// Run after the image is pushed and signed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub post_push: Vec<String>,
+
+    /// Run when the build of the image fails.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub on_failure: Vec<String>,
+}
+
+impl Hooks {
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.pre_build.is_empty()
+            && self.post_build.is_empty()
+            && self.post_push.is_empty()
+            && self.on_failure.is_empty()
+    }
+
+    /// The commands of `hook`.
+    #[must_use]
+    pub fn commands(&self, hook: Hook) -> &[String] {
+        match hook {
+            Hook::PreBuild => &self.pre_build,
+            Hook::PostBuild => &self.post_build,
+            Hook::PostPush => &self.post_push,
+            Hook::OnFailure => &self.on_failure,
+        }
+    }
+
+    /// Adds the commands of `other` after the commands of this.
+    pub fn extend(&mut self, other: Self) {
+        self.pre_build.extend(other.pre_build);
+        self.post_build.extend(other.post_build);
+        self.post_push.extend(other.post_push);
+        self.on_failure.extend(other.on_failure);
+    }
+}
+
+/// A point of the build that commands can be hooked into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hook {
+    PreBuild,
+    PostBuild,
+    PostPush,
+    OnFailure,
+}
+
+impl Display for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::PreBuild => "pre-build",
+            Self::PostBuild => "post-build",
+            Self::PostPush => "post-push",
+            Self::OnFailure => "on-failure",
+        })
+    }
+}
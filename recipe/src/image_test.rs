@@ -0,0 +1,62 @@
+use std::fmt::Display;
+
+use blue_build_utils::string_vec;
+use serde::{Deserialize, Serialize};
+
+/// A check that `bluebuild build` runs in a container
+/// of the built image before the image is pushed.
+///
+/// The build fails if any of the checks fail:
+/// ```yaml
+/// tests:
+///   - file-exists: /usr/bin/htop
+///   - command: htop --version
+///   - service-enabled: tailscaled.service
+///   - package-installed: htop
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged, rename_all_fields = "kebab-case", deny_unknown_fields)]
+pub enum ImageTest {
+    /// A file or directory that must exist.
+    FileExists { file_exists: String },
+
+    /// A command, run with `sh -c`, that must succeed.
+    Command { command: String },
+
+    /// A systemd unit that must be enabled.
+    ServiceEnabled { service_enabled: String },
+
+    /// An RPM package that must be installed.
+    PackageInstalled { package_installed: String },
+}
+
+impl ImageTest {
+    /// The command that runs the check in a container of the image.
+    #[must_use]
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Self::FileExists { file_exists: path } => string_vec!["test", "-e", path],
+            Self::Command { command } => string_vec!["/bin/sh", "-c", command],
+            Self::ServiceEnabled {
+                service_enabled: unit,
+            } => string_vec!["systemctl", "is-enabled", "--quiet", unit],
+            Self::PackageInstalled {
+                package_installed: package,
+            } => string_vec!["rpm", "-q", "--quiet", package],
+        }
+    }
+}
+
+impl Display for ImageTest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (kind, value) = match self {
+            Self::FileExists { file_exists } => ("file-exists", file_exists),
+            Self::Command { command } => ("command", command),
+            Self::ServiceEnabled { service_enabled } => ("service-enabled", service_enabled),
+            Self::PackageInstalled { package_installed } => {
+                ("package-installed", package_installed)
+            }
+        };
+        write!(f, "{kind}: {value}")
+    }
+}
@@ -1,7 +1,18 @@
 pub mod akmods_info;
+pub mod alt_tag;
+pub mod api_version;
+pub mod cache_mount;
+pub mod expression;
+pub mod git_source;
+pub mod hooks;
+pub mod image_test;
 pub mod module;
 pub mod module_ext;
+pub mod mok;
+pub mod on_failure;
 pub mod recipe;
+pub mod remote_include;
+pub mod secret;
 pub mod stage;
 pub mod stages_ext;
 
@@ -11,9 +22,20 @@ use blue_build_utils::constants::{CONFIG_PATH, RECIPE_PATH};
 use log::warn;
 
 pub use akmods_info::*;
+pub use alt_tag::*;
+pub use api_version::*;
+pub use cache_mount::*;
+pub use expression::*;
+pub use git_source::*;
+pub use hooks::*;
+pub use image_test::*;
 pub use module::*;
 pub use module_ext::*;
+pub use mok::*;
+pub use on_failure::*;
 pub use recipe::*;
+pub use remote_include::*;
+pub use secret::*;
 pub use stage::*;
 pub use stages_ext::*;
 
@@ -4,12 +4,16 @@ use blue_build_utils::syntax_highlighting::highlight_ser;
 use bon::Builder;
 use colored::Colorize;
 use indexmap::IndexMap;
-use log::trace;
-use miette::{bail, Result};
+use log::{trace, warn};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
 use serde::{Deserialize, Serialize};
 use serde_yaml::Value;
+use sha2::{Digest, Sha256};
 
-use crate::{base_recipe_path, AkmodsInfo, ModuleExt};
+use crate::{
+    base_recipe_path, AkmodsInfo, CacheMount, GitSource, ModuleExt, OnFailure, RemoteInclude,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone, Builder, Default)]
 pub struct ModuleRequiredFields<'a> {
@@ -25,11 +29,51 @@ pub struct ModuleRequiredFields<'a> {
     #[serde(rename = "no-cache", default, skip_serializing_if = "is_false")]
     pub no_cache: bool,
 
+    /// Directories that are kept between builds, like
+    /// the package manager's cache.
+    #[builder(default)]
+    #[serde(
+        rename = "cache-mounts",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub cache_mounts: Vec<CacheMount<'a>>,
+
+    /// The secrets of the recipe that are exported
+    /// as environment variables for the module.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub secrets: Vec<Cow<'a, str>>,
+
+    /// Retry the module or keep building
+    /// the image when the module fails.
+    #[serde(rename = "on-failure", skip_serializing_if = "Option::is_none")]
+    pub on_failure: Option<OnFailure>,
+
     #[serde(flatten)]
     #[builder(default, into)]
     pub config: IndexMap<String, Value>,
 }
 
+const OCI_SOURCE_PREFIX: &str = "oci://";
+
+/// The name of the stage that holds the modules from `image`.
+///
+/// The name ends with a short hash of the full reference, since
+/// references like `a/b-c` and `a/b/c` are the same once their
+/// special characters are replaced.
+#[must_use]
+pub fn oci_source_stage(image: &str) -> String {
+    let hash = format!("{:x}", Sha256::digest(image.as_bytes()));
+    let image = image
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+        .to_lowercase();
+    format!("stage-module-{image}-{}", &hash[..8])
+}
+
 #[allow(clippy::trivially_copy_pass_by_ref)]
 const fn is_false(b: &bool) -> bool {
     !*b
@@ -80,6 +124,73 @@ impl<'a> ModuleRequiredFields<'a> {
         }
     }
 
+    /// Gets the stage, artifact, and destination of a
+    /// `copy` module that copies an artifact of a stage.
+    #[must_use]
+    #[allow(clippy::missing_const_for_fn)]
+    pub fn get_copy_artifact(&'a self) -> Option<(&'a str, &'a str, &'a str)> {
+        #[cfg(feature = "copy")]
+        {
+            if self.module_type != "copy" {
+                return None;
+            }
+            Some((
+                self.config.get("from-stage")?.as_str()?,
+                self.config.get("artifact")?.as_str()?,
+                self.config.get("dest")?.as_str()?,
+            ))
+        }
+
+        #[cfg(not(feature = "copy"))]
+        {
+            None
+        }
+    }
+
+    /// Whether this is a `files` module that renders
+    /// the expressions in its files before copying them.
+    #[must_use]
+    pub fn is_templated_files(&self) -> bool {
+        self.module_type == "files"
+            && self
+                .config
+                .get("template")
+                .and_then(Value::as_bool)
+                .unwrap_or_default()
+    }
+
+    /// The sources of a `files` module, relative to the files directory.
+    #[must_use]
+    pub fn get_files_sources(&'a self) -> Vec<&'a str> {
+        self.config
+            .get("files")
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_mapping)
+            .flat_map(|file| {
+                file.get("source").and_then(Value::as_str).map_or_else(
+                    // The older format maps each source to its destination
+                    || file.keys().filter_map(Value::as_str).collect(),
+                    |source| vec![source],
+                )
+            })
+            .collect()
+    }
+
+    /// The number of times to run the module again after it fails.
+    #[must_use]
+    pub fn retries(&self) -> u8 {
+        self.on_failure.map_or(0, |on_failure| on_failure.retry)
+    }
+
+    /// Whether to keep building the image if the module fails.
+    #[must_use]
+    pub fn continue_on_failure(&self) -> bool {
+        self.on_failure
+            .is_some_and(|on_failure| on_failure.continue_build)
+    }
+
     #[must_use]
     pub fn get_non_local_source(&'a self) -> Option<&'a str> {
         let source = self.source.as_deref()?;
@@ -91,6 +202,37 @@ impl<'a> ModuleRequiredFields<'a> {
         }
     }
 
+    /// Gets the image of a module that is
+    /// sourced from an OCI artifact.
+    ///
+    /// These are set with `source: oci://registry/org/module:tag`.
+    #[must_use]
+    pub fn get_oci_source(&'a self) -> Option<&'a str> {
+        self.source.as_deref()?.strip_prefix(OCI_SOURCE_PREFIX)
+    }
+
+    /// Gets the git repo of a module that is sourced
+    /// from git, like `source: git+https://...#ref=v1.2.0`.
+    #[must_use]
+    pub fn get_git_source(&'a self) -> Option<GitSource<'a>> {
+        GitSource::parse(self.source.as_deref()?).ok().flatten()
+    }
+
+    /// The name of the stage that holds the
+    /// modules of a git sourced module.
+    #[must_use]
+    pub fn get_git_source_stage(&'a self) -> Option<String> {
+        self.get_git_source()
+            .map(|source| format!("stage-module-{}", source.key(&self.module_type)))
+    }
+
+    /// The name of the stage that holds the
+    /// modules of an OCI sourced module.
+    #[must_use]
+    pub fn get_oci_source_stage(&'a self) -> Option<String> {
+        self.get_oci_source().map(oci_source_stage)
+    }
+
     #[must_use]
     pub fn generate_akmods_info(&'a self, os_version: &u64) -> AkmodsInfo {
         #[derive(Debug, Default, Copy, Clone)]
@@ -200,56 +342,120 @@ impl Module<'_> {
         let traversed_files = traversed_files.unwrap_or_default();
 
         for module in modules {
-            found_modules.extend(
-                match &module {
-                    Module {
-                        required_fields: Some(_),
-                        from_file: None,
-                    } => vec![module.clone()],
-                    Module {
-                        required_fields: None,
-                        from_file: Some(file_name),
-                    } => {
-                        let file_name = PathBuf::from(file_name.as_ref());
-                        if traversed_files.contains(&file_name) {
+            found_modules.extend(match &module {
+                Module {
+                    required_fields: Some(required_fields),
+                    from_file: None,
+                } => {
+                    if let Some(mount) = required_fields
+                        .cache_mounts
+                        .iter()
+                        .find(|mount| !mount.path().starts_with('/'))
+                    {
+                        bail!(
+                            "The cache mount {} for module {} must be an absolute path",
+                            mount.path().bold(),
+                            required_fields.module_type.bold(),
+                        );
+                    }
+                    if matches!(
+                        &*required_fields.module_type,
+                        "containerfile" | "copy" | "package"
+                    ) {
+                        if let Some(property) = [
+                            (required_fields.on_failure.is_some(), "on-failure"),
+                            (!required_fields.secrets.is_empty(), "secrets"),
+                        ]
+                        .into_iter()
+                        .find_map(|(set, property)| set.then_some(property))
+                        {
                             bail!(
-                                "{} File {} has already been parsed:\n{traversed_files:?}",
-                                "Circular dependency detected!".bright_red(),
-                                file_name.display().to_string().bold(),
+                                "The {} module doesn't support {} since it's built into the Containerfile",
+                                required_fields.module_type.bold(),
+                                property.bold(),
                             );
                         }
-
-                        let mut traversed_files = traversed_files.clone();
-                        traversed_files.push(file_name.clone());
-
-                        Self::get_modules(
-                            &ModuleExt::try_from(&file_name)?.modules,
-                            Some(traversed_files),
-                        )?
                     }
-                    _ => {
-                        let from_example = Self::builder().from_file("test.yml").build();
-                        let module_example = Self::example();
-
+                    if let Some(source) = required_fields.source.as_deref() {
+                        GitSource::parse(source).with_context(|| {
+                            format!(
+                                "The source of module {} isn't a valid git source",
+                                required_fields.module_type.bold()
+                            )
+                        })?;
+                    }
+                    if let Some(image) = required_fields.get_oci_source() {
+                        image
+                            .parse::<Reference>()
+                            .into_diagnostic()
+                            .with_context(|| {
+                                format!(
+                                    "The source of module {} isn't a valid image reference",
+                                    required_fields.module_type.bold()
+                                )
+                            })?;
+                    }
+                    vec![module.clone()]
+                }
+                Module {
+                    required_fields: None,
+                    from_file: Some(file_name),
+                } => {
+                    let file_name = if RemoteInclude::is_remote(file_name) {
+                        RemoteInclude::parse(file_name)?.fetch()?
+                    } else {
+                        PathBuf::from(file_name.as_ref())
+                    };
+                    if traversed_files.contains(&file_name) {
                         bail!(
-                            "Improper format for module. Must be in the format like:\n{}\n{}\n\n{}",
-                            highlight_ser(&module_example, "yaml", None)?,
-                            "or".bold(),
-                            highlight_ser(&from_example, "yaml", None)?
+                            "{} File {} has already been parsed:\n{traversed_files:?}",
+                            "Circular dependency detected!".bright_red(),
+                            file_name.display().to_string().bold(),
                         );
                     }
+
+                    let mut traversed_files = traversed_files.clone();
+                    traversed_files.push(file_name.clone());
+
+                    Self::get_modules(
+                        &ModuleExt::try_from(&file_name)?.modules,
+                        Some(traversed_files),
+                    )?
+                }
+                _ => {
+                    let from_example = Self::builder().from_file("test.yml").build();
+                    let module_example = Self::example();
+
+                    bail!(
+                        "Improper format for module. Must be in the format like:\n{}\n{}\n\n{}",
+                        highlight_ser(&module_example, "yaml", None)?,
+                        "or".bold(),
+                        highlight_ser(&from_example, "yaml", None)?
+                    );
                 }
-                .into_iter(),
-            );
+            });
         }
         Ok(found_modules)
     }
 
+    /// The path of the `from-file` of the module.
+    ///
+    /// A remote `from-file` is fetched into the
+    /// cache and the path of the cached file is used.
     #[must_use]
     pub fn get_from_file_path(&self) -> Option<PathBuf> {
-        self.from_file
-            .as_ref()
-            .map(|path| base_recipe_path().join(&**path))
+        let path = self.from_file.as_deref()?;
+
+        if RemoteInclude::is_remote(path) {
+            let remote = RemoteInclude::parse(path)
+                .inspect_err(|e| warn!("{e:?}"))
+                .ok()?;
+            return Some(remote.fetch().unwrap_or_else(|e| {
+                warn!("{e:?}");
+                remote.cache_path().unwrap_or_default()
+            }));
+        }
+        Some(base_recipe_path().join(path))
     }
 
     #[must_use]
@@ -0,0 +1,31 @@
+use std::borrow::Cow;
+
+use bon::Builder;
+use serde::{Deserialize, Serialize};
+
+/// Signs the kernel modules that the `akmods` modules install
+/// with a Machine Owner Key (MOK), so that they can be loaded
+/// with Secure Boot enabled.
+///
+/// The private key is read from a secret of the recipe and the
+/// public certificate is copied into the image, where
+/// `bluebuild mok enroll` can enroll it on the machine:
+/// ```yaml
+/// secrets:
+///   - name: MOK_KEY
+///     file: ./keys/mok.priv
+/// mok:
+///   key: MOK_KEY
+///   cert: files/mok/public_key.der
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Builder, PartialEq, Eq)]
+pub struct MokSigning<'a> {
+    /// The name of the secret that holds the private key in PEM format.
+    #[builder(into)]
+    pub key: Cow<'a, str>,
+
+    /// The path of the public certificate in DER
+    /// format, relative to the root of the repo.
+    #[builder(into)]
+    pub cert: Cow<'a, str>,
+}
@@ -0,0 +1,36 @@
+use serde::{Deserialize, Serialize};
+
+/// What to do when a module fails.
+///
+/// This is useful for modules that depend on the network,
+/// like installing flatpaks or adding COPR repos:
+/// ```yaml
+/// on-failure:
+///   retry: 2
+///   continue: true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct OnFailure {
+    /// The number of times to run the module again after it fails.
+    #[serde(default, skip_serializing_if = "is_zero")]
+    pub retry: u8,
+
+    /// Keep building the image if the module still
+    /// fails after all of its retries.
+    #[serde(rename = "continue", default, skip_serializing_if = "is_false")]
+    pub continue_build: bool,
+}
+
+/// The number of seconds to wait before running a module again.
+pub const RETRY_DELAY_SECS: u8 = 10;
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_zero(n: &u8) -> bool {
+    *n == 0
+}
+
+#[allow(clippy::trivially_copy_pass_by_ref)]
+const fn is_false(b: &bool) -> bool {
+    !*b
+}
@@ -1,12 +1,28 @@
-use std::{borrow::Cow, fs, path::Path};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
 
 use bon::Builder;
-use log::{debug, trace};
-use miette::{Context, IntoDiagnostic, Result};
+use colored::Colorize;
+use log::{debug, trace, warn};
+use miette::{bail, Context, IntoDiagnostic, Result};
 use oci_distribution::Reference;
 use serde::{Deserialize, Serialize};
+use serde_yaml::Value;
 
-use crate::{Module, ModuleExt, StagesExt};
+use crate::{
+    api_version, is_valid_secret_name, AltTag, ApiVersion, Hooks, ImageTest, Module, ModuleExt,
+    ModuleRequiredFields, MokSigning, RecipeSecret, StageArtifact, StagesExt,
+    STAGE_PLATFORM_ARCHES,
+};
+
+/// The v1 recipes that were warned about, which
+/// are parsed more than once during a build.
+static WARNED_V1: Mutex<BTreeSet<PathBuf>> = Mutex::new(BTreeSet::new());
 
 /// The build recipe.
 ///
@@ -16,6 +32,14 @@ use crate::{Module, ModuleExt, StagesExt};
 /// and tagging the image appropriately.
 #[derive(Default, Serialize, Clone, Deserialize, Debug, Builder)]
 pub struct Recipe<'a> {
+    /// The version of the format that the recipe was written in.
+    ///
+    /// Every version is deserialized into the same recipe,
+    /// so this is only used to warn about deprecated versions.
+    #[serde(skip)]
+    #[builder(default)]
+    pub api_version: ApiVersion,
+
     /// The name of the user's image.
     ///
     /// This will be set on the `org.opencontainers.image.title` label.
@@ -50,9 +74,50 @@ pub struct Recipe<'a> {
     /// timestamp with no version (e.g. `20240429`).
     ///
     /// Any user input will override the `latest` and timestamp tags.
+    /// A tag can have conditions, in which case it's only added
+    /// to the builds that match them.
     #[serde(alias = "alt-tags", skip_serializing_if = "Option::is_none")]
     #[builder(into)]
-    pub alt_tags: Option<Vec<String>>,
+    pub alt_tags: Option<Vec<AltTag<'a>>>,
+
+    /// Variables that are used in the recipe and in the
+    /// files of the files modules that are templated.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[builder(default)]
+    pub vars: BTreeMap<String, String>,
+
+    /// Secrets that modules can use during the build.
+    ///
+    /// A module only gets the secrets that it lists in its `secrets`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub secrets: Vec<RecipeSecret<'a>>,
+
+    /// Labels to set on the image.
+    ///
+    /// These override the default labels, like
+    /// `org.opencontainers.image.vendor`. A label
+    /// set to an empty string is removed.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    #[builder(default)]
+    pub labels: BTreeMap<String, String>,
+
+    /// Signs the kernel modules that the
+    /// akmods modules install with a MOK.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mok: Option<MokSigning<'a>>,
+
+    /// Commands to run on the host during the build of
+    /// the image. These run after the hooks in the config files.
+    #[serde(default, skip_serializing_if = "Hooks::is_empty")]
+    #[builder(default)]
+    pub hooks: Hooks,
+
+    /// Checks that are run in a container of the
+    /// built image before the image is pushed.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    #[builder(default)]
+    pub tests: Vec<ImageTest>,
 
     /// The stages extension of the recipe.
     ///
@@ -69,7 +134,30 @@ pub struct Recipe<'a> {
     pub modules_ext: ModuleExt<'a>,
 }
 
-impl<'a> Recipe<'a> {
+impl Recipe<'_> {
+    /// Deserializes a recipe of any version without
+    /// reading the files that its modules reference.
+    ///
+    /// # Errors
+    /// Errors when the yaml can't be deserialized into a recipe.
+    pub fn from_yaml(file: &str) -> Result<Self> {
+        let value = serde_yaml::from_str::<Value>(file)
+            .map_err(blue_build_utils::serde_yaml_err(file))
+            .into_diagnostic()?;
+
+        match ApiVersion::of(&value)? {
+            ApiVersion::V1 => serde_yaml::from_str::<Self>(file)
+                .map_err(blue_build_utils::serde_yaml_err(file))
+                .into_diagnostic(),
+            ApiVersion::V2 => {
+                let mut recipe = serde_yaml::from_value::<Self>(api_version::from_v2(value)?)
+                    .into_diagnostic()?;
+                recipe.api_version = ApiVersion::V2;
+                Ok(recipe)
+            }
+        }
+    }
+
     /// Parse a recipe file
     ///
     /// # Errors
@@ -92,9 +180,19 @@ impl<'a> Recipe<'a> {
 
         debug!("Recipe contents: {file}");
 
-        let mut recipe = serde_yaml::from_str::<Recipe>(&file)
-            .map_err(blue_build_utils::serde_yaml_err(&file))
-            .into_diagnostic()?;
+        let mut recipe = Self::from_yaml(&file)?;
+        if recipe.api_version.is_v1()
+            && WARNED_V1
+                .lock()
+                .is_ok_and(|mut warned| warned.insert(file_path.clone()))
+        {
+            warn!(
+                "The recipe {} uses the deprecated v1 format, run `bluebuild migrate {}` to convert it to {}",
+                path.as_ref().display(),
+                path.as_ref().display(),
+                ApiVersion::LATEST
+            );
+        }
 
         recipe.modules_ext.modules = Module::get_modules(&recipe.modules_ext.modules, None)?;
 
@@ -108,9 +206,302 @@ impl<'a> Recipe<'a> {
             recipe.stages_ext = None;
         }
 
+        recipe.check_artifacts()?;
+        recipe.check_stage_platforms()?;
+        recipe.check_secrets()?;
+
         Ok(recipe)
     }
 
+    /// Gets the modules of the recipe and its stages.
+    pub fn all_modules(&self) -> impl Iterator<Item = &ModuleRequiredFields<'_>> {
+        self.modules_ext
+            .modules
+            .iter()
+            .chain(
+                self.stages_ext
+                    .iter()
+                    .flat_map(|stages_ext| &stages_ext.stages)
+                    .filter_map(|stage| stage.required_fields.as_ref())
+                    .flat_map(|stage| &stage.modules_ext.modules),
+            )
+            .filter_map(|module| module.required_fields.as_ref())
+    }
+
+    /// Whether a module of the recipe or its stages
+    /// renders the expressions in its files.
+    #[must_use]
+    pub fn has_templated_files(&self) -> bool {
+        self.all_modules()
+            .any(ModuleRequiredFields::is_templated_files)
+    }
+
+    /// Gets the images of all the OCI sourced modules
+    /// in the recipe and its stages without duplicates.
+    #[must_use]
+    pub fn get_oci_module_sources(&self) -> Vec<&str> {
+        let mut sources: Vec<&str> = Vec::new();
+
+        for source in self
+            .all_modules()
+            .filter_map(ModuleRequiredFields::get_oci_source)
+        {
+            if !sources.contains(&source) {
+                sources.push(source);
+            }
+        }
+
+        sources
+    }
+
+    /// Gets the stage name and build context directory
+    /// of all the git sourced modules without duplicates.
+    #[must_use]
+    pub fn get_git_module_stages(&self) -> Vec<(String, String)> {
+        let mut stages: Vec<(String, String)> = Vec::new();
+
+        for module in self.all_modules() {
+            let (Some(source), Some(stage)) =
+                (module.get_git_source(), module.get_git_source_stage())
+            else {
+                continue;
+            };
+            if !stages.iter().any(|(s, _)| *s == stage) {
+                stages.push((stage, source.context_dir(&module.module_type)));
+            }
+        }
+
+        stages
+    }
+
+    /// Gets the artifact `name` of the stage `stage`.
+    #[must_use]
+    pub fn get_stage_artifact<'b>(&'b self, stage: &str, name: &str) -> Option<StageArtifact<'b>> {
+        self.stages_ext
+            .iter()
+            .flat_map(|stages_ext| &stages_ext.stages)
+            .filter_map(|s| s.required_fields.as_ref())
+            .find(|s| s.name == stage)
+            .and_then(|s| {
+                let (name, path) = s.artifacts.get_key_value(name)?;
+                Some(StageArtifact {
+                    stage: &s.name,
+                    name,
+                    path,
+                    verify: s.from != "scratch",
+                })
+            })
+    }
+
+    /// Whether any stage has a base image for a specific platform.
+    #[must_use]
+    pub fn has_platform_stages(&self) -> bool {
+        self.stages_ext
+            .iter()
+            .flat_map(|stages_ext| &stages_ext.stages)
+            .filter_map(|stage| stage.required_fields.as_ref())
+            .any(|stage| !stage.platform_from.is_empty())
+    }
+
+    /// Checks that the platform base images of
+    /// the stages are for known architectures.
+    ///
+    /// # Errors
+    /// Will error if an architecture is unknown or
+    /// a `scratch` stage has platform base images.
+    pub fn check_stage_platforms(&self) -> Result<()> {
+        let stages = self
+            .stages_ext
+            .iter()
+            .flat_map(|stages_ext| &stages_ext.stages)
+            .filter_map(|stage| stage.required_fields.as_ref());
+
+        for stage in stages {
+            for (arch, from) in &stage.platform_from {
+                if !STAGE_PLATFORM_ARCHES.contains(&arch.as_str()) {
+                    bail!(
+                        "The platform {} in stage {} isn't supported, expected one of {}",
+                        arch.bold(),
+                        stage.name.bold(),
+                        STAGE_PLATFORM_ARCHES.join(", "),
+                    );
+                }
+                if stage.from == "scratch" || from == "scratch" {
+                    bail!(
+                        "The stage {} can't use {} with {}",
+                        stage.name.bold(),
+                        "scratch".bold(),
+                        "platform-from".bold(),
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the stage artifacts are valid and that every
+    /// artifact copied with `from-stage:` is declared by its stage.
+    ///
+    /// # Errors
+    /// Will error if an artifact has an invalid name or path,
+    /// or a module copies an artifact that doesn't exist.
+    pub fn check_artifacts(&self) -> Result<()> {
+        let stages = self
+            .stages_ext
+            .iter()
+            .flat_map(|stages_ext| &stages_ext.stages)
+            .filter_map(|stage| stage.required_fields.as_ref());
+
+        for stage in stages.clone() {
+            for (name, path) in &stage.artifacts {
+                if name.is_empty()
+                    || !name
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+                {
+                    bail!(
+                        "The artifact name {} in stage {} can only contain letters, numbers, '-', '_', and '.'",
+                        name.bold(),
+                        stage.name.bold(),
+                    );
+                }
+                if !path.starts_with('/') {
+                    bail!(
+                        "The artifact {} in stage {} must be an absolute path",
+                        name.bold(),
+                        stage.name.bold(),
+                    );
+                }
+            }
+        }
+
+        for module in self.all_modules() {
+            let Some((stage, name, _)) = module.get_copy_artifact() else {
+                continue;
+            };
+
+            if self.get_stage_artifact(stage, name).is_none() {
+                let declared = stages.clone().find(|s| s.name == stage).map(|s| {
+                    s.artifacts
+                        .keys()
+                        .map(String::as_str)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                });
+
+                match declared {
+                    None => bail!(
+                        "The copy module copies artifact {} from stage {}, which doesn't exist",
+                        name.bold(),
+                        stage.bold(),
+                    ),
+                    Some(declared) => bail!(
+                        help = format!("Stage {stage} declares the artifacts: {declared}"),
+                        "The copy module copies artifact {} from stage {}, which doesn't declare it",
+                        name.bold(),
+                        stage.bold(),
+                    ),
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks that the secrets are valid and that every secret
+    /// used by a module or the MOK is declared in the recipe.
+    ///
+    /// # Errors
+    /// Will error if a secret has an invalid name, is declared more
+    /// than once, or a module or the MOK uses a secret that isn't declared.
+    pub fn check_secrets(&self) -> Result<()> {
+        for (index, secret) in self.secrets.iter().enumerate() {
+            let name = secret.name();
+
+            if !is_valid_secret_name(name) {
+                bail!(
+                    "The secret name {} can only contain letters, numbers, and '_', and can't start with a number",
+                    name.bold(),
+                );
+            }
+            if secret.source().is_none() {
+                bail!(
+                    "The secret {} can't be read from both an {} and a {}",
+                    name.bold(),
+                    "env".bold(),
+                    "file".bold(),
+                );
+            }
+            if self.secrets[..index].iter().any(|s| s.name() == name) {
+                bail!("The secret {} is declared more than once", name.bold());
+            }
+        }
+
+        for module in self.all_modules() {
+            if let Some(secret) = module
+                .secrets
+                .iter()
+                .find(|secret| !self.secrets.iter().any(|s| s.name() == *secret))
+            {
+                bail!(
+                    help = format!(
+                        "Declare the secret in the recipe's secrets:\n\nsecrets:\n  - {secret}"
+                    ),
+                    "The module {} uses the secret {}, which isn't declared in the recipe",
+                    module.module_type.bold(),
+                    secret.bold(),
+                );
+            }
+        }
+
+        if let Some(mok) = self.mok.as_ref() {
+            if !self.secrets.iter().any(|s| s.name() == mok.key) {
+                bail!(
+                    help = format!(
+                        "Declare the secret in the recipe's secrets:\n\nsecrets:\n  - {}",
+                        mok.key
+                    ),
+                    "The MOK key {} isn't declared in the recipe's secrets",
+                    mok.key.bold(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The alt tags that are added to the build of the
+    /// `branch`, if it's known, for the `platform`.
+    ///
+    /// Returns `None` when no alt tags match, so that the
+    /// default `latest` and timestamp tags are used instead.
+    #[must_use]
+    pub fn matching_alt_tags(&self, branch: Option<&str>, platform: &str) -> Option<Vec<String>> {
+        let tags = self
+            .alt_tags
+            .as_ref()?
+            .iter()
+            .filter(|alt_tag| alt_tag.matches(branch, platform))
+            .map(|alt_tag| alt_tag.tag().to_owned())
+            .collect::<Vec<_>>();
+        trace!("matching_alt_tags({branch:?}, {platform}): {tags:?}");
+
+        (!tags.is_empty()).then_some(tags)
+    }
+
+    /// Whether the recipe signs the kernel modules of
+    /// its akmods modules, which needs the MOK key.
+    #[must_use]
+    pub fn signs_akmods(&self) -> bool {
+        self.mok.is_some()
+            && self
+                .modules_ext
+                .modules
+                .iter()
+                .filter_map(|module| module.required_fields.as_ref())
+                .any(|module| module.module_type == "akmods")
+    }
+
     /// Get a `Reference` object of the `base_image`.
     ///
     /// # Errors
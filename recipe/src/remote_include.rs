@@ -0,0 +1,233 @@
+use std::{
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_utils::credentials::Credentials;
+use colored::Colorize;
+use log::{debug, info, trace};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use oci_distribution::{client::ClientConfig, secrets::RegistryAuth, Client, Reference};
+use sha2::{Digest, Sha256};
+
+const HTTPS_PREFIX: &str = "https://";
+const OCI_PREFIX: &str = "oci://";
+const SHA256_FRAGMENT: &str = "sha256=";
+
+/// The annotation that ORAS sets to the file name of a layer.
+const TITLE_ANNOTATION: &str = "org.opencontainers.image.title";
+
+/// A `from-file` that is fetched instead of read from the repo,
+/// so that a module list can be shared between repos.
+///
+/// The checksum of the file is required so that a changed file
+/// can't change the build without the recipe changing:
+/// ```yaml
+/// modules:
+///   - from-file: https://example.com/common-modules.yml#sha256=<digest>
+///   - from-file: oci://ghcr.io/octocat/common-modules:v1#sha256=<digest>
+/// ```
+/// An `oci://` include is an artifact with the file as its layer,
+/// like the ones pushed with `oras push`.
+///
+/// Fetched files are cached by their checksum, so a build
+/// only needs the network the first time a file is used.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteInclude<'a> {
+    pub location: RemoteLocation<'a>,
+    pub sha256: &'a str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RemoteLocation<'a> {
+    Https(&'a str),
+    Oci(&'a str),
+}
+
+impl<'a> RemoteInclude<'a> {
+    /// Whether the `from-file` is fetched instead of read from the repo.
+    #[must_use]
+    pub fn is_remote(from_file: &str) -> bool {
+        from_file.starts_with(HTTPS_PREFIX) || from_file.starts_with(OCI_PREFIX)
+    }
+
+    /// Parses a remote `from-file`.
+    ///
+    /// # Errors
+    /// Will error if it isn't remote or doesn't have a valid checksum.
+    pub fn parse(from_file: &'a str) -> Result<Self> {
+        let (location, fragment) = from_file.split_once('#').unwrap_or((from_file, ""));
+
+        let location = if location.starts_with(HTTPS_PREFIX) {
+            RemoteLocation::Https(location)
+        } else if let Some(image) = location.strip_prefix(OCI_PREFIX) {
+            RemoteLocation::Oci(image)
+        } else {
+            bail!(
+                "The from-file {} must start with {HTTPS_PREFIX} or {OCI_PREFIX}",
+                from_file.bold()
+            );
+        };
+
+        let Some(sha256) = fragment.strip_prefix(SHA256_FRAGMENT) else {
+            bail!(
+                help = format!("Add the checksum like {location}#sha256=<digest>"),
+                "The remote from-file {} doesn't have a checksum",
+                from_file.bold()
+            );
+        };
+        if sha256.len() != 64 || !sha256.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!(
+                "The checksum {} of the remote from-file {location} isn't a sha256 digest",
+                sha256.bold()
+            );
+        }
+
+        Ok(Self { location, sha256 })
+    }
+
+    /// The path that the file is cached at.
+    ///
+    /// # Errors
+    /// Will error if there isn't a cache directory.
+    pub fn cache_path(&self) -> Result<PathBuf> {
+        Ok(blue_build_utils::cache_dir()
+            .ok_or_else(|| miette!("Unable to find a cache directory for remote from-files"))?
+            .join("includes")
+            .join(format!("{}.yml", self.sha256.to_lowercase())))
+    }
+
+    /// Fetches the file unless it's cached and returns the path of the
+    /// cached file. The checksum is verified before the file is cached.
+    ///
+    /// # Errors
+    /// Will error if the file can't be fetched or doesn't match its checksum.
+    pub fn fetch(&self) -> Result<PathBuf> {
+        trace!("RemoteInclude::fetch({self})");
+
+        let path = self.cache_path()?;
+        if path.is_file() {
+            debug!("Using the cached from-file {} for {self}", path.display());
+            return Ok(path);
+        }
+
+        info!("Fetching the from-file {self}");
+        let contents = match self.location {
+            RemoteLocation::Https(url) => fetch_https(url),
+            RemoteLocation::Oci(image) => fetch_oci(image),
+        }
+        .with_context(|| format!("Failed to fetch the from-file {self}"))?;
+
+        let digest = format!("{:x}", Sha256::digest(&contents));
+        if !digest.eq_ignore_ascii_case(self.sha256) {
+            bail!(
+                help = "Update the checksum if the file was changed on purpose",
+                "The from-file {} has the checksum {digest}, but {} was expected",
+                self.location,
+                self.sha256
+            );
+        }
+
+        write_cached(&path, &contents)?;
+        Ok(path)
+    }
+}
+
+impl Display for RemoteInclude<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}#{SHA256_FRAGMENT}{}", self.location, self.sha256)
+    }
+}
+
+impl Display for RemoteLocation<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Https(url) => f.write_str(url),
+            Self::Oci(image) => write!(f, "{OCI_PREFIX}{image}"),
+        }
+    }
+}
+
+fn fetch_https(url: &str) -> Result<Vec<u8>> {
+    let response = reqwest::blocking::get(url)
+        .and_then(reqwest::blocking::Response::error_for_status)
+        .into_diagnostic()?;
+    Ok(response.bytes().into_diagnostic()?.to_vec())
+}
+
+fn fetch_oci(image: &str) -> Result<Vec<u8>> {
+    let image: Reference = image.parse().into_diagnostic()?;
+
+    // The recipe can be parsed from inside of an async runtime,
+    // so the artifact is pulled on its own thread and runtime
+    std::thread::scope(|scope| {
+        scope
+            .spawn(|| {
+                tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                    .into_diagnostic()?
+                    .block_on(pull_artifact(&image))
+            })
+            .join()
+            .map_err(|_| miette!("The thread that pulls {image} panicked"))?
+    })
+}
+
+async fn pull_artifact(image: &Reference) -> Result<Vec<u8>> {
+    let client = Client::new(ClientConfig::default());
+    let auth = Credentials::for_registry(image.registry())
+        .map_or(RegistryAuth::Anonymous, |creds| {
+            RegistryAuth::Basic(creds.username, creds.password)
+        });
+
+    let (manifest, _) = client
+        .pull_image_manifest(image, &auth)
+        .await
+        .into_diagnostic()?;
+
+    let is_yaml = |title: &String| {
+        Path::new(title)
+            .extension()
+            .is_some_and(|ext| ext == "yml" || ext == "yaml")
+    };
+    let layer = match &*manifest.layers {
+        [layer] => layer,
+        layers => layers
+            .iter()
+            .find(|layer| {
+                layer
+                    .annotations
+                    .as_ref()
+                    .and_then(|annotations| annotations.get(TITLE_ANNOTATION))
+                    .is_some_and(is_yaml)
+            })
+            .ok_or_else(|| miette!("The artifact {image} doesn't have a layer with a YAML file"))?,
+    };
+
+    let mut contents = Vec::new();
+    client
+        .pull_blob(image, layer, &mut contents)
+        .await
+        .into_diagnostic()?;
+    Ok(contents)
+}
+
+fn write_cached(path: &Path, contents: &[u8]) -> Result<()> {
+    let dir = path.parent().unwrap_or_else(|| Path::new("."));
+    fs::create_dir_all(dir).into_diagnostic()?;
+
+    // Written to a temporary file first so that a
+    // build running at the same time never reads
+    // a file that is only partly written
+    let temp_path = dir.join(format!(
+        ".{}.tmp-{}",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id()
+    ));
+    fs::write(&temp_path, contents).into_diagnostic()?;
+    fs::rename(&temp_path, path)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to cache the from-file at {}", path.display()))
+}
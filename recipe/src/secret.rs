@@ -0,0 +1,99 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+
+/// A secret that modules can use during the build.
+///
+/// Secrets are mounted into the `RUN` of each module that lists
+/// them in its `secrets`, and are exported as environment variables
+/// for that module only. They never end up in a layer of the image.
+///
+/// This can either be just the name, which reads the secret
+/// from the environment variable of the same name:
+/// ```yaml
+/// secrets:
+///   - GITHUB_TOKEN
+/// ```
+/// or a map that reads the secret from another
+/// environment variable or a file:
+/// ```yaml
+/// secrets:
+///   - name: COPR_TOKEN
+///     env: MY_COPR_TOKEN
+///   - name: SIGNING_KEY
+///     file: ./keys/signing.key
+/// ```
+///
+/// The environment variables have to be allowed with
+/// `--allow-secret-env` and the files have to be in the project.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum RecipeSecret<'a> {
+    Name(Cow<'a, str>),
+    Full {
+        name: Cow<'a, str>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        env: Option<Cow<'a, str>>,
+
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        file: Option<Cow<'a, str>>,
+    },
+}
+
+/// Where the value of a secret is read from when building.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecipeSecretSource<'a> {
+    Env(&'a str),
+    File(&'a str),
+}
+
+impl RecipeSecret<'_> {
+    /// The name of the secret, which is also the name of the
+    /// environment variable that modules read it from.
+    #[must_use]
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Name(name) | Self::Full { name, .. } => name,
+        }
+    }
+
+    /// Where the value of the secret is read from.
+    ///
+    /// Returns `None` if both an `env` and a `file` are set.
+    #[must_use]
+    pub fn source(&self) -> Option<RecipeSecretSource<'_>> {
+        match self {
+            Self::Name(name)
+            | Self::Full {
+                name,
+                env: None,
+                file: None,
+            } => Some(RecipeSecretSource::Env(name)),
+            Self::Full {
+                env: Some(env),
+                file: None,
+                ..
+            } => Some(RecipeSecretSource::Env(env)),
+            Self::Full {
+                env: None,
+                file: Some(file),
+                ..
+            } => Some(RecipeSecretSource::File(file)),
+            Self::Full {
+                env: Some(_),
+                file: Some(_),
+                ..
+            } => None,
+        }
+    }
+}
+
+/// Whether `name` can be used as the name of an environment variable.
+#[must_use]
+pub fn is_valid_secret_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
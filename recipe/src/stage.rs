@@ -3,11 +3,16 @@ use std::{borrow::Cow, path::PathBuf};
 use blue_build_utils::syntax_highlighting::highlight_ser;
 use bon::Builder;
 use colored::Colorize;
+use indexmap::IndexMap;
 use miette::{bail, Result};
 use serde::{Deserialize, Serialize};
 
 use crate::{base_recipe_path, Module, ModuleExt, StagesExt};
 
+/// The architectures that a stage can have its own base
+/// image for, named like the `TARGETARCH` build arg.
+pub const STAGE_PLATFORM_ARCHES: [&str; 2] = ["amd64", "arm64"];
+
 /// Contains the required fields for a stage.
 #[derive(Serialize, Deserialize, Debug, Clone, Builder)]
 pub struct StageRequiredFields<'a> {
@@ -24,16 +29,95 @@ pub struct StageRequiredFields<'a> {
     #[builder(into)]
     pub from: Cow<'a, str>,
 
+    /// Base images of the stage for specific platforms,
+    /// keyed by their architecture like `amd64` or `arm64`.
+    ///
+    /// The image is picked with the `TARGETARCH` build arg
+    /// during the build. Platforms that aren't listed use `from`.
+    #[builder(default)]
+    #[serde(
+        default,
+        rename = "platform-from",
+        skip_serializing_if = "IndexMap::is_empty"
+    )]
+    pub platform_from: IndexMap<String, String>,
+
     /// The shell to use in the stage.
     #[builder(into)]
     #[serde(skip_serializing_if = "Option::is_none")]
     pub shell: Option<Vec<String>>,
 
+    /// Named paths in the stage that later modules can
+    /// copy with the `copy` module's `from-stage:` property.
+    ///
+    /// The digests of the artifacts are recorded when the
+    /// stage is built and checked again after they're copied.
+    #[builder(default)]
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub artifacts: IndexMap<String, String>,
+
     /// The modules extension for the stage
     #[serde(flatten)]
     pub modules_ext: ModuleExt<'a>,
 }
 
+impl StageRequiredFields<'_> {
+    /// The base image of the stage for each architecture, or
+    /// `None` if the stage uses `from` for every platform.
+    #[must_use]
+    pub fn platform_bases(&self) -> Option<Vec<(&str, &str)>> {
+        if self.platform_from.is_empty() {
+            return None;
+        }
+
+        Some(
+            STAGE_PLATFORM_ARCHES
+                .iter()
+                .map(|&arch| {
+                    (
+                        arch,
+                        self.platform_from
+                            .get(arch)
+                            .map_or(&*self.from, String::as_str),
+                    )
+                })
+                .collect(),
+        )
+    }
+}
+
+/// An artifact of a stage that a module copies.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StageArtifact<'a> {
+    /// The name of the stage the artifact is in.
+    pub stage: &'a str,
+
+    /// The name of the artifact.
+    pub name: &'a str,
+
+    /// The path of the artifact in the stage.
+    pub path: &'a str,
+
+    /// Whether the digest of the artifact was recorded,
+    /// which can't be done in `scratch` stages.
+    pub verify: bool,
+}
+
+impl StageArtifact<'_> {
+    /// The path a file artifact ends up at when
+    /// it's copied to `dest`, following the rules
+    /// of the `COPY` instruction.
+    #[must_use]
+    pub fn file_dest(&self, dest: &str) -> String {
+        if dest.ends_with('/') {
+            let file_name = self.path.rsplit('/').next().unwrap_or_default();
+            format!("{dest}{file_name}")
+        } else {
+            dest.to_owned()
+        }
+    }
+}
+
 /// Corresponds to a stage in a Containerfile
 ///
 /// A stage has its own list of modules to run which
@@ -98,43 +182,40 @@ impl Stage<'_> {
         let traversed_files = traversed_files.unwrap_or_default();
 
         for stage in stages {
-            found_stages.extend(
-                match stage {
-                    Stage {
-                        required_fields: Some(_),
-                        from_file: None,
-                    } => vec![stage.clone()],
-                    Stage {
-                        required_fields: None,
-                        from_file: Some(file_name),
-                    } => {
-                        let file_name = PathBuf::from(file_name.as_ref());
-                        if traversed_files.contains(&file_name) {
-                            bail!(
-                                "{} File {} has already been parsed:\n{traversed_files:?}",
-                                "Circular dependency detected!".bright_red(),
-                                file_name.display().to_string().bold(),
-                            );
-                        }
-                        let mut tf = traversed_files.clone();
-                        tf.push(file_name.clone());
-
-                        Self::get_stages(&StagesExt::try_from(&file_name)?.stages, Some(tf))?
-                    }
-                    _ => {
-                        let from_example = Stage::builder().from_file("path/to/stage.yml").build();
-                        let stage_example = Self::example();
-
+            found_stages.extend(match stage {
+                Stage {
+                    required_fields: Some(_),
+                    from_file: None,
+                } => vec![stage.clone()],
+                Stage {
+                    required_fields: None,
+                    from_file: Some(file_name),
+                } => {
+                    let file_name = PathBuf::from(file_name.as_ref());
+                    if traversed_files.contains(&file_name) {
                         bail!(
-                            "Improper format for stage. Must be in the format like:\n{}\n{}\n\n{}",
-                            highlight_ser(&stage_example, "yaml", None)?,
-                            "or".bold(),
-                            highlight_ser(&from_example, "yaml", None)?
+                            "{} File {} has already been parsed:\n{traversed_files:?}",
+                            "Circular dependency detected!".bright_red(),
+                            file_name.display().to_string().bold(),
                         );
                     }
+                    let mut tf = traversed_files.clone();
+                    tf.push(file_name.clone());
+
+                    Self::get_stages(&StagesExt::try_from(&file_name)?.stages, Some(tf))?
+                }
+                _ => {
+                    let from_example = Stage::builder().from_file("path/to/stage.yml").build();
+                    let stage_example = Self::example();
+
+                    bail!(
+                        "Improper format for stage. Must be in the format like:\n{}\n{}\n\n{}",
+                        highlight_ser(&stage_example, "yaml", None)?,
+                        "or".bold(),
+                        highlight_ser(&from_example, "yaml", None)?
+                    );
                 }
-                .into_iter(),
-            );
+            });
         }
         Ok(found_stages)
     }
@@ -0,0 +1,186 @@
+//! Picks the akmods images that match the kernel of the base image.
+//!
+//! The ublue-os akmods images are built for one kernel flavor, like
+//! `main`, `asus`, `surface`, or `coreos-stable`, and only install on
+//! an image with the same kernel. An `akmods` module that doesn't set
+//! its `base` gets the flavor of the base image's kernel, and the
+//! kernel of every akmods image is checked against the base image's
+//! kernel so that a mismatch fails before the build starts.
+
+use blue_build_process_management::drivers::{
+    opts::GetMetadataOpts, types::Platform, Driver, InspectDriver,
+};
+use blue_build_recipe::{ModuleRequiredFields, Recipe};
+use blue_build_utils::constants::KERNEL_LABEL;
+use log::{debug, info, trace, warn};
+use miette::{bail, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+use serde_yaml::Value;
+
+/// The registry that the akmods images are pulled from.
+const AKMODS_REGISTRY: &str = "ghcr.io/ublue-os";
+
+/// The flavors whose kernels have the name of the
+/// flavor in their version, like `6.11.5-101.surface.fc41`.
+const NAMED_FLAVORS: &[&str] = &["asus", "surface", "bazzite"];
+
+/// Sets the `base` of the akmods modules that don't have one to the
+/// kernel flavor of the base image and checks that every akmods
+/// image was built for the base image's kernel.
+///
+/// Returns the kernel flavor, or `None` if the recipe doesn't have
+/// an akmods module or the base image doesn't label its kernel.
+///
+/// # Errors
+/// Will error if the images can't be inspected or an akmods
+/// image was built for a different kernel.
+pub fn select_flavor(
+    recipe: &mut Recipe,
+    os_version: u64,
+    platform: Platform,
+) -> Result<Option<String>> {
+    trace!("akmods::select_flavor({os_version}, {platform})");
+
+    if akmods_modules(recipe).next().is_none() {
+        return Ok(None);
+    }
+
+    let base_image = recipe.base_image_ref()?;
+    let Some(kernel) = kernel_version(&base_image, platform)? else {
+        warn!(
+            "The base image {base_image} doesn't have a {KERNEL_LABEL} label, so the \
+            akmods images can't be matched to its kernel"
+        );
+        return Ok(None);
+    };
+
+    let flavor = flavor_of(&kernel);
+    info!("Using the {flavor} akmods for the kernel {kernel} of {base_image}");
+    set_flavor(recipe, flavor);
+
+    for module in akmods_modules(recipe) {
+        let akmods_info = module.generate_akmods_info(&os_version);
+        let akmods_image: Reference = format!("{AKMODS_REGISTRY}/{}", akmods_info.images.0)
+            .parse()
+            .into_diagnostic()?;
+
+        match kernel_version(&akmods_image, platform)? {
+            Some(akmods_kernel) if akmods_kernel != kernel => {
+                bail!(
+                    help = format!(
+                        "Remove `base` from the akmods module to use the {flavor} akmods, \
+                        or set it to the flavor of the base image's kernel"
+                    ),
+                    "The akmods image {akmods_image} was built for the kernel {akmods_kernel}, \
+                    but the base image {base_image} has the kernel {kernel}"
+                );
+            }
+            Some(_) => debug!("The akmods image {akmods_image} matches the kernel {kernel}"),
+            None => warn!(
+                "The akmods image {akmods_image} doesn't have a {KERNEL_LABEL} label, \
+                so it can't be matched to the kernel {kernel}"
+            ),
+        }
+    }
+
+    Ok(Some(flavor.to_owned()))
+}
+
+/// Sets the `base` of the akmods modules that don't have one.
+pub fn set_flavor(recipe: &mut Recipe, flavor: &str) {
+    for module in recipe
+        .modules_ext
+        .modules
+        .iter_mut()
+        .filter_map(|module| module.required_fields.as_mut())
+        .filter(|module| module.module_type == "akmods")
+    {
+        module
+            .config
+            .entry(String::from("base"))
+            .or_insert_with(|| Value::String(flavor.to_owned()));
+    }
+}
+
+/// The akmods modules of the final image, which
+/// are the only ones that the akmods are copied for.
+fn akmods_modules<'a>(recipe: &'a Recipe) -> impl Iterator<Item = &'a ModuleRequiredFields<'a>> {
+    recipe
+        .modules_ext
+        .modules
+        .iter()
+        .filter_map(|module| module.required_fields.as_ref())
+        .filter(|module| module.module_type == "akmods")
+}
+
+/// The flavor of the akmods images that are built for the kernel.
+fn flavor_of(kernel: &str) -> &'static str {
+    kernel
+        .split(['.', '-', '+'])
+        .find_map(|part| NAMED_FLAVORS.iter().find(|flavor| **flavor == part))
+        .copied()
+        .unwrap_or("main")
+}
+
+fn kernel_version(image: &Reference, platform: Platform) -> Result<Option<String>> {
+    let metadata = Driver::get_metadata(
+        &GetMetadataOpts::builder()
+            .image(image)
+            .platform(platform)
+            .build(),
+    )?;
+
+    Ok(metadata
+        .labels
+        .get(KERNEL_LABEL)
+        .and_then(|kernel| kernel.as_str())
+        .map(ToOwned::to_owned))
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_recipe::Recipe;
+    use rstest::rstest;
+
+    use super::{flavor_of, set_flavor};
+
+    #[rstest]
+    #[case("6.11.5-300.fc41.x86_64", "main")]
+    #[case("6.11.5-101.surface.fc41.x86_64", "surface")]
+    #[case("6.11.5-300.asus.fc41.x86_64", "asus")]
+    #[case("6.11.5-201.bazzite.fc41.x86_64", "bazzite")]
+    #[case("6.11.5-300.fc41.aarch64", "main")]
+    fn kernel_flavor(#[case] kernel: &str, #[case] expected: &str) {
+        assert_eq!(flavor_of(kernel), expected);
+    }
+
+    #[test]
+    fn sets_missing_base() {
+        let mut recipe = serde_yaml::from_str::<Recipe>(
+            r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/aurora-surface
+image-version: 41
+modules:
+  - type: akmods
+    install: [openrazer]
+  - type: akmods
+    base: main
+    install: [v4l2loopback]
+",
+        )
+        .unwrap();
+
+        set_flavor(&mut recipe, "surface");
+
+        let bases = recipe
+            .modules_ext
+            .modules
+            .iter()
+            .filter_map(|module| module.required_fields.as_ref())
+            .map(|module| module.generate_akmods_info(&41).images.0)
+            .collect::<Vec<_>>();
+        assert_eq!(bases, ["akmods:surface-41", "akmods:main-41"]);
+    }
+}
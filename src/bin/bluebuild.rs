@@ -1,10 +1,32 @@
-use blue_build::commands::{BlueBuildArgs, BlueBuildCommand, CommandArgs};
-use blue_build_process_management::{logging::Logger, signal_handler};
-use clap::Parser;
+use blue_build::{
+    commands::{
+        completions::{completion_command, COMPLETE_ENV_VAR},
+        plugin::PluginCommand,
+        BlueBuildCommand, CommandArgs,
+    },
+    config::Config,
+    output, prompt,
+};
+use blue_build_process_management::{drivers::Driver, logging::Logger, signal_handler};
+use clap_complete::CompleteEnv;
 use log::LevelFilter;
 
 fn main() {
-    let args = BlueBuildArgs::parse();
+    CompleteEnv::with_factory(completion_command)
+        .var(COMPLETE_ENV_VAR)
+        .complete();
+
+    let config = Config::load()
+        .and_then(|config| {
+            blue_build_utils::tools::set_configured(config.tool_paths()?);
+            blue_build_utils::helper_images::set_configured(config.helper_images()?);
+            Ok(config)
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("{e:?}");
+            std::process::exit(1);
+        });
+    let args = config.parse_args();
 
     Logger::new()
         .filter_level(args.verbosity.log_level_filter())
@@ -15,9 +37,22 @@ fn main() {
             ("reqwest", LevelFilter::Off),
         ])
         .log_out_dir(args.log_out.clone())
+        .log_format(args.log_format)
         .init();
     log::trace!("Parsed arguments: {args:#?}");
 
+    for key in config.ignored() {
+        log::warn!("Ignoring `{key}` in the repo config, it can only be set in the user config");
+    }
+
+    if let Err(e) = Driver::check_tool_paths() {
+        log::error!("{e:?}");
+        std::process::exit(1);
+    }
+
+    output::set_format(args.format);
+    prompt::set_non_interactive(args.non_interactive);
+
     signal_handler::init(|| match args.command {
         // #[cfg(feature = "init")]
         // CommandArgs::Init(mut command) => command.run(),
@@ -25,6 +60,7 @@ fn main() {
         // #[cfg(feature = "init")]
         // CommandArgs::New(mut command) => command.run(),
         CommandArgs::Build(mut command) => command.run(),
+        CommandArgs::Test(mut command) => command.run(),
 
         CommandArgs::Generate(mut command) => command.run(),
 
@@ -40,17 +76,43 @@ fn main() {
         #[cfg(feature = "init")]
         CommandArgs::Init(mut command) => command.run(),
 
+        #[cfg(feature = "init")]
+        CommandArgs::Module(mut command) => command.run(),
+
+        CommandArgs::Import(mut command) => command.run(),
+
+        CommandArgs::Export(mut command) => command.run(),
+
+        CommandArgs::Migrate(mut command) => command.run(),
+
+        CommandArgs::Mok(mut command) => command.run(),
+
         #[cfg(feature = "iso")]
         CommandArgs::GenerateIso(mut command) => command.run(),
 
+        CommandArgs::GeneratePolicy(mut command) => command.run(),
+
         #[cfg(feature = "validate")]
         CommandArgs::Validate(mut command) => command.run(),
 
+        CommandArgs::UpdateFeed(mut command) => command.run(),
+
         #[cfg(feature = "prune")]
         CommandArgs::Prune(mut command) => command.run(),
 
+        CommandArgs::Registry(mut command) => command.run(),
+        CommandArgs::Cache(mut command) => command.run(),
+        CommandArgs::Clean(mut command) => command.run(),
+        CommandArgs::Storage(mut command) => command.run(),
+
         CommandArgs::BugReport(mut command) => command.run(),
 
         CommandArgs::Completions(mut command) => command.run(),
+
+        CommandArgs::Env(mut command) => command.run(),
+
+        CommandArgs::Schema(mut command) => command.run(),
+
+        CommandArgs::External(args) => PluginCommand::from(args).run(),
     });
 }
@@ -0,0 +1,266 @@
+//! Advisory locks that keep two runs of bluebuild from
+//! changing the same state at the same time.
+//!
+//! The lock files are kept in the runtime dir of the user, named by
+//! a hash of the path they lock, so nothing is written to the project
+//! and other users can't create or replace them. Each lock file holds
+//! the pid of the process that has it.
+//!
+//! The slots of the host are lock files in a directory of the
+//! runtime dir. Each run takes a free slot for every heavy operation,
+//! which limits how many runs of the user run at once.
+
+use std::{
+    env,
+    fs::{self, DirBuilder, File, OpenOptions},
+    io::{ErrorKind, Write},
+    num::NonZeroUsize,
+    os::unix::fs::{DirBuilderExt, OpenOptionsExt},
+    path::{Path, PathBuf},
+    process, thread,
+    time::Duration,
+};
+
+use blue_build_utils::constants::LOCAL_BUILD;
+use log::{debug, info, trace};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use nix::{
+    errno::Errno,
+    fcntl::{Flock, FlockArg, OFlag},
+};
+
+use crate::content_hash::ContentHasher;
+
+const WAIT_HELP: &str = "Wait for it to finish, or run with `--wait` to wait for it automatically";
+const SLOTS_DIR: &str = "bluebuild-slots";
+const SLOT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A held lock, which is released when dropped.
+#[derive(Debug)]
+pub struct BuildLock {
+    _lock: Flock<File>,
+}
+
+impl BuildLock {
+    /// Locks the project in the current directory
+    /// so that only one build runs in it at a time.
+    ///
+    /// # Errors
+    /// Will error if another build holds the lock and `wait` is false.
+    pub fn project(wait: bool) -> Result<Self> {
+        let project_dir = env::current_dir()
+            .and_then(fs::canonicalize)
+            .into_diagnostic()?;
+
+        Self::acquire("build", &project_dir, wait)
+    }
+
+    /// Locks the local build directory, which the archives
+    /// of local builds are moved to before switching to them.
+    ///
+    /// # Errors
+    /// Will error if another switch holds the lock and `wait` is false.
+    pub fn local_build(wait: bool) -> Result<Self> {
+        Self::acquire("switch", Path::new(LOCAL_BUILD), wait)
+    }
+
+    /// Takes one of the `slots` of the host, waiting for one to be
+    /// free, so that at most that many heavy operations run at once
+    /// across all of the runs of bluebuild of the user.
+    ///
+    /// # Errors
+    /// Will error if the slot files can't be opened or locked.
+    pub fn host_slot(slots: NonZeroUsize) -> Result<Self> {
+        Self::slot_in(&slots_dir()?, slots)
+    }
+
+    fn slot_in(dir: &Path, slots: NonZeroUsize) -> Result<Self> {
+        trace!("BuildLock::slot_in({}, {slots})", dir.display());
+
+        let mut waiting = false;
+        loop {
+            for slot in 0..slots.get() {
+                let path = dir.join(format!("slot-{slot}.lock"));
+                let file = open_lock_file(&path)
+                    .with_context(|| format!("Failed to open the slot file {}", path.display()))?;
+
+                match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+                    Ok(lock) => {
+                        debug!("Took slot {slot} of the host with {}", path.display());
+                        lock.set_len(0).into_diagnostic()?;
+                        write!(&*lock, "{}", process::id()).into_diagnostic()?;
+                        return Ok(Self { _lock: lock });
+                    }
+                    Err((_, Errno::EWOULDBLOCK)) => {}
+                    Err((_, e)) => {
+                        return Err(e)
+                            .into_diagnostic()
+                            .with_context(|| format!("Failed to lock {}", path.display()))
+                    }
+                }
+            }
+
+            if !waiting {
+                info!("Waiting for one of the {slots} build slots of the host to be free");
+                waiting = true;
+            }
+            thread::sleep(SLOT_POLL_INTERVAL);
+        }
+    }
+
+    fn acquire(kind: &str, locked: &Path, wait: bool) -> Result<Self> {
+        trace!("BuildLock::acquire({kind}, {}, {wait})", locked.display());
+
+        let mut hasher = ContentHasher::new();
+        hasher.add("path", locked.as_os_str().as_encoded_bytes());
+        let hash = hasher.finish();
+        let path = locks_dir()?.join(format!("{kind}-{}.lock", &hash[..16]));
+
+        let file = open_lock_file(&path)
+            .with_context(|| format!("Failed to open the lock file {}", path.display()))?;
+
+        let lock = match Flock::lock(file, FlockArg::LockExclusiveNonblock) {
+            Ok(lock) => lock,
+            Err((file, Errno::EWOULDBLOCK)) => {
+                let holder = fs::read_to_string(&path)
+                    .ok()
+                    .and_then(|pid| pid.trim().parse::<u32>().ok())
+                    .map(|pid| format!(" (pid {pid})"))
+                    .unwrap_or_default();
+
+                if !wait {
+                    bail!(
+                        help = WAIT_HELP,
+                        "Another {kind} is running for {}{holder}",
+                        locked.display()
+                    );
+                }
+                info!(
+                    "Waiting for another {kind} for {} to finish{holder}",
+                    locked.display()
+                );
+
+                Flock::lock(file, FlockArg::LockExclusive)
+                    .map_err(|(_, e)| e)
+                    .into_diagnostic()?
+            }
+            Err((_, e)) => {
+                return Err(e)
+                    .into_diagnostic()
+                    .with_context(|| format!("Failed to lock {}", path.display()))
+            }
+        };
+        debug!("Locked {} with {}", locked.display(), path.display());
+
+        lock.set_len(0).into_diagnostic()?;
+        write!(&*lock, "{}", process::id()).into_diagnostic()?;
+
+        Ok(Self { _lock: lock })
+    }
+}
+
+/// The directory of the lock files, which only the user can write to.
+fn locks_dir() -> Result<PathBuf> {
+    let dir = blue_build_utils::runtime_dir()
+        .ok_or_else(|| miette!("Failed to find the runtime dir for the lock files"))?;
+
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&dir)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to create {}", dir.display()))?;
+
+    if !dir.symlink_metadata().into_diagnostic()?.is_dir() {
+        bail!("The lock dir {} isn't a directory", dir.display());
+    }
+    Ok(dir)
+}
+
+/// The directory of the slot files.
+fn slots_dir() -> Result<PathBuf> {
+    let dir = locks_dir()?.join(SLOTS_DIR);
+
+    match DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            return Err(e)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to create {}", dir.display()))
+        }
+    }
+
+    if !dir.symlink_metadata().into_diagnostic()?.is_dir() {
+        bail!("The slots dir {} isn't a directory", dir.display());
+    }
+    Ok(dir)
+}
+
+/// Opens the lock file. A symlink is never followed,
+/// so that the lock can't be pointed at another file.
+fn open_lock_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .mode(0o600)
+        .custom_flags(OFlag::O_NOFOLLOW.bits())
+        .open(path)
+        .into_diagnostic()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{num::NonZeroUsize, process};
+
+    use super::BuildLock;
+
+    #[test]
+    fn second_lock_fails() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let lock = BuildLock::acquire("build", dir.path(), false).unwrap();
+
+        let err = BuildLock::acquire("build", dir.path(), false).unwrap_err();
+        assert!(err
+            .to_string()
+            .contains(&format!("(pid {})", process::id())));
+
+        drop(lock);
+        assert!(BuildLock::acquire("build", dir.path(), false).is_ok());
+    }
+
+    #[test]
+    fn symlinks_arent_followed() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let target = dir.path().join("target");
+        std::fs::write(&target, "keep").unwrap();
+        let link = dir.path().join("link.lock");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+
+        assert!(super::open_lock_file(&link).is_err());
+        assert_eq!(std::fs::read_to_string(&target).unwrap(), "keep");
+    }
+
+    #[test]
+    fn slots_are_shared() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let slots = NonZeroUsize::new(2).unwrap();
+
+        let first = BuildLock::slot_in(dir.path(), slots).unwrap();
+        let _second = BuildLock::slot_in(dir.path(), slots).unwrap();
+        assert!(dir.path().join("slot-1.lock").is_file());
+
+        // Both slots are taken, so the next waits until one is free
+        let waiter = std::thread::spawn({
+            let dir = dir.path().to_owned();
+            move || BuildLock::slot_in(&dir, slots).map(drop)
+        });
+        std::thread::sleep(std::time::Duration::from_millis(200));
+        assert!(!waiter.is_finished());
+
+        drop(first);
+        waiter.join().unwrap().unwrap();
+    }
+}
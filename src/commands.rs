@@ -1,26 +1,43 @@
-use std::path::PathBuf;
+use std::{ffi::OsString, path::PathBuf};
 
 use log::error;
 
-use clap::{command, crate_authors, Parser, Subcommand};
+use blue_build_process_management::logging::LogFormat;
+use clap::{crate_authors, Parser, Subcommand};
 use clap_verbosity_flag::{InfoLevel, Verbosity};
 
-use crate::shadow;
+use crate::{output::OutputFormat, shadow};
 
 pub mod bug_report;
 pub mod build;
+pub mod cache;
+pub mod clean;
 pub mod completions;
+pub mod env;
+pub mod export;
 pub mod generate;
 #[cfg(feature = "iso")]
 pub mod generate_iso;
+pub mod generate_policy;
+pub mod import;
 #[cfg(feature = "init")]
 pub mod init;
 #[cfg(feature = "login")]
 pub mod login;
+pub mod migrate;
+#[cfg(feature = "init")]
+pub mod module;
+pub mod mok;
+pub mod plugin;
 #[cfg(feature = "prune")]
 pub mod prune;
+pub mod registry;
+pub mod schema;
+pub mod storage;
 #[cfg(feature = "switch")]
 pub mod switch;
+pub mod test_image;
+pub mod update_feed;
 #[cfg(feature = "validate")]
 pub mod validate;
 
@@ -59,6 +76,21 @@ pub struct BlueBuildArgs {
     #[arg(long)]
     pub log_out: Option<PathBuf>,
 
+    /// The format of the logs printed to the terminal.
+    #[arg(long, default_value = "pretty")]
+    pub log_format: LogFormat,
+
+    /// The format of the output of informational commands.
+    #[arg(long, global = true)]
+    pub format: Option<OutputFormat>,
+
+    /// Fail instead of prompting for input.
+    ///
+    /// This is also the case when stdin
+    /// isn't a terminal.
+    #[arg(long, global = true, env = "BB_NON_INTERACTIVE")]
+    pub non_interactive: bool,
+
     #[clap(flatten)]
     pub verbosity: Verbosity<InfoLevel>,
 }
@@ -68,6 +100,10 @@ pub enum CommandArgs {
     /// Build an image from a recipe
     Build(build::BuildCommand),
 
+    /// Run the tests of a recipe and other scripts
+    /// against a built or published image.
+    Test(test_image::TestCommand),
+
     /// Generate a Containerfile from a recipe
     #[clap(visible_alias = "template")]
     Generate(generate::GenerateCommand),
@@ -76,6 +112,11 @@ pub enum CommandArgs {
     #[cfg(feature = "iso")]
     GenerateIso(generate_iso::GenerateIsoCommand),
 
+    /// Generate a policy that verifies the signatures of
+    /// the images of a recipe, for Kubernetes admission
+    /// controllers or the `policy.json` of a host.
+    GeneratePolicy(generate_policy::GeneratePolicyCommand),
+
     /// Switch your current OS onto the image
     /// being built.
     ///
@@ -105,20 +146,78 @@ pub enum CommandArgs {
     #[cfg(feature = "init")]
     Init(init::InitCommand),
 
+    /// Manage the local modules of a bluebuild project.
+    #[cfg(feature = "init")]
+    Module(module::ModuleCommand),
+
+    /// Convert a repo that builds its image another way to a bluebuild project.
+    Import(import::ImportCommand),
+
+    /// Convert a bluebuild project to a repo that builds its image another way.
+    Export(export::ExportCommand),
+
+    /// Convert recipes to the latest recipe format.
+    ///
+    /// This rewrites the recipes in place and keeps their comments.
+    Migrate(migrate::MigrateCommand),
+
+    /// Manage the Machine Owner Key that the
+    /// kernel modules of the image are signed with.
+    Mok(mok::MokCommand),
+
     /// Validate your recipe file and display
     /// errors to help fix problems.
     #[cfg(feature = "validate")]
     Validate(Box<validate::ValidateCommand>),
 
+    /// Update the feed of the builds of an image.
+    ///
+    /// This writes a JSON and an RSS feed with the latest digest
+    /// and version of each tag, which can be published on a static
+    /// site for update notifiers to poll instead of the registry.
+    UpdateFeed(update_feed::UpdateFeedCommand),
+
     /// Clean up cache and images for build drivers.
     #[cfg(feature = "prune")]
     Prune(prune::PruneCommand),
 
+    /// Manage the repos that images are pushed to.
+    Registry(registry::RegistryCommand),
+
+    /// Show and clean the files that bluebuild caches.
+    Cache(cache::CacheCommand),
+
+    /// Remove what builds that crashed left behind.
+    Clean(clean::CleanCommand),
+
+    /// Show how much container storage the images
+    /// that bluebuild built use, and prune them.
+    Storage(storage::StorageCommand),
+
     /// Create a pre-populated GitHub issue with information about your configuration
     BugReport(bug_report::BugReportCommand),
 
     /// Generate shell completions for your shell to stdout
     Completions(completions::CompletionsCommand),
+
+    /// Show the effective configuration.
+    ///
+    /// This includes the loaded config files, `BB_*`
+    /// environment variables, the drivers that will be
+    /// used and their versions, and the detected CI platform.
+    #[command(visible_alias = "config")]
+    Env(env::EnvCommand),
+
+    /// Print the JSON Schema of the `--format json`
+    /// output of a command.
+    ///
+    /// Use it to validate the output or generate
+    /// types for it in scripts and other tools.
+    Schema(schema::SchemaCommand),
+
+    /// Runs a `bb-<name>` plugin from `PATH`.
+    #[command(external_subcommand)]
+    External(Vec<OsString>),
 }
 
 #[cfg(test)]
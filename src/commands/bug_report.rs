@@ -1,22 +1,34 @@
+use blue_build_process_management::logging::Logger;
 use blue_build_recipe::Recipe;
-use blue_build_template::{GithubIssueTemplate, Template};
+use blue_build_template::{GithubIssueTemplate, GitlabIssueTemplate, Template};
 use blue_build_utils::constants::{
-    BUG_REPORT_WARNING_MESSAGE, GITHUB_CHAR_LIMIT, LC_TERMINAL, LC_TERMINAL_VERSION, TERM_PROGRAM,
-    TERM_PROGRAM_VERSION, UNKNOWN_SHELL, UNKNOWN_TERMINAL, UNKNOWN_VERSION,
+    BUG_REPORT_WARNING_MESSAGE, GITHUB_CHAR_LIMIT, GITLAB_CHAR_LIMIT, GITLAB_URL, LC_TERMINAL,
+    LC_TERMINAL_VERSION, TERM_PROGRAM, TERM_PROGRAM_VERSION, UNKNOWN_SHELL, UNKNOWN_TERMINAL,
+    UNKNOWN_VERSION,
 };
 use bon::Builder;
-use clap::Args;
+use clap::{Args, ValueEnum};
 use clap_complete::Shell;
 use colored::Colorize;
 use fuzzy_matcher::{skim::SkimMatcherV2, FuzzyMatcher};
-use log::{debug, error, trace};
-use miette::{IntoDiagnostic, Result};
+use log::{debug, error, info, trace};
+use miette::{Context, IntoDiagnostic, Result};
 use requestty::question::{completions, Completions};
-use std::time::Duration;
+use serde::Serialize;
+use std::{
+    env,
+    fmt::Display,
+    fs,
+    path::{Path, PathBuf},
+    time::Duration,
+};
 
 use super::BlueBuildCommand;
 
-use crate::shadow;
+use crate::{
+    output::{self, OutputFormat},
+    prompt, shadow,
+};
 
 #[derive(Default, Debug, Clone, Builder, Args)]
 pub struct BugReportRecipe {
@@ -24,11 +36,68 @@ pub struct BugReportRecipe {
     recipe_path: Option<String>,
 }
 
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum IssueProvider {
+    #[default]
+    Github,
+    Gitlab,
+}
+
+impl Display for IssueProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Github => "GitHub",
+                Self::Gitlab => "GitLab",
+            }
+        )
+    }
+}
+
 #[derive(Debug, Clone, Args, Builder)]
 pub struct BugReportCommand {
     /// Path to the recipe file
     #[arg(short, long)]
     recipe_path: Option<String>,
+
+    /// The issue tracker to file the report with.
+    #[arg(long, default_value = "github")]
+    #[builder(default)]
+    provider: IssueProvider,
+
+    /// The GitLab project to file the report against.
+    ///
+    /// This can be a project path on gitlab.com
+    /// (e.g. `my-group/my-project`) or the full URL
+    /// of a project on a self-hosted instance.
+    #[arg(long, required_if_eq("provider", "gitlab"))]
+    #[builder(into)]
+    project: Option<String>,
+
+    /// Don't include an excerpt of the most recent
+    /// build logs in the report.
+    #[arg(long)]
+    #[builder(default)]
+    no_logs: bool,
+
+    /// The number of lines from the end of the most
+    /// recent build log to include in the report.
+    #[arg(long, default_value_t = 50)]
+    #[builder(default = 50)]
+    log_lines: usize,
+
+    /// Write the report to a file instead of opening
+    /// an issue in the browser. Use `-` for stdout.
+    ///
+    /// The report is written as markdown unless `--format`
+    /// is used or the file ends in `.json`, `.yml`, or `.yaml`.
+    /// Setting `--format` without `--output` writes the
+    /// report to stdout.
+    #[arg(short, long)]
+    #[builder(into)]
+    output: Option<PathBuf>,
 }
 
 impl BlueBuildCommand for BugReportCommand {
@@ -54,6 +123,11 @@ impl BugReportCommand {
     pub fn create_bugreport(&self) -> Result<()> {
         let os_info = os_info::get();
         let recipe = self.get_recipe();
+        let logs = if self.no_logs {
+            None
+        } else {
+            self.get_recent_logs()
+        };
 
         let environment = Environment {
             os_type: os_info.os_type(),
@@ -62,7 +136,16 @@ impl BugReportCommand {
             os_version: os_info.version().clone(),
         };
 
-        let issue_body = match generate_github_issue(&environment, &recipe) {
+        if self.output.is_some() || output::format().is_some() {
+            return self.write_report(&environment, recipe.as_ref(), logs.as_deref());
+        }
+
+        let issue_body = match generate_issue(
+            self.provider,
+            &environment,
+            recipe.as_ref(),
+            logs.as_deref(),
+        ) {
             Ok(body) => body,
             Err(e) => {
                 println!("{}: {e}", "Failed to generate bug report".bright_red());
@@ -76,21 +159,38 @@ impl BugReportCommand {
             issue_body.on_bright_black().bright_white()
         );
 
+        prompt::ensure_interactive(
+            "confirmation to open the report",
+            "Use --output to write the report to a file instead",
+        )?;
+
         let question = requestty::Question::confirm("anonymous")
             .message(
-                "Forward the pre-filled report above to GitHub in your browser?"
-                    .bright_yellow()
-                    .to_string(),
+                format!(
+                    "Forward the pre-filled report above to {} in your browser?",
+                    self.provider
+                )
+                .bright_yellow()
+                .to_string(),
             )
             .default(true)
             .build();
 
         println!("{} To avoid any sensitive data from being exposed, please review the included information before proceeding.", "Warning:".on_bright_red().bright_white());
-        println!("Data forwarded to GitHub is subject to GitHub's privacy policy. For more information, see https://docs.github.com/en/github/site-policy/github-privacy-statement.\n");
+        match self.provider {
+            IssueProvider::Github => println!("Data forwarded to GitHub is subject to GitHub's privacy policy. For more information, see https://docs.github.com/en/github/site-policy/github-privacy-statement.\n"),
+            IssueProvider::Gitlab => println!("Data forwarded to GitLab is subject to the privacy policy of the GitLab instance. For gitlab.com, see https://about.gitlab.com/privacy/.\n"),
+        }
         match requestty::prompt_one(question) {
             Ok(answer) => {
                 if answer.as_bool().unwrap() {
-                    let link = make_github_issue_link(&issue_body);
+                    let link = match self.provider {
+                        IssueProvider::Github => make_github_issue_link(&issue_body),
+                        IssueProvider::Gitlab => make_gitlab_issue_link(
+                            self.project.as_deref().unwrap_or_default(),
+                            &issue_body,
+                        ),
+                    };
                     if let Err(e) = open::that(&link) {
                         println!("Failed to open issue report in your browser: {e}");
                         println!("Please copy the above report and open an issue manually, or try opening the following link:\n{link}");
@@ -113,7 +213,54 @@ impl BugReportCommand {
         Ok(())
     }
 
-    fn get_recipe(&self) -> Option<Recipe> {
+    /// Writes the report to the output file or stdout
+    /// without opening an issue.
+    fn write_report(
+        &self,
+        environment: &Environment,
+        recipe: Option<&Recipe>,
+        logs: Option<&str>,
+    ) -> Result<()> {
+        let output = self
+            .output
+            .as_deref()
+            .filter(|path| *path != Path::new("-"));
+        let format = output::format().unwrap_or_else(|| {
+            match output
+                .and_then(Path::extension)
+                .and_then(|ext| ext.to_str())
+            {
+                Some("json") => OutputFormat::Json,
+                Some("yml" | "yaml") => OutputFormat::Yaml,
+                _ => OutputFormat::Table,
+            }
+        });
+
+        let report = match format {
+            OutputFormat::Table => generate_issue(self.provider, environment, recipe, logs)?,
+            OutputFormat::Json => {
+                serde_json::to_string_pretty(&ReportData::new(environment, recipe, logs))
+                    .into_diagnostic()?
+            }
+            OutputFormat::Yaml => {
+                serde_yaml::to_string(&ReportData::new(environment, recipe, logs))
+                    .into_diagnostic()?
+            }
+        };
+
+        if let Some(output) = output {
+            fs::write(output, report)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to write report to {}", output.display()))?;
+            info!("Wrote bug report to {}", output.display());
+        } else {
+            println!("{report}");
+        }
+
+        Ok(())
+    }
+
+    fn get_recipe(&self) -> Option<Recipe<'_>> {
         let recipe_path = self.recipe_path.clone().unwrap_or_else(|| {
             get_config_file("recipe", "Enter path to recipe file").unwrap_or_else(|_| {
                 trace!("Failed to get recipe");
@@ -123,11 +270,199 @@ impl BugReportCommand {
 
         Recipe::parse(&recipe_path).ok()
     }
+
+    /// Gets a redacted excerpt of the most recently written log file.
+    fn get_recent_logs(&self) -> Option<String> {
+        let log_path = find_latest_log(&Logger::log_dir())?;
+        debug!("Including logs from {}", log_path.display());
+
+        let logs = fs::read_to_string(&log_path)
+            .inspect_err(|e| trace!("Failed to read {}: {e}", log_path.display()))
+            .ok()?;
+
+        let redactor = Redactor {
+            home: blue_build_utils::home_dir().map(|home| home.display().to_string()),
+            user: env::var("USER").ok(),
+        };
+        let excerpt = redactor.redact_tail(&logs, self.log_lines);
+
+        (!excerpt.is_empty()).then_some(excerpt)
+    }
+}
+
+/// Finds the most recently modified `.log` file in `dir`.
+fn find_latest_log(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| {
+            entry
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "log")
+        })
+        .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+        .max_by_key(|(modified, _)| *modified)
+        .map(|(_, path)| path)
+}
+
+/// Removes information from logs that users
+/// may not want to share in a public issue.
+struct Redactor {
+    home: Option<String>,
+    user: Option<String>,
+}
+
+impl Redactor {
+    const REDACTED: &'static str = "<redacted>";
+    const SECRET_KEYS: [&'static str; 6] = [
+        "password",
+        "passwd",
+        "token",
+        "secret",
+        "private_key",
+        "auth",
+    ];
+    const SECRET_PREFIXES: [&'static str; 7] = [
+        "ghp_",
+        "gho_",
+        "ghu_",
+        "ghs_",
+        "ghr_",
+        "github_pat_",
+        "glpat-",
+    ];
+
+    /// Redacts the last `lines` lines of `logs`.
+    fn redact_tail(&self, logs: &str, lines: usize) -> String {
+        let all_lines = logs.lines().collect::<Vec<_>>();
+        all_lines[all_lines.len().saturating_sub(lines)..]
+            .iter()
+            .map(|line| self.redact_line(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn redact_line(&self, line: &str) -> String {
+        let mut line = line.to_string();
+
+        if let Some(home) = self.home.as_deref().filter(|home| home.len() > 1) {
+            line = line.replace(home, "~");
+        }
+
+        let mut redact_next = false;
+        let line = line
+            .split(' ')
+            .map(|word| {
+                if std::mem::take(&mut redact_next) && !word.is_empty() {
+                    return Self::REDACTED.to_string();
+                }
+
+                let trimmed = word.trim_matches(|c: char| "\"'`,;()[]{}".contains(c));
+
+                if let Some(index) = trimmed.find(['=', ':']).filter(|&index| {
+                    Self::is_secret_key(&trimmed[..index]) && index + 1 < trimmed.len()
+                }) {
+                    return word.replace(
+                        trimmed,
+                        &format!("{}{}", &trimmed[..=index], Self::REDACTED),
+                    );
+                }
+
+                if Self::is_secret_key(trimmed.trim_end_matches(':'))
+                    && (trimmed.starts_with('-') || trimmed.ends_with(':'))
+                {
+                    redact_next = true;
+                    return word.to_string();
+                }
+
+                if Self::is_secret_value(trimmed) {
+                    return word.replace(trimmed, Self::REDACTED);
+                }
+
+                Self::redact_registry(trimmed)
+                    .map_or_else(|| word.to_string(), |image| word.replace(trimmed, &image))
+            })
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        match self.user.as_deref().filter(|user| user.len() > 2) {
+            Some(user) => line.replace(user, "<user>"),
+            None => line,
+        }
+    }
+
+    fn is_secret_key(key: &str) -> bool {
+        let key = key.trim_start_matches('-').to_lowercase();
+        key == "p" || Self::SECRET_KEYS.iter().any(|secret| key.contains(secret))
+    }
+
+    fn is_secret_value(value: &str) -> bool {
+        const MIN_SECRET_LEN: usize = 32;
+        const DIGEST_LEN: usize = 64;
+
+        if Self::SECRET_PREFIXES
+            .iter()
+            .any(|prefix| value.starts_with(prefix))
+        {
+            return true;
+        }
+
+        // Digests and container IDs are useful for debugging
+        // and aren't sensitive, so they are left alone.
+        let is_digest = value.len() == DIGEST_LEN
+            && value
+                .chars()
+                .all(|c| c.is_ascii_digit() || ('a'..='f').contains(&c));
+
+        !is_digest
+            && value.len() >= MIN_SECRET_LEN
+            && value.chars().any(|c| c.is_ascii_digit())
+            && value.chars().any(|c| c.is_ascii_alphabetic())
+            && value
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+/=_-.".contains(c))
+            && !value.contains("//")
+    }
+
+    /// Replaces the registry and namespace of an image ref,
+    /// keeping the image name for context.
+    fn redact_registry(word: &str) -> Option<String> {
+        let (transport, image) = word
+            .split_once("://")
+            .map_or(("", word), |(transport, image)| (transport, image));
+        let mut parts = image.split('/');
+        let registry = parts.next()?;
+        let rest = parts.collect::<Vec<_>>();
+
+        let is_registry = registry == "localhost"
+            || (registry.contains(['.', ':'])
+                && registry
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || ".-:".contains(c)));
+
+        if !is_registry || rest.len() < 2 || rest.iter().any(|part| part.is_empty()) {
+            return None;
+        }
+
+        let transport = if transport.is_empty() {
+            String::new()
+        } else {
+            format!("{transport}://")
+        };
+
+        Some(format!(
+            "{transport}<registry>/<namespace>/{}",
+            rest.last()?
+        ))
+    }
 }
 
 fn get_config_file(title: &str, message: &str) -> Result<String> {
     use std::path::Path;
 
+    prompt::ensure_interactive("a recipe", "Pass the recipe with --recipe-path")?;
+
     let question = requestty::Question::input(title)
         .message(message)
         .auto_complete(|p, _| auto_complete(p))
@@ -200,14 +535,27 @@ fn auto_complete(p: String) -> Completions<String> {
 
 // ============================================================================= //
 
+#[derive(Debug, Serialize)]
 struct Environment {
+    #[serde(rename = "shell")]
     shell_info: ShellInfo,
+    #[serde(rename = "os", serialize_with = "serialize_display")]
     os_type: os_info::Type,
+    #[serde(rename = "terminal")]
     terminal_info: TerminalInfo,
+    #[serde(serialize_with = "serialize_display")]
     os_version: os_info::Version,
 }
 
-#[derive(Debug)]
+fn serialize_display<T, S>(value: &T, serializer: S) -> std::result::Result<S::Ok, S::Error>
+where
+    T: Display,
+    S: serde::Serializer,
+{
+    serializer.collect_str(value)
+}
+
+#[derive(Debug, Serialize)]
 struct TerminalInfo {
     name: String,
     version: String,
@@ -228,7 +576,7 @@ fn get_terminal_info() -> TerminalInfo {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 struct ShellInfo {
     name: String,
     version: String,
@@ -276,8 +624,13 @@ fn get_pkg_branch_tag() -> String {
     format!("{} ({})", shadow::BRANCH, shadow::LAST_TAG)
 }
 
-fn generate_github_issue(environment: &Environment, recipe: &Option<Recipe>) -> Result<String> {
-    let recipe = serde_yaml::to_string(recipe).into_diagnostic()?;
+fn generate_issue(
+    provider: IssueProvider,
+    environment: &Environment,
+    recipe: Option<&Recipe>,
+    logs: Option<&str>,
+) -> Result<String> {
+    let recipe = serde_yaml::to_string(&recipe).into_diagnostic()?;
 
     let github_template = GithubIssueTemplate::builder()
         .bb_version(shadow::PKG_VERSION)
@@ -294,9 +647,53 @@ fn generate_github_issue(environment: &Environment, recipe: &Option<Recipe>) ->
         .shell_version(environment.shell_info.version.clone())
         .terminal_name(environment.terminal_info.name.clone())
         .terminal_version(environment.terminal_info.version.clone())
+        .logs(logs.unwrap_or_default())
         .build();
 
-    github_template.render().into_diagnostic()
+    match provider {
+        IssueProvider::Github => github_template.render(),
+        IssueProvider::Gitlab => GitlabIssueTemplate::builder()
+            .issue(github_template)
+            .build()
+            .render(),
+    }
+    .into_diagnostic()
+}
+
+/// The bug report in a machine readable format.
+#[derive(Debug, Serialize)]
+struct ReportData<'a> {
+    bb_version: &'a str,
+    pkg_branch_tag: String,
+    git_commit_hash: &'a str,
+    build_time: &'a str,
+    rust_version: &'a str,
+    rust_channel: &'a str,
+    build_rust_channel: &'a str,
+    environment: &'a Environment,
+    recipe: Option<&'a Recipe<'a>>,
+    logs: Option<&'a str>,
+}
+
+impl<'a> ReportData<'a> {
+    fn new(
+        environment: &'a Environment,
+        recipe: Option<&'a Recipe<'a>>,
+        logs: Option<&'a str>,
+    ) -> Self {
+        Self {
+            bb_version: shadow::PKG_VERSION,
+            pkg_branch_tag: get_pkg_branch_tag(),
+            git_commit_hash: shadow::COMMIT_HASH,
+            build_time: shadow::BUILD_TIME,
+            rust_version: shadow::RUST_VERSION,
+            rust_channel: shadow::RUST_CHANNEL,
+            build_rust_channel: shadow::BUILD_RUST_CHANNEL,
+            environment,
+            recipe,
+            logs,
+        }
+    }
 }
 
 fn make_github_issue_link(body: &str) -> String {
@@ -312,12 +709,130 @@ fn make_github_issue_link(body: &str) -> String {
     .collect()
 }
 
+fn make_gitlab_issue_link(project: &str, body: &str) -> String {
+    let project_url = if project.starts_with("http://") || project.starts_with("https://") {
+        project.trim_end_matches('/').to_string()
+    } else {
+        format!("{GITLAB_URL}/{}", project.trim_matches('/'))
+    };
+
+    format!(
+        "{project_url}/-/issues/new?{}={}&{}={}",
+        urlencoding::encode("issue[title]"),
+        urlencoding::encode("Bug report"),
+        urlencoding::encode("issue[description]"),
+        urlencoding::encode(body)
+    )
+    .chars()
+    .take(GITLAB_CHAR_LIMIT)
+    .collect()
+}
+
 // ============================================================================= //
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::env;
+    use rstest::rstest;
+    use std::{env, time::SystemTime};
+
+    fn redactor() -> Redactor {
+        Redactor {
+            home: Some("/home/jdoe".into()),
+            user: Some("jdoe".into()),
+        }
+    }
+
+    #[rstest]
+    #[case(
+        "Pushing ghcr.io/jdoe/my-image:latest",
+        "Pushing <registry>/<namespace>/my-image:latest"
+    )]
+    #[case(
+        "Copying docker://registry.example.com:5000/org/team/image",
+        "Copying docker://<registry>/<namespace>/image"
+    )]
+    #[case(
+        "Reading /home/jdoe/project/recipe.yml",
+        "Reading ~/project/recipe.yml"
+    )]
+    #[case("Running as jdoe", "Running as <user>")]
+    #[case(
+        "docker login -u user -p hunter2",
+        "docker login -u user -p <redacted>"
+    )]
+    #[case("--password=hunter2 --other", "--password=<redacted> --other")]
+    #[case("REGISTRY_TOKEN=abc123", "REGISTRY_TOKEN=<redacted>")]
+    #[case("token ghp_abcdefghijklmnop used", "token <redacted> used")]
+    #[case(
+        "key: 'dGhpc2lzYXZlcnlsb25nc2VjcmV0a2V5MTIzNDU2Nzg5MA=='",
+        "key: '<redacted>'"
+    )]
+    #[case(
+        "Digest sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+        "Digest sha256:0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef"
+    )]
+    #[case("STEP 1/4: FROM fedora", "STEP 1/4: FROM fedora")]
+    fn redact_lines(#[case] line: &str, #[case] expected: &str) {
+        assert_eq!(redactor().redact_line(line), expected);
+    }
+
+    #[test]
+    fn redact_tail() {
+        let logs = "line 1\nline 2\nline 3\n";
+
+        assert_eq!(redactor().redact_tail(logs, 2), "line 2\nline 3");
+        assert_eq!(redactor().redact_tail(logs, 10), "line 1\nline 2\nline 3");
+    }
+
+    #[test]
+    fn latest_log() {
+        let dir = tempfile::TempDir::new().unwrap();
+        let now = SystemTime::now();
+        for (name, age) in [
+            ("bluebuild.log", 60),
+            ("ghcr_io_test_image.log", 30),
+            ("notes.txt", 0),
+        ] {
+            let file = fs::File::create(dir.path().join(name)).unwrap();
+            file.set_modified(now - Duration::from_secs(age)).unwrap();
+        }
+
+        assert_eq!(
+            find_latest_log(dir.path()),
+            Some(dir.path().join("ghcr_io_test_image.log"))
+        );
+    }
+
+    fn environment() -> Environment {
+        Environment {
+            os_type: os_info::Type::Linux,
+            os_version: os_info::Version::Semantic(1, 2, 3),
+            shell_info: ShellInfo {
+                version: "2.3.4".to_string(),
+                name: "test_shell".to_string(),
+            },
+            terminal_info: TerminalInfo {
+                name: "test_terminal".to_string(),
+                version: "5.6.7".to_string(),
+            },
+        }
+    }
+
+    #[test]
+    fn issue_with_logs() {
+        let environment = environment();
+
+        let body = generate_issue(
+            IssueProvider::Github,
+            &environment,
+            None,
+            Some("Build failed"),
+        )
+        .unwrap();
+
+        assert!(body.contains("#### Logs:\n```\nBuild failed\n```"));
+    }
 
     #[test]
     fn test_make_github_link() {
@@ -335,7 +850,8 @@ mod tests {
         };
 
         let recipe = Recipe::default();
-        let body = generate_github_issue(&environment, &Some(recipe)).unwrap();
+        let body =
+            generate_issue(IssueProvider::Github, &environment, Some(&recipe), None).unwrap();
         let link = make_github_issue_link(&body);
 
         assert!(link.contains(clap::crate_version!()));
@@ -344,4 +860,42 @@ mod tests {
         assert!(link.contains("test_shell"));
         assert!(link.contains("2.3.4"));
     }
+
+    #[rstest]
+    #[case(
+        "my-group/my-project",
+        "https://gitlab.com/my-group/my-project/-/issues/new?"
+    )]
+    #[case(
+        "https://gitlab.example.com/my-group/my-project/",
+        "https://gitlab.example.com/my-group/my-project/-/issues/new?"
+    )]
+    fn test_make_gitlab_link(#[case] project: &str, #[case] expected_prefix: &str) {
+        let body = generate_issue(IssueProvider::Gitlab, &environment(), None, None).unwrap();
+        let link = make_gitlab_issue_link(project, &body);
+
+        assert!(body.trim_end().ends_with("/label ~bug"));
+        assert!(link.starts_with(expected_prefix));
+        assert!(link.contains("issue%5Bdescription%5D="));
+        assert!(link.contains("test_shell"));
+    }
+
+    #[test]
+    fn json_report() {
+        let recipe = Recipe::default();
+        let report = serde_json::to_string(&ReportData::new(
+            &environment(),
+            Some(&recipe),
+            Some("Build failed"),
+        ))
+        .unwrap();
+        let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+
+        assert_eq!(report["bb_version"], clap::crate_version!());
+        assert_eq!(report["environment"]["os"], "Linux");
+        assert_eq!(report["environment"]["os_version"], "1.2.3");
+        assert_eq!(report["environment"]["shell"]["name"], "test_shell");
+        assert_eq!(report["logs"], "Build failed");
+        assert!(report["recipe"].is_object());
+    }
 }
@@ -1,21 +1,39 @@
-use std::path::{Path, PathBuf};
+use std::{
+    env, fs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
+#[cfg(feature = "rechunk")]
+use blue_build_process_management::drivers::opts::Rechunker;
 use blue_build_process_management::{
     drivers::{
         opts::{
-            BuildTagPushOpts, CheckKeyPairOpts, CompressionType, GenerateImageNameOpts,
-            GenerateTagsOpts, SignVerifyOpts,
+            BuildCache, BuildSecret, BuildSecretSource, BuildTagPushOpts, CheckKeyPairOpts,
+            CompressionType, ExtraArg, GenerateImageNameOpts, GenerateTagsOpts, GetMetadataOpts,
+            SignVerifyOpts, DEFAULT_PUSH_JOBS,
         },
-        types::Platform,
-        BuildDriver, CiDriver, Driver, DriverArgs, SigningDriver,
+        types::{BuildDriverType, CiDriverType, Platform, SigningDriverType},
+        BuildDriver, CiDriver, Driver, DriverArgs, InspectDriver, RunDriver, SigningDriver,
     },
+    generated,
     logging::{color_str, gen_random_ansi_color},
+    metrics,
+    preflight::{self, ByteSize, SpaceNeeded},
+    signal_handler::{CleanupGuard, CleanupItem},
+    summary::{self, strip_ansi, ImageSummary},
 };
-use blue_build_recipe::Recipe;
+use blue_build_recipe::{Hook, Hooks, ImageTest, Recipe, RecipeSecretSource};
 use blue_build_utils::{
     constants::{
-        ARCHIVE_SUFFIX, BB_BUILD_RECHUNK, BB_BUILD_RECHUNK_CLEAR_PLAN, BB_REGISTRY_NAMESPACE,
-        CONFIG_PATH, CONTAINER_FILE, RECIPE_FILE, RECIPE_PATH,
+        ARCHIVE_SUFFIX, BB_BUILD_ALLOW_RECIPE_HOOKS, BB_BUILD_ALLOW_SECRET_ENV,
+        BB_BUILD_CACHE_FROM, BB_BUILD_CACHE_TO, BB_BUILD_RECHUNK, BB_BUILD_RECHUNKER,
+        BB_BUILD_RECHUNK_CLEAR_PLAN, BB_BUILD_RM_AFTER_PUSH, BB_BUILD_SKIP_PREFLIGHT,
+        BB_BUILD_SKIP_TESTS, BB_HOST_JOBS, BB_INSECURE_ALLOW_UNVERIFIED_TOOLS,
+        BB_METRICS_PUSHGATEWAY, BB_METRICS_TEXTFILE, BB_NO_VERIFY_TOOLS, BB_OFFLINE,
+        BB_REGISTRY_NAMESPACE, CONFIG_PATH, CONTAINER_FILE, CONTENT_HASH_LABEL, COSIGN_PUB_PATH,
+        RECIPE_FILE, RECIPE_PATH,
     },
     cowstr,
     credentials::{Credentials, CredentialsArgs},
@@ -24,15 +42,54 @@ use blue_build_utils::{
 };
 use bon::Builder;
 use clap::Args;
-use log::{info, trace, warn};
+use log::{debug, info, trace, warn};
 use miette::{bail, IntoDiagnostic, Result};
 use oci_distribution::Reference;
 use tempfile::TempDir;
 
-use crate::commands::generate::GenerateCommand;
+use crate::{
+    build_lock::BuildLock,
+    commands::generate::GenerateCommand,
+    content_hash::build_inputs_hash,
+    error::{BuildError, BuildStep},
+    git_modules,
+    hooks::{self, HookEnv},
+    image_tests,
+    lockfile::Lockfile,
+    module_overrides::{self, ModuleOverride},
+    notify, templated_files,
+};
 
 use super::BlueBuildCommand;
 
+/// The least space the tempdir needs when rechunking.
+#[cfg(feature = "rechunk")]
+const RECHUNK_TEMPDIR_MIN_FREE: ByteSize = ByteSize(20 * 1024 * 1024 * 1024);
+
+/// How many times the compressed size of the base image that
+/// container storage needs, for the unpacked base image and
+/// the layers that the build adds.
+const STORAGE_FACTOR: u64 = 3;
+
+/// Rechunking also keeps a squashed copy of the
+/// image and its ostree commit in container storage.
+#[cfg(feature = "rechunk")]
+const RECHUNK_STORAGE_FACTOR: u64 = 3;
+
+/// How many times the compressed size of the base
+/// image that the chunked image needs in the tempdir.
+#[cfg(feature = "rechunk")]
+const RECHUNK_TEMPDIR_FACTOR: u64 = 2;
+
+/// The default size of the layers that
+/// are never merged with `--max-layers`.
+#[cfg(feature = "rechunk")]
+const MERGE_KEEP_SIZE: ByteSize = ByteSize(64 * 1024 * 1024);
+
+/// How many times the compressed size of
+/// the base image that an archive needs.
+const ARCHIVE_FACTOR: u64 = 2;
+
 #[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Args, Builder)]
 pub struct BuildCommand {
@@ -83,6 +140,27 @@ pub struct BuildCommand {
     #[builder(default)]
     retry_count: u8,
 
+    /// The maximum number of tags to push at once.
+    ///
+    /// Most of the image is shared between tags, so
+    /// pushing them concurrently mostly saves on
+    /// round trips to the registry.
+    #[arg(long, default_value_t = DEFAULT_PUSH_JOBS)]
+    #[builder(default = DEFAULT_PUSH_JOBS)]
+    push_jobs: NonZeroUsize,
+
+    /// The maximum number of layers to upload at
+    /// once when pushing a rechunked image.
+    ///
+    /// Raising this can speed up pushing large
+    /// images on runners with a lot of bandwidth.
+    /// Uses skopeo's default if not set.
+    ///
+    /// NOTE: Requires skopeo 1.15 or newer.
+    #[arg(long, requires = "rechunk")]
+    #[cfg(feature = "rechunk")]
+    push_concurrency: Option<NonZeroUsize>,
+
     /// Archives the built image into a tarfile
     /// in the specified directory.
     #[arg(short, long, group = "archive_rechunk", group = "archive_push")]
@@ -100,6 +178,47 @@ pub struct BuildCommand {
     #[builder(default)]
     no_sign: bool,
 
+    /// Remove the image and its tags from local
+    /// storage once it's pushed and signed.
+    ///
+    /// This keeps CI runners and machines from filling
+    /// up when building many images or tags.
+    #[arg(long, requires = "push", env = BB_BUILD_RM_AFTER_PUSH)]
+    #[builder(default)]
+    rm_after_push: bool,
+
+    /// Run the `hooks` of the recipe.
+    ///
+    /// The hooks run commands on the host, so a recipe
+    /// with hooks only builds when they're allowed. The
+    /// hooks of the user config always run.
+    #[arg(long, env = BB_BUILD_ALLOW_RECIPE_HOOKS)]
+    #[builder(default)]
+    allow_recipe_hooks: bool,
+
+    /// Allow the `secrets` of the recipe to be read
+    /// from the environment variable `NAME`.
+    ///
+    /// The secrets are given to the modules of the
+    /// recipe, so a recipe can only read the
+    /// environment variables that are allowed.
+    #[arg(
+        long,
+        value_name = "NAME",
+        env = BB_BUILD_ALLOW_SECRET_ENV,
+        value_delimiter = ','
+    )]
+    #[builder(default, into)]
+    allow_secret_env: Vec<String>,
+
+    /// Don't run the `tests` of the recipe.
+    ///
+    /// The tests can't run when archiving or
+    /// rechunking, so they must be skipped then.
+    #[arg(long, env = BB_BUILD_SKIP_TESTS)]
+    #[builder(default)]
+    skip_tests: bool,
+
     /// Runs all instructions inside one layer of the final image.
     ///
     /// WARN: This doesn't work with the
@@ -123,19 +242,258 @@ pub struct BuildCommand {
     #[cfg(feature = "rechunk")]
     rechunk: bool,
 
-    /// Use a fresh rechunk plan, regardless of previous ref.
+    /// The tool that splits the image into chunks.
     ///
     /// NOTE: Only works with `--rechunk`.
+    #[arg(long, value_name = "NAME", requires = "rechunk", default_value_t, env = BB_BUILD_RECHUNKER)]
+    #[builder(default)]
+    #[cfg(feature = "rechunk")]
+    rechunker: Rechunker,
+
+    /// Use a fresh rechunk plan, regardless of previous ref.
+    ///
+    /// NOTE: Only works with `--rechunk`, and only
+    /// the `hhd` rechunker reuses the previous plan.
     #[arg(long, env = BB_BUILD_RECHUNK_CLEAR_PLAN)]
     #[builder(default)]
     #[cfg(feature = "rechunk")]
     rechunk_clear_plan: bool,
 
+    /// Run the rechunk image even if its signature
+    /// can't be verified.
+    ///
+    /// WARN: The rechunk image runs with `--privileged`,
+    /// so an image that was tampered with gets root
+    /// on this machine.
+    #[arg(long, env = BB_INSECURE_ALLOW_UNVERIFIED_TOOLS)]
+    #[builder(default)]
+    #[cfg(feature = "rechunk")]
+    insecure_allow_unverified_tools: bool,
+
+    /// Merges the smallest adjacent layers of the image after
+    /// it's built until it has at most this many layers.
+    ///
+    /// Some registries and clients behave badly with images
+    /// that have 100 or more layers. Layers at least as big as
+    /// `--merge-keep-size` are never merged since they're
+    /// usually shared with the base image and other images.
+    ///
+    /// With `--rechunk`, this is the most layers that
+    /// the rechunker splits the image into instead.
+    ///
+    /// NOTE: Requires skopeo.
+    #[arg(long, value_name = "LAYERS", conflicts_with = "archive")]
+    #[cfg(feature = "rechunk")]
+    max_layers: Option<NonZeroUsize>,
+
+    /// Layers at least this big are left
+    /// alone when merging layers, like `64M`.
+    #[arg(long, value_name = "SIZE", default_value = "64M")]
+    #[builder(default = MERGE_KEEP_SIZE)]
+    #[cfg(feature = "rechunk")]
+    merge_keep_size: ByteSize,
+
+    /// Skip building a recipe when the image that was last
+    /// pushed was built from the same inputs.
+    ///
+    /// The inputs are hashed from the recipe, the project's
+    /// files, and the digest of the base image. This makes
+    /// scheduled builds cheap when the base image hasn't changed.
+    #[arg(long, requires = "push")]
+    #[builder(default)]
+    skip_unchanged: bool,
+
+    /// Always render the Containerfile instead of
+    /// reusing one cached from an earlier run.
+    #[arg(long)]
+    #[builder(default)]
+    no_generate_cache: bool,
+
+    /// A registry repo to pull cached layers from.
+    /// Can be used more than once.
+    ///
+    /// Podman and buildah use the repo as is, while
+    /// docker keeps the cache in the `cache` tag of the repo.
+    #[arg(long, value_name = "REPO", env = BB_BUILD_CACHE_FROM, value_delimiter = ',')]
+    #[builder(default, into)]
+    cache_from: Vec<String>,
+
+    /// A registry repo to push the layers of the build to
+    /// so that later builds can use them with `--cache-from`.
+    #[arg(long, value_name = "REPO", env = BB_BUILD_CACHE_TO)]
+    #[builder(into)]
+    cache_to: Option<String>,
+
+    /// Don't pass the proxy env vars of the host, like
+    /// `HTTP_PROXY`, `HTTPS_PROXY`, and `NO_PROXY`, into the build.
+    ///
+    /// The vars are passed as build args, so `RUN` instructions
+    /// use the proxy without it being stored in the image.
+    #[arg(long)]
+    #[builder(default)]
+    no_proxy: bool,
+
+    /// An option to pass to the build command of the build
+    /// driver, like `ulimit=nofile=4096` for `--ulimit=nofile=4096`.
+    /// Can be used more than once.
+    ///
+    /// This is for options of the build driver that bluebuild
+    /// doesn't have a flag for. Options with a key like `token`
+    /// or `password` have their value redacted in the logs. It
+    /// can be set in the user config, but not the repo config.
+    #[arg(
+        long = "build-opt",
+        value_name = "KEY[=VALUE]",
+        allow_hyphen_values = true
+    )]
+    #[builder(default, into)]
+    build_opts: Vec<ExtraArg>,
+
+    /// An option to pass to the command that pushes the image,
+    /// like `tls-verify=false`. Can be used more than once.
+    ///
+    /// The image is pushed with the build driver, or with skopeo
+    /// when using `--rechunk` or `--max-layers`. It can be set in
+    /// the user config, but not the repo config.
+    #[arg(
+        long = "push-opt",
+        value_name = "KEY[=VALUE]",
+        allow_hyphen_values = true
+    )]
+    #[builder(default, into)]
+    push_opts: Vec<ExtraArg>,
+
+    /// Build without the network, using the lockfile of the
+    /// recipe and images that are already in local storage.
+    ///
+    /// The base image at the digest in the lockfile, the build
+    /// scripts image, and the images of OCI sourced modules must
+    /// already be pulled. Git sourced modules must be in the cache.
+    #[arg(
+        long,
+        env = BB_OFFLINE,
+        conflicts_with_all = ["push", "cache_from", "cache_to", "lock"],
+    )]
+    #[builder(default)]
+    offline: bool,
+
+    /// Write the lockfile of the recipe, which
+    /// is required to build with `--offline`.
+    #[arg(long)]
+    #[builder(default)]
+    lock: bool,
+
+    /// Don't validate the recipes against their schemas,
+    /// like for recipes that were already validated.
+    #[arg(skip)]
+    #[builder(default)]
+    skip_validation: bool,
+
+    /// Don't verify the images that are copied into the build,
+    /// like the build scripts, the modules, and cosign.
+    #[arg(long, env = BB_NO_VERIFY_TOOLS)]
+    #[builder(default)]
+    no_verify_tools: bool,
+
+    /// Wait for another build in this directory to
+    /// finish instead of failing.
+    ///
+    /// Only one build can run in a directory at a time,
+    /// since builds share the files they generate.
+    #[arg(long)]
+    #[builder(default)]
+    wait: bool,
+
+    /// The most builds that can run at once on this machine,
+    /// across every run of bluebuild of the user that sets this.
+    ///
+    /// Useful when CI runs several builds on one runner, like
+    /// the jobs of a matrix, so they don't run out of memory.
+    /// A build waits for a free slot before it starts.
+    #[arg(long, value_name = "JOBS", env = BB_HOST_JOBS)]
+    host_jobs: Option<NonZeroUsize>,
+
+    /// Build the stages of the recipe as their own images
+    /// at the same time, before building the image.
+    ///
+    /// The stages are given to the build of the image with
+    /// `--build-context`, which shortens builds of recipes
+    /// with heavy compile stages.
+    #[arg(long)]
+    #[builder(default)]
+    parallel_stages: bool,
+
+    /// The maximum number of stages to build at once
+    /// when using `--parallel-stages`.
+    #[arg(long, default_value_t = NonZeroUsize::new(4).unwrap())]
+    #[builder(default = NonZeroUsize::new(4).unwrap())]
+    jobs: NonZeroUsize,
+
+    /// The number of buildx builders that the builds of
+    /// recipes and stages are spread over with docker.
+    ///
+    /// Builders are created as needed and kept between runs
+    /// for their caches. Builders beyond this number that were
+    /// left by earlier runs are removed.
+    #[arg(long, default_value_t = NonZeroUsize::MIN)]
+    #[builder(default = NonZeroUsize::MIN)]
+    builders: NonZeroUsize,
+
+    /// Use the module in this directory instead of the
+    /// published one. Can be used more than once.
+    ///
+    /// The name of the directory is the type of the module
+    /// it overrides, like `../modules/modules/rpm-ostree`.
+    #[arg(long = "module-path", value_name = "DIR")]
+    #[builder(default, into)]
+    module_paths: Vec<PathBuf>,
+
     /// The location to temporarily store files
     /// while building. If unset, it will use `/tmp`.
     #[arg(long)]
     tempdir: Option<PathBuf>,
 
+    /// The space that must be free in the tempdir
+    /// before building, like `30G`.
+    ///
+    /// Defaults to twice the size of the base image, and at
+    /// least 20G, when rechunking, which can take tens of GB.
+    /// Otherwise the space isn't checked.
+    #[arg(long, value_name = "SIZE")]
+    tempdir_min_free: Option<ByteSize>,
+
+    /// The space that must be free in container
+    /// storage before building, like `50G`.
+    ///
+    /// Defaults to an estimate from the size of the base
+    /// image, which is larger when rechunking.
+    #[arg(long, value_name = "SIZE")]
+    storage_min_free: Option<ByteSize>,
+
+    /// Skip the checks for free space and rootless
+    /// setup that run before building.
+    ///
+    /// Use this when the checks are wrong about your
+    /// system, like with a custom storage setup.
+    #[arg(long, env = BB_BUILD_SKIP_PREFLIGHT)]
+    #[builder(default)]
+    skip_preflight: bool,
+
+    /// Write build metrics in the Prometheus text
+    /// format to this file at the end of the build.
+    ///
+    /// Point this into the directory of a node-exporter
+    /// textfile collector to monitor your build hosts.
+    #[arg(long, env = BB_METRICS_TEXTFILE)]
+    #[builder(into)]
+    metrics_textfile: Option<PathBuf>,
+
+    /// Push build metrics to a Prometheus
+    /// pushgateway at the end of the build.
+    #[arg(long, env = BB_METRICS_PUSHGATEWAY)]
+    #[builder(into)]
+    metrics_pushgateway: Option<String>,
+
     #[clap(flatten)]
     #[builder(default)]
     credentials: CredentialsArgs,
@@ -150,12 +508,105 @@ impl BlueBuildCommand for BuildCommand {
     fn try_run(&mut self) -> Result<()> {
         trace!("BuildCommand::try_run()");
 
+        self.build_images().map(|_| ())
+    }
+}
+
+impl BuildCommand {
+    /// Builds the images of the recipes and returns what was built.
+    ///
+    /// An error from building a recipe wraps a [`BuildError`] with
+    /// the step of the build that failed.
+    ///
+    /// # Errors
+    /// Will error if the images can't be built, pushed, or signed.
+    pub fn build_images(&self) -> Result<Vec<ImageSummary>> {
+        trace!("BuildCommand::build_images()");
+
+        clean_generated();
+
+        let start = Instant::now();
+        let result = self.run_build();
+
+        if self.metrics_textfile.is_some() || self.metrics_pushgateway.is_some() {
+            if let Err(e) = metrics::export()
+                .maybe_textfile(self.metrics_textfile.as_deref())
+                .maybe_pushgateway(self.metrics_pushgateway.as_deref())
+                .success(result.is_ok())
+                .call()
+            {
+                warn!("Failed to export build metrics:\n{e:?}");
+            }
+        }
+
+        let summary = summary::get(result.is_ok());
+        if let Err(e) = Driver::report_summary(&summary) {
+            warn!("Failed to report the build summary:\n{e:?}");
+        }
+        notify::send(&summary, start.elapsed());
+
+        result
+    }
+
+    /// Sets up the drivers from the args and makes sure
+    /// the build driver can build as this user.
+    fn init_drivers(&self) -> Result<()> {
+        Driver::init(self.drivers.clone());
+        Driver::set_offline(self.offline);
+        Driver::set_builders(self.builders);
+        #[cfg(feature = "rechunk")]
+        Driver::set_allow_unverified_tools(self.insecure_allow_unverified_tools);
+
+        if self.skip_preflight {
+            warn!("Skipping the preflight checks");
+            return Ok(());
+        }
+        match Driver::get_build_driver() {
+            BuildDriverType::Podman => preflight::check_rootless("podman"),
+            BuildDriverType::Buildah => preflight::check_rootless("buildah"),
+            BuildDriverType::Docker | BuildDriverType::External => Ok(()),
+            #[cfg(feature = "test")]
+            BuildDriverType::Mock => Ok(()),
+        }
+    }
+
+    /// Logs into the registry to push to. Outside of CI, the user is
+    /// asked for credentials when there aren't any instead of failing
+    /// the push at the end of the build.
+    fn login(&self) -> Result<()> {
+        #[cfg(feature = "login")]
+        if Credentials::all().is_empty()
+            && matches!(Driver::get_ci_driver(), CiDriverType::Local)
+            && crate::prompt::is_interactive()
+        {
+            super::login::prompt_login(self.credentials.registry.as_deref())?;
+            return Driver::signing_login();
+        }
+
+        Driver::login()?;
+        Driver::signing_login()
+    }
+
+    fn run_build(&self) -> Result<Vec<ImageSummary>> {
         #[cfg(feature = "rechunk")]
         if !nix::unistd::Uid::effective().is_root() && self.rechunk {
             bail!("You must be root to use the rechunk feature!");
         }
 
-        Driver::init(self.drivers);
+        #[cfg(feature = "rechunk")]
+        if self.offline && self.rechunk {
+            bail!("You cannot use '--offline' and '--rechunk' at the same time");
+        }
+
+        if self.offline
+            && (self.push || !self.cache_from.is_empty() || self.cache_to.is_some() || self.lock)
+        {
+            bail!("You cannot use '--offline' with '--push', '--cache-from', '--cache-to', or '--lock'");
+        }
+
+        let _lock = BuildLock::project(self.wait)?;
+
+        self.init_drivers()?;
 
         Credentials::init(self.credentials.clone());
 
@@ -164,10 +615,19 @@ impl BlueBuildCommand for BuildCommand {
         }
 
         if self.push {
-            blue_build_utils::check_command_exists("cosign")?;
-            Driver::check_signing_files(&CheckKeyPairOpts::builder().dir(Path::new(".")).build())?;
-            Driver::login()?;
-            Driver::signing_login()?;
+            if matches!(Driver::get_signing_driver(), SigningDriverType::Cosign) {
+                blue_build_utils::check_command_exists("cosign")?;
+            }
+
+            // Images are signed keylessly in CI when there isn't a key pair
+            if Path::new(COSIGN_PUB_PATH).exists()
+                || matches!(Driver::get_ci_driver(), CiDriverType::Local)
+            {
+                Driver::check_signing_files(
+                    &CheckKeyPairOpts::builder().dir(Path::new(".")).build(),
+                )?;
+            }
+            self.login()?;
         }
 
         let tempdir = if let Some(ref dir) = self.tempdir {
@@ -175,6 +635,7 @@ impl BlueBuildCommand for BuildCommand {
         } else {
             TempDir::new().into_diagnostic()?
         };
+        let _tempdir_cleanup = CleanupGuard::new(CleanupItem::Dir(tempdir.path().to_owned()));
 
         #[cfg(feature = "multi-recipe")]
         {
@@ -195,18 +656,27 @@ impl BlueBuildCommand for BuildCommand {
                 recipes.into_iter().filter(|recipe| same.insert(recipe.clone())).collect()
             });
 
+            self.resolve_base_images(&recipe_paths);
+
+            let recipe_paths = self.changed_recipes(recipe_paths)?;
+
+            if recipe_paths.is_empty() {
+                info!("All images are up to date");
+                return Ok(Vec::new());
+            }
+            self.check_space(&recipe_paths)?;
+
             recipe_paths.par_iter().try_for_each(|recipe| {
-                GenerateCommand::builder()
-                    .output(tempdir.path().join(if recipe_paths.len() > 1 {
-                        blue_build_utils::generate_containerfile_path(recipe)?
-                    } else {
-                        PathBuf::from(CONTAINER_FILE)
-                    }))
-                    .platform(self.platform)
-                    .recipe(recipe)
-                    .drivers(self.drivers)
-                    .build()
-                    .try_run()
+                metrics::time_phase(&recipe.display().to_string(), "generate", || {
+                    self.generate(
+                        recipe,
+                        &tempdir.path().join(if recipe_paths.len() > 1 {
+                            blue_build_utils::generate_containerfile_path(recipe)?
+                        } else {
+                            PathBuf::from(CONTAINER_FILE)
+                        }),
+                    )
+                })
             })?;
 
             self.start(&recipe_paths, tempdir.path())
@@ -225,41 +695,254 @@ impl BlueBuildCommand for BuildCommand {
                 }
             });
 
+            if self.skip_unchanged && self.is_unchanged(&recipe_path)? {
+                return Ok(Vec::new());
+            }
+            self.check_space(std::slice::from_ref(&recipe_path))?;
+
+            metrics::time_phase(&recipe_path.display().to_string(), "generate", || {
+                self.generate(&recipe_path, &tempdir.path().join(CONTAINER_FILE))
+            })?;
+
+            self.start(&recipe_path, tempdir.path())
+        }
+    }
+
+    /// Makes sure container storage, the tempdir, and the archive
+    /// dir have enough free space for the build. The space is
+    /// estimated from the size of the base images unless it's given.
+    fn check_space(&self, recipe_paths: &[PathBuf]) -> Result<()> {
+        if self.skip_preflight {
+            return Ok(());
+        }
+        let base_size = self.base_images_size(recipe_paths);
+        let estimate = |factor: u64| base_size.map(|size| ByteSize(size.saturating_mul(factor)));
+
+        #[cfg(feature = "rechunk")]
+        let (storage_factor, tempdir_default) = if self.rechunk {
+            (
+                STORAGE_FACTOR + RECHUNK_STORAGE_FACTOR,
+                Some(
+                    estimate(RECHUNK_TEMPDIR_FACTOR).map_or(RECHUNK_TEMPDIR_MIN_FREE, |size| {
+                        size.max(RECHUNK_TEMPDIR_MIN_FREE)
+                    }),
+                ),
+            )
+        } else {
+            (STORAGE_FACTOR, None)
+        };
+        #[cfg(not(feature = "rechunk"))]
+        let (storage_factor, tempdir_default) = (STORAGE_FACTOR, None);
+
+        let mut needed = Vec::new();
+        if let Some(required) = self.tempdir_min_free.or(tempdir_default) {
+            needed.push(
+                SpaceNeeded::builder()
+                    .path(self.tempdir.clone().unwrap_or_else(env::temp_dir))
+                    .required(required)
+                    .purpose("the tempdir")
+                    .build(),
+            );
+        }
+        if let Some(required) = self.storage_min_free.or_else(|| estimate(storage_factor)) {
+            match Driver::storage_dir() {
+                Ok(Some(dir)) => needed.push(
+                    SpaceNeeded::builder()
+                        .path(dir)
+                        .required(required)
+                        .purpose("container storage")
+                        .build(),
+                ),
+                Ok(None) => debug!("The build driver doesn't report its storage"),
+                Err(e) => warn!("Unable to check the free space in container storage:\n{e:?}"),
+            }
+        }
+        if let (Some(archive), Some(required)) = (&self.archive, estimate(ARCHIVE_FACTOR)) {
+            needed.push(
+                SpaceNeeded::builder()
+                    .path(archive)
+                    .required(required)
+                    .purpose("the archive")
+                    .build(),
+            );
+        }
+
+        preflight::check_space_needed(&needed)
+    }
+
+    /// The compressed size of the base images of the recipes,
+    /// or `None` if it isn't known for every base image.
+    fn base_images_size(&self, recipe_paths: &[PathBuf]) -> Option<u64> {
+        let base_images = recipe_paths
+            .iter()
+            .filter_map(|recipe_path| {
+                Recipe::parse(recipe_path)
+                    .and_then(|recipe| recipe.base_image_ref())
+                    .ok()
+            })
+            .map(|image| (image.to_string(), image))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        base_images
+            .values()
+            .map(|image| {
+                Driver::get_metadata(
+                    &GetMetadataOpts::builder()
+                        .image(image)
+                        .platform(self.platform)
+                        .build(),
+                )
+                .inspect_err(|e| debug!("Unable to get the size of {image}: {e:?}"))
+                .ok()?
+                .layers
+                .iter()
+                .map(|layer| layer.size)
+                .sum::<Option<u64>>()
+            })
+            .sum()
+    }
+
+    /// Generates the Containerfile for the recipe.
+    ///
+    /// When building stages in parallel, the Containerfile
+    /// that the stages are built from is generated next to it
+    /// and the stages are left out of the Containerfile.
+    fn generate(&self, recipe_path: &Path, containerfile: &Path) -> Result<()> {
+        let wrap = BuildError::wrap(BuildStep::Generate, recipe_path);
+        let stages = self.parallel_stages(&Recipe::parse(recipe_path).map_err(wrap)?);
+        let generate = |output: &Path, prebuilt_stages: Vec<String>| {
+            // Tracked along with the tempdir they are in, so that
+            // they are listed when cleaning up after a crash
+            generated::track(output);
+            generated::track(&templated_files::dir(containerfile));
             GenerateCommand::builder()
-                .output(tempdir.path().join(CONTAINER_FILE))
-                .recipe(&recipe_path)
-                .drivers(self.drivers)
+                .output(output)
+                .platform(self.platform)
+                .recipe(recipe_path)
+                .no_cache(self.no_generate_cache)
+                .module_paths(self.module_paths.clone())
+                .prebuilt_stages(prebuilt_stages)
+                .templated_files_dir(templated_files::dir(containerfile))
+                .offline(self.offline)
+                .skip_validation(self.skip_validation)
+                .lock(self.lock)
+                .no_verify_tools(self.no_verify_tools)
+                .drivers(self.drivers.clone())
                 .build()
-                .try_run()?;
+                .try_run()
+        };
 
-            self.start(&recipe_path, tempdir.path())
+        if !stages.is_empty() {
+            generate(&stages_containerfile(containerfile), Vec::new()).map_err(wrap)?;
         }
+        generate(containerfile, stages).map_err(wrap)
+    }
+
+    /// The names of the stages to build as their own images.
+    fn parallel_stages(&self, recipe: &Recipe) -> Vec<String> {
+        if !self.parallel_stages {
+            return Vec::new();
+        }
+
+        #[cfg(feature = "rechunk")]
+        if self.rechunk {
+            warn!(
+                "Stages can't be built in parallel when rechunking, building them with the image"
+            );
+            return Vec::new();
+        }
+
+        recipe
+            .stages_ext
+            .iter()
+            .flat_map(|stages_ext| &stages_ext.stages)
+            .filter_map(|stage| stage.required_fields.as_ref())
+            .map(|stage| stage.name.to_string())
+            .collect()
+    }
+
+    /// Inspects the base images of all the recipes at once.
+    ///
+    /// The results are cached by the inspect driver, so the
+    /// builds don't have to wait on the registry one at a time.
+    /// Errors are left for the builds to report.
+    #[cfg(feature = "multi-recipe")]
+    fn resolve_base_images(&self, recipe_paths: &[PathBuf]) {
+        use tokio::task::JoinSet;
+
+        let base_images = recipe_paths
+            .iter()
+            .filter_map(|recipe_path| {
+                Recipe::parse(recipe_path)
+                    .and_then(|recipe| recipe.base_image_ref())
+                    .inspect_err(|e| trace!("Skipping {}: {e:?}", recipe_path.display()))
+                    .ok()
+            })
+            .map(|image| (image.to_string(), image))
+            .collect::<std::collections::HashMap<_, _>>();
+
+        if base_images.len() < 2 {
+            return;
+        }
+        debug!("Resolving {} base images", base_images.len());
+
+        let platform = self.platform;
+        blue_build_process_management::block_on(async {
+            let mut lookups = JoinSet::new();
+            for image in base_images.into_values() {
+                lookups.spawn(async move {
+                    let result = Driver::get_metadata_async(
+                        &GetMetadataOpts::builder()
+                            .image(&image)
+                            .platform(platform)
+                            .build(),
+                    )
+                    .await;
+                    (image, result)
+                });
+            }
+
+            while let Some(lookup) = lookups.join_next().await {
+                match lookup {
+                    Ok((image, Err(e))) => debug!("Failed to resolve {image}: {e:?}"),
+                    Ok((image, Ok(_))) => trace!("Resolved {image}"),
+                    Err(e) => debug!("Failed to resolve base image: {e}"),
+                }
+            }
+        });
     }
-}
 
-impl BuildCommand {
     #[cfg(feature = "multi-recipe")]
-    fn start(&self, recipe_paths: &[PathBuf], temp_dir: &Path) -> Result<()> {
+    fn start(&self, recipe_paths: &[PathBuf], temp_dir: &Path) -> Result<Vec<ImageSummary>> {
         use rayon::prelude::*;
 
         trace!("BuildCommand::build_image()");
 
-        let images = recipe_paths
+        let (images, built) = recipe_paths
             .par_iter()
-            .try_fold(Vec::new, |mut images, recipe_path| -> Result<Vec<String>> {
-                let containerfile = temp_dir.join(if recipe_paths.len() > 1 {
-                    blue_build_utils::generate_containerfile_path(recipe_path)?
-                } else {
-                    PathBuf::from(CONTAINER_FILE)
-                });
-                images.extend(self.build(recipe_path, &containerfile)?);
-                Ok(images)
-            })
-            .try_reduce(Vec::new, |mut init, image_names| {
-                let color = gen_random_ansi_color();
-                init.extend(image_names.iter().map(|image| color_str(image, color)));
-                Ok(init)
-            })?;
+            .try_fold(
+                <(Vec<String>, Vec<ImageSummary>)>::default,
+                |(mut images, mut built), recipe_path| -> Result<_> {
+                    let containerfile = temp_dir.join(if recipe_paths.len() > 1 {
+                        blue_build_utils::generate_containerfile_path(recipe_path)?
+                    } else {
+                        PathBuf::from(CONTAINER_FILE)
+                    });
+                    let (image_names, image) = self.build(recipe_path, &containerfile)?;
+                    images.extend(image_names);
+                    built.push(image);
+                    Ok((images, built))
+                },
+            )
+            .try_reduce(
+                <(Vec<String>, Vec<ImageSummary>)>::default,
+                |(mut init, mut built), (image_names, other)| {
+                    let color = gen_random_ansi_color();
+                    init.extend(image_names.iter().map(|image| color_str(image, color)));
+                    built.extend(other);
+                    Ok((init, built))
+                },
+            )?;
 
         info!(
             "Finished building:\n{}",
@@ -269,14 +952,14 @@ impl BuildCommand {
                 .collect::<Vec<_>>()
                 .join("\n")
         );
-        Ok(())
+        Ok(built)
     }
 
     #[cfg(not(feature = "multi-recipe"))]
-    fn start(&self, recipe_path: &Path, temp_dir: &Path) -> Result<()> {
+    fn start(&self, recipe_path: &Path, temp_dir: &Path) -> Result<Vec<ImageSummary>> {
         trace!("BuildCommand::start()");
 
-        let images = self.build(recipe_path, &temp_dir.join(CONTAINER_FILE))?;
+        let (images, built) = self.build(recipe_path, &temp_dir.join(CONTAINER_FILE))?;
         let color = gen_random_ansi_color();
 
         info!(
@@ -287,98 +970,210 @@ impl BuildCommand {
                 .collect::<Vec<_>>()
                 .join("\n")
         );
-        Ok(())
+        Ok(vec![built])
+    }
+
+    fn build(
+        &self,
+        recipe_path: &Path,
+        containerfile: &Path,
+    ) -> Result<(Vec<String>, ImageSummary)> {
+        let recipe =
+            Recipe::parse(recipe_path).map_err(BuildError::wrap(BuildStep::Recipe, recipe_path))?;
+        let hooks = hooks::load(&recipe, self.allow_recipe_hooks)
+            .map_err(BuildError::wrap(BuildStep::Recipe, recipe_path))?;
+        let image_name = self.image_name(&recipe).ok();
+
+        self.build_hooked(
+            &recipe,
+            recipe_path,
+            containerfile,
+            &hooks,
+            image_name.as_deref(),
+        )
+        .inspect_err(|e| {
+            let env = HookEnv::builder()
+                .recipe(recipe_path)
+                .maybe_image(image_name.as_deref())
+                .error(strip_ansi(
+                    &e.chain()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(": "),
+                ))
+                .build();
+            if let Err(e) = hooks::run(&hooks, Hook::OnFailure, &env) {
+                warn!("Failed to run the on-failure hook:\n{e:?}");
+            }
+        })
+    }
+
+    /// Builds, signs, and records the image of the recipe
+    /// and runs the hooks of the build in between.
+    fn build_hooked(
+        &self,
+        recipe: &Recipe,
+        recipe_path: &Path,
+        containerfile: &Path,
+        hooks: &Hooks,
+        image_name: Option<&str>,
+    ) -> Result<(Vec<String>, ImageSummary)> {
+        let run_hook = |hook, env: &HookEnv| {
+            hooks::run(hooks, hook, env).map_err(BuildError::wrap(BuildStep::Hook, recipe_path))
+        };
+
+        run_hook(
+            Hook::PreBuild,
+            &HookEnv::builder()
+                .recipe(recipe_path)
+                .maybe_image(image_name)
+                .build(),
+        )?;
+
+        let slot = self
+            .host_jobs
+            .map(BuildLock::host_slot)
+            .transpose()
+            .map_err(BuildError::wrap(BuildStep::Build, recipe_path))?;
+        let (image, mut built, images) = self
+            .build_image(recipe, recipe_path, containerfile)
+            .map_err(BuildError::wrap(BuildStep::Build, recipe_path))?;
+        drop(slot);
+        run_hook(
+            Hook::PostBuild,
+            &HookEnv::builder()
+                .recipe(recipe_path)
+                .image(&built.image)
+                .tags(&built.tags)
+                .images(&images)
+                .build(),
+        )?;
+
+        if self.push && !self.no_sign {
+            metrics::time_phase(&built.recipe, "sign", || self.sign(&image))
+                .map_err(BuildError::wrap(BuildStep::Sign, recipe_path))?;
+            built.signed = true;
+        }
+        built.digest = self.pushed_digest(&image);
+
+        if self.push {
+            run_hook(
+                Hook::PostPush,
+                &HookEnv::builder()
+                    .recipe(recipe_path)
+                    .image(&built.image)
+                    .tags(&built.tags)
+                    .images(&images)
+                    .maybe_digest(built.digest.as_deref())
+                    .build(),
+            )?;
+
+            if self.rm_after_push {
+                self.remove_pushed(&images);
+            }
+        }
+
+        summary::record_image(built.clone());
+        Ok((images, built))
     }
 
-    fn build(&self, recipe_path: &Path, containerfile: &Path) -> Result<Vec<String>> {
-        let recipe = Recipe::parse(recipe_path)?;
+    /// Builds the image of the recipe, which is returned with
+    /// the names of the images that were built.
+    #[allow(clippy::too_many_lines)]
+    fn build_image(
+        &self,
+        recipe: &Recipe,
+        recipe_path: &Path,
+        containerfile: &Path,
+    ) -> Result<(Reference, ImageSummary, Vec<String>)> {
+        let recipe_display = recipe_path.display().to_string();
+        let alt_tags = self.alt_tags(recipe);
         let tags = Driver::generate_tags(
             &GenerateTagsOpts::builder()
-                .oci_ref(&recipe.base_image_ref()?)
-                .maybe_alt_tags(recipe.alt_tags.as_ref().map(CowCollecter::collect_cow_vec))
+                .oci_ref(&self.base_image_ref(recipe, recipe_path)?)
+                .maybe_alt_tags(alt_tags.as_ref().map(CowCollecter::collect_cow_vec))
                 .platform(self.platform)
                 .build(),
         )?;
-        let image_name = self.image_name(&recipe)?;
+        let image_name = self.image_name(recipe)?;
+        let secrets = build_secrets(recipe, &self.allow_secret_env)?;
+        let cache = BuildCache {
+            from: self.cache_from.clone(),
+            to: self.cache_to.clone(),
+        };
+        let build_contexts = templated_files::build_contexts(containerfile);
+        let stages = self.parallel_stages(recipe);
+        let stages_containerfile =
+            (!stages.is_empty()).then(|| stages_containerfile(containerfile));
         let image: Reference = format!("{image_name}:{}", tags.first().map_or("latest", |tag| tag))
             .parse()
             .into_diagnostic()?;
 
-        let build_fn = || -> Result<Vec<String>> {
-            Driver::build_tag_push(&self.archive.as_ref().map_or_else(
-                || {
-                    BuildTagPushOpts::builder()
-                        .image(&image)
-                        .containerfile(containerfile)
-                        .platform(self.platform)
-                        .tags(tags.collect_cow_vec())
-                        .push(self.push)
-                        .retry_push(self.retry_push)
-                        .retry_count(self.retry_count)
-                        .compression(self.compression_format)
-                        .squash(self.squash)
-                        .build()
-                },
-                |archive_dir| {
-                    BuildTagPushOpts::builder()
-                        .containerfile(containerfile)
-                        .platform(self.platform)
-                        .archive_path(PathBuf::from(format!(
-                            "{}/{}.{ARCHIVE_SUFFIX}",
-                            archive_dir.to_string_lossy().trim_end_matches('/'),
-                            recipe.name.to_lowercase().replace('/', "_"),
-                        )))
-                        .squash(self.squash)
-                        .build()
-                },
-            ))
-        };
+        self.log_extra_args();
 
-        #[cfg(feature = "rechunk")]
-        let images = if self.rechunk {
-            use blue_build_process_management::drivers::{
-                opts::{GetMetadataOpts, RechunkOpts},
-                InspectDriver, RechunkDriver,
-            };
-
-            let base_image: Reference = format!("{}:{}", &recipe.base_image, &recipe.image_version)
-                .parse()
-                .into_diagnostic()?;
-
-            Driver::rechunk(
-                &RechunkOpts::builder()
-                    .image(&image_name)
+        // Tested images are pushed once their tests pass
+        let tests = self.image_tests(recipe, recipe_path)?;
+        let build_opts = self.archive.as_ref().map_or_else(
+            || {
+                BuildTagPushOpts::builder()
+                    .image(&image)
                     .containerfile(containerfile)
                     .platform(self.platform)
                     .tags(tags.collect_cow_vec())
-                    .push(self.push)
-                    .version(format!(
-                        "{version}.<date>",
-                        version = Driver::get_os_version()
-                            .oci_ref(&recipe.base_image_ref()?)
-                            .platform(self.platform)
-                            .call()?,
-                    ))
+                    .push(self.push && !self.merges_layers() && tests.is_empty())
                     .retry_push(self.retry_push)
                     .retry_count(self.retry_count)
                     .compression(self.compression_format)
-                    .base_digest(
-                        Driver::get_metadata(
-                            &GetMetadataOpts::builder()
-                                .image(&base_image)
-                                .platform(self.platform)
-                                .build(),
-                        )?
-                        .digest,
-                    )
-                    .repo(Driver::get_repo_url()?)
-                    .name(&*recipe.name)
-                    .description(&*recipe.description)
-                    .base_image(format!("{}:{}", &recipe.base_image, &recipe.image_version))
-                    .maybe_tempdir(self.tempdir.as_deref())
-                    .clear_plan(self.rechunk_clear_plan)
-                    .build(),
-            )?
+                    .push_jobs(self.push_jobs)
+                    .squash(self.squash)
+                    .secrets(secrets.clone())
+                    .build_contexts(build_contexts.clone())
+                    .stages(stages.collect_cow_vec())
+                    .maybe_stages_containerfile(stages_containerfile.as_deref())
+                    .stage_jobs(self.jobs)
+                    .maybe_stages_tempdir(self.tempdir.as_deref())
+                    .cache(cache.clone())
+                    .proxy(!self.no_proxy)
+                    .pull(!self.offline)
+                    .build_extra_args(self.build_opts.clone())
+                    .push_extra_args(self.push_opts.clone())
+                    .build()
+            },
+            |archive_dir| {
+                BuildTagPushOpts::builder()
+                    .containerfile(containerfile)
+                    .platform(self.platform)
+                    .archive_path(archive_path(archive_dir, recipe))
+                    .squash(self.squash)
+                    .secrets(secrets.clone())
+                    .build_contexts(build_contexts.clone())
+                    .stages(stages.collect_cow_vec())
+                    .maybe_stages_containerfile(stages_containerfile.as_deref())
+                    .stage_jobs(self.jobs)
+                    .maybe_stages_tempdir(self.tempdir.as_deref())
+                    .cache(cache.clone())
+                    .proxy(!self.no_proxy)
+                    .pull(!self.offline)
+                    .build_extra_args(self.build_opts.clone())
+                    .build()
+            },
+        );
+        let build_fn = || -> Result<Vec<String>> {
+            let images = Driver::build_tag_push(&build_opts)?;
+            if !tests.is_empty() {
+                self.test_and_push(&image, tests, &images, &build_opts, recipe_path)?;
+            }
+            Ok(images)
+        };
+
+        let build_start = Instant::now();
+
+        #[cfg(feature = "rechunk")]
+        let images = if self.rechunk {
+            self.rechunk(recipe, &image_name, containerfile, &tags, &secrets)?
+        } else if let Some(max_layers) = self.max_layers {
+            build_fn()?;
+            self.merge_layers(&image, &tags, max_layers)?
         } else {
             build_fn()?
         };
@@ -386,37 +1181,743 @@ impl BuildCommand {
         #[cfg(not(feature = "rechunk"))]
         let images = build_fn()?;
 
-        if self.push && !self.no_sign {
-            Driver::sign_and_verify(
-                &SignVerifyOpts::builder()
-                    .image(&image)
-                    .retry_push(self.retry_push)
-                    .retry_count(self.retry_count)
-                    .platform(self.platform)
-                    .build(),
+        metrics::record_phase(&recipe_display, "build", build_start.elapsed());
+        let size = self.image_size(recipe, &image);
+        if let Some(size) = size {
+            metrics::record_image_size(&recipe_display, &image.to_string(), size);
+        }
+
+        let built = ImageSummary::builder()
+            .recipe(recipe_display)
+            .image(image_name)
+            .tags(tags)
+            .maybe_size(size)
+            .pushed(self.push)
+            .build();
+
+        Ok((image, built, images))
+    }
+
+    /// The base image that the OS version of the tags is taken
+    /// from. When offline, this is the base image at the digest in
+    /// the lockfile, which is inspected in local storage.
+    fn base_image_ref(&self, recipe: &Recipe, recipe_path: &Path) -> Result<Reference> {
+        if self.offline {
+            return format!(
+                "{}@{}",
+                recipe.base_image,
+                Lockfile::load(recipe_path)?.base_digest
+            )
+            .parse()
+            .into_diagnostic();
+        }
+        recipe.base_image_ref()
+    }
+
+    #[cfg(feature = "rechunk")]
+    fn rechunk(
+        &self,
+        recipe: &Recipe,
+        image_name: &str,
+        containerfile: &Path,
+        tags: &[String],
+        secrets: &[BuildSecret],
+    ) -> Result<Vec<String>> {
+        use blue_build_process_management::drivers::{opts::RechunkOpts, RechunkDriver};
+
+        use crate::labels;
+
+        let base_image: Reference = format!("{}:{}", recipe.base_image, recipe.image_version)
+            .parse()
+            .into_diagnostic()?;
+        let base_digest = Driver::get_metadata(
+            &GetMetadataOpts::builder()
+                .image(&base_image)
+                .platform(self.platform)
+                .build(),
+        )?
+        .digest;
+
+        Driver::rechunk(
+            &RechunkOpts::builder()
+                .image(image_name)
+                .containerfile(containerfile)
+                .platform(self.platform)
+                .tags(tags.collect_cow_vec())
+                .push(self.push)
+                .version(format!(
+                    "{version}.<date>",
+                    version = Driver::get_os_version()
+                        .oci_ref(&recipe.base_image_ref()?)
+                        .platform(self.platform)
+                        .call()?,
+                ))
+                .retry_push(self.retry_push)
+                .retry_count(self.retry_count)
+                .compression(self.compression_format)
+                .push_jobs(self.push_jobs)
+                .maybe_push_concurrency(self.push_concurrency)
+                .labels(
+                    labels::generate_default_labels()
+                        .recipe(recipe)
+                        .base_digest(&base_digest)
+                        .repo(&Driver::get_repo_url()?)
+                        .maybe_revision(Driver::get_commit_sha().as_deref())
+                        .call(),
+                )
+                .maybe_tempdir(self.tempdir.as_deref())
+                .rechunker(self.rechunker)
+                .maybe_max_layers(self.max_layers)
+                .clear_plan(self.rechunk_clear_plan)
+                .secrets(secrets.to_vec())
+                .build_contexts(templated_files::build_contexts(containerfile))
+                .build_extra_args(self.build_opts.clone())
+                .push_extra_args(self.push_opts.clone())
+                .build(),
+        )
+    }
+
+    #[cfg(feature = "rechunk")]
+    fn merge_layers(
+        &self,
+        image: &Reference,
+        tags: &[String],
+        max_layers: NonZeroUsize,
+    ) -> Result<Vec<String>> {
+        use blue_build_process_management::drivers::{opts::MergeLayersOpts, LayerMergeDriver};
+
+        blue_build_utils::check_command_exists("skopeo")?;
+
+        Driver::merge_layers(
+            &MergeLayersOpts::builder()
+                .image(image)
+                .max_layers(max_layers)
+                .keep_size(self.merge_keep_size.0)
+                .tags(tags.collect_cow_vec())
+                .push(self.push)
+                .retry_count(self.retry_count)
+                .push_jobs(self.push_jobs)
+                .maybe_tempdir(self.tempdir.as_deref())
+                .push_extra_args(self.push_opts.clone())
+                .build(),
+        )
+    }
+
+    /// Logs the raw options that are passed to the tools
+    /// so that the build can be reproduced from the logs.
+    fn log_extra_args(&self) {
+        for (kind, args) in [("build", &self.build_opts), ("push", &self.push_opts)] {
+            if !args.is_empty() {
+                info!(
+                    "Passing {kind} options: {}",
+                    args.iter()
+                        .map(ToString::to_string)
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                );
+            }
+        }
+    }
+
+    /// The tests of the recipe, which can only be run
+    /// against an image that's in local storage.
+    ///
+    /// # Errors
+    /// Will error if the recipe has tests that can't be run
+    /// because the image is archived or rechunked.
+    fn image_tests<'a>(&self, recipe: &'a Recipe, recipe_path: &Path) -> Result<&'a [ImageTest]> {
+        if self.skip_tests || recipe.tests.is_empty() {
+            return Ok(&[]);
+        }
+
+        #[cfg(feature = "rechunk")]
+        let local = self.archive.is_none() && !self.rechunk;
+        #[cfg(not(feature = "rechunk"))]
+        let local = self.archive.is_none();
+
+        if !local {
+            bail!(
+                help = "Build without `--archive` or `--rechunk` to run the tests, or use `--skip-tests`",
+                "The tests of {} can't run when archiving or rechunking",
+                recipe_path.display()
+            );
+        }
+        Ok(&recipe.tests)
+    }
+
+    /// Tests the built image, and pushes its tagged
+    /// images once the tests pass when pushing.
+    fn test_and_push(
+        &self,
+        image: &Reference,
+        tests: &[ImageTest],
+        images: &[String],
+        build_opts: &BuildTagPushOpts,
+        recipe_path: &Path,
+    ) -> Result<()> {
+        image_tests::check(image, tests).map_err(BuildError::wrap(BuildStep::Test, recipe_path))?;
+
+        if self.push && !self.merges_layers() {
+            Driver::push_tagged(
+                &images
+                    .iter()
+                    .map(|image| image.parse())
+                    .collect::<Result<Vec<Reference>, _>>()
+                    .into_diagnostic()?,
+                build_opts,
             )?;
         }
+        Ok(())
+    }
+
+    /// Removes the tagged images of a build from local storage
+    /// after they're pushed. Failing to remove them doesn't
+    /// fail the build since the images were already pushed.
+    fn remove_pushed(&self, images: &[String]) {
+        // Rechunked images are pushed from a directory
+        // and are never in local storage
+        #[cfg(feature = "rechunk")]
+        if self.rechunk {
+            return;
+        }
 
-        Ok(images)
+        info!("Removing {} from local storage", images.join(", "));
+        if let Err(e) = Driver::remove_local_images(images) {
+            warn!("Failed to remove the pushed images:\n{e:?}");
+        }
     }
 
-    fn image_name(&self, recipe: &Recipe) -> Result<String> {
-        let image_name = Driver::generate_image_name(
-            GenerateImageNameOpts::builder()
-                .name(recipe.name.trim())
-                .maybe_registry(self.credentials.registry.as_ref().map(|r| cowstr!(r)))
-                .maybe_registry_namespace(self.registry_namespace.as_ref().map(|r| cowstr!(r)))
+    /// Whether the image is pushed after its layers are
+    /// merged instead of being pushed by the build.
+    #[cfg_attr(not(feature = "rechunk"), allow(clippy::unused_self))]
+    const fn merges_layers(&self) -> bool {
+        #[cfg(feature = "rechunk")]
+        {
+            self.max_layers.is_some() && !self.rechunk
+        }
+
+        #[cfg(not(feature = "rechunk"))]
+        {
+            false
+        }
+    }
+
+    fn sign(&self, image: &Reference) -> Result<()> {
+        Driver::sign_and_verify(
+            &SignVerifyOpts::builder()
+                .image(image)
+                .retry_push(self.retry_push)
+                .retry_count(self.retry_count)
+                .platform(self.platform)
+                .build(),
+        )
+    }
+
+    /// Whether a summary of the build is reported to the CI system.
+    fn reports_summary() -> bool {
+        !matches!(Driver::get_ci_driver(), CiDriverType::Local)
+    }
+
+    /// The digest of the pushed image for the build summary.
+    fn pushed_digest(&self, image: &Reference) -> Option<String> {
+        if !self.push || !Self::reports_summary() {
+            return None;
+        }
+
+        Driver::get_metadata(
+            &GetMetadataOpts::builder()
+                .image(image)
+                .platform(self.platform)
+                .fresh(true)
+                .build(),
+        )
+        .inspect_err(|e| debug!("Failed to get the digest of {image}: {e:?}"))
+        .ok()
+        .map(|metadata| metadata.digest)
+    }
+
+    /// The size of the built image for metrics and the build summary.
+    ///
+    /// Archived images use the size of the archive file,
+    /// otherwise the local image is inspected when present.
+    fn image_size(&self, recipe: &Recipe, image: &Reference) -> Option<u64> {
+        if self.metrics_textfile.is_none()
+            && self.metrics_pushgateway.is_none()
+            && !Self::reports_summary()
+        {
+            return None;
+        }
+
+        self.archive.as_ref().map_or_else(
+            || {
+                Driver::get_local_metadata(
+                    &GetMetadataOpts::builder()
+                        .image(image)
+                        .platform(self.platform)
+                        .build(),
+                )
+                .inspect_err(|e| debug!("Unable to get the size of {image}: {e:?}"))
+                .ok()?
+                .size
+            },
+            |archive_dir| {
+                fs::metadata(archive_path(archive_dir, recipe))
+                    .ok()
+                    .map(|meta| meta.len())
+            },
+        )
+    }
+
+    /// Filters out the recipes that haven't changed
+    /// when `--skip-unchanged` is used.
+    #[cfg(feature = "multi-recipe")]
+    fn changed_recipes(&self, recipe_paths: Vec<PathBuf>) -> Result<Vec<PathBuf>> {
+        if !self.skip_unchanged {
+            return Ok(recipe_paths);
+        }
+
+        let mut changed = Vec::with_capacity(recipe_paths.len());
+        for recipe_path in recipe_paths {
+            if !self.is_unchanged(&recipe_path)? {
+                changed.push(recipe_path);
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Whether the image last pushed for the recipe has
+    /// the same content hash as the current inputs.
+    fn is_unchanged(&self, recipe_path: &Path) -> Result<bool> {
+        let mut recipe = Recipe::parse(recipe_path)?;
+        let base_image = recipe.base_image_ref()?;
+        let alt_tags = self.alt_tags(&recipe);
+        let tags = Driver::generate_tags(
+            &GenerateTagsOpts::builder()
+                .oci_ref(&base_image)
+                .maybe_alt_tags(alt_tags.as_ref().map(CowCollecter::collect_cow_vec))
+                .platform(self.platform)
                 .build(),
         )?;
+        let image: Reference = format!(
+            "{}:{}",
+            self.image_name(&recipe)?,
+            tags.first().map_or("latest", |tag| tag)
+        )
+        .parse()
+        .into_diagnostic()?;
 
-        let image_name = if image_name.registry().is_empty() {
-            string!(image_name.repository())
-        } else if image_name.registry() == "" {
-            image_name.repository().to_string()
-        } else {
-            format!("{}/{}", image_name.registry(), image_name.repository())
+        let base_digest = Driver::get_metadata(
+            &GetMetadataOpts::builder()
+                .image(&base_image)
+                .platform(self.platform)
+                .build(),
+        )?
+        .digest;
+        let mut module_sources = git_modules::pin(&mut recipe)?;
+        module_sources.extend(
+            module_overrides::prepare(&self.module_paths)?
+                .iter()
+                .map(ModuleOverride::source),
+        );
+        let content_hash =
+            build_inputs_hash(recipe_path, &base_digest, self.platform, &module_sources)?;
+        debug!("Content hash for {}: {content_hash}", recipe_path.display());
+
+        let pushed_hash = match Driver::get_metadata(
+            &GetMetadataOpts::builder()
+                .image(&image)
+                .platform(self.platform)
+                .build(),
+        ) {
+            Ok(metadata) => metadata
+                .labels
+                .get(CONTENT_HASH_LABEL)
+                .and_then(|hash| hash.as_str().map(ToOwned::to_owned)),
+            Err(e) => {
+                debug!("Unable to inspect {image}, it will be built:\n{e:?}");
+                None
+            }
+        };
+
+        let unchanged = pushed_hash.is_some_and(|hash| hash == content_hash);
+        if unchanged {
+            info!(
+                "Skipping {}, {image} was built from the same inputs",
+                recipe_path.display()
+            );
+        }
+        Ok(unchanged)
+    }
+
+    /// The alt tags of the recipe whose conditions
+    /// match the branch and platform of the build.
+    fn alt_tags(&self, recipe: &Recipe) -> Option<Vec<String>> {
+        recipe.matching_alt_tags(Driver::get_branch().as_deref(), &self.platform.to_string())
+    }
+
+    fn image_name(&self, recipe: &Recipe) -> Result<String> {
+        image_name(
+            recipe,
+            self.credentials.registry.as_deref(),
+            self.registry_namespace.as_deref(),
+        )
+    }
+}
+
+/// The name of the image of the recipe without a tag, like
+/// `ghcr.io/octocat/my-image`, in the registry and namespace
+/// that the image is pushed to.
+pub(crate) fn image_name(
+    recipe: &Recipe,
+    registry: Option<&str>,
+    registry_namespace: Option<&str>,
+) -> Result<String> {
+    let image_name = Driver::generate_image_name(
+        GenerateImageNameOpts::builder()
+            .name(recipe.name.trim())
+            .maybe_registry(registry.map(|r| cowstr!(r)))
+            .maybe_registry_namespace(registry_namespace.map(|r| cowstr!(r)))
+            .build(),
+    )?;
+
+    Ok(if image_name.registry().is_empty() {
+        string!(image_name.repository())
+    } else {
+        format!("{}/{}", image_name.registry(), image_name.repository())
+    })
+}
+
+/// Gets the secrets that the modules of the recipe
+/// and the signing of its kernel modules use.
+///
+/// The secrets are checked before building so that a
+/// missing secret doesn't fail the build part way through.
+/// Since the modules of the recipe get the secrets, a recipe
+/// can only read the env vars in `allowed_env` and the files
+/// in the project.
+fn build_secrets(recipe: &Recipe, allowed_env: &[String]) -> Result<Vec<BuildSecret>> {
+    let project_dir = env::current_dir()
+        .and_then(fs::canonicalize)
+        .into_diagnostic()?;
+
+    recipe
+        .secrets
+        .iter()
+        .filter(|secret| {
+            recipe
+                .all_modules()
+                .any(|module| module.secrets.iter().any(|s| s == secret.name()))
+                || (recipe.signs_akmods()
+                    && recipe
+                        .mok
+                        .as_ref()
+                        .is_some_and(|mok| mok.key == secret.name()))
+        })
+        .map(|secret| {
+            let source = match secret.source() {
+                Some(RecipeSecretSource::Env(var)) => {
+                    if !allowed_env.iter().any(|allowed| allowed == var) {
+                        bail!(
+                            help = format!(
+                                "Pass `--allow-secret-env {var}`, add it to {BB_BUILD_ALLOW_SECRET_ENV}, or set `allow-secret-env` in the `[build]` table of the user config"
+                            ),
+                            "The secret {} is read from the environment variable {var}, which isn't allowed",
+                            secret.name()
+                        );
+                    }
+                    if env::var_os(var).is_none() {
+                        bail!(
+                            "The secret {} is read from the environment variable {var}, which isn't set",
+                            secret.name()
+                        );
+                    }
+                    BuildSecretSource::Env(var.to_owned())
+                }
+                Some(RecipeSecretSource::File(file)) => {
+                    if !Path::new(file).is_file() {
+                        bail!(
+                            "The secret {} is read from the file {file}, which doesn't exist",
+                            secret.name()
+                        );
+                    }
+                    let path = fs::canonicalize(file).into_diagnostic()?;
+                    if !path.starts_with(&project_dir) {
+                        bail!(
+                            "The secret {} is read from the file {file}, which is outside of the project",
+                            secret.name()
+                        );
+                    }
+                    BuildSecretSource::File(path)
+                }
+                None => bail!("The secret {} has more than one source", secret.name()),
+            };
+            Ok(BuildSecret {
+                id: secret.name().to_owned(),
+                source,
+            })
+        })
+        .collect()
+}
+
+/// The Containerfile that the stages are built from
+/// when building them in parallel.
+/// Removes the temp dirs that builds which crashed left behind.
+fn clean_generated() {
+    match generated::clean_stale(false) {
+        Ok(removed) if !removed.is_empty() => info!(
+            "Removed {} files left behind by builds that crashed",
+            removed.len()
+        ),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to clean up after builds that crashed:\n{e:?}"),
+    }
+}
+
+fn stages_containerfile(containerfile: &Path) -> PathBuf {
+    PathBuf::from(format!("{}.stages", containerfile.display()))
+}
+
+fn archive_path(archive_dir: &Path, recipe: &Recipe) -> PathBuf {
+    PathBuf::from(format!(
+        "{}/{}.{ARCHIVE_SUFFIX}",
+        archive_dir.to_string_lossy().trim_end_matches('/'),
+        recipe.name.to_lowercase().replace('/', "_"),
+    ))
+}
+
+#[cfg(all(test, feature = "test", feature = "multi-recipe"))]
+mod test {
+    use std::{
+        collections::{HashMap, HashSet},
+        fs,
+        path::{Path, PathBuf},
+        sync::{Mutex, MutexGuard, PoisonError},
+    };
+
+    use blue_build_process_management::drivers::{
+        types::ImageMetadata, Driver, MockDriver, MockResults,
+    };
+    use blue_build_recipe::Recipe;
+    use tempfile::TempDir;
+
+    use super::{build_secrets, BuildCommand};
+
+    /// The mock driver is shared by the whole process,
+    /// so the tests that use it can't run at the same time.
+    static MOCK: Mutex<()> = Mutex::new(());
+
+    const RECIPE: &str = r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+modules: []
+";
+
+    fn mock(results: MockResults) -> MutexGuard<'static, ()> {
+        let guard = MOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        MockDriver::set_results(results);
+        Driver::init(MockDriver::args());
+        _ = MockDriver::take_calls();
+        guard
+    }
+
+    fn recipe(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join("recipe.yml");
+        fs::write(&path, RECIPE).unwrap();
+        path
+    }
+
+    /// The build, push, and signing operations that were done.
+    fn operations() -> Vec<&'static str> {
+        MockDriver::take_calls()
+            .into_iter()
+            .filter(|call| matches!(call.driver, "build" | "signing"))
+            .map(|call| call.operation)
+            .collect()
+    }
+
+    #[test]
+    fn build_and_push() {
+        let _mock = mock(MockResults::default());
+        let dir = TempDir::new().unwrap();
+
+        let summaries = BuildCommand::builder()
+            .recipe(vec![recipe(&dir)])
+            .push(true)
+            .skip_validation(true)
+            .no_verify_tools(true)
+            .build()
+            .build_images()
+            .unwrap();
+
+        assert_eq!(
+            operations(),
+            [
+                "check_signing_files",
+                "login",
+                "signing_login",
+                "build",
+                "tag",
+                "push",
+                "sign",
+                "verify"
+            ]
+        );
+        assert_eq!(summaries.len(), 1);
+        assert!(summaries[0].pushed);
+        assert!(summaries[0].signed);
+    }
+
+    #[test]
+    fn offline_cant_push() {
+        let _mock = mock(MockResults::default());
+        let dir = TempDir::new().unwrap();
+
+        let err = BuildCommand::builder()
+            .recipe(vec![recipe(&dir)])
+            .push(true)
+            .offline(true)
+            .build()
+            .build_images()
+            .unwrap_err();
+
+        assert!(err.to_string().contains("cannot use '--offline'"));
+        assert_eq!(operations(), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn build_without_push() {
+        let _mock = mock(MockResults::default());
+        let dir = TempDir::new().unwrap();
+
+        BuildCommand::builder()
+            .recipe(vec![recipe(&dir)])
+            .skip_validation(true)
+            .no_verify_tools(true)
+            .build()
+            .build_images()
+            .unwrap();
+
+        assert_eq!(operations(), ["build", "tag"]);
+    }
+
+    #[test]
+    fn skip_unchanged_reports_pushed_digest() {
+        let image = "localhost/test:latest";
+        let metadata = |digest: char| ImageMetadata {
+            digest: format!("sha256:{}", digest.to_string().repeat(64)),
+            ..ImageMetadata::default()
         };
+        let _mock = mock(
+            MockResults::builder()
+                .images(HashMap::from([(image.to_owned(), metadata('1'))]))
+                .pushed(HashMap::from([(image.to_owned(), metadata('2'))]))
+                .build(),
+        );
+        let dir = TempDir::new().unwrap();
+
+        // The image is inspected for its content hash before it's built,
+        // which mustn't be the digest that's signed and reported after
+        let summaries = BuildCommand::builder()
+            .recipe(vec![recipe(&dir)])
+            .push(true)
+            .skip_unchanged(true)
+            .skip_validation(true)
+            .no_verify_tools(true)
+            .build()
+            .build_images()
+            .unwrap();
+
+        let pushed = metadata('2').digest;
+        assert_eq!(summaries[0].digest.as_deref(), Some(pushed.as_str()));
+        let signed = MockDriver::take_calls()
+            .into_iter()
+            .find(|call| call.operation == "sign")
+            .unwrap();
+        assert_eq!(signed.opts["image"], format!("localhost/test@{pushed}"));
+    }
+
+    #[test]
+    fn failed_push_isnt_signed() {
+        let _mock = mock(
+            MockResults::builder()
+                .failures(HashSet::from(["push"]))
+                .build(),
+        );
+        let dir = TempDir::new().unwrap();
+
+        let err = BuildCommand::builder()
+            .recipe(vec![recipe(&dir)])
+            .push(true)
+            .skip_validation(true)
+            .no_verify_tools(true)
+            .build()
+            .build_images()
+            .unwrap_err();
+
+        assert!(format!("{err:?}").contains("The mock build driver failed to push"));
+        assert!(!operations().contains(&"sign"));
+    }
+
+    #[test]
+    fn tests_must_run_or_be_skipped() {
+        let recipe = Recipe::from_yaml(&format!("{RECIPE}tests:\n  - command: 'true'\n")).unwrap();
+        let recipe_path = Path::new("recipe.yml");
+        let archive = |skip_tests| {
+            BuildCommand::builder()
+                .archive(PathBuf::from("archive"))
+                .skip_tests(skip_tests)
+                .build()
+        };
+
+        let err = archive(false)
+            .image_tests(&recipe, recipe_path)
+            .unwrap_err();
+        assert!(err.to_string().contains("can't run when archiving"));
+
+        assert_eq!(archive(true).image_tests(&recipe, recipe_path).unwrap(), []);
+        assert_eq!(
+            BuildCommand::builder()
+                .build()
+                .image_tests(&recipe, recipe_path)
+                .unwrap(),
+            recipe.tests
+        );
+    }
+
+    #[test]
+    fn secrets_must_be_allowed() {
+        let outside = tempfile::NamedTempFile::new().unwrap();
+        let recipe = |env: &str, file: &str| {
+            Recipe::from_yaml(&format!(
+                r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+secrets:
+  - name: TOKEN
+    env: {env}
+  - name: KEY
+    file: {file}
+modules:
+  - type: script
+    secrets: [TOKEN, KEY]
+"
+            ))
+            .unwrap()
+        };
+
+        let secrets = build_secrets(&recipe("PATH", "./Cargo.toml"), &["PATH".into()]).unwrap();
+        assert_eq!(secrets.len(), 2);
 
-        Ok(image_name)
+        assert!(build_secrets(&recipe("PATH", "./Cargo.toml"), &[]).is_err());
+        assert!(build_secrets(&recipe("PATH", "../Cargo.toml"), &["PATH".into()]).is_err());
+        assert!(build_secrets(
+            &recipe("PATH", &outside.path().display().to_string()),
+            &["PATH".into()]
+        )
+        .is_err());
     }
 }
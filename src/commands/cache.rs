@@ -0,0 +1,429 @@
+//! Shows and cleans the files that bluebuild caches on this machine.
+//!
+//! Only the caches on disk are covered. The metadata of inspected
+//! images and the downloaded JSON schemas are only cached in memory
+//! for the length of a run.
+
+use std::{
+    fmt::Write as _,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+use blue_build_process_management::{logging::Logger, preflight::ByteSize};
+use blue_build_utils::constants::DRIVER_VERSIONS_CACHE_FILE;
+use bon::Builder;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::{Args, Subcommand, ValueEnum};
+use colored::Colorize;
+use log::{debug, info, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::{generate, BlueBuildCommand};
+use crate::output::{self, TableOutput};
+
+const DAY: Duration = Duration::from_hours(24);
+
+#[derive(Debug, Clone, Args)]
+pub struct CacheCommand {
+    #[command(subcommand)]
+    command: CacheSubcommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum CacheSubcommand {
+    /// Show what the caches are using space for.
+    Stats(CacheStatsCommand),
+
+    /// Delete cached files by their age or
+    /// the space that the caches use.
+    Clean(CacheCleanCommand),
+}
+
+impl BlueBuildCommand for CacheCommand {
+    fn try_run(&mut self) -> Result<()> {
+        match &mut self.command {
+            CacheSubcommand::Stats(command) => command.try_run(),
+            CacheSubcommand::Clean(command) => command.try_run(),
+        }
+    }
+}
+
+/// A cache that bluebuild keeps on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum, Serialize, JsonSchema)]
+#[serde(rename_all = "kebab-case")]
+pub enum CacheKind {
+    /// The log files of earlier runs.
+    Logs,
+
+    /// The Containerfiles rendered by `generate`.
+    Containerfiles,
+
+    /// The repos of git sourced modules.
+    Git,
+
+    /// The files of remote `from-file` includes.
+    Includes,
+
+    /// The versions of the driver binaries.
+    DriverVersions,
+}
+
+impl CacheKind {
+    /// Where the entries of the cache are, or
+    /// `None` if there isn't a cache directory.
+    fn path(self) -> Option<PathBuf> {
+        let cache_dir = blue_build_utils::cache_dir();
+
+        match self {
+            Self::Logs => Some(Logger::log_dir()),
+            Self::Containerfiles => generate::cache::cache_dir(),
+            Self::Git => cache_dir.map(|dir| dir.join("git")),
+            Self::Includes => cache_dir.map(|dir| dir.join("includes")),
+            Self::DriverVersions => cache_dir.map(|dir| dir.join(DRIVER_VERSIONS_CACHE_FILE)),
+        }
+    }
+
+    /// The entries of the cache, which are removed
+    /// as a whole, like the repo of a git module.
+    fn entries(self) -> Result<Vec<CacheEntry>> {
+        let Some(path) = self.path() else {
+            return Ok(Vec::new());
+        };
+
+        let paths = match self {
+            Self::DriverVersions => vec![path],
+            Self::Logs => read_dir(&path)?
+                .into_iter()
+                // The log of this run is still being written
+                .filter(|path| {
+                    path.extension().is_some_and(|ext| ext == "log")
+                        && path
+                            .file_name()
+                            .is_some_and(|name| name != Logger::LOG_FILENAME)
+                })
+                .collect(),
+            _ => read_dir(&path)?,
+        };
+
+        paths
+            .into_iter()
+            .filter_map(|path| match usage(&path) {
+                Ok((size, modified)) => Some(Ok(CacheEntry {
+                    kind: self,
+                    path,
+                    size,
+                    modified,
+                })),
+                Err(e) if e.kind() == ErrorKind::NotFound => None,
+                Err(e) => Some(
+                    Err(e)
+                        .into_diagnostic()
+                        .with_context(|| format!("Failed to read {}", path.display())),
+                ),
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CacheEntry {
+    kind: CacheKind,
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<PathBuf>> {
+    match fs::read_dir(dir) {
+        Ok(entries) => entries
+            .map(|entry| entry.map(|entry| entry.path()).into_diagnostic())
+            .collect(),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to read {}", dir.display())),
+    }
+}
+
+/// The size of everything under `path` and when
+/// anything under it was last modified.
+fn usage(path: &Path) -> std::io::Result<(u64, SystemTime)> {
+    let metadata = fs::symlink_metadata(path)?;
+    let mut size = metadata.len();
+    let mut modified = metadata.modified()?;
+
+    if metadata.is_dir() {
+        size = 0;
+        for entry in fs::read_dir(path)? {
+            let (entry_size, entry_modified) = usage(&entry?.path())?;
+            size += entry_size;
+            modified = modified.max(entry_modified);
+        }
+    }
+    Ok((size, modified))
+}
+
+fn rfc3339(time: SystemTime) -> String {
+    DateTime::<Utc>::from(time).to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct CacheStatsCommand {}
+
+/// The space used by the caches of bluebuild.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct CacheStats {
+    caches: Vec<CacheStat>,
+
+    /// The total size of the caches in bytes.
+    size: u64,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct CacheStat {
+    cache: CacheKind,
+    path: Option<PathBuf>,
+    entries: usize,
+
+    /// The size of the cache in bytes.
+    size: u64,
+
+    /// When the least recently used entry was last used, in RFC 3339.
+    oldest: Option<String>,
+}
+
+impl BlueBuildCommand for CacheStatsCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("CacheStatsCommand::try_run()");
+
+        let caches = CacheKind::value_variants()
+            .iter()
+            .map(|&kind| {
+                let entries = kind.entries()?;
+                Ok(CacheStat {
+                    cache: kind,
+                    path: kind.path(),
+                    entries: entries.len(),
+                    size: entries.iter().map(|entry| entry.size).sum(),
+                    oldest: entries
+                        .iter()
+                        .map(|entry| entry.modified)
+                        .min()
+                        .map(rfc3339),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        output::print(&CacheStats {
+            size: caches.iter().map(|cache| cache.size).sum(),
+            caches,
+        })
+    }
+}
+
+impl TableOutput for CacheStats {
+    fn to_table(&self) -> String {
+        let mut out = String::new();
+
+        for cache in &self.caches {
+            let name = cache
+                .cache
+                .to_possible_value()
+                .map(|value| value.get_name().to_owned())
+                .unwrap_or_default();
+            let _ = writeln!(
+                out,
+                "{:<16} {:>6} entries {:>10}  {}",
+                name.bold(),
+                cache.entries,
+                ByteSize(cache.size).to_string(),
+                cache
+                    .path
+                    .as_ref()
+                    .map_or_else(
+                        || "no cache directory".into(),
+                        |path| path.display().to_string()
+                    )
+                    .dimmed()
+            );
+        }
+        let _ = writeln!(
+            out,
+            "{:<16} {:>25}",
+            "total".bold(),
+            ByteSize(self.size).to_string()
+        );
+        out
+    }
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct CacheCleanCommand {
+    /// Delete the entries that haven't been used in this many days.
+    #[arg(long, value_name = "DAYS")]
+    older_than: Option<u64>,
+
+    /// Delete the least recently used entries until
+    /// the caches use at most this much space, like `1G`.
+    #[arg(long, value_name = "SIZE")]
+    max_size: Option<ByteSize>,
+
+    /// Delete everything in the caches.
+    #[arg(long, conflicts_with_all = ["older_than", "max_size"])]
+    #[builder(default)]
+    all: bool,
+
+    /// The caches to clean. Can be used more than once.
+    ///
+    /// Defaults to every cache.
+    #[arg(long = "cache", value_name = "CACHE")]
+    #[builder(default, into)]
+    caches: Vec<CacheKind>,
+
+    /// Show what would be deleted without deleting it.
+    #[arg(long)]
+    #[builder(default)]
+    dry_run: bool,
+}
+
+impl BlueBuildCommand for CacheCleanCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("CacheCleanCommand::try_run()");
+
+        if self.older_than.is_none() && self.max_size.is_none() && !self.all {
+            bail!(
+                help = "Pass `--older-than`, `--max-size`, or `--all`",
+                "Choose which entries of the caches to delete"
+            );
+        }
+
+        let kinds = if self.caches.is_empty() {
+            CacheKind::value_variants()
+        } else {
+            &self.caches
+        };
+        let entries = kinds
+            .iter()
+            .map(|kind| kind.entries())
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let stale = self.select(entries, SystemTime::now());
+        let freed = ByteSize(stale.iter().map(|entry| entry.size).sum());
+
+        for entry in &stale {
+            if self.dry_run {
+                info!(
+                    "Would delete {} ({})",
+                    entry.path.display(),
+                    ByteSize(entry.size)
+                );
+                continue;
+            }
+
+            debug!("Deleting {:?} entry {}", entry.kind, entry.path.display());
+            let result = if entry.path.is_dir() {
+                fs::remove_dir_all(&entry.path)
+            } else {
+                fs::remove_file(&entry.path)
+            };
+            result
+                .into_diagnostic()
+                .with_context(|| format!("Failed to delete {}", entry.path.display()))?;
+        }
+
+        let verb = if self.dry_run { "Would free" } else { "Freed" };
+        info!("{verb} {freed} from {} cache entries", stale.len());
+        Ok(())
+    }
+}
+
+impl CacheCleanCommand {
+    /// The entries to delete, which are the entries that are
+    /// too old, then the least recently used entries until the
+    /// rest fit in `max_size`.
+    fn select(&self, mut entries: Vec<CacheEntry>, now: SystemTime) -> Vec<CacheEntry> {
+        if self.all {
+            return entries;
+        }
+
+        entries.sort_by_key(|entry| entry.modified);
+
+        let cutoff = self
+            .older_than
+            .and_then(|days| now.checked_sub(DAY * u32::try_from(days).unwrap_or(u32::MAX)));
+        let too_old = entries.partition_point(|entry| cutoff.is_some_and(|c| entry.modified < c));
+
+        let mut kept: u64 = entries[too_old..].iter().map(|entry| entry.size).sum();
+        let mut stale = too_old;
+        if let Some(ByteSize(max_size)) = self.max_size {
+            while kept > max_size && stale < entries.len() {
+                kept -= entries[stale].size;
+                stale += 1;
+            }
+        }
+
+        entries.truncate(stale);
+        entries
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        path::PathBuf,
+        time::{Duration, SystemTime},
+    };
+
+    use blue_build_process_management::preflight::ByteSize;
+    use rstest::rstest;
+
+    use super::{CacheCleanCommand, CacheEntry, CacheKind, DAY};
+
+    fn entry(name: &str, days_old: u32, size: u64, now: SystemTime) -> CacheEntry {
+        CacheEntry {
+            kind: CacheKind::Containerfiles,
+            path: PathBuf::from(name),
+            size,
+            modified: now - DAY * days_old,
+        }
+    }
+
+    #[rstest]
+    #[case(Some(30), None, &["a"])]
+    #[case(None, Some(ByteSize(40)), &["a", "b", "c"])]
+    #[case(Some(30), Some(ByteSize(40)), &["a", "b", "c"])]
+    #[case(Some(1), None, &["a", "b", "c"])]
+    #[case(None, Some(ByteSize(1000)), &[])]
+    fn select(
+        #[case] older_than: Option<u64>,
+        #[case] max_size: Option<ByteSize>,
+        #[case] expected: &[&str],
+    ) {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let entries = vec![
+            entry("c", 10, 30, now),
+            entry("a", 60, 10, now),
+            entry("d", 0, 40, now),
+            entry("b", 20, 20, now),
+        ];
+        let command = CacheCleanCommand::builder()
+            .maybe_older_than(older_than)
+            .maybe_max_size(max_size)
+            .build();
+
+        let selected = command
+            .select(entries, now)
+            .into_iter()
+            .map(|entry| entry.path.display().to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(selected, expected);
+    }
+}
@@ -0,0 +1,50 @@
+use blue_build_process_management::generated;
+use bon::Builder;
+use clap::Args;
+use log::{info, trace};
+use miette::{bail, Result};
+
+use super::BlueBuildCommand;
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct CleanCommand {
+    /// Remove the temp dirs and Containerfiles that
+    /// were left behind by builds that crashed.
+    ///
+    /// Only what a build that is no longer running
+    /// generated is removed.
+    #[arg(long)]
+    #[builder(default)]
+    generated: bool,
+
+    /// Show what would be removed without removing it.
+    #[arg(long)]
+    #[builder(default)]
+    dry_run: bool,
+}
+
+impl BlueBuildCommand for CleanCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("CleanCommand::try_run()");
+
+        if !self.generated {
+            bail!(
+                help = "Pass `--generated` to remove what crashed builds left behind",
+                "Choose what to clean"
+            );
+        }
+
+        let removed = generated::clean_stale(self.dry_run)?;
+        let verb = if self.dry_run {
+            "Would remove"
+        } else {
+            "Removed"
+        };
+
+        for path in &removed {
+            info!("{verb} {}", path.display());
+        }
+        info!("{verb} {} generated files", removed.len());
+        Ok(())
+    }
+}
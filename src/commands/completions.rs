@@ -1,28 +1,240 @@
-use clap::{Args, CommandFactory};
-use clap_complete::{generate, Shell as CompletionShell};
-use miette::Result;
+use std::{
+    ffi::OsStr,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_utils::{constants::RECIPE_PATH, credentials};
+use clap::{Args, Command, CommandFactory};
+use clap_complete::{
+    engine::{ArgValueCompleter, CompletionCandidate, PathCompleter, ValueCompleter},
+    env::Shells,
+    generate, Shell as CompletionShell,
+};
+use miette::{miette, IntoDiagnostic, Result};
+use serde::Deserialize;
 
 use crate::commands::BlueBuildArgs;
 
 use super::BlueBuildCommand;
 
+/// The environment variable that triggers dynamic completions.
+pub const COMPLETE_ENV_VAR: &str = "COMPLETE";
+
 #[derive(Debug, Clone, Args)]
 pub struct CompletionsCommand {
     #[arg(value_enum)]
     shell: CompletionShell,
+
+    /// Generate a script that asks `bluebuild` for completions
+    /// as you type.
+    ///
+    /// This allows completing recipe files in `recipes/` and
+    /// registries you're logged into in addition to the
+    /// static flags and values.
+    #[arg(long)]
+    dynamic: bool,
 }
 
 impl BlueBuildCommand for CompletionsCommand {
     fn try_run(&mut self) -> Result<()> {
         log::debug!("Generating completions for {}", self.shell);
 
-        generate(
-            self.shell,
-            &mut BlueBuildArgs::command(),
-            "bluebuild",
-            &mut std::io::stdout().lock(),
-        );
+        if self.dynamic {
+            let shell_name = self.shell.to_string();
+            let shells = Shells::builtins();
+            let shell = shells
+                .completer(&shell_name)
+                .ok_or_else(|| miette!("Dynamic completions aren't supported for {shell_name}"))?;
+            let completer = std::env::current_exe()
+                .ok()
+                .and_then(|exe| exe.to_str().map(ToOwned::to_owned))
+                .unwrap_or_else(|| String::from("bluebuild"));
+
+            shell
+                .write_registration(
+                    COMPLETE_ENV_VAR,
+                    "bluebuild",
+                    "bluebuild",
+                    &completer,
+                    &mut std::io::stdout().lock(),
+                )
+                .into_diagnostic()?;
+        } else {
+            generate(
+                self.shell,
+                &mut BlueBuildArgs::command(),
+                "bluebuild",
+                &mut std::io::stdout().lock(),
+            );
+        }
 
         Ok(())
     }
 }
+
+/// Builds the CLI command with dynamic value
+/// completers attached to its arguments.
+#[must_use]
+pub fn completion_command() -> Command {
+    add_completers(BlueBuildArgs::command())
+}
+
+fn add_completers(command: Command) -> Command {
+    // The names are collected so that `command` isn't
+    // borrowed while its subcommands are modified
+    #[allow(clippy::needless_collect)]
+    let subcommands = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_owned())
+        .collect::<Vec<_>>();
+
+    subcommands.into_iter().fold(
+        command.mut_args(|arg| match arg.get_id().as_str() {
+            "recipe" | "recipe_path" => arg.add(ArgValueCompleter::new(complete_recipe)),
+            "registry" | "server" => arg.add(ArgValueCompleter::new(complete_registry)),
+            _ => arg,
+        }),
+        |command, name| command.mut_subcommand(name, add_completers),
+    )
+}
+
+/// Completes recipe files in the `recipes/` directory,
+/// falling back to any file path.
+fn complete_recipe(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current_str = current.to_string_lossy();
+    let candidates = recipe_files(Path::new(RECIPE_PATH))
+        .into_iter()
+        .map(|path| path.display().to_string())
+        .map(|path| {
+            path.strip_prefix("./")
+                .map_or_else(|| path.clone(), ToOwned::to_owned)
+        })
+        .filter(|path| path.starts_with(&*current_str))
+        .map(CompletionCandidate::new)
+        .collect::<Vec<_>>();
+
+    if candidates.is_empty() {
+        PathCompleter::file().complete(current)
+    } else {
+        candidates
+    }
+}
+
+/// Finds all YAML files in `dir` and its sub-directories.
+fn recipe_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut files = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .flat_map(|path| {
+            if path.is_dir() {
+                recipe_files(&path)
+            } else if path
+                .extension()
+                .is_some_and(|ext| ext == "yml" || ext == "yaml")
+            {
+                vec![path]
+            } else {
+                Vec::new()
+            }
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// Completes registries that the user has logged into.
+fn complete_registry(current: &OsStr) -> Vec<CompletionCandidate> {
+    let current = current.to_string_lossy();
+
+    known_registries()
+        .into_iter()
+        .filter(|registry| registry.starts_with(&*current))
+        .map(CompletionCandidate::new)
+        .collect()
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct AuthFile {
+    #[serde(default)]
+    auths: serde_json::Map<String, serde_json::Value>,
+}
+
+/// Reads the registries from the auth files used by
+/// podman, buildah, skopeo, and docker.
+fn known_registries() -> Vec<String> {
+    let mut registries = credentials::auth_files()
+        .into_iter()
+        .filter_map(|path| fs::read_to_string(path).ok())
+        .flat_map(|contents| registries_from_auth(&contents))
+        .collect::<Vec<_>>();
+    registries.sort();
+    registries.dedup();
+    registries
+}
+
+fn registries_from_auth(contents: &str) -> Vec<String> {
+    serde_json::from_str::<AuthFile>(contents)
+        .unwrap_or_default()
+        .auths
+        .into_iter()
+        .map(|(registry, _)| {
+            registry
+                .trim_start_matches("https://")
+                .trim_start_matches("http://")
+                .split('/')
+                .next()
+                .unwrap_or_default()
+                .to_owned()
+        })
+        .filter(|registry| !registry.is_empty())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use std::ffi::OsStr;
+
+    use clap_complete::engine::CompletionCandidate;
+
+    use super::{completion_command, registries_from_auth};
+
+    #[test]
+    fn parse_auth_file() {
+        let contents = r#"{
+            "auths": {
+                "ghcr.io": { "auth": "dXNlcjpwYXNz" },
+                "https://index.docker.io/v1/": {}
+            }
+        }"#;
+
+        assert_eq!(
+            registries_from_auth(contents),
+            vec![String::from("ghcr.io"), String::from("index.docker.io")]
+        );
+        assert_eq!(registries_from_auth("not json"), Vec::<String>::new());
+    }
+
+    #[test]
+    fn completers_attached() {
+        let command = completion_command();
+        let build = command.find_subcommand("build").unwrap();
+        let recipe = build
+            .get_arguments()
+            .find(|arg| arg.get_id() == "recipe")
+            .unwrap();
+
+        let completer = recipe
+            .get::<clap_complete::engine::ArgValueCompleter>()
+            .unwrap();
+        let candidates: Vec<CompletionCandidate> = completer.complete(OsStr::new("Cargo.t"));
+
+        assert!(candidates
+            .iter()
+            .any(|candidate| candidate.get_value() == "Cargo.toml"));
+    }
+}
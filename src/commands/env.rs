@@ -0,0 +1,597 @@
+use std::{env, ffi::OsString, fmt::Write as _, path::PathBuf};
+
+use blue_build_process_management::drivers::{
+    types::{
+        BuildDriverType, CiDriverType, DriverSelector, InspectDriverType, RunDriverType,
+        SigningDriverType,
+    },
+    BuildahDriver, DockerDriver, DriverArgs, DriverVersion, PodmanDriver,
+};
+use blue_build_utils::constants::{
+    CI_COMMIT_REF_NAME, CI_PIPELINE_SOURCE, CI_PROJECT_URL, CI_REGISTRY, GITHUB_EVENT_NAME,
+    GITHUB_REF_NAME, GITHUB_RESPOSITORY, GITHUB_SHA,
+};
+use clap::{parser::ValueSource, ArgAction, ArgMatches, Args, Command, CommandFactory, ValueEnum};
+use colored::Colorize;
+use indexmap::IndexMap;
+use log::trace;
+use miette::{IntoDiagnostic, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
+use toml::{Table, Value};
+
+use crate::{
+    config::{Config, ConfigFile},
+    output::{self, TableOutput},
+};
+
+use super::{BlueBuildArgs, BlueBuildCommand};
+
+#[derive(Debug, Clone, Args)]
+pub struct EnvCommand {
+    #[clap(flatten)]
+    drivers: DriverArgs,
+}
+
+impl BlueBuildCommand for EnvCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("EnvCommand::try_run()");
+
+        let config = Config::load()?;
+
+        output::print(&EnvReport {
+            config_files: config.files().to_vec(),
+            config: flatten_config(config.values(), "").into_iter().collect(),
+            ignored: config.ignored().to_vec(),
+            flags: flags(&config, env::args_os())?,
+            env: env_vars(env::vars()),
+            drivers: self.drivers(),
+            build_driver_versions: vec![
+                DriverVersionInfo::new::<DockerDriver>("docker"),
+                DriverVersionInfo::new::<PodmanDriver>("podman"),
+                DriverVersionInfo::new::<BuildahDriver>("buildah"),
+            ],
+            ci: CiInfo::detect(),
+        })
+    }
+}
+
+impl EnvCommand {
+    fn drivers(&self) -> IndexMap<&'static str, DriverInfo> {
+        IndexMap::from([
+            (
+                "build",
+                DriverInfo::new(
+                    self.drivers
+                        .build_driver()
+                        .and_then(DriverSelector::selector),
+                    BuildDriverType::detect,
+                ),
+            ),
+            (
+                "inspect",
+                DriverInfo::new(
+                    self.drivers
+                        .inspect_driver()
+                        .and_then(DriverSelector::selector),
+                    InspectDriverType::detect,
+                ),
+            ),
+            (
+                "signing",
+                DriverInfo::new(
+                    self.drivers
+                        .signing_driver()
+                        .and_then(DriverSelector::selector),
+                    || Some(SigningDriverType::detect()),
+                ),
+            ),
+            (
+                "run",
+                DriverInfo::new(
+                    self.drivers.run_driver().as_ref().and_then(driver_name),
+                    RunDriverType::detect,
+                ),
+            ),
+            ("ci", DriverInfo::new(None, || Some(CiDriverType::detect()))),
+        ])
+    }
+}
+
+/// The effective configuration of the CLI.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct EnvReport {
+    config_files: Vec<PathBuf>,
+    config: IndexMap<String, String>,
+
+    /// The keys of the repo config that are
+    /// only read from the user config.
+    ignored: Vec<String>,
+
+    /// The flags of this command, with where
+    /// their effective value comes from.
+    flags: Vec<FlagInfo>,
+    env: IndexMap<String, String>,
+    drivers: IndexMap<&'static str, DriverInfo>,
+
+    /// Listed in the order that they're checked
+    /// when detecting the build driver.
+    build_driver_versions: Vec<DriverVersionInfo>,
+    ci: CiInfo,
+}
+
+impl TableOutput for EnvReport {
+    fn to_table(&self) -> String {
+        let mut out = String::new();
+
+        let _ = writeln!(out, "{}", "Config files:".bold());
+        if self.config_files.is_empty() {
+            let _ = writeln!(out, "  (none)");
+        }
+        for file in &self.config_files {
+            let _ = writeln!(out, "  {}", file.display());
+        }
+
+        let _ = writeln!(out, "\n{}", "Config values:".bold());
+        write_pairs(&mut out, &self.config, " = ");
+
+        if !self.ignored.is_empty() {
+            let _ = writeln!(out, "\n{}", "Ignored repo config keys:".bold());
+            for key in &self.ignored {
+                let _ = writeln!(out, "  {}", key.yellow());
+            }
+        }
+
+        let _ = writeln!(out, "\n{}", "Flags:".bold());
+        let width = self
+            .flags
+            .iter()
+            .map(|flag| flag.name.len())
+            .max()
+            .unwrap_or_default();
+        for flag in &self.flags {
+            let name = format!("--{:<width$}", flag.name);
+            let _ = match &flag.value {
+                Some(value) => writeln!(out, "  {name} {value} ({})", flag.source),
+                None => writeln!(out, "  {name} {}", "<unset>".dimmed()),
+            };
+        }
+
+        let _ = writeln!(out, "\n{}", "Environment:".bold());
+        write_pairs(&mut out, &self.env, "=");
+
+        let _ = writeln!(out, "\n{}", "Drivers:".bold());
+        for (kind, info) in &self.drivers {
+            let _ = match &info.driver {
+                Some(driver) => writeln!(out, "  {kind:<8} {driver} ({})", info.source),
+                None => writeln!(out, "  {kind:<8} {}", "none found".red()),
+            };
+        }
+
+        let _ = writeln!(out, "\n{}", "Build driver versions:".bold());
+        for info in &self.build_driver_versions {
+            let name = info.name;
+            let _ = match (&info.version, info.supported) {
+                (Some(version), true) => writeln!(out, "  {name:<8} {version} (supported)"),
+                (Some(version), false) => writeln!(
+                    out,
+                    "  {name:<8} {version} ({})",
+                    format!("unsupported, requires {}", info.version_req).yellow()
+                ),
+                (None, _) => writeln!(out, "  {name:<8} {}", "not found".dimmed()),
+            };
+        }
+
+        let _ = writeln!(out, "\n{}", "CI:".bold());
+        let _ = writeln!(out, "  platform {}", self.ci.platform);
+        for (var, value) in &self.ci.vars {
+            let _ = writeln!(out, "  {var}={}", value.as_deref().unwrap_or("<unset>"));
+        }
+
+        out
+    }
+}
+
+fn write_pairs(out: &mut String, pairs: &IndexMap<String, String>, separator: &str) {
+    if pairs.is_empty() {
+        let _ = writeln!(out, "  (none)");
+    }
+    for (key, value) in pairs {
+        let _ = writeln!(out, "  {key}{separator}{value}");
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct DriverInfo {
+    driver: Option<String>,
+
+    /// Whether the driver was `selected` with a flag,
+    /// env var, or config file, or `detected`.
+    source: &'static str,
+}
+
+impl DriverInfo {
+    fn new<T, F>(selected: Option<String>, detect: F) -> Self
+    where
+        T: ValueEnum,
+        F: FnOnce() -> Option<T>,
+    {
+        let (driver, source) = selected.map_or_else(
+            || (detect().as_ref().and_then(driver_name), "detected"),
+            |driver| (Some(driver), "selected"),
+        );
+
+        Self { driver, source }
+    }
+}
+
+fn driver_name<T: ValueEnum>(driver: &T) -> Option<String> {
+    driver
+        .to_possible_value()
+        .map(|driver| driver.get_name().to_owned())
+}
+
+#[derive(Debug, Serialize, JsonSchema, PartialEq, Eq)]
+struct FlagInfo {
+    name: String,
+    value: Option<String>,
+    source: FlagSource,
+}
+
+/// Where the effective value of a flag comes from.
+#[derive(Debug, Clone, Copy, Serialize, JsonSchema, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+enum FlagSource {
+    Cli,
+    Env,
+    RepoConfig,
+    UserConfig,
+    Default,
+}
+
+impl std::fmt::Display for FlagSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Cli => "cli",
+            Self::Env => "env",
+            Self::RepoConfig => "repo config",
+            Self::UserConfig => "user config",
+            Self::Default => "default",
+        })
+    }
+}
+
+/// The flags of the top-level command and the subcommand that
+/// `args` run, with their effective values and where they come from.
+fn flags<I, T>(config: &Config, args: I) -> Result<Vec<FlagInfo>>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString> + Clone,
+{
+    let mut command = config.apply(BlueBuildArgs::command());
+    let matches = command.try_get_matches_from_mut(args).into_diagnostic()?;
+
+    let mut flags = flag_infos(config, &command, &matches, None);
+    if let Some((name, sub_matches)) = matches.subcommand() {
+        if let Some(subcommand) = command.find_subcommand(name) {
+            flags.extend(flag_infos(config, subcommand, sub_matches, Some(name)));
+        }
+    }
+    Ok(flags)
+}
+
+fn flag_infos(
+    config: &Config,
+    command: &Command,
+    matches: &ArgMatches,
+    subcommand: Option<&str>,
+) -> Vec<FlagInfo> {
+    command
+        .get_arguments()
+        .filter(|arg| {
+            !matches!(
+                arg.get_action(),
+                ArgAction::Help | ArgAction::HelpShort | ArgAction::HelpLong | ArgAction::Version
+            )
+        })
+        // Global flags are listed with the top-level command
+        .filter(|arg| subcommand.is_none() || !arg.is_global_set())
+        .filter_map(|arg| {
+            let name = arg.get_long()?;
+            let id = arg.get_id().as_str();
+            let value = matches.try_get_raw(id).ok().flatten().map(|values| {
+                if is_secret_key(name) {
+                    String::from(REDACTED)
+                } else {
+                    values
+                        .map(|value| value.to_string_lossy())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                }
+            });
+            let source = match matches.value_source(id) {
+                Some(ValueSource::CommandLine) => FlagSource::Cli,
+                Some(ValueSource::EnvVariable) => FlagSource::Env,
+                _ => match config.file_of(name, subcommand) {
+                    Some(ConfigFile::Repo) => FlagSource::RepoConfig,
+                    Some(ConfigFile::User) => FlagSource::UserConfig,
+                    None => FlagSource::Default,
+                },
+            };
+
+            Some(FlagInfo {
+                name: name.to_owned(),
+                value,
+                source,
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct DriverVersionInfo {
+    name: &'static str,
+    version: Option<String>,
+    version_req: &'static str,
+    supported: bool,
+}
+
+impl DriverVersionInfo {
+    fn new<T: DriverVersion>(name: &'static str) -> Self {
+        let version = T::version().ok();
+
+        Self {
+            name,
+            supported: version.is_some() && T::is_supported_version(),
+            version: version.map(|version| version.to_string()),
+            version_req: T::VERSION_REQ,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct CiInfo {
+    platform: String,
+    vars: IndexMap<&'static str, Option<String>>,
+}
+
+impl CiInfo {
+    fn detect() -> Self {
+        let ci = CiDriverType::detect();
+        let vars: &[&'static str] = match ci {
+            CiDriverType::Github => &[
+                GITHUB_RESPOSITORY,
+                GITHUB_REF_NAME,
+                GITHUB_EVENT_NAME,
+                GITHUB_SHA,
+            ],
+            CiDriverType::Gitlab => &[
+                CI_PROJECT_URL,
+                CI_COMMIT_REF_NAME,
+                CI_PIPELINE_SOURCE,
+                CI_REGISTRY,
+            ],
+            CiDriverType::Local => &[],
+            #[cfg(feature = "test")]
+            CiDriverType::Mock => &[],
+        };
+
+        Self {
+            platform: ci
+                .to_possible_value()
+                .map(|ci| ci.get_name().to_owned())
+                .unwrap_or_default(),
+            vars: vars.iter().map(|&var| (var, env::var(var).ok())).collect(),
+        }
+    }
+}
+
+const REDACTED: &str = "<redacted>";
+
+/// Flattens subcommand tables into dotted keys
+/// and arrays of tables into indexed keys, with
+/// secrets redacted.
+fn flatten_config(table: &Table, prefix: &str) -> Vec<(String, String)> {
+    table
+        .iter()
+        .flat_map(|(key, value)| flatten_value(format!("{prefix}{key}"), value))
+        .collect()
+}
+
+fn flatten_value(key: String, value: &Value) -> Vec<(String, String)> {
+    match value {
+        Value::Table(table) => flatten_config(table, &format!("{key}.")),
+        Value::Array(values) if !values.is_empty() && values.iter().all(Value::is_table) => values
+            .iter()
+            .enumerate()
+            .flat_map(|(index, value)| flatten_value(format!("{key}[{index}]"), value))
+            .collect(),
+        _ if is_secret_key(&key) => vec![(key, String::from(REDACTED))],
+        Value::Array(values) => vec![(
+            key,
+            Value::Array(values.iter().map(redact_option).collect()).to_string(),
+        )],
+        value => vec![(key, value.to_string())],
+    }
+}
+
+/// Whether the value of a config key is a secret, like the
+/// `creds` or the URL of a webhook, which has its token in it.
+fn is_secret_key(key: &str) -> bool {
+    is_secret_var(&key.to_uppercase().replace(['-', '.'], "_"))
+        || key
+            .strip_prefix("notifications[")
+            .is_some_and(|key| key.ends_with("].url"))
+}
+
+/// Redacts the value of a `key=value` option, like
+/// a `build-opt`, if its key is for a secret.
+fn redact_option(value: &Value) -> Value {
+    match value.as_str().and_then(|option| option.split_once('=')) {
+        Some((key, _)) if is_secret_var(&key.to_uppercase()) => {
+            Value::String(format!("{key}={REDACTED}"))
+        }
+        _ => value.clone(),
+    }
+}
+
+/// Collects the `BB_*` env vars with secrets redacted.
+fn env_vars<I>(vars: I) -> IndexMap<String, String>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    let mut vars = vars
+        .into_iter()
+        .filter(|(key, _)| key.starts_with("BB_"))
+        .map(|(key, value)| {
+            if is_secret_var(&key) {
+                (key, String::from(REDACTED))
+            } else {
+                (key, value)
+            }
+        })
+        .collect::<IndexMap<_, _>>();
+    vars.sort_keys();
+    vars
+}
+
+fn is_secret_var(key: &str) -> bool {
+    ["PASSWORD", "PRIVATE_KEY", "TOKEN", "SECRET", "CREDS"]
+        .iter()
+        .any(|secret| key.contains(secret))
+}
+
+#[cfg(test)]
+mod test {
+    use indexmap::IndexMap;
+    use toml::Table;
+
+    use crate::config::Config;
+
+    use super::{env_vars, flags, flatten_config, FlagInfo, FlagSource};
+
+    #[test]
+    fn flatten() {
+        let table = r#"
+registry = "ghcr.io"
+
+[build]
+retry-count = 3
+"#
+        .parse::<Table>()
+        .unwrap();
+
+        assert_eq!(
+            flatten_config(&table, ""),
+            vec![
+                (String::from("build.retry-count"), String::from("3")),
+                (String::from("registry"), String::from("\"ghcr.io\"")),
+            ]
+        );
+    }
+
+    #[test]
+    fn flag_sources() {
+        let config = Config::from_tables(
+            Vec::new(),
+            "build-driver = \"docker\"\n[env]\nrun-driver = \"podman\"\n"
+                .parse()
+                .unwrap(),
+            "inspect-driver = \"skopeo\"\n[env]\nrun-driver = \"docker\"\n"
+                .parse()
+                .unwrap(),
+        );
+        let flags = flags(&config, ["bluebuild", "env", "--signing-driver", "cosign"]).unwrap();
+        let flag = |name: &str| flags.iter().find(|flag| flag.name == name).unwrap();
+        let info = |name: &str, value: Option<&str>, source| FlagInfo {
+            name: name.to_owned(),
+            value: value.map(ToOwned::to_owned),
+            source,
+        };
+
+        assert_eq!(
+            flag("build-driver"),
+            &info("build-driver", Some("docker"), FlagSource::UserConfig)
+        );
+        assert_eq!(
+            flag("inspect-driver"),
+            &info("inspect-driver", Some("skopeo"), FlagSource::RepoConfig)
+        );
+        assert_eq!(
+            flag("run-driver"),
+            &info("run-driver", Some("docker"), FlagSource::RepoConfig)
+        );
+        assert_eq!(
+            flag("signing-driver"),
+            &info("signing-driver", Some("cosign"), FlagSource::Cli)
+        );
+        assert_eq!(
+            flag("log-format"),
+            &info("log-format", Some("pretty"), FlagSource::Default)
+        );
+        assert_eq!(flag("log-out"), &info("log-out", None, FlagSource::Default));
+        assert!(!flags.iter().any(|flag| flag.name == "help"));
+    }
+
+    #[test]
+    fn redact_config() {
+        let table = r#"
+creds = ["quay.io=robot:hunter2"]
+
+[build]
+build-opt = ["ulimit=nofile=4096", "secret=id=token,src=token.txt"]
+signing-token = "hunter2"
+
+[[notifications]]
+type = "discord"
+url = "https://discord.com/api/webhooks/1/hunter2"
+"#
+        .parse::<Table>()
+        .unwrap();
+
+        assert_eq!(
+            flatten_config(&table, ""),
+            vec![
+                (
+                    String::from("build.build-opt"),
+                    String::from(r#"["ulimit=nofile=4096", "secret=<redacted>"]"#)
+                ),
+                (
+                    String::from("build.signing-token"),
+                    String::from("<redacted>")
+                ),
+                (String::from("creds"), String::from("<redacted>")),
+                (
+                    String::from("notifications[0].type"),
+                    String::from("\"discord\"")
+                ),
+                (
+                    String::from("notifications[0].url"),
+                    String::from("<redacted>")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn redact_env_vars() {
+        let vars = env_vars([
+            (String::from("BB_REGISTRY"), String::from("ghcr.io")),
+            (String::from("BB_PASSWORD"), String::from("hunter2")),
+            (
+                String::from("BB_CREDS"),
+                String::from("quay.io=robot:hunter2"),
+            ),
+            (String::from("HOME"), String::from("/root")),
+        ]);
+
+        assert_eq!(
+            vars,
+            IndexMap::from([
+                (String::from("BB_CREDS"), String::from("<redacted>")),
+                (String::from("BB_PASSWORD"), String::from("<redacted>")),
+                (String::from("BB_REGISTRY"), String::from("ghcr.io")),
+            ])
+        );
+    }
+}
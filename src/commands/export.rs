@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use blue_build_recipe::Recipe;
+use bon::Builder;
+use clap::{Args, Subcommand};
+use log::trace;
+use miette::Result;
+
+use crate::ublue;
+
+use super::BlueBuildCommand;
+
+#[derive(Debug, Clone, Args)]
+pub struct ExportCommand {
+    #[command(subcommand)]
+    command: ExportSubcommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ExportSubcommand {
+    /// Export a recipe to a repo like the ones
+    /// made from the ublue-os image template.
+    ///
+    /// The modules are written as a `build.sh` that the
+    /// `Containerfile` runs. Modules that the script can't
+    /// do are left as comments in the script.
+    Ublue(ExportUblueCommand),
+}
+
+impl BlueBuildCommand for ExportCommand {
+    fn try_run(&mut self) -> Result<()> {
+        match &mut self.command {
+            ExportSubcommand::Ublue(command) => command.try_run(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct ExportUblueCommand {
+    /// The recipe to export.
+    #[builder(into)]
+    recipe: PathBuf,
+
+    /// The directory to write the repo to.
+    #[arg(short, long)]
+    #[builder(into)]
+    output: PathBuf,
+}
+
+impl BlueBuildCommand for ExportUblueCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("ExportUblueCommand::try_run()");
+
+        ublue::export(&Recipe::parse(&self.recipe)?, &self.output)
+    }
+}
@@ -1,32 +1,64 @@
 use std::{
+    collections::BTreeMap,
     env,
     path::{Path, PathBuf},
 };
 
-use blue_build_process_management::drivers::{
-    opts::GetMetadataOpts, types::Platform, CiDriver, Driver, DriverArgs, InspectDriver,
+use blue_build_process_management::{
+    drivers::{
+        opts::GetMetadataOpts, types::Platform, CiDriver, Driver, DriverArgs, InspectDriver,
+    },
+    summary,
 };
 use blue_build_recipe::Recipe;
-use blue_build_template::{ContainerFileTemplate, Template};
+use blue_build_template::{ContainerFileTemplate, ModuleExplainTemplate, Template};
 use blue_build_utils::{
-    constants::{BUILD_SCRIPTS_IMAGE_REF, CONFIG_PATH, RECIPE_FILE, RECIPE_PATH},
+    constants::{
+        BB_NO_VERIFY_TOOLS, BB_OFFLINE, BLUE_BUILD_IMAGE_REF, BUILD_SCRIPTS_IMAGE_REF, CONFIG_PATH,
+        COSIGN_IMAGE, MODULES_IMAGE, MODULES_LABEL, RECIPE_FILE, RECIPE_PATH,
+        TEMPLATED_FILES_CONTEXT,
+    },
+    helper_images,
+    os_family::OsFamily,
     syntax_highlighting::{self, DefaultThemes},
 };
 use bon::Builder;
 use cached::proc_macro::cached;
-use clap::{crate_version, Args};
+use clap::{crate_version, Args, Subcommand};
 use log::{debug, info, trace, warn};
-use miette::{IntoDiagnostic, Result};
+use miette::{bail, IntoDiagnostic, Result};
 use oci_distribution::Reference;
 
 #[cfg(feature = "validate")]
 use crate::commands::validate::ValidateCommand;
-use crate::shadow;
+use crate::{
+    akmods,
+    content_hash::build_inputs_hash,
+    git_modules, labels,
+    lockfile::{git_sources, Lockfile},
+    module_manifest,
+    module_overrides::{self, ModuleOverride},
+    shadow, templated_files,
+};
 
 use super::BlueBuildCommand;
 
+pub(crate) mod cache;
+mod explain;
+mod graph;
+mod quadlet;
+
+/// The modules that install packages with `rpm-ostree` or
+/// `dnf`, which only exist on Fedora based images.
+const RPM_OSTREE_MODULES: &[&str] = &["akmods", "rpm-ostree", "dnf"];
+
+#[allow(clippy::struct_excessive_bools)]
 #[derive(Debug, Clone, Args, Builder)]
+#[command(args_conflicts_with_subcommands = true)]
 pub struct GenerateCommand {
+    #[command(subcommand)]
+    command: Option<GenerateSubcommand>,
+
     /// The recipe file to create a template from
     #[arg()]
     #[builder(into)]
@@ -62,6 +94,18 @@ pub struct GenerateCommand {
     #[builder(default)]
     display_full_recipe: bool,
 
+    /// Instead of creating a Containerfile, show what a
+    /// module will run during the build. This includes the
+    /// command line, the config passed to the module,
+    /// and everything that is mounted for it.
+    ///
+    /// The module is selected by its number, starting at 1,
+    /// or by its type. Modules are numbered in the order
+    /// they run, so the modules of stages come first.
+    #[arg(long, value_name = "MODULE", conflicts_with = "display_full_recipe")]
+    #[builder(into)]
+    explain: Option<String>,
+
     /// Choose a theme for the syntax highlighting
     /// for the Containerfile or Yaml.
     ///
@@ -75,40 +119,191 @@ pub struct GenerateCommand {
     #[builder(default)]
     platform: Platform,
 
+    /// Use the module in this directory instead of the
+    /// published one. Can be used more than once.
+    ///
+    /// The name of the directory is the type of the module
+    /// it overrides, like `../modules/modules/rpm-ostree`.
+    #[arg(long = "module-path", value_name = "DIR")]
+    #[builder(default, into)]
+    module_paths: Vec<PathBuf>,
+
+    /// Always render the Containerfile instead of
+    /// reusing one cached from an earlier run.
+    ///
+    /// Rendered Containerfiles are cached in
+    /// `~/.cache/bluebuild` by the hash of the
+    /// recipe, its files, and the CLI version.
+    #[arg(long)]
+    #[builder(default)]
+    no_cache: bool,
+
+    /// The stages that are built as their own images. Their
+    /// definitions are left out of the Containerfile.
+    #[arg(skip)]
+    #[builder(default, into)]
+    prebuilt_stages: Vec<String>,
+
+    /// The directory to render the templated files to, which is
+    /// given to the build as the templated files build context.
+    ///
+    /// Defaults to a directory next to the output, or a new
+    /// temporary directory when writing to stdout.
+    #[arg(skip)]
+    #[builder(into)]
+    templated_files_dir: Option<PathBuf>,
+
+    /// Generate without the network, using the lockfile
+    /// of the recipe instead of inspecting images.
+    ///
+    /// The recipe isn't validated since
+    /// its schemas can't be fetched.
+    #[arg(long, env = BB_OFFLINE, conflicts_with = "lock")]
+    #[builder(default)]
+    offline: bool,
+
+    /// Don't validate the recipe against its schemas,
+    /// like for a recipe that was already validated.
+    #[arg(skip)]
+    #[builder(default)]
+    skip_validation: bool,
+
+    /// Write the digest of the base image, its OS version,
+    /// the build scripts image, and the commits of git sourced
+    /// modules to the lockfile of the recipe.
+    ///
+    /// The lockfile is next to the recipe, like `recipe.lock.json`
+    /// for `recipe.yml`, and is required to build with `--offline`.
+    #[arg(long)]
+    #[builder(default)]
+    lock: bool,
+
+    /// Don't verify the images that are copied into the build,
+    /// like the build scripts, the modules, and cosign.
+    ///
+    /// Images published by bluebuild and cosign have their signature
+    /// verified, and every image must match the digest in the lockfile
+    /// of the recipe if it has one.
+    #[arg(long, env = BB_NO_VERIFY_TOOLS)]
+    #[builder(default)]
+    no_verify_tools: bool,
+
     #[clap(flatten)]
     #[builder(default)]
     drivers: DriverArgs,
 }
 
+#[derive(Debug, Clone, Subcommand)]
+pub enum GenerateSubcommand {
+    /// Generate podman Quadlet units that run
+    /// an image as a system service.
+    ///
+    /// A `.container` and `.image` unit are created. With
+    /// `--verify-key`, a signature policy and registries.d
+    /// config are also created so that the image is only
+    /// pulled if its signature is valid.
+    Quadlet(quadlet::GenerateQuadletCommand),
+
+    /// Draw a graph of the stages and modules of recipes,
+    /// with their base images and what they copy from
+    /// each other, in Graphviz DOT or Mermaid.
+    ///
+    /// Pass every recipe of a matrix build to
+    /// see which ones share a base image.
+    Graph(graph::GenerateGraphCommand),
+}
+
 impl BlueBuildCommand for GenerateCommand {
     fn try_run(&mut self) -> Result<()> {
-        Driver::init(self.drivers);
+        match self.command.as_mut() {
+            Some(GenerateSubcommand::Quadlet(command)) => return command.try_run(),
+            Some(GenerateSubcommand::Graph(command)) => return command.try_run(),
+            None => {}
+        }
+
+        Driver::init(self.drivers.clone());
+        Driver::set_offline(self.offline);
 
         self.template_file()
     }
 }
 
 impl GenerateCommand {
+    /// Renders the Containerfile of the recipe and returns it
+    /// instead of writing it to the output.
+    ///
+    /// The lockfile of the recipe is still written with `lock`.
+    ///
+    /// # Errors
+    /// Will error if the recipe is invalid or the
+    /// Containerfile can't be rendered.
+    pub fn containerfile(&self) -> Result<String> {
+        trace!("GenerateCommand::containerfile()");
+
+        Driver::init(self.drivers.clone());
+        Driver::set_offline(self.offline);
+
+        let recipe_path = self.recipe_path();
+        let recipe = self.parse_recipe(&recipe_path)?;
+        self.render(&recipe_path, recipe)
+    }
+
     fn template_file(&self) -> Result<()> {
         trace!("TemplateCommand::template_file()");
 
-        let recipe_path = self.recipe.clone().unwrap_or_else(|| {
-            let legacy_path = Path::new(CONFIG_PATH);
-            let recipe_path = Path::new(RECIPE_PATH);
-            if recipe_path.exists() && recipe_path.is_dir() {
-                recipe_path.join(RECIPE_FILE)
+        let recipe_path = self.recipe_path();
+        let mut recipe = self.parse_recipe(&recipe_path)?;
+
+        if self.display_full_recipe {
+            self.validate(&recipe_path)?;
+
+            if let Some(output) = self.output.as_ref() {
+                std::fs::write(output, serde_yaml::to_string(&recipe).into_diagnostic()?)
+                    .into_diagnostic()?;
             } else {
-                warn!("Use of {CONFIG_PATH} for recipes is deprecated, please move your recipe files into {RECIPE_PATH}");
-                legacy_path.join(RECIPE_FILE)
+                syntax_highlighting::print_ser(&recipe, "yml", self.syntax_theme)?;
             }
-        });
+            return Ok(());
+        }
 
-        #[cfg(feature = "validate")]
-        ValidateCommand::builder()
-            .recipe(recipe_path.clone())
-            .build()
-            .try_run()?;
+        if let Some(selector) = self.explain.as_deref() {
+            return self.explain_module(&mut recipe, selector);
+        }
+
+        let output_str = self.render(&recipe_path, recipe)?;
+        self.write_containerfile(&output_str)
+    }
+
+    /// The directory that the templated files are rendered to.
+    fn templated_files_dir(&self) -> Result<PathBuf> {
+        if let Some(dir) = &self.templated_files_dir {
+            return Ok(dir.clone());
+        }
+        if let Some(output) = &self.output {
+            return Ok(templated_files::dir(output));
+        }
+        Ok(tempfile::Builder::new()
+            .prefix("bluebuild-templated-files-")
+            .tempdir()
+            .into_diagnostic()?
+            .into_path())
+    }
+
+    fn parse_recipe(&self, recipe_path: &Path) -> Result<Recipe<'static>> {
+        debug!("Deserializing recipe");
+        let recipe = match Recipe::parse(recipe_path) {
+            Ok(recipe) => recipe,
+            Err(err) => {
+                // Validation gives better errors for a broken recipe
+                self.validate(recipe_path)?;
+                return Err(err);
+            }
+        };
+        trace!("recipe_de: {recipe:#?}");
+        Ok(recipe)
+    }
 
+    fn render(&self, recipe_path: &Path, mut recipe: Recipe<'_>) -> Result<String> {
         let registry = if let (Some(registry), Some(registry_namespace)) =
             (&self.registry, &self.registry_namespace)
         {
@@ -117,51 +312,102 @@ impl GenerateCommand {
             Driver::get_registry()?
         };
 
-        debug!("Deserializing recipe");
-        let recipe = Recipe::parse(&recipe_path)?;
-        trace!("recipe_de: {recipe:#?}");
+        info!("Templating for recipe at {}", recipe_path.display());
 
-        if self.display_full_recipe {
-            if let Some(output) = self.output.as_ref() {
-                std::fs::write(output, serde_yaml::to_string(&recipe).into_diagnostic()?)
-                    .into_diagnostic()?;
-            } else {
-                syntax_highlighting::print_ser(&recipe, "yml", self.syntax_theme)?;
+        let lockfile = self.load_lockfile(recipe_path, &mut recipe)?;
+
+        let base_digest = self.base_digest(&recipe, lockfile.as_ref())?;
+        let module_overrides = module_overrides::prepare(&self.module_paths)?;
+        let git_sources = git_sources(&recipe);
+        let mut module_sources = git_modules::pin(&mut recipe)?;
+        let tools = self.verify_tools(&recipe, lockfile.as_ref())?;
+        let os_version = self.os_version(&recipe, lockfile.as_ref())?;
+        let akmods_flavor = self.akmods_flavor(&mut recipe, os_version, lockfile.as_ref())?;
+
+        if self.lock {
+            self.new_lockfile(&recipe, &base_digest)?
+                .with_akmods_flavor(akmods_flavor)
+                .with_git_modules(git_sources, module_sources.clone())
+                .with_tools(tools.clone())
+                .save(recipe_path)?;
+            info!(
+                "Wrote the lockfile {}",
+                Lockfile::path(recipe_path).display()
+            );
+        }
+        module_sources.extend(module_overrides.iter().map(ModuleOverride::source));
+        let content_hash =
+            build_inputs_hash(recipe_path, &base_digest, self.platform, &module_sources)?;
+        let os_family = self.os_family(&recipe, lockfile.as_ref())?;
+        let module_manifest = module_manifest::to_json(&recipe, &tools, &module_overrides)?;
+        let labels = Self::labels(&recipe, &base_digest, os_version, &module_manifest)?;
+        let templated_files = recipe.has_templated_files();
+        if templated_files {
+            let dir = self.templated_files_dir()?;
+            templated_files::prepare(&recipe, &templated_files::values(&recipe, &labels), &dir)?;
+            if self.templated_files_dir.is_none() {
+                info!(
+                    "Rendered the templated files to {}, build with `--build-context {TEMPLATED_FILES_CONTEXT}={0}`",
+                    dir.display()
+                );
             }
-            return Ok(());
         }
 
-        info!("Templating for recipe at {}", recipe_path.display());
+        let cache_dir = cache::cache_dir().filter(|_| !self.no_cache);
+        let cache_key = cache::cache_key(&content_hash, &registry, &labels, &self.prebuilt_stages);
 
-        let base_image: Reference = format!("{}:{}", &recipe.base_image, &recipe.image_version)
-            .parse()
-            .into_diagnostic()?;
+        let output_str = if let Some(containerfile) = cache_dir
+            .as_deref()
+            .and_then(|dir| cache::get(dir, &cache_key, Driver::get_build_id()))
+        {
+            info!("Recipe is unchanged, using the cached Containerfile");
+            containerfile
+        } else {
+            self.validate(recipe_path)?;
 
-        let template = ContainerFileTemplate::builder()
-            .os_version(
-                Driver::get_os_version()
-                    .oci_ref(&recipe.base_image_ref()?)
-                    .platform(self.platform)
-                    .call()?,
-            )
-            .build_id(Driver::get_build_id())
-            .recipe(&recipe)
-            .recipe_path(recipe_path.as_path())
-            .registry(registry)
-            .repo(Driver::get_repo_url()?)
-            .build_scripts_image(determine_scripts_tag(self.platform)?.to_string())
-            .base_digest(
-                Driver::get_metadata(
-                    &GetMetadataOpts::builder()
-                        .image(&base_image)
-                        .platform(self.platform)
-                        .build(),
-                )?
-                .digest,
-            )
-            .build();
-
-        let output_str = template.render().into_diagnostic()?;
+            let tool_images = pinned_tools(&tools)?;
+            let build_scripts_image = self.build_scripts_image(lockfile.as_ref())?;
+            let build_scripts_image = tool_images
+                .get(&build_scripts_image)
+                .cloned()
+                .unwrap_or(build_scripts_image);
+            let output_str = ContainerFileTemplate::builder()
+                .os_version(os_version)
+                .os_family(os_family)
+                .build_id(Driver::get_build_id())
+                .recipe(&recipe)
+                .recipe_path(recipe_path)
+                .registry(registry)
+                .labels(labels)
+                .module_manifest(module_manifest)
+                .build_scripts_image(build_scripts_image)
+                .base_digest(base_digest)
+                .content_hash(content_hash)
+                .module_overrides(
+                    module_overrides
+                        .into_iter()
+                        .map(|module_override| module_override.name)
+                        .collect(),
+                )
+                .templated_files(templated_files)
+                .prebuilt_stages(self.prebuilt_stages.clone())
+                .tool_images(tool_images)
+                .build()
+                .render()
+                .into_diagnostic()?;
+
+            if let Some(dir) = cache_dir.as_deref() {
+                if let Err(e) = cache::put(dir, &cache_key, Driver::get_build_id(), &output_str) {
+                    warn!("Failed to cache the Containerfile: {e:?}");
+                }
+            }
+            output_str
+        };
+
+        Ok(output_str)
+    }
+
+    fn write_containerfile(&self, output_str: &str) -> Result<()> {
         if let Some(output) = self.output.as_ref() {
             debug!("Templating to file {}", output.display());
             trace!("Containerfile:\n{output_str}");
@@ -169,11 +415,280 @@ impl GenerateCommand {
             std::fs::write(output, output_str).into_diagnostic()?;
         } else {
             debug!("Templating to stdout");
+            syntax_highlighting::print(output_str, "Dockerfile", self.syntax_theme)?;
+        }
+
+        Ok(())
+    }
+
+    fn explain_module(&self, recipe: &mut Recipe, selector: &str) -> Result<()> {
+        trace!("GenerateCommand::explain_module({selector})");
+
+        let module_overrides = module_overrides::prepare(&self.module_paths)?
+            .into_iter()
+            .map(|module_override| module_override.name)
+            .collect::<Vec<_>>();
+        git_modules::pin(recipe)?;
+
+        let os_version = Driver::get_os_version()
+            .oci_ref(&recipe.base_image_ref()?)
+            .platform(self.platform)
+            .call()?;
+        akmods::select_flavor(recipe, os_version, self.platform)?;
+        let os_family = self.os_family(recipe, None)?;
+        let build_scripts_image = determine_scripts_tag(self.platform)?.to_string();
+
+        let output_str = explain::find_modules(recipe, selector)?
+            .into_iter()
+            .map(|selected| {
+                ModuleExplainTemplate::builder()
+                    .recipe(recipe)
+                    .module(selected.module)
+                    .number(selected.number)
+                    .maybe_stage(selected.stage)
+                    .os_version(os_version)
+                    .os_family(os_family)
+                    .build_scripts_image(&build_scripts_image)
+                    .module_overrides(module_overrides.clone())
+                    .build()
+                    .render()
+                    .into_diagnostic()
+            })
+            .collect::<Result<Vec<_>>>()?
+            .join("\n\n");
+
+        if let Some(output) = self.output.as_ref() {
+            std::fs::write(output, output_str).into_diagnostic()?;
+        } else {
             syntax_highlighting::print(&output_str, "Dockerfile", self.syntax_theme)?;
         }
+        Ok(())
+    }
+
+    /// The digest of the base image, which is taken
+    /// from the lockfile when offline.
+    fn base_digest(&self, recipe: &Recipe, lockfile: Option<&Lockfile>) -> Result<String> {
+        if let Some(lockfile) = lockfile {
+            return Ok(lockfile.base_digest.clone());
+        }
+
+        let base_image: Reference = format!("{}:{}", recipe.base_image, recipe.image_version)
+            .parse()
+            .into_diagnostic()?;
+
+        Ok(Driver::get_metadata(
+            &GetMetadataOpts::builder()
+                .image(&base_image)
+                .platform(self.platform)
+                .build(),
+        )?
+        .digest)
+    }
+
+    #[cfg_attr(not(feature = "validate"), allow(clippy::unnecessary_wraps))]
+    fn validate(&self, recipe_path: &Path) -> Result<()> {
+        if self.skip_validation {
+            return Ok(());
+        }
+
+        if self.offline {
+            let warning =
+                "Skipping validation of the recipe since its schemas can't be fetched offline";
+            warn!("{warning}");
+            summary::record_warning(warning);
+            return Ok(());
+        }
+
+        #[cfg(feature = "validate")]
+        ValidateCommand::builder()
+            .recipe(recipe_path.to_path_buf())
+            .build()
+            .try_run()?;
+
+        #[cfg(not(feature = "validate"))]
+        let _ = recipe_path;
 
         Ok(())
     }
+
+    /// Loads the lockfile of the recipe and applies it when offline.
+    fn load_lockfile(&self, recipe_path: &Path, recipe: &mut Recipe) -> Result<Option<Lockfile>> {
+        if !self.offline {
+            return Ok(None);
+        }
+
+        let lockfile = Lockfile::load(recipe_path)?;
+        lockfile.apply(recipe)?;
+        Ok(Some(lockfile))
+    }
+
+    /// Creates the lockfile of the recipe from what was resolved.
+    fn new_lockfile(&self, recipe: &Recipe, base_digest: &str) -> Result<Lockfile> {
+        Ok(Lockfile::new(
+            recipe,
+            base_digest.to_owned(),
+            self.os_version(recipe, None)?,
+            self.build_scripts_image(None)?,
+        )
+        .with_os_family(self.os_family(recipe, None)?))
+    }
+
+    /// Verifies the images that are copied into the build
+    /// and returns their digests for the lockfile.
+    fn verify_tools(
+        &self,
+        recipe: &Recipe,
+        lockfile: Option<&Lockfile>,
+    ) -> Result<BTreeMap<String, String>> {
+        if self.no_verify_tools {
+            let warning = "Skipping verification of the images that are copied into the build";
+            warn!("{warning}");
+            summary::record_warning(warning);
+            return Ok(BTreeMap::new());
+        }
+
+        let installer_image = format!(
+            "{BLUE_BUILD_IMAGE_REF}:{}",
+            recipe
+                .blue_build_tag
+                .as_deref()
+                .unwrap_or("latest-installer")
+        );
+        let mut images = vec![
+            self.build_scripts_image(lockfile)?,
+            MODULES_IMAGE.to_owned(),
+            COSIGN_IMAGE.to_owned(),
+            installer_image,
+        ];
+        images.extend(recipe.get_oci_module_sources().into_iter().map(Into::into));
+
+        images
+            .into_iter()
+            .map(|image| {
+                let image_ref: Reference = image.parse().into_diagnostic()?;
+                let locked_digest = lockfile
+                    .and_then(|lockfile| lockfile.tools.get(&image))
+                    .map(String::as_str);
+                let digest = Driver::verify_tool_image(&image_ref, locked_digest)?;
+                Ok((image, digest))
+            })
+            .collect()
+    }
+
+    fn labels(
+        recipe: &Recipe,
+        base_digest: &str,
+        os_version: u64,
+        module_manifest: &str,
+    ) -> Result<BTreeMap<String, String>> {
+        labels::check_keys(&recipe.labels)?;
+        let mut labels = labels::generate_default_labels()
+            .recipe(recipe)
+            .base_digest(base_digest)
+            .repo(&Driver::get_repo_url()?)
+            .maybe_revision(Driver::get_commit_sha().as_deref())
+            .version(&format!(
+                "{os_version}.{}",
+                blue_build_utils::get_tag_timestamp()
+            ))
+            .call();
+        labels.insert(MODULES_LABEL.into(), module_manifest.into());
+        Ok(labels)
+    }
+
+    fn os_version(&self, recipe: &Recipe, lockfile: Option<&Lockfile>) -> Result<u64> {
+        lockfile.map_or_else(
+            || {
+                Driver::get_os_version()
+                    .oci_ref(&recipe.base_image_ref()?)
+                    .platform(self.platform)
+                    .call()
+            },
+            |lockfile| Ok(lockfile.os_version),
+        )
+    }
+
+    /// Sets the kernel flavor of the akmods modules, which
+    /// the lockfile already did when offline.
+    fn akmods_flavor(
+        &self,
+        recipe: &mut Recipe,
+        os_version: u64,
+        lockfile: Option<&Lockfile>,
+    ) -> Result<Option<String>> {
+        if lockfile.is_some() {
+            return Ok(None);
+        }
+        akmods::select_flavor(recipe, os_version, self.platform)
+    }
+
+    fn os_family(&self, recipe: &Recipe, lockfile: Option<&Lockfile>) -> Result<OsFamily> {
+        let os_family = match lockfile {
+            Some(lockfile) => lockfile.os_family,
+            None => Driver::get_os_family()
+                .oci_ref(&recipe.base_image_ref()?)
+                .platform(self.platform)
+                .call(),
+        };
+
+        if os_family == OsFamily::Debian {
+            if let Some(module) = recipe
+                .all_modules()
+                .map(|module| &*module.module_type)
+                .find(|module_type| RPM_OSTREE_MODULES.contains(module_type))
+            {
+                bail!(
+                    help = "Use the `package` module to install packages on Debian based images",
+                    "The {module} module can't be used on the Debian based image {}",
+                    recipe.base_image
+                );
+            }
+        }
+        Ok(os_family)
+    }
+
+    /// The image of the build scripts, which is the one in the
+    /// lockfile, then the pinned one, then the one for this version.
+    fn build_scripts_image(&self, lockfile: Option<&Lockfile>) -> Result<String> {
+        if let Some(lockfile) = lockfile {
+            return Ok(lockfile.build_scripts_image.clone());
+        }
+        helper_images::pinned("build-scripts")
+            .map_or_else(|| Ok(determine_scripts_tag(self.platform)?.to_string()), Ok)
+    }
+
+    fn recipe_path(&self) -> PathBuf {
+        self.recipe.clone().unwrap_or_else(|| {
+            let legacy_path = Path::new(CONFIG_PATH);
+            let recipe_path = Path::new(RECIPE_PATH);
+            if recipe_path.exists() && recipe_path.is_dir() {
+                recipe_path.join(RECIPE_FILE)
+            } else {
+                let warning = format!("Use of {CONFIG_PATH} for recipes is deprecated, please move your recipe files into {RECIPE_PATH}");
+                warn!("{warning}");
+                summary::record_warning(warning);
+                legacy_path.join(RECIPE_FILE)
+            }
+        })
+    }
+}
+
+/// Pins each verified tool image to the digest it was verified at.
+fn pinned_tools(tools: &BTreeMap<String, String>) -> Result<BTreeMap<String, String>> {
+    tools
+        .iter()
+        .map(|(image, digest)| {
+            let image_ref: Reference = image.parse().into_diagnostic()?;
+            Ok((
+                image.clone(),
+                format!(
+                    "{}/{}@{digest}",
+                    image_ref.resolve_registry(),
+                    image_ref.repository()
+                ),
+            ))
+        })
+        .collect()
 }
 
 #[cached(
@@ -209,3 +724,68 @@ fn determine_scripts_tag(platform: Platform) -> Result<Reference> {
         })
         .inspect(|image| debug!("Using build scripts image: {image}"))
 }
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, path::Path};
+
+    use blue_build_recipe::Recipe;
+    use blue_build_template::{ContainerFileTemplate, Template};
+    use blue_build_utils::constants::{BUILD_SCRIPTS_IMAGE_REF, COSIGN_IMAGE, MODULES_IMAGE};
+    use uuid::Uuid;
+
+    use super::pinned_tools;
+
+    const RECIPE: &str = r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+modules: []
+";
+
+    const DIGEST: &str = "sha256:1111111111111111111111111111111111111111111111111111111111111111";
+
+    #[test]
+    fn renders_verified_digests() {
+        let recipe: Recipe = serde_yaml::from_str(RECIPE).unwrap();
+        let build_scripts_image = format!("{BUILD_SCRIPTS_IMAGE_REF}:v1");
+        let tools = [
+            MODULES_IMAGE,
+            COSIGN_IMAGE,
+            "ghcr.io/blue-build/cli:latest-installer",
+            &build_scripts_image,
+        ]
+        .into_iter()
+        .map(|image| (image.to_owned(), DIGEST.to_owned()))
+        .collect::<BTreeMap<_, _>>();
+        let tool_images = pinned_tools(&tools).unwrap();
+
+        let containerfile = ContainerFileTemplate::builder()
+            .os_version(41)
+            .build_id(Uuid::nil())
+            .recipe(&recipe)
+            .recipe_path(Path::new("recipe.yml"))
+            .registry("ghcr.io/test")
+            .build_scripts_image(&tool_images[&build_scripts_image])
+            .base_digest(DIGEST)
+            .tool_images(tool_images.clone())
+            .build()
+            .render()
+            .unwrap();
+
+        for image in tool_images.values() {
+            assert!(
+                containerfile.contains(&format!("from={image} "))
+                    || containerfile.contains(&format!("from={image},")),
+                "{image} isn't used in:\n{containerfile}"
+            );
+        }
+        for image in tools.keys() {
+            assert!(
+                !containerfile.contains(image.as_str()),
+                "{image} is used in:\n{containerfile}"
+            );
+        }
+    }
+}
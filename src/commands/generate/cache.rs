@@ -0,0 +1,136 @@
+//! Cache of rendered Containerfiles.
+//!
+//! Entries are keyed by the hash of everything that goes into
+//! rendering the Containerfile, so an entry can be used as is
+//! whenever its key matches. The build ID changes on every run,
+//! so it's swapped out for a placeholder in the cached file.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use log::{debug, trace};
+use miette::{IntoDiagnostic, Result};
+use uuid::Uuid;
+
+use crate::content_hash::ContentHasher;
+
+const BUILD_ID_PLACEHOLDER: &str = "__BLUEBUILD_BUILD_ID__";
+
+/// The directory that rendered Containerfiles are cached in.
+pub fn cache_dir() -> Option<PathBuf> {
+    blue_build_utils::cache_dir().map(|cache| cache.join("containerfiles"))
+}
+
+/// Creates the cache key for a Containerfile.
+///
+/// The `content_hash` already covers the CLI version and
+/// commit, which is what the templates are versioned by.
+pub fn cache_key(
+    content_hash: &str,
+    registry: &str,
+    labels: &BTreeMap<String, String>,
+    prebuilt_stages: &[String],
+) -> String {
+    let mut hasher = ContentHasher::new();
+    hasher
+        .add("content-hash", content_hash)
+        .add("registry", registry)
+        .add("prebuilt-stages", prebuilt_stages.join(","));
+    for (key, value) in labels {
+        hasher.add(&format!("label:{key}"), value);
+    }
+    hasher.finish()
+}
+
+/// Gets the cached Containerfile for `key` with the
+/// placeholder replaced by the current `build_id`.
+pub fn get(dir: &Path, key: &str, build_id: Uuid) -> Option<String> {
+    let path = dir.join(key);
+    trace!("generate::cache::get({})", path.display());
+
+    let containerfile = fs::read_to_string(&path).ok()?;
+    debug!("Using cached Containerfile {}", path.display());
+
+    Some(containerfile.replace(BUILD_ID_PLACEHOLDER, &build_id.to_string()))
+}
+
+/// Stores the rendered Containerfile under `key`.
+///
+/// The file is written to a temporary file first so that
+/// a concurrent run never reads a partially written entry.
+///
+/// # Errors
+/// Will error if the cache directory or file can't be written.
+pub fn put(dir: &Path, key: &str, build_id: Uuid, containerfile: &str) -> Result<()> {
+    let path = dir.join(key);
+    trace!("generate::cache::put({})", path.display());
+
+    fs::create_dir_all(dir).into_diagnostic()?;
+
+    let tmp = dir.join(format!(".{key}.{}", Uuid::new_v4()));
+    fs::write(
+        &tmp,
+        containerfile.replace(&build_id.to_string(), BUILD_ID_PLACEHOLDER),
+    )
+    .into_diagnostic()?;
+    fs::rename(&tmp, &path).into_diagnostic()
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use tempfile::TempDir;
+    use uuid::Uuid;
+
+    use super::{cache_key, get, put};
+
+    fn labels() -> BTreeMap<String, String> {
+        BTreeMap::from([(
+            "org.opencontainers.image.source".into(),
+            "https://example.com/repo".into(),
+        )])
+    }
+
+    #[test]
+    fn cache_round_trip() {
+        let dir = TempDir::new().unwrap();
+        let key = cache_key("hash", "ghcr.io/blue-build", &labels(), &[]);
+        let first_id = Uuid::new_v4();
+        let second_id = Uuid::new_v4();
+
+        assert_eq!(get(dir.path(), &key, first_id), None);
+
+        put(
+            dir.path(),
+            &key,
+            first_id,
+            &format!("ARG CACHEBUST=\"{first_id}\"\nLABEL id=\"{first_id}\"\n"),
+        )
+        .unwrap();
+
+        assert_eq!(
+            get(dir.path(), &key, second_id).unwrap(),
+            format!("ARG CACHEBUST=\"{second_id}\"\nLABEL id=\"{second_id}\"\n")
+        );
+        assert_eq!(
+            get(
+                dir.path(),
+                &cache_key("other", "ghcr.io/blue-build", &labels(), &[]),
+                second_id
+            ),
+            None
+        );
+        assert_eq!(
+            get(
+                dir.path(),
+                &cache_key("hash", "ghcr.io/blue-build", &labels(), &["builder".into()]),
+                second_id
+            ),
+            None
+        );
+    }
+}
@@ -0,0 +1,167 @@
+//! Selection of the module to show with `--explain`.
+//!
+//! Modules are numbered in the order they appear in the
+//! Containerfile, so the modules of every stage come before
+//! the modules of the final image.
+
+use std::fmt::Write as _;
+
+use blue_build_recipe::{ModuleRequiredFields, Recipe};
+use miette::{miette, Result};
+
+/// A module of the recipe and where it runs.
+#[derive(Debug, Clone, Copy)]
+pub struct NumberedModule<'a> {
+    /// The position of the module, starting at 1.
+    pub number: usize,
+
+    /// The stage the module runs in, or
+    /// `None` if it runs in the final image.
+    pub stage: Option<&'a str>,
+
+    pub module: &'a ModuleRequiredFields<'a>,
+}
+
+/// Gets every module in the order they're run.
+pub fn numbered_modules<'a>(recipe: &'a Recipe<'a>) -> Vec<NumberedModule<'a>> {
+    recipe
+        .stages_ext
+        .iter()
+        .flat_map(|stages_ext| &stages_ext.stages)
+        .filter_map(|stage| stage.required_fields.as_ref())
+        .flat_map(|stage| {
+            stage
+                .modules_ext
+                .modules
+                .iter()
+                .map(|module| (Some(&*stage.name), module))
+        })
+        .chain(
+            recipe
+                .modules_ext
+                .modules
+                .iter()
+                .map(|module| (None, module)),
+        )
+        .filter_map(|(stage, module)| Some((stage, module.required_fields.as_ref()?)))
+        .enumerate()
+        .map(|(index, (stage, module))| NumberedModule {
+            number: index + 1,
+            stage,
+            module,
+        })
+        .collect()
+}
+
+/// Finds the modules selected by their number or type.
+///
+/// # Errors
+/// Will error if no module matches, listing the modules of the recipe.
+pub fn find_modules<'a>(recipe: &'a Recipe<'a>, selector: &str) -> Result<Vec<NumberedModule<'a>>> {
+    let modules = numbered_modules(recipe);
+
+    let selected = selector.parse::<usize>().map_or_else(
+        |_| {
+            modules
+                .iter()
+                .filter(|m| m.module.module_type == selector)
+                .copied()
+                .collect::<Vec<_>>()
+        },
+        |number| {
+            modules
+                .iter()
+                .filter(|m| m.number == number)
+                .copied()
+                .collect()
+        },
+    );
+
+    if selected.is_empty() {
+        let mut help = String::from("The modules of the recipe are:");
+        for m in &modules {
+            let _ = write!(help, "\n  {}: {}", m.number, m.module.module_type);
+            if let Some(stage) = m.stage {
+                let _ = write!(help, " (stage {stage})");
+            }
+        }
+        return Err(miette!(help = help, "No module matches {selector}"));
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_recipe::{
+        Module, ModuleExt, ModuleRequiredFields, Recipe, Stage, StageRequiredFields, StagesExt,
+    };
+    use rstest::rstest;
+
+    use super::find_modules;
+
+    fn module(module_type: &str) -> Module<'_> {
+        Module::builder()
+            .required_fields(
+                ModuleRequiredFields::builder()
+                    .module_type(module_type)
+                    .build(),
+            )
+            .build()
+    }
+
+    fn recipe() -> Recipe<'static> {
+        Recipe::builder()
+            .name("test")
+            .description("test")
+            .base_image("base")
+            .image_version("latest")
+            .stages_ext(
+                StagesExt::builder()
+                    .stages(vec![Stage::builder()
+                        .required_fields(
+                            StageRequiredFields::builder()
+                                .name("builder")
+                                .from("fedora")
+                                .modules_ext(
+                                    ModuleExt::builder().modules(vec![module("script")]).build(),
+                                )
+                                .build(),
+                        )
+                        .build()])
+                    .build(),
+            )
+            .modules_ext(
+                ModuleExt::builder()
+                    .modules(vec![module("rpm-ostree"), module("script")])
+                    .build(),
+            )
+            .build()
+    }
+
+    #[rstest]
+    #[case("1", &[(1, Some("builder"), "script")])]
+    #[case("3", &[(3, None, "script")])]
+    #[case("rpm-ostree", &[(2, None, "rpm-ostree")])]
+    #[case("script", &[(1, Some("builder"), "script"), (3, None, "script")])]
+    fn select_modules(#[case] selector: &str, #[case] expected: &[(usize, Option<&str>, &str)]) {
+        let recipe = recipe();
+        let selected = find_modules(&recipe, selector).unwrap();
+
+        assert_eq!(
+            selected
+                .iter()
+                .map(|m| (m.number, m.stage, &*m.module.module_type))
+                .collect::<Vec<_>>(),
+            expected
+        );
+    }
+
+    #[rstest]
+    #[case("0")]
+    #[case("4")]
+    #[case("files")]
+    fn select_missing_module(#[case] selector: &str) {
+        assert!(find_modules(&recipe(), selector).is_err());
+    }
+}
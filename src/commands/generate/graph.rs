@@ -0,0 +1,380 @@
+//! Draws recipes as a graph of their stages and modules, with
+//! the images they're based on and what they copy from each other.
+//!
+//! Recipes that share a base image, like the recipes of a matrix
+//! build, are drawn in one graph so the shared image stands out.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_recipe::{Module, Recipe};
+use blue_build_utils::constants::{RECIPE_FILE, RECIPE_PATH};
+use bon::Builder;
+use clap::{Args, ValueEnum};
+use log::{info, trace};
+use miette::{IntoDiagnostic, Result};
+
+use crate::commands::BlueBuildCommand;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum GraphFormat {
+    /// Graphviz DOT, which can be rendered with `dot -Tsvg`.
+    #[default]
+    Dot,
+
+    /// Mermaid, which renders in Markdown on GitHub and GitLab.
+    Mermaid,
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct GenerateGraphCommand {
+    /// The recipes to draw.
+    ///
+    /// Defaults to `recipes/recipe.yml`.
+    #[arg()]
+    #[builder(default, into)]
+    recipes: Vec<PathBuf>,
+
+    /// The language to write the graph in.
+    #[arg(long, default_value = "dot")]
+    #[builder(default)]
+    graph_format: GraphFormat,
+
+    /// File to output to instead of STDOUT
+    #[arg(short, long)]
+    #[builder(into)]
+    output: Option<PathBuf>,
+}
+
+impl BlueBuildCommand for GenerateGraphCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("GenerateGraphCommand::try_run()");
+
+        let recipe_paths = if self.recipes.is_empty() {
+            vec![Path::new(RECIPE_PATH).join(RECIPE_FILE)]
+        } else {
+            self.recipes.clone()
+        };
+        let recipes = recipe_paths
+            .iter()
+            .map(Recipe::parse)
+            .collect::<Result<Vec<_>>>()?;
+
+        let graph = Graph::new(&recipes);
+        let rendered = match self.graph_format {
+            GraphFormat::Dot => graph.to_dot(),
+            GraphFormat::Mermaid => graph.to_mermaid(),
+        };
+
+        if let Some(output) = self.output.as_ref() {
+            fs::write(output, rendered).into_diagnostic()?;
+            info!("Wrote the graph to {}", output.display());
+        } else {
+            print!("{rendered}");
+        }
+        Ok(())
+    }
+}
+
+/// A stage or the final image of a recipe, with its modules in order.
+#[derive(Debug)]
+struct Cluster {
+    id: String,
+    label: String,
+    nodes: Vec<(String, String)>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Edge {
+    from: String,
+    to: String,
+    label: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct Graph {
+    /// The ID of the node of each image that isn't built by a recipe.
+    images: BTreeMap<String, String>,
+    clusters: Vec<Cluster>,
+    edges: Vec<Edge>,
+}
+
+impl Graph {
+    fn new(recipes: &[Recipe]) -> Self {
+        let mut graph = Self::default();
+        for (index, recipe) in recipes.iter().enumerate() {
+            graph.add_recipe(index, recipe);
+        }
+        graph
+    }
+
+    fn add_recipe(&mut self, index: usize, recipe: &Recipe) {
+        // The last node of each stage, which later
+        // stages and `copy` modules can refer to
+        let mut stages = HashMap::new();
+
+        for (stage_index, stage) in recipe
+            .stages_ext
+            .iter()
+            .flat_map(|stages_ext| &stages_ext.stages)
+            .filter_map(|stage| stage.required_fields.as_ref())
+            .enumerate()
+        {
+            let bases = stage.platform_bases().map_or_else(
+                || vec![&*stage.from],
+                |bases| {
+                    let mut bases = bases.into_iter().map(|(_, base)| base).collect::<Vec<_>>();
+                    bases.dedup();
+                    bases
+                },
+            );
+            let bases = bases
+                .into_iter()
+                .map(|base| self.source(&stages, base))
+                .collect::<Vec<_>>();
+
+            let last = self.add_cluster(
+                format!("r{index}_s{stage_index}"),
+                format!("stage {}", stage.name),
+                &bases,
+                &stage.modules_ext.modules,
+                &stages,
+            );
+            stages.insert(stage.name.to_string(), last);
+        }
+
+        let base = self.image(&format!("{}:{}", recipe.base_image, recipe.image_version));
+        self.add_cluster(
+            format!("r{index}_image"),
+            recipe.name.to_string(),
+            &[base],
+            &recipe.modules_ext.modules,
+            &stages,
+        );
+    }
+
+    /// Adds the modules of a stage or image and
+    /// returns the ID of its last node.
+    fn add_cluster(
+        &mut self,
+        id: String,
+        label: String,
+        bases: &[String],
+        modules: &[Module],
+        stages: &HashMap<String, String>,
+    ) -> String {
+        let mut nodes = Vec::new();
+
+        for module in modules
+            .iter()
+            .filter_map(|module| module.required_fields.as_ref())
+        {
+            let node = format!("{id}_m{}", nodes.len());
+
+            if let Some((stage, artifact, _)) = module.get_copy_artifact() {
+                let from = self.source(stages, stage);
+                self.edge(from, &node, Some(artifact));
+            } else if let Some((Some(from), _, _)) = module.get_copy_args() {
+                let from = self.source(stages, from);
+                self.edge(from, &node, Some("copy"));
+            }
+
+            nodes.push((node, module.module_type.to_string()));
+        }
+        if nodes.is_empty() {
+            nodes.push((format!("{id}_m0"), String::from("no modules")));
+        }
+
+        for base in bases {
+            self.edge(base.clone(), &nodes[0].0, None);
+        }
+        for pair in nodes.windows(2) {
+            self.edge(pair[0].0.clone(), &pair[1].0, None);
+        }
+
+        let last = nodes[nodes.len() - 1].0.clone();
+        self.clusters.push(Cluster { id, label, nodes });
+        last
+    }
+
+    /// The ID of the last node of a stage, or of the node of an image.
+    fn source(&mut self, stages: &HashMap<String, String>, from: &str) -> String {
+        stages
+            .get(from)
+            .cloned()
+            .unwrap_or_else(|| self.image(from))
+    }
+
+    fn image(&mut self, image: &str) -> String {
+        let next_id = format!("image{}", self.images.len());
+        self.images
+            .entry(image.to_owned())
+            .or_insert(next_id)
+            .clone()
+    }
+
+    fn edge(&mut self, from: String, to: &str, label: Option<&str>) {
+        self.edges.push(Edge {
+            from,
+            to: to.to_owned(),
+            label: label.map(ToOwned::to_owned),
+        });
+    }
+
+    fn to_dot(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+        let mut dot = String::from("digraph recipes {\n  rankdir=TB;\n  node [shape=box];\n");
+
+        for (image, id) in &self.images {
+            _ = writeln!(dot, "  {id} [label={}, shape=ellipse];", quote(image));
+        }
+        for cluster in &self.clusters {
+            _ = writeln!(dot, "  subgraph cluster_{} {{", cluster.id);
+            _ = writeln!(dot, "    label={};", quote(&cluster.label));
+            for (id, label) in &cluster.nodes {
+                _ = writeln!(dot, "    {id} [label={}];", quote(label));
+            }
+            dot.push_str("  }\n");
+        }
+        for edge in &self.edges {
+            match &edge.label {
+                Some(label) => {
+                    _ = writeln!(
+                        dot,
+                        "  {} -> {} [label={}, style=dashed];",
+                        edge.from,
+                        edge.to,
+                        quote(label)
+                    );
+                }
+                None => _ = writeln!(dot, "  {} -> {};", edge.from, edge.to),
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    fn to_mermaid(&self) -> String {
+        let quote = |s: &str| format!("\"{}\"", s.replace('"', "#quot;"));
+        let mut mermaid = String::from("flowchart TD\n");
+
+        for (image, id) in &self.images {
+            _ = writeln!(mermaid, "  {id}([{}])", quote(image));
+        }
+        for cluster in &self.clusters {
+            _ = writeln!(
+                mermaid,
+                "  subgraph {} [{}]",
+                cluster.id,
+                quote(&cluster.label)
+            );
+            for (id, label) in &cluster.nodes {
+                _ = writeln!(mermaid, "    {id}[{}]", quote(label));
+            }
+            mermaid.push_str("  end\n");
+        }
+        for edge in &self.edges {
+            match &edge.label {
+                Some(label) => {
+                    _ = writeln!(
+                        mermaid,
+                        "  {} -.->|{}| {}",
+                        edge.from,
+                        quote(label),
+                        edge.to
+                    );
+                }
+                None => _ = writeln!(mermaid, "  {} --> {}", edge.from, edge.to),
+            }
+        }
+        mermaid
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_recipe::Recipe;
+
+    use super::Graph;
+
+    const RECIPE: &str = r"
+name: my-image
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+stages:
+  - name: builder
+    from: rust
+    artifacts:
+      binary: /out/app
+    modules:
+      - type: containerfile
+        snippets:
+          - RUN cargo build
+modules:
+  - type: rpm-ostree
+    install: [micro]
+  - type: copy
+    from: builder
+    src: /out/app
+    dest: /usr/bin/app
+  - type: copy
+    from-stage: builder
+    artifact: binary
+    dest: /usr/bin/app
+";
+
+    const OTHER_RECIPE: &str = r"
+name: my-other-image
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+modules: []
+";
+
+    fn graph() -> Graph {
+        Graph::new(&[
+            serde_yaml::from_str::<Recipe>(RECIPE).unwrap(),
+            serde_yaml::from_str::<Recipe>(OTHER_RECIPE).unwrap(),
+        ])
+    }
+
+    #[test]
+    fn dot() {
+        let dot = graph().to_dot();
+
+        assert!(dot.starts_with("digraph recipes {\n"));
+        assert!(dot.contains(
+            "  image1 [label=\"ghcr.io/ublue-os/silverblue-main:41\", shape=ellipse];\n"
+        ));
+        assert!(dot.contains("  image0 [label=\"rust\", shape=ellipse];\n"));
+        assert!(dot.contains("  subgraph cluster_r0_s0 {\n    label=\"stage builder\";\n"));
+        assert!(dot.contains("    r0_image_m0 [label=\"rpm-ostree\"];\n"));
+        assert!(dot.contains("  image0 -> r0_s0_m0;\n"));
+        assert!(dot.contains("  r0_image_m0 -> r0_image_m1;\n"));
+        assert!(dot.contains("  r0_s0_m0 -> r0_image_m1 [label=\"copy\", style=dashed];\n"));
+        assert!(dot.contains("  r0_s0_m0 -> r0_image_m2 [label=\"binary\", style=dashed];\n"));
+
+        // Both recipes are based on the same image
+        assert!(dot.contains("  image1 -> r0_image_m0;\n"));
+        assert!(dot.contains("  image1 -> r1_image_m0;\n"));
+        assert!(dot.contains("    r1_image_m0 [label=\"no modules\"];\n"));
+    }
+
+    #[test]
+    fn mermaid() {
+        let mermaid = graph().to_mermaid();
+
+        assert!(mermaid.starts_with("flowchart TD\n"));
+        assert!(mermaid.contains("  image1([\"ghcr.io/ublue-os/silverblue-main:41\"])\n"));
+        assert!(mermaid.contains("  subgraph r0_image [\"my-image\"]\n"));
+        assert!(mermaid.contains("    r0_s0_m0[\"containerfile\"]\n"));
+        assert!(mermaid.contains("  image0 --> r0_s0_m0\n"));
+        assert!(mermaid.contains("  r0_s0_m0 -.->|\"copy\"| r0_image_m1\n"));
+        assert!(mermaid.ends_with("  image1 --> r1_image_m0\n"));
+    }
+}
@@ -0,0 +1,257 @@
+//! Generation of podman Quadlet units that run a built image
+//! as a system service.
+//!
+//! The `.image` unit pulls the image and the `.container` unit
+//! runs it. When the signature of the image is verified, the
+//! image is pulled with its own signature policy so that the
+//! system policy doesn't need to change.
+
+use std::{fs, path::PathBuf};
+
+use blue_build_template::{
+    QuadletContainerTemplate, QuadletImageTemplate, QuadletPolicyTemplate,
+    QuadletRegistriesTemplate, Template,
+};
+use bon::Builder;
+use clap::Args;
+use log::{info, trace};
+use miette::{bail, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+
+use crate::commands::BlueBuildCommand;
+
+const QUADLET_DIR: &str = "/etc/containers/systemd";
+const REGISTRIES_D_DIR: &str = "/etc/containers/registries.d";
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct GenerateQuadletCommand {
+    /// The image to run, like `ghcr.io/octocat/my-server:latest`.
+    #[arg()]
+    #[builder(into)]
+    image: String,
+
+    /// The name of the container and its units.
+    ///
+    /// Defaults to the name of the image.
+    #[arg(long)]
+    #[builder(into)]
+    name: Option<String>,
+
+    /// Directory to write the units to instead of STDOUT.
+    #[arg(short, long)]
+    #[builder(into)]
+    output: Option<PathBuf>,
+
+    /// A port to publish, like `8080:80`.
+    /// Can be used more than once.
+    #[arg(short, long = "publish", value_name = "PORT")]
+    #[builder(default, into)]
+    ports: Vec<String>,
+
+    /// A volume to mount, like `/srv/data:/data:Z`.
+    /// Can be used more than once.
+    #[arg(long = "volume", value_name = "VOLUME")]
+    #[builder(default, into)]
+    volumes: Vec<String>,
+
+    /// An environment variable to set in the container.
+    /// Can be used more than once.
+    #[arg(short, long = "env", value_name = "KEY=VALUE")]
+    #[builder(default, into)]
+    env: Vec<String>,
+
+    /// Let `podman auto-update` restart the
+    /// container when the image is updated.
+    #[arg(long)]
+    #[builder(default)]
+    auto_update: bool,
+
+    /// Refuse to pull the image unless it's signed by
+    /// the private key of this public key.
+    ///
+    /// This is the path of the key on the machine that
+    /// runs the container, like `/etc/pki/containers/my-server.pub`.
+    #[arg(long, value_name = "PATH")]
+    #[builder(into)]
+    verify_key: Option<String>,
+}
+
+/// A file to install on the machine that runs the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct QuadletFile {
+    /// Where the file is installed.
+    path: String,
+    contents: String,
+}
+
+impl BlueBuildCommand for GenerateQuadletCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("GenerateQuadletCommand::try_run()");
+
+        let files = self.files()?;
+
+        if let Some(output) = self.output.as_ref() {
+            fs::create_dir_all(output).into_diagnostic()?;
+
+            for file in &files {
+                let file_name = file.path.rsplit('/').next().unwrap_or_default();
+                fs::write(output.join(file_name), &file.contents).into_diagnostic()?;
+                info!("Generated {file_name}, install it to {}", file.path);
+            }
+        } else {
+            print!(
+                "{}",
+                files
+                    .iter()
+                    .map(|file| format!("# {}\n{}\n", file.path, file.contents.trim_end()))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            );
+        }
+        Ok(())
+    }
+}
+
+impl GenerateQuadletCommand {
+    fn files(&self) -> Result<Vec<QuadletFile>> {
+        let image: Reference = self.image.parse().into_diagnostic()?;
+        let name = self.name.clone().unwrap_or_else(|| {
+            image
+                .repository()
+                .rsplit('/')
+                .next()
+                .unwrap_or_default()
+                .to_owned()
+        });
+
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'))
+        {
+            bail!("The name {name} can only contain letters, numbers, '-', '_', and '.'");
+        }
+
+        let repo = format!("{}/{}", image.registry(), image.repository());
+        let policy_path = format!("/etc/containers/{name}-policy.json");
+
+        let mut files = vec![
+            QuadletFile {
+                path: format!("{QUADLET_DIR}/{name}.container"),
+                contents: QuadletContainerTemplate::builder()
+                    .name(&name)
+                    .ports(self.ports.iter().map(Into::into).collect())
+                    .volumes(self.volumes.iter().map(Into::into).collect())
+                    .env(self.env.iter().map(Into::into).collect())
+                    .auto_update(self.auto_update)
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            },
+            QuadletFile {
+                path: format!("{QUADLET_DIR}/{name}.image"),
+                contents: QuadletImageTemplate::builder()
+                    .name(&name)
+                    .image(image.whole())
+                    .maybe_signature_policy(self.verify_key.as_ref().map(|_| &*policy_path))
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            },
+        ];
+
+        if let Some(key_path) = self.verify_key.as_deref() {
+            files.push(QuadletFile {
+                path: policy_path,
+                contents: QuadletPolicyTemplate::builder()
+                    .repo(&repo)
+                    .key_path(key_path)
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            });
+            files.push(QuadletFile {
+                path: format!("{REGISTRIES_D_DIR}/{name}.yaml"),
+                contents: QuadletRegistriesTemplate::builder()
+                    .repo(&repo)
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            });
+        }
+
+        Ok(files)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::GenerateQuadletCommand;
+
+    #[test]
+    fn generate_units() {
+        let files = GenerateQuadletCommand::builder()
+            .image("ghcr.io/octocat/my-server:latest")
+            .ports(vec!["8080:80".into()])
+            .auto_update(true)
+            .build()
+            .files()
+            .unwrap();
+
+        assert_eq!(
+            files.iter().map(|f| &*f.path).collect::<Vec<_>>(),
+            [
+                "/etc/containers/systemd/my-server.container",
+                "/etc/containers/systemd/my-server.image"
+            ]
+        );
+        assert!(files[0].contents.contains("Image=my-server.image\n"));
+        assert!(files[0].contents.contains("PublishPort=8080:80\n"));
+        assert!(files[0].contents.contains("AutoUpdate=registry\n"));
+        assert!(files[1]
+            .contents
+            .contains("Image=ghcr.io/octocat/my-server:latest"));
+        assert!(!files[1].contents.contains("PodmanArgs"));
+    }
+
+    #[test]
+    fn generate_verified_units() {
+        let files = GenerateQuadletCommand::builder()
+            .image("ghcr.io/octocat/my-server:latest")
+            .name("server")
+            .verify_key("/etc/pki/containers/server.pub")
+            .build()
+            .files()
+            .unwrap();
+
+        assert_eq!(
+            files.iter().map(|f| &*f.path).collect::<Vec<_>>(),
+            [
+                "/etc/containers/systemd/server.container",
+                "/etc/containers/systemd/server.image",
+                "/etc/containers/server-policy.json",
+                "/etc/containers/registries.d/server.yaml",
+            ]
+        );
+        assert!(files[1]
+            .contents
+            .contains("PodmanArgs=--signature-policy=/etc/containers/server-policy.json"));
+
+        let policy: serde_json::Value = serde_json::from_str(&files[2].contents).unwrap();
+        assert_eq!(
+            policy["transports"]["docker"]["ghcr.io/octocat/my-server"][0]["keyPath"],
+            "/etc/pki/containers/server.pub"
+        );
+        assert!(files[3].contents.contains("ghcr.io/octocat/my-server:"));
+    }
+
+    #[test]
+    fn invalid_name() {
+        assert!(GenerateQuadletCommand::builder()
+            .image("ghcr.io/octocat/my-server:latest")
+            .name("my server")
+            .build()
+            .files()
+            .is_err());
+    }
+}
@@ -4,7 +4,11 @@ use std::{
 };
 
 use blue_build_recipe::Recipe;
-use blue_build_utils::{constants::ARCHIVE_SUFFIX, string_vec, traits::CowCollecter};
+use blue_build_utils::{
+    constants::{ARCHIVE_SUFFIX, BB_INSECURE_ALLOW_UNVERIFIED_TOOLS},
+    helper_images, string_vec,
+    traits::CowCollecter,
+};
 use bon::Builder;
 use clap::{Args, Subcommand, ValueEnum};
 use miette::{bail, Context, IntoDiagnostic, Result};
@@ -18,6 +22,8 @@ use blue_build_process_management::{
 
 use super::{build::BuildCommand, BlueBuildCommand};
 
+const INSTALLER_IMAGE: &str = "ghcr.io/jasonn3/build-container-installer";
+
 #[derive(Clone, Debug, Builder, Args)]
 pub struct GenerateIsoCommand {
     #[command(subcommand)]
@@ -76,6 +82,16 @@ pub struct GenerateIsoCommand {
     #[arg(long)]
     tempdir: Option<PathBuf>,
 
+    /// Run the installer image even if its signature
+    /// can't be verified.
+    ///
+    /// WARN: The installer image runs with `--privileged`,
+    /// so an image that was tampered with gets root
+    /// on this machine.
+    #[arg(long, env = BB_INSECURE_ALLOW_UNVERIFIED_TOOLS)]
+    #[builder(default)]
+    insecure_allow_unverified_tools: bool,
+
     #[clap(flatten)]
     #[builder(default)]
     drivers: DriverArgs,
@@ -125,7 +141,7 @@ impl std::fmt::Display for GenIsoVariant {
 
 impl BlueBuildCommand for GenerateIsoCommand {
     fn try_run(&mut self) -> Result<()> {
-        Driver::init(self.drivers);
+        Driver::init(self.drivers.clone());
 
         if !nix::unistd::Uid::effective().is_root()
             && matches!(Driver::get_run_driver(), RunDriverType::Podman)
@@ -181,6 +197,8 @@ impl BlueBuildCommand for GenerateIsoCommand {
             fs::remove_file(iso_path).into_diagnostic()?;
         }
 
+        // The build of the recipe sets this from its own args
+        Driver::set_allow_unverified_tools(self.insecure_allow_unverified_tools);
         self.build_iso(iso_name, &output_dir, image_out_dir.path())
     }
 }
@@ -217,7 +235,7 @@ impl GenerateIsoCommand {
                 };
 
                 args.extend([
-                    format!("IMAGE_NAME={image_name}",),
+                    format!("IMAGE_NAME={image_name}"),
                     format!("IMAGE_REPO={image_repo}"),
                     format!("IMAGE_TAG={}", image.tag().unwrap_or("latest")),
                     format!(
@@ -247,9 +265,18 @@ impl GenerateIsoCommand {
             }
         }
 
+        let installer_image = Driver::verify_helper_image(
+            &Reference::try_from(helper_images::helper_image(
+                "iso-installer",
+                INSTALLER_IMAGE,
+            ))
+            .into_diagnostic()?,
+        )?
+        .to_string();
+
         // Currently testing local tarball builds
         let opts = RunOpts::builder()
-            .image("ghcr.io/jasonn3/build-container-installer")
+            .image(installer_image)
             .privileged(true)
             .remove(true)
             .args(args.collect_cow_vec())
@@ -0,0 +1,355 @@
+//! Generates policies that verify the signatures of the images of a
+//! recipe, so that clusters and hosts can enforce them.
+//!
+//! Images signed with the `cosign.pub` of the repo are verified with
+//! that key, otherwise the identity and issuer of keyless signing in
+//! CI are used.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_process_management::drivers::{types::CiDriverType, CiDriver, Driver};
+use blue_build_recipe::Recipe;
+use blue_build_utils::constants::{
+    BB_REGISTRY, BB_REGISTRY_NAMESPACE, COSIGN_PUB_PATH, RECIPE_FILE, RECIPE_PATH,
+};
+use bon::Builder;
+use clap::{Args, ValueEnum};
+use log::{debug, info, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use serde_json::{json, Value};
+
+use super::{build, BlueBuildCommand};
+
+const FULCIO_URL: &str = "https://fulcio.sigstore.dev";
+const REKOR_URL: &str = "https://rekor.sigstore.dev";
+const KEYS_DIR: &str = "/etc/pki/containers";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PolicyType {
+    /// A `ClusterImagePolicy` for the Sigstore policy controller.
+    SigstorePolicyController,
+
+    /// A `ClusterPolicy` for Kyverno.
+    Kyverno,
+
+    /// A `policy.json` for podman, skopeo, and bootc hosts.
+    #[value(name = "containers-policy.json")]
+    ContainersPolicyJson,
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct GeneratePolicyCommand {
+    /// The recipe of the images to verify.
+    #[arg()]
+    #[builder(into)]
+    recipe: Option<PathBuf>,
+
+    /// The type of policy to generate.
+    #[arg(short = 't', long = "type")]
+    policy_type: PolicyType,
+
+    /// The image repo to verify, like `ghcr.io/octocat/my-image`.
+    ///
+    /// This is taken from the recipe by default.
+    #[arg(long)]
+    #[builder(into)]
+    image: Option<String>,
+
+    /// The registry that the images are pushed to.
+    #[arg(long, env = BB_REGISTRY)]
+    #[builder(into)]
+    registry: Option<String>,
+
+    /// The url path to your base
+    /// project images.
+    #[arg(long, env = BB_REGISTRY_NAMESPACE, visible_alias("registry-path"))]
+    #[builder(into)]
+    registry_namespace: Option<String>,
+
+    /// The OIDC issuer of keyless signing.
+    ///
+    /// Defaults to the issuer of the CI system when
+    /// the repo doesn't have a `cosign.pub`.
+    #[arg(long, requires = "identity")]
+    #[builder(into)]
+    issuer: Option<String>,
+
+    /// A regular expression for the identity of keyless signing,
+    /// like the workflow that signs the images.
+    ///
+    /// Defaults to the identity of the CI system when
+    /// the repo doesn't have a `cosign.pub`.
+    #[arg(long, requires = "issuer")]
+    #[builder(into)]
+    identity: Option<String>,
+
+    /// The path that the public key is installed at on the host.
+    /// Only used for `containers-policy.json`.
+    ///
+    /// Defaults to `/etc/pki/containers/<name>.pub`.
+    #[arg(long)]
+    #[builder(into)]
+    key_path: Option<String>,
+
+    /// The file to write the policy to instead of stdout.
+    #[arg(short, long)]
+    #[builder(into)]
+    output: Option<PathBuf>,
+}
+
+/// How the signatures of the images are verified.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Verification {
+    /// The contents of the public key.
+    Key(String),
+    Keyless {
+        issuer: String,
+        identity: String,
+    },
+}
+
+impl BlueBuildCommand for GeneratePolicyCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("GeneratePolicyCommand::try_run()");
+
+        let recipe_path = self
+            .recipe
+            .clone()
+            .unwrap_or_else(|| Path::new(RECIPE_PATH).join(RECIPE_FILE));
+        let recipe = Recipe::parse(&recipe_path)?;
+        let name = recipe.name.trim().replace('/', "-");
+        let image = self.image(&recipe)?;
+
+        let policy = generate_policy(
+            self.policy_type,
+            &name,
+            &image,
+            &self.verification()?,
+            &self
+                .key_path
+                .clone()
+                .unwrap_or_else(|| format!("{KEYS_DIR}/{name}.pub")),
+        )?;
+
+        if let Some(output) = &self.output {
+            fs::write(output, policy)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to write {}", output.display()))?;
+            info!("Wrote the policy for {image} to {}", output.display());
+        } else {
+            print!("{policy}");
+        }
+        Ok(())
+    }
+}
+
+impl GeneratePolicyCommand {
+    fn image(&self, recipe: &Recipe) -> Result<String> {
+        if let Some(image) = &self.image {
+            return Ok(image.trim().to_lowercase());
+        }
+
+        build::image_name(
+            recipe,
+            self.registry.as_deref(),
+            self.registry_namespace.as_deref(),
+        )
+    }
+
+    fn verification(&self) -> Result<Verification> {
+        if let (Some(issuer), Some(identity)) = (&self.issuer, &self.identity) {
+            return Ok(Verification::Keyless {
+                issuer: issuer.clone(),
+                identity: identity.clone(),
+            });
+        }
+
+        if Path::new(COSIGN_PUB_PATH).exists() {
+            debug!("Verifying with the key in {COSIGN_PUB_PATH}");
+            return Ok(Verification::Key(
+                fs::read_to_string(COSIGN_PUB_PATH)
+                    .into_diagnostic()
+                    .with_context(|| format!("Failed to read {COSIGN_PUB_PATH}"))?,
+            ));
+        }
+
+        if matches!(Driver::get_ci_driver(), CiDriverType::Local) {
+            bail!(
+                help = "Pass `--issuer` and `--identity` for keyless signing",
+                "The repo doesn't have a {COSIGN_PUB_PATH} and isn't being built in CI"
+            );
+        }
+
+        Ok(Verification::Keyless {
+            issuer: Driver::oidc_provider()?,
+            identity: Driver::keyless_cert_identity()?,
+        })
+    }
+}
+
+/// Renders a policy that requires the images of `image` to be signed.
+fn generate_policy(
+    policy_type: PolicyType,
+    name: &str,
+    image: &str,
+    verification: &Verification,
+    key_path: &str,
+) -> Result<String> {
+    let images = [format!("{image}:*"), format!("{image}@*")];
+
+    Ok(match policy_type {
+        PolicyType::SigstorePolicyController => {
+            let authority = match verification {
+                Verification::Key(key) => json!({ "key": { "data": key } }),
+                Verification::Keyless { issuer, identity } => json!({
+                    "keyless": {
+                        "url": FULCIO_URL,
+                        "identities": [{ "issuer": issuer, "subjectRegExp": identity }],
+                    },
+                    "ctlog": { "url": REKOR_URL },
+                }),
+            };
+
+            serde_yaml::to_string(&json!({
+                "apiVersion": "policy.sigstore.dev/v1beta1",
+                "kind": "ClusterImagePolicy",
+                "metadata": { "name": format!("{name}-signature") },
+                "spec": {
+                    "images": images.iter().map(|glob| json!({ "glob": glob })).collect::<Value>(),
+                    "authorities": [authority],
+                },
+            }))
+            .into_diagnostic()?
+        }
+        PolicyType::Kyverno => {
+            let entry = match verification {
+                Verification::Key(key) => json!({ "keys": { "publicKeys": key } }),
+                Verification::Keyless { issuer, identity } => json!({
+                    "keyless": {
+                        "issuer": issuer,
+                        "subjectRegExp": identity,
+                        "rekor": { "url": REKOR_URL },
+                    },
+                }),
+            };
+
+            serde_yaml::to_string(&json!({
+                "apiVersion": "kyverno.io/v1",
+                "kind": "ClusterPolicy",
+                "metadata": { "name": format!("{name}-signature") },
+                "spec": {
+                    "validationFailureAction": "Enforce",
+                    "webhookTimeoutSeconds": 30,
+                    "rules": [{
+                        "name": "verify-signature",
+                        "match": { "any": [{ "resources": { "kinds": ["Pod"] } }] },
+                        "verifyImages": [{
+                            "imageReferences": images,
+                            "attestors": [{ "entries": [entry] }],
+                        }],
+                    }],
+                },
+            }))
+            .into_diagnostic()?
+        }
+        PolicyType::ContainersPolicyJson => {
+            let Verification::Key(_) = verification else {
+                bail!(
+                    help = format!("Sign the images with a key in {COSIGN_PUB_PATH} instead"),
+                    "policy.json can only verify keyless signatures by email, not by workflow identity"
+                );
+            };
+
+            let mut policy = serde_json::to_string_pretty(&json!({
+                "default": [{ "type": "insecureAcceptAnything" }],
+                "transports": {
+                    "docker": {
+                        image: [{
+                            "type": "sigstoreSigned",
+                            "keyPath": key_path,
+                            "signedIdentity": { "type": "matchRepository" },
+                        }],
+                    },
+                },
+            }))
+            .into_diagnostic()?;
+            policy.push('\n');
+            policy
+        }
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+    use serde_json::Value;
+
+    use super::{generate_policy, PolicyType, Verification};
+
+    const IMAGE: &str = "ghcr.io/octocat/my-image";
+    const KEY_PATH: &str = "/etc/pki/containers/my-image.pub";
+
+    fn key() -> Verification {
+        Verification::Key("-----BEGIN PUBLIC KEY-----\nabc\n-----END PUBLIC KEY-----\n".into())
+    }
+
+    fn keyless() -> Verification {
+        Verification::Keyless {
+            issuer: "https://token.actions.githubusercontent.com".into(),
+            identity: "^https://github.com/octocat/my-image/".into(),
+        }
+    }
+
+    fn parse(policy_type: PolicyType, verification: &Verification) -> Value {
+        let policy =
+            generate_policy(policy_type, "my-image", IMAGE, verification, KEY_PATH).unwrap();
+        serde_yaml::from_str(&policy).unwrap()
+    }
+
+    #[rstest]
+    #[case::key(key(), "key")]
+    #[case::keyless(keyless(), "keyless")]
+    fn sigstore_policy_controller(#[case] verification: Verification, #[case] authority: &str) {
+        let policy = parse(PolicyType::SigstorePolicyController, &verification);
+
+        assert_eq!(policy["kind"], "ClusterImagePolicy");
+        assert_eq!(policy["spec"]["images"][0]["glob"], format!("{IMAGE}:*"));
+        assert_eq!(policy["spec"]["images"][1]["glob"], format!("{IMAGE}@*"));
+        assert!(policy["spec"]["authorities"][0].get(authority).is_some());
+    }
+
+    #[rstest]
+    #[case::key(key(), "keys")]
+    #[case::keyless(keyless(), "keyless")]
+    fn kyverno(#[case] verification: Verification, #[case] entry: &str) {
+        let policy = parse(PolicyType::Kyverno, &verification);
+        let verify_images = &policy["spec"]["rules"][0]["verifyImages"][0];
+
+        assert_eq!(policy["kind"], "ClusterPolicy");
+        assert_eq!(verify_images["imageReferences"][0], format!("{IMAGE}:*"));
+        assert!(verify_images["attestors"][0]["entries"][0]
+            .get(entry)
+            .is_some());
+    }
+
+    #[test]
+    fn containers_policy_json() {
+        let policy = parse(PolicyType::ContainersPolicyJson, &key());
+
+        assert_eq!(
+            policy["transports"]["docker"][IMAGE][0]["keyPath"],
+            KEY_PATH
+        );
+        assert!(generate_policy(
+            PolicyType::ContainersPolicyJson,
+            "my-image",
+            IMAGE,
+            &keyless(),
+            KEY_PATH
+        )
+        .is_err());
+    }
+}
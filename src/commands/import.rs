@@ -0,0 +1,72 @@
+use std::path::PathBuf;
+
+use bon::Builder;
+use clap::{Args, Subcommand};
+use log::trace;
+use miette::{miette, IntoDiagnostic, Result};
+
+use crate::ublue;
+
+use super::BlueBuildCommand;
+
+#[derive(Debug, Clone, Args)]
+pub struct ImportCommand {
+    #[command(subcommand)]
+    command: ImportSubcommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ImportSubcommand {
+    /// Import a repo made from the ublue-os image template.
+    ///
+    /// The packages that its `build.sh` installs and the
+    /// units that it enables are converted to modules, and
+    /// the rest of the script is kept as a script module.
+    Ublue(ImportUblueCommand),
+}
+
+impl BlueBuildCommand for ImportCommand {
+    fn try_run(&mut self) -> Result<()> {
+        match &mut self.command {
+            ImportSubcommand::Ublue(command) => command.try_run(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct ImportUblueCommand {
+    /// The path to the repo to import.
+    #[builder(into)]
+    path: PathBuf,
+
+    /// The directory to write the bluebuild project to.
+    #[arg(short, long, default_value = ".")]
+    #[builder(into, default = PathBuf::from("."))]
+    output: PathBuf,
+
+    /// The name of the image.
+    ///
+    /// Defaults to the name of the repo's directory.
+    #[arg(long)]
+    #[builder(into)]
+    name: Option<String>,
+}
+
+impl BlueBuildCommand for ImportUblueCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("ImportUblueCommand::try_run()");
+
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .path
+                .canonicalize()
+                .into_diagnostic()?
+                .file_name()
+                .map(|name| name.to_string_lossy().to_lowercase())
+                .ok_or_else(|| miette!("Unable to get the name of the repo, set it with --name"))?,
+        };
+
+        ublue::import(&self.path, &self.output, &name)
+    }
+}
@@ -3,26 +3,38 @@ use std::{
     fmt::{Display, Write as FmtWrite},
     fs::{self, OpenOptions},
     io::{BufWriter, Write as IoWrite},
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
 };
 
 use blue_build_process_management::drivers::{
-    opts::GenerateKeyPairOpts, CiDriver, Driver, DriverArgs, GitlabDriver, SigningDriver,
+    opts::GenerateKeyPairOpts, CiDriver, Driver, DriverArgs, GithubDriver, GitlabDriver,
+    SigningDriver,
+};
+use blue_build_template::{
+    GithubChangelogWorkflowTemplate, GithubIsoWorkflowTemplate, GithubVerifyWorkflowTemplate,
+    GithubWorkflowTemplate, GitlabCiTemplate, InitDependabotTemplate, InitModulesCommonTemplate,
+    InitReadmeTemplate, InitRecipeTemplate, InitRenovateTemplate, KeylessSigning, Template,
+    INIT_PROJECT_TEMPLATE,
 };
-use blue_build_template::{GitlabCiTemplate, InitReadmeTemplate, Template};
 use blue_build_utils::{
     cmd,
-    constants::{COSIGN_PUB_PATH, RECIPE_FILE, RECIPE_PATH, TEMPLATE_REPO_URL},
+    constants::{
+        COSIGN_PUB_PATH, GITHUB_TOKEN_ISSUER_URL, RECIPE_FILE, RECIPE_PATH, TEMPLATE_REPO_URL,
+    },
+    copy_dir,
+    traits::CowCollecter,
 };
 use bon::Builder;
 use clap::{crate_version, Args, ValueEnum};
-use log::{debug, info, trace};
+use log::{debug, info, trace, warn};
 use miette::{bail, miette, Context, IntoDiagnostic, Report, Result};
 use requestty::{questions, Answer, Answers, OnEsc};
 use semver::Version;
 
-use crate::commands::BlueBuildCommand;
+use crate::{commands::BlueBuildCommand, prompt};
+
+mod update;
 
 #[derive(Debug, Default, Clone, Copy, ValueEnum)]
 pub enum CiProvider {
@@ -36,25 +48,106 @@ impl CiProvider {
     fn default_ci_file_path(self) -> std::path::PathBuf {
         match self {
             Self::Gitlab => GitlabDriver::default_ci_file_path(),
-            Self::None | Self::Github => unimplemented!(),
+            Self::Github => GithubDriver::default_ci_file_path(),
+            Self::None => unimplemented!(),
         }
     }
 
-    fn render_file(self) -> Result<String> {
+    fn render_file(self, recipes: &[String], keyless: bool) -> Result<String> {
         match self {
             Self::Gitlab => GitlabCiTemplate::builder()
-                .version({
-                    let version = crate_version!();
-                    let version: Version = version.parse().into_diagnostic()?;
-
-                    format!("v{}.{}", version.major, version.minor)
-                })
+                .version(Self::version()?)
+                .recipes(recipes.collect_cow_vec())
+                .keyless(keyless)
                 .build()
                 .render()
                 .into_diagnostic(),
-            Self::None | Self::Github => unimplemented!(),
+            Self::Github => GithubWorkflowTemplate::builder()
+                .version(Self::version()?)
+                .recipes(recipes.collect_cow_vec())
+                .keyless(keyless)
+                .build()
+                .render()
+                .into_diagnostic(),
+            Self::None => unimplemented!(),
         }
     }
+
+    /// The version of the CLI to pin CI files to
+    /// in the format of `v{major}.{minor}`.
+    fn version() -> Result<String> {
+        let version: Version = crate_version!().parse().into_diagnostic()?;
+
+        Ok(format!("v{}.{}", version.major, version.minor))
+    }
+
+    /// The identity and issuer that keyless
+    /// signatures from this CI provider have.
+    fn keyless_signing(self, org_name: &str) -> Option<KeylessSigning<'static>> {
+        let (identity, issuer) = match self {
+            Self::Github => (
+                format!("^https://github.com/{org_name}/"),
+                GITHUB_TOKEN_ISSUER_URL.to_string(),
+            ),
+            Self::Gitlab => (
+                format!("^https://gitlab.com/{org_name}/"),
+                String::from("https://gitlab.com"),
+            ),
+            Self::None => return None,
+        };
+
+        Some(
+            KeylessSigning::builder()
+                .identity(identity)
+                .issuer(issuer)
+                .build(),
+        )
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum SigningMethod {
+    /// Sign with a cosign key pair that
+    /// is generated for the project.
+    #[default]
+    Keypair,
+
+    /// Sign with the identity of the CI
+    /// pipeline that builds the image.
+    Keyless,
+}
+
+impl TryFrom<&str> for SigningMethod {
+    type Error = Report;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        Ok(match value {
+            "Key pair" => Self::Keypair,
+            "Keyless" => Self::Keyless,
+            _ => bail!("Unable to parse for SigningMethod"),
+        })
+    }
+}
+
+impl TryFrom<&String> for SigningMethod {
+    type Error = Report;
+
+    fn try_from(value: &String) -> std::result::Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl Display for SigningMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Keypair => "Key pair",
+                Self::Keyless => "Keyless",
+            }
+        )
+    }
 }
 
 impl TryFrom<&str> for CiProvider {
@@ -100,8 +193,70 @@ impl Display for CiProvider {
     }
 }
 
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum UpdateBot {
+    Renovate,
+    Dependabot,
+    #[default]
+    None,
+}
+
+impl UpdateBot {
+    fn config_file_path(self) -> PathBuf {
+        match self {
+            Self::Renovate => PathBuf::from("renovate.json"),
+            Self::Dependabot => PathBuf::from(".github/dependabot.yml"),
+            Self::None => unimplemented!(),
+        }
+    }
+
+    fn render_file(self) -> Result<String> {
+        match self {
+            Self::Renovate => InitRenovateTemplate.render().into_diagnostic(),
+            Self::Dependabot => InitDependabotTemplate.render().into_diagnostic(),
+            Self::None => unimplemented!(),
+        }
+    }
+}
+
+impl TryFrom<&str> for UpdateBot {
+    type Error = Report;
+
+    fn try_from(value: &str) -> std::result::Result<Self, Self::Error> {
+        Ok(match value {
+            "Renovate" => Self::Renovate,
+            "Dependabot" => Self::Dependabot,
+            "None" => Self::None,
+            _ => bail!("Unable to parse for UpdateBot"),
+        })
+    }
+}
+
+impl TryFrom<&String> for UpdateBot {
+    type Error = Report;
+
+    fn try_from(value: &String) -> std::result::Result<Self, Self::Error> {
+        Self::try_from(value.as_str())
+    }
+}
+
+impl Display for UpdateBot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Renovate => "Renovate",
+                Self::Dependabot => "Dependabot",
+                Self::None => "None",
+            }
+        )
+    }
+}
+
 #[derive(Debug, Clone, Default, Args, Builder)]
 #[builder(on(String, into))]
+#[allow(clippy::struct_excessive_bools)]
 pub struct NewInitCommon {
     /// The name of the image for the recipe.
     #[arg(long)]
@@ -120,6 +275,26 @@ pub struct NewInitCommon {
     #[arg(long)]
     registry: Option<String>,
 
+    /// The base image to build your image on top of.
+    ///
+    /// Setting this will generate a new recipe
+    /// instead of using the template's recipe.
+    #[arg(long)]
+    base_image: Option<String>,
+
+    /// The version of the base image to use.
+    #[arg(long)]
+    image_version: Option<String>,
+
+    /// Scaffold a repository that builds multiple images.
+    ///
+    /// This creates a recipe for each desktop variant
+    /// that pulls its modules from a shared
+    /// `modules-common.yml` file, and configures CI
+    /// to build every recipe.
+    #[arg(long, conflicts_with = "base_image")]
+    multi: bool,
+
     /// The CI provider that will be building the image.
     ///
     /// GitHub Actions and Gitlab CI are currently the
@@ -127,10 +302,70 @@ pub struct NewInitCommon {
     #[arg(long, short)]
     ci_provider: Option<CiProvider>,
 
+    /// The bot that will open pull requests to keep
+    /// base images, modules, and the CLI up to date.
+    ///
+    /// Dependabot is only able to update the
+    /// GitHub Action.
+    #[arg(long)]
+    update_bot: Option<UpdateBot>,
+
+    /// How the image will be signed in CI.
+    ///
+    /// Keyless signing uses the identity of the
+    /// CI pipeline instead of generating a cosign
+    /// key pair.
+    #[arg(long)]
+    signing: Option<SigningMethod>,
+
+    /// Add a workflow that builds an ISO of each
+    /// image at the start of every month.
+    ///
+    /// Only supported on GitHub.
+    #[arg(long)]
+    with_iso: bool,
+
+    /// Add a workflow that publishes a changelog
+    /// to GitHub Releases every week.
+    ///
+    /// Only supported on GitHub.
+    #[arg(long)]
+    with_changelog: bool,
+
+    /// Add a workflow that verifies the signature
+    /// of each image along with a badge for it
+    /// in the README.
+    ///
+    /// Only supported on GitHub.
+    #[arg(long)]
+    with_verify: bool,
+
     /// Disable setting up git.
     #[arg(long)]
     no_git: bool,
 
+    /// Clone the template repository instead of using
+    /// the template that is embedded in the CLI.
+    ///
+    /// This fetches the freshest template, but
+    /// requires git and network access.
+    #[arg(long)]
+    clone_template: bool,
+
+    /// The repository to use as the starting point
+    /// for the new project.
+    ///
+    /// This can be a git URL or a path to a local
+    /// directory. Defaults to the official BlueBuild
+    /// template repository. Implies `--clone-template`.
+    #[arg(long)]
+    template_repo: Option<String>,
+
+    /// The branch, tag, or commit of the template
+    /// repository to use. Implies `--clone-template`.
+    #[arg(long)]
+    template_ref: Option<String>,
+
     #[clap(flatten)]
     #[builder(default)]
     drivers: DriverArgs,
@@ -161,23 +396,42 @@ pub struct InitCommand {
     #[builder(into)]
     dir: Option<PathBuf>,
 
+    /// Regenerate the CI files of an existing project
+    /// from the current templates.
+    ///
+    /// Recipes and other files are left untouched. A diff
+    /// of the changes is shown before they are written.
+    #[arg(long)]
+    #[builder(default)]
+    update: bool,
+
     #[clap(flatten)]
     common: NewInitCommon,
 }
 
 impl BlueBuildCommand for InitCommand {
     fn try_run(&mut self) -> Result<()> {
-        Driver::init(self.common.drivers);
+        Driver::init(self.common.drivers.clone());
 
         let base_dir = self
             .dir
             .get_or_insert(env::current_dir().into_diagnostic()?);
 
+        if self.update {
+            return self.update_ci_files();
+        }
+
         if base_dir.exists() && fs::read_dir(base_dir).is_ok_and(|dir| dir.count() != 0) {
             bail!("Must be in an empty directory!");
         }
 
-        self.start(&self.questions()?)
+        let answers = if prompt::is_interactive() {
+            self.questions()?
+        } else {
+            self.default_answers()?
+        };
+
+        self.start(&answers)
     }
 }
 
@@ -187,13 +441,92 @@ macro_rules! when {
     };
 }
 
+/// Common base images offered when customizing the recipe.
+const BASE_IMAGES: &[(&str, &str)] = &[
+    ("Silverblue (GNOME)", "ghcr.io/ublue-os/silverblue-main"),
+    ("Kinoite (KDE Plasma)", "ghcr.io/ublue-os/kinoite-main"),
+    ("Bluefin", "ghcr.io/ublue-os/bluefin"),
+    ("Aurora", "ghcr.io/ublue-os/aurora"),
+    ("Bazzite", "ghcr.io/ublue-os/bazzite"),
+    ("Base (no desktop)", "ghcr.io/ublue-os/base-main"),
+];
+const OTHER_BASE_IMAGE: &str = "Other";
+
+/// The recipe variants created with `--multi`.
+const MULTI_VARIANTS: &[(&str, &str)] = &[
+    ("gnome", "ghcr.io/ublue-os/silverblue-main"),
+    ("kde", "ghcr.io/ublue-os/kinoite-main"),
+];
+const MODULES_COMMON_FILE: &str = "modules-common.yml";
+
+const FLATPAKS_MODULE: &str = "default-flatpaks";
+const BREW_MODULE: &str = "brew";
+const SIGNING_MODULE: &str = "signing";
+
+const ISO_WORKFLOW: &str = "Scheduled ISO builds";
+const CHANGELOG_WORKFLOW: &str = "Changelog releases";
+const VERIFY_WORKFLOW: &str = "Signature verification badge";
+
 impl InitCommand {
     const CI_PROVIDER: &str = "ci_provider";
+    const UPDATE_BOT: &str = "update_bot";
+    const SIGNING: &str = "signing";
     const REGISTRY: &str = "registry";
     const IMAGE_NAME: &str = "image_name";
     const ORG_NAME: &str = "org_name";
     const DESCRIPTION: &str = "description";
+    const CUSTOMIZE_RECIPE: &str = "customize_recipe";
+    const BASE_IMAGE: &str = "base_image";
+    const CUSTOM_BASE_IMAGE: &str = "custom_base_image";
+    const IMAGE_VERSION: &str = "image_version";
+    const MODULES: &str = "modules";
+    const EXTRA_WORKFLOWS: &str = "extra_workflows";
+
+    /// The answers used in place of the prompts when
+    /// running non-interactively.
+    ///
+    /// Every value without a default must be passed with a flag.
+    fn default_answers(&self) -> Result<Answers> {
+        let common = &self.common;
+        let missing = [
+            ("--image-name", common.image_name.is_none()),
+            ("--registry", common.registry.is_none()),
+            ("--org-name", common.org_name.is_none()),
+            ("--description", common.description.is_none()),
+            (
+                "--ci-provider",
+                !common.no_git && common.ci_provider.is_none(),
+            ),
+            (
+                "--update-bot",
+                !common.no_git && common.update_bot.is_none(),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(flag, missing)| missing.then_some(flag))
+        .collect::<Vec<_>>();
+
+        if !missing.is_empty() {
+            bail!(
+                help = format!("Pass {}", missing.join(", ")),
+                "Can't prompt for the project details in non-interactive mode"
+            );
+        }
+
+        let mut answers = Answers::default();
+        if self.customize_recipe(&answers) {
+            answers.insert(
+                Self::MODULES.into(),
+                Answer::ListItems(vec![
+                    (0, FLATPAKS_MODULE).into(),
+                    (2, SIGNING_MODULE).into(),
+                ]),
+            );
+        }
+        Ok(answers)
+    }
 
+    #[allow(clippy::too_many_lines)]
     fn questions(&self) -> Result<Answers> {
         let questions = questions![
             Input {
@@ -227,6 +560,91 @@ impl InitCommand {
                 when: when!(!self.common.no_git && self.common.ci_provider.is_none()),
                 on_esc: OnEsc::Terminate,
                 choices: vec!["Github", "Gitlab", "None"],
+            },
+            Select {
+                name: Self::UPDATE_BOT,
+                message: "Which bot would you like to use to keep your image up to date?",
+                when: when!(!self.common.no_git && self.common.update_bot.is_none()),
+                on_esc: OnEsc::Terminate,
+                choices: vec!["Renovate", "Dependabot", "None"],
+            },
+            Select {
+                name: Self::SIGNING,
+                message: "How would you like to sign your image?",
+                when: |answers: &Answers| {
+                    self.common.signing.is_none()
+                        && !matches!(self.selected_ci_provider(answers), Ok(CiProvider::None))
+                },
+                on_esc: OnEsc::Terminate,
+                choices: vec![
+                    SigningMethod::Keypair.to_string(),
+                    SigningMethod::Keyless.to_string(),
+                ],
+            },
+            MultiSelect {
+                name: Self::EXTRA_WORKFLOWS,
+                message: "Which extra workflows would you like to add?",
+                when: |answers: &Answers| {
+                    !self.common.no_git
+                        && !self.common.with_iso
+                        && !self.common.with_changelog
+                        && !self.common.with_verify
+                        && matches!(self.selected_ci_provider(answers), Ok(CiProvider::Github))
+                },
+                on_esc: OnEsc::Terminate,
+                choices: [ISO_WORKFLOW, CHANGELOG_WORKFLOW, VERIFY_WORKFLOW],
+            },
+            Confirm {
+                name: Self::CUSTOMIZE_RECIPE,
+                message: "Would you like to customize your recipe?",
+                when: when!(self.common.base_image.is_none() && !self.common.multi),
+                on_esc: OnEsc::Terminate,
+                default: false,
+            },
+            Select {
+                name: Self::BASE_IMAGE,
+                message: "Which base image would you like to build on?",
+                when: |answers: &Answers| {
+                    self.common.base_image.is_none()
+                        && !self.common.multi
+                        && self.customize_recipe(answers)
+                },
+                on_esc: OnEsc::Terminate,
+                choices: BASE_IMAGES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .chain([OTHER_BASE_IMAGE]),
+            },
+            Input {
+                name: Self::CUSTOM_BASE_IMAGE,
+                message: "What is the base image? (e.g. quay.io/fedora-ostree-desktops/sway-atomic)",
+                when: |answers: &Answers| {
+                    answers
+                        .get(Self::BASE_IMAGE)
+                        .and_then(Answer::as_list_item)
+                        .is_some_and(|li| li.text == OTHER_BASE_IMAGE)
+                },
+                on_esc: OnEsc::Terminate,
+            },
+            Input {
+                name: Self::IMAGE_VERSION,
+                message: "What version of the base image would you like to use?",
+                when: |answers: &Answers| {
+                    self.common.image_version.is_none() && self.customize_recipe(answers)
+                },
+                on_esc: OnEsc::Terminate,
+                default: "latest",
+            },
+            MultiSelect {
+                name: Self::MODULES,
+                message: "Which common modules would you like to include?",
+                when: |answers: &Answers| self.customize_recipe(answers),
+                on_esc: OnEsc::Terminate,
+                choices: [
+                    FLATPAKS_MODULE default true,
+                    BREW_MODULE,
+                    SIGNING_MODULE default true,
+                ],
             }
         ];
 
@@ -234,12 +652,26 @@ impl InitCommand {
     }
 
     fn start(&self, answers: &Answers) -> Result<()> {
-        self.clone_repository()?;
+        if self.keyless(answers) && matches!(self.selected_ci_provider(answers)?, CiProvider::None)
+        {
+            bail!("Keyless signing requires a CI provider");
+        }
+
+        if self.common.clone_template
+            || self.common.template_repo.is_some()
+            || self.common.template_ref.is_some()
+        {
+            self.clone_repository()?;
+        } else {
+            self.extract_template()?;
+        }
         self.remove_git_directory()?;
         self.template_readme(answers)?;
         self.template_ci_file(answers)?;
+        self.template_extra_workflows(answers)?;
+        self.template_update_bot_file(answers)?;
         self.update_recipe_file(answers)?;
-        self.generate_signing_files()?;
+        self.generate_signing_files(answers)?;
 
         if !self.common.no_git {
             self.initialize_git()?;
@@ -255,11 +687,46 @@ impl InitCommand {
         Ok(())
     }
 
+    fn extract_template(&self) -> Result<()> {
+        let dir = self.dir.as_ref().unwrap();
+        trace!("extract_template()");
+
+        debug!("Extracting embedded template to {}", dir.display());
+        fs::create_dir_all(dir)
+            .and_then(|()| INIT_PROJECT_TEMPLATE.extract(dir))
+            .into_diagnostic()
+            .with_context(|| format!("Failed to extract template to {}", dir.display()))
+    }
+
     fn clone_repository(&self) -> Result<()> {
         let dir = self.dir.as_ref().unwrap();
         trace!("clone_repository()");
 
-        let mut command = cmd!("git", "clone", "-q", TEMPLATE_REPO_URL, dir);
+        let template_repo = self
+            .common
+            .template_repo
+            .as_deref()
+            .unwrap_or(TEMPLATE_REPO_URL);
+        let template_path = Path::new(template_repo);
+
+        // Plain directories can't be cloned so we copy them instead
+        if template_path.is_dir() && !template_path.join(".git").exists() {
+            if self.common.template_ref.is_some() {
+                bail!(
+                    "Cannot use '--template-ref' with a template directory that isn't a git repo"
+                );
+            }
+
+            debug!("Copying template from {}", template_path.display());
+            return copy_dir(template_path, dir).with_context(|| {
+                format!(
+                    "Failed to copy template directory {}",
+                    template_path.display()
+                )
+            });
+        }
+
+        let mut command = cmd!("git", "clone", "-q", "--", template_repo, dir);
         trace!("{command:?}");
 
         let status = command
@@ -268,7 +735,34 @@ impl InitCommand {
             .context("Failed to execute git clone")?;
 
         if !status.success() {
-            bail!("Failed to clone template repo");
+            bail!("Failed to clone template repo {template_repo}");
+        }
+
+        if let Some(template_ref) = self.common.template_ref.as_deref() {
+            // The ref can't come after `--` since that would make it a
+            // path, so refs that would be read as an option are rejected
+            if template_ref.starts_with('-') {
+                bail!("The template ref {template_ref} can't start with '-'");
+            }
+
+            let mut command = cmd!(
+                "git",
+                "checkout",
+                "-q",
+                template_ref,
+                "--",
+                current_dir = dir
+            );
+            trace!("{command:?}");
+
+            let status = command
+                .status()
+                .into_diagnostic()
+                .context("Failed to execute git checkout")?;
+
+            if !status.success() {
+                bail!("Failed to checkout {template_ref} of template repo {template_repo}");
+            }
         }
 
         Ok(())
@@ -367,14 +861,37 @@ impl InitCommand {
 
         let readme_path = self.dir.as_ref().unwrap().join("README.md");
 
+        let org_name = self
+            .common
+            .org_name
+            .as_deref()
+            .or_else(|| answers.get(Self::ORG_NAME).and_then(Answer::as_string))
+            .ok_or_else(|| miette!("Failed to get organization name"))?;
+
+        // The badge needs the name of the GitHub repo,
+        // which is assumed to be the name of the project directory
+        let verify_badge_repo =
+            if Self::wants_workflow(answers, self.common.with_verify, VERIFY_WORKFLOW)
+                && matches!(self.selected_ci_provider(answers)?, CiProvider::Github)
+            {
+                self.dir
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .map(|repo| format!("{org_name}/{}", repo.to_string_lossy()))
+            } else {
+                None
+            };
+
+        let keyless = if self.keyless(answers) {
+            self.selected_ci_provider(answers)?
+                .keyless_signing(org_name)
+        } else {
+            None
+        };
+
         let readme = InitReadmeTemplate::builder()
-            .repo_name(
-                self.common
-                    .org_name
-                    .as_deref()
-                    .or_else(|| answers.get(Self::ORG_NAME).and_then(Answer::as_string))
-                    .ok_or_else(|| miette!("Failed to get organization name"))?,
-            )
+            .repo_name(org_name)
             .image_name(
                 self.common
                     .image_name
@@ -389,6 +906,8 @@ impl InitCommand {
                     .or_else(|| answers.get(Self::REGISTRY).and_then(Answer::as_string))
                     .ok_or_else(|| miette!("Failed to get registry"))?,
             )
+            .maybe_verify_badge_repo(verify_badge_repo)
+            .maybe_keyless(keyless)
             .build();
 
         debug!("Templating README");
@@ -414,13 +933,17 @@ impl InitCommand {
                     .and_then(CiProvider::try_from)
             })?;
 
+        let github_path = self.dir.as_ref().unwrap().join(".github");
+
         if matches!(ci_provider, CiProvider::Github) {
-            fs::remove_file(self.dir.as_ref().unwrap().join(".github/CODEOWNERS"))
-                .into_diagnostic()?;
-            return Ok(());
-        }
+            let codeowners_path = github_path.join("CODEOWNERS");
 
-        fs::remove_dir_all(self.dir.as_ref().unwrap().join(".github")).into_diagnostic()?;
+            if codeowners_path.exists() {
+                fs::remove_file(codeowners_path).into_diagnostic()?;
+            }
+        } else if github_path.exists() {
+            fs::remove_dir_all(github_path).into_diagnostic()?;
+        }
 
         // Never run for None
         if matches!(ci_provider, CiProvider::None) {
@@ -432,12 +955,15 @@ impl InitCommand {
             .as_ref()
             .unwrap()
             .join(ci_provider.default_ci_file_path());
-        let parent_path = ci_file_path
-            .parent()
-            .ok_or_else(|| miette!("Couldn't get parent directory from {ci_file_path:?}"))?;
+        let parent_path = ci_file_path.parent().ok_or_else(|| {
+            miette!(
+                "Couldn't get parent directory from {}",
+                ci_file_path.display()
+            )
+        })?;
         fs::create_dir_all(parent_path)
             .into_diagnostic()
-            .with_context(|| format!("Couldn't create directory path {parent_path:?}"))?;
+            .with_context(|| format!("Couldn't create directory path {}", parent_path.display()))?;
 
         let file = &mut BufWriter::new(
             OpenOptions::new()
@@ -446,14 +972,200 @@ impl InitCommand {
                 .write(true)
                 .open(&ci_file_path)
                 .into_diagnostic()
-                .with_context(|| format!("Failed to open file at {ci_file_path:?}"))?,
+                .with_context(|| format!("Failed to open file at {}", ci_file_path.display()))?,
         );
 
-        let template = ci_provider.render_file()?;
+        let template =
+            ci_provider.render_file(&self.recipe_files(answers)?, self.keyless(answers))?;
 
         writeln!(file, "{template}")
             .into_diagnostic()
-            .with_context(|| format!("Failed to write CI file {ci_file_path:?}"))
+            .with_context(|| format!("Failed to write CI file {}", ci_file_path.display()))
+    }
+
+    /// Whether a new recipe should be generated
+    /// instead of updating the template's recipe.
+    fn customize_recipe(&self, answers: &Answers) -> bool {
+        self.common.multi
+            || self.common.base_image.is_some()
+            || answers
+                .get(Self::CUSTOMIZE_RECIPE)
+                .and_then(Answer::as_bool)
+                .unwrap_or_default()
+    }
+
+    /// The CI provider from the args or the answers,
+    /// defaulting to `None` if neither are set.
+    fn selected_ci_provider(&self, answers: &Answers) -> Result<CiProvider> {
+        self.common.ci_provider.map_or_else(
+            || {
+                answers
+                    .get(Self::CI_PROVIDER)
+                    .and_then(Answer::as_list_item)
+                    .map_or(Ok(CiProvider::None), |li| CiProvider::try_from(&li.text))
+            },
+            Ok,
+        )
+    }
+
+    /// Whether the image will be signed keylessly
+    /// instead of with a generated key pair.
+    fn keyless(&self, answers: &Answers) -> bool {
+        self.common.signing.map_or_else(
+            || {
+                answers
+                    .get(Self::SIGNING)
+                    .and_then(Answer::as_list_item)
+                    .and_then(|li| SigningMethod::try_from(&li.text).ok())
+                    .is_some_and(|signing| matches!(signing, SigningMethod::Keyless))
+            },
+            |signing| matches!(signing, SigningMethod::Keyless),
+        )
+    }
+
+    /// Whether an extra workflow was requested
+    /// with its flag or selected in the prompt.
+    fn wants_workflow(answers: &Answers, flag: bool, workflow: &str) -> bool {
+        flag || answers
+            .get(Self::EXTRA_WORKFLOWS)
+            .and_then(Answer::as_list_items)
+            .is_some_and(|items| items.iter().any(|li| li.text == workflow))
+    }
+
+    fn template_extra_workflows(&self, answers: &Answers) -> Result<()> {
+        trace!("template_extra_workflows()");
+
+        let iso = Self::wants_workflow(answers, self.common.with_iso, ISO_WORKFLOW);
+        let changelog =
+            Self::wants_workflow(answers, self.common.with_changelog, CHANGELOG_WORKFLOW);
+        let verify = Self::wants_workflow(answers, self.common.with_verify, VERIFY_WORKFLOW);
+
+        if !(iso || changelog || verify) {
+            return Ok(());
+        }
+
+        if !matches!(self.selected_ci_provider(answers)?, CiProvider::Github) {
+            warn!("Extra workflows are only supported on Github, skipping");
+            return Ok(());
+        }
+
+        let images = self.image_refs(answers)?;
+        let workflows_dir = self.dir.as_ref().unwrap().join(".github/workflows");
+        fs::create_dir_all(&workflows_dir)
+            .into_diagnostic()
+            .with_context(|| format!("Couldn't create directory {}", workflows_dir.display()))?;
+
+        let write_workflow = |file_name: &str, workflow: String| -> Result<()> {
+            let workflow_path = workflows_dir.join(file_name);
+
+            debug!("Writing workflow {}", workflow_path.display());
+            fs::write(&workflow_path, workflow)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to write {}", workflow_path.display()))
+        };
+
+        if iso {
+            write_workflow(
+                "build-iso.yml",
+                GithubIsoWorkflowTemplate::builder()
+                    .version(CiProvider::version()?)
+                    .images(images.collect_cow_vec())
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            )?;
+        }
+
+        if changelog {
+            write_workflow(
+                "changelog.yml",
+                GithubChangelogWorkflowTemplate::builder()
+                    .images(images.collect_cow_vec())
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            )?;
+        }
+
+        if verify {
+            write_workflow(
+                "verify.yml",
+                GithubVerifyWorkflowTemplate::builder()
+                    .images(images.collect_cow_vec())
+                    .keyless(self.keyless(answers))
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The full references of the images that CI will publish.
+    fn image_refs(&self, answers: &Answers) -> Result<Vec<String>> {
+        let registry = self
+            .common
+            .registry
+            .as_deref()
+            .or_else(|| answers.get(Self::REGISTRY).and_then(Answer::as_string))
+            .ok_or_else(|| miette!("Failed to get registry"))?;
+        let org_name = self
+            .common
+            .org_name
+            .as_deref()
+            .or_else(|| answers.get(Self::ORG_NAME).and_then(Answer::as_string))
+            .ok_or_else(|| miette!("Failed to get organization name"))?;
+        let name = self.image_name(answers)?;
+
+        Ok(if self.common.multi {
+            MULTI_VARIANTS
+                .iter()
+                .map(|(variant, _)| format!("{registry}/{org_name}/{name}-{variant}"))
+                .map(|image| image.to_lowercase())
+                .collect()
+        } else {
+            vec![format!("{registry}/{org_name}/{name}").to_lowercase()]
+        })
+    }
+
+    fn template_update_bot_file(&self, answers: &Answers) -> Result<()> {
+        trace!("template_update_bot_file()");
+
+        let update_bot = match self.common.update_bot {
+            Some(update_bot) => update_bot,
+            None => answers
+                .get(Self::UPDATE_BOT)
+                .and_then(Answer::as_list_item)
+                .map_or(Ok(UpdateBot::None), |li| UpdateBot::try_from(&li.text))?,
+        };
+
+        if matches!(update_bot, UpdateBot::None) {
+            return Ok(());
+        }
+
+        let ci_provider = self.selected_ci_provider(answers)?;
+
+        if matches!(update_bot, UpdateBot::Dependabot) && !matches!(ci_provider, CiProvider::Github)
+        {
+            warn!("Dependabot is only supported on Github, skipping {update_bot} config");
+            return Ok(());
+        }
+
+        let config_path = self
+            .dir
+            .as_ref()
+            .unwrap()
+            .join(update_bot.config_file_path());
+
+        if let Some(parent) = config_path.parent() {
+            fs::create_dir_all(parent).into_diagnostic()?;
+        }
+
+        debug!("Writing {update_bot} config to {}", config_path.display());
+        fs::write(&config_path, update_bot.render_file()?)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to write {}", config_path.display()))
     }
 
     fn update_recipe_file(&self, answers: &Answers) -> Result<()> {
@@ -466,11 +1178,6 @@ impl InitCommand {
             .join(RECIPE_PATH)
             .join(RECIPE_FILE);
 
-        debug!("Reading {recipe_path:?}");
-        let file = fs::read_to_string(&recipe_path)
-            .into_diagnostic()
-            .with_context(|| format!("Failed to read {recipe_path:?}"))?;
-
         let description = self
             .common
             .description
@@ -482,21 +1189,38 @@ impl InitCommand {
                     .and_then(Answer::as_string)
                     .ok_or_else(|| miette!("Failed to get description:\n{e}"))
             })?;
-        let name = self
-            .common
-            .image_name
-            .as_deref()
-            .ok_or("Description arg not set")
-            .or_else(|e| {
-                answers
-                    .get(Self::IMAGE_NAME)
-                    .and_then(Answer::as_string)
-                    .ok_or_else(|| miette!("Failed to get description:\n{e}"))
-            })?;
+        let name = self.image_name(answers)?;
+
+        if self.common.multi {
+            return self.write_multi_recipe_files(name, description, answers);
+        }
+
+        if self.customize_recipe(answers) {
+            return self.write_recipe_file(&recipe_path, name, description, answers);
+        }
+
+        if !recipe_path.exists() {
+            warn!(
+                "Template doesn't contain {}, skipping recipe update",
+                recipe_path.display()
+            );
+            return Ok(());
+        }
+
+        debug!("Reading {}", recipe_path.display());
+        let file = fs::read_to_string(&recipe_path)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to read {}", recipe_path.display()))?;
 
         let mut new_file_str = String::with_capacity(file.capacity());
 
-        for line in file.lines() {
+        let keyless = self.keyless(answers);
+
+        // The signing module requires the project's public key
+        for line in file
+            .lines()
+            .filter(|line| !(keyless && line.trim_start().starts_with("- type: signing")))
+        {
             if line.starts_with("description:") {
                 writeln!(&mut new_file_str, "description: {description}").into_diagnostic()?;
             } else if line.starts_with("name: ") {
@@ -512,20 +1236,193 @@ impl InitCommand {
                 .write(true)
                 .open(&recipe_path)
                 .into_diagnostic()
-                .with_context(|| format!("Failed to open {recipe_path:?}"))?,
+                .with_context(|| format!("Failed to open {}", recipe_path.display()))?,
         );
         write!(file, "{new_file_str}")
             .into_diagnostic()
-            .with_context(|| format!("Failed to write to file {recipe_path:?}"))
+            .with_context(|| format!("Failed to write to file {}", recipe_path.display()))
     }
 
-    fn generate_signing_files(&self) -> Result<()> {
-        trace!("generate_signing_files()");
+    fn image_name<'a>(&'a self, answers: &'a Answers) -> Result<&'a str> {
+        self.common
+            .image_name
+            .as_deref()
+            .ok_or("Image name arg not set")
+            .or_else(|e| {
+                answers
+                    .get(Self::IMAGE_NAME)
+                    .and_then(Answer::as_string)
+                    .ok_or_else(|| miette!("Failed to get image name:\n{e}"))
+            })
+    }
+
+    /// The recipe files that CI should build.
+    fn recipe_files(&self, answers: &Answers) -> Result<Vec<String>> {
+        Ok(if self.common.multi {
+            let name = self.image_name(answers)?;
+            MULTI_VARIANTS
+                .iter()
+                .map(|(variant, _)| format!("{name}-{variant}.yml"))
+                .collect()
+        } else {
+            vec![RECIPE_FILE.into()]
+        })
+    }
+
+    fn write_multi_recipe_files(
+        &self,
+        name: &str,
+        description: &str,
+        answers: &Answers,
+    ) -> Result<()> {
+        trace!("write_multi_recipe_files()");
+
+        let recipe_dir = self.dir.as_ref().unwrap().join(RECIPE_PATH);
+        fs::create_dir_all(&recipe_dir).into_diagnostic()?;
+
+        let template_recipe = recipe_dir.join(RECIPE_FILE);
+        if template_recipe.exists() {
+            debug!("Removing template recipe {}", template_recipe.display());
+            fs::remove_file(&template_recipe).into_diagnostic()?;
+        }
+
+        let image_version = self.selected_image_version(answers);
+        let modules = self.selected_modules(answers);
+        let has_module = |module: &str| modules.contains(&module);
+
+        let modules_common = InitModulesCommonTemplate::builder()
+            .flatpaks(has_module(FLATPAKS_MODULE))
+            .brew(has_module(BREW_MODULE))
+            .signing(has_module(SIGNING_MODULE))
+            .build()
+            .render()
+            .into_diagnostic()?;
+        let modules_common_path = recipe_dir.join(MODULES_COMMON_FILE);
+
+        debug!("Writing {}", modules_common_path.display());
+        fs::write(&modules_common_path, modules_common)
+            .into_diagnostic()
+            .with_context(|| {
+                format!("Failed to write to file {}", modules_common_path.display())
+            })?;
+
+        for ((variant, base_image), file_name) in
+            MULTI_VARIANTS.iter().zip(self.recipe_files(answers)?)
+        {
+            let recipe = InitRecipeTemplate::builder()
+                .name(format!("{name}-{variant}"))
+                .description(description)
+                .base_image(*base_image)
+                .image_version(image_version)
+                .common_modules(MODULES_COMMON_FILE)
+                .build()
+                .render()
+                .into_diagnostic()?;
+            let recipe_path = recipe_dir.join(file_name);
+
+            debug!("Writing generated recipe to {}", recipe_path.display());
+            fs::write(&recipe_path, recipe)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to write to file {}", recipe_path.display()))?;
+        }
+
+        Ok(())
+    }
+
+    fn selected_image_version<'a>(&'a self, answers: &'a Answers) -> &'a str {
+        self.common
+            .image_version
+            .as_deref()
+            .or_else(|| answers.get(Self::IMAGE_VERSION).and_then(Answer::as_string))
+            .unwrap_or("latest")
+    }
+
+    fn selected_modules<'a>(&self, answers: &'a Answers) -> Vec<&'a str> {
+        // The signing module requires the project's public key
+        let keyless = self.keyless(answers);
+
+        answers
+            .get(Self::MODULES)
+            .and_then(Answer::as_list_items)
+            .unwrap_or_default()
+            .iter()
+            .map(|li| li.text.as_str())
+            .filter(|module| !(keyless && *module == SIGNING_MODULE))
+            .collect()
+    }
+
+    fn write_recipe_file(
+        &self,
+        recipe_path: &Path,
+        name: &str,
+        description: &str,
+        answers: &Answers,
+    ) -> Result<()> {
+        trace!("write_recipe_file()");
+
+        let base_image = self
+            .common
+            .base_image
+            .as_deref()
+            .or_else(|| {
+                answers
+                    .get(Self::BASE_IMAGE)
+                    .and_then(Answer::as_list_item)
+                    .and_then(|li| {
+                        BASE_IMAGES
+                            .iter()
+                            .find(|(base_name, _)| *base_name == li.text)
+                            .map(|(_, image)| *image)
+                    })
+            })
+            .or_else(|| {
+                answers
+                    .get(Self::CUSTOM_BASE_IMAGE)
+                    .and_then(Answer::as_string)
+            })
+            .ok_or_else(|| miette!("Failed to get base image"))?;
+        let image_version = self.selected_image_version(answers);
+        let modules = self.selected_modules(answers);
+        let has_module = |module: &str| modules.contains(&module);
+
+        let recipe = InitRecipeTemplate::builder()
+            .name(name)
+            .description(description)
+            .base_image(base_image)
+            .image_version(image_version)
+            .flatpaks(has_module(FLATPAKS_MODULE))
+            .brew(has_module(BREW_MODULE))
+            .signing(has_module(SIGNING_MODULE))
+            .build()
+            .render()
+            .into_diagnostic()?;
+
+        if let Some(parent) = recipe_path.parent() {
+            fs::create_dir_all(parent).into_diagnostic()?;
+        }
 
-        debug!("Removing old cosign files {COSIGN_PUB_PATH}");
-        fs::remove_file(self.dir.as_ref().unwrap().join(COSIGN_PUB_PATH))
+        debug!("Writing generated recipe to {}", recipe_path.display());
+        fs::write(recipe_path, recipe)
             .into_diagnostic()
-            .with_context(|| format!("Failed to delete old public file {COSIGN_PUB_PATH}"))?;
+            .with_context(|| format!("Failed to write to file {}", recipe_path.display()))
+    }
+
+    fn generate_signing_files(&self, answers: &Answers) -> Result<()> {
+        trace!("generate_signing_files()");
+
+        let cosign_pub_path = self.dir.as_ref().unwrap().join(COSIGN_PUB_PATH);
+
+        if cosign_pub_path.exists() {
+            debug!("Removing old cosign files {COSIGN_PUB_PATH}");
+            fs::remove_file(cosign_pub_path)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to delete old public file {COSIGN_PUB_PATH}"))?;
+        }
+
+        if self.keyless(answers) {
+            debug!("Using keyless signing, skipping key pair generation");
+            return Ok(());
+        }
 
         Driver::generate_key_pair(
             &GenerateKeyPairOpts::builder()
@@ -534,3 +1431,313 @@ impl InitCommand {
         )
     }
 }
+
+#[cfg(test)]
+mod test {
+    use std::{
+        fs,
+        path::Path,
+        process::{Command, Stdio},
+    };
+
+    use clap::crate_version;
+    use semver::Version;
+    use tempfile::TempDir;
+
+    use blue_build_recipe::{ModuleExt, Recipe};
+    use blue_build_template::{
+        GithubChangelogWorkflowTemplate, GithubIsoWorkflowTemplate, GithubVerifyWorkflowTemplate,
+        InitModulesCommonTemplate, InitReadmeTemplate, InitRecipeTemplate, Template,
+        INIT_PROJECT_TEMPLATE,
+    };
+
+    use super::{
+        copy_dir, CiProvider, InitCommand, NewInitCommon, UpdateBot, FLATPAKS_MODULE,
+        SIGNING_MODULE,
+    };
+
+    #[test]
+    fn non_interactive_answers() {
+        let common = NewInitCommon {
+            image_name: Some("test".into()),
+            registry: Some("ghcr.io".into()),
+            org_name: Some("blue-build".into()),
+            ..Default::default()
+        };
+
+        let err = InitCommand::builder()
+            .common(common.clone())
+            .build()
+            .default_answers()
+            .unwrap_err();
+        assert_eq!(
+            err.help().unwrap().to_string(),
+            "Pass --description, --ci-provider, --update-bot"
+        );
+
+        let answers = InitCommand::builder()
+            .common(NewInitCommon {
+                description: Some("A test image".into()),
+                no_git: true,
+                multi: true,
+                ..common
+            })
+            .build()
+            .default_answers()
+            .unwrap();
+        let modules = answers
+            .get(InitCommand::MODULES)
+            .and_then(|answer| answer.as_list_items())
+            .unwrap()
+            .iter()
+            .map(|li| li.text.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(modules, [FLATPAKS_MODULE, SIGNING_MODULE]);
+    }
+
+    #[test]
+    fn render_github_workflow() {
+        let version: Version = crate_version!().parse().unwrap();
+        let workflow = CiProvider::Github
+            .render_file(&["test-gnome.yml".into(), "test-kde.yml".into()], false)
+            .unwrap();
+
+        assert!(workflow.contains(&format!(
+            "cli_version: v{}.{}",
+            version.major, version.minor
+        )));
+        assert!(workflow.contains("recipe: ${{ matrix.recipe }}"));
+        assert!(workflow.contains("          - test-gnome.yml\n          - test-kde.yml\n"));
+    }
+
+    #[test]
+    fn render_keyless_ci_files() {
+        let workflow = CiProvider::Github
+            .render_file(&["recipe.yml".into()], true)
+            .unwrap();
+        assert!(!workflow.contains("cosign_private_key"));
+        assert!(workflow.contains("id-token: write"));
+
+        let ci_file = CiProvider::Gitlab
+            .render_file(&["recipe.yml".into()], true)
+            .unwrap();
+        assert!(ci_file.contains("  id_tokens:\n    # Used by cosign"));
+        assert!(!ci_file.contains("COSIGN_PRIVATE_KEY"));
+
+        let ci_file = CiProvider::Gitlab
+            .render_file(&["recipe.yml".into()], false)
+            .unwrap();
+        assert!(!ci_file.contains("id_tokens"));
+        assert!(ci_file.contains("export COSIGN_PRIVATE_KEY"));
+    }
+
+    #[test]
+    fn render_keyless_readme() {
+        let readme = InitReadmeTemplate::builder()
+            .repo_name("test")
+            .registry("ghcr.io")
+            .image_name("test-image")
+            .maybe_keyless(CiProvider::Github.keyless_signing("test"))
+            .build()
+            .render()
+            .unwrap();
+
+        assert!(readme.contains(
+            "cosign verify --certificate-identity-regexp \"^https://github.com/test/\" --certificate-oidc-issuer https://token.actions.githubusercontent.com ghcr.io/test/test-image"
+        ));
+        assert!(!readme.contains("ostree-image-signed"));
+        assert!(!readme.contains("cosign.pub"));
+    }
+
+    #[test]
+    fn render_extra_workflows() {
+        let images = vec![
+            "ghcr.io/test/test-gnome".into(),
+            "ghcr.io/test/test-kde".into(),
+        ];
+
+        let iso = GithubIsoWorkflowTemplate::builder()
+            .version("v0.9")
+            .images(images.clone())
+            .build()
+            .render()
+            .unwrap();
+        assert!(iso.contains("ghcr.io/blue-build/cli:v0.9-installer"));
+        assert!(iso
+            .contains("          - ghcr.io/test/test-gnome\n          - ghcr.io/test/test-kde\n"));
+        assert!(iso.contains("IMAGE: ${{ matrix.image }}"));
+
+        let changelog = GithubChangelogWorkflowTemplate::builder()
+            .images(images.clone())
+            .build()
+            .render()
+            .unwrap();
+        assert!(changelog.contains("echo '- `ghcr.io/test/test-kde:latest`'"));
+
+        let verify = GithubVerifyWorkflowTemplate::builder()
+            .images(images)
+            .build()
+            .render()
+            .unwrap();
+        assert!(verify.contains("cosign verify --key cosign.pub \"${IMAGE}:latest\""));
+    }
+
+    #[test]
+    fn render_renovate_config() {
+        let config: serde_json::Value =
+            serde_json::from_str(&UpdateBot::Renovate.render_file().unwrap()).unwrap();
+        let managers = config["customManagers"].as_array().unwrap();
+
+        assert_eq!(managers.len(), 3);
+        assert!(managers
+            .iter()
+            .all(|manager| manager["customType"] == "regex"));
+        assert!(managers
+            .iter()
+            .any(|manager| manager["depNameTemplate"] == "ghcr.io/blue-build/cli"));
+    }
+
+    #[test]
+    fn copy_template_dir() {
+        let from = TempDir::new().unwrap();
+        let to = TempDir::new().unwrap();
+        let to = to.path().join("project");
+
+        fs::create_dir_all(from.path().join("recipes")).unwrap();
+        fs::write(from.path().join("recipes/recipe.yml"), "name: test").unwrap();
+        fs::write(from.path().join("README.md"), "# test").unwrap();
+
+        copy_dir(from.path(), &to).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(to.join("recipes/recipe.yml")).unwrap(),
+            "name: test"
+        );
+        assert!(to.join("README.md").exists());
+    }
+
+    #[test]
+    fn clone_template_repo() {
+        let git = |dir: &Path, args: &[&str]| {
+            assert!(Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+                .args(args)
+                .current_dir(dir)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .unwrap()
+                .success());
+        };
+        let parent = TempDir::new().unwrap();
+        let repo = parent.path().join("template");
+        fs::create_dir(&repo).unwrap();
+        git(&repo, &["init", "-q"]);
+        fs::write(repo.join("README.md"), "# v1").unwrap();
+        git(&repo, &["add", "README.md"]);
+        git(&repo, &["commit", "-q", "-m", "v1"]);
+        git(&repo, &["tag", "v1"]);
+        fs::write(repo.join("README.md"), "# v2").unwrap();
+        git(&repo, &["commit", "-q", "-a", "-m", "v2"]);
+
+        let clone = |template_ref: &str| {
+            let dir = parent.path().join(format!("project{template_ref}"));
+            InitCommand::builder()
+                .dir(dir.clone())
+                .common(NewInitCommon {
+                    template_repo: Some(repo.display().to_string()),
+                    template_ref: Some(template_ref.into()),
+                    ..Default::default()
+                })
+                .build()
+                .clone_repository()
+                .map(|()| fs::read_to_string(dir.join("README.md")).unwrap())
+        };
+
+        assert_eq!(clone("v1").unwrap(), "# v1");
+        assert!(clone("--orphan=x").is_err());
+    }
+
+    #[test]
+    fn extract_embedded_template() {
+        let dir = TempDir::new().unwrap();
+        let dir = dir.path().join("project");
+
+        fs::create_dir_all(&dir).unwrap();
+        INIT_PROJECT_TEMPLATE.extract(&dir).unwrap();
+
+        let recipe: Recipe =
+            serde_yaml::from_str(&fs::read_to_string(dir.join("recipes/recipe.yml")).unwrap())
+                .unwrap();
+
+        assert_eq!(recipe.name, "template");
+        assert!(dir.join("files/scripts/example.sh").exists());
+        assert!(dir.join("modules/.gitkeep").exists());
+        assert!(dir.join(".gitignore").exists());
+    }
+
+    #[test]
+    fn render_recipe() {
+        let recipe = InitRecipeTemplate::builder()
+            .name("test-image")
+            .description("A test image")
+            .base_image("ghcr.io/ublue-os/silverblue-main")
+            .image_version("41")
+            .flatpaks(true)
+            .signing(true)
+            .build()
+            .render()
+            .unwrap();
+        let recipe: Recipe = serde_yaml::from_str(&recipe).unwrap();
+
+        assert_eq!(recipe.name, "test-image");
+        assert_eq!(recipe.base_image, "ghcr.io/ublue-os/silverblue-main");
+        assert_eq!(recipe.image_version, "41");
+        assert_eq!(recipe.modules_ext.modules.len(), 4);
+    }
+
+    #[test]
+    fn render_recipe_description() {
+        let description = "Mine: a \"test\" image # with \\ and\nnewlines\t\u{7}";
+        let recipe = InitRecipeTemplate::builder()
+            .name("test-image")
+            .description(description)
+            .base_image("ghcr.io/ublue-os/silverblue-main")
+            .image_version("41")
+            .build()
+            .render()
+            .unwrap();
+        let recipe: Recipe = serde_yaml::from_str(&recipe).unwrap();
+
+        assert_eq!(recipe.description, description);
+    }
+
+    #[test]
+    fn render_multi_recipe() {
+        let recipe = InitRecipeTemplate::builder()
+            .name("test-image-kde")
+            .description("A test image")
+            .base_image("ghcr.io/ublue-os/kinoite-main")
+            .image_version("latest")
+            .common_modules("modules-common.yml")
+            .build()
+            .render()
+            .unwrap();
+        let recipe: Recipe = serde_yaml::from_str(&recipe).unwrap();
+
+        assert_eq!(recipe.modules_ext.modules.len(), 1);
+        assert_eq!(
+            recipe.modules_ext.modules[0].from_file.as_deref(),
+            Some("modules-common.yml")
+        );
+
+        let modules = InitModulesCommonTemplate::builder()
+            .brew(true)
+            .build()
+            .render()
+            .unwrap();
+        let modules: ModuleExt = serde_yaml::from_str(&modules).unwrap();
+
+        assert_eq!(modules.modules.len(), 3);
+    }
+}
@@ -0,0 +1,422 @@
+use std::{
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_process_management::drivers::{CiDriver, GithubDriver, GitlabDriver};
+use blue_build_recipe::Recipe;
+use blue_build_template::{
+    GithubChangelogWorkflowTemplate, GithubIsoWorkflowTemplate, GithubVerifyWorkflowTemplate,
+    Template,
+};
+use blue_build_utils::{
+    constants::{COSIGN_PUB_PATH, RECIPE_PATH},
+    traits::CowCollecter,
+};
+use colored::Colorize;
+use log::{debug, info, trace, warn};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use requestty::Question;
+use serde_yaml::Value;
+use similar::{ChangeTag, TextDiff};
+
+use crate::prompt;
+
+use super::{CiProvider, InitCommand};
+
+const ISO_WORKFLOW_FILE: &str = "build-iso.yml";
+const CHANGELOG_WORKFLOW_FILE: &str = "changelog.yml";
+const VERIFY_WORKFLOW_FILE: &str = "verify.yml";
+const VERIFY_BADGE: &str = "actions/workflows/verify.yml/badge.svg";
+
+impl CiProvider {
+    /// Determines the CI provider of an existing
+    /// project from the CI files that it contains.
+    fn detect(dir: &Path) -> Option<Self> {
+        if dir.join(GithubDriver::default_ci_file_path()).exists() {
+            Some(Self::Github)
+        } else if dir.join(GitlabDriver::default_ci_file_path()).exists() {
+            Some(Self::Gitlab)
+        } else {
+            None
+        }
+    }
+
+    /// The key of the CI matrix that holds the recipe files.
+    const fn recipe_matrix_key(self) -> &'static str {
+        match self {
+            Self::Gitlab => "RECIPE",
+            Self::Github | Self::None => "recipe",
+        }
+    }
+}
+
+impl InitCommand {
+    /// Regenerates the CI files of an existing project
+    /// from the current templates.
+    ///
+    /// The recipes and images that the CI files build are
+    /// taken from the existing files so they are kept as is.
+    pub(super) fn update_ci_files(&self) -> Result<()> {
+        trace!("update_ci_files()");
+
+        let dir = self.dir.as_ref().unwrap();
+
+        let ci_provider = match self.common.ci_provider {
+            Some(CiProvider::None) => bail!("Cannot update CI files without a CI provider"),
+            Some(ci_provider) => ci_provider,
+            None => CiProvider::detect(dir).ok_or_else(|| {
+                miette!("Couldn't find a CI file to update, set one with '--ci-provider'")
+            })?,
+        };
+
+        // Projects that sign keylessly don't have a public key
+        let keyless = !dir.join(COSIGN_PUB_PATH).exists();
+
+        let ci_file_path = dir.join(ci_provider.default_ci_file_path());
+        let recipes = match read_existing(&ci_file_path)?
+            .and_then(|file| matrix_values(&file, ci_provider.recipe_matrix_key()))
+        {
+            Some(recipes) => recipes,
+            None => find_recipe_files(&dir.join(RECIPE_PATH))?,
+        };
+
+        let mut changes = vec![(
+            ci_file_path,
+            format!("{}\n", ci_provider.render_file(&recipes, keyless)?),
+        )];
+
+        if matches!(ci_provider, CiProvider::Github) {
+            changes.extend(self.updated_extra_workflows(dir, &recipes, keyless)?);
+        }
+
+        let changes = changes
+            .into_iter()
+            .filter(|(path, new)| fs::read_to_string(path).ok().as_deref() != Some(new))
+            .collect::<Vec<_>>();
+
+        if changes.is_empty() {
+            info!("CI files are already up to date");
+            return Ok(());
+        }
+
+        for (path, new) in &changes {
+            let old = fs::read_to_string(path).unwrap_or_default();
+            print!("{}", print_diff(path, &old, new));
+        }
+
+        prompt::ensure_interactive(
+            "confirmation to apply the changes",
+            "Run `bluebuild init --update` in a terminal to review the changes",
+        )?;
+
+        let confirmed = requestty::prompt_one(
+            Question::confirm("apply")
+                .message("Apply these changes?")
+                .default(false)
+                .build(),
+        )
+        .into_diagnostic()?
+        .as_bool()
+        .unwrap_or_default();
+
+        if !confirmed {
+            info!("No changes were made");
+            return Ok(());
+        }
+
+        for (path, new) in changes {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).into_diagnostic()?;
+            }
+
+            debug!("Writing {}", path.display());
+            fs::write(&path, new)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to write {}", path.display()))?;
+        }
+
+        info!("Updated CI files in {}", dir.display());
+
+        Ok(())
+    }
+
+    /// Regenerates the extra workflows that the project already
+    /// has along with the verification badge in the README.
+    fn updated_extra_workflows(
+        &self,
+        dir: &Path,
+        recipes: &[String],
+        keyless: bool,
+    ) -> Result<Vec<(PathBuf, String)>> {
+        let workflows_dir = dir.join(".github/workflows");
+        let iso_path = workflows_dir.join(ISO_WORKFLOW_FILE);
+        let changelog_path = workflows_dir.join(CHANGELOG_WORKFLOW_FILE);
+        let verify_path = workflows_dir.join(VERIFY_WORKFLOW_FILE);
+
+        if !(iso_path.exists() || changelog_path.exists() || verify_path.exists()) {
+            return Ok(Vec::new());
+        }
+
+        let existing_images = [&iso_path, &verify_path]
+            .into_iter()
+            .filter_map(|path| read_existing(path).transpose())
+            .collect::<Result<Vec<_>>>()?
+            .iter()
+            .find_map(|file| matrix_values(file, "image"));
+        let Some(images) = existing_images.map_or_else(
+            || self.images_from_recipes(dir, recipes),
+            |images| Ok(Some(images)),
+        )?
+        else {
+            warn!("Couldn't determine the images to publish, set '--registry' and '--org-name' to update the extra workflows");
+            return Ok(Vec::new());
+        };
+
+        let mut changes = Vec::new();
+
+        if iso_path.exists() {
+            changes.push((
+                iso_path,
+                GithubIsoWorkflowTemplate::builder()
+                    .version(CiProvider::version()?)
+                    .images(images.collect_cow_vec())
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            ));
+        }
+
+        if changelog_path.exists() {
+            changes.push((
+                changelog_path,
+                GithubChangelogWorkflowTemplate::builder()
+                    .images(images.collect_cow_vec())
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            ));
+        }
+
+        if verify_path.exists() {
+            changes.push((
+                verify_path,
+                GithubVerifyWorkflowTemplate::builder()
+                    .images(images.collect_cow_vec())
+                    .keyless(keyless)
+                    .build()
+                    .render()
+                    .into_diagnostic()?,
+            ));
+
+            let readme_path = dir.join("README.md");
+            let org_name = self
+                .common
+                .org_name
+                .as_deref()
+                .or_else(|| images.first().and_then(|image| image.split('/').nth(1)));
+
+            if let (Some(readme), Some(org_name), Some(repo)) = (
+                read_existing(&readme_path)?,
+                org_name,
+                dir.file_name().map(|repo| repo.to_string_lossy()),
+            ) {
+                if !readme.contains(VERIFY_BADGE) {
+                    changes.push((readme_path, add_verify_badge(&readme, org_name, &repo)));
+                }
+            }
+        }
+
+        Ok(changes)
+    }
+
+    /// Builds the image refs from the names of the recipes
+    /// using the `--registry` and `--org-name` args.
+    fn images_from_recipes(&self, dir: &Path, recipes: &[String]) -> Result<Option<Vec<String>>> {
+        let (Some(registry), Some(org_name)) = (
+            self.common.registry.as_deref(),
+            self.common.org_name.as_deref(),
+        ) else {
+            return Ok(None);
+        };
+
+        recipes
+            .iter()
+            .map(|recipe| {
+                let recipe_path = dir.join(RECIPE_PATH).join(recipe);
+                let file = fs::read_to_string(&recipe_path)
+                    .into_diagnostic()
+                    .with_context(|| format!("Failed to read {}", recipe_path.display()))?;
+                let recipe = Recipe::from_yaml(&file)
+                    .with_context(|| format!("Failed to parse {}", recipe_path.display()))?;
+
+                Ok(format!("{registry}/{org_name}/{}", recipe.name).to_lowercase())
+            })
+            .collect::<Result<Vec<_>>>()
+            .map(Some)
+    }
+}
+
+fn read_existing(path: &Path) -> Result<Option<String>> {
+    if !path.exists() {
+        return Ok(None);
+    }
+
+    fs::read_to_string(path)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to read {}", path.display()))
+        .map(Some)
+}
+
+/// Finds the files in the recipe directory that are full recipes,
+/// skipping any files that only contain modules or stages.
+fn find_recipe_files(recipe_dir: &Path) -> Result<Vec<String>> {
+    let mut recipes = fs::read_dir(recipe_dir)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to read {}", recipe_dir.display()))?
+        .filter_map(std::result::Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| ext == "yml" || ext == "yaml")
+                && fs::read_to_string(path).is_ok_and(|file| Recipe::from_yaml(&file).is_ok())
+        })
+        .filter_map(|path| Some(path.file_name()?.to_string_lossy().into_owned()))
+        .collect::<Vec<_>>();
+    recipes.sort();
+
+    if recipes.is_empty() {
+        bail!("Couldn't find any recipes in {}", recipe_dir.display());
+    }
+
+    Ok(recipes)
+}
+
+/// Finds the first list of strings under `key`
+/// in a CI file's build matrix.
+fn matrix_values(file: &str, key: &str) -> Option<Vec<String>> {
+    fn find(value: &Value, key: &str) -> Option<Vec<String>> {
+        match value {
+            Value::Mapping(map) => map
+                .get(key)
+                .and_then(Value::as_sequence)
+                .and_then(|list| {
+                    list.iter()
+                        .map(|item| item.as_str().map(ToOwned::to_owned))
+                        .collect::<Option<Vec<_>>>()
+                })
+                .or_else(|| map.values().find_map(|value| find(value, key))),
+            Value::Sequence(list) => list.iter().find_map(|value| find(value, key)),
+            _ => None,
+        }
+    }
+
+    find(&serde_yaml::from_str(file).ok()?, key).filter(|values| !values.is_empty())
+}
+
+fn add_verify_badge(readme: &str, org_name: &str, repo: &str) -> String {
+    let badge = format!(
+        "[![Signature verification](https://github.com/{org_name}/{repo}/{VERIFY_BADGE})](https://github.com/{org_name}/{repo}/actions/workflows/{VERIFY_WORKFLOW_FILE})"
+    );
+
+    let mut lines = readme.lines().map(ToOwned::to_owned).collect::<Vec<_>>();
+    let index = lines
+        .iter()
+        .position(|line| line.starts_with("# "))
+        .map_or(0, |i| i + 1);
+    lines.splice(index..index, [String::new(), badge]);
+
+    let mut readme = lines.join("\n");
+    readme.push('\n');
+    readme
+}
+
+fn print_diff(path: &Path, old: &str, new: &str) -> String {
+    let diff = TextDiff::from_lines(old, new);
+    let mut out = format!("{}\n", format!("--- {}", path.display()).bold());
+
+    for group in diff.grouped_ops(3) {
+        for op in group {
+            for change in diff.iter_changes(&op) {
+                let value = change.to_string_lossy();
+                let value = value.trim_end_matches('\n');
+                let line = match change.tag() {
+                    ChangeTag::Delete => format!("-{value}").red(),
+                    ChangeTag::Insert => format!("+{value}").green(),
+                    ChangeTag::Equal => format!(" {value}").normal(),
+                };
+                let _ = writeln!(out, "{line}");
+            }
+        }
+        let _ = writeln!(out, "{}", "...".dimmed());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::{add_verify_badge, find_recipe_files, matrix_values};
+    use crate::commands::init::CiProvider;
+
+    #[test]
+    fn github_matrix() {
+        let workflow = CiProvider::Github
+            .render_file(&["test-gnome.yml".into(), "test-kde.yml".into()], false)
+            .unwrap();
+
+        assert_eq!(
+            matrix_values(&workflow, "recipe"),
+            Some(vec!["test-gnome.yml".into(), "test-kde.yml".into()])
+        );
+        assert_eq!(matrix_values(&workflow, "image"), None);
+    }
+
+    #[test]
+    fn gitlab_matrix() {
+        let ci_file = CiProvider::Gitlab
+            .render_file(&["recipe.yml".into()], false)
+            .unwrap();
+
+        assert_eq!(
+            matrix_values(&ci_file, "RECIPE"),
+            Some(vec!["recipe.yml".into()])
+        );
+    }
+
+    #[test]
+    fn recipe_files() {
+        let dir = TempDir::new().unwrap();
+
+        fs::write(
+            dir.path().join("recipe.yml"),
+            "name: test\ndescription: test\nbase-image: ghcr.io/ublue-os/silverblue-main\nimage-version: latest\nmodules: []\n",
+        )
+        .unwrap();
+        fs::write(
+            dir.path().join("modules-common.yml"),
+            "modules:\n  - type: signing\n",
+        )
+        .unwrap();
+
+        assert_eq!(
+            find_recipe_files(dir.path()).unwrap(),
+            vec![String::from("recipe.yml")]
+        );
+    }
+
+    #[test]
+    fn verify_badge() {
+        let readme = add_verify_badge("# test Image Repo\n\nSome text\n", "test", "my-image");
+
+        assert_eq!(
+            readme,
+            "# test Image Repo\n\n[![Signature verification](https://github.com/test/my-image/actions/workflows/verify.yml/badge.svg)](https://github.com/test/my-image/actions/workflows/verify.yml)\n\nSome text\n"
+        );
+    }
+}
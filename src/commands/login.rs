@@ -3,8 +3,11 @@ use std::io::{self, Read};
 use blue_build_process_management::drivers::{BuildDriver, Driver, DriverArgs, SigningDriver};
 use blue_build_utils::credentials::{Credentials, CredentialsArgs};
 use clap::Args;
+use log::{info, warn};
 use miette::{bail, IntoDiagnostic, Result};
-use requestty::questions;
+use requestty::{questions, Question};
+
+use crate::prompt;
 
 use super::BlueBuildCommand;
 
@@ -35,7 +38,7 @@ pub struct LoginCommand {
 
 impl BlueBuildCommand for LoginCommand {
     fn try_run(&mut self) -> miette::Result<()> {
-        Driver::init(self.drivers);
+        Driver::init(self.drivers.clone());
 
         Credentials::init(
             CredentialsArgs::builder()
@@ -57,19 +60,8 @@ impl LoginCommand {
         Ok(if let Some(ref username) = self.username {
             username.clone()
         } else if !self.password_stdin {
-            let questions = questions! [ inline
-                Input {
-                    name: "username",
-                },
-            ];
-
-            requestty::prompt(questions)
-                .into_diagnostic()?
-                .get("username")
-                .unwrap()
-                .as_string()
-                .unwrap()
-                .to_string()
+            prompt::ensure_interactive("a username", "Pass the username with --username")?;
+            ask_username()?
         } else {
             bail!("Cannot prompt for username when using `--password-stdin`");
         })
@@ -85,19 +77,131 @@ impl LoginCommand {
                 .into_diagnostic()?;
             password
         } else {
-            let questions = questions! [ inline
-                Password {
-                    name: "password",
-                }
-            ];
-
-            requestty::prompt(questions)
-                .into_diagnostic()?
-                .get("password")
-                .unwrap()
-                .as_string()
-                .unwrap()
-                .to_string()
+            prompt::ensure_interactive(
+                "a password",
+                "Pass the password with --password or --password-stdin",
+            )?;
+            ask_password()?
         })
     }
 }
+
+fn ask_username() -> Result<String> {
+    let questions = questions! [ inline
+        Input {
+            name: "username",
+        },
+    ];
+
+    Ok(requestty::prompt(questions)
+        .into_diagnostic()?
+        .get("username")
+        .unwrap()
+        .as_string()
+        .unwrap()
+        .to_string())
+}
+
+fn ask_password() -> Result<String> {
+    let questions = questions! [ inline
+        Password {
+            name: "password",
+        }
+    ];
+
+    Ok(requestty::prompt(questions)
+        .into_diagnostic()?
+        .get("password")
+        .unwrap()
+        .as_string()
+        .unwrap()
+        .to_string())
+}
+
+/// The number of times the user is asked for
+/// credentials before the login gives up.
+const LOGIN_ATTEMPTS: usize = 3;
+
+/// Asks for the credentials of `registry` and logs in with them,
+/// asking again if the login fails. The user is then offered to
+/// save them so that they aren't asked for on the next push.
+///
+/// The registry is asked for too if it isn't known.
+///
+/// # Errors
+/// Will error if the user can't be prompted or the login
+/// still fails after the last attempt.
+pub(crate) fn prompt_login(registry: Option<&str>) -> Result<()> {
+    prompt::ensure_interactive(
+        "registry credentials",
+        "Pass the credentials with --username and --password, or run `bluebuild login`",
+    )?;
+    info!("No credentials were found to push with, log in to continue");
+
+    let registry = if let Some(registry) = registry {
+        registry.to_owned()
+    } else {
+        requestty::prompt_one(
+            Question::input("registry")
+                .message("registry")
+                .default("ghcr.io")
+                .build(),
+        )
+        .into_diagnostic()?
+        .as_string()
+        .unwrap()
+        .to_string()
+    };
+
+    let mut attempt = 1;
+    let creds = loop {
+        let creds = Credentials::builder()
+            .registry(registry.clone())
+            .username(ask_username()?)
+            .password(ask_password()?)
+            .build();
+        Credentials::set(creds.clone());
+
+        match Driver::login() {
+            Ok(()) => break creds,
+            Err(e) if attempt < LOGIN_ATTEMPTS => {
+                warn!("{e:?}");
+                attempt += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    };
+
+    offer_save(&creds)
+}
+
+/// Asks the user where to save `creds`, if anywhere.
+fn offer_save(creds: &Credentials) -> Result<()> {
+    let stores = creds.stores();
+    if stores.is_empty() {
+        return Ok(());
+    }
+
+    let mut choices = stores
+        .iter()
+        .map(|store| format!("Save them in {store}"))
+        .collect::<Vec<_>>();
+    choices.push("Don't save them".into());
+
+    let answer = requestty::prompt_one(
+        Question::select("save")
+            .message(format!("Save the credentials of {}?", creds.registry))
+            .choices(choices)
+            .build(),
+    )
+    .into_diagnostic()?;
+
+    if let Some(store) = answer
+        .as_list_item()
+        .and_then(|item| stores.get(item.index))
+    {
+        creds.save(store)?;
+        info!("Saved the credentials of {} in {store}", creds.registry);
+    }
+    Ok(())
+}
@@ -0,0 +1,280 @@
+use std::{fs, path::PathBuf};
+
+use blue_build_recipe::{ApiVersion, Recipe, API_VERSION_KEY, V1_KEYS};
+use bon::Builder;
+use clap::Args;
+use colored::Colorize;
+use log::{debug, info, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+
+use super::BlueBuildCommand;
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct MigrateCommand {
+    /// The recipes to convert to the latest format.
+    #[arg(required = true)]
+    #[builder(into)]
+    recipes: Vec<PathBuf>,
+
+    /// Print the converted recipes instead of writing them.
+    #[arg(long)]
+    #[builder(default)]
+    dry_run: bool,
+}
+
+impl BlueBuildCommand for MigrateCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("MigrateCommand::try_run()");
+
+        for recipe_path in &self.recipes {
+            let file = fs::read_to_string(recipe_path)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to read {}", recipe_path.display()))?;
+
+            let recipe = Recipe::from_yaml(&file)
+                .with_context(|| format!("Failed to parse {}", recipe_path.display()))?;
+            if recipe.api_version == ApiVersion::LATEST {
+                info!(
+                    "The recipe {} is already a {} recipe",
+                    recipe_path.display(),
+                    ApiVersion::LATEST
+                );
+                continue;
+            }
+
+            let migrated = migrate(&file)
+                .with_context(|| format!("Failed to migrate {}", recipe_path.display()))?;
+            check_unchanged(&recipe, &migrated)
+                .with_context(|| format!("Failed to migrate {}", recipe_path.display()))?;
+
+            if self.dry_run {
+                println!("# {}\n{migrated}", recipe_path.display());
+            } else {
+                debug!("Writing the migrated recipe to {}", recipe_path.display());
+                fs::write(recipe_path, migrated)
+                    .into_diagnostic()
+                    .with_context(|| format!("Failed to write {}", recipe_path.display()))?;
+                info!(
+                    "Migrated {} to a {} recipe",
+                    recipe_path.display(),
+                    ApiVersion::LATEST
+                );
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// The comment that points editors at the schema of v1
+/// recipes, which doesn't match the recipe once it's migrated.
+const V1_SCHEMA_COMMENT: &str = "# yaml-language-server: $schema=";
+
+/// Converts a v1 recipe to the latest version line by
+/// line, so that the comments of the recipe are kept.
+fn migrate(file: &str) -> Result<String> {
+    let mut lines = Vec::new();
+    let mut image_version = None;
+    let mut inserted_version = false;
+
+    for line in file.lines() {
+        if line
+            .strip_prefix(V1_SCHEMA_COMMENT)
+            .is_some_and(|schema| schema.trim_end().ends_with("recipe-v1.json"))
+        {
+            debug!("Removing the v1 schema comment {line}");
+            continue;
+        }
+
+        let Some((key, rest)) = top_level_key(line) else {
+            lines.push(line.to_owned());
+            continue;
+        };
+
+        if !inserted_version {
+            lines.push(format!("{API_VERSION_KEY}: {}", ApiVersion::LATEST));
+            inserted_version = true;
+        }
+
+        if matches!(key, "image-version" | "image_version") {
+            let (version, comment) = split_comment(rest);
+            image_version = Some((unquote(version).to_owned(), comment.to_owned()));
+            continue;
+        }
+
+        match V1_KEYS.iter().find(|(v1_key, _)| *v1_key == key) {
+            Some((_, v2_key)) => lines.push(format!("{v2_key}:{rest}")),
+            None => lines.push(line.to_owned()),
+        }
+    }
+
+    let Some((image_version, version_comment)) = image_version else {
+        bail!("The recipe doesn't have an {}", "image-version".bold());
+    };
+    let Some(base_image_line) = lines
+        .iter_mut()
+        .find(|line| top_level_key(line).is_some_and(|(key, _)| key == "base-image"))
+    else {
+        bail!("The recipe doesn't have a {}", "base-image".bold());
+    };
+
+    let (base_image, comment) = top_level_key(base_image_line)
+        .map(|(_, rest)| split_comment(rest))
+        .unwrap_or_default();
+    if base_image.is_empty() {
+        bail!(
+            "The {} must be on the same line as its key to be migrated",
+            "base-image".bold()
+        );
+    }
+    let base_image = match base_image.chars().next() {
+        Some(quote @ ('"' | '\'')) => {
+            format!("{quote}{}:{image_version}{quote}", unquote(base_image))
+        }
+        _ => format!("{base_image}:{image_version}"),
+    };
+    let comment = if comment.is_empty() {
+        version_comment
+    } else {
+        comment.to_owned()
+    };
+    *base_image_line = format!("base-image: {base_image}{comment}");
+
+    let mut migrated = lines.join("\n");
+    if file.ends_with('\n') {
+        migrated.push('\n');
+    }
+    Ok(migrated)
+}
+
+/// Checks that the migrated recipe builds the same image.
+fn check_unchanged(recipe: &Recipe, migrated: &str) -> Result<()> {
+    let migrated = Recipe::from_yaml(migrated).context("The migrated recipe is invalid")?;
+
+    if serde_yaml::to_value(recipe).into_diagnostic()?
+        != serde_yaml::to_value(&migrated).into_diagnostic()?
+    {
+        bail!(
+            help = "Migrate the recipe by hand",
+            "The migrated recipe doesn't match the original recipe"
+        );
+    }
+    Ok(())
+}
+
+/// The key and the rest of a line that sets a key of the recipe itself.
+fn top_level_key(line: &str) -> Option<(&str, &str)> {
+    if line.starts_with(|c: char| c.is_whitespace() || matches!(c, '#' | '-')) {
+        return None;
+    }
+    line.split_once(':')
+        .filter(|(key, _)| !key.is_empty() && !key.contains(char::is_whitespace))
+}
+
+/// Splits a value from the comment after it.
+fn split_comment(rest: &str) -> (&str, &str) {
+    let value = rest.trim_start();
+    let end = match value.chars().next() {
+        Some(quote @ ('"' | '\'')) => value[1..].find(quote).map_or(value.len(), |i| i + 2),
+        _ => value.find(" #").unwrap_or(value.len()),
+    };
+    (value[..end].trim_end(), &value[end..])
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim_matches(|c| c == '"' || c == '\'')
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_recipe::{ApiVersion, Recipe};
+    use rstest::rstest;
+
+    use super::{check_unchanged, migrate};
+
+    const V1_RECIPE: &str = r#"# yaml-language-server: $schema=https://schema.blue-build.org/recipe-v1.json
+name: test
+description: test
+# the base image to build on top of
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41 # latest is also supported
+alt_tags:
+  - gts
+modules:
+  - type: script
+    snippets:
+      - echo "image-version: ${{ github.sha }}"
+"#;
+
+    const V2_RECIPE: &str = r#"apiVersion: v2
+name: test
+description: test
+# the base image to build on top of
+base-image: ghcr.io/ublue-os/silverblue-main:41 # latest is also supported
+alt-tags:
+  - gts
+modules:
+  - type: script
+    snippets:
+      - echo "image-version: ${{ github.sha }}"
+"#;
+
+    #[test]
+    fn migrates_v1_recipe() {
+        let migrated = migrate(V1_RECIPE).unwrap();
+        assert_eq!(migrated, V2_RECIPE);
+
+        let recipe = Recipe::from_yaml(V1_RECIPE).unwrap();
+        check_unchanged(&recipe, &migrated).unwrap();
+        assert_eq!(
+            Recipe::from_yaml(&migrated).unwrap().api_version,
+            ApiVersion::V2
+        );
+    }
+
+    #[rstest]
+    #[case(
+        "base_image: \"ghcr.io/ublue-os/bazzite\"\nimage_version: 'stable'\n",
+        "apiVersion: v2\nbase-image: \"ghcr.io/ublue-os/bazzite:stable\"\n"
+    )]
+    #[case(
+        "base-image: ghcr.io/ublue-os/bazzite # gaming\nimage-version: stable\n",
+        "apiVersion: v2\nbase-image: ghcr.io/ublue-os/bazzite:stable # gaming\n"
+    )]
+    fn migrates_base_image(#[case] v1: &str, #[case] expected: &str) {
+        assert_eq!(migrate(v1).unwrap(), expected);
+    }
+
+    #[test]
+    fn parses_v2_vars() {
+        let recipe = Recipe::from_yaml(
+            r"
+apiVersion: v2
+name: test
+description: Built on Fedora ${{ vars.FEDORA_VERSION }}
+vars:
+  FEDORA_VERSION: 41
+base-image: ghcr.io/ublue-os/silverblue-main:${{ vars.FEDORA_VERSION }}
+modules: []
+",
+        )
+        .unwrap();
+
+        assert_eq!(recipe.description, "Built on Fedora 41");
+        assert_eq!(recipe.base_image, "ghcr.io/ublue-os/silverblue-main");
+        assert_eq!(recipe.image_version, "41");
+    }
+
+    #[rstest]
+    #[case("name: ${{ vars.MISSING }}\nbase-image: ghcr.io/ublue-os/silverblue-main\n")]
+    #[case("name: test\nbase-image: ghcr.io/ublue-os/silverblue-main\nimage-version: 41\n")]
+    #[case("name: test\nbase-image: ghcr.io/ublue-os/silverblue-main\nprofiles:\n  dev: {}\n")]
+    #[case(
+        "name: test\nbase-image: ghcr.io/ublue-os/silverblue-main\nmatrix:\n  variant: [a, b]\n"
+    )]
+    #[case("name: test\nbase-image: ghcr.io/ublue-os/silverblue-main\nbase_image_typo: x\n")]
+    fn rejects_invalid_v2(#[case] recipe: &str) {
+        let recipe = format!("apiVersion: v2\ndescription: test\nmodules: []\n{recipe}");
+        assert!(Recipe::from_yaml(&recipe).is_err());
+    }
+}
@@ -0,0 +1,351 @@
+use std::{
+    fmt::Display,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+use blue_build_template::{
+    ModuleNuTemplate, ModuleReadmeTemplate, ModuleSchemaTemplate, ModuleShTemplate, Template,
+};
+use blue_build_utils::constants::{LOCAL_MODULES_PATH, RECIPE_FILE, RECIPE_PATH};
+use bon::Builder;
+use clap::{Args, Subcommand, ValueEnum};
+use log::{debug, info, trace, warn};
+use miette::{bail, Context, IntoDiagnostic, Result};
+
+use super::BlueBuildCommand;
+
+#[derive(Debug, Clone, Args)]
+pub struct ModuleCommand {
+    #[command(subcommand)]
+    command: ModuleSubcommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum ModuleSubcommand {
+    /// Scaffold a new local module in the `modules/` directory.
+    ///
+    /// This creates the module's entrypoint script, a JSON
+    /// schema for its configuration, and a README. An example
+    /// usage of the module is added to the recipe.
+    New(NewModuleCommand),
+}
+
+impl BlueBuildCommand for ModuleCommand {
+    fn try_run(&mut self) -> Result<()> {
+        match &mut self.command {
+            ModuleSubcommand::New(command) => command.try_run(),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, ValueEnum)]
+pub enum ModuleLanguage {
+    #[default]
+    Bash,
+    Nu,
+}
+
+impl ModuleLanguage {
+    const fn extension(self) -> &'static str {
+        match self {
+            Self::Bash => "sh",
+            Self::Nu => "nu",
+        }
+    }
+
+    fn render_entrypoint(self, name: &str) -> Result<String> {
+        match self {
+            Self::Bash => ModuleShTemplate::builder().name(name).build().render(),
+            Self::Nu => ModuleNuTemplate::builder().name(name).build().render(),
+        }
+        .into_diagnostic()
+    }
+}
+
+impl Display for ModuleLanguage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}",
+            match *self {
+                Self::Bash => "bash",
+                Self::Nu => "nu",
+            }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct NewModuleCommand {
+    /// The name of the module.
+    ///
+    /// This is used as the `type` of the module
+    /// in the recipe.
+    #[arg()]
+    #[builder(into)]
+    name: String,
+
+    /// The language to write the module in.
+    #[arg(long, short, default_value_t)]
+    #[builder(default)]
+    language: ModuleLanguage,
+
+    /// The recipe to add an example usage of the module to.
+    ///
+    /// Defaults to `recipes/recipe.yml`.
+    #[arg(long, short)]
+    #[builder(into)]
+    recipe: Option<PathBuf>,
+
+    /// Don't add an example usage of the module to a recipe.
+    #[arg(long, conflicts_with = "recipe")]
+    #[builder(default)]
+    no_recipe: bool,
+}
+
+impl BlueBuildCommand for NewModuleCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("NewModuleCommand::try_run()");
+
+        if !is_valid_module_name(&self.name) {
+            bail!(
+                "Module name '{}' must only contain lowercase letters, numbers, and dashes",
+                self.name
+            );
+        }
+
+        let module_dir = Path::new(LOCAL_MODULES_PATH).join(&self.name);
+
+        if module_dir.exists() {
+            bail!("Module {} already exists", module_dir.display());
+        }
+
+        self.write_module_files(&module_dir)?;
+
+        if !self.no_recipe {
+            self.add_to_recipe()?;
+        }
+
+        info!(
+            "Created new {} module in {}",
+            self.language,
+            module_dir.display()
+        );
+
+        Ok(())
+    }
+}
+
+impl NewModuleCommand {
+    fn write_module_files(&self, module_dir: &Path) -> Result<()> {
+        trace!("write_module_files({})", module_dir.display());
+
+        let entrypoint = format!("{}.{}", self.name, self.language.extension());
+
+        fs::create_dir_all(module_dir)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to create {}", module_dir.display()))?;
+
+        let entrypoint_path = module_dir.join(&entrypoint);
+        debug!("Writing {}", entrypoint_path.display());
+        fs::write(
+            &entrypoint_path,
+            self.language.render_entrypoint(&self.name)?,
+        )
+        .and_then(|()| fs::set_permissions(&entrypoint_path, fs::Permissions::from_mode(0o755)))
+        .into_diagnostic()
+        .with_context(|| format!("Failed to write {}", entrypoint_path.display()))?;
+
+        let schema_path = module_dir.join(format!("{}.schema.json", self.name));
+        debug!("Writing {}", schema_path.display());
+        fs::write(
+            &schema_path,
+            ModuleSchemaTemplate::builder()
+                .name(&self.name)
+                .build()
+                .render()
+                .into_diagnostic()?,
+        )
+        .into_diagnostic()
+        .with_context(|| format!("Failed to write {}", schema_path.display()))?;
+
+        let readme_path = module_dir.join("README.md");
+        debug!("Writing {}", readme_path.display());
+        fs::write(
+            &readme_path,
+            ModuleReadmeTemplate::builder()
+                .name(&self.name)
+                .entrypoint(entrypoint)
+                .build()
+                .render()
+                .into_diagnostic()?,
+        )
+        .into_diagnostic()
+        .with_context(|| format!("Failed to write {}", readme_path.display()))
+    }
+
+    fn add_to_recipe(&self) -> Result<()> {
+        trace!("add_to_recipe()");
+
+        let recipe_path = self
+            .recipe
+            .clone()
+            .unwrap_or_else(|| Path::new(RECIPE_PATH).join(RECIPE_FILE));
+
+        if !recipe_path.exists() {
+            warn!(
+                "Recipe {} doesn't exist, skipping adding the module to it",
+                recipe_path.display()
+            );
+            return Ok(());
+        }
+
+        let recipe = fs::read_to_string(&recipe_path)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to read {}", recipe_path.display()))?;
+
+        let Some(recipe) = add_module_usage(&recipe, &self.name) else {
+            warn!(
+                "Couldn't find the modules list in {}, add the module to it with:\n- type: {}\n  source: local",
+                recipe_path.display(),
+                self.name
+            );
+            return Ok(());
+        };
+
+        debug!("Adding module {} to {}", self.name, recipe_path.display());
+        fs::write(&recipe_path, recipe)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to write {}", recipe_path.display()))
+    }
+}
+
+fn is_valid_module_name(name: &str) -> bool {
+    name.chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphanumeric())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-')
+}
+
+/// Adds an example usage of a local module to the end of the
+/// top-level `modules` list of a recipe.
+///
+/// The recipe is edited as text so that comments and formatting
+/// are kept. Returns `None` if there isn't a `modules` list.
+fn add_module_usage(recipe: &str, name: &str) -> Option<String> {
+    let lines = recipe.lines().collect::<Vec<_>>();
+
+    let start = lines.iter().position(|line| {
+        line.split('#')
+            .next()
+            .is_some_and(|line| line.trim_end() == "modules:")
+    })?;
+    let end = lines[start + 1..]
+        .iter()
+        .position(|line| !line.is_empty() && !line.starts_with([' ', '\t', '#', '-']))
+        .map_or(lines.len(), |i| start + 1 + i);
+
+    // Skip past any trailing blank lines or comments in the list
+    let insert_at = lines[..end]
+        .iter()
+        .rposition(|line| {
+            let line = line.trim_start();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .map_or(end, |i| i + 1);
+    let indent = lines[start + 1..end]
+        .iter()
+        .find_map(|line| {
+            line.find('-')
+                .filter(|&i| line[..i].chars().all(char::is_whitespace))
+        })
+        .unwrap_or(2);
+    let indent = " ".repeat(indent);
+
+    let usage = [
+        String::new(),
+        format!("{indent}- type: {name}"),
+        format!("{indent}  source: local"),
+        format!("{indent}  message: Hello World!"),
+    ];
+
+    let mut new_recipe = lines[..insert_at]
+        .iter()
+        .map(ToString::to_string)
+        .chain(usage)
+        .chain(lines[insert_at..].iter().map(ToString::to_string))
+        .collect::<Vec<_>>()
+        .join("\n");
+    new_recipe.push('\n');
+
+    Some(new_recipe)
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_recipe::Recipe;
+    use rstest::rstest;
+
+    use super::{add_module_usage, is_valid_module_name};
+
+    const RECIPE: &str = r"---
+name: test
+description: A test image
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: latest
+modules:
+  - type: files
+    files:
+      - source: system
+        destination: /
+
+  - type: signing # sets up signing
+";
+
+    const RECIPE_WITH_STAGES: &str = r"---
+name: test
+description: A test image
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: latest
+modules:
+- type: signing
+
+# Stages that are built before the image
+stages: []
+";
+
+    #[rstest]
+    #[case("my-module", true)]
+    #[case("module2", true)]
+    #[case("My-Module", false)]
+    #[case("-module", false)]
+    #[case("my_module", false)]
+    #[case("", false)]
+    fn module_names(#[case] name: &str, #[case] expected: bool) {
+        assert_eq!(is_valid_module_name(name), expected);
+    }
+
+    #[rstest]
+    #[case(RECIPE, 3)]
+    #[case(RECIPE_WITH_STAGES, 2)]
+    fn add_usage(#[case] recipe: &str, #[case] expected_modules: usize) {
+        let new_recipe = add_module_usage(recipe, "my-module").unwrap();
+        let parsed: Recipe = serde_yaml::from_str(&new_recipe).unwrap();
+
+        assert_eq!(parsed.modules_ext.modules.len(), expected_modules);
+
+        let module = parsed.modules_ext.modules.last().unwrap();
+        let required = module.required_fields.as_ref().unwrap();
+        assert_eq!(required.module_type, "my-module");
+        assert_eq!(required.source.as_deref(), Some("local"));
+    }
+
+    #[test]
+    fn add_usage_no_modules() {
+        assert!(add_module_usage("name: test\n", "my-module").is_none());
+    }
+}
@@ -0,0 +1,143 @@
+use std::path::{Path, PathBuf};
+
+use blue_build_utils::{cmd, constants::MOK_CERT_PATH};
+use bon::Builder;
+use clap::{Args, Subcommand};
+use colored::Colorize;
+use log::{info, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+
+use crate::prompt;
+
+use super::BlueBuildCommand;
+
+#[derive(Debug, Clone, Args)]
+pub struct MokCommand {
+    #[command(subcommand)]
+    command: MokSubcommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum MokSubcommand {
+    /// Enroll the MOK that the kernel modules of
+    /// the image were signed with on this machine.
+    ///
+    /// This asks for a one-time password, which
+    /// is entered again in the MOK manager on the
+    /// next boot to finish the enrollment.
+    Enroll(MokEnrollCommand),
+}
+
+impl BlueBuildCommand for MokCommand {
+    fn try_run(&mut self) -> Result<()> {
+        match &mut self.command {
+            MokSubcommand::Enroll(command) => command.try_run(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct MokEnrollCommand {
+    /// The public certificate of the MOK in DER format.
+    ///
+    /// Defaults to the certificate that the
+    /// image copies in when it signs kernel modules.
+    #[arg(long)]
+    #[builder(into)]
+    cert: Option<PathBuf>,
+}
+
+impl BlueBuildCommand for MokEnrollCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("MokEnrollCommand::try_run()");
+
+        let cert = self
+            .cert
+            .as_deref()
+            .unwrap_or_else(|| Path::new(MOK_CERT_PATH));
+
+        if !cert.is_file() {
+            bail!(
+                help = "Set `mok` in the recipe to sign the kernel modules of the akmods modules, or pass the certificate with `--cert`",
+                "The MOK certificate {} doesn't exist",
+                cert.display()
+            );
+        }
+
+        let output = cmd!("mokutil", "--sb-state")
+            .output()
+            .into_diagnostic()
+            .context("Failed to run mokutil, make sure it's installed")?;
+        if !secure_boot_enabled(&String::from_utf8_lossy(&output.stdout)) {
+            info!("Secure Boot is disabled, the key only takes effect once it's enabled");
+        }
+
+        let output = cmd!("mokutil", "--test-key", cert)
+            .output()
+            .into_diagnostic()?;
+        if is_enrolled(&String::from_utf8_lossy(&output.stdout)) {
+            info!("The MOK {} is already enrolled", cert.display());
+            return Ok(());
+        }
+
+        prompt::ensure_interactive(
+            "the MOK enrollment password",
+            &format!("Run `sudo mokutil --import {}` instead", cert.display()),
+        )?;
+
+        info!(
+            "Choose a one-time password for the enrollment, it's only used once on the next boot"
+        );
+        let status = cmd!("sudo", "mokutil", "--import", cert)
+            .status()
+            .into_diagnostic()?;
+        if !status.success() {
+            bail!("Failed to import the MOK {}", cert.display());
+        }
+
+        println!(
+            "\n{}\n\
+            1. Reboot the machine\n\
+            2. Press a key when the blue MOK manager screen shows up\n\
+            3. Choose {} and then {}\n\
+            4. Choose {} and enter the one-time password\n\
+            5. Choose {} to boot with the kernel modules loaded",
+            "To finish enrolling the MOK:".bold(),
+            "Enroll MOK".bold(),
+            "Continue".bold(),
+            "Yes".bold(),
+            "Reboot".bold(),
+        );
+        Ok(())
+    }
+}
+
+fn secure_boot_enabled(sb_state: &str) -> bool {
+    sb_state.contains("SecureBoot enabled")
+}
+
+fn is_enrolled(test_key: &str) -> bool {
+    test_key.contains("is already enrolled")
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+
+    use super::{is_enrolled, secure_boot_enabled};
+
+    #[rstest]
+    #[case("SecureBoot enabled\n", true)]
+    #[case("SecureBoot disabled\nPlatform is in Setup Mode\n", false)]
+    #[case("This system doesn't support Secure Boot\n", false)]
+    fn sb_state(#[case] output: &str, #[case] expected: bool) {
+        assert_eq!(secure_boot_enabled(output), expected);
+    }
+
+    #[rstest]
+    #[case("/usr/share/bluebuild/mok.der is already enrolled\n", true)]
+    #[case("/usr/share/bluebuild/mok.der is not enrolled\n", false)]
+    fn test_key(#[case] output: &str, #[case] expected: bool) {
+        assert_eq!(is_enrolled(output), expected);
+    }
+}
@@ -0,0 +1,260 @@
+use std::{
+    env,
+    ffi::{OsStr, OsString},
+    os::unix::{fs::PermissionsExt, process::CommandExt},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use blue_build_process_management::drivers::{
+    types::{
+        BuildDriverType, CiDriverType, DriverSelector, InspectDriverType, RunDriverType,
+        SigningDriverType,
+    },
+    DriverArgs,
+};
+use blue_build_utils::constants::{
+    BB_BUILD_DRIVER, BB_CI_DRIVER, BB_INSPECT_DRIVER, BB_RUN_DRIVER, BB_SIGNING_DRIVER,
+    RECIPE_FILE, RECIPE_PATH,
+};
+use clap::{Args, FromArgMatches, ValueEnum};
+use log::{debug, trace};
+use miette::{bail, miette, IntoDiagnostic, Result};
+
+use crate::{config::Config, shadow};
+
+use super::BlueBuildCommand;
+
+/// The prefix of the executables that are run for unknown subcommands.
+pub const PLUGIN_PREFIX: &str = "bb-";
+
+/// Runs a `bb-<name>` executable found on `PATH`
+/// for a subcommand that isn't built in.
+#[derive(Debug, Clone)]
+pub struct PluginCommand {
+    args: Vec<OsString>,
+}
+
+impl From<Vec<OsString>> for PluginCommand {
+    fn from(args: Vec<OsString>) -> Self {
+        Self { args }
+    }
+}
+
+impl BlueBuildCommand for PluginCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("PluginCommand::try_run()");
+
+        let Some((name, args)) = self.args.split_first() else {
+            bail!("No subcommand given");
+        };
+        let name = name.to_string_lossy();
+
+        let Some(plugin) = env::var_os("PATH").and_then(|path| find_plugin(&name, &path)) else {
+            bail!(
+                "Unrecognized subcommand '{name}', no {PLUGIN_PREFIX}{name} plugin was found on PATH"
+            );
+        };
+        debug!("Running plugin {}", plugin.display());
+
+        let err = Command::new(&plugin)
+            .args(args)
+            .envs(plugin_env(&Config::load()?)?)
+            .envs(blue_build_utils::tools::envs())
+            .exec();
+
+        Err(miette!("Failed to run plugin {}: {err}", plugin.display()))
+    }
+}
+
+/// Finds an executable named `bb-<name>` in the directories of `path`.
+fn find_plugin(name: &str, path: &OsStr) -> Option<PathBuf> {
+    env::split_paths(path)
+        .map(|dir| dir.join(format!("{PLUGIN_PREFIX}{name}")))
+        .find(|plugin| is_executable(plugin))
+}
+
+fn is_executable(path: &Path) -> bool {
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+/// The environment that describes the current
+/// project and drivers to the plugin.
+///
+/// Driver vars that are already set are left for the
+/// plugin to inherit, like `BB_BUILD_DRIVER=external:<name>`.
+/// Drivers that aren't set and can't be detected, like the
+/// build driver on a host without container tools, are left
+/// unset so that plugins that don't need them still run.
+fn plugin_env(config: &Config) -> Result<Vec<(&'static str, String)>> {
+    let mut vars = vec![("BB_VERSION", shadow::PKG_VERSION.to_owned())];
+
+    if let Some(bin) = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.to_str().map(ToOwned::to_owned))
+    {
+        vars.push(("BB_BIN", bin));
+    }
+
+    let recipe = Path::new(RECIPE_PATH).join(RECIPE_FILE);
+    if recipe.is_file() {
+        vars.push(("BB_RECIPE", recipe.display().to_string()));
+    }
+
+    let args = driver_args(config)?;
+    let drivers = [
+        (
+            BB_BUILD_DRIVER,
+            args.build_driver().map_or_else(
+                || BuildDriverType::detect().and_then(|driver| driver_name(&driver)),
+                DriverSelector::selector,
+            ),
+        ),
+        (
+            BB_INSPECT_DRIVER,
+            args.inspect_driver().map_or_else(
+                || InspectDriverType::detect().and_then(|driver| driver_name(&driver)),
+                DriverSelector::selector,
+            ),
+        ),
+        (
+            BB_SIGNING_DRIVER,
+            args.signing_driver().map_or_else(
+                || driver_name(&SigningDriverType::detect()),
+                DriverSelector::selector,
+            ),
+        ),
+        (
+            BB_RUN_DRIVER,
+            args.run_driver()
+                .or_else(RunDriverType::detect)
+                .and_then(|driver| driver_name(&driver)),
+        ),
+        (
+            BB_CI_DRIVER,
+            driver_name(&args.ci_driver().unwrap_or_else(CiDriverType::detect)),
+        ),
+    ];
+    vars.extend(
+        drivers
+            .into_iter()
+            .filter(|(var, _)| env::var_os(var).is_none())
+            .filter_map(|(var, driver)| Some((var, driver?))),
+    );
+
+    Ok(vars)
+}
+
+/// The driver args with the drivers set in the config as defaults.
+fn driver_args(config: &Config) -> Result<DriverArgs> {
+    let matches = config
+        .apply(DriverArgs::augment_args(clap::Command::new("plugin")))
+        .try_get_matches_from(["plugin"])
+        .into_diagnostic()?;
+    DriverArgs::from_arg_matches(&matches).into_diagnostic()
+}
+
+fn driver_name<T: ValueEnum>(driver: &T) -> Option<String> {
+    driver
+        .to_possible_value()
+        .map(|driver| driver.get_name().to_owned())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs, os::unix::fs::PermissionsExt, process::Command};
+
+    use blue_build_process_management::drivers::types::{BuildDriverType, DriverSelector};
+    use blue_build_utils::constants::{
+        BB_BUILD_DRIVER, BB_CI_DRIVER, BB_INSPECT_DRIVER, BB_RUN_DRIVER, BB_SIGNING_DRIVER,
+    };
+
+    use crate::config::Config;
+
+    use super::{driver_args, find_plugin, plugin_env};
+
+    const NO_TOOLS_ENV: &str = "BB_TEST_PLUGIN_NO_TOOLS";
+
+    #[test]
+    fn find_plugins() {
+        let first = tempfile::TempDir::new().unwrap();
+        let second = tempfile::TempDir::new().unwrap();
+
+        let not_executable = first.path().join("bb-hello");
+        fs::write(&not_executable, "").unwrap();
+
+        let plugin = second.path().join("bb-hello");
+        fs::write(&plugin, "#!/bin/sh\n").unwrap();
+        fs::set_permissions(&plugin, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let path = env::join_paths([first.path(), second.path()]).unwrap();
+
+        assert_eq!(find_plugin("hello", &path), Some(plugin));
+        assert_eq!(find_plugin("missing", &path), None);
+    }
+
+    #[test]
+    fn config_drivers() {
+        let config = Config::from_values(
+            "build-driver = \"podman\"\n[build]\nrun-driver = \"docker\"\n"
+                .parse()
+                .unwrap(),
+        );
+        let args = driver_args(&config).unwrap();
+
+        assert!(matches!(
+            args.build_driver(),
+            Some(DriverSelector::Driver(BuildDriverType::Podman))
+        ));
+        // Subcommand tables don't apply to plugins
+        assert!(args.run_driver().is_none());
+
+        let config = Config::from_values("build-driver = \"external:buildkit\"\n".parse().unwrap());
+        let args = driver_args(&config).unwrap();
+
+        assert_eq!(
+            args.build_driver()
+                .and_then(DriverSelector::selector)
+                .as_deref(),
+            Some("external:buildkit")
+        );
+    }
+
+    #[test]
+    fn env_without_container_tools() {
+        // PATH is process wide, so the test runs
+        // itself again in a process with an empty PATH
+        if env::var_os(NO_TOOLS_ENV).is_none() {
+            let path = tempfile::TempDir::new().unwrap();
+            let status = Command::new(env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "commands::plugin::test::env_without_container_tools",
+                ])
+                .env(NO_TOOLS_ENV, "1")
+                .env("PATH", path.path())
+                .env_remove(BB_BUILD_DRIVER)
+                .env_remove(BB_INSPECT_DRIVER)
+                .env_remove(BB_SIGNING_DRIVER)
+                .env_remove(BB_RUN_DRIVER)
+                .env_remove(BB_CI_DRIVER)
+                .status()
+                .unwrap();
+            assert!(status.success());
+            return;
+        }
+
+        let vars = plugin_env(&Config::default()).unwrap();
+        let var = |name: &str| {
+            vars.iter()
+                .find(|(var, _)| *var == name)
+                .map(|(_, value)| value.as_str())
+        };
+
+        assert_eq!(var(BB_BUILD_DRIVER), None);
+        assert_eq!(var(BB_RUN_DRIVER), None);
+        assert_eq!(var(BB_INSPECT_DRIVER), Some("oci-client"));
+        assert!(var(BB_SIGNING_DRIVER).is_some());
+    }
+}
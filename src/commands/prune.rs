@@ -4,6 +4,8 @@ use clap::Args;
 use colored::Colorize;
 use miette::bail;
 
+use crate::prompt;
+
 use super::BlueBuildCommand;
 
 #[derive(Debug, Args, Builder)]
@@ -30,7 +32,7 @@ pub struct PruneCommand {
 
 impl BlueBuildCommand for PruneCommand {
     fn try_run(&mut self) -> miette::Result<()> {
-        Driver::init(self.drivers);
+        Driver::init(self.drivers.clone());
 
         if !self.force {
             eprintln!(
@@ -57,6 +59,8 @@ impl BlueBuildCommand for PruneCommand {
                 },
             );
 
+            prompt::ensure_interactive("confirmation", "Use --force to prune without confirming")?;
+
             match requestty::prompt_one(
                 requestty::Question::confirm("anonymous")
                     .message("Are you sure you want to continue?")
@@ -0,0 +1,584 @@
+//! Cleans up the repos that builds push to, using the APIs of
+//! GitHub for `ghcr.io` and of GitLab for its registries.
+//!
+//! Deleting a tag only deletes its manifest, so the layers
+//! that it used are freed by the registry's own garbage
+//! collection afterwards.
+
+use std::{collections::HashSet, env};
+
+use blue_build_process_management::block_on;
+use blue_build_utils::constants::{
+    CI_API_V4_URL, CI_JOB_TOKEN, CI_REGISTRY, GITHUB_API_URL, GITHUB_TOKEN, GITLAB_TOKEN,
+};
+use bon::Builder;
+use chrono::{DateTime, TimeDelta, Utc};
+use clap::{Args, Subcommand};
+use log::{debug, info, trace};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize};
+
+use super::BlueBuildCommand;
+
+const PER_PAGE: u32 = 100;
+
+#[derive(Debug, Clone, Args)]
+pub struct RegistryCommand {
+    #[command(subcommand)]
+    command: RegistrySubcommand,
+}
+
+#[derive(Debug, Clone, Subcommand)]
+pub enum RegistrySubcommand {
+    /// Delete the stale tags of a repo.
+    ///
+    /// These are the layer caches pushed with `--cache-to`, like
+    /// the `cache` tag of docker, that haven't been pushed to
+    /// recently. The layer tags of podman and the signatures of
+    /// images that no longer exist are only deleted when asked for.
+    ///
+    /// Needs `GH_TOKEN` for ghcr.io, or `GITLAB_TOKEN`
+    /// or `CI_JOB_TOKEN` for GitLab.
+    Clean(RegistryCleanCommand),
+}
+
+impl BlueBuildCommand for RegistryCommand {
+    fn try_run(&mut self) -> Result<()> {
+        match &mut self.command {
+            RegistrySubcommand::Clean(command) => command.try_run(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct RegistryCleanCommand {
+    /// The repo to clean, like `ghcr.io/octocat/my-image`.
+    #[arg()]
+    #[builder(into)]
+    repo: String,
+
+    /// Only delete cache tags that haven't
+    /// been pushed in this many days.
+    #[arg(long, value_name = "DAYS", default_value_t = 7)]
+    #[builder(default = 7)]
+    older_than: u32,
+
+    /// Also delete the tags that are 64 hex digits, which is how
+    /// podman and buildah tag the layers they push with `--cache-to`.
+    ///
+    /// Only use this for a repo that is only used as a cache,
+    /// since images can be tagged like that too.
+    #[arg(long)]
+    #[builder(default)]
+    layer_tags: bool,
+
+    /// Also delete the signatures and attestations
+    /// of images that aren't in the repo anymore.
+    ///
+    /// Only supported on ghcr.io, since the GitLab API only lists
+    /// the images that have a tag, and the signatures of images
+    /// that are only pushed by digest would look orphaned.
+    #[arg(long)]
+    #[builder(default)]
+    orphaned_signatures: bool,
+
+    /// Show the tags that would be deleted
+    /// without deleting them.
+    #[arg(long)]
+    #[builder(default)]
+    dry_run: bool,
+
+    /// The URL of the API of the registry.
+    ///
+    /// Defaults to `GITHUB_API_URL` or the GitHub API for ghcr.io,
+    /// and to `CI_API_V4_URL` or the host of the registry without
+    /// its `registry.` prefix for GitLab.
+    #[arg(long)]
+    #[builder(into)]
+    api_url: Option<String>,
+}
+
+impl BlueBuildCommand for RegistryCleanCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("RegistryCleanCommand::try_run()");
+
+        let repo: Reference = self
+            .repo
+            .parse()
+            .into_diagnostic()
+            .with_context(|| format!("Failed to parse the repo {}", self.repo))?;
+        let api = block_on(RegistryApi::new(&repo, self.api_url.as_deref()))?;
+        if self.orphaned_signatures && !api.lists_untagged() {
+            bail!(
+                "Deleting orphaned signatures isn't supported for {}, since its API doesn't list untagged images",
+                self.repo
+            );
+        }
+        let versions = block_on(api.versions())?;
+        debug!("Found {} versions in {}", versions.len(), self.repo);
+
+        let cutoff = Utc::now() - TimeDelta::days(self.older_than.into());
+        let stale = stale_versions(&versions, cutoff, self.layer_tags, self.orphaned_signatures);
+
+        if stale.is_empty() {
+            info!("Found nothing to clean in {}", self.repo);
+            return Ok(());
+        }
+
+        for version in &stale {
+            let tags = version.tags.join(", ");
+            if self.dry_run {
+                info!("Would delete {tags} ({})", version.digest);
+            } else {
+                block_on(api.delete(version))?;
+                info!("Deleted {tags} ({})", version.digest);
+            }
+        }
+
+        let verb = if self.dry_run {
+            "Would delete"
+        } else {
+            "Deleted"
+        };
+        info!(
+            "{verb} {} tags from {}",
+            stale
+                .iter()
+                .map(|version| version.tags.len())
+                .sum::<usize>(),
+            self.repo
+        );
+        Ok(())
+    }
+}
+
+/// A manifest in a repo and its tags. GitHub deletes by
+/// manifest, while GitLab has one version per tag.
+#[derive(Debug, Clone)]
+struct Version {
+    id: String,
+    digest: String,
+    tags: Vec<String>,
+    updated: DateTime<Utc>,
+}
+
+/// The versions whose tags can all be deleted, which are cache tags
+/// that are older than `cutoff`, including the layer tags of podman
+/// if `layer_tags` is set, and if `orphaned_signatures` is set, the
+/// signatures of digests that aren't in the repo anymore.
+fn stale_versions(
+    versions: &[Version],
+    cutoff: DateTime<Utc>,
+    layer_tags: bool,
+    orphaned_signatures: bool,
+) -> Vec<&Version> {
+    let digests = versions
+        .iter()
+        .map(|version| version.digest.as_str())
+        .collect::<HashSet<_>>();
+
+    versions
+        .iter()
+        .filter(|version| {
+            !version.tags.is_empty()
+                && version.tags.iter().all(|tag| {
+                    ((is_cache_tag(tag) || (layer_tags && is_sha256_hex(tag)))
+                        && version.updated < cutoff)
+                        || (orphaned_signatures
+                            && signed_digest(tag)
+                                .is_some_and(|digest| !digests.contains(digest.as_str())))
+                })
+        })
+        .collect()
+}
+
+/// Whether the tag holds a layer cache, which is
+/// the `cache` tag of docker or a `<tag>-cache` tag.
+fn is_cache_tag(tag: &str) -> bool {
+    tag == "cache" || tag.ends_with("-cache")
+}
+
+/// The digest of the image that a signature tag,
+/// like `sha256-<hex>.sig`, is for.
+fn signed_digest(tag: &str) -> Option<String> {
+    let (hex, suffix) = tag.strip_prefix("sha256-")?.split_once('.')?;
+
+    (matches!(suffix, "sig" | "att" | "sbom") && is_sha256_hex(hex))
+        .then(|| format!("sha256:{hex}"))
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+fn parse_time(time: &str) -> Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to parse the time {time}"))
+        .map(|time| time.with_timezone(&Utc))
+}
+
+enum GitLabToken {
+    Private(String),
+    Job(String),
+}
+
+enum RegistryApi {
+    /// The url of the container package.
+    GitHub {
+        client: Client,
+        url: String,
+        token: String,
+    },
+
+    /// The url of the registry repository.
+    GitLab {
+        client: Client,
+        url: String,
+        token: GitLabToken,
+    },
+}
+
+impl RegistryApi {
+    async fn new(repo: &Reference, api_url: Option<&str>) -> Result<Self> {
+        let client = Client::builder()
+            .user_agent("bluebuild")
+            .build()
+            .into_diagnostic()?;
+        let registry = repo.resolve_registry();
+
+        if registry == "ghcr.io" {
+            Self::github(client, repo, api_url).await
+        } else if api_url.is_some()
+            || registry.contains("gitlab")
+            || env::var(CI_REGISTRY).is_ok_and(|ci_registry| ci_registry == registry)
+        {
+            Self::gitlab(client, repo, api_url).await
+        } else {
+            bail!(
+                help = "Pass the GitLab API with `--api-url` if this is a GitLab registry",
+                "Cleaning {registry} isn't supported, only ghcr.io and GitLab registries are"
+            )
+        }
+    }
+
+    async fn github(client: Client, repo: &Reference, api_url: Option<&str>) -> Result<Self> {
+        let token = env::var(GITHUB_TOKEN)
+            .map_err(|_| miette!("Set {GITHUB_TOKEN} to a token that can delete packages"))?;
+        let api_url = api_url
+            .map(ToOwned::to_owned)
+            .or_else(|| env::var(GITHUB_API_URL).ok())
+            .unwrap_or_else(|| "https://api.github.com".into());
+        let Some((owner, package)) = repo.repository().split_once('/') else {
+            bail!("Expected the repo to be ghcr.io/<owner>/<package>");
+        };
+        let package = urlencoding::encode(package);
+
+        // The package is owned by either an organization or a user
+        for kind in ["orgs", "users"] {
+            let url = format!(
+                "{}/{kind}/{owner}/packages/container/{package}",
+                api_url.trim_end_matches('/')
+            );
+            let response = client
+                .get(&url)
+                .bearer_auth(&token)
+                .send()
+                .await
+                .into_diagnostic()?;
+
+            if response.status() == StatusCode::NOT_FOUND {
+                continue;
+            }
+            response.error_for_status().into_diagnostic()?;
+
+            return Ok(Self::GitHub { client, url, token });
+        }
+        bail!("Failed to find the package {}", repo.repository())
+    }
+
+    async fn gitlab(client: Client, repo: &Reference, api_url: Option<&str>) -> Result<Self> {
+        #[derive(Deserialize)]
+        struct Repository {
+            id: u64,
+            path: String,
+        }
+
+        let token = env::var(GITLAB_TOKEN)
+            .map(GitLabToken::Private)
+            .or_else(|_| env::var(CI_JOB_TOKEN).map(GitLabToken::Job))
+            .map_err(|_| miette!("Set {GITLAB_TOKEN} or {CI_JOB_TOKEN} to clean the repo"))?;
+        let api_url = api_url
+            .map(ToOwned::to_owned)
+            .or_else(|| env::var(CI_API_V4_URL).ok())
+            .unwrap_or_else(|| {
+                let registry = repo.resolve_registry();
+                format!(
+                    "https://{}/api/v4",
+                    registry.strip_prefix("registry.").unwrap_or(registry)
+                )
+            });
+        let api_url = api_url.trim_end_matches('/');
+        let path = repo.repository();
+
+        // The repo is the project or a repo under it, so the
+        // project is the longest part of the path that exists
+        let mut project = path;
+        loop {
+            let url = format!(
+                "{api_url}/projects/{}/registry/repositories",
+                urlencoding::encode(project)
+            );
+            let repositories: Option<Vec<Repository>> =
+                get_pages(|page| token.auth(client.get(&url)).query(&page)).await?;
+
+            if let Some(repository) = repositories
+                .into_iter()
+                .flatten()
+                .find(|repository| repository.path == path)
+            {
+                return Ok(Self::GitLab {
+                    url: format!(
+                        "{api_url}/projects/{}/registry/repositories/{}",
+                        urlencoding::encode(project),
+                        repository.id
+                    ),
+                    client,
+                    token,
+                });
+            }
+
+            match project.rsplit_once('/') {
+                Some((parent, _)) => project = parent,
+                None => bail!("Failed to find the registry repository {path}"),
+            }
+        }
+    }
+
+    /// Whether the API lists the images that don't have a tag, which
+    /// is needed to tell that the image of a signature is gone.
+    const fn lists_untagged(&self) -> bool {
+        matches!(self, Self::GitHub { .. })
+    }
+
+    async fn versions(&self) -> Result<Vec<Version>> {
+        match self {
+            Self::GitHub { client, url, token } => {
+                #[derive(Deserialize)]
+                struct GitHubVersion {
+                    id: u64,
+                    name: String,
+                    updated_at: String,
+                    metadata: GitHubMetadata,
+                }
+
+                #[derive(Deserialize)]
+                struct GitHubMetadata {
+                    container: GitHubContainer,
+                }
+
+                #[derive(Deserialize)]
+                struct GitHubContainer {
+                    tags: Vec<String>,
+                }
+
+                let url = format!("{url}/versions");
+                let versions: Vec<GitHubVersion> =
+                    get_pages(|page| client.get(&url).bearer_auth(token).query(&page))
+                        .await?
+                        .unwrap_or_default();
+
+                versions
+                    .into_iter()
+                    .map(|version| {
+                        Ok(Version {
+                            id: version.id.to_string(),
+                            digest: version.name,
+                            tags: version.metadata.container.tags,
+                            updated: parse_time(&version.updated_at)?,
+                        })
+                    })
+                    .collect()
+            }
+            Self::GitLab { client, url, token } => {
+                #[derive(Deserialize)]
+                struct GitLabTag {
+                    name: String,
+                }
+
+                #[derive(Deserialize)]
+                struct GitLabTagDetails {
+                    digest: String,
+                    created_at: String,
+                }
+
+                let tags_url = format!("{url}/tags");
+                let tags: Vec<GitLabTag> =
+                    get_pages(|page| token.auth(client.get(&tags_url)).query(&page))
+                        .await?
+                        .unwrap_or_default();
+
+                let mut versions = Vec::with_capacity(tags.len());
+                for tag in tags {
+                    // The list of tags doesn't have their digests
+                    let details: GitLabTagDetails = token
+                        .auth(client.get(format!("{tags_url}/{}", urlencoding::encode(&tag.name))))
+                        .send()
+                        .await
+                        .and_then(reqwest::Response::error_for_status)
+                        .into_diagnostic()?
+                        .json()
+                        .await
+                        .into_diagnostic()?;
+
+                    versions.push(Version {
+                        id: tag.name.clone(),
+                        digest: details.digest,
+                        tags: vec![tag.name],
+                        updated: parse_time(&details.created_at)?,
+                    });
+                }
+                Ok(versions)
+            }
+        }
+    }
+
+    async fn delete(&self, version: &Version) -> Result<()> {
+        let request = match self {
+            Self::GitHub { client, url, token } => client
+                .delete(format!("{url}/versions/{}", version.id))
+                .bearer_auth(token),
+            Self::GitLab { client, url, token } => token
+                .auth(client.delete(format!("{url}/tags/{}", urlencoding::encode(&version.id)))),
+        };
+
+        request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to delete {}", version.tags.join(", ")))?;
+        Ok(())
+    }
+}
+
+impl GitLabToken {
+    fn auth(&self, request: RequestBuilder) -> RequestBuilder {
+        match self {
+            Self::Private(token) => request.header("PRIVATE-TOKEN", token),
+            Self::Job(token) => request.header("JOB-TOKEN", token),
+        }
+    }
+}
+
+/// Gets every page of a list, or `None` if the list doesn't exist.
+async fn get_pages<T, R>(request: R) -> Result<Option<Vec<T>>>
+where
+    T: DeserializeOwned,
+    R: Fn([(&'static str, u32); 2]) -> RequestBuilder,
+{
+    let mut items = Vec::new();
+
+    for page in 1.. {
+        let response = request([("per_page", PER_PAGE), ("page", page)])
+            .send()
+            .await
+            .into_diagnostic()?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let page_items: Vec<T> = response
+            .error_for_status()
+            .into_diagnostic()?
+            .json()
+            .await
+            .into_diagnostic()?;
+        let last = page_items.len() < PER_PAGE as usize;
+        items.extend(page_items);
+
+        if last {
+            break;
+        }
+    }
+    Ok(Some(items))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{DateTime, TimeDelta, Utc};
+    use rstest::rstest;
+
+    use super::{is_cache_tag, signed_digest, stale_versions, Version};
+
+    const IMAGE: &str = "sha256:1111111111111111111111111111111111111111111111111111111111111111";
+    const GONE: &str = "2222222222222222222222222222222222222222222222222222222222222222";
+
+    fn version(digest: &str, tags: &[&str], days_old: i64) -> Version {
+        Version {
+            id: digest.to_owned(),
+            digest: digest.to_owned(),
+            tags: tags.iter().map(ToString::to_string).collect(),
+            updated: Utc::now() - TimeDelta::days(days_old),
+        }
+    }
+
+    #[rstest]
+    #[case("cache", true)]
+    #[case("latest-cache", true)]
+    #[case(GONE, false)]
+    #[case("latest", false)]
+    #[case("42", false)]
+    fn cache_tags(#[case] tag: &str, #[case] expected: bool) {
+        assert_eq!(is_cache_tag(tag), expected);
+    }
+
+    #[test]
+    fn signature_tags() {
+        assert_eq!(
+            signed_digest(&format!("sha256-{GONE}.sig")),
+            Some(format!("sha256:{GONE}"))
+        );
+        assert_eq!(signed_digest(&format!("sha256-{GONE}.txt")), None);
+        assert_eq!(signed_digest("sha256-abc.sig"), None);
+    }
+
+    #[rstest]
+    #[case(false, false)]
+    #[case(true, false)]
+    #[case(false, true)]
+    #[case(true, true)]
+    fn stale(#[case] layer_tags: bool, #[case] orphaned_signatures: bool) {
+        let cutoff: DateTime<Utc> = Utc::now() - TimeDelta::days(7);
+        let orphan_sig = format!("sha256-{GONE}.sig");
+        let image_sig = format!("{}.sig", IMAGE.replace(':', "-"));
+        let versions = [
+            version(IMAGE, &["latest", "42"], 30),
+            version("sha256:a", &["cache"], 30),
+            version("sha256:b", &["old-cache"], 8),
+            version("sha256:c", &["new-cache"], 1),
+            version("sha256:d", &["latest-cache", "pinned"], 30),
+            version("sha256:e", &[&orphan_sig], 1),
+            version("sha256:f", &[&image_sig], 30),
+            version("sha256:g", &[], 30),
+            version("sha256:h", &[GONE], 30),
+        ];
+
+        let stale = stale_versions(&versions, cutoff, layer_tags, orphaned_signatures)
+            .into_iter()
+            .flat_map(|version| version.tags.clone())
+            .collect::<Vec<_>>();
+
+        let mut expected = vec!["cache".to_owned(), "old-cache".to_owned()];
+        if orphaned_signatures {
+            expected.push(orphan_sig);
+        }
+        if layer_tags {
+            expected.push(GONE.to_owned());
+        }
+        assert_eq!(stale, expected);
+    }
+}
@@ -0,0 +1,91 @@
+use blue_build_process_management::summary::BuildSummary;
+use clap::{Args, ValueEnum};
+use log::trace;
+use miette::{IntoDiagnostic, Result};
+use schemars::{schema_for, Schema};
+
+use super::{env::EnvReport, BlueBuildCommand};
+
+/// The commands with output that has a schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SchemaOutput {
+    /// The summary of a build that is reported to
+    /// the CI system and sent to notification webhooks.
+    Build,
+
+    /// The output of `bluebuild env --format json`.
+    Env,
+
+    /// The output of `bluebuild cache stats --format json`.
+    Cache,
+
+    /// The output of `bluebuild storage --format json`.
+    Storage,
+
+    /// The output of `bluebuild validate --format json`.
+    #[cfg(feature = "validate")]
+    Validate,
+}
+
+impl SchemaOutput {
+    /// The JSON Schema of the output.
+    #[must_use]
+    pub fn schema(self) -> Schema {
+        match self {
+            Self::Build => schema_for!(BuildSummary),
+            Self::Env => schema_for!(EnvReport),
+            Self::Cache => schema_for!(super::cache::CacheStats),
+            Self::Storage => schema_for!(super::storage::StorageReport),
+            #[cfg(feature = "validate")]
+            Self::Validate => schema_for!(super::validate::ValidationReport),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SchemaCommand {
+    /// The command to print the schema of the output of.
+    #[arg(value_enum)]
+    command: SchemaOutput,
+}
+
+impl BlueBuildCommand for SchemaCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("SchemaCommand::try_run()");
+
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&self.command.schema()).into_diagnostic()?
+        );
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::ValueEnum;
+    use rstest::rstest;
+
+    use super::SchemaOutput;
+
+    #[rstest]
+    #[case(SchemaOutput::Build, "BuildSummary", "images")]
+    #[case(SchemaOutput::Env, "EnvReport", "drivers")]
+    #[cfg_attr(
+        feature = "validate",
+        case(SchemaOutput::Validate, "ValidationReport", "diagnostics")
+    )]
+    fn schema(#[case] output: SchemaOutput, #[case] title: &str, #[case] property: &str) {
+        let schema = output.schema();
+
+        assert_eq!(schema.get("title").unwrap(), title);
+        assert!(schema.get("properties").unwrap().get(property).is_some());
+    }
+
+    #[test]
+    fn every_output_has_schema() {
+        for output in SchemaOutput::value_variants() {
+            assert!(output.schema().get("$schema").is_some());
+        }
+    }
+}
@@ -0,0 +1,447 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt::Write as _,
+};
+
+use blue_build_process_management::{
+    drivers::{types::LocalImage, BuildDriver, Driver, DriverArgs},
+    preflight::ByteSize,
+};
+use blue_build_utils::constants::BUILD_ID_LABEL;
+use bon::Builder;
+use clap::Args;
+use colored::Colorize;
+use log::{info, trace, warn};
+use miette::{bail, Result};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use super::BlueBuildCommand;
+use crate::{
+    labels,
+    output::{self, TableOutput},
+    prompt,
+};
+
+/// The length that image IDs are shortened to, the same as podman and docker.
+const SHORT_ID_LEN: usize = 12;
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct StorageCommand {
+    /// Remove the images that bluebuild built, and the
+    /// dangling images that they were built on top of.
+    #[arg(long)]
+    #[builder(default)]
+    prune: bool,
+
+    /// Show what would be removed without removing it.
+    #[arg(long, requires = "prune")]
+    #[builder(default)]
+    dry_run: bool,
+
+    /// Do not prompt for confirmation.
+    #[arg(short, long, requires = "prune")]
+    #[builder(default)]
+    force: bool,
+
+    #[clap(flatten)]
+    #[builder(default)]
+    drivers: DriverArgs,
+}
+
+impl BlueBuildCommand for StorageCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("StorageCommand::try_run()");
+
+        Driver::init(self.drivers.clone());
+
+        let images = Driver::list_local_images()?;
+
+        if !self.prune {
+            return output::print(&StorageReport::new(&images));
+        }
+
+        let prunable = prunable(&images);
+        if prunable.is_empty() {
+            info!("There are no images that bluebuild built");
+            return Ok(());
+        }
+
+        let size = ByteSize(prunable.iter().map(|image| image.size).sum());
+        if self.dry_run {
+            for image in &prunable {
+                info!("Would remove {}", image_name(image));
+            }
+            info!("Would remove {} images using {size}", prunable.len());
+            return Ok(());
+        }
+
+        if !self.force {
+            eprintln!(
+                "{} This will remove {} images using {size}:\n{}",
+                "WARNING!".bright_yellow(),
+                prunable.len(),
+                prunable
+                    .iter()
+                    .map(|image| format!(" - {}", image_name(image)))
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+            );
+
+            prompt::ensure_interactive("confirmation", "Use --force to prune without confirming")?;
+
+            match requestty::prompt_one(
+                requestty::Question::confirm("anonymous")
+                    .message("Are you sure you want to continue?")
+                    .default(false)
+                    .build(),
+            ) {
+                Err(e) => bail!("Canceled {e:?}"),
+                Ok(answer) => {
+                    if answer.as_bool().is_some_and(|a| !a) {
+                        return Ok(());
+                    }
+                }
+            }
+        }
+
+        // Images that are used by containers or that other images
+        // were built on top of are skipped instead of stopping
+        let mut removed = 0;
+        for image in &prunable {
+            let refs = if image.tags.is_empty() {
+                vec![image.id.clone()]
+            } else {
+                image.tags.clone()
+            };
+
+            match Driver::remove_local_images(&refs) {
+                Ok(()) => removed += 1,
+                Err(e) => warn!("{e}"),
+            }
+        }
+        info!("Removed {removed} of {} images", prunable.len());
+        Ok(())
+    }
+}
+
+/// How much storage the images that bluebuild built use.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct StorageReport {
+    /// The images grouped by the name of their recipe.
+    recipes: Vec<RecipeStorage>,
+
+    /// The total size of the images in bytes.
+    ///
+    /// Layers that images share are counted for each image.
+    size: u64,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct RecipeStorage {
+    recipe: String,
+    images: Vec<StoredImage>,
+
+    /// The total size of the images in bytes.
+    size: u64,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+struct StoredImage {
+    id: String,
+    tags: Vec<String>,
+    build_id: String,
+
+    /// The size of the image in bytes.
+    size: u64,
+    created: Option<String>,
+}
+
+impl StorageReport {
+    fn new(images: &[LocalImage]) -> Self {
+        let by_id = by_id(images);
+        let mut recipes = BTreeMap::<String, Vec<StoredImage>>::new();
+
+        for image in images {
+            let Some(build_id) = built_id(image, &by_id) else {
+                continue;
+            };
+            let recipe = image
+                .labels
+                .get(labels::TITLE)
+                .cloned()
+                .unwrap_or_else(|| "unknown".into());
+
+            recipes.entry(recipe).or_default().push(StoredImage {
+                id: short_id(&image.id).into(),
+                tags: image.tags.clone(),
+                build_id: build_id.clone(),
+                size: image.size,
+                created: image.created.clone(),
+            });
+        }
+
+        let recipes = recipes
+            .into_iter()
+            .map(|(recipe, mut images)| {
+                images.sort_by(|a, b| b.created.cmp(&a.created));
+                RecipeStorage {
+                    recipe,
+                    size: images.iter().map(|image| image.size).sum(),
+                    images,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        Self {
+            size: recipes.iter().map(|recipe| recipe.size).sum(),
+            recipes,
+        }
+    }
+}
+
+impl TableOutput for StorageReport {
+    fn to_table(&self) -> String {
+        let mut out = String::new();
+
+        if self.recipes.is_empty() {
+            return "There are no images that bluebuild built\n".into();
+        }
+
+        for recipe in &self.recipes {
+            let _ = writeln!(
+                out,
+                "{} ({} images, {})",
+                recipe.recipe.bold(),
+                recipe.images.len(),
+                ByteSize(recipe.size)
+            );
+            for image in &recipe.images {
+                let _ = writeln!(
+                    out,
+                    "  {}  {:>10}  {}",
+                    image.id.dimmed(),
+                    ByteSize(image.size).to_string(),
+                    if image.tags.is_empty() {
+                        "<none>".to_owned()
+                    } else {
+                        image.tags.join(", ")
+                    }
+                );
+            }
+        }
+        let _ = writeln!(out, "{} {}", "Total:".bold(), ByteSize(self.size));
+        out
+    }
+}
+
+fn short_id(id: &str) -> &str {
+    let id = id.strip_prefix("sha256:").unwrap_or(id);
+    id.get(..SHORT_ID_LEN).unwrap_or(id)
+}
+
+fn image_name(image: &LocalImage) -> String {
+    if image.tags.is_empty() {
+        short_id(&image.id).into()
+    } else {
+        image.tags.join(", ")
+    }
+}
+
+fn by_id(images: &[LocalImage]) -> BTreeMap<&str, &LocalImage> {
+    images
+        .iter()
+        .map(|image| (image.id.as_str(), image))
+        .collect()
+}
+
+/// The build ID of an image that bluebuild built.
+///
+/// Images that are built on top of an image that bluebuild built
+/// inherit its build ID label, so an image only counts as built
+/// when its build ID isn't the same as the one of its parent.
+fn built_id<'a>(image: &'a LocalImage, by_id: &BTreeMap<&str, &LocalImage>) -> Option<&'a String> {
+    let build_id = image.labels.get(BUILD_ID_LABEL)?;
+    let parent_build_id = image
+        .parent
+        .as_deref()
+        .and_then(|parent| by_id.get(parent))
+        .and_then(|parent| parent.labels.get(BUILD_ID_LABEL));
+
+    (parent_build_id != Some(build_id)).then_some(build_id)
+}
+
+/// The images that bluebuild built, and the dangling images that
+/// would be left only for them, in the order to remove them in.
+/// An image always comes before the image it was built on top of.
+fn prunable(images: &[LocalImage]) -> Vec<&LocalImage> {
+    let by_id = by_id(images);
+    let mut children = BTreeMap::<&str, Vec<&str>>::new();
+    for image in images {
+        if let Some(parent) = image.parent.as_deref() {
+            children.entry(parent).or_default().push(&image.id);
+        }
+    }
+    let children = |id: &str| children.get(id).into_iter().flatten().copied();
+
+    let mut prune = images
+        .iter()
+        .filter(|image| built_id(image, &by_id).is_some())
+        .map(|image| image.id.as_str())
+        .collect::<HashSet<_>>();
+
+    // A dangling parent is only removed once every
+    // image built on top of it is being removed
+    loop {
+        let parents = prune
+            .iter()
+            .filter_map(|id| by_id.get(id)?.parent.as_deref())
+            .filter(|parent| {
+                !prune.contains(parent)
+                    && by_id.get(parent).is_some_and(|image| image.tags.is_empty())
+                    && children(parent).all(|child| prune.contains(child))
+            })
+            .collect::<Vec<_>>();
+
+        if parents.is_empty() {
+            break;
+        }
+        prune.extend(parents);
+    }
+
+    let mut ordered = Vec::with_capacity(prune.len());
+    while !prune.is_empty() {
+        let leaves = prune
+            .iter()
+            .copied()
+            .filter(|id| children(id).all(|child| !prune.contains(child)))
+            .collect::<Vec<_>>();
+
+        // Parents can't form a cycle unless the driver is broken
+        if leaves.is_empty() {
+            break;
+        }
+        for id in leaves {
+            prune.remove(id);
+            ordered.extend(by_id.get(id));
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use blue_build_process_management::drivers::types::LocalImage;
+    use blue_build_utils::constants::BUILD_ID_LABEL;
+
+    use super::{prunable, StorageReport};
+    use crate::labels;
+
+    fn image(id: &str, parent: Option<&str>, tags: &[&str], recipe: Option<&str>) -> LocalImage {
+        built_image(id, parent, tags, recipe.map(|recipe| (recipe, "1234")))
+    }
+
+    fn built_image(
+        id: &str,
+        parent: Option<&str>,
+        tags: &[&str],
+        build: Option<(&str, &str)>,
+    ) -> LocalImage {
+        LocalImage {
+            id: id.into(),
+            tags: tags.iter().map(|&tag| tag.into()).collect(),
+            size: 100,
+            created: None,
+            parent: parent.map(Into::into),
+            labels: build
+                .map(|(recipe, build_id)| {
+                    BTreeMap::from([
+                        (BUILD_ID_LABEL.to_owned(), build_id.to_owned()),
+                        (labels::TITLE.to_owned(), recipe.to_owned()),
+                    ])
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    fn images() -> Vec<LocalImage> {
+        vec![
+            image("base", None, &["quay.io/fedora/fedora:41"], None),
+            image("stage", Some("base"), &[], None),
+            image("shared", Some("base"), &[], None),
+            image("other", Some("shared"), &["localhost/other"], None),
+            image("a1", Some("stage"), &["localhost/a:latest"], Some("a")),
+            image("a2", Some("shared"), &[], Some("a")),
+            image(
+                "b1",
+                None,
+                &["localhost/b:41", "localhost/b:latest"],
+                Some("b"),
+            ),
+        ]
+    }
+
+    #[test]
+    fn prunes_built_images_and_dangling_parents() {
+        let images = images();
+        let ids = prunable(&images)
+            .into_iter()
+            .map(|image| image.id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids.len(), 4);
+        for id in ["a1", "a2", "b1", "stage"] {
+            assert!(ids.contains(&id), "{id} should be pruned");
+        }
+        let position = |id| ids.iter().position(|other| *other == id);
+        assert!(position("a1") < position("stage"));
+    }
+
+    #[test]
+    fn skips_inherited_build_ids() {
+        let images = vec![
+            built_image("a", None, &["localhost/a:latest"], Some(("a", "1234"))),
+            built_image("step", Some("a"), &[], Some(("a", "1234"))),
+            built_image(
+                "derived",
+                Some("step"),
+                &["localhost/derived"],
+                Some(("a", "1234")),
+            ),
+            built_image("b", Some("step"), &["localhost/b"], Some(("b", "5678"))),
+        ];
+        let mut ids = prunable(&images)
+            .into_iter()
+            .map(|image| image.id.as_str())
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+
+        // `derived` was built on top of `a` without bluebuild
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(
+            StorageReport::new(&images)
+                .recipes
+                .iter()
+                .map(|recipe| recipe.images.len())
+                .sum::<usize>(),
+            2
+        );
+    }
+
+    #[test]
+    fn groups_by_recipe() {
+        let report = StorageReport::new(&images());
+
+        assert_eq!(report.size, 300);
+        assert_eq!(
+            report
+                .recipes
+                .iter()
+                .map(|recipe| (recipe.recipe.as_str(), recipe.images.len(), recipe.size))
+                .collect::<Vec<_>>(),
+            [("a", 2, 200), ("b", 1, 100)]
+        );
+    }
+}
@@ -10,7 +10,9 @@ use blue_build_process_management::{
 use blue_build_recipe::Recipe;
 use blue_build_utils::{
     cmd,
-    constants::{ARCHIVE_SUFFIX, LOCAL_BUILD, OCI_ARCHIVE, OSTREE_UNVERIFIED_IMAGE},
+    constants::{
+        ARCHIVE_SUFFIX, LOCAL_BUILD, OCI_ARCHIVE, OSTREE_IMAGE_SIGNED, OSTREE_UNVERIFIED_IMAGE,
+    },
 };
 use bon::Builder;
 use clap::Args;
@@ -20,10 +22,14 @@ use log::{debug, trace, warn};
 use miette::{bail, IntoDiagnostic, Result};
 use tempfile::TempDir;
 
-use crate::{commands::build::BuildCommand, rpm_ostree_status::RpmOstreeStatus};
+use crate::{
+    build_lock::BuildLock, commands::build::BuildCommand, rpm_ostree_status::RpmOstreeStatus,
+};
 
 use super::BlueBuildCommand;
 
+mod signing;
+
 #[derive(Default, Clone, Debug, Builder, Args)]
 pub struct SwitchCommand {
     /// The recipe file to build an image.
@@ -41,6 +47,23 @@ pub struct SwitchCommand {
     #[arg(long)]
     tempdir: Option<PathBuf>,
 
+    /// Configure the system to verify the images of this repo,
+    /// like `ghcr.io/octocat/my-image`, with the `cosign.pub`
+    /// of your repo.
+    ///
+    /// The local build is then switched to with an
+    /// `ostree-image-signed` ref, so that the system keeps
+    /// verifying the updates it gets from the repo.
+    #[arg(long, value_name = "REPO")]
+    #[builder(into)]
+    signed_repo: Option<String>,
+
+    /// Wait for another switch or build of this
+    /// directory to finish instead of failing.
+    #[arg(long)]
+    #[builder(default)]
+    wait: bool,
+
     #[clap(flatten)]
     #[builder(default)]
     drivers: DriverArgs,
@@ -50,13 +73,25 @@ impl BlueBuildCommand for SwitchCommand {
     fn try_run(&mut self) -> Result<()> {
         trace!("SwitchCommand::try_run()");
 
-        Driver::init(self.drivers);
+        let _lock = BuildLock::local_build(self.wait)?;
+        Driver::init(self.drivers.clone());
 
         let status = RpmOstreeStatus::try_new()?;
         trace!("{status:?}");
 
-        if status.transaction_in_progress() {
-            bail!("There is a transaction in progress. Please cancel it using `rpm-ostree cancel`");
+        if let Some(transaction) = status.transaction() {
+            bail!(
+                "There is a transaction {} in progress{}. Please cancel it using `rpm-ostree cancel`",
+                transaction.method,
+                transaction
+                    .initiator
+                    .map(|initiator| format!(" started by {initiator}"))
+                    .unwrap_or_default(),
+            );
+        }
+
+        if let Some(repo) = self.signed_repo.as_deref() {
+            signing::check(repo)?;
         }
 
         let tempdir = if let Some(ref dir) = self.tempdir {
@@ -71,6 +106,7 @@ impl BlueBuildCommand for SwitchCommand {
             .recipe([self.recipe.clone()])
             .archive(tempdir.path())
             .maybe_tempdir(self.tempdir.clone())
+            .wait(self.wait)
             .build()
             .try_run()?;
         #[cfg(not(feature = "multi-recipe"))]
@@ -78,14 +114,13 @@ impl BlueBuildCommand for SwitchCommand {
             .recipe(self.recipe.clone())
             .archive(tempdir.path())
             .maybe_tempdir(self.tempdir.clone())
+            .wait(self.wait)
             .build()
             .try_run()?;
 
         let recipe = Recipe::parse(&self.recipe)?;
-        let image_file_name = format!(
-            "{}.{ARCHIVE_SUFFIX}",
-            recipe.name.to_lowercase().replace('/', "_")
-        );
+        let image_name = recipe.name.to_lowercase().replace('/', "_");
+        let image_file_name = format!("{image_name}.{ARCHIVE_SUFFIX}");
         let temp_file_path = tempdir.path().join(&image_file_name);
         let archive_path = Path::new(LOCAL_BUILD).join(&image_file_name);
 
@@ -96,6 +131,9 @@ impl BlueBuildCommand for SwitchCommand {
             notice = "NOTICE".bright_red().bold(),
             sudo = "`sudo`.".italic().bright_red().bold(),
         );
+        if let Some(repo) = self.signed_repo.as_deref() {
+            signing::configure(repo, &image_name)?;
+        }
         Self::sudo_clean_local_build_dir()?;
         Self::sudo_move_archive(&temp_file_path, &archive_path)?;
 
@@ -127,7 +165,12 @@ impl SwitchCommand {
             command
         } else {
             let image_ref = format!(
-                "{OSTREE_UNVERIFIED_IMAGE}:{OCI_ARCHIVE}:{path}",
+                "{transport}:{OCI_ARCHIVE}:{path}",
+                transport = if self.signed_repo.is_some() {
+                    OSTREE_IMAGE_SIGNED
+                } else {
+                    OSTREE_UNVERIFIED_IMAGE
+                },
                 path = archive_path.display()
             );
 
@@ -140,7 +183,7 @@ impl SwitchCommand {
             trace!("{command:?}");
             command
         }
-        .build_status(
+        .boot_status(
             format!("{}", archive_path.display()),
             "Switching to new image",
         )
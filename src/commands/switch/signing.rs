@@ -0,0 +1,175 @@
+//! Configuration of the signature policy of the system so that
+//! a system that was switched to a local build still verifies
+//! the updates it gets from the registry.
+//!
+//! The public key of the repo is installed in `/etc/pki/containers`
+//! and the policy requires the images of the repo to be signed with
+//! it. The archives in the local build directory can't be signed, so
+//! the policy accepts them since they were built on the machine.
+
+use std::{fs, io::Write, path::Path};
+
+use blue_build_template::{QuadletRegistriesTemplate, Template};
+use blue_build_utils::{
+    cmd,
+    constants::{COSIGN_PUB_PATH, LOCAL_BUILD},
+};
+use log::{debug, info, trace};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+use serde_json::{json, Map, Value};
+use tempfile::NamedTempFile;
+
+const POLICY_PATH: &str = "/etc/containers/policy.json";
+const REGISTRIES_D_DIR: &str = "/etc/containers/registries.d";
+const KEYS_DIR: &str = "/etc/pki/containers";
+
+/// Checks that the images of `repo` can be verified
+/// before anything is built.
+///
+/// # Errors
+/// Will error if the repo isn't a valid image
+/// repo or the repo doesn't have a `cosign.pub`.
+pub fn check(repo: &str) -> Result<()> {
+    repo.parse::<Reference>().into_diagnostic()?;
+
+    if !Path::new(COSIGN_PUB_PATH).exists() {
+        bail!("A {COSIGN_PUB_PATH} is needed to verify the images of {repo}");
+    }
+    Ok(())
+}
+
+/// Installs the public key of the repo and adds the
+/// images of `repo` to the signature policy of the system.
+///
+/// # Errors
+/// Will error if the repo doesn't have a `cosign.pub`
+/// or the files can't be installed.
+pub fn configure(repo: &str, name: &str) -> Result<()> {
+    trace!("signing::configure({repo}, {name})");
+
+    let image: Reference = repo.parse().into_diagnostic()?;
+    let repo = format!("{}/{}", image.registry(), image.repository());
+
+    let public_key = fs::read(COSIGN_PUB_PATH)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to read {COSIGN_PUB_PATH} to verify {repo} with"))?;
+    let key_path = format!("{KEYS_DIR}/{name}.pub");
+
+    let mut policy = if Path::new(POLICY_PATH).exists() {
+        serde_json::from_slice(&fs::read(POLICY_PATH).into_diagnostic()?)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to parse {POLICY_PATH}"))?
+    } else {
+        json!({ "default": [{ "type": "insecureAcceptAnything" }] })
+    };
+    add_to_policy(&mut policy, &repo, &key_path)?;
+
+    let registries = QuadletRegistriesTemplate::builder()
+        .repo(&repo)
+        .build()
+        .render()
+        .into_diagnostic()?;
+
+    info!("Configuring the system to verify the signatures of {repo}");
+    sudo_install(&public_key, &key_path)?;
+    sudo_install(
+        &serde_json::to_vec_pretty(&policy).into_diagnostic()?,
+        POLICY_PATH,
+    )?;
+    sudo_install(
+        registries.as_bytes(),
+        &format!("{REGISTRIES_D_DIR}/{name}.yaml"),
+    )?;
+
+    Ok(())
+}
+
+/// Requires the images of `repo` to be signed with the key at
+/// `key_path` and accepts the archives of local builds.
+fn add_to_policy(policy: &mut Value, repo: &str, key_path: &str) -> Result<()> {
+    transport(policy, "docker")?.insert(
+        repo.to_owned(),
+        json!([{
+            "type": "sigstoreSigned",
+            "keyPath": key_path,
+            "signedIdentity": { "type": "matchRepository" },
+        }]),
+    );
+    transport(policy, "oci-archive")?.insert(
+        LOCAL_BUILD.to_owned(),
+        json!([{ "type": "insecureAcceptAnything" }]),
+    );
+
+    Ok(())
+}
+
+/// Gets the scopes of a transport of the policy, adding it if needed.
+fn transport<'a>(policy: &'a mut Value, name: &str) -> Result<&'a mut Map<String, Value>> {
+    policy
+        .as_object_mut()
+        .ok_or_else(|| miette!("The policy in {POLICY_PATH} must be an object"))?
+        .entry("transports")
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| miette!("The transports of {POLICY_PATH} must be an object"))?
+        .entry(name)
+        .or_insert_with(|| json!({}))
+        .as_object_mut()
+        .ok_or_else(|| miette!("The {name} transport of {POLICY_PATH} must be an object"))
+}
+
+fn sudo_install(contents: &[u8], dest: &str) -> Result<()> {
+    let mut file = NamedTempFile::new().into_diagnostic()?;
+    file.write_all(contents).into_diagnostic()?;
+
+    debug!("Installing {dest}");
+    let mut command = cmd!("sudo", "install", "-D", "-m", "0644", file.path(), dest);
+    trace!("{command:?}");
+
+    if !command.status().into_diagnostic()?.success() {
+        bail!("Failed to install {dest}");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::add_to_policy;
+
+    #[test]
+    fn add_repo_to_policy() {
+        let mut policy = json!({
+            "default": [{ "type": "insecureAcceptAnything" }],
+            "transports": {
+                "docker": {
+                    "ghcr.io/octocat/other": [{ "type": "reject" }]
+                }
+            }
+        });
+
+        add_to_policy(
+            &mut policy,
+            "ghcr.io/octocat/my-image",
+            "/etc/pki/containers/my-image.pub",
+        )
+        .unwrap();
+
+        assert_eq!(
+            policy["transports"]["docker"]["ghcr.io/octocat/other"][0]["type"],
+            "reject"
+        );
+        assert_eq!(
+            policy["transports"]["docker"]["ghcr.io/octocat/my-image"][0]["keyPath"],
+            "/etc/pki/containers/my-image.pub"
+        );
+        assert_eq!(
+            policy["transports"]["oci-archive"]["/etc/bluebuild"][0]["type"],
+            "insecureAcceptAnything"
+        );
+
+        assert!(add_to_policy(&mut json!([]), "ghcr.io/octocat/my-image", "key.pub").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::{fs, path::PathBuf};
+
+use blue_build_process_management::drivers::{Driver, DriverArgs};
+use blue_build_recipe::Recipe;
+use bon::Builder;
+use clap::Args;
+use log::{debug, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+
+use super::BlueBuildCommand;
+use crate::image_tests::{self, BootedContainer, TestCase, TestTarget};
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct TestCommand {
+    /// The image to test, which is pulled if
+    /// it isn't in local storage.
+    #[builder(into)]
+    image: String,
+
+    /// Run the `tests` of this recipe.
+    #[arg(short, long)]
+    #[builder(into)]
+    recipe: Option<PathBuf>,
+
+    /// Run a script in the image with `sh`,
+    /// which passes if it exits successfully.
+    ///
+    /// Can be used more than once.
+    #[arg(short, long = "script", value_name = "PATH")]
+    #[builder(default)]
+    scripts: Vec<PathBuf>,
+
+    /// Boot the image with systemd before running the tests,
+    /// so that they can check the services that are running.
+    ///
+    /// NOTE: Requires podman.
+    #[arg(long)]
+    #[builder(default)]
+    boot: bool,
+
+    /// Write a JUnit report of the tests to this file.
+    #[arg(long, value_name = "PATH")]
+    #[builder(into)]
+    junit: Option<PathBuf>,
+
+    #[clap(flatten)]
+    #[builder(default)]
+    drivers: DriverArgs,
+}
+
+impl BlueBuildCommand for TestCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("TestCommand::try_run()");
+
+        let image: Reference = self.image.parse().into_diagnostic()?;
+        let mut tests = self
+            .recipe
+            .as_ref()
+            .map(Recipe::parse)
+            .transpose()?
+            .map(|recipe| recipe.tests.iter().map(TestCase::from).collect::<Vec<_>>())
+            .unwrap_or_default();
+        for script in &self.scripts {
+            tests.push(TestCase::script(script)?);
+        }
+
+        if tests.is_empty() {
+            bail!(
+                help = "Add `tests` to the recipe or pass scripts with `--script`",
+                "There are no tests to run"
+            );
+        }
+
+        Driver::init(self.drivers.clone());
+
+        let booted = self
+            .boot
+            .then(|| BootedContainer::boot(&image))
+            .transpose()?;
+        let target = booted
+            .as_ref()
+            .map_or(TestTarget::Image(&image), TestTarget::Booted);
+
+        let outcomes = image_tests::run(target, &tests);
+        drop(booted);
+
+        if let Some(junit) = &self.junit {
+            debug!("Writing the JUnit report to {}", junit.display());
+            fs::write(junit, image_tests::junit_report(&self.image, &outcomes))
+                .into_diagnostic()
+                .with_context(|| format!("Failed to write {}", junit.display()))?;
+        }
+
+        image_tests::ensure_passed(&image, &outcomes)
+    }
+}
@@ -0,0 +1,408 @@
+//! Keeps a feed of the builds of an image, with the latest
+//! digest and version of each of its tags.
+//!
+//! The feed is a JSON file and an RSS file that can be published
+//! on a static site like GitHub Pages, so that update notifiers
+//! can poll it instead of the registry.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_process_management::drivers::{
+    opts::GetMetadataOpts, types::Platform, CiDriver, Driver, DriverArgs, InspectDriver,
+};
+use blue_build_recipe::Recipe;
+use blue_build_utils::{
+    constants::{
+        BB_REGISTRY, BB_REGISTRY_NAMESPACE, IMAGE_VERSION_LABEL, RECIPE_FILE, RECIPE_PATH,
+    },
+    escape_xml,
+};
+use bon::Builder;
+use chrono::{DateTime, SecondsFormat, Utc};
+use clap::Args;
+use log::{debug, info, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+use serde::{Deserialize, Serialize};
+
+use super::{build, BlueBuildCommand};
+
+const FEED_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Args, Builder)]
+pub struct UpdateFeedCommand {
+    /// The recipe of the image.
+    #[arg()]
+    #[builder(into)]
+    recipe: Option<PathBuf>,
+
+    /// The tags to follow, which each get their own stream of builds.
+    ///
+    /// Defaults to the `alt-tags` of the recipe, or `latest`.
+    #[arg(short, long = "tag")]
+    #[builder(default, into)]
+    tags: Vec<String>,
+
+    /// The image repo, like `ghcr.io/octocat/my-image`.
+    ///
+    /// This is taken from the recipe by default.
+    #[arg(long)]
+    #[builder(into)]
+    image: Option<String>,
+
+    /// The registry that the images are pushed to.
+    #[arg(long, env = BB_REGISTRY)]
+    #[builder(into)]
+    registry: Option<String>,
+
+    /// The url path to your base
+    /// project images.
+    #[arg(long, env = BB_REGISTRY_NAMESPACE, visible_alias("registry-path"))]
+    #[builder(into)]
+    registry_namespace: Option<String>,
+
+    /// The directory to write `<name>.json` and `<name>.xml` to.
+    #[arg(short, long, default_value = "feed")]
+    #[builder(default = PathBuf::from("feed"), into)]
+    output_dir: PathBuf,
+
+    /// The url that the feed is published at,
+    /// which the RSS feed links to.
+    #[arg(long)]
+    #[builder(into)]
+    link: Option<String>,
+
+    /// The changes in this build, which are added
+    /// to the new entries of the feed.
+    #[arg(long)]
+    #[builder(into)]
+    changelog: Option<String>,
+
+    /// The number of builds to keep for each tag.
+    #[arg(long, default_value_t = 20)]
+    #[builder(default = 20)]
+    max_entries: usize,
+
+    #[clap(flatten)]
+    #[builder(default)]
+    drivers: DriverArgs,
+}
+
+/// The builds of an image for each of its tags.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Feed {
+    version: u32,
+    image: String,
+
+    /// When the feed last changed, in RFC 3339.
+    updated: String,
+
+    /// The builds of each tag, starting with the latest.
+    streams: BTreeMap<String, Vec<FeedEntry>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FeedEntry {
+    digest: String,
+
+    /// The version of the image from its labels.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
+
+    /// When the build was added to the feed, in RFC 3339.
+    published: String,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    changelog: Option<String>,
+}
+
+impl BlueBuildCommand for UpdateFeedCommand {
+    fn try_run(&mut self) -> Result<()> {
+        trace!("UpdateFeedCommand::try_run()");
+
+        Driver::init(self.drivers.clone());
+
+        let recipe_path = self
+            .recipe
+            .clone()
+            .unwrap_or_else(|| Path::new(RECIPE_PATH).join(RECIPE_FILE));
+        let recipe = Recipe::parse(&recipe_path)?;
+        let name = recipe.name.trim().replace('/', "-");
+        let image = self.image(&recipe)?;
+
+        let tags = if !self.tags.is_empty() {
+            self.tags.clone()
+        } else if let Some(alt_tags) = recipe.matching_alt_tags(
+            Driver::get_branch().as_deref(),
+            &Platform::default().to_string(),
+        ) {
+            alt_tags
+        } else {
+            vec!["latest".into()]
+        };
+
+        let json_path = self.output_dir.join(format!("{name}.json"));
+        let mut feed = Feed::load(&json_path, &image)?;
+        let now = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+
+        let mut changed = false;
+        for tag in &tags {
+            let entry = self.latest_entry(&image, tag, &now)?;
+            changed |= feed.add(tag, entry, self.max_entries);
+        }
+
+        if !changed {
+            info!("The feed of {image} is up to date");
+            return Ok(());
+        }
+        feed.updated = now;
+
+        fs::create_dir_all(&self.output_dir).into_diagnostic()?;
+        fs::write(
+            &json_path,
+            serde_json::to_string_pretty(&feed).into_diagnostic()? + "\n",
+        )
+        .into_diagnostic()
+        .with_context(|| format!("Failed to write {}", json_path.display()))?;
+
+        let rss_path = self.output_dir.join(format!("{name}.xml"));
+        fs::write(&rss_path, feed.to_rss(self.link.as_deref()))
+            .into_diagnostic()
+            .with_context(|| format!("Failed to write {}", rss_path.display()))?;
+
+        info!(
+            "Updated the feed of {image} in {} and {}",
+            json_path.display(),
+            rss_path.display()
+        );
+        Ok(())
+    }
+}
+
+impl UpdateFeedCommand {
+    fn image(&self, recipe: &Recipe) -> Result<String> {
+        if let Some(image) = &self.image {
+            return Ok(image.trim().to_lowercase());
+        }
+
+        build::image_name(
+            recipe,
+            self.registry.as_deref(),
+            self.registry_namespace.as_deref(),
+        )
+    }
+
+    /// Gets the build that a tag of the image currently points to.
+    fn latest_entry(&self, image: &str, tag: &str, now: &str) -> Result<FeedEntry> {
+        let image_ref: Reference = format!("{image}:{tag}").parse().into_diagnostic()?;
+        debug!("Getting the latest build of {image_ref}");
+
+        let metadata = Driver::get_metadata(&GetMetadataOpts::builder().image(&image_ref).build())
+            .with_context(|| format!("Failed to get the latest build of {image_ref}"))?;
+
+        Ok(FeedEntry {
+            version: metadata
+                .labels
+                .get(IMAGE_VERSION_LABEL)
+                .and_then(serde_json::Value::as_str)
+                .map(ToOwned::to_owned)
+                .or_else(|| metadata.get_version().map(|version| version.to_string())),
+            digest: metadata.digest,
+            published: now.to_owned(),
+            changelog: self.changelog.clone(),
+        })
+    }
+}
+
+impl Feed {
+    /// Reads the feed, or starts a new one if it doesn't exist yet.
+    fn load(path: &Path, image: &str) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self {
+                version: FEED_VERSION,
+                image: image.to_owned(),
+                updated: String::new(),
+                streams: BTreeMap::new(),
+            });
+        }
+
+        let feed: Self = serde_json::from_str(&fs::read_to_string(path).into_diagnostic()?)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to parse the feed {}", path.display()))?;
+
+        if feed.version != FEED_VERSION {
+            bail!(
+                "The feed {} has version {}, but only version {FEED_VERSION} is supported",
+                path.display(),
+                feed.version
+            );
+        }
+        if feed.image != image {
+            bail!(
+                "The feed {} is for {}, not {image}",
+                path.display(),
+                feed.image
+            );
+        }
+        Ok(feed)
+    }
+
+    /// Adds a build to the stream of a tag unless it's already the
+    /// latest one, and returns whether it was added.
+    fn add(&mut self, tag: &str, entry: FeedEntry, max_entries: usize) -> bool {
+        let stream = self.streams.entry(tag.to_owned()).or_default();
+
+        if stream
+            .first()
+            .is_some_and(|latest| latest.digest == entry.digest)
+        {
+            return false;
+        }
+        stream.insert(0, entry);
+        stream.truncate(max_entries.max(1));
+        true
+    }
+
+    fn to_rss(&self, link: Option<&str>) -> String {
+        let image = escape_xml(&self.image);
+        let link = escape_xml(link.unwrap_or(&format!("https://{}", self.image)));
+
+        let mut items = self
+            .streams
+            .iter()
+            .flat_map(|(tag, stream)| stream.iter().map(move |entry| (tag, entry)))
+            .collect::<Vec<_>>();
+        items.sort_by(|(_, a), (_, b)| b.published.cmp(&a.published));
+
+        let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str("<rss version=\"2.0\">\n  <channel>\n");
+        _ = writeln!(out, "    <title>{image}</title>");
+        _ = writeln!(out, "    <link>{link}</link>");
+        _ = writeln!(out, "    <description>Builds of {image}</description>");
+        if let Some(updated) = rfc2822(&self.updated) {
+            _ = writeln!(out, "    <lastBuildDate>{updated}</lastBuildDate>");
+        }
+
+        for (tag, entry) in items {
+            let tag = escape_xml(tag);
+            out.push_str("    <item>\n");
+            _ = writeln!(
+                out,
+                "      <title>{image}:{tag}{}</title>",
+                entry
+                    .version
+                    .as_deref()
+                    .map(|version| format!(" {}", escape_xml(version)))
+                    .unwrap_or_default()
+            );
+            _ = writeln!(
+                out,
+                "      <guid isPermaLink=\"false\">{image}:{tag}@{}</guid>",
+                escape_xml(&entry.digest)
+            );
+            if let Some(published) = rfc2822(&entry.published) {
+                _ = writeln!(out, "      <pubDate>{published}</pubDate>");
+            }
+            if let Some(changelog) = &entry.changelog {
+                _ = writeln!(
+                    out,
+                    "      <description>{}</description>",
+                    escape_xml(changelog)
+                );
+            }
+            out.push_str("    </item>\n");
+        }
+
+        out.push_str("  </channel>\n</rss>\n");
+        out
+    }
+}
+
+fn rfc2822(rfc3339: &str) -> Option<String> {
+    DateTime::parse_from_rfc3339(rfc3339)
+        .ok()
+        .map(|date| date.to_rfc2822())
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use super::{Feed, FeedEntry};
+
+    fn entry(digest: &str, published: &str) -> FeedEntry {
+        FeedEntry {
+            digest: digest.into(),
+            version: Some("41.20261016".into()),
+            published: published.into(),
+            changelog: Some("Added <micro> & more".into()),
+        }
+    }
+
+    fn feed() -> Feed {
+        Feed {
+            version: 1,
+            image: "ghcr.io/octocat/my-image".into(),
+            updated: "2026-10-16T00:00:00Z".into(),
+            streams: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn add_new_builds() {
+        let mut feed = feed();
+
+        assert!(feed.add("latest", entry("sha256:a", "2026-10-15T00:00:00Z"), 2));
+        assert!(!feed.add("latest", entry("sha256:a", "2026-10-16T00:00:00Z"), 2));
+        assert!(feed.add("latest", entry("sha256:b", "2026-10-16T00:00:00Z"), 2));
+        assert!(feed.add("latest", entry("sha256:c", "2026-10-17T00:00:00Z"), 2));
+
+        assert_eq!(
+            feed.streams["latest"]
+                .iter()
+                .map(|entry| entry.digest.as_str())
+                .collect::<Vec<_>>(),
+            ["sha256:c", "sha256:b"]
+        );
+    }
+
+    #[test]
+    fn rss() {
+        let mut feed = feed();
+        feed.add("latest", entry("sha256:a", "2026-10-15T00:00:00Z"), 20);
+        feed.add("41", entry("sha256:b", "2026-10-16T00:00:00Z"), 20);
+
+        assert_eq!(
+            feed.to_rss(Some("https://octocat.github.io/my-image")),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<rss version="2.0">
+  <channel>
+    <title>ghcr.io/octocat/my-image</title>
+    <link>https://octocat.github.io/my-image</link>
+    <description>Builds of ghcr.io/octocat/my-image</description>
+    <lastBuildDate>Fri, 16 Oct 2026 00:00:00 +0000</lastBuildDate>
+    <item>
+      <title>ghcr.io/octocat/my-image:41 41.20261016</title>
+      <guid isPermaLink="false">ghcr.io/octocat/my-image:41@sha256:b</guid>
+      <pubDate>Fri, 16 Oct 2026 00:00:00 +0000</pubDate>
+      <description>Added &lt;micro&gt; &amp; more</description>
+    </item>
+    <item>
+      <title>ghcr.io/octocat/my-image:latest 41.20261016</title>
+      <guid isPermaLink="false">ghcr.io/octocat/my-image:latest@sha256:a</guid>
+      <pubDate>Thu, 15 Oct 2026 00:00:00 +0000</pubDate>
+      <description>Added &lt;micro&gt; &amp; more</description>
+    </item>
+  </channel>
+</rss>
+"#
+        );
+    }
+}
@@ -1,29 +1,41 @@
 use std::{
+    fmt::Write as _,
     fs::OpenOptions,
     io::{BufReader, Read},
     path::{Path, PathBuf},
     sync::Arc,
 };
 
-use blue_build_process_management::ASYNC_RUNTIME;
-use blue_build_recipe::{FromFileList, ModuleExt, Recipe, StagesExt};
+use blue_build_process_management::{
+    drivers::{CiDriver, Driver, DriverArgs},
+    summary, ASYNC_RUNTIME,
+};
+use blue_build_recipe::{ApiVersion, FromFileList, ModuleExt, Recipe, StagesExt};
 use bon::Builder;
 use clap::Args;
 use colored::Colorize;
-use log::{debug, info, trace};
-use miette::{bail, miette, Context, IntoDiagnostic, Report};
+use log::{debug, info, trace, warn};
+use miette::{bail, miette, Context, IntoDiagnostic, NarratableReportHandler, Report};
 use rayon::prelude::*;
 use schema_validator::{
-    SchemaValidator, MODULE_STAGE_LIST_V1_SCHEMA_URL, MODULE_V1_SCHEMA_URL, RECIPE_V1_SCHEMA_URL,
+    MODULE_STAGE_LIST_V1_SCHEMA_URL, MODULE_V1_SCHEMA_URL, RECIPE_V1_SCHEMA_URL,
     STAGE_V1_SCHEMA_URL,
 };
-use serde::de::DeserializeOwned;
+use schemars::JsonSchema;
+use serde::{de::DeserializeOwned, Serialize};
 use serde_json::Value;
 
+use crate::output::{self, OutputFormat, TableOutput};
+
 use super::BlueBuildCommand;
 
+pub use diagnostic::{DiagnosticSeverity, DiagnosticSpan, ValidationDiagnostic};
+pub use schema_validator::SchemaValidator;
+
+mod diagnostic;
 mod location;
-mod schema_validator;
+pub mod schema_validator;
+mod script_lint;
 mod yaml_span;
 
 #[derive(Debug, Args, Builder)]
@@ -41,6 +53,22 @@ pub struct ValidateCommand {
     #[builder(default)]
     pub all_errors: bool,
 
+    /// Lint the scripts of `script` modules with shellcheck,
+    /// which is run in a container.
+    #[arg(long)]
+    #[builder(default)]
+    pub lint_scripts: bool,
+
+    #[clap(flatten)]
+    #[builder(default)]
+    drivers: DriverArgs,
+
+    /// Whether the result is reported to the CI system. This is
+    /// left to the build when validating as part of one.
+    #[clap(skip = true)]
+    #[builder(default)]
+    report_summary: bool,
+
     #[clap(skip)]
     recipe_validator: Option<SchemaValidator>,
 
@@ -56,17 +84,81 @@ pub struct ValidateCommand {
 
 impl BlueBuildCommand for ValidateCommand {
     fn try_run(&mut self) -> miette::Result<()> {
+        let result = self.validate();
+
+        if self.report_summary {
+            if let Err(e) = Driver::report_summary(&summary::get(result.is_ok())) {
+                warn!("Failed to report the validation summary:\n{e:?}");
+            }
+        }
+
+        result
+    }
+}
+
+impl ValidateCommand {
+    /// Validates the recipe and the files that it references,
+    /// and returns the problems with them instead of printing them.
+    ///
+    /// This runs the same checks as `bluebuild validate`, so a
+    /// valid recipe returns no diagnostics.
+    ///
+    /// # Errors
+    /// Will error if the schemas can't be fetched.
+    pub fn diagnostics(&mut self) -> miette::Result<Vec<ValidationDiagnostic>> {
+        if self.lint_scripts {
+            Driver::init(self.drivers.clone());
+        }
+
+        ASYNC_RUNTIME.block_on(self.setup_validators())?;
+
+        Ok(self
+            .validate_recipe()
+            .err()
+            .unwrap_or_default()
+            .iter()
+            .flat_map(|report| ValidationDiagnostic::from_report(report, &self.recipe))
+            .collect())
+    }
+
+    fn validate(&mut self) -> miette::Result<()> {
         let recipe_path_display = self.recipe.display().to_string().bold().italic();
 
         if !self.recipe.is_file() {
             bail!("File {recipe_path_display} must exist");
         }
 
+        let machine_output = output::format().filter(|format| *format != OutputFormat::Table);
+        if machine_output.is_some() {
+            // The errors are rendered as plain text in the report
+            colored::control::set_override(false);
+        }
+
+        if self.lint_scripts {
+            Driver::init(self.drivers.clone());
+        }
+
         ASYNC_RUNTIME.block_on(self.setup_validators())?;
 
-        if let Err(errors) = self.validate_recipe() {
+        let result = self.validate_recipe();
+        let report = ValidationReport::new(
+            &self.recipe,
+            result.as_ref().err().map_or(&[], Vec::as_slice),
+        );
+        summary::record_validation(self.recipe.display().to_string(), &report.errors);
+
+        if machine_output.is_some() {
+            output::print(&report)?;
+
+            if !report.valid {
+                bail!("Recipe {} failed to validate", self.recipe.display());
+            }
+            return Ok(());
+        }
+
+        if let Err(errors) = result {
             let errors = errors.into_iter().fold(String::new(), |mut full, err| {
-                full.push_str(&format!("{err:?}"));
+                let _ = write!(full, "{err:?}");
                 full
             });
             let main_err = format!("Recipe {recipe_path_display} failed to validate");
@@ -92,9 +184,7 @@ impl BlueBuildCommand for ValidateCommand {
 
         Ok(())
     }
-}
 
-impl ValidateCommand {
     async fn setup_validators(&mut self) -> Result<(), Report> {
         let (rv, sv, mv, mslv) = tokio::try_join!(
             SchemaValidator::builder().url(RECIPE_V1_SCHEMA_URL).build(),
@@ -124,6 +214,7 @@ impl ValidateCommand {
 
         if traversed_files.contains(&path) {
             return vec![miette!(
+                code = "bluebuild::circular-from-file",
                 "{} File {path_display} has already been parsed:\n{traversed_files:?}",
                 "Circular dependency detected!".bright_red(),
             )];
@@ -219,16 +310,34 @@ impl ValidateCommand {
             .map_err(err_vec)?;
         trace!("{recipe_path_display}:\n{recipe}");
 
-        let schema_validator = self.recipe_validator.as_ref().unwrap();
-        let err = schema_validator
-            .process_validation(&self.recipe, recipe_str.clone(), self.all_errors)
+        let yaml_recipe: serde_yaml::Value = serde_yaml::from_str(&recipe_str)
+            .into_diagnostic()
+            .map_err(err_vec)?;
+        let api_version = ApiVersion::of(&yaml_recipe).map_err(err_vec)?;
+
+        // The schema is only published for v1 recipes, so other
+        // versions are converted to the keys of v1 and checked
+        // with it. Their errors point into the converted recipe.
+        let schema_str = if api_version.is_v1() {
+            recipe_str.clone()
+        } else {
+            debug!("Checking the {api_version} recipe {recipe_path_display} as a v1 recipe");
+            blue_build_recipe::from_v2(yaml_recipe)
+                .and_then(|recipe| serde_yaml::to_string(&recipe).into_diagnostic())
+                .map(Arc::new)
+                .map_err(err_vec)?
+        };
+        let err = self
+            .recipe_validator
+            .as_ref()
+            .unwrap()
+            .process_validation(&self.recipe, schema_str, self.all_errors)
             .map_err(err_vec)?;
 
         if let Some(err) = err {
             Err(vec![err])
         } else {
-            let recipe: Recipe = serde_yaml::from_str(&recipe_str)
-                .into_diagnostic()
+            let recipe = Recipe::from_yaml(&recipe_str)
                 .with_context(|| {
                     format!("Unable to convert Value to Recipe for {recipe_path_display}")
                 })
@@ -278,13 +387,95 @@ impl ValidateCommand {
                     .flatten()
                     .collect::<Vec<_>>(),
             );
-            if errors.is_empty() {
-                Ok(())
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+
+            // Checks that need the from-file references resolved,
+            // like the artifacts that modules copy from stages
+            let recipe = Recipe::parse(&self.recipe).map_err(err_vec)?;
+
+            if self.lint_scripts {
+                self.lint_scripts(&recipe)
             } else {
-                Err(errors)
+                Ok(())
             }
         }
     }
+
+    /// Lints the scripts of the script modules in the
+    /// recipe and in the files that it references.
+    fn lint_scripts(&self, recipe: &Recipe) -> Result<(), Vec<Report>> {
+        let mut paths = vec![self.recipe.clone()];
+        paths.extend(recipe.modules_ext.get_from_file_paths());
+        if let Some(stages) = &recipe.stages_ext {
+            paths.extend(stages.get_from_file_paths());
+        }
+
+        let errors = paths
+            .iter()
+            .map(|path| {
+                let file = Arc::new(read_file(path)?);
+                let value: Value = serde_yaml::from_str(&file).into_diagnostic()?;
+                script_lint::lint(path, &file, &script_lint::script_refs(&value))
+            })
+            .collect::<Result<Vec<_>, Report>>()
+            .map_err(err_vec)?
+            .into_iter()
+            .flatten()
+            .collect::<Vec<_>>();
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// The result of validating a recipe.
+#[derive(Debug, Serialize, JsonSchema)]
+pub(crate) struct ValidationReport {
+    recipe: PathBuf,
+    valid: bool,
+    errors: Vec<String>,
+    diagnostics: Vec<ValidationDiagnostic>,
+}
+
+impl ValidationReport {
+    fn new(recipe: &Path, errors: &[Report]) -> Self {
+        let handler = NarratableReportHandler::new();
+
+        Self {
+            recipe: recipe.to_path_buf(),
+            valid: errors.is_empty(),
+            errors: errors
+                .iter()
+                .map(|err| {
+                    let mut rendered = String::new();
+                    let _ = handler.render_report(&mut rendered, err.as_ref());
+                    rendered.trim_end().to_owned()
+                })
+                .collect(),
+            diagnostics: errors
+                .iter()
+                .flat_map(|err| ValidationDiagnostic::from_report(err, recipe))
+                .collect(),
+        }
+    }
+}
+
+impl TableOutput for ValidationReport {
+    fn to_table(&self) -> String {
+        if self.valid {
+            format!("Recipe {} is valid\n", self.recipe.display())
+        } else {
+            self.errors.iter().fold(String::new(), |mut out, err| {
+                let _ = writeln!(out, "{err}\n");
+                out
+            })
+        }
+    }
 }
 
 fn err_vec(err: Report) -> Vec<Report> {
@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use blue_build_process_management::summary::strip_ansi;
+use miette::{NarratableReportHandler, Report, Severity};
+use schemars::JsonSchema;
+use serde::Serialize;
+
+/// The rule of the reports that don't have a code.
+const DEFAULT_RULE: &str = "bluebuild::recipe";
+
+/// A problem found when validating a recipe, for tools
+/// like IDE plugins that show it next to the file.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct ValidationDiagnostic {
+    /// The file that the problem is in.
+    pub path: PathBuf,
+
+    /// Where the problem is in the file, if it's known.
+    pub span: Option<DiagnosticSpan>,
+
+    /// The check that found the problem, like `bluebuild::schema`.
+    pub rule: String,
+
+    pub severity: DiagnosticSeverity,
+
+    pub message: String,
+}
+
+/// A span of a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+pub struct DiagnosticSpan {
+    /// The byte offset of the start of the span.
+    pub offset: usize,
+
+    /// The length of the span in bytes.
+    pub length: usize,
+
+    /// The line of the start of the span, starting at 1.
+    pub line: usize,
+
+    /// The column of the start of the span, starting at 1.
+    pub column: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DiagnosticSeverity {
+    Error,
+    Warning,
+    Advice,
+}
+
+impl From<Severity> for DiagnosticSeverity {
+    fn from(value: Severity) -> Self {
+        match value {
+            Severity::Error => Self::Error,
+            Severity::Warning => Self::Warning,
+            Severity::Advice => Self::Advice,
+        }
+    }
+}
+
+impl ValidationDiagnostic {
+    /// The diagnostics of a report, one for each of its labels.
+    ///
+    /// A report without labels is a single diagnostic
+    /// without a span in the file at `path`.
+    #[must_use]
+    pub fn from_report(report: &Report, path: &Path) -> Vec<Self> {
+        let rule = report
+            .code()
+            .map_or_else(|| DEFAULT_RULE.to_owned(), |code| code.to_string());
+        let severity = report.severity().unwrap_or(Severity::Error).into();
+        let labels = report
+            .labels()
+            .map(Iterator::collect::<Vec<_>>)
+            .unwrap_or_default();
+
+        let Some(source) = report.source_code().filter(|_| !labels.is_empty()) else {
+            let mut rendered = String::new();
+            let _ = NarratableReportHandler::new().render_report(&mut rendered, report.as_ref());
+
+            return vec![Self {
+                path: path.to_path_buf(),
+                span: None,
+                rule,
+                severity,
+                message: strip_ansi(rendered.trim_end()),
+            }];
+        };
+
+        labels
+            .into_iter()
+            .map(|label| {
+                let contents = source.read_span(label.inner(), 0, 0).ok();
+
+                Self {
+                    path: contents
+                        .as_ref()
+                        .and_then(|contents| contents.name())
+                        .map_or_else(|| path.to_path_buf(), PathBuf::from),
+                    span: contents.map(|contents| DiagnosticSpan {
+                        offset: label.offset(),
+                        length: label.len(),
+                        line: contents.line() + 1,
+                        column: contents.column() + 1,
+                    }),
+                    rule: rule.clone(),
+                    severity,
+                    message: strip_ansi(
+                        &label
+                            .label()
+                            .map_or_else(|| report.to_string(), ToOwned::to_owned),
+                    ),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use miette::{miette, LabeledSpan, NamedSource};
+
+    use super::{DiagnosticSeverity, DiagnosticSpan, ValidationDiagnostic};
+
+    #[test]
+    fn labeled_report() {
+        let file = "name: test\nimage-version: forty\n";
+        let report = miette!(
+            code = "bluebuild::schema",
+            labels = vec![LabeledSpan::new_with_span(
+                Some("\u{1b}[1;31m\"forty\" is not of type \"integer\"\u{1b}[0m".into()),
+                (26, 5)
+            )],
+            "1 error encountered"
+        )
+        .with_source_code(NamedSource::new("recipes/recipe.yml", file));
+
+        assert_eq!(
+            ValidationDiagnostic::from_report(&report, Path::new("recipe.yml")),
+            [ValidationDiagnostic {
+                path: "recipes/recipe.yml".into(),
+                span: Some(DiagnosticSpan {
+                    offset: 26,
+                    length: 5,
+                    line: 2,
+                    column: 16,
+                }),
+                rule: "bluebuild::schema".into(),
+                severity: DiagnosticSeverity::Error,
+                message: "\"forty\" is not of type \"integer\"".into(),
+            }]
+        );
+    }
+
+    #[test]
+    fn unlabeled_report() {
+        let report = miette!("Unable to open recipe.yml");
+
+        let diagnostics = ValidationDiagnostic::from_report(&report, Path::new("recipe.yml"));
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].path, Path::new("recipe.yml"));
+        assert_eq!(diagnostics[0].span, None);
+        assert_eq!(diagnostics[0].rule, "bluebuild::recipe");
+        assert!(diagnostics[0].message.contains("Unable to open recipe.yml"));
+    }
+}
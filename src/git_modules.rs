@@ -0,0 +1,425 @@
+//! Fetching of modules that are sourced from git repos.
+//!
+//! Every repo is kept as a bare repo in the cache directory so that
+//! a module pinned to a commit doesn't need the network after the
+//! first fetch. The `ref` of each module is resolved to a commit and
+//! the module's directory at that commit is exported into the build
+//! context, where the Containerfile copies it into its own stage.
+
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Mutex,
+};
+
+use blue_build_process_management::signal_handler::{CleanupGuard, CleanupItem};
+use blue_build_recipe::{GitSource, ModuleRequiredFields, Recipe};
+use blue_build_utils::{cmd, constants::GIT_MODULES_PATH};
+use log::{debug, info, trace};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use uuid::Uuid;
+
+/// Repos are fetched one at a time since git can't
+/// update a shallow repo from two processes at once.
+static FETCH_LOCK: Mutex<()> = Mutex::new(());
+
+/// The URL schemes that module repos can be fetched with. Other
+/// transports, like `ext::`, can run commands on the host.
+const URL_SCHEMES: &[&str] = &["https", "http", "ssh", "git", "file"];
+
+/// Resolves the `ref` of every git sourced module to a commit, exports
+/// the modules into the build context, and rewrites their `source` so
+/// that it's pinned to the commit.
+///
+/// Returns the pinned sources.
+///
+/// # Errors
+/// Will error if a repo can't be fetched or doesn't
+/// contain the module's directory.
+pub fn pin(recipe: &mut Recipe) -> Result<Vec<String>> {
+    if recipe
+        .all_modules()
+        .all(|module| module.get_git_source().is_none())
+    {
+        return Ok(Vec::new());
+    }
+
+    let repos_dir = blue_build_utils::cache_dir()
+        .ok_or_else(|| miette!("Unable to find a cache directory for git modules"))?
+        .join("git");
+
+    pin_in(recipe, &repos_dir, Path::new(GIT_MODULES_PATH))
+}
+
+fn pin_in(recipe: &mut Recipe, repos_dir: &Path, context_dir: &Path) -> Result<Vec<String>> {
+    let mut commits: HashMap<(String, Option<String>), String> = HashMap::new();
+    let mut pinned = Vec::new();
+
+    for module in modules_mut(recipe) {
+        let Some(source) = module.get_git_source() else {
+            continue;
+        };
+        let module_type = module.module_type.to_string();
+        let repo = repos_dir.join(sanitize(source.url));
+
+        let key = (source.url.to_owned(), source.git_ref.map(ToOwned::to_owned));
+        let commit = if let Some(commit) = commits.get(&key) {
+            commit.clone()
+        } else {
+            let commit = resolve(&repo, &source)?;
+            commits.insert(key, commit.clone());
+            commit
+        };
+
+        let pinned_source = GitSource {
+            git_ref: Some(&commit),
+            ..source
+        };
+        export(
+            &repo,
+            &commit,
+            &source.module_path(&module_type),
+            &context_dir.join(pinned_source.key(&module_type)),
+            &module_type,
+        )
+        .with_context(|| format!("Failed to get module {module_type} from {}", source.url))?;
+
+        let pinned_source = pinned_source.to_string();
+        debug!("Pinned module {module_type} to {pinned_source}");
+        module.source = Some(pinned_source.clone().into());
+        pinned.push(pinned_source);
+    }
+
+    Ok(pinned)
+}
+
+pub(crate) fn modules_mut<'a, 'b>(
+    recipe: &'a mut Recipe<'b>,
+) -> impl Iterator<Item = &'a mut ModuleRequiredFields<'b>> {
+    recipe
+        .modules_ext
+        .modules
+        .iter_mut()
+        .chain(
+            recipe
+                .stages_ext
+                .iter_mut()
+                .flat_map(|stages_ext| &mut stages_ext.stages)
+                .filter_map(|stage| stage.required_fields.as_mut())
+                .flat_map(|stage| &mut stage.modules_ext.modules),
+        )
+        .filter_map(|module| module.required_fields.as_mut())
+}
+
+/// Resolves the `ref` of the source to a commit, fetching
+/// it unless it's a commit that has already been fetched.
+fn resolve(repo: &Path, source: &GitSource) -> Result<String> {
+    trace!("git_modules::resolve({}, {source})", repo.display());
+
+    check_url(source.url)?;
+
+    let _lock = FETCH_LOCK
+        .lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner);
+
+    if !repo.join("HEAD").exists() {
+        fs::create_dir_all(repo).into_diagnostic()?;
+        git(cmd!("git", "init", "-q", "--bare", repo))?;
+    }
+
+    if let Some(commit) = source.git_ref.filter(|git_ref| is_commit(git_ref)) {
+        if cmd!(
+            "git",
+            "-C",
+            repo,
+            "cat-file",
+            "-e",
+            format!("{commit}^{{commit}}")
+        )
+        .stderr(Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+        {
+            debug!("Using cached commit {commit} of {}", source.url);
+            return Ok(commit.to_owned());
+        }
+    }
+
+    let git_ref = source.git_ref.unwrap_or("HEAD");
+    info!("Fetching {git_ref} of module repo {}", source.url);
+
+    let tmp_ref = format!("refs/bluebuild/{}", Uuid::new_v4());
+    git(cmd!(
+        "git",
+        "-C",
+        repo,
+        "fetch",
+        "-q",
+        "--depth",
+        "1",
+        "--",
+        source.url,
+        format!("+{git_ref}:{tmp_ref}"),
+    ))
+    .with_context(|| format!("Failed to fetch {git_ref} from {}", source.url))?;
+
+    let commit = git(cmd!(
+        "git",
+        "-C",
+        repo,
+        "rev-parse",
+        "--verify",
+        format!("{tmp_ref}^{{commit}}"),
+    ))?;
+    git(cmd!("git", "-C", repo, "update-ref", "-d", &tmp_ref))?;
+
+    Ok(commit)
+}
+
+/// Exports the directory `path` of the repo at `commit` into
+/// `<dest>/<module_type>`. The destination is never changed once it
+/// exists since its name includes the commit.
+fn export(repo: &Path, commit: &str, path: &str, dest: &Path, module_type: &str) -> Result<()> {
+    trace!(
+        "git_modules::export({}, {commit}, {path}, {})",
+        repo.display(),
+        dest.display()
+    );
+
+    if dest.exists() {
+        return Ok(());
+    }
+
+    let parent = dest.parent().unwrap_or_else(|| Path::new("."));
+    let tmp = parent.join(format!(".tmp-{}", Uuid::new_v4()));
+    let _tmp_cleanup = CleanupGuard::new(CleanupItem::Dir(tmp.clone()));
+    let module_dir = tmp.join(module_type);
+    fs::create_dir_all(&module_dir).into_diagnostic()?;
+
+    let result = extract(repo, commit, path, &module_dir).and_then(|()| {
+        match fs::rename(&tmp, dest) {
+            // Another build exported the same commit first
+            Err(_) if dest.exists() => fs::remove_dir_all(&tmp).into_diagnostic(),
+            result => result.into_diagnostic(),
+        }
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&tmp);
+    }
+    result
+}
+
+fn extract(repo: &Path, commit: &str, path: &str, module_dir: &Path) -> Result<()> {
+    let mut archive = cmd!(
+        "git",
+        "-C",
+        repo,
+        "archive",
+        "--format=tar",
+        format!("{commit}:{path}"),
+        stdout = Stdio::piped(),
+        stderr = Stdio::piped(),
+    )
+    .spawn()
+    .into_diagnostic()?;
+
+    let archive_stdout = archive
+        .stdout
+        .take()
+        .ok_or_else(|| miette!("Failed to read the output of git archive"))?;
+    let tar = cmd!("tar", "-x", "-C", module_dir, stdin = archive_stdout)
+        .output()
+        .into_diagnostic()?;
+    let archive = archive.wait_with_output().into_diagnostic()?;
+
+    if !archive.status.success() {
+        bail!(
+            "The directory {path} doesn't exist at commit {commit}: {}",
+            String::from_utf8_lossy(&archive.stderr).trim()
+        );
+    }
+    if !tar.status.success() {
+        bail!(
+            "Failed to extract the module from {path}: {}",
+            String::from_utf8_lossy(&tar.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Runs a git command and returns its trimmed stdout.
+fn git(mut command: std::process::Command) -> Result<String> {
+    trace!("{command:?}");
+
+    let output = command.output().into_diagnostic()?;
+    if !output.status.success() {
+        bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// Checks that a module repo is fetched with one of [`URL_SCHEMES`],
+/// or over ssh like `git@github.com:org/repo.git`.
+fn check_url(url: &str) -> Result<()> {
+    let valid = match url.split_once("://") {
+        Some((scheme, _)) => URL_SCHEMES.contains(&scheme),
+        None => url.split_once(':').is_some_and(|(host, path)| {
+            !host.is_empty()
+                && !host.starts_with('-')
+                && !host.contains('/')
+                && !path.starts_with(':')
+        }),
+    };
+    if !valid {
+        bail!(
+            "The module repo {url} must be a URL with one of the schemes {}, or an ssh address like git@github.com:org/repo.git",
+            URL_SCHEMES.join(", ")
+        );
+    }
+    Ok(())
+}
+
+fn is_commit(git_ref: &str) -> bool {
+    git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+fn sanitize(url: &str) -> PathBuf {
+    PathBuf::from(
+        url.split(|c: char| !c.is_ascii_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>()
+            .join("-"),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use blue_build_recipe::{GitSource, Module, ModuleExt, ModuleRequiredFields, Recipe};
+    use blue_build_utils::cmd;
+    use rstest::rstest;
+    use tempfile::TempDir;
+
+    use super::{check_url, git, pin_in};
+
+    fn module_repo(dir: &TempDir) -> String {
+        let repo = dir.path().join("source");
+        fs::create_dir_all(repo.join("modules/hello")).unwrap();
+        fs::write(repo.join("modules/hello/hello.sh"), "echo hello").unwrap();
+
+        git(cmd!("git", "init", "-q", &repo)).unwrap();
+        git(cmd!("git", "-C", &repo, "add", ".")).unwrap();
+        git(cmd!(
+            "git",
+            "-C",
+            &repo,
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@example.com",
+            "commit",
+            "-q",
+            "-m",
+            "init",
+        ))
+        .unwrap();
+        git(cmd!("git", "-C", &repo, "tag", "v1")).unwrap();
+
+        format!("file://{}", repo.display())
+    }
+
+    #[test]
+    fn pin_git_module() {
+        let dir = TempDir::new().unwrap();
+        let url = module_repo(&dir);
+        let source = format!("git+{url}#ref=v1");
+
+        let mut recipe = Recipe::builder()
+            .name("test")
+            .description("test")
+            .base_image("base")
+            .image_version("latest")
+            .modules_ext(
+                ModuleExt::builder()
+                    .modules(vec![Module::builder()
+                        .required_fields(
+                            ModuleRequiredFields::builder()
+                                .module_type("hello")
+                                .source(source.as_str())
+                                .build(),
+                        )
+                        .build()])
+                    .build(),
+            )
+            .build();
+
+        let repos = dir.path().join("repos");
+        let context = dir.path().join("context");
+        let pinned = pin_in(&mut recipe, &repos, &context).unwrap();
+
+        assert_eq!(pinned.len(), 1);
+        let pinned_source = recipe
+            .all_modules()
+            .next()
+            .and_then(|module| module.source.clone())
+            .unwrap()
+            .into_owned();
+        let git_source = GitSource::parse(&pinned_source).unwrap().unwrap();
+        assert_eq!(git_source.url, url);
+        assert_eq!(git_source.git_ref.unwrap().len(), 40);
+        assert_eq!(
+            fs::read_to_string(context.join(git_source.key("hello")).join("hello/hello.sh"))
+                .unwrap(),
+            "echo hello"
+        );
+
+        // A pinned module is used from the cache
+        // without fetching from the repo again
+        fs::remove_dir_all(dir.path().join("source")).unwrap();
+        assert_eq!(pin_in(&mut recipe, &repos, &context).unwrap(), pinned);
+
+        let missing = GitSource {
+            path: Some("modules/missing"),
+            ..git_source
+        }
+        .to_string();
+        let mut recipe = Recipe::builder()
+            .name("test")
+            .description("test")
+            .base_image("base")
+            .image_version("latest")
+            .modules_ext(
+                ModuleExt::builder()
+                    .modules(vec![Module::builder()
+                        .required_fields(
+                            ModuleRequiredFields::builder()
+                                .module_type("missing")
+                                .source(missing)
+                                .build(),
+                        )
+                        .build()])
+                    .build(),
+            )
+            .build();
+        assert!(pin_in(&mut recipe, &repos, &context).is_err());
+    }
+
+    #[rstest]
+    #[case("https://github.com/blue-build/modules.git", true)]
+    #[case("ssh://git@github.com/blue-build/modules.git", true)]
+    #[case("file:///srv/modules", true)]
+    #[case("git@github.com:blue-build/modules.git", true)]
+    #[case("ext::sh -c touch% /tmp/pwned", false)]
+    #[case("fd::17", false)]
+    #[case("foo://github.com/blue-build/modules.git", false)]
+    #[case("--upload-pack=touch /tmp/pwned:repo", false)]
+    #[case("/srv/modules", false)]
+    #[case("./modules:repo", false)]
+    fn url_schemes(#[case] url: &str, #[case] valid: bool) {
+        assert_eq!(check_url(url).is_ok(), valid);
+    }
+}
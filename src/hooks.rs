@@ -0,0 +1,228 @@
+//! Runs the commands of the `hooks` of the user config and
+//! recipes at points of the build of each image.
+//!
+//! ```toml
+//! [hooks]
+//! post-push = ["./scripts/deploy.sh"]
+//! on-failure = ["./scripts/notify.sh \"$BB_RECIPE failed: $BB_ERROR\""]
+//! ```
+//!
+//! The hooks of the user config run before the hooks of the
+//! recipe. Since the hooks of a recipe run on the host, they're
+//! only run with `--allow-recipe-hooks`, so that building someone
+//! else's recipe can't run commands outside of the build. For the
+//! same reason, the `hooks` of the repo config are ignored.
+//!
+//! Each command is run with `sh -c` and gets these env vars:
+//! - `BB_HOOK`: the hook, like `post-push`
+//! - `BB_RECIPE`: the path of the recipe
+//! - `BB_IMAGE`: the name of the image without a tag
+//! - `BB_TAGS`: the tags of the image, separated by spaces
+//! - `BB_IMAGES`: the images that were built, separated by spaces
+//! - `BB_DIGEST`: the digest of the pushed image, in `post-push`
+//! - `BB_ERROR`: the error of the build, in `on-failure`
+//!
+//! A command that fails fails the build, except in `on-failure`
+//! where it's only logged.
+
+use std::path::Path;
+
+use blue_build_recipe::{Hook, Hooks, Recipe};
+use blue_build_utils::{cmd, constants::BB_BUILD_ALLOW_RECIPE_HOOKS};
+use bon::Builder;
+use log::{info, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use toml::Value;
+
+use crate::config::Config;
+
+const HOOKS_KEY: &str = "hooks";
+
+/// What the commands of a hook are told about the build.
+#[derive(Debug, Clone, Builder)]
+pub struct HookEnv<'a> {
+    recipe: &'a Path,
+    image: Option<&'a str>,
+
+    #[builder(default)]
+    tags: &'a [String],
+
+    #[builder(default)]
+    images: &'a [String],
+
+    digest: Option<&'a str>,
+    error: Option<String>,
+}
+
+impl HookEnv<'_> {
+    fn vars(&self, hook: Hook) -> Vec<(&'static str, String)> {
+        let mut vars = vec![
+            ("BB_HOOK", hook.to_string()),
+            ("BB_RECIPE", self.recipe.display().to_string()),
+        ];
+        vars.extend(
+            [
+                ("BB_IMAGE", self.image.map(ToOwned::to_owned)),
+                (
+                    "BB_TAGS",
+                    (!self.tags.is_empty()).then(|| self.tags.join(" ")),
+                ),
+                (
+                    "BB_IMAGES",
+                    (!self.images.is_empty()).then(|| self.images.join(" ")),
+                ),
+                ("BB_DIGEST", self.digest.map(ToOwned::to_owned)),
+                ("BB_ERROR", self.error.clone()),
+            ]
+            .into_iter()
+            .filter_map(|(key, value)| Some((key, value?))),
+        );
+        vars
+    }
+}
+
+/// The hooks of the user config followed by the hooks of the recipe.
+///
+/// # Errors
+/// Will error if the config files can't be loaded, their `hooks`
+/// table isn't valid, or the recipe has hooks that aren't allowed.
+pub fn load(recipe: &Recipe, allow_recipe_hooks: bool) -> Result<Hooks> {
+    let mut hooks = config_hooks(Config::load()?.values().get(HOOKS_KEY))?;
+    hooks.extend(recipe_hooks(recipe, allow_recipe_hooks)?);
+    Ok(hooks)
+}
+
+fn recipe_hooks(recipe: &Recipe, allow: bool) -> Result<Hooks> {
+    if !allow && !recipe.hooks.is_empty() {
+        bail!(
+            help = format!(
+                "Pass `--allow-recipe-hooks`, set {BB_BUILD_ALLOW_RECIPE_HOOKS}=true, or set `allow-recipe-hooks = true` in the `[build]` table of the user config"
+            ),
+            "The recipe {} has hooks, which run commands on the host and have to be allowed",
+            recipe.name
+        );
+    }
+    Ok(recipe.hooks.clone())
+}
+
+fn config_hooks(value: Option<&Value>) -> Result<Hooks> {
+    value.map_or_else(
+        || Ok(Hooks::default()),
+        |value| {
+            value
+                .clone()
+                .try_into()
+                .into_diagnostic()
+                .context("`hooks` must be a table of lists of commands")
+        },
+    )
+}
+
+/// Runs the commands of `hook` one after the other.
+///
+/// # Errors
+/// Will error if a command can't be run or exits with an error.
+pub fn run(hooks: &Hooks, hook: Hook, env: &HookEnv) -> Result<()> {
+    trace!("hooks::run({hook}, {env:?})");
+
+    for command in hooks.commands(hook) {
+        info!("Running {hook} hook: {command}");
+        let status = cmd!("sh", "-c", command)
+            .envs(env.vars(hook))
+            .status()
+            .into_diagnostic()
+            .with_context(|| format!("Failed to run the {hook} hook `{command}`"))?;
+
+        if !status.success() {
+            bail!("The {hook} hook `{command}` failed with {status}");
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::Path};
+
+    use blue_build_recipe::{Hook, Hooks, Recipe};
+    use tempfile::TempDir;
+
+    use super::{config_hooks, recipe_hooks, run, HookEnv};
+
+    const CONFIG: &str = r#"
+[hooks]
+pre-build = ["true"]
+post-push = ["echo config"]
+"#;
+
+    const RECIPE: &str = r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+hooks:
+  post-push:
+    - echo recipe
+modules: []
+";
+
+    #[test]
+    fn config_hooks_run_first() {
+        let config = CONFIG.parse::<toml::Table>().unwrap();
+        let recipe = Recipe::from_yaml(RECIPE).unwrap();
+
+        let mut hooks = config_hooks(config.get("hooks")).unwrap();
+        hooks.extend(recipe_hooks(&recipe, true).unwrap());
+
+        assert_eq!(hooks.commands(Hook::PreBuild), ["true"]);
+        assert_eq!(
+            hooks.commands(Hook::PostPush),
+            ["echo config", "echo recipe"]
+        );
+        assert!(config_hooks(Some(&toml::Value::from("echo"))).is_err());
+    }
+
+    #[test]
+    fn recipe_hooks_must_be_allowed() {
+        let recipe = Recipe::from_yaml(RECIPE).unwrap();
+        assert!(recipe_hooks(&recipe, false).is_err());
+
+        let recipe =
+            Recipe::from_yaml(&RECIPE.replace("hooks:\n  post-push:\n    - echo recipe\n", ""))
+                .unwrap();
+        assert!(recipe_hooks(&recipe, false).unwrap().is_empty());
+    }
+
+    #[test]
+    fn runs_commands_with_env() {
+        let dir = TempDir::new().unwrap();
+        let out = dir.path().join("out");
+        let hooks = Hooks {
+            post_push: vec![format!(
+                "echo \"$BB_HOOK $BB_IMAGE $BB_TAGS $BB_DIGEST\" > {}",
+                out.display()
+            )],
+            on_failure: vec!["exit 3".into()],
+            ..Hooks::default()
+        };
+        let tags = ["latest".to_owned(), "41".to_owned()];
+        let env = HookEnv::builder()
+            .recipe(Path::new("recipes/recipe.yml"))
+            .image("ghcr.io/octocat/test")
+            .tags(&tags)
+            .digest("sha256:abc")
+            .build();
+
+        run(&hooks, Hook::PostPush, &env).unwrap();
+        assert_eq!(
+            fs::read_to_string(&out).unwrap(),
+            "post-push ghcr.io/octocat/test latest 41 sha256:abc\n"
+        );
+
+        let err = run(&hooks, Hook::OnFailure, &env).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "The on-failure hook `exit 3` failed with exit status: 3"
+        );
+    }
+}
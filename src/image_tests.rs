@@ -0,0 +1,471 @@
+//! Runs the `tests` of a recipe against an image.
+//!
+//! ```yaml
+//! tests:
+//!   - file-exists: /usr/bin/htop
+//!   - command: htop --version
+//! ```
+//!
+//! By default each test runs in its own container that is removed
+//! afterwards, so a test can't change what the tests after it see.
+//! The image isn't booted then, so commands that need a running
+//! systemd fail. A [`BootedContainer`] boots the image with systemd
+//! first and runs each test in it with `exec` instead.
+
+use std::{
+    fmt::Write as _,
+    path::Path,
+    process::{Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use blue_build_process_management::{
+    drivers::{opts::RunOpts, types::RunDriverType, Driver, RunDriver},
+    signal_handler::{CleanupGuard, CleanupItem, ContainerRuntime},
+    summary::strip_ansi,
+};
+use blue_build_recipe::ImageTest;
+use blue_build_utils::{cmd, escape_xml, string_vec};
+use colored::Colorize;
+use log::{debug, info, trace, warn};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use oci_distribution::Reference;
+
+/// How long a booted image has for systemd to finish starting up.
+const BOOT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// A test to run against an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestCase {
+    pub name: String,
+
+    /// The command that runs the test in the container.
+    pub args: Vec<String>,
+}
+
+impl From<&ImageTest> for TestCase {
+    fn from(test: &ImageTest) -> Self {
+        Self {
+            name: test.to_string(),
+            args: test.args(),
+        }
+    }
+}
+
+impl TestCase {
+    /// A script of the user that is run with `sh`.
+    ///
+    /// The contents are passed to `sh -c` so that the script doesn't
+    /// need to be mounted into the container.
+    ///
+    /// # Errors
+    /// Will error if the script can't be read.
+    pub fn script(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to read the script {}", path.display()))?;
+        let name = path.display().to_string();
+
+        Ok(Self {
+            args: string_vec!["/bin/sh", "-c", contents, &name],
+            name,
+        })
+    }
+}
+
+/// The result of a test of an image.
+#[derive(Debug, Clone)]
+pub struct TestOutcome {
+    pub name: String,
+
+    /// Whether the test passed.
+    pub passed: bool,
+
+    /// The stdout and stderr of the test, or the
+    /// error of the container if it couldn't run.
+    pub output: String,
+    pub duration: Duration,
+}
+
+/// Where the tests of an image run.
+#[derive(Debug, Clone, Copy)]
+pub enum TestTarget<'a> {
+    /// A new container of the image for each test.
+    Image(&'a Reference),
+
+    /// A container of the image that was booted with systemd.
+    Booted(&'a BootedContainer),
+}
+
+impl TestTarget<'_> {
+    fn run(&self, args: &[String]) -> Result<Output> {
+        match self {
+            Self::Image(image) => Driver::run_output(
+                &RunOpts::builder()
+                    .image(image.to_string())
+                    .args(args.iter().map(Into::into).collect::<Vec<_>>())
+                    .remove(true)
+                    .build(),
+            ),
+            Self::Booted(container) => container.exec(args),
+        }
+    }
+}
+
+/// Runs each test against `target`.
+///
+/// A test whose container can't be run fails.
+#[must_use]
+pub fn run(target: TestTarget, tests: &[TestCase]) -> Vec<TestOutcome> {
+    trace!("image_tests::run({target:?}, {tests:?})");
+
+    tests
+        .iter()
+        .map(|test| {
+            let start = Instant::now();
+            let (passed, output) = match target.run(&test.args) {
+                Ok(output) => (
+                    output.status.success(),
+                    format!(
+                        "{}{}",
+                        String::from_utf8_lossy(&output.stdout),
+                        String::from_utf8_lossy(&output.stderr)
+                    ),
+                ),
+                Err(e) => (false, format!("{e:?}")),
+            };
+
+            let outcome = TestOutcome {
+                name: test.name.clone(),
+                passed,
+                output,
+                duration: start.elapsed(),
+            };
+            if outcome.passed {
+                info!("{} {}", "PASS".bright_green(), outcome.name);
+            } else {
+                warn!(
+                    "{} {}\n{}",
+                    "FAIL".bright_red(),
+                    outcome.name,
+                    outcome.output.trim_end()
+                );
+            }
+            outcome
+        })
+        .collect()
+}
+
+/// Runs the tests of a recipe in containers of `image`,
+/// erroring if any of them fail.
+///
+/// # Errors
+/// Will error if a test fails.
+pub fn check(image: &Reference, tests: &[ImageTest]) -> Result<()> {
+    if tests.is_empty() {
+        return Ok(());
+    }
+
+    info!("Testing {image}");
+    let outcomes = run(
+        TestTarget::Image(image),
+        &tests.iter().map(TestCase::from).collect::<Vec<_>>(),
+    );
+    ensure_passed(image, &outcomes)
+}
+
+/// Errors with the tests that failed, if any did.
+///
+/// # Errors
+/// Will error if a test failed.
+pub fn ensure_passed(image: &Reference, outcomes: &[TestOutcome]) -> Result<()> {
+    let failed = outcomes
+        .iter()
+        .filter(|outcome| !outcome.passed)
+        .map(|outcome| format!(" - {}", outcome.name))
+        .collect::<Vec<_>>();
+
+    if !failed.is_empty() {
+        bail!(
+            "{} of {} tests failed for {image}:\n{}",
+            failed.len(),
+            outcomes.len(),
+            failed.join("\n")
+        );
+    }
+    Ok(())
+}
+
+/// The outcomes of the tests of `image` as a JUnit report.
+#[must_use]
+pub fn junit_report(image: &str, outcomes: &[TestOutcome]) -> String {
+    let failures = outcomes.iter().filter(|outcome| !outcome.passed).count();
+    let tests = outcomes.len();
+    let time = outcomes
+        .iter()
+        .map(|outcome| outcome.duration)
+        .sum::<Duration>()
+        .as_secs_f64();
+    let image = escape_xml(image);
+
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    _ = writeln!(
+        out,
+        "<testsuites name=\"bluebuild\" tests=\"{tests}\" failures=\"{failures}\" time=\"{time:.3}\">"
+    );
+    _ = writeln!(
+        out,
+        "  <testsuite name=\"{image}\" tests=\"{tests}\" failures=\"{failures}\" time=\"{time:.3}\">"
+    );
+
+    for outcome in outcomes {
+        let name = escape_xml(&outcome.name);
+        let time = outcome.duration.as_secs_f64();
+
+        if outcome.passed {
+            _ = writeln!(
+                out,
+                "    <testcase classname=\"{image}\" name=\"{name}\" time=\"{time:.3}\" />"
+            );
+        } else {
+            _ = writeln!(
+                out,
+                "    <testcase classname=\"{image}\" name=\"{name}\" time=\"{time:.3}\">"
+            );
+            _ = writeln!(
+                out,
+                "      <failure message=\"{name} failed\">{}</failure>",
+                escape_xml(&strip_ansi(outcome.output.trim_end()))
+            );
+            out.push_str("    </testcase>\n");
+        }
+    }
+
+    out.push_str("  </testsuite>\n</testsuites>\n");
+    out
+}
+
+/// A container of an image that was booted with systemd as its init,
+/// which is removed when dropped.
+///
+/// Only podman can boot systemd in a container without
+/// extra privileges, so this needs the podman run driver.
+#[derive(Debug)]
+pub struct BootedContainer {
+    id: String,
+    _cleanup: CleanupGuard,
+}
+
+impl BootedContainer {
+    /// Boots `image` and waits for systemd to finish starting up.
+    ///
+    /// # Errors
+    /// Will error if the run driver isn't podman or the container doesn't start.
+    pub fn boot(image: &Reference) -> Result<Self> {
+        trace!("BootedContainer::boot({image})");
+
+        if !matches!(Driver::get_run_driver(), RunDriverType::Podman) {
+            bail!(
+                help = "Use `--run-driver podman`",
+                "Images can only be booted with podman"
+            );
+        }
+
+        info!("Booting {image}");
+        let output = cmd!(
+            "podman",
+            "run",
+            "--detach",
+            "--systemd=always",
+            image.to_string(),
+            "/sbin/init"
+        )
+        .output()
+        .into_diagnostic()?;
+        if !output.status.success() {
+            bail!(
+                "Failed to boot {image}:\n{}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        let id = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+        let container = Self {
+            _cleanup: CleanupGuard::new(CleanupItem::Container {
+                container_id: id.clone(),
+                container_runtime: ContainerRuntime::Podman,
+                requires_sudo: false,
+            }),
+            id,
+        };
+
+        // A degraded system still runs the tests, the
+        // units that failed usually fail a test too
+        let state = container
+            .wait_for_boot()
+            .with_context(|| format!("Failed to boot {image}"))?;
+        if state != "running" {
+            warn!("The system of {image} is {state}");
+        }
+        Ok(container)
+    }
+
+    /// Waits for systemd to finish starting up, returning the state of the system.
+    ///
+    /// `systemctl is-system-running --wait` waits forever
+    /// for a unit that hangs, so it's stopped after a while.
+    fn wait_for_boot(&self) -> Result<String> {
+        let mut child = cmd!(
+            "podman",
+            "exec",
+            &self.id,
+            "systemctl",
+            "is-system-running",
+            "--wait"
+        )
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .into_diagnostic()?;
+
+        let start = Instant::now();
+        while child.try_wait().into_diagnostic()?.is_none() {
+            if start.elapsed() > BOOT_TIMEOUT {
+                _ = child.kill();
+                _ = child.wait();
+                bail!(
+                    help = "Check `systemctl list-jobs` in the image for the units that don't finish starting",
+                    "systemd didn't finish starting up in {} seconds",
+                    BOOT_TIMEOUT.as_secs()
+                );
+            }
+            thread::sleep(Duration::from_millis(500));
+        }
+
+        let output = child.wait_with_output().into_diagnostic()?;
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    fn exec(&self, args: &[String]) -> Result<Output> {
+        cmd!("podman", "exec", &self.id, for args)
+            .output()
+            .into_diagnostic()
+    }
+}
+
+impl Drop for BootedContainer {
+    fn drop(&mut self) {
+        debug!("Removing container {}", self.id);
+        if let Err(e) = cmd!("podman", "container", "rm", "--force", &self.id).output() {
+            warn!("Failed to remove container {}: {e}", self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, time::Duration};
+
+    use blue_build_recipe::{ImageTest, Recipe};
+    use tempfile::TempDir;
+
+    use super::{junit_report, TestCase, TestOutcome};
+
+    const RECIPE: &str = r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+tests:
+  - file-exists: /usr/bin/htop
+  - command: htop --version | grep -q htop
+  - service-enabled: tailscaled.service
+  - package-installed: htop
+modules: []
+";
+
+    #[test]
+    fn recipe_tests() {
+        let recipe = Recipe::from_yaml(RECIPE).unwrap();
+
+        assert_eq!(
+            recipe.tests,
+            [
+                ImageTest::FileExists {
+                    file_exists: "/usr/bin/htop".into()
+                },
+                ImageTest::Command {
+                    command: "htop --version | grep -q htop".into()
+                },
+                ImageTest::ServiceEnabled {
+                    service_enabled: "tailscaled.service".into()
+                },
+                ImageTest::PackageInstalled {
+                    package_installed: "htop".into()
+                },
+            ]
+        );
+        assert_eq!(
+            recipe.tests[1].args(),
+            ["/bin/sh", "-c", "htop --version | grep -q htop"]
+        );
+        assert_eq!(
+            recipe.tests[2].to_string(),
+            "service-enabled: tailscaled.service"
+        );
+        assert!(Recipe::from_yaml(&RECIPE.replace("file-exists", "file-missing")).is_err());
+    }
+
+    #[test]
+    fn script() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("check.sh");
+        fs::write(&path, "rpm -q htop\n").unwrap();
+
+        let case = TestCase::script(&path).unwrap();
+        assert_eq!(case.name, path.display().to_string());
+        assert_eq!(
+            case.args,
+            [
+                "/bin/sh".to_owned(),
+                "-c".into(),
+                "rpm -q htop\n".into(),
+                path.display().to_string()
+            ]
+        );
+        assert!(TestCase::script(&dir.path().join("missing.sh")).is_err());
+    }
+
+    #[test]
+    fn junit() {
+        let outcomes = [
+            TestOutcome {
+                name: "file-exists: /usr/bin/htop".into(),
+                passed: true,
+                output: String::new(),
+                duration: Duration::from_millis(250),
+            },
+            TestOutcome {
+                name: "command: test -n \"$HOME\"".into(),
+                passed: false,
+                output: "\x1b[31m<nothing>\x1b[0m & more\x07\n".into(),
+                duration: Duration::from_millis(1500),
+            },
+        ];
+
+        assert_eq!(
+            junit_report("ghcr.io/octocat/os:latest", &outcomes),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites name="bluebuild" tests="2" failures="1" time="1.750">
+  <testsuite name="ghcr.io/octocat/os:latest" tests="2" failures="1" time="1.750">
+    <testcase classname="ghcr.io/octocat/os:latest" name="file-exists: /usr/bin/htop" time="0.250" />
+    <testcase classname="ghcr.io/octocat/os:latest" name="command: test -n &quot;$HOME&quot;" time="1.500">
+      <failure message="command: test -n &quot;$HOME&quot; failed">&lt;nothing&gt; &amp; more</failure>
+    </testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
@@ -0,0 +1,208 @@
+//! The labels that are set on every image, so that a published
+//! image can be traced back to the repo, commit, and base image
+//! that it was built from.
+
+use std::collections::BTreeMap;
+
+use blue_build_recipe::Recipe;
+use bon::builder;
+use miette::{bail, Result};
+
+pub const TITLE: &str = "org.opencontainers.image.title";
+pub const DESCRIPTION: &str = "org.opencontainers.image.description";
+pub const SOURCE: &str = "org.opencontainers.image.source";
+pub const REVISION: &str = "org.opencontainers.image.revision";
+pub const CREATED: &str = "org.opencontainers.image.created";
+pub const VERSION: &str = "org.opencontainers.image.version";
+pub const VENDOR: &str = "org.opencontainers.image.vendor";
+pub const DOCUMENTATION: &str = "org.opencontainers.image.documentation";
+pub const BASE_NAME: &str = "org.opencontainers.image.base.name";
+pub const BASE_DIGEST: &str = "org.opencontainers.image.base.digest";
+pub const ARTIFACTHUB_README: &str = "io.artifacthub.package.readme-url";
+
+const BLUEBUILD_README: &str = "https://raw.githubusercontent.com/blue-build/cli/main/README.md";
+
+/// Checks that the keys of the labels of the recipe follow the
+/// grammar of OCI annotation keys: lowercase letters and digits,
+/// separated by single periods or hyphens.
+///
+/// The keys are written to the Containerfile as they are,
+/// so anything else could break or inject instructions.
+///
+/// # Errors
+/// Will error if a key doesn't follow the grammar.
+pub fn check_keys(labels: &BTreeMap<String, String>) -> Result<()> {
+    for key in labels.keys() {
+        if !key.split(['.', '-']).all(|part| {
+            !part.is_empty()
+                && part
+                    .bytes()
+                    .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit())
+        }) {
+            bail!(
+                "The label key {key:?} must only have lowercase letters and digits, separated by single periods or hyphens"
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Generates the labels of an image, with the `labels`
+/// of the recipe applied on top.
+///
+/// The `created` label isn't included since it changes on every
+/// build, so it's set when the image is built unless the recipe
+/// overrides it. Labels without a value are left out.
+#[builder]
+pub fn generate_default_labels(
+    recipe: &Recipe<'_>,
+    base_digest: &str,
+
+    /// The URL of the repo that the image is built from.
+    repo: Option<&str>,
+
+    /// The full sha of the commit that is built.
+    revision: Option<&str>,
+    version: Option<&str>,
+) -> BTreeMap<String, String> {
+    let repo = repo
+        .map(|repo| repo.trim().trim_end_matches('/'))
+        .filter(|repo| !repo.is_empty());
+    let vendor = repo.and_then(|repo| {
+        repo.split_once("://")
+            .map_or(repo, |(_, rest)| rest)
+            .split('/')
+            .nth(1)
+    });
+
+    let mut labels = [
+        (TITLE, Some(recipe.name.trim().to_owned())),
+        (DESCRIPTION, Some(recipe.description.to_string())),
+        (SOURCE, repo.map(ToOwned::to_owned)),
+        (REVISION, revision.map(ToOwned::to_owned)),
+        (VERSION, version.map(ToOwned::to_owned)),
+        (VENDOR, vendor.map(ToOwned::to_owned)),
+        (DOCUMENTATION, repo.map(|repo| format!("{repo}#readme"))),
+        (
+            BASE_NAME,
+            Some(format!("{}:{}", recipe.base_image, recipe.image_version)),
+        ),
+        (BASE_DIGEST, Some(base_digest.to_owned())),
+        (ARTIFACTHUB_README, Some(BLUEBUILD_README.to_owned())),
+    ]
+    .into_iter()
+    .filter_map(|(key, value)| value.map(|value| (key.to_owned(), value)))
+    .chain(recipe.labels.clone())
+    .collect::<BTreeMap<_, _>>();
+
+    // A label value has to fit on one line in a Containerfile
+    labels.retain(|_, value| !value.trim().is_empty());
+    for value in labels.values_mut() {
+        *value = value.trim().replace('\n', " ");
+    }
+    labels
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use blue_build_recipe::Recipe;
+
+    use rstest::rstest;
+
+    use super::{
+        check_keys, generate_default_labels, ARTIFACTHUB_README, BASE_DIGEST, BASE_NAME,
+        DESCRIPTION, DOCUMENTATION, REVISION, SOURCE, VENDOR, VERSION,
+    };
+
+    fn recipe(labels: &[(&str, &str)]) -> Recipe<'static> {
+        Recipe::builder()
+            .name("my-image")
+            .description("My image.\nWith a second line.\n")
+            .base_image("ghcr.io/ublue-os/silverblue-main")
+            .image_version("41")
+            .labels(
+                labels
+                    .iter()
+                    .map(|(key, value)| ((*key).to_owned(), (*value).to_owned()))
+                    .collect::<BTreeMap<_, _>>(),
+            )
+            .modules_ext(serde_yaml::from_str("modules: []").unwrap())
+            .build()
+    }
+
+    #[test]
+    fn default_labels() {
+        let labels = generate_default_labels()
+            .recipe(&recipe(&[]))
+            .base_digest("sha256:abc")
+            .repo("https://github.com/octocat/my-image/")
+            .revision("0123456789abcdef")
+            .version("41.20241029")
+            .call();
+
+        assert_eq!(labels[SOURCE], "https://github.com/octocat/my-image");
+        assert_eq!(labels[REVISION], "0123456789abcdef");
+        assert_eq!(labels[VERSION], "41.20241029");
+        assert_eq!(labels[VENDOR], "octocat");
+        assert_eq!(
+            labels[DOCUMENTATION],
+            "https://github.com/octocat/my-image#readme"
+        );
+        assert_eq!(labels[BASE_NAME], "ghcr.io/ublue-os/silverblue-main:41");
+        assert_eq!(labels[BASE_DIGEST], "sha256:abc");
+        assert_eq!(labels[DESCRIPTION], "My image. With a second line.");
+    }
+
+    #[test]
+    fn recipe_overrides() {
+        let labels = generate_default_labels()
+            .recipe(&recipe(&[
+                (VENDOR, "Octo Corp"),
+                (DOCUMENTATION, ""),
+                ("com.example.team", "desktop"),
+            ]))
+            .base_digest("sha256:abc")
+            .repo("https://github.com/octocat/my-image")
+            .call();
+
+        assert_eq!(labels[VENDOR], "Octo Corp");
+        assert_eq!(labels["com.example.team"], "desktop");
+        assert!(!labels.contains_key(DOCUMENTATION));
+        assert!(!labels.contains_key(REVISION));
+        assert!(!labels.contains_key(VERSION));
+    }
+
+    #[test]
+    fn local_build() {
+        let labels = generate_default_labels()
+            .recipe(&recipe(&[]))
+            .base_digest("sha256:abc")
+            .repo("")
+            .call();
+
+        assert!(!labels.contains_key(SOURCE));
+        assert!(!labels.contains_key(VENDOR));
+        assert!(!labels.contains_key(DOCUMENTATION));
+    }
+
+    #[rstest]
+    #[case(VENDOR, true)]
+    #[case(ARTIFACTHUB_README, true)]
+    #[case("com.example.team-1", true)]
+    #[case("", false)]
+    #[case("Com.Example", false)]
+    #[case("com..example", false)]
+    #[case("com.-example", false)]
+    #[case(".com.example", false)]
+    #[case("com.example-", false)]
+    #[case("com.example_team", false)]
+    #[case("com.example=\"x\"\nRUN", false)]
+    fn label_keys(#[case] key: &str, #[case] valid: bool) {
+        assert_eq!(
+            check_keys(&BTreeMap::from([(key.to_owned(), "value".to_owned())])).is_ok(),
+            valid
+        );
+    }
+}
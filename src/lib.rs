@@ -1,7 +1,77 @@
 //! The root library for blue-build.
 #![doc = include_str!("../README.md")]
+//!
+//! ## Library
+//!
+//! The commands can be run from other Rust tools. The items
+//! re-exported here are the stable API of the crate, which
+//! only changes in a breaking way with a new major version:
+//! - [`BuildCommand`] builds the images of recipes and returns
+//!   an [`ImageSummary`] of each image with its tags and digest.
+//! - [`GenerateCommand`] renders the Containerfile of a recipe.
+//! - [`ValidateCommand`] checks a recipe and the files it references,
+//!   and returns each problem as a [`ValidationDiagnostic`] with
+//!   its file, span, rule, and severity. The JSON schemas that it
+//!   checks files against can be used on their own with
+//!   [`SchemaValidator`].
+//! - [`BuildError`] is wrapped by the errors of a build, and tells
+//!   which [`BuildStep`] of which recipe failed.
+//!
+//! The commands are made with their builders, which take the same
+//! options as the command line. The other modules are used by the
+//! CLI and can change in any release.
+//!
+//! ```no_run
+//! use blue_build::{BuildCommand, BuildError, GenerateCommand};
+//!
+//! # fn main() -> miette::Result<()> {
+//! let containerfile = GenerateCommand::builder()
+//!     .recipe("recipes/recipe.yml")
+//!     .build()
+//!     .containerfile()?;
+//! println!("{containerfile}");
+//!
+//! match BuildCommand::builder().push(true).build().build_images() {
+//!     Ok(images) => {
+//!         for image in images {
+//!             println!("{} {:?}", image.image, image.digest);
+//!         }
+//!     }
+//!     Err(report) => match report.downcast_ref::<BuildError>() {
+//!         Some(error) => eprintln!("{:?} failed: {:?}", error.step(), error.cause()),
+//!         None => return Err(report),
+//!     },
+//! }
+//! # Ok(())
+//! # }
+//! ```
 
 shadow_rs::shadow!(shadow);
 
+pub mod akmods;
+pub mod build_lock;
 pub mod commands;
+pub mod config;
+pub mod content_hash;
+pub mod error;
+pub mod git_modules;
+pub mod hooks;
+pub mod image_tests;
+pub mod labels;
+pub mod lockfile;
+pub mod module_manifest;
+pub mod module_overrides;
+pub mod notify;
+pub mod output;
+pub mod prompt;
 pub mod rpm_ostree_status;
+pub mod templated_files;
+pub mod ublue;
+
+pub use blue_build_process_management::summary::ImageSummary;
+#[cfg(feature = "validate")]
+pub use commands::validate::{
+    DiagnosticSeverity, DiagnosticSpan, SchemaValidator, ValidateCommand, ValidationDiagnostic,
+};
+pub use commands::{build::BuildCommand, generate::GenerateCommand};
+pub use error::{BuildError, BuildStep};
@@ -0,0 +1,325 @@
+//! The lockfile of a recipe, which records everything that
+//! generating its Containerfile would look up over the network.
+//!
+//! The lockfile is written next to the recipe with `--lock`, like
+//! `recipes/recipe.lock.json` for `recipes/recipe.yml`. Building
+//! with `--offline` requires it, so that a vendored project can be
+//! rebuilt on a machine without network access.
+
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_recipe::Recipe;
+use blue_build_utils::os_family::OsFamily;
+use log::debug;
+use miette::{bail, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::{akmods, git_modules};
+
+const LOCKFILE_VERSION: u32 = 1;
+const LOCK_HELP: &str = "Run `bluebuild generate --lock` for the recipe while online";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Lockfile {
+    /// The version of the lockfile format.
+    pub version: u32,
+
+    /// The base image of the recipe, like
+    /// `ghcr.io/ublue-os/silverblue-main:41`.
+    pub base_image: String,
+
+    /// The digest that the base image resolved to.
+    pub base_digest: String,
+
+    /// The OS version of the base image.
+    pub os_version: u64,
+
+    /// The family of the OS of the base image.
+    #[serde(default)]
+    pub os_family: OsFamily,
+
+    /// The kernel flavor of the base image that the
+    /// akmods modules without a `base` are set to.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub akmods_flavor: Option<String>,
+
+    /// The image that the build scripts are mounted from.
+    pub build_scripts_image: String,
+
+    /// The `source` of every git sourced module
+    /// and the source pinned to its commit.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub git_modules: BTreeMap<String, String>,
+
+    /// The images that are copied into the build, like
+    /// the build scripts and modules, and their digests.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub tools: BTreeMap<String, String>,
+}
+
+impl Lockfile {
+    #[must_use]
+    pub fn new(
+        recipe: &Recipe,
+        base_digest: String,
+        os_version: u64,
+        build_scripts_image: String,
+    ) -> Self {
+        Self {
+            version: LOCKFILE_VERSION,
+            base_image: base_image(recipe),
+            base_digest,
+            os_version,
+            os_family: OsFamily::default(),
+            akmods_flavor: None,
+            build_scripts_image,
+            git_modules: BTreeMap::new(),
+            tools: BTreeMap::new(),
+        }
+    }
+
+    /// The path of the lockfile of the recipe.
+    #[must_use]
+    pub fn path(recipe_path: &Path) -> PathBuf {
+        recipe_path.with_extension("lock.json")
+    }
+
+    /// Reads the lockfile of the recipe.
+    ///
+    /// # Errors
+    /// Will error if the lockfile doesn't exist or can't be parsed.
+    pub fn load(recipe_path: &Path) -> Result<Self> {
+        let path = Self::path(recipe_path);
+
+        if !path.exists() {
+            bail!(
+                help = LOCK_HELP,
+                "The recipe {} doesn't have a lockfile at {}",
+                recipe_path.display(),
+                path.display()
+            );
+        }
+
+        let lockfile: Self = serde_json::from_str(&fs::read_to_string(&path).into_diagnostic()?)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to parse the lockfile {}", path.display()))?;
+
+        if lockfile.version != LOCKFILE_VERSION {
+            bail!(
+                help = LOCK_HELP,
+                "The lockfile {} has version {}, but only version {LOCKFILE_VERSION} is supported",
+                path.display(),
+                lockfile.version
+            );
+        }
+        debug!("Loaded lockfile {}", path.display());
+
+        Ok(lockfile)
+    }
+
+    /// Writes the lockfile of the recipe.
+    ///
+    /// # Errors
+    /// Will error if the lockfile can't be written.
+    pub fn save(&self, recipe_path: &Path) -> Result<()> {
+        let path = Self::path(recipe_path);
+        let mut contents = serde_json::to_string_pretty(self).into_diagnostic()?;
+        contents.push('\n');
+
+        fs::write(&path, contents)
+            .into_diagnostic()
+            .with_context(|| format!("Failed to write the lockfile {}", path.display()))
+    }
+
+    /// Records the commits that the git sourced modules are pinned to.
+    ///
+    /// The `sources` are the sources of the modules before they were
+    /// pinned, in the order that [`git_modules::pin`] returns them.
+    #[must_use]
+    pub fn with_git_modules(mut self, sources: Vec<String>, pinned: Vec<String>) -> Self {
+        self.git_modules = sources.into_iter().zip(pinned).collect();
+        self
+    }
+
+    /// Records the family of the OS of the base image.
+    #[must_use]
+    pub const fn with_os_family(mut self, os_family: OsFamily) -> Self {
+        self.os_family = os_family;
+        self
+    }
+
+    /// Records the kernel flavor that the akmods modules are set to.
+    #[must_use]
+    pub fn with_akmods_flavor(mut self, akmods_flavor: Option<String>) -> Self {
+        self.akmods_flavor = akmods_flavor;
+        self
+    }
+
+    /// Records the digests of the images that are copied into the build.
+    #[must_use]
+    pub fn with_tools(mut self, tools: BTreeMap<String, String>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    /// Makes sure the lockfile was written for the recipe, pins
+    /// its git sourced modules to the commits in the lockfile, so
+    /// that they're taken from the cache, and sets the kernel
+    /// flavor of its akmods modules.
+    ///
+    /// # Errors
+    /// Will error if the base image of the recipe changed or a git
+    /// sourced module isn't in the lockfile.
+    pub fn apply(&self, recipe: &mut Recipe) -> Result<()> {
+        let recipe_base_image = base_image(recipe);
+        if recipe_base_image != self.base_image {
+            bail!(
+                help = LOCK_HELP,
+                "The lockfile is for the base image {}, but the recipe uses {recipe_base_image}",
+                self.base_image
+            );
+        }
+
+        for module in git_modules::modules_mut(recipe) {
+            if module.get_git_source().is_none() {
+                continue;
+            }
+            let source = module.source.as_deref().unwrap_or_default();
+
+            let Some(pinned) = self.git_modules.get(source) else {
+                bail!(
+                    help = LOCK_HELP,
+                    "The module {} from {source} isn't in the lockfile",
+                    module.module_type
+                );
+            };
+            module.source = Some(pinned.clone().into());
+        }
+
+        if let Some(akmods_flavor) = self.akmods_flavor.as_deref() {
+            akmods::set_flavor(recipe, akmods_flavor);
+        }
+        Ok(())
+    }
+}
+
+/// The sources of the git sourced modules of the recipe,
+/// in the order that [`git_modules::pin`] pins them.
+#[must_use]
+pub fn git_sources(recipe: &Recipe) -> Vec<String> {
+    recipe
+        .all_modules()
+        .filter(|module| module.get_git_source().is_some())
+        .filter_map(|module| module.source.as_deref().map(ToOwned::to_owned))
+        .collect()
+}
+
+fn base_image(recipe: &Recipe) -> String {
+    format!("{}:{}", recipe.base_image, recipe.image_version)
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_recipe::{Module, ModuleExt, ModuleRequiredFields, Recipe};
+    use blue_build_utils::os_family::OsFamily;
+
+    use super::{git_sources, Lockfile};
+
+    const SOURCE: &str = "git+https://github.com/octocat/modules.git#ref=main";
+    const PINNED: &str =
+        "git+https://github.com/octocat/modules.git#ref=0123456789abcdef0123456789abcdef01234567";
+
+    fn recipe(base_image: &str) -> Recipe<'_> {
+        Recipe::builder()
+            .name("test")
+            .description("test")
+            .base_image(base_image)
+            .image_version("41")
+            .modules_ext(
+                ModuleExt::builder()
+                    .modules(vec![
+                        Module::builder()
+                            .required_fields(
+                                ModuleRequiredFields::builder()
+                                    .module_type("rpm-ostree")
+                                    .build(),
+                            )
+                            .build(),
+                        Module::builder()
+                            .required_fields(
+                                ModuleRequiredFields::builder()
+                                    .module_type("hello")
+                                    .source(SOURCE)
+                                    .build(),
+                            )
+                            .build(),
+                    ])
+                    .build(),
+            )
+            .build()
+    }
+
+    #[test]
+    fn apply_pins_git_modules() {
+        let mut recipe = recipe("ghcr.io/ublue-os/silverblue-main");
+        let lockfile = Lockfile::new(&recipe, "sha256:abc".into(), 41, "scripts".into())
+            .with_git_modules(git_sources(&recipe), vec![PINNED.into()]);
+
+        assert_eq!(lockfile.base_image, "ghcr.io/ublue-os/silverblue-main:41");
+
+        lockfile.apply(&mut recipe).unwrap();
+        assert_eq!(
+            recipe
+                .all_modules()
+                .filter_map(|module| module.source.as_deref())
+                .collect::<Vec<_>>(),
+            [PINNED]
+        );
+    }
+
+    #[test]
+    fn apply_changed_base_image() {
+        let lockfile = Lockfile::new(
+            &recipe("ghcr.io/ublue-os/silverblue-main"),
+            "sha256:abc".into(),
+            41,
+            "scripts".into(),
+        );
+
+        assert!(lockfile
+            .apply(&mut recipe("ghcr.io/ublue-os/bazzite"))
+            .is_err());
+    }
+
+    #[test]
+    fn apply_missing_git_module() {
+        let mut recipe = recipe("ghcr.io/ublue-os/silverblue-main");
+        let lockfile = Lockfile::new(&recipe, "sha256:abc".into(), 41, "scripts".into());
+
+        assert!(lockfile.apply(&mut recipe).is_err());
+    }
+
+    #[test]
+    fn os_family_defaults_to_fedora() {
+        let lockfile: Lockfile = serde_json::from_str(
+            r#"{
+                "version": 1,
+                "base-image": "ghcr.io/ublue-os/silverblue-main:41",
+                "base-digest": "sha256:abc",
+                "os-version": 41,
+                "build-scripts-image": "scripts"
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(lockfile.os_family, OsFamily::Fedora);
+
+        let lockfile = lockfile.with_os_family(OsFamily::Debian);
+        let json = serde_json::to_string(&lockfile).unwrap();
+        assert!(json.contains(r#""os-family":"debian""#));
+    }
+}
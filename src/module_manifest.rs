@@ -0,0 +1,231 @@
+//! The list of modules that built an image, with the version and
+//! digest of where each module came from.
+//!
+//! It's set as a label on the image and written to
+//! `/usr/share/bluebuild/modules.json` so that a running
+//! system can tell which module versions built it.
+
+use std::collections::BTreeMap;
+
+use blue_build_recipe::{ModuleRequiredFields, Recipe};
+use blue_build_utils::constants::MODULES_IMAGE;
+use miette::{IntoDiagnostic, Result};
+use oci_distribution::Reference;
+use serde::Serialize;
+
+use crate::module_overrides::ModuleOverride;
+
+/// Modules that are part of the Containerfile
+/// instead of being run from a modules image.
+const BUILT_IN_MODULES: &[&str] = &["containerfile", "copy", "package"];
+
+/// A module that was run during the build.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ModuleEntry {
+    #[serde(rename = "type")]
+    pub module_type: String,
+
+    /// The stage the module ran in, or `None` for the final image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub stage: Option<String>,
+
+    /// The image, repo, or directory that the module came from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source: Option<String>,
+
+    /// The tag of the image or the commit of the repo.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    /// The digest of the image or the hash of the directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub digest: Option<String>,
+}
+
+/// Lists the modules of a recipe and its stages in the order they run.
+///
+/// The `tools` are the digests of the verified images, which
+/// includes the modules image and the OCI sourced modules.
+#[must_use]
+pub fn module_manifest(
+    recipe: &Recipe,
+    tools: &BTreeMap<String, String>,
+    module_overrides: &[ModuleOverride],
+) -> Vec<ModuleEntry> {
+    let stages = recipe
+        .stages_ext
+        .iter()
+        .flat_map(|stages_ext| &stages_ext.stages)
+        .filter_map(|stage| stage.required_fields.as_ref())
+        .flat_map(|stage| {
+            stage
+                .modules_ext
+                .modules
+                .iter()
+                .map(move |module| (Some(stage.name.to_string()), module))
+        });
+
+    stages
+        .chain(
+            recipe
+                .modules_ext
+                .modules
+                .iter()
+                .map(|module| (None, module)),
+        )
+        .filter_map(|(stage, module)| {
+            module
+                .required_fields
+                .as_ref()
+                .map(|module| entry(module, stage, tools, module_overrides))
+        })
+        .collect()
+}
+
+/// Renders the [`module_manifest`] as compact JSON.
+///
+/// # Errors
+/// Will error if the manifest can't be serialized.
+pub fn to_json(
+    recipe: &Recipe,
+    tools: &BTreeMap<String, String>,
+    module_overrides: &[ModuleOverride],
+) -> Result<String> {
+    serde_json::to_string(&module_manifest(recipe, tools, module_overrides)).into_diagnostic()
+}
+
+fn entry(
+    module: &ModuleRequiredFields,
+    stage: Option<String>,
+    tools: &BTreeMap<String, String>,
+    module_overrides: &[ModuleOverride],
+) -> ModuleEntry {
+    let module_type = module.module_type.to_string();
+    let mut entry = ModuleEntry {
+        module_type,
+        stage,
+        source: None,
+        version: None,
+        digest: None,
+    };
+
+    if let Some(module_override) = module_overrides
+        .iter()
+        .find(|module_override| module_override.name == entry.module_type)
+    {
+        entry.source = Some(String::from("module-path"));
+        entry.digest = Some(module_override.hash.clone());
+    } else if let Some(source) = module.get_git_source() {
+        entry.source = Some(source.url.to_owned());
+        entry.version = source.git_ref.map(ToOwned::to_owned);
+    } else if !BUILT_IN_MODULES.contains(&&*entry.module_type) {
+        let image = module
+            .get_oci_source()
+            .or_else(|| module.get_non_local_source())
+            .unwrap_or(MODULES_IMAGE);
+
+        entry.source = Some(image.to_owned());
+        entry.version = image
+            .parse::<Reference>()
+            .ok()
+            .and_then(|image| image.tag().map(ToOwned::to_owned));
+        entry.digest = tools.get(image).cloned();
+    }
+    entry
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use blue_build_recipe::Recipe;
+    use blue_build_utils::constants::MODULES_IMAGE;
+
+    use crate::module_overrides::ModuleOverride;
+
+    use super::{module_manifest, ModuleEntry};
+
+    const RECIPE: &str = r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+stages:
+  - name: builder
+    from: rust
+    modules:
+      - type: containerfile
+        snippets:
+          - RUN cargo build
+modules:
+  - type: rpm-ostree
+    install: [micro]
+  - type: script
+    source: oci://ghcr.io/octocat/modules:v1
+    scripts: [example.sh]
+  - type: files
+    source: git+https://github.com/octocat/modules#ref=0123456789abcdef
+  - type: signing
+";
+
+    fn entry(
+        module_type: &str,
+        stage: Option<&str>,
+        source: Option<&str>,
+        version: Option<&str>,
+        digest: Option<&str>,
+    ) -> ModuleEntry {
+        ModuleEntry {
+            module_type: module_type.into(),
+            stage: stage.map(Into::into),
+            source: source.map(Into::into),
+            version: version.map(Into::into),
+            digest: digest.map(Into::into),
+        }
+    }
+
+    #[test]
+    fn manifest() {
+        let recipe = serde_yaml::from_str::<Recipe>(RECIPE).unwrap();
+        let tools = BTreeMap::from([
+            (MODULES_IMAGE.to_owned(), "sha256:modules".to_owned()),
+            (
+                "ghcr.io/octocat/modules:v1".to_owned(),
+                "sha256:octocat".to_owned(),
+            ),
+        ]);
+        let overrides = [ModuleOverride {
+            name: "signing".into(),
+            hash: "abc".into(),
+        }];
+
+        assert_eq!(
+            module_manifest(&recipe, &tools, &overrides),
+            [
+                entry("containerfile", Some("builder"), None, None, None),
+                entry(
+                    "rpm-ostree",
+                    None,
+                    Some(MODULES_IMAGE),
+                    Some("latest"),
+                    Some("sha256:modules")
+                ),
+                entry(
+                    "script",
+                    None,
+                    Some("ghcr.io/octocat/modules:v1"),
+                    Some("v1"),
+                    Some("sha256:octocat")
+                ),
+                entry(
+                    "files",
+                    None,
+                    Some("https://github.com/octocat/modules"),
+                    Some("0123456789abcdef"),
+                    None
+                ),
+                entry("signing", None, Some("module-path"), None, Some("abc")),
+            ]
+        );
+    }
+}
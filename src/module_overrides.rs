@@ -0,0 +1,164 @@
+//! Overrides of where modules are sourced from with `--module-path`.
+//!
+//! The module directories are copied into the build context since
+//! they're usually outside of it. The Containerfile then copies them
+//! over the other modules in their own stage, and every module with
+//! an overridden type is run from that stage.
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use blue_build_process_management::signal_handler::{CleanupGuard, CleanupItem};
+use blue_build_utils::{constants::MODULE_OVERRIDES_PATH, copy_dir};
+use log::{debug, trace};
+use miette::{bail, IntoDiagnostic, Result};
+use uuid::Uuid;
+
+use crate::content_hash::ContentHasher;
+
+/// A module that is sourced from a local directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleOverride {
+    /// The type of the module, which is the name of its directory.
+    pub name: String,
+
+    /// The hash of the module's files.
+    pub hash: String,
+}
+
+impl ModuleOverride {
+    /// Describes the source of the module for the content hash.
+    #[must_use]
+    pub fn source(&self) -> String {
+        format!("module-path:{}:{}", self.name, self.hash)
+    }
+}
+
+/// Copies the module directories into the build context.
+///
+/// # Errors
+/// Will error if a path isn't a directory, two paths are
+/// for the same module, or the files can't be copied.
+pub fn prepare(module_paths: &[PathBuf]) -> Result<Vec<ModuleOverride>> {
+    prepare_in(module_paths, Path::new(MODULE_OVERRIDES_PATH))
+}
+
+fn prepare_in(module_paths: &[PathBuf], context_dir: &Path) -> Result<Vec<ModuleOverride>> {
+    trace!(
+        "module_overrides::prepare_in({module_paths:?}, {})",
+        context_dir.display()
+    );
+
+    let mut overrides: Vec<ModuleOverride> = Vec::new();
+
+    for path in module_paths {
+        if !path.is_dir() {
+            bail!("The module path {} must be a directory", path.display());
+        }
+        let Some(name) = path
+            .canonicalize()
+            .into_diagnostic()?
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+        else {
+            bail!("Unable to get the module name of {}", path.display());
+        };
+        if overrides.iter().any(|o| o.name == name) {
+            bail!("More than one module path was given for module {name}");
+        }
+
+        let mut hasher = ContentHasher::with_root(path)?;
+        hasher.add_path(path)?;
+
+        debug!("Using module {name} from {}", path.display());
+        copy_module(path, &context_dir.join(&name))?;
+
+        overrides.push(ModuleOverride {
+            name,
+            hash: hasher.finish(),
+        });
+    }
+
+    // Modules left over from earlier runs would
+    // also override the published modules
+    if !overrides.is_empty() {
+        for entry in fs::read_dir(context_dir).into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            if !name.starts_with('.') && !overrides.iter().any(|o| o.name == name) {
+                fs::remove_dir_all(entry.path()).into_diagnostic()?;
+            }
+        }
+    }
+
+    Ok(overrides)
+}
+
+/// Replaces `dest` with a copy of `module`.
+///
+/// The copy is made next to `dest` and renamed into place so
+/// that builds running at the same time don't see a partial copy.
+fn copy_module(module: &Path, dest: &Path) -> Result<()> {
+    let parent = dest.parent().unwrap_or_else(|| Path::new("."));
+    let tmp = parent.join(format!(".tmp-{}", Uuid::new_v4()));
+    let _tmp_cleanup = CleanupGuard::new(CleanupItem::Dir(tmp.clone()));
+
+    let result = copy_dir(module, &tmp).and_then(|()| {
+        if dest.exists() {
+            fs::remove_dir_all(dest).into_diagnostic()?;
+        }
+        match fs::rename(&tmp, dest) {
+            // Another build copied the module first
+            Err(_) if dest.exists() => fs::remove_dir_all(&tmp).into_diagnostic(),
+            result => result.into_diagnostic(),
+        }
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&tmp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod test {
+    use std::fs;
+
+    use tempfile::TempDir;
+
+    use super::prepare_in;
+
+    #[test]
+    fn prepare_overrides() {
+        let dir = TempDir::new().unwrap();
+        let module = dir.path().join("dev/rpm-ostree");
+        fs::create_dir_all(&module).unwrap();
+        fs::write(module.join("rpm-ostree.sh"), "echo one").unwrap();
+
+        let context = dir.path().join("context");
+        fs::create_dir_all(context.join("stale")).unwrap();
+
+        let first = prepare_in(std::slice::from_ref(&module), &context).unwrap();
+        assert_eq!(first.len(), 1);
+        assert_eq!(first[0].name, "rpm-ostree");
+        assert_eq!(
+            fs::read_to_string(context.join("rpm-ostree/rpm-ostree.sh")).unwrap(),
+            "echo one"
+        );
+        assert!(!context.join("stale").exists());
+
+        fs::write(module.join("rpm-ostree.sh"), "echo two").unwrap();
+        let second = prepare_in(std::slice::from_ref(&module), &context).unwrap();
+        assert_ne!(first[0].hash, second[0].hash);
+        assert_eq!(
+            fs::read_to_string(context.join("rpm-ostree/rpm-ostree.sh")).unwrap(),
+            "echo two"
+        );
+
+        assert!(prepare_in(&[module.clone(), module], &context).is_err());
+        assert!(prepare_in(&[dir.path().join("missing")], &context).is_err());
+    }
+}
@@ -0,0 +1,340 @@
+//! Posts the results of a build to the targets in the
+//! `notifications` of the user config.
+//!
+//! ```toml
+//! [[notifications]]
+//! type = "webhook"
+//! url = "https://example.com/hooks/bluebuild"
+//!
+//! [[notifications]]
+//! type = "discord"
+//! url = "https://discord.com/api/webhooks/<id>/<token>"
+//! on = "failure"
+//!
+//! [[notifications]]
+//! type = "matrix"
+//! homeserver = "https://matrix.org"
+//! room = "!abcdef:matrix.org"
+//! ```
+//!
+//! Webhooks get the results as JSON, Discord and Matrix get a short
+//! message. The Matrix access token is read from `BB_MATRIX_TOKEN`
+//! when the entry doesn't set a `token`. A failed notification is
+//! only logged, it never fails the build.
+//!
+//! Notifications are only read from the user config, since the
+//! repo config could otherwise send the results and the token to
+//! any server.
+
+use std::{env, fmt::Write as _, time::Duration};
+
+use blue_build_process_management::{block_on, summary::BuildSummary};
+use blue_build_utils::constants::{
+    BB_MATRIX_TOKEN, CI_JOB_URL, GITHUB_RESPOSITORY, GITHUB_RUN_ID, GITHUB_SERVER_URL,
+};
+use log::{debug, trace, warn};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use toml::Value;
+
+use crate::config::Config;
+
+const NOTIFICATIONS_KEY: &str = "notifications";
+const DISCORD_MAX_LEN: usize = 2000;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Where a notification is posted to.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+enum Target {
+    /// Posts the results as JSON.
+    Webhook { url: String },
+
+    /// Posts a message to a Discord webhook.
+    Discord { url: String },
+
+    /// Sends a message to a Matrix room. The access token
+    /// is read from `BB_MATRIX_TOKEN` when it isn't set.
+    Matrix {
+        homeserver: String,
+        room: String,
+        token: Option<String>,
+    },
+}
+
+/// Which builds a notification is sent for.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+enum On {
+    #[default]
+    Always,
+    Success,
+    Failure,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct Notification {
+    #[serde(flatten)]
+    target: Target,
+
+    #[serde(default)]
+    on: On,
+}
+
+/// The results of a build that are posted to a webhook.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct Payload<'a> {
+    success: bool,
+    duration_secs: u64,
+    logs_url: Option<String>,
+
+    #[serde(flatten)]
+    summary: &'a BuildSummary,
+}
+
+/// Sends the notifications in the config files for the build.
+///
+/// Errors are logged, since a build that
+/// finished shouldn't fail because of them.
+pub fn send(summary: &BuildSummary, duration: Duration) {
+    let notifications = match Config::load()
+        .and_then(|config| notifications(config.values().get(NOTIFICATIONS_KEY)))
+    {
+        Ok(notifications) => notifications,
+        Err(e) => {
+            warn!("Invalid notifications in the config:\n{e:?}");
+            return;
+        }
+    };
+    trace!("notify::send({notifications:?})");
+
+    let payload = Payload {
+        success: summary.success,
+        duration_secs: duration.as_secs(),
+        logs_url: logs_url(),
+        summary,
+    };
+
+    for notification in notifications
+        .iter()
+        .filter(|notification| match notification.on {
+            On::Always => true,
+            On::Success => summary.success,
+            On::Failure => !summary.success,
+        })
+    {
+        if let Err(e) = block_on(post(&notification.target, &payload)) {
+            warn!("Failed to send a build notification:\n{e:?}");
+        }
+    }
+}
+
+fn notifications(value: Option<&Value>) -> Result<Vec<Notification>> {
+    value.map_or_else(
+        || Ok(Vec::new()),
+        |value| {
+            value
+                .clone()
+                .try_into()
+                .into_diagnostic()
+                .context("`notifications` must be a list of tables with a `type`")
+        },
+    )
+}
+
+async fn post(target: &Target, payload: &Payload<'_>) -> Result<()> {
+    let client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .build()
+        .into_diagnostic()?;
+
+    let request = match target {
+        Target::Webhook { url } => {
+            debug!("Sending the build results to {url}");
+            client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(serde_json::to_string(payload).into_diagnostic()?)
+        }
+        Target::Discord { url } => {
+            debug!("Sending the build results to Discord");
+            client
+                .post(url)
+                .header("Content-Type", "application/json")
+                .body(
+                    json!({ "content": truncate(&message(payload), DISCORD_MAX_LEN) }).to_string(),
+                )
+        }
+        Target::Matrix {
+            homeserver,
+            room,
+            token,
+        } => {
+            debug!("Sending the build results to {room}");
+            let Some(token) = token.clone().or_else(|| env::var(BB_MATRIX_TOKEN).ok()) else {
+                bail!("The Matrix notification needs a `token` or {BB_MATRIX_TOKEN} to be set");
+            };
+
+            client
+                .put(format!(
+                    "{}/_matrix/client/v3/rooms/{}/send/m.room.message/bluebuild-{}",
+                    homeserver.trim_end_matches('/'),
+                    urlencoding::encode(room),
+                    uuid::Uuid::new_v4()
+                ))
+                .bearer_auth(token)
+                .header("Content-Type", "application/json")
+                .body(json!({ "msgtype": "m.text", "body": message(payload) }).to_string())
+        }
+    };
+
+    let response = request.send().await.into_diagnostic()?;
+    if !response.status().is_success() {
+        return Err(miette!(
+            "The notification was rejected with {}: {}",
+            response.status(),
+            response.text().await.unwrap_or_default()
+        ));
+    }
+    Ok(())
+}
+
+/// The link to the logs of the CI job that is running the build.
+fn logs_url() -> Option<String> {
+    env::var(CI_JOB_URL).ok().or_else(|| {
+        Some(format!(
+            "{}/{}/actions/runs/{}",
+            env::var(GITHUB_SERVER_URL).ok()?,
+            env::var(GITHUB_RESPOSITORY).ok()?,
+            env::var(GITHUB_RUN_ID).ok()?,
+        ))
+    })
+}
+
+/// A short message about the build for chat rooms.
+fn message(payload: &Payload) -> String {
+    let mut out = format!(
+        "{} BlueBuild build {} in {}",
+        if payload.success { "✅" } else { "❌" },
+        if payload.success {
+            "succeeded"
+        } else {
+            "failed"
+        },
+        format_duration(payload.duration_secs)
+    );
+
+    for image in &payload.summary.images {
+        _ = write!(out, "\n- {} ({})", image.image, image.tags.join(", "));
+        if let Some(digest) = &image.digest {
+            _ = write!(out, " {digest}");
+        }
+    }
+    if let Some(logs_url) = &payload.logs_url {
+        _ = write!(out, "\nLogs: {logs_url}");
+    }
+    out
+}
+
+fn format_duration(secs: u64) -> String {
+    match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!("{s}s"),
+        (0, m, s) => format!("{m}m {s}s"),
+        (h, m, s) => format!("{h}h {m}m {s}s"),
+    }
+}
+
+fn truncate(text: &str, max_chars: usize) -> String {
+    if text.chars().count() <= max_chars {
+        return text.to_owned();
+    }
+    let mut out = text.chars().take(max_chars - 1).collect::<String>();
+    out.push('…');
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_process_management::summary::{BuildSummary, ImageSummary};
+    use toml::Table;
+
+    use super::{message, notifications, truncate, Notification, On, Payload, Target};
+
+    #[test]
+    fn parse_notifications() {
+        let config = r#"
+[[notifications]]
+type = "webhook"
+url = "https://example.com/hook"
+
+[[notifications]]
+type = "matrix"
+homeserver = "https://matrix.org"
+room = "!abc:matrix.org"
+on = "failure"
+"#
+        .parse::<Table>()
+        .unwrap();
+
+        assert_eq!(
+            notifications(config.get("notifications")).unwrap(),
+            [
+                Notification {
+                    target: Target::Webhook {
+                        url: "https://example.com/hook".into()
+                    },
+                    on: On::Always,
+                },
+                Notification {
+                    target: Target::Matrix {
+                        homeserver: "https://matrix.org".into(),
+                        room: "!abc:matrix.org".into(),
+                        token: None,
+                    },
+                    on: On::Failure,
+                },
+            ]
+        );
+
+        let invalid = "[[notifications]]\ntype = \"email\""
+            .parse::<Table>()
+            .unwrap();
+        assert!(notifications(invalid.get("notifications")).is_err());
+        assert_eq!(notifications(None).unwrap(), []);
+    }
+
+    #[test]
+    fn chat_message() {
+        let summary = BuildSummary {
+            success: true,
+            images: vec![ImageSummary::builder()
+                .recipe("recipes/recipe.yml")
+                .image("ghcr.io/octocat/my-image")
+                .tags(vec!["latest".into(), "41".into()])
+                .digest("sha256:abc".into())
+                .build()],
+            ..BuildSummary::default()
+        };
+        let payload = Payload {
+            success: true,
+            duration_secs: 3723,
+            logs_url: Some("https://github.com/octocat/my-image/actions/runs/1".into()),
+            summary: &summary,
+        };
+
+        assert_eq!(
+            message(&payload),
+            "✅ BlueBuild build succeeded in 1h 2m 3s\n\
+            - ghcr.io/octocat/my-image (latest, 41) sha256:abc\n\
+            Logs: https://github.com/octocat/my-image/actions/runs/1"
+        );
+    }
+
+    #[test]
+    fn truncate_message() {
+        assert_eq!(truncate("abcdef", 10), "abcdef");
+        assert_eq!(truncate("abcdef", 4), "abc…");
+    }
+}
@@ -0,0 +1,116 @@
+//! Shared output handling for informational commands.
+//!
+//! Commands that report information build a serializable
+//! value and print it with [`print`], which respects the
+//! global `--format` flag. The `table` format is meant to
+//! be read by people and the `json` and `yaml` formats are
+//! meant for scripts.
+
+use std::sync::RwLock;
+
+use clap::ValueEnum;
+use miette::{IntoDiagnostic, Result};
+use serde::Serialize;
+
+static FORMAT: RwLock<Option<OutputFormat>> = RwLock::new(None);
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable output.
+    #[default]
+    #[value(alias = "markdown")]
+    Table,
+
+    /// Pretty printed JSON.
+    Json,
+
+    /// YAML.
+    Yaml,
+}
+
+/// Values that can be displayed as human readable output.
+pub trait TableOutput {
+    /// Renders the value for the `table` format.
+    fn to_table(&self) -> String;
+}
+
+/// Sets the format chosen with the global `--format` flag.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn set_format(format: Option<OutputFormat>) {
+    *FORMAT.write().expect("Should lock FORMAT") = format;
+}
+
+/// The format chosen with the global `--format` flag
+/// or `None` if the flag wasn't used.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+#[must_use]
+pub fn format() -> Option<OutputFormat> {
+    *FORMAT.read().expect("Should lock FORMAT")
+}
+
+/// Renders `value` in the given format.
+///
+/// # Errors
+/// Will error if the value can't be serialized.
+pub fn render<T>(value: &T, format: OutputFormat) -> Result<String>
+where
+    T: Serialize + TableOutput,
+{
+    match format {
+        OutputFormat::Table => Ok(value.to_table()),
+        OutputFormat::Json => serde_json::to_string_pretty(value)
+            .map(|json| json + "\n")
+            .into_diagnostic(),
+        OutputFormat::Yaml => serde_yaml::to_string(value).into_diagnostic(),
+    }
+}
+
+/// Prints `value` to stdout in the format chosen
+/// with the global `--format` flag.
+///
+/// # Errors
+/// Will error if the value can't be serialized.
+pub fn print<T>(value: &T) -> Result<()>
+where
+    T: Serialize + TableOutput,
+{
+    print!("{}", render(value, format().unwrap_or_default())?);
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use rstest::rstest;
+    use serde::Serialize;
+
+    use super::{render, OutputFormat, TableOutput};
+
+    #[derive(Serialize)]
+    struct Example {
+        name: &'static str,
+        count: u8,
+    }
+
+    impl TableOutput for Example {
+        fn to_table(&self) -> String {
+            format!("{}: {}\n", self.name, self.count)
+        }
+    }
+
+    #[rstest]
+    #[case(OutputFormat::Table, "test: 2\n")]
+    #[case(OutputFormat::Json, "{\n  \"name\": \"test\",\n  \"count\": 2\n}\n")]
+    #[case(OutputFormat::Yaml, "name: test\ncount: 2\n")]
+    fn render_formats(#[case] format: OutputFormat, #[case] expected: &str) {
+        let example = Example {
+            name: "test",
+            count: 2,
+        };
+
+        assert_eq!(render(&example, format).unwrap(), expected);
+    }
+}
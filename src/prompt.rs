@@ -0,0 +1,88 @@
+//! Guards for interactive prompts.
+//!
+//! Commands check [`ensure_interactive`] before asking
+//! questions so that they fail right away instead of
+//! waiting for input that will never come, like in CI.
+
+use std::{
+    io::{self, IsTerminal},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use miette::{bail, Result};
+
+static NON_INTERACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Sets whether the global `--non-interactive` flag was used.
+pub fn set_non_interactive(non_interactive: bool) {
+    NON_INTERACTIVE.store(non_interactive, Ordering::Relaxed);
+}
+
+/// Whether the user can be prompted for input.
+///
+/// This is `false` when the `--non-interactive` flag
+/// was used or when stdin isn't a terminal.
+#[must_use]
+pub fn is_interactive() -> bool {
+    non_interactive_reason(NON_INTERACTIVE.load(Ordering::Relaxed)).is_none()
+}
+
+/// Errors if the user can't be prompted for input.
+///
+/// The `prompt` describes what would have been asked for
+/// and the `help` tells the user how to provide it instead.
+///
+/// # Errors
+/// Will error if the `--non-interactive` flag was used
+/// or stdin isn't a terminal.
+pub fn ensure_interactive(prompt: &str, help: &str) -> Result<()> {
+    ensure(
+        non_interactive_reason(NON_INTERACTIVE.load(Ordering::Relaxed)),
+        prompt,
+        help,
+    )
+}
+
+fn ensure(reason: Option<&str>, prompt: &str, help: &str) -> Result<()> {
+    match reason {
+        None => Ok(()),
+        Some(reason) => bail!(
+            help = help.to_owned(),
+            "Can't prompt for {prompt}, {reason}"
+        ),
+    }
+}
+
+fn non_interactive_reason(non_interactive: bool) -> Option<&'static str> {
+    if non_interactive {
+        Some("running in non-interactive mode")
+    } else if !io::stdin().is_terminal() {
+        Some("stdin isn't a terminal")
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ensure, non_interactive_reason};
+
+    #[test]
+    fn non_interactive_fails() {
+        let err = ensure(
+            non_interactive_reason(true),
+            "a username",
+            "Pass --username",
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "Can't prompt for a username, running in non-interactive mode"
+        );
+        assert_eq!(
+            err.help().map(|help| help.to_string()).as_deref(),
+            Some("Pass --username")
+        );
+    }
+}
@@ -7,19 +7,74 @@ use serde::Deserialize;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct RpmOstreeStatus<'a> {
-    deployments: Cow<'a, [RpmOstreeDeployments<'a>]>,
+    deployments: Cow<'a, [RpmOstreeDeployment<'a>]>,
+
+    #[serde(default, alias = "transaction")]
     transactions: Option<Cow<'a, [Cow<'a, str>]>>,
 }
 
-#[derive(Debug, Clone, Deserialize)]
+/// A deployment of the system.
+#[derive(Debug, Clone, Default, Deserialize)]
 #[serde(rename_all = "kebab-case")]
-struct RpmOstreeDeployments<'a> {
-    container_image_reference: Cow<'a, str>,
-    booted: bool,
-    staged: bool,
+pub struct RpmOstreeDeployment<'a> {
+    #[serde(default)]
+    pub id: Option<Cow<'a, str>>,
+
+    /// The ostree commit of the deployment.
+    #[serde(default)]
+    pub checksum: Option<Cow<'a, str>>,
+
+    #[serde(default)]
+    pub version: Option<Cow<'a, str>>,
+
+    /// When the commit was created, in seconds since the epoch.
+    #[serde(default)]
+    pub timestamp: Option<i64>,
+
+    /// The image the deployment was created from, like
+    /// `ostree-image-signed:docker://ghcr.io/octocat/my-image`.
+    ///
+    /// This is `None` for deployments that aren't from an image.
+    #[serde(default)]
+    pub container_image_reference: Option<Cow<'a, str>>,
+
+    #[serde(default)]
+    pub container_image_reference_digest: Option<Cow<'a, str>>,
+
+    #[serde(default)]
+    pub booted: bool,
+
+    #[serde(default)]
+    pub staged: bool,
+
+    /// Whether the deployment is kept when newer ones are deployed.
+    #[serde(default)]
+    pub pinned: bool,
+
+    /// The packages that were requested to be layered.
+    #[serde(default)]
+    pub requested_packages: Vec<Cow<'a, str>>,
+
+    /// The packages that are layered on the deployment.
+    #[serde(default)]
+    pub packages: Vec<Cow<'a, str>>,
+
+    /// The packages that were requested to be removed from the image.
+    #[serde(default)]
+    pub requested_base_removals: Vec<Cow<'a, str>>,
+}
+
+/// A transaction that's in progress.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpmOstreeTransaction<'a> {
+    /// The operation, like `Upgrade` or `Rebase`.
+    pub method: &'a str,
+
+    /// The D-Bus name of the client that started the transaction.
+    pub initiator: Option<&'a str>,
 }
 
-impl<'a> RpmOstreeStatus<'a> {
+impl RpmOstreeStatus<'_> {
     /// Creates a status struct for `rpm-ostree`.
     ///
     /// # Errors
@@ -47,14 +102,74 @@ impl<'a> RpmOstreeStatus<'a> {
         self.transactions.as_ref().is_some_and(|tr| !tr.is_empty())
     }
 
+    /// Gets the transaction that's in progress.
+    #[must_use]
+    pub fn transaction(&self) -> Option<RpmOstreeTransaction<'_>> {
+        let transaction = self.transactions.as_deref()?;
+
+        Some(RpmOstreeTransaction {
+            method: transaction.first()?,
+            initiator: transaction.get(1).map(AsRef::as_ref),
+        })
+    }
+
+    /// All deployments, in the order they're listed
+    /// in the boot menu.
+    #[must_use]
+    pub fn deployments(&self) -> &[RpmOstreeDeployment<'_>] {
+        &self.deployments
+    }
+
+    /// The deployment that the system is booted into.
+    #[must_use]
+    pub fn booted_deployment(&self) -> Option<&RpmOstreeDeployment<'_>> {
+        self.deployments.iter().find(|deployment| deployment.booted)
+    }
+
+    /// The deployment that's staged to be
+    /// finalized when the system shuts down.
+    #[must_use]
+    pub fn staged_deployment(&self) -> Option<&RpmOstreeDeployment<'_>> {
+        self.deployments.iter().find(|deployment| deployment.staged)
+    }
+
+    /// The deployment that the system boots into next
+    /// if it's not the booted deployment.
+    #[must_use]
+    pub fn pending_deployment(&self) -> Option<&RpmOstreeDeployment<'_>> {
+        self.deployments
+            .first()
+            .filter(|deployment| !deployment.booted)
+    }
+
+    /// The deployment that `rpm-ostree rollback` boots into.
+    ///
+    /// This is the booted deployment when there's a pending
+    /// deployment, otherwise it's the deployment after it.
+    #[must_use]
+    pub fn rollback_deployment(&self) -> Option<&RpmOstreeDeployment<'_>> {
+        if self.pending_deployment().is_some() {
+            self.booted_deployment()
+        } else {
+            let booted = self.deployments.iter().position(|d| d.booted)?;
+            self.deployments.get(booted + 1)
+        }
+    }
+
+    /// The deployments that are pinned.
+    pub fn pinned_deployments(&self) -> impl Iterator<Item = &RpmOstreeDeployment<'_>> {
+        self.deployments
+            .iter()
+            .filter(|deployment| deployment.pinned)
+    }
+
     /// Get the booted image's reference.
     #[must_use]
     pub fn booted_image(&self) -> Option<String> {
         Some(
-            self.deployments
-                .iter()
-                .find(|deployment| deployment.booted)?
+            self.booted_deployment()?
                 .container_image_reference
+                .as_deref()?
                 .to_string(),
         )
     }
@@ -63,10 +178,9 @@ impl<'a> RpmOstreeStatus<'a> {
     #[must_use]
     pub fn staged_image(&self) -> Option<String> {
         Some(
-            self.deployments
-                .iter()
-                .find(|deployment| deployment.staged)?
+            self.staged_deployment()?
                 .container_image_reference
+                .as_deref()?
                 .to_string(),
         )
     }
@@ -79,7 +193,7 @@ impl<'a> RpmOstreeStatus<'a> {
         self.booted_image().is_some_and(|deployment| {
             deployment
                 .split(':')
-                .last()
+                .next_back()
                 .is_some_and(|boot_ref| Path::new(boot_ref) == archive_path.as_ref())
         })
     }
@@ -92,7 +206,7 @@ impl<'a> RpmOstreeStatus<'a> {
         self.staged_image().is_some_and(|deployment| {
             deployment
                 .split(':')
-                .last()
+                .next_back()
                 .is_some_and(|boot_ref| Path::new(boot_ref) == archive_path.as_ref())
         })
     }
@@ -106,26 +220,28 @@ mod test {
         ARCHIVE_SUFFIX, LOCAL_BUILD, OCI_ARCHIVE, OSTREE_IMAGE_SIGNED, OSTREE_UNVERIFIED_IMAGE,
     };
 
-    use super::{RpmOstreeDeployments, RpmOstreeStatus};
+    use super::{RpmOstreeDeployment, RpmOstreeStatus};
 
     fn create_image_status<'a>() -> RpmOstreeStatus<'a> {
         RpmOstreeStatus {
             deployments: vec![
-                RpmOstreeDeployments {
-                    container_image_reference: format!(
-                        "{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test"
-                    )
-                    .into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(
+                        format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test")
+                            .into(),
+                    ),
                     booted: true,
                     staged: false,
+                    ..Default::default()
                 },
-                RpmOstreeDeployments {
-                    container_image_reference: format!(
-                        "{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last"
-                    )
-                    .into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(
+                        format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last")
+                            .into(),
+                    ),
                     booted: false,
                     staged: false,
+                    ..Default::default()
                 },
             ]
             .into(),
@@ -136,21 +252,23 @@ mod test {
     fn create_transaction_status<'a>() -> RpmOstreeStatus<'a> {
         RpmOstreeStatus {
             deployments: vec![
-                RpmOstreeDeployments {
-                    container_image_reference: format!(
-                        "{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test"
-                    )
-                    .into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(
+                        format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test")
+                            .into(),
+                    ),
                     booted: true,
                     staged: false,
+                    ..Default::default()
                 },
-                RpmOstreeDeployments {
-                    container_image_reference: format!(
-                        "{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last"
-                    )
-                    .into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(
+                        format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last")
+                            .into(),
+                    ),
                     booted: false,
                     staged: false,
+                    ..Default::default()
                 },
             ]
             .into(),
@@ -161,17 +279,17 @@ mod test {
     fn create_archive_status<'a>() -> RpmOstreeStatus<'a> {
         RpmOstreeStatus {
             deployments: vec![
-                RpmOstreeDeployments {
-                    container_image_reference:
-                        format!("{OSTREE_UNVERIFIED_IMAGE}:{OCI_ARCHIVE}:{LOCAL_BUILD}/cli_test.{ARCHIVE_SUFFIX}").into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(format!("{OSTREE_UNVERIFIED_IMAGE}:{OCI_ARCHIVE}:{LOCAL_BUILD}/cli_test.{ARCHIVE_SUFFIX}").into()),
                     booted: true,
                     staged: false,
+                    ..Default::default()
                 },
-                RpmOstreeDeployments {
-                    container_image_reference:
-                        format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last").into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last").into()),
                     booted: false,
                     staged: false,
+                    ..Default::default()
                 },
             ]
             .into(),
@@ -182,23 +300,23 @@ mod test {
     fn create_archive_staged_status<'a>() -> RpmOstreeStatus<'a> {
         RpmOstreeStatus {
             deployments: vec![
-                RpmOstreeDeployments {
-                    container_image_reference:
-                        format!("{OSTREE_UNVERIFIED_IMAGE}:{OCI_ARCHIVE}:{LOCAL_BUILD}/cli_test.{ARCHIVE_SUFFIX}").into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(format!("{OSTREE_UNVERIFIED_IMAGE}:{OCI_ARCHIVE}:{LOCAL_BUILD}/cli_test.{ARCHIVE_SUFFIX}").into()),
                     booted: false,
                     staged: true,
+                    ..Default::default()
                 },
-                RpmOstreeDeployments {
-                    container_image_reference:
-                        format!("{OSTREE_UNVERIFIED_IMAGE}:{OCI_ARCHIVE}:{LOCAL_BUILD}/cli_test.{ARCHIVE_SUFFIX}").into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(format!("{OSTREE_UNVERIFIED_IMAGE}:{OCI_ARCHIVE}:{LOCAL_BUILD}/cli_test.{ARCHIVE_SUFFIX}").into()),
                     booted: true,
                     staged: false,
+                    ..Default::default()
                 },
-                RpmOstreeDeployments {
-                    container_image_reference:
-                        format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last").into(),
+                RpmOstreeDeployment {
+                    container_image_reference: Some(format!("{OSTREE_IMAGE_SIGNED}:docker://ghcr.io/blue-build/cli/test:last").into()),
                     booted: false,
                     staged: false,
+                    ..Default::default()
                 },
             ]
             .into(),
@@ -206,6 +324,83 @@ mod test {
         }
     }
 
+    #[test]
+    fn test_parse_status() {
+        let status: RpmOstreeStatus = serde_json::from_str(
+            r#"{
+                "deployments": [
+                    {
+                        "id": "fedora-b.0",
+                        "checksum": "b",
+                        "version": "41.20241020.0",
+                        "container-image-reference": "ostree-image-signed:docker://ghcr.io/blue-build/cli/test:41",
+                        "booted": false,
+                        "staged": true,
+                        "pinned": false,
+                        "requested-packages": ["htop"],
+                        "packages": ["htop"]
+                    },
+                    {
+                        "id": "fedora-a.0",
+                        "checksum": "a",
+                        "container-image-reference": "ostree-image-signed:docker://ghcr.io/blue-build/cli/test:40",
+                        "booted": true,
+                        "staged": false,
+                        "pinned": false
+                    },
+                    {
+                        "id": "fedora-c.0",
+                        "checksum": "c",
+                        "booted": false,
+                        "pinned": true
+                    }
+                ],
+                "transaction": ["Upgrade", ":1.42", "/org/projectatomic/rpmostree1/fedora"]
+            }"#,
+        )
+        .unwrap();
+
+        let id = |deployment: Option<&RpmOstreeDeployment>| {
+            deployment.and_then(|d| d.id.as_deref().map(ToOwned::to_owned))
+        };
+        assert_eq!(
+            id(status.pending_deployment()).as_deref(),
+            Some("fedora-b.0")
+        );
+        assert_eq!(
+            id(status.booted_deployment()).as_deref(),
+            Some("fedora-a.0")
+        );
+        assert_eq!(
+            id(status.rollback_deployment()).as_deref(),
+            Some("fedora-a.0")
+        );
+        assert_eq!(
+            status
+                .pinned_deployments()
+                .filter_map(|d| d.id.as_deref())
+                .collect::<Vec<_>>(),
+            ["fedora-c.0"]
+        );
+        assert_eq!(status.deployments()[0].packages, ["htop"]);
+        assert!(status.booted_image().unwrap().ends_with("test:40"));
+
+        let transaction = status.transaction().unwrap();
+        assert_eq!(transaction.method, "Upgrade");
+        assert_eq!(transaction.initiator, Some(":1.42"));
+    }
+
+    #[test]
+    fn test_rollback_deployment() {
+        let status = create_image_status();
+        assert!(status.pending_deployment().is_none());
+        assert!(status
+            .rollback_deployment()
+            .and_then(|d| d.container_image_reference.as_deref())
+            .unwrap()
+            .ends_with("test:last"));
+    }
+
     #[test]
     fn test_booted_image() {
         assert!(create_image_status()
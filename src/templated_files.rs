@@ -0,0 +1,267 @@
+//! Renders the files of the `files` modules that set `template: true`.
+//!
+//! The expressions in the files, like `${{ image.version }}` or
+//! `${{ vars.NAME }}`, are replaced before the build. The rendered
+//! files are given to the build as a named build context and
+//! copied over the files directory in their own stage, which
+//! only the templated modules mount.
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Component, Path, PathBuf},
+};
+
+use blue_build_process_management::{
+    drivers::opts::BuildContext,
+    signal_handler::{CleanupGuard, CleanupItem},
+};
+use blue_build_recipe::{
+    expression_values, substitute_expressions, ModuleRequiredFields, Recipe, VARS_NAMESPACE,
+};
+use blue_build_utils::constants::{FILES_PATH, TEMPLATED_FILES_CONTEXT};
+use chrono::{SecondsFormat, Utc};
+use log::{debug, trace};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use uuid::Uuid;
+
+use crate::labels;
+
+/// The namespaces of the expressions in templated files.
+const NAMESPACES: &[&str] = &[VARS_NAMESPACE, "image", "build"];
+
+/// The values of the expressions in templated files, which are the
+/// variables of the recipe and the metadata of the build:
+/// - `image.name`, `image.version`, `image.base`, and `image.base-digest`
+/// - `build.date` and `build.revision`
+#[must_use]
+pub fn values(recipe: &Recipe, labels: &BTreeMap<String, String>) -> BTreeMap<String, String> {
+    let label = |key: &str| labels.get(key).cloned();
+
+    let mut values = expression_values(&recipe.vars);
+    values.extend(
+        [
+            ("image.name", Some(recipe.name.to_string())),
+            ("image.version", label(labels::VERSION)),
+            ("image.base", label(labels::BASE_NAME)),
+            ("image.base-digest", label(labels::BASE_DIGEST)),
+            ("build.revision", label(labels::REVISION)),
+            (
+                "build.date",
+                Some(Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| Some((key.to_owned(), value?))),
+    );
+    values
+}
+
+/// The directory next to `containerfile` that the
+/// templated files of its recipe are rendered to.
+#[must_use]
+pub fn dir(containerfile: &Path) -> PathBuf {
+    let mut name = containerfile.file_name().unwrap_or_default().to_os_string();
+    name.push(".templated-files");
+    containerfile.with_file_name(name)
+}
+
+/// The build contexts of the templated files that were rendered
+/// for `containerfile`, which are empty if its recipe has none.
+#[must_use]
+pub fn build_contexts(containerfile: &Path) -> Vec<BuildContext> {
+    let dir = dir(containerfile);
+    dir.is_dir()
+        .then(|| BuildContext {
+            name: TEMPLATED_FILES_CONTEXT.into(),
+            location: dir.display().to_string(),
+        })
+        .into_iter()
+        .collect()
+}
+
+/// Renders the templated files of the recipe into `dir`,
+/// which is given to the build as the build context
+/// named [`TEMPLATED_FILES_CONTEXT`].
+///
+/// # Errors
+/// Will error if a source is outside of the files directory,
+/// an expression doesn't have a value, or a file can't be written.
+pub fn prepare(recipe: &Recipe, values: &BTreeMap<String, String>, dir: &Path) -> Result<()> {
+    prepare_in(recipe, values, Path::new(FILES_PATH), dir)
+}
+
+fn prepare_in(
+    recipe: &Recipe,
+    values: &BTreeMap<String, String>,
+    files_dir: &Path,
+    context_dir: &Path,
+) -> Result<()> {
+    trace!(
+        "templated_files::prepare_in({}, {})",
+        files_dir.display(),
+        context_dir.display()
+    );
+
+    let sources = recipe
+        .all_modules()
+        .filter(|module| module.is_templated_files())
+        .flat_map(ModuleRequiredFields::get_files_sources)
+        .collect::<BTreeSet<_>>();
+
+    // Rendered next to the directory and renamed into
+    // place so that the build doesn't see partly
+    // rendered files
+    let parent = context_dir.parent().unwrap_or_else(|| Path::new("."));
+    let tmp = parent.join(format!(".tmp-{}", Uuid::new_v4()));
+    let _tmp_cleanup = CleanupGuard::new(CleanupItem::Dir(tmp.clone()));
+
+    let result = fs::create_dir_all(&tmp).into_diagnostic().and_then(|()| {
+        for source in sources {
+            if !Path::new(source)
+                .components()
+                .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+            {
+                bail!("The templated file {source} must be inside of {FILES_PATH}");
+            }
+
+            debug!("Rendering the templated file {source}");
+            render(&files_dir.join(source), &tmp.join(source), values)
+                .with_context(|| format!("Failed to render the templated file {source}"))?;
+        }
+
+        if context_dir.exists() {
+            fs::remove_dir_all(context_dir).into_diagnostic()?;
+        }
+        fs::rename(&tmp, context_dir).into_diagnostic()
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_dir_all(&tmp);
+    }
+    result
+}
+
+/// Renders a file, or every file in a directory. Files
+/// that aren't text are copied as they are.
+fn render(source: &Path, dest: &Path, values: &BTreeMap<String, String>) -> Result<()> {
+    if source.is_dir() {
+        fs::create_dir_all(dest).into_diagnostic()?;
+        for entry in fs::read_dir(source).into_diagnostic()? {
+            let entry = entry.into_diagnostic()?;
+            render(&entry.path(), &dest.join(entry.file_name()), values)?;
+        }
+        return Ok(());
+    }
+
+    if let Some(parent) = dest.parent() {
+        fs::create_dir_all(parent).into_diagnostic()?;
+    }
+    let contents = fs::read(source)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to read {}", source.display()))?;
+    let contents = match String::from_utf8(contents) {
+        Ok(text) => substitute_expressions(&text, NAMESPACES, values)
+            .with_context(|| format!("Failed to render {}", source.display()))?
+            .into_bytes(),
+        Err(binary) => binary.into_bytes(),
+    };
+
+    fs::write(dest, contents).into_diagnostic()?;
+    fs::set_permissions(dest, fs::metadata(source).into_diagnostic()?.permissions())
+        .into_diagnostic()
+}
+
+#[cfg(test)]
+mod test {
+    use std::{collections::BTreeMap, fs};
+
+    use blue_build_recipe::Recipe;
+    use tempfile::TempDir;
+
+    use super::{build_contexts, prepare_in};
+
+    const RECIPE: &str = r"
+apiVersion: v2
+name: test
+description: test
+vars:
+  CHANNEL: stable
+base-image: ghcr.io/ublue-os/silverblue-main:41
+modules:
+  - type: files
+    template: true
+    files:
+      - source: system
+        destination: /
+  - type: files
+    files:
+      - source: other
+        destination: /
+";
+
+    #[test]
+    fn renders_files() {
+        let recipe = Recipe::from_yaml(RECIPE).unwrap();
+        let dir = TempDir::new().unwrap();
+        let files = dir.path().join("files");
+        fs::create_dir_all(files.join("system/etc")).unwrap();
+        fs::create_dir_all(files.join("other")).unwrap();
+        fs::write(
+            files.join("system/etc/os-info"),
+            "VERSION=${{ image.version }}\nCHANNEL=${{ vars.CHANNEL }}\nSHA=${{ github.sha }}\n",
+        )
+        .unwrap();
+        fs::write(files.join("system/etc/logo.png"), [0xff, 0xfe, 0x00]).unwrap();
+        fs::write(files.join("other/file"), "${{ image.version }}").unwrap();
+
+        let values = super::values(
+            &recipe,
+            &BTreeMap::from([(
+                "org.opencontainers.image.version".to_owned(),
+                "41.20241030".to_owned(),
+            )]),
+        );
+        let context = dir.path().join("context/test");
+        prepare_in(&recipe, &values, &files, &context).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(context.join("system/etc/os-info")).unwrap(),
+            "VERSION=41.20241030\nCHANNEL=stable\nSHA=${{ github.sha }}\n"
+        );
+        assert_eq!(
+            fs::read(context.join("system/etc/logo.png")).unwrap(),
+            [0xff, 0xfe, 0x00]
+        );
+        assert!(!context.join("other").exists());
+    }
+
+    #[test]
+    fn missing_value() {
+        let recipe = Recipe::from_yaml(RECIPE).unwrap();
+        let dir = TempDir::new().unwrap();
+        let files = dir.path().join("files");
+        fs::create_dir_all(files.join("system")).unwrap();
+        fs::write(files.join("system/file"), "${{ vars.MISSING }}").unwrap();
+
+        let context = dir.path().join("context/test");
+        assert!(prepare_in(&recipe, &BTreeMap::new(), &files, &context).is_err());
+        assert!(!context.exists());
+    }
+
+    #[test]
+    fn build_context_next_to_containerfile() {
+        let dir = TempDir::new().unwrap();
+        let containerfile = dir.path().join("Containerfile.test");
+        assert_eq!(build_contexts(&containerfile), []);
+
+        let rendered = dir.path().join("Containerfile.test.templated-files");
+        fs::create_dir_all(&rendered).unwrap();
+        let contexts = build_contexts(&containerfile);
+        assert_eq!(contexts.len(), 1);
+        assert_eq!(
+            contexts[0].arg(),
+            format!("bluebuild-templated-files={}", rendered.display())
+        );
+    }
+}
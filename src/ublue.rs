@@ -0,0 +1,854 @@
+//! Converts between bluebuild projects and repos made from the
+//! ublue-os image template, which build their image with a
+//! `Containerfile` that runs a `build.sh` script.
+//!
+//! The package installs and the systemd units that the script
+//! enables are converted to modules, and the rest of the
+//! script is kept as script modules, which are split at each
+//! package install so that the steps still run in order.
+//! Exporting a recipe goes the other way and writes the
+//! modules as a `build.sh`.
+
+use std::{collections::HashMap, fmt::Write as _, fs, os::unix::fs::PermissionsExt, path::Path};
+
+use blue_build_recipe::{ApiVersion, ModuleRequiredFields, Recipe, API_VERSION_KEY};
+use blue_build_utils::constants::{
+    CONTAINER_FILE, FILES_PATH, RECIPE_FILE, RECIPE_PATH, SCRIPTS_PATH,
+};
+use log::{debug, info, warn};
+use miette::{bail, miette, Context, IntoDiagnostic, Result};
+use serde_yaml::{Mapping, Value};
+
+/// The script that the image template runs to build the image.
+const BUILD_SCRIPT: &str = "build.sh";
+
+/// The directory that the image template keeps its build script in.
+const BUILD_FILES_DIR: &str = "build_files";
+
+/// The directory of files that are copied into the image.
+const SYSTEM_FILES_DIR: &str = "system_files";
+
+/// The directory in the build files that the
+/// scripts of the script modules are exported to.
+const EXPORTED_SCRIPTS_DIR: &str = "scripts";
+
+/// The flags of the package managers that don't
+/// change what the command does in an image build.
+const PACKAGE_FLAGS: &[&str] = &["-y", "--assumeyes", "-q", "--quiet", "--idempotent"];
+
+/// Imports an image template repo into a bluebuild project in `output`.
+///
+/// # Errors
+/// Will error if the repo doesn't have a `Containerfile`,
+/// its base image can't be found, or a file can't be written.
+pub fn import(repo: &Path, output: &Path, name: &str) -> Result<()> {
+    let recipe_path = output.join(RECIPE_PATH).join(RECIPE_FILE);
+    if recipe_path.exists() {
+        bail!(
+            "The recipe {} already exists, import the repo into another directory",
+            recipe_path.display()
+        );
+    }
+
+    let containerfile_path = repo.join(CONTAINER_FILE);
+    let containerfile = fs::read_to_string(&containerfile_path)
+        .into_diagnostic()
+        .with_context(|| format!("Failed to read {}", containerfile_path.display()))?;
+    let base_image = base_image_of(&containerfile)?;
+    debug!(
+        "Importing {} with the base image {base_image}",
+        repo.display()
+    );
+
+    let mut modules = Vec::new();
+
+    let script_path = [repo.join(BUILD_FILES_DIR), repo.to_path_buf()]
+        .into_iter()
+        .map(|dir| dir.join(BUILD_SCRIPT))
+        .find(|path| path.is_file());
+    let script = if let Some(script_path) = script_path {
+        debug!("Converting the build script {}", script_path.display());
+        convert_script(
+            &fs::read_to_string(&script_path)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to read {}", script_path.display()))?,
+        )
+    } else {
+        warn!("The repo {} doesn't have a {BUILD_SCRIPT}", repo.display());
+        BuildScript::default()
+    };
+    // The files are copied into the image before the build script runs
+    let system_files = repo.join(SYSTEM_FILES_DIR);
+    if system_files.is_dir() {
+        let files_dir = output.join(FILES_PATH).join("system");
+        debug!(
+            "Copying {} to {}",
+            system_files.display(),
+            files_dir.display()
+        );
+        blue_build_utils::copy_dir(&system_files, &files_dir)?;
+        modules.push(module(
+            "files",
+            [(
+                "files",
+                Value::Sequence(vec![mapping([
+                    ("source", "system".into()),
+                    ("destination", "/".into()),
+                ])]),
+            )],
+        ));
+    }
+
+    let mut scripts = 0;
+    for step in &script.steps {
+        match step {
+            Step::Packages(packages) => modules.push(packages.module()),
+            Step::Script(part) => {
+                let name = script_name(scripts);
+                scripts += 1;
+
+                let scripts_dir = output.join(SCRIPTS_PATH);
+                fs::create_dir_all(&scripts_dir).into_diagnostic()?;
+                write_script(&scripts_dir.join(&name), part)?;
+                modules.push(module(
+                    "script",
+                    [("scripts", Value::Sequence(vec![name.into()]))],
+                ));
+            }
+        }
+    }
+    // The units that are enabled can come from the
+    // packages, the files, or the rest of the script
+    modules.extend(script.systemd_module());
+
+    let recipe = mapping([
+        (API_VERSION_KEY, ApiVersion::LATEST.to_string().into()),
+        ("name", name.into()),
+        (
+            "description",
+            format!("{name}, imported from the ublue-os image template").into(),
+        ),
+        ("base-image", base_image.into()),
+        ("modules", Value::Sequence(modules)),
+    ]);
+
+    fs::create_dir_all(output.join(RECIPE_PATH)).into_diagnostic()?;
+    fs::write(
+        &recipe_path,
+        serde_yaml::to_string(&recipe).into_diagnostic()?,
+    )
+    .into_diagnostic()
+    .with_context(|| format!("Failed to write {}", recipe_path.display()))?;
+    info!("Imported {} into {}", repo.display(), recipe_path.display());
+
+    Ok(())
+}
+
+/// Exports a recipe to an image template repo in `output`.
+///
+/// # Errors
+/// Will error if the repo already has a `Containerfile`
+/// or a file can't be copied or written.
+pub fn export(recipe: &Recipe, output: &Path) -> Result<()> {
+    let containerfile_path = output.join(CONTAINER_FILE);
+    if containerfile_path.exists() {
+        bail!(
+            "The Containerfile {} already exists, export the recipe into another directory",
+            containerfile_path.display()
+        );
+    }
+
+    if recipe
+        .stages_ext
+        .as_ref()
+        .is_some_and(|stages| !stages.stages.is_empty())
+    {
+        warn!("The stages of the recipe aren't exported, add them to the Containerfile");
+    }
+
+    let exported = export_modules(recipe);
+
+    let build_files = output.join(BUILD_FILES_DIR);
+    fs::create_dir_all(&build_files).into_diagnostic()?;
+    write_script(&build_files.join(BUILD_SCRIPT), &exported.script)?;
+    if !exported.scripts.is_empty() {
+        fs::create_dir_all(build_files.join(EXPORTED_SCRIPTS_DIR)).into_diagnostic()?;
+    }
+    for script in &exported.scripts {
+        let source = Path::new(SCRIPTS_PATH).join(script);
+        fs::copy(&source, build_files.join(EXPORTED_SCRIPTS_DIR).join(script))
+            .into_diagnostic()
+            .with_context(|| format!("Failed to copy {}", source.display()))?;
+    }
+
+    for (source, destination) in &exported.files {
+        let source = Path::new(FILES_PATH).join(source);
+        let destination = output
+            .join(SYSTEM_FILES_DIR)
+            .join(destination.trim_start_matches('/'));
+        if source.is_dir() {
+            blue_build_utils::copy_dir(&source, &destination)?;
+        } else {
+            fs::create_dir_all(destination.parent().unwrap_or(output)).into_diagnostic()?;
+            fs::copy(&source, &destination)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to copy {}", source.display()))?;
+        }
+    }
+
+    fs::write(
+        &containerfile_path,
+        containerfile(recipe, !exported.files.is_empty()),
+    )
+    .into_diagnostic()
+    .with_context(|| format!("Failed to write {}", containerfile_path.display()))?;
+    info!(
+        "Exported the recipe {} to {}",
+        recipe.name,
+        output.display()
+    );
+
+    Ok(())
+}
+
+/// The changes of a build script that have modules,
+/// and the rest of the script.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct BuildScript {
+    /// The package installs and the parts of the script
+    /// between them without the converted lines, in the
+    /// order they run in.
+    steps: Vec<Step>,
+    system: Units,
+    user: Units,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Step {
+    Packages(Packages),
+
+    /// A part of the script, which starts with
+    /// the shebang and `set` lines of the script.
+    Script(String),
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Packages {
+    install: Vec<String>,
+    remove: Vec<String>,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Units {
+    enabled: Vec<String>,
+    disabled: Vec<String>,
+}
+
+impl Packages {
+    const fn is_empty(&self) -> bool {
+        self.install.is_empty() && self.remove.is_empty()
+    }
+
+    fn module(&self) -> Value {
+        module(
+            "package",
+            [("install", &self.install), ("remove", &self.remove)]
+                .into_iter()
+                .filter(|(_, packages)| !packages.is_empty())
+                .map(|(key, packages)| (key, strings(packages))),
+        )
+    }
+}
+
+impl BuildScript {
+    fn systemd_module(&self) -> Option<Value> {
+        let units = [("system", &self.system), ("user", &self.user)]
+            .into_iter()
+            .filter(|(_, units)| !units.enabled.is_empty() || !units.disabled.is_empty())
+            .map(|(key, units)| {
+                (
+                    key,
+                    mapping(
+                        [("enabled", &units.enabled), ("disabled", &units.disabled)]
+                            .into_iter()
+                            .filter(|(_, units)| !units.is_empty())
+                            .map(|(key, units)| (key, strings(units))),
+                    ),
+                )
+            })
+            .collect::<Vec<_>>();
+
+        (!units.is_empty()).then(|| module("systemd", units))
+    }
+}
+
+/// Finds the base image of the last stage, with the `ARG`s
+/// that it's built from replaced with their defaults.
+fn base_image_of(containerfile: &str) -> Result<String> {
+    let mut args = HashMap::new();
+    let mut base_image = None;
+
+    for instruction in instructions(containerfile) {
+        let mut words = instruction.split_whitespace();
+        match words.next().map(str::to_ascii_uppercase).as_deref() {
+            Some("ARG") => {
+                args.extend(words.filter_map(|arg| {
+                    arg.split_once('=')
+                        .map(|(name, value)| (name.to_owned(), unquote(value).to_owned()))
+                }));
+            }
+            Some("FROM") => {
+                let image = words
+                    .find(|word| !word.starts_with("--"))
+                    .ok_or_else(|| miette!("The instruction `{instruction}` has no image"))?;
+                if image != "scratch" {
+                    base_image = Some(substitute_args(image, &args)?);
+                }
+            }
+            Some("RUN") if instruction.contains(BUILD_SCRIPT) => {}
+            Some("RUN")
+                if instruction.contains("bootc container lint")
+                    || instruction.ends_with("ostree container commit") => {}
+            Some("COPY")
+                if [BUILD_SCRIPT, BUILD_FILES_DIR, SYSTEM_FILES_DIR]
+                    .iter()
+                    .any(|path| instruction.contains(path)) => {}
+            _ => warn!(
+                "The step `{instruction}` of the Containerfile isn't imported, add it to the recipe"
+            ),
+        }
+    }
+
+    base_image.ok_or_else(|| miette!("The Containerfile doesn't have a base image"))
+}
+
+/// The instructions of a `Containerfile`, with the
+/// lines that continue an instruction joined.
+fn instructions(containerfile: &str) -> Vec<String> {
+    let mut instructions = Vec::new();
+    let mut current = String::new();
+
+    for line in containerfile.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(line) = line.strip_suffix('\\') {
+            current.push_str(line.trim_end());
+            current.push(' ');
+        } else {
+            current.push_str(line);
+            instructions.push(std::mem::take(&mut current));
+        }
+    }
+    if !current.is_empty() {
+        instructions.push(current.trim_end().to_owned());
+    }
+
+    instructions
+}
+
+fn substitute_args(image: &str, args: &HashMap<String, String>) -> Result<String> {
+    let mut image = image.to_owned();
+    for (name, value) in args {
+        image = image
+            .replace(&format!("${{{name}}}"), value)
+            .replace(&format!("${name}"), value);
+    }
+
+    if image.contains('$') {
+        bail!(
+            help = "Set the base image in the recipe by hand",
+            "The base image {image} uses an ARG without a default"
+        );
+    }
+    Ok(image)
+}
+
+/// Converts the simple package and systemd commands of a
+/// build script. A command is only converted if it doesn't
+/// use any shell syntax, so that nothing is lost.
+///
+/// The script is split at each package install that comes after
+/// other commands, since those can be needed by the install,
+/// like enabling a repo.
+fn convert_script(script: &str) -> BuildScript {
+    let mut converted = BuildScript::default();
+    let mut packages = Packages::default();
+    let mut header = Vec::new();
+    let mut part = Vec::new();
+    let mut continued = false;
+
+    for line in script.lines() {
+        let is_continued = continued;
+        continued = line.trim_end().ends_with('\\');
+
+        if !is_continued && !continued && convert_line(line.trim(), &mut packages, &mut converted) {
+            if has_commands(&part) {
+                converted.steps.push(Step::Script(join_lines(&part)));
+                part.clone_from(&header);
+            }
+            continue;
+        }
+
+        if is_continued || is_command(line) {
+            if !packages.is_empty() {
+                converted
+                    .steps
+                    .push(Step::Packages(std::mem::take(&mut packages)));
+            }
+        } else if converted.steps.is_empty()
+            && !has_commands(&part)
+            && (line.starts_with("#!") || line.trim().starts_with("set "))
+        {
+            header.push(line);
+        }
+        part.push(line);
+    }
+
+    if !packages.is_empty() {
+        converted.steps.push(Step::Packages(packages));
+    }
+    if has_commands(&part) {
+        converted.steps.push(Step::Script(join_lines(&part)));
+    }
+
+    converted
+}
+
+/// Whether the line runs something, instead
+/// of being a comment or setting up the shell.
+fn is_command(line: &str) -> bool {
+    let line = line.trim();
+    !line.is_empty() && !line.starts_with('#') && !line.starts_with("set ")
+}
+
+fn has_commands(lines: &[&str]) -> bool {
+    lines.iter().any(|line| is_command(line))
+}
+
+fn join_lines(lines: &[&str]) -> String {
+    let mut script = lines.join("\n");
+    script.push('\n');
+    script
+}
+
+/// The name of a part of the build script, which
+/// is the name of the build script for the first.
+fn script_name(index: usize) -> String {
+    match index {
+        0 => BUILD_SCRIPT.to_owned(),
+        index => format!("build-{}.sh", index + 1),
+    }
+}
+
+/// Converts a line into the changes of the build script.
+/// Returns `false` if the line has to stay in the script.
+fn convert_line(line: &str, packages: &mut Packages, converted: &mut BuildScript) -> bool {
+    if line.contains(|c: char| "&|;$`'\"\\(){}<>*?".contains(c)) {
+        return false;
+    }
+
+    let mut words = line.split_whitespace();
+    match words.next() {
+        Some(manager @ ("dnf" | "dnf5" | "rpm-ostree")) => {
+            let (flags, args): (Vec<_>, Vec<_>) = words.partition(|word| word.starts_with('-'));
+            if !flags.iter().all(|flag| PACKAGE_FLAGS.contains(flag)) {
+                return false;
+            }
+            match args.split_first() {
+                Some((&"install", names)) if !names.is_empty() => {
+                    packages
+                        .install
+                        .extend(names.iter().map(ToString::to_string));
+                    true
+                }
+                Some((&"remove", names)) if manager != "rpm-ostree" && !names.is_empty() => {
+                    packages
+                        .remove
+                        .extend(names.iter().map(ToString::to_string));
+                    true
+                }
+                _ => false,
+            }
+        }
+        Some("systemctl") => {
+            let (flags, args): (Vec<_>, Vec<_>) = words.partition(|word| word.starts_with('-'));
+            let units = match flags.as_slice() {
+                [] => &mut converted.system,
+                ["--global"] => &mut converted.user,
+                _ => return false,
+            };
+            match args.split_first() {
+                Some((&"enable", names)) if !names.is_empty() => {
+                    units.enabled.extend(names.iter().map(ToString::to_string));
+                    true
+                }
+                Some((&"disable", names)) if !names.is_empty() => {
+                    units.disabled.extend(names.iter().map(ToString::to_string));
+                    true
+                }
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+/// The modules of a recipe as a build script.
+#[derive(Debug, Default)]
+struct ExportedModules {
+    script: String,
+
+    /// The scripts of the script modules, which are
+    /// copied next to the build script.
+    scripts: Vec<String>,
+
+    /// The sources and destinations of the files modules.
+    files: Vec<(String, String)>,
+}
+
+fn export_modules(recipe: &Recipe) -> ExportedModules {
+    let mut exported = ExportedModules {
+        script: String::from("#!/bin/bash\n\nset -ouex pipefail\n"),
+        ..Default::default()
+    };
+
+    for module in recipe
+        .modules_ext
+        .modules
+        .iter()
+        .filter_map(|module| module.required_fields.as_ref())
+    {
+        let mut lines = String::new();
+        let handled_keys: &[&str] = match &*module.module_type {
+            "package" | "dnf" | "rpm-ostree" => {
+                export_packages(module, &mut lines);
+                &["install", "remove"]
+            }
+            "systemd" => {
+                export_units(module, &mut lines);
+                &["system", "user"]
+            }
+            "script" => {
+                for script in list(module, "scripts") {
+                    let _ = writeln!(lines, "/ctx/{EXPORTED_SCRIPTS_DIR}/{script}");
+                    exported.scripts.push(script);
+                }
+                for snippet in list(module, "snippets") {
+                    let _ = writeln!(lines, "{snippet}");
+                }
+                &["scripts", "snippets"]
+            }
+            "files" => {
+                exported.files.extend(
+                    module
+                        .config
+                        .get("files")
+                        .and_then(Value::as_sequence)
+                        .into_iter()
+                        .flatten()
+                        .filter_map(|file| {
+                            Some((
+                                file.get("source")?.as_str()?.to_owned(),
+                                file.get("destination")?.as_str()?.to_owned(),
+                            ))
+                        }),
+                );
+                &["files"]
+            }
+            module_type => {
+                warn!("The {module_type} module isn't exported, add its steps to the build script");
+                let _ = writeln!(lines, "# The {module_type} module isn't exported");
+                &[]
+            }
+        };
+
+        for key in module
+            .config
+            .keys()
+            .filter(|key| !handled_keys.is_empty() && !handled_keys.contains(&key.as_str()))
+        {
+            warn!(
+                "The {key} option of the {} module isn't exported, add it to the build script",
+                module.module_type
+            );
+            let _ = writeln!(
+                exported.script,
+                "# The {key} option of the {} module isn't exported",
+                module.module_type
+            );
+        }
+
+        if !lines.is_empty() {
+            exported.script.push('\n');
+            exported.script.push_str(&lines);
+        }
+    }
+
+    exported
+}
+
+fn export_packages(module: &ModuleRequiredFields, script: &mut String) {
+    for (key, command) in [("remove", "dnf5 -y remove"), ("install", "dnf5 -y install")] {
+        let packages = module
+            .config
+            .get(key)
+            .map(|packages| packages.get("packages").unwrap_or(packages))
+            .and_then(Value::as_sequence)
+            .into_iter()
+            .flatten()
+            .filter_map(Value::as_str)
+            .collect::<Vec<_>>();
+        if !packages.is_empty() {
+            let _ = writeln!(script, "{command} {}", packages.join(" "));
+        }
+    }
+}
+
+fn export_units(module: &ModuleRequiredFields, script: &mut String) {
+    for (key, flags) in [("system", ""), ("user", " --global")] {
+        let Some(units) = module.config.get(key) else {
+            continue;
+        };
+        for (state, verb) in [
+            ("enabled", "enable"),
+            ("disabled", "disable"),
+            ("masked", "mask"),
+            ("unmasked", "unmask"),
+        ] {
+            let names = units
+                .get(state)
+                .and_then(Value::as_sequence)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+                .collect::<Vec<_>>();
+            if !names.is_empty() {
+                let _ = writeln!(script, "systemctl{flags} {verb} {}", names.join(" "));
+            }
+        }
+    }
+}
+
+fn containerfile(recipe: &Recipe, has_files: bool) -> String {
+    let mut containerfile = format!(
+        "# Exported from the bluebuild recipe for {}\n\
+        FROM scratch AS ctx\n\
+        COPY {BUILD_FILES_DIR} /\n\
+        \n\
+        FROM {}:{}\n",
+        recipe.name, recipe.base_image, recipe.image_version
+    );
+    if has_files {
+        let _ = writeln!(containerfile, "\nCOPY {SYSTEM_FILES_DIR} /");
+    }
+    containerfile.push_str(
+        "\nRUN --mount=type=bind,from=ctx,source=/,target=/ctx \\\n    \
+        --mount=type=cache,dst=/var/cache \\\n    \
+        --mount=type=cache,dst=/var/log \\\n    \
+        --mount=type=tmpfs,dst=/tmp \\\n    \
+        /ctx/build.sh && \\\n    \
+        ostree container commit\n\
+        \n\
+        RUN bootc container lint\n",
+    );
+    containerfile
+}
+
+fn list(module: &ModuleRequiredFields, key: &str) -> Vec<String> {
+    module
+        .config
+        .get(key)
+        .and_then(Value::as_sequence)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .map(ToOwned::to_owned)
+        .collect()
+}
+
+fn write_script(path: &Path, contents: &str) -> Result<()> {
+    fs::write(path, contents)
+        .and_then(|()| fs::set_permissions(path, fs::Permissions::from_mode(0o755)))
+        .into_diagnostic()
+        .with_context(|| format!("Failed to write {}", path.display()))
+}
+
+fn module<'a>(module_type: &str, config: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+    let mut module = mapping([("type", module_type.into())]);
+    if let Value::Mapping(module) = &mut module {
+        module.extend(config.into_iter().map(|(key, value)| (key.into(), value)));
+    }
+    module
+}
+
+fn mapping<'a>(entries: impl IntoIterator<Item = (&'a str, Value)>) -> Value {
+    Value::Mapping(
+        entries
+            .into_iter()
+            .map(|(key, value)| (key.into(), value))
+            .collect::<Mapping>(),
+    )
+}
+
+fn strings(values: &[String]) -> Value {
+    Value::Sequence(values.iter().map(|value| value.as_str().into()).collect())
+}
+
+fn unquote(value: &str) -> &str {
+    value.trim_matches(|c| c == '"' || c == '\'')
+}
+
+#[cfg(test)]
+mod test {
+    use blue_build_recipe::Recipe;
+    use rstest::rstest;
+
+    use super::{
+        base_image_of, convert_script, export_modules, BuildScript, Packages, Step, Units,
+    };
+
+    #[rstest]
+    #[case(
+        "FROM scratch AS ctx\nCOPY build_files /\n\nFROM ghcr.io/ublue-os/bazzite:stable\n\
+        RUN --mount=type=bind,from=ctx,source=/,target=/ctx \\\n    /ctx/build.sh && \\\n    ostree container commit\n",
+        "ghcr.io/ublue-os/bazzite:stable"
+    )]
+    #[case(
+        "ARG SOURCE_IMAGE=\"silverblue\"\nARG SOURCE_SUFFIX=\"-main\"\nARG SOURCE_TAG=\"41\"\n\
+        FROM ghcr.io/ublue-os/${SOURCE_IMAGE}${SOURCE_SUFFIX}:${SOURCE_TAG}\n\
+        COPY build.sh /tmp/build.sh\nRUN /tmp/build.sh && ostree container commit\n",
+        "ghcr.io/ublue-os/silverblue-main:41"
+    )]
+    fn containerfile_base_image(#[case] containerfile: &str, #[case] expected: &str) {
+        assert_eq!(base_image_of(containerfile).unwrap(), expected);
+    }
+
+    #[test]
+    fn unset_arg() {
+        assert!(base_image_of("ARG IMAGE\nFROM ghcr.io/ublue-os/${IMAGE}\n").is_err());
+    }
+
+    #[test]
+    fn converts_script() {
+        let script = convert_script(
+            "#!/bin/bash\n\
+            set -ouex pipefail\n\
+            # this installs a package from fedora repos\n\
+            dnf5 install -y tmux\n\
+            dnf5 -y remove firefox\n\
+            dnf5 -y copr enable ublue-os/staging\n\
+            systemctl enable podman.socket\n\
+            systemctl --global disable foo.service\n",
+        );
+
+        assert_eq!(
+            script,
+            BuildScript {
+                steps: vec![
+                    Step::Packages(Packages {
+                        install: vec!["tmux".into()],
+                        remove: vec!["firefox".into()],
+                    }),
+                    Step::Script(
+                        "#!/bin/bash\n\
+                        set -ouex pipefail\n\
+                        # this installs a package from fedora repos\n\
+                        dnf5 -y copr enable ublue-os/staging\n"
+                            .into()
+                    ),
+                ],
+                system: Units {
+                    enabled: vec!["podman.socket".into()],
+                    disabled: vec![],
+                },
+                user: Units {
+                    enabled: vec![],
+                    disabled: vec!["foo.service".into()],
+                },
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_order_of_installs() {
+        let script = convert_script(
+            "#!/bin/bash\n\
+            set -ouex pipefail\n\
+            dnf5 -y copr enable ublue-os/staging\n\
+            dnf5 -y install tmux\n\
+            dnf5 -y install htop\n\
+            echo done\n",
+        );
+
+        assert_eq!(
+            script.steps,
+            [
+                Step::Script(
+                    "#!/bin/bash\nset -ouex pipefail\ndnf5 -y copr enable ublue-os/staging\n"
+                        .into()
+                ),
+                Step::Packages(Packages {
+                    install: vec!["tmux".into(), "htop".into()],
+                    remove: vec![],
+                }),
+                Step::Script("#!/bin/bash\nset -ouex pipefail\necho done\n".into()),
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case("dnf5 install -y --setopt=install_weak_deps=False tmux\n")]
+    #[case("dnf5 install -y \\\n  tmux\n")]
+    #[case("dnf5 install -y $(cat packages.txt)\n")]
+    #[case("systemctl enable --now podman.socket\n")]
+    fn keeps_complex_commands(#[case] line: &str) {
+        let script = convert_script(line);
+
+        assert_eq!(script.steps, [Step::Script(line.into())]);
+        assert_eq!(script.system, Units::default());
+    }
+
+    #[test]
+    fn exports_modules() {
+        let recipe = serde_yaml::from_str::<Recipe>(
+            r"
+name: test
+description: test
+base-image: ghcr.io/ublue-os/silverblue-main
+image-version: 41
+modules:
+  - type: rpm-ostree
+    install: [tmux, htop]
+    remove: [firefox]
+  - type: systemd
+    user:
+      enabled: [foo.service]
+  - type: script
+    scripts: [setup.sh]
+    snippets: [echo hi]
+  - type: files
+    files:
+      - source: system
+        destination: /
+  - type: fonts
+    fonts:
+      nerd-fonts: [FiraCode]
+",
+        )
+        .unwrap();
+
+        let exported = export_modules(&recipe);
+
+        assert_eq!(
+            exported.script,
+            "#!/bin/bash\n\nset -ouex pipefail\n\
+            \ndnf5 -y remove firefox\ndnf5 -y install tmux htop\n\
+            \nsystemctl --global enable foo.service\n\
+            \n/ctx/scripts/setup.sh\necho hi\n\
+            \n# The fonts module isn't exported\n"
+        );
+        assert_eq!(exported.scripts, ["setup.sh"]);
+        assert_eq!(exported.files, [("system".into(), "/".into())]);
+    }
+}
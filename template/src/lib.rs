@@ -1,17 +1,26 @@
-use std::{borrow::Cow, fs, path::Path, process};
+use std::{borrow::Cow, collections::BTreeMap, fs, path::Path, process};
 
-use blue_build_recipe::Recipe;
-use blue_build_utils::constants::{
-    CONFIG_PATH, CONTAINERFILES_PATH, CONTAINER_FILE, COSIGN_PUB_PATH, FILES_PATH,
+use blue_build_recipe::{ModuleRequiredFields, Recipe};
+use blue_build_utils::{
+    constants::{
+        BLUE_BUILD_IMAGE_REF, CONFIG_PATH, CONTAINERFILES_PATH, CONTAINER_FILE, COSIGN_PUB_PATH,
+        FILES_PATH, RECIPE_FILE,
+    },
+    os_family::OsFamily,
 };
 use bon::Builder;
 use chrono::Utc;
 use colored::control::ShouldColorize;
+use include_dir::{include_dir, Dir};
 use log::{debug, error, trace, warn};
 use uuid::Uuid;
 
 pub use rinja::Template;
 
+/// The starting project used by `bluebuild init` and `bluebuild new`
+/// when a template repository isn't cloned.
+pub static INIT_PROJECT_TEMPLATE: Dir<'static> = include_dir!("$CARGO_MANIFEST_DIR/project");
+
 #[derive(Debug, Clone, Template, Builder)]
 #[template(path = "Containerfile.j2", escape = "none", whitespace = "minimize")]
 #[builder(on(Cow<'_, str>, into))]
@@ -25,10 +34,98 @@ pub struct ContainerFileTemplate<'a> {
     #[builder(into)]
     build_id: Uuid,
     os_version: u64,
+
+    /// The family of the OS of the base image, which decides
+    /// how packages are installed and each `RUN` is cleaned up.
+    #[builder(default)]
+    os_family: OsFamily,
+
     registry: Cow<'a, str>,
     build_scripts_image: Cow<'a, str>,
-    repo: Cow<'a, str>,
     base_digest: Cow<'a, str>,
+
+    /// The labels to set on the image, other
+    /// than the build ID and content hash.
+    #[builder(default)]
+    labels: BTreeMap<String, String>,
+
+    /// The JSON list of the modules that build the
+    /// image, which is written into the image.
+    module_manifest: Option<Cow<'a, str>>,
+
+    /// The hash of the build's inputs, used to
+    /// skip builds when nothing has changed.
+    content_hash: Option<Cow<'a, str>>,
+
+    /// The types of the modules that are
+    /// sourced from a local directory.
+    #[builder(default)]
+    module_overrides: Vec<String>,
+
+    /// Whether the files of the templated files modules are
+    /// given to the build as the templated files build context.
+    #[builder(default)]
+    templated_files: bool,
+
+    /// The stages that are built as their own images
+    /// and given to the build with `--build-context`.
+    #[builder(default)]
+    prebuilt_stages: Vec<String>,
+
+    /// The tool images that were verified, mapped to
+    /// the reference pinned to their verified digest.
+    #[builder(default)]
+    tool_images: BTreeMap<String, String>,
+}
+
+impl ContainerFileTemplate<'_> {
+    /// Gets the reference to copy a tool image from, which is pinned
+    /// to its digest when it was verified so the image that's copied
+    /// can't change between verifying it and the build.
+    fn tool_image(&self, image: &str) -> String {
+        self.tool_images
+            .get(image)
+            .map_or_else(|| image.to_owned(), Clone::clone)
+    }
+
+    fn installer_image(&self) -> String {
+        self.tool_image(&format!(
+            "{BLUE_BUILD_IMAGE_REF}:{}",
+            self.recipe
+                .blue_build_tag
+                .as_deref()
+                .unwrap_or("latest-installer")
+        ))
+    }
+}
+
+/// Shows what a single module runs during the build.
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "explain.j2", escape = "none", whitespace = "minimize")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct ModuleExplainTemplate<'a> {
+    recipe: &'a Recipe<'a>,
+    module: &'a ModuleRequiredFields<'a>,
+
+    /// The position of the module in the
+    /// Containerfile, starting at 1.
+    number: usize,
+
+    /// The stage the module runs in, or
+    /// `None` if it runs in the final image.
+    stage: Option<Cow<'a, str>>,
+
+    os_version: u64,
+
+    #[builder(default)]
+    os_family: OsFamily,
+
+    build_scripts_image: Cow<'a, str>,
+
+    /// The types of the modules that are
+    /// sourced from a local directory.
+    #[builder(default)]
+    module_overrides: Vec<String>,
 }
 
 #[derive(Debug, Clone, Template, Builder)]
@@ -49,6 +146,18 @@ pub struct GithubIssueTemplate<'a> {
     shell_version: Cow<'a, str>,
     terminal_name: Cow<'a, str>,
     terminal_version: Cow<'a, str>,
+
+    #[builder(default)]
+    logs: Cow<'a, str>,
+}
+
+/// A GitLab issue body. GitLab doesn't have a
+/// bug report template to fill in, so the issue is
+/// labeled with a quick action instead.
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "gitlab_issue.j2", escape = "md")]
+pub struct GitlabIssueTemplate<'a> {
+    issue: GithubIssueTemplate<'a>,
 }
 
 #[derive(Debug, Clone, Template, Builder)]
@@ -58,20 +167,214 @@ pub struct InitReadmeTemplate<'a> {
     repo_name: Cow<'a, str>,
     registry: Cow<'a, str>,
     image_name: Cow<'a, str>,
+    verify_badge_repo: Option<Cow<'a, str>>,
+    keyless: Option<KeylessSigning<'a>>,
+}
+
+/// The identity that keyless signatures are verified against.
+#[derive(Debug, Clone, Builder)]
+#[builder(on(Cow<'_, str>, into))]
+pub struct KeylessSigning<'a> {
+    /// A regex that matches the certificate identity.
+    identity: Cow<'a, str>,
+
+    /// The OIDC issuer of the certificate.
+    issuer: Cow<'a, str>,
 }
 
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "init/recipe.yml.j2", escape = "none")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct InitRecipeTemplate<'a> {
+    name: Cow<'a, str>,
+    description: Cow<'a, str>,
+    base_image: Cow<'a, str>,
+    image_version: Cow<'a, str>,
+
+    /// Pull all modules from this module list
+    /// file instead of listing them in the recipe.
+    common_modules: Option<Cow<'a, str>>,
+
+    #[builder(default)]
+    flatpaks: bool,
+
+    #[builder(default)]
+    brew: bool,
+
+    #[builder(default)]
+    signing: bool,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "init/modules-common.yml.j2", escape = "none")]
+pub struct InitModulesCommonTemplate {
+    #[builder(default)]
+    flatpaks: bool,
+
+    #[builder(default)]
+    brew: bool,
+
+    #[builder(default)]
+    signing: bool,
+}
+
+#[derive(Debug, Clone, Template)]
+#[template(path = "init/renovate.json.j2", escape = "none")]
+pub struct InitRenovateTemplate;
+
+#[derive(Debug, Clone, Template)]
+#[template(path = "init/dependabot.yml.j2", escape = "none")]
+pub struct InitDependabotTemplate;
+
 #[derive(Debug, Clone, Template, Builder)]
 #[template(path = "init/gitlab-ci.yml.j2", escape = "none")]
 #[builder(on(Cow<'_, str>, into))]
 pub struct GitlabCiTemplate<'a> {
     version: Cow<'a, str>,
+
+    #[builder(default = vec![Cow::Borrowed(RECIPE_FILE)])]
+    recipes: Vec<Cow<'a, str>>,
+
+    #[builder(default)]
+    keyless: bool,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(
+    path = "init/github-workflow.yml.j2",
+    escape = "none",
+    syntax = "github-actions"
+)]
+#[builder(on(Cow<'_, str>, into))]
+pub struct GithubWorkflowTemplate<'a> {
+    version: Cow<'a, str>,
+
+    #[builder(default = vec![Cow::Borrowed(RECIPE_FILE)])]
+    recipes: Vec<Cow<'a, str>>,
+
+    #[builder(default)]
+    keyless: bool,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "module/module.sh.j2", escape = "none")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct ModuleShTemplate<'a> {
+    name: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "module/module.nu.j2", escape = "none")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct ModuleNuTemplate<'a> {
+    name: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "module/schema.json.j2", escape = "none")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct ModuleSchemaTemplate<'a> {
+    name: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "module/README.md.j2", escape = "none")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct ModuleReadmeTemplate<'a> {
+    name: Cow<'a, str>,
+    entrypoint: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(
+    path = "init/github-iso.yml.j2",
+    escape = "none",
+    syntax = "github-actions"
+)]
+#[builder(on(Cow<'_, str>, into))]
+pub struct GithubIsoWorkflowTemplate<'a> {
+    version: Cow<'a, str>,
+    images: Vec<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(
+    path = "init/github-changelog.yml.j2",
+    escape = "none",
+    syntax = "github-actions"
+)]
+#[builder(on(Cow<'_, str>, into))]
+pub struct GithubChangelogWorkflowTemplate<'a> {
+    images: Vec<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(
+    path = "init/github-verify.yml.j2",
+    escape = "none",
+    syntax = "github-actions"
+)]
+#[builder(on(Cow<'_, str>, into))]
+pub struct GithubVerifyWorkflowTemplate<'a> {
+    images: Vec<Cow<'a, str>>,
+
+    #[builder(default)]
+    keyless: bool,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(
+    path = "quadlet/container.j2",
+    escape = "none",
+    whitespace = "minimize"
+)]
+#[builder(on(Cow<'_, str>, into))]
+pub struct QuadletContainerTemplate<'a> {
+    name: Cow<'a, str>,
+
+    #[builder(default)]
+    ports: Vec<Cow<'a, str>>,
+
+    #[builder(default)]
+    volumes: Vec<Cow<'a, str>>,
+
+    #[builder(default)]
+    env: Vec<Cow<'a, str>>,
+
+    #[builder(default)]
+    auto_update: bool,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "quadlet/image.j2", escape = "none", whitespace = "minimize")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct QuadletImageTemplate<'a> {
+    name: Cow<'a, str>,
+    image: Cow<'a, str>,
+
+    /// The path of the policy that the
+    /// signature of the image is checked with.
+    signature_policy: Option<Cow<'a, str>>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "quadlet/policy.json.j2", escape = "none")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct QuadletPolicyTemplate<'a> {
+    repo: Cow<'a, str>,
+    key_path: Cow<'a, str>,
+}
+
+#[derive(Debug, Clone, Template, Builder)]
+#[template(path = "quadlet/registries.yaml.j2", escape = "none")]
+#[builder(on(Cow<'_, str>, into))]
+pub struct QuadletRegistriesTemplate<'a> {
+    repo: Cow<'a, str>,
 }
 
 fn has_cosign_file() -> bool {
     trace!("has_cosign_file()");
-    std::env::current_dir()
-        .map(|p| p.join(COSIGN_PUB_PATH).exists())
-        .unwrap_or(false)
+    std::env::current_dir().is_ok_and(|p| p.join(COSIGN_PUB_PATH).exists())
 }
 
 #[must_use]
@@ -136,4 +439,52 @@ mod filters {
     {
         Ok(format!("{input}").replace(from, to))
     }
+
+    /// Quotes a value for a shell with single quotes.
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn sh_quote<T>(input: T) -> rinja::Result<String>
+    where
+        T: std::fmt::Display,
+    {
+        Ok(format!("'{}'", format!("{input}").replace('\'', r"'\''")))
+    }
+
+    /// Escapes a value for a double quoted `LABEL`, including
+    /// `$` so that the value isn't expanded like a variable.
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn label<T>(input: T) -> rinja::Result<String>
+    where
+        T: std::fmt::Display,
+    {
+        Ok(format!("{input}")
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"")
+            .replace('$', "\\$"))
+    }
+
+    /// Quotes a value as a double quoted YAML string.
+    #[allow(clippy::unnecessary_wraps)]
+    pub fn yaml_str<T>(input: T) -> rinja::Result<String>
+    where
+        T: std::fmt::Display,
+    {
+        use std::fmt::Write;
+
+        let mut quoted = String::from('"');
+        for c in format!("{input}").chars() {
+            match c {
+                '\\' => quoted.push_str("\\\\"),
+                '"' => quoted.push_str("\\\""),
+                '\n' => quoted.push_str("\\n"),
+                '\r' => quoted.push_str("\\r"),
+                '\t' => quoted.push_str("\\t"),
+                c if c.is_control() => {
+                    let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+                }
+                c => quoted.push(c),
+            }
+        }
+        quoted.push('"');
+        Ok(quoted)
+    }
 }
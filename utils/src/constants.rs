@@ -3,20 +3,36 @@ pub const ARCHIVE_SUFFIX: &str = "tar.gz";
 pub const CONFIG_PATH: &str = "./config";
 pub const CONTAINERFILES_PATH: &str = "./containerfiles";
 pub const CONTAINER_FILE: &str = "Containerfile";
+pub const DRIVER_VERSIONS_CACHE_FILE: &str = "driver-versions.json";
 pub const COSIGN_PUB_PATH: &str = "./cosign.pub";
 pub const COSIGN_PRIV_PATH: &str = "./cosign.key";
 pub const FILES_PATH: &str = "./files";
+pub const GIT_MODULES_PATH: &str = "./.bluebuild-git-modules";
+pub const GITLAB_DOTENV_REPORT: &str = "./bluebuild.env";
+pub const GITLAB_JUNIT_REPORT: &str = "./bluebuild-junit.xml";
 pub const LOCAL_BUILD: &str = "/etc/bluebuild";
+pub const LOCAL_MODULES_PATH: &str = "./modules";
 pub const MODULES_PATH: &str = "./config/modules";
+pub const MODULE_MANIFEST_PATH: &str = "/usr/share/bluebuild/modules.json";
+pub const MOK_CERT_PATH: &str = "/usr/share/bluebuild/mok.der";
+pub const MODULE_OVERRIDES_PATH: &str = "./.bluebuild-module-overrides";
 pub const RECIPE_FILE: &str = "recipe.yml";
 pub const RECIPE_PATH: &str = "./recipes";
+pub const SCRIPTS_PATH: &str = "./files/scripts";
+pub const TEMPLATED_FILES_CONTEXT: &str = "bluebuild-templated-files";
+pub const REPO_CONFIG_FILE: &str = "./.bluebuild.toml";
+pub const USER_CONFIG_FILE: &str = "bluebuild/config.toml";
 
 // Labels
 pub const BUILD_ID_LABEL: &str = "org.blue-build.build-id";
+pub const CONTENT_HASH_LABEL: &str = "org.blue-build.content-hash";
 pub const IMAGE_VERSION_LABEL: &str = "org.opencontainers.image.version";
+pub const KERNEL_LABEL: &str = "ostree.linux";
+pub const MODULES_LABEL: &str = "org.blue-build.modules";
 
 // BlueBuild vars
 pub const BB_BUILDKIT_CACHE_GHA: &str = "BB_BUILDKIT_CACHE_GHA";
+pub const BB_CREDS: &str = "BB_CREDS";
 pub const BB_PASSWORD: &str = "BB_PASSWORD";
 pub const BB_PRIVATE_KEY: &str = "BB_PRIVATE_KEY";
 pub const BB_REGISTRY: &str = "BB_REGISTRY";
@@ -24,10 +40,35 @@ pub const BB_REGISTRY_NAMESPACE: &str = "BB_REGISTRY_NAMESPACE";
 pub const BB_USERNAME: &str = "BB_USERNAME";
 pub const BB_BUILD_RECHUNK: &str = "BB_BUILD_RECHUNK";
 pub const BB_BUILD_RECHUNK_CLEAR_PLAN: &str = "BB_BUILD_RECHUNK_CLEAR_PLAN";
+pub const BB_BUILD_RECHUNKER: &str = "BB_BUILD_RECHUNKER";
+pub const BB_BUILD_RM_AFTER_PUSH: &str = "BB_BUILD_RM_AFTER_PUSH";
+pub const BB_BUILD_ALLOW_RECIPE_HOOKS: &str = "BB_BUILD_ALLOW_RECIPE_HOOKS";
+pub const BB_BUILD_ALLOW_SECRET_ENV: &str = "BB_BUILD_ALLOW_SECRET_ENV";
+pub const BB_BUILD_SKIP_PREFLIGHT: &str = "BB_BUILD_SKIP_PREFLIGHT";
+pub const BB_BUILD_SKIP_TESTS: &str = "BB_BUILD_SKIP_TESTS";
+pub const BB_BUILD_CACHE_FROM: &str = "BB_BUILD_CACHE_FROM";
+pub const BB_BUILD_CACHE_TO: &str = "BB_BUILD_CACHE_TO";
+pub const BB_BUILD_DRIVER: &str = "BB_BUILD_DRIVER";
+pub const BB_CI_DRIVER: &str = "BB_CI_DRIVER";
+pub const BB_CONTAINERIZED: &str = "BB_CONTAINERIZED";
+pub const BB_INSPECT_DRIVER: &str = "BB_INSPECT_DRIVER";
+pub const BB_RUN_DRIVER: &str = "BB_RUN_DRIVER";
+pub const BB_SIGNING_DRIVER: &str = "BB_SIGNING_DRIVER";
+pub const BB_MATRIX_TOKEN: &str = "BB_MATRIX_TOKEN";
+pub const BB_METRICS_PUSHGATEWAY: &str = "BB_METRICS_PUSHGATEWAY";
+pub const BB_METRICS_TEXTFILE: &str = "BB_METRICS_TEXTFILE";
+pub const BB_NO_VERIFY_TOOLS: &str = "BB_NO_VERIFY_TOOLS";
+pub const BB_OFFLINE: &str = "BB_OFFLINE";
+pub const BB_HOST_JOBS: &str = "BB_HOST_JOBS";
+pub const BB_INSECURE_ALLOW_UNVERIFIED_TOOLS: &str = "BB_INSECURE_ALLOW_UNVERIFIED_TOOLS";
 
 // Docker vars
 pub const DOCKER_HOST: &str = "DOCKER_HOST";
 
+// Podman/Buildah vars
+pub const BUILDAH_ISOLATION: &str = "BUILDAH_ISOLATION";
+pub const STORAGE_DRIVER: &str = "STORAGE_DRIVER";
+
 // Cosign vars
 pub const COSIGN_PASSWORD: &str = "COSIGN_PASSWORD";
 pub const COSIGN_PRIVATE_KEY: &str = "COSIGN_PRIVATE_KEY";
@@ -36,23 +77,34 @@ pub const GITHUB_TOKEN_ISSUER_URL: &str = "https://token.actions.githubuserconte
 pub const SIGSTORE_ID_TOKEN: &str = "SIGSTORE_ID_TOKEN";
 
 // GitHub CI vars
+pub const ACTIONS_ID_TOKEN_REQUEST_TOKEN: &str = "ACTIONS_ID_TOKEN_REQUEST_TOKEN";
+pub const ACTIONS_ID_TOKEN_REQUEST_URL: &str = "ACTIONS_ID_TOKEN_REQUEST_URL";
 pub const GITHUB_ACTIONS: &str = "GITHUB_ACTIONS";
 pub const GITHUB_ACTOR: &str = "GITHUB_ACTOR";
+pub const GITHUB_API_URL: &str = "GITHUB_API_URL";
 pub const GITHUB_EVENT_NAME: &str = "GITHUB_EVENT_NAME";
 pub const GITHUB_EVENT_PATH: &str = "GITHUB_EVENT_PATH";
+pub const GITHUB_HEAD_REF: &str = "GITHUB_HEAD_REF";
+pub const GITHUB_OUTPUT: &str = "GITHUB_OUTPUT";
 pub const GITHUB_REF_NAME: &str = "GITHUB_REF_NAME";
 pub const GITHUB_RESPOSITORY: &str = "GITHUB_REPOSITORY";
 pub const GITHUB_REPOSITORY_OWNER: &str = "GITHUB_REPOSITORY_OWNER";
+pub const GITHUB_RUN_ID: &str = "GITHUB_RUN_ID";
 pub const GITHUB_SERVER_URL: &str = "GITHUB_SERVER_URL";
 pub const GITHUB_SHA: &str = "GITHUB_SHA";
+pub const GITHUB_STEP_SUMMARY: &str = "GITHUB_STEP_SUMMARY";
 pub const GITHUB_TOKEN: &str = "GH_TOKEN";
 pub const GITHUB_WORKFLOW_REF: &str = "GITHUB_WORKFLOW_REF";
 pub const PR_EVENT_NUMBER: &str = "GH_PR_EVENT_NUMBER";
 
 // GitLab CI vars
+pub const CI_API_V4_URL: &str = "CI_API_V4_URL";
 pub const CI_COMMIT_REF_NAME: &str = "CI_COMMIT_REF_NAME";
+pub const CI_COMMIT_SHA: &str = "CI_COMMIT_SHA";
 pub const CI_COMMIT_SHORT_SHA: &str = "CI_COMMIT_SHORT_SHA";
 pub const CI_DEFAULT_BRANCH: &str = "CI_DEFAULT_BRANCH";
+pub const CI_JOB_TOKEN: &str = "CI_JOB_TOKEN";
+pub const CI_JOB_URL: &str = "CI_JOB_URL";
 pub const CI_MERGE_REQUEST_IID: &str = "CI_MERGE_REQUEST_IID";
 pub const CI_PIPELINE_SOURCE: &str = "CI_PIPELINE_SOURCE";
 pub const CI_PROJECT_NAME: &str = "CI_PROJECT_NAME";
@@ -64,6 +116,7 @@ pub const CI_REGISTRY: &str = "CI_REGISTRY";
 pub const CI_REGISTRY_PASSWORD: &str = "CI_REGISTRY_PASSWORD";
 pub const CI_REGISTRY_USER: &str = "CI_REGISTRY_USER";
 pub const GITLAB_CI: &str = "GITLAB_CI";
+pub const GITLAB_TOKEN: &str = "GITLAB_TOKEN";
 
 // Terminal vars
 pub const TERM_PROGRAM: &str = "TERM_PROGRAM";
@@ -73,17 +126,22 @@ pub const LC_TERMINAL_VERSION: &str = "LC_TERMINAL_VERSION";
 pub const XDG_RUNTIME_DIR: &str = "XDG_RUNTIME_DIR";
 
 // Misc
+pub const BLUE_BUILD_IMAGE_REF: &str = "ghcr.io/blue-build/cli";
 pub const BUILD_SCRIPTS_IMAGE_REF: &str = "ghcr.io/blue-build/cli/build-scripts";
 pub const COSIGN_IMAGE: &str = "ghcr.io/sigstore/cosign/cosign:v2.4.1";
+pub const MODULES_IMAGE: &str = "ghcr.io/blue-build/modules:latest";
 pub const OCI_ARCHIVE: &str = "oci-archive";
 pub const OSTREE_IMAGE_SIGNED: &str = "ostree-image-signed";
 pub const OSTREE_UNVERIFIED_IMAGE: &str = "ostree-unverified-image";
+pub const SHELLCHECK_IMAGE: &str = "docker.io/koalaman/shellcheck:stable";
 pub const SKOPEO_IMAGE: &str = "quay.io/skopeo/stable:latest";
 pub const TEMPLATE_REPO_URL: &str = "https://github.com/blue-build/template.git";
 pub const UNKNOWN_SHELL: &str = "<unknown shell>";
 pub const UNKNOWN_VERSION: &str = "<unknown version>";
 pub const UNKNOWN_TERMINAL: &str = "<unknown terminal>";
 pub const GITHUB_CHAR_LIMIT: usize = 8100; // Magic number accepted by Github
+pub const GITLAB_CHAR_LIMIT: usize = 8100; // Keeps the URL under common proxy limits
+pub const GITLAB_URL: &str = "https://gitlab.com";
 
 // Messages
 pub const BUG_REPORT_WARNING_MESSAGE: &str =
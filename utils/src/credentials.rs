@@ -1,21 +1,26 @@
 use std::{
-    env,
-    sync::{LazyLock, Mutex},
+    env, mem,
+    str::FromStr,
+    sync::{LazyLock, Mutex, RwLock},
 };
 
 use bon::Builder;
 use clap::Args;
-use docker_credential::DockerCredential;
 use log::trace;
+use miette::{bail, Result};
 
 use crate::{
     constants::{
-        BB_PASSWORD, BB_REGISTRY, BB_USERNAME, CI_REGISTRY, CI_REGISTRY_PASSWORD, CI_REGISTRY_USER,
-        GITHUB_ACTIONS, GITHUB_ACTOR, GITHUB_TOKEN,
+        BB_CREDS, BB_PASSWORD, BB_REGISTRY, BB_USERNAME, CI_REGISTRY, CI_REGISTRY_PASSWORD,
+        CI_REGISTRY_USER, GITHUB_ACTIONS, GITHUB_ACTOR, GITHUB_TOKEN,
     },
     string,
 };
 
+mod auth_file;
+
+pub use auth_file::{auth_files, CredentialStore};
+
 static INIT: LazyLock<Mutex<bool>> = LazyLock::new(|| Mutex::new(false));
 
 /// Stored user creds.
@@ -28,18 +33,25 @@ static INIT_CREDS: Mutex<CredentialsArgs> = Mutex::new(CredentialsArgs {
     username: None,
     password: None,
     registry: None,
+    creds: Vec::new(),
 });
 
+/// The credentials of each registry that were given with `--creds`.
+static REGISTRY_CREDENTIALS: RwLock<Vec<Credentials>> = RwLock::new(Vec::new());
+
 /// Stores the global env credentials.
 ///
 /// This on load will determine the credentials based off of
 /// `USER_CREDS` and env vars from CI systems. Once this is called
-/// the value is stored and cannot change.
+/// the value is stored and only changes with [`Credentials::set`].
 ///
 /// If you have user
 /// provided credentials, make sure you update `USER_CREDS`
 /// before trying to access this reference.
-static ENV_CREDENTIALS: LazyLock<Option<Credentials>> = LazyLock::new(|| {
+static ENV_CREDENTIALS: LazyLock<RwLock<Option<Credentials>>> =
+    LazyLock::new(|| RwLock::new(env_credentials()));
+
+fn env_credentials() -> Option<Credentials> {
     let (username, password, registry) = {
         INIT_CREDS.lock().map_or((None, None, None), |mut creds| {
             (
@@ -61,27 +73,18 @@ static ENV_CREDENTIALS: LazyLock<Option<Credentials>> = LazyLock::new(|| {
     };
     trace!("Registry: {registry:?}");
 
-    let (username, password) = match (
-        (username, password),
-        docker_credential::get_credential(&registry).ok(),
-        docker_credential::get_podman_credential(&registry).ok(),
-        (
-            env::var(CI_REGISTRY_USER).ok(),
-            env::var(CI_REGISTRY_PASSWORD).ok(),
-        ),
-        (env::var(GITHUB_ACTOR).ok(), env::var(GITHUB_TOKEN).ok()),
-    ) {
-        ((Some(username), Some(password)), _, _, _, _)
-        | (_, Some(DockerCredential::UsernamePassword(username, password)), _, _, _)
-        | (_, _, Some(DockerCredential::UsernamePassword(username, password)), _, _)
-        | (_, _, _, (Some(username), Some(password)), _)
-        | (_, _, _, _, (Some(username), Some(password)))
-            if !username.is_empty() && !password.is_empty() =>
-        {
-            (username, password)
-        }
-        _ => return None,
-    };
+    // The sources are checked lazily so that credential
+    // helpers only run when they're needed
+    let (username, password) = non_empty(username, password)
+        .or_else(|| Credentials::given(&registry).map(|creds| (creds.username, creds.password)))
+        .or_else(|| auth_file::credential(&registry))
+        .or_else(|| {
+            non_empty(
+                env::var(CI_REGISTRY_USER).ok(),
+                env::var(CI_REGISTRY_PASSWORD).ok(),
+            )
+        })
+        .or_else(|| non_empty(env::var(GITHUB_ACTOR).ok(), env::var(GITHUB_TOKEN).ok()))?;
     trace!("Username: {username}");
 
     Some(
@@ -91,7 +94,13 @@ static ENV_CREDENTIALS: LazyLock<Option<Credentials>> = LazyLock::new(|| {
             .password(password)
             .build(),
     )
-});
+}
+
+fn non_empty(username: Option<String>, password: Option<String>) -> Option<(String, String)> {
+    username
+        .zip(password)
+        .filter(|(username, password)| !username.is_empty() && !password.is_empty())
+}
 
 /// The credentials for logging into image registries.
 #[derive(Debug, Default, Clone, Builder)]
@@ -101,6 +110,48 @@ pub struct Credentials {
     pub password: String,
 }
 
+/// Parses `registry=username:password`.
+///
+/// The password is everything after the first `:`,
+/// so it can contain `:` itself.
+impl FromStr for Credentials {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let Some((registry, (username, password))) = s
+            .split_once('=')
+            .and_then(|(registry, login)| Some((registry, login.split_once(':')?)))
+        else {
+            bail!("Expected `registry=username:password`");
+        };
+        if registry.is_empty() || username.is_empty() || password.is_empty() {
+            bail!("The registry, username, and password can't be empty");
+        }
+
+        Ok(Self {
+            registry: registry.into(),
+            username: username.into(),
+            password: password.into(),
+        })
+    }
+}
+
+/// The credentials of one or more registries, separated by
+/// whitespace so that `BB_CREDS` can hold several of them.
+#[derive(Debug, Default, Clone)]
+pub struct CredentialsList(pub Vec<Credentials>);
+
+impl FromStr for CredentialsList {
+    type Err = miette::Report;
+
+    fn from_str(s: &str) -> Result<Self> {
+        s.split_whitespace()
+            .map(str::parse)
+            .collect::<Result<_>>()
+            .map(Self)
+    }
+}
+
 impl Credentials {
     /// Set the users credentials for
     /// the current set of actions.
@@ -111,24 +162,122 @@ impl Credentials {
     ///
     /// # Panics
     /// Will panic if it can't lock the mutex.
-    pub fn init(args: CredentialsArgs) {
+    pub fn init(mut args: CredentialsArgs) {
         trace!("Credentials::init()");
         let mut initialized = INIT.lock().expect("Must lock INIT");
 
         if !*initialized {
+            *REGISTRY_CREDENTIALS
+                .write()
+                .expect("Must lock REGISTRY_CREDENTIALS") = mem::take(&mut args.creds)
+                .into_iter()
+                .flat_map(|list| list.0)
+                .collect();
             let mut creds_lock = INIT_CREDS.lock().expect("Must lock USER_CREDS");
             *creds_lock = args;
             drop(creds_lock);
-            let _ = ENV_CREDENTIALS.as_ref();
+            LazyLock::force(&ENV_CREDENTIALS);
 
             *initialized = true;
         }
     }
 
     /// Get the credentials for the current set of actions.
-    pub fn get() -> Option<&'static Self> {
+    ///
+    /// The credentials are returned as a copy since they can be
+    /// replaced with [`Credentials::set`], so callers that held
+    /// on to the `&'static` reference this used to return need
+    /// to call this again after the credentials change.
+    ///
+    /// # Panics
+    /// Will panic if the lock is poisoned.
+    pub fn get() -> Option<Self> {
         trace!("credentials::get()");
-        ENV_CREDENTIALS.as_ref()
+        ENV_CREDENTIALS
+            .read()
+            .expect("Should lock ENV_CREDENTIALS")
+            .clone()
+    }
+
+    /// The credentials of every registry, starting with
+    /// the ones from [`Credentials::get`].
+    ///
+    /// A registry that was given more than once uses
+    /// the credentials it was given first.
+    ///
+    /// # Panics
+    /// Will panic if a lock is poisoned.
+    #[must_use]
+    pub fn all() -> Vec<Self> {
+        trace!("Credentials::all()");
+        let mut all = Vec::new();
+
+        for creds in Self::get().into_iter().chain(
+            REGISTRY_CREDENTIALS
+                .read()
+                .expect("Should lock REGISTRY_CREDENTIALS")
+                .iter()
+                .cloned(),
+        ) {
+            if !all.iter().any(|other: &Self| other.is_for(&creds.registry)) {
+                all.push(creds);
+            }
+        }
+        all
+    }
+
+    /// The credentials of `registry`, if any were given for it.
+    #[must_use]
+    pub fn for_registry(registry: &str) -> Option<Self> {
+        trace!("Credentials::for_registry({registry})");
+        Self::all().into_iter().find(|creds| creds.is_for(registry))
+    }
+
+    /// The credentials of `registry` from `--creds`.
+    fn given(registry: &str) -> Option<Self> {
+        REGISTRY_CREDENTIALS
+            .read()
+            .ok()?
+            .iter()
+            .find(|creds| creds.is_for(registry))
+            .cloned()
+    }
+
+    /// Whether these are the credentials of `registry`,
+    /// which can be written differently, like `index.docker.io`
+    /// for `docker.io`.
+    #[must_use]
+    pub fn is_for(&self, registry: &str) -> bool {
+        auth_file::normalize(&self.registry) == auth_file::normalize(registry)
+    }
+
+    /// Replaces the credentials for the current set of actions,
+    /// like with the ones the user was prompted for.
+    ///
+    /// # Panics
+    /// Will panic if the lock is poisoned.
+    pub fn set(creds: Self) {
+        trace!("Credentials::set({})", creds.registry);
+        *ENV_CREDENTIALS
+            .write()
+            .expect("Should lock ENV_CREDENTIALS") = Some(creds);
+    }
+
+    /// The places these credentials can be saved in, with the
+    /// credential helper of the registry first if it has one.
+    #[must_use]
+    pub fn stores(&self) -> Vec<CredentialStore> {
+        auth_file::stores(&self.registry)
+    }
+
+    /// Saves these credentials in `store` so that later
+    /// builds find them without being given them.
+    ///
+    /// # Errors
+    /// Will error if the auth file can't be written
+    /// or the credential helper fails.
+    pub fn save(&self, store: &CredentialStore) -> Result<()> {
+        auth_file::save(store, &self.registry, &self.username, &self.password)
     }
 }
 
@@ -148,4 +297,20 @@ pub struct CredentialsArgs {
     /// container registry.
     #[arg(short = 'P', long, env = BB_PASSWORD, hide_env_values = true)]
     pub password: Option<String>,
+
+    /// The credentials of another registry, as
+    /// `registry=username:password`, like for pulling
+    /// the base image from a private registry.
+    ///
+    /// Can be used more than once. In the config file,
+    /// set `creds` to a list of them. `BB_CREDS` can
+    /// hold several, separated by spaces or newlines.
+    #[arg(
+        long,
+        env = BB_CREDS,
+        value_name = "REGISTRY=USERNAME:PASSWORD",
+        hide_env_values = true
+    )]
+    #[builder(default)]
+    pub creds: Vec<CredentialsList>,
 }
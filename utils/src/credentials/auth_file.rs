@@ -0,0 +1,709 @@
+//! Reads the credentials of a registry from the auth files of
+//! docker and podman.
+//!
+//! Like docker and podman, a registry in `credHelpers` gets its
+//! credentials from that helper, otherwise they're read from
+//! `auths` and then from the `credsStore` helper. A helper is
+//! the program `docker-credential-<name>`, which is run with
+//! `get` and the registry on stdin.
+//!
+//! Credentials are saved either in the auth file of podman
+//! or with a helper that an auth file already references.
+
+use std::{
+    cmp::Reverse,
+    collections::HashMap,
+    env,
+    fmt::Display,
+    fs::{self, OpenOptions, Permissions},
+    io::{ErrorKind, Write},
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+    process::Stdio,
+};
+
+use base64::{
+    alphabet,
+    engine::{general_purpose::GeneralPurpose, DecodePaddingMode, GeneralPurposeConfig},
+    Engine,
+};
+use log::{debug, trace, warn};
+use miette::{bail, Context, IntoDiagnostic, Result};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::cmd;
+
+/// The response of a helper that holds an identity
+/// token instead of a password, which can't log in.
+const IDENTITY_TOKEN_USER: &str = "<token>";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthFile {
+    #[serde(default)]
+    auths: HashMap<String, AuthEntry>,
+
+    #[serde(default)]
+    cred_helpers: HashMap<String, String>,
+
+    creds_store: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AuthEntry {
+    auth: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct HelperResponse {
+    username: String,
+    secret: String,
+}
+
+/// Where credentials can be saved.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialStore {
+    /// An auth file, where the credentials are stored base64 encoded.
+    AuthFile(PathBuf),
+
+    /// A credential helper, like `secretservice` or `pass`,
+    /// which usually keeps them in the keyring.
+    Helper(String),
+}
+
+impl Display for CredentialStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::AuthFile(path) => write!(f, "the auth file {}", path.display()),
+            Self::Helper(helper) => write!(f, "the credential helper docker-credential-{helper}"),
+        }
+    }
+}
+
+/// The standard base64 engine that
+/// doesn't care about the padding.
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// The auth files in the order they're read in, which
+/// is the config of docker and then the auth files of podman.
+pub fn auth_files() -> Vec<PathBuf> {
+    let mut files = Vec::with_capacity(3);
+
+    files.extend(
+        env::var_os("DOCKER_CONFIG")
+            .map(PathBuf::from)
+            .or_else(|| crate::home_dir().map(|home| home.join(".docker")))
+            .map(|dir| dir.join("config.json")),
+    );
+
+    if let Some(auth_file) = env::var_os("REGISTRY_AUTH_FILE") {
+        files.push(auth_file.into());
+    } else {
+        files.extend(
+            env::var_os("XDG_RUNTIME_DIR")
+                .map(|dir| PathBuf::from(dir).join("containers/auth.json")),
+        );
+        files.extend(crate::config_dir().map(|dir| dir.join("containers/auth.json")));
+    }
+    files
+}
+
+/// The host of a key of an auth file, which can have
+/// a scheme, a path, or be an alias of Docker Hub.
+pub(super) fn normalize(key: &str) -> &str {
+    let (host, _) = split(key);
+
+    match host {
+        "index.docker.io" | "registry-1.docker.io" => "docker.io",
+        _ => host,
+    }
+}
+
+/// Splits a key of an auth file into its host and its
+/// path, which are without the scheme and the slashes.
+fn split(key: &str) -> (&str, &str) {
+    let key = key
+        .strip_prefix("https://")
+        .or_else(|| key.strip_prefix("http://"))
+        .unwrap_or(key);
+    key.split_once('/')
+        .map_or((key, ""), |(host, path)| (host, path.trim_matches('/')))
+}
+
+impl AuthFile {
+    /// Looks up `registry` in `map`, preferring an exact match.
+    ///
+    /// Otherwise the most specific key of the same host is used:
+    /// the key with the longest path that `registry` is in, like
+    /// `quay.io/org` for `quay.io/org/image`, then a key with any
+    /// other path, like `https://index.docker.io/v1/`. Keys that
+    /// are as specific are ordered by name, so that the same key
+    /// is used every time.
+    fn lookup<'a, V>(map: &'a HashMap<String, V>, registry: &str) -> Option<&'a V> {
+        map.get(registry).or_else(|| {
+            let host = normalize(registry);
+            let (_, path) = split(registry);
+            map.iter()
+                .filter(|(key, _)| normalize(key) == host)
+                .min_by_key(|(key, _)| {
+                    let (_, key_path) = split(key);
+                    let specificity = if key_path.is_empty() {
+                        1
+                    } else if path == key_path
+                        || path
+                            .strip_prefix(key_path)
+                            .is_some_and(|rest| rest.starts_with('/'))
+                    {
+                        key_path.len() + 1
+                    } else {
+                        0
+                    };
+                    (Reverse(specificity), key.as_str())
+                })
+                .map(|(_, value)| value)
+        })
+    }
+
+    fn helper(&self, registry: &str) -> Option<&str> {
+        Self::lookup(&self.cred_helpers, registry)
+            .map(String::as_str)
+            .filter(|helper| !helper.is_empty())
+    }
+
+    fn auth(&self, registry: &str) -> Result<Option<(String, String)>> {
+        let Some(auth) = Self::lookup(&self.auths, registry).and_then(|entry| entry.auth.as_ref())
+        else {
+            return Ok(None);
+        };
+
+        // Some tools write the auth without its padding
+        let decoded = BASE64.decode(auth).into_diagnostic()?;
+        let decoded = String::from_utf8(decoded).into_diagnostic()?;
+        let Some((username, password)) = decoded.split_once(':') else {
+            bail!("The auth of {registry} isn't `username:password`");
+        };
+        Ok(Some((username.into(), password.into())))
+    }
+}
+
+/// The credentials of `registry` from the auth files, running the
+/// credential helpers that they reference. A helper that fails is
+/// logged and the next auth file is tried.
+pub(super) fn credential(registry: &str) -> Option<(String, String)> {
+    trace!("auth_file::credential({registry})");
+
+    for (path, file) in read_auth_files() {
+        let result = if let Some(helper) = file.helper(registry) {
+            run_helper(helper, registry)
+        } else {
+            match file.auth(registry) {
+                Ok(None) => file
+                    .creds_store
+                    .as_deref()
+                    .filter(|store| !store.is_empty())
+                    .map_or(Ok(None), |store| run_helper(store, registry)),
+                result => result,
+            }
+        };
+
+        match result {
+            Ok(Some((username, password))) if !username.is_empty() && !password.is_empty() => {
+                debug!(
+                    "Using the credentials of {registry} from {}",
+                    path.display()
+                );
+                return Some((username, password));
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Failed to get the credentials of {registry} from {}:\n{e:?}",
+                path.display()
+            ),
+        }
+    }
+    None
+}
+
+/// Reads the auth files, skipping the ones
+/// that are missing or can't be parsed.
+fn read_auth_files() -> impl Iterator<Item = (PathBuf, AuthFile)> {
+    auth_files().into_iter().filter_map(|path| {
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == ErrorKind::NotFound => return None,
+            Err(e) => {
+                debug!("Failed to read {}: {e}", path.display());
+                return None;
+            }
+        };
+        match serde_json::from_slice::<AuthFile>(&contents) {
+            Ok(file) => Some((path, file)),
+            Err(e) => {
+                warn!("Failed to parse {}: {e}", path.display());
+                None
+            }
+        }
+    })
+}
+
+/// The auth file that credentials are saved in. Podman reads it
+/// after the one in `$XDG_RUNTIME_DIR`, but unlike that one it
+/// survives a reboot.
+fn save_file() -> Option<PathBuf> {
+    env::var_os("REGISTRY_AUTH_FILE")
+        .map(PathBuf::from)
+        .or_else(|| crate::config_dir().map(|dir| dir.join("containers/auth.json")))
+}
+
+/// The places the credentials of `registry` can be saved in.
+///
+/// A helper is only offered if an auth file references it for
+/// the registry, since the credentials wouldn't be found otherwise.
+pub(super) fn stores(registry: &str) -> Vec<CredentialStore> {
+    let mut stores = read_auth_files()
+        .filter_map(|(_, file)| {
+            file.helper(registry)
+                .or_else(|| {
+                    file.creds_store
+                        .as_deref()
+                        .filter(|store| !store.is_empty())
+                })
+                .map(|helper| CredentialStore::Helper(helper.to_owned()))
+        })
+        .take(1)
+        .collect::<Vec<_>>();
+    stores.extend(save_file().map(CredentialStore::AuthFile));
+    stores
+}
+
+/// Saves the credentials of `registry` in `store`.
+pub(super) fn save(
+    store: &CredentialStore,
+    registry: &str,
+    username: &str,
+    password: &str,
+) -> Result<()> {
+    trace!("auth_file::save({store}, {registry}, {username})");
+
+    match store {
+        CredentialStore::AuthFile(path) => {
+            let mut file = match fs::read(path) {
+                Ok(contents) => serde_json::from_slice::<Map<String, Value>>(&contents)
+                    .into_diagnostic()
+                    .with_context(|| format!("Failed to parse {}", path.display()))?,
+                Err(e) if e.kind() == ErrorKind::NotFound => Map::new(),
+                Err(e) => {
+                    return Err(e)
+                        .into_diagnostic()
+                        .with_context(|| format!("Failed to read {}", path.display()))
+                }
+            };
+            let auths = file
+                .entry("auths")
+                .or_insert_with(|| Value::Object(Map::new()));
+            let Some(auths) = auths.as_object_mut() else {
+                bail!("The `auths` of {} isn't an object", path.display());
+            };
+            auths.insert(
+                registry.to_owned(),
+                json!({ "auth": BASE64.encode(format!("{username}:{password}")) }),
+            );
+
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent).into_diagnostic()?;
+            }
+            let contents = serde_json::to_vec_pretty(&file).into_diagnostic()?;
+            write_private(path, &contents)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to write {}", path.display()))
+        }
+        CredentialStore::Helper(helper) => {
+            let program = format!("docker-credential-{helper}");
+            let request = json!({
+                "ServerURL": server_url(registry),
+                "Username": username,
+                "Secret": password,
+            });
+
+            let mut child = cmd!(&program, "store")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::piped())
+                .stderr(Stdio::piped())
+                .spawn()
+                .into_diagnostic()
+                .with_context(|| format!("Failed to run {program}"))?;
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin
+                    .write_all(request.to_string().as_bytes())
+                    .into_diagnostic()?;
+            }
+            let output = child.wait_with_output().into_diagnostic()?;
+
+            if !output.status.success() {
+                bail!(
+                    "{program} failed with {}:\n{}{}",
+                    output.status,
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Writes `contents` to a temp file next to `path` that only the
+/// user can read, which is then renamed over `path` so that the
+/// file is never partly written or readable by others. A symlinked
+/// auth file stays a symlink.
+fn write_private(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_owned());
+    let tmp = path.with_file_name(format!(
+        ".{}.{}.tmp",
+        path.file_name().unwrap_or_default().to_string_lossy(),
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&tmp);
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .and_then(|mut out| {
+            // The mode is only applied when creating the file, minus the umask
+            out.set_permissions(Permissions::from_mode(0o600))?;
+            out.write_all(contents)?;
+            out.sync_all()
+        })
+        .and_then(|()| fs::rename(&tmp, &path));
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result
+}
+
+/// The server a helper stores the credentials of `registry` under.
+fn server_url(registry: &str) -> &str {
+    // Docker stores the credentials of Docker Hub under its old URL
+    if normalize(registry) == "docker.io" {
+        "https://index.docker.io/v1/"
+    } else {
+        registry
+    }
+}
+
+/// Gets the credentials of `registry` from the helper `docker-credential-<helper>`.
+///
+/// Returns `None` if the helper doesn't have credentials for the registry.
+fn run_helper(helper: &str, registry: &str) -> Result<Option<(String, String)>> {
+    let program = format!("docker-credential-{helper}");
+    trace!("{program} get");
+
+    let server = server_url(registry);
+
+    let mut child = match cmd!(&program, "get")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            bail!(
+                help = format!("Install {program} or remove `{helper}` from the auth file"),
+                "The credential helper {program} isn't installed"
+            );
+        }
+        Err(e) => return Err(e).into_diagnostic(),
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(server.as_bytes()).into_diagnostic()?;
+    }
+    let output = child
+        .wait_with_output()
+        .into_diagnostic()
+        .with_context(|| format!("Failed to run {program}"))?;
+
+    if !output.status.success() {
+        let stdout = String::from_utf8_lossy(&output.stdout);
+
+        // The helpers all print this when they don't have the registry
+        if stdout.contains("credentials not found") {
+            debug!("{program} has no credentials for {registry}");
+            return Ok(None);
+        }
+        bail!(
+            "{program} failed with {}:\n{stdout}{}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let response: HelperResponse = serde_json::from_slice(&output.stdout)
+        .into_diagnostic()
+        .with_context(|| format!("{program} returned malformed credentials"))?;
+    if response.username == IDENTITY_TOKEN_USER {
+        debug!("{program} has an identity token for {registry}, which can't be used to log in");
+        return Ok(None);
+    }
+    Ok(Some((response.username, response.secret)))
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        collections::HashMap,
+        env, fs,
+        path::Path,
+        sync::{Mutex, PoisonError},
+    };
+
+    use rstest::rstest;
+    use serde_json::json;
+    use tempfile::TempDir;
+
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::{
+        credential, normalize, save, stores, AuthEntry, AuthFile, CredentialStore, BASE64,
+    };
+    use base64::Engine;
+
+    /// Runs `test` with `DOCKER_CONFIG` and `REGISTRY_AUTH_FILE`
+    /// in a temp dir. The env is shared, so only one test uses
+    /// the auth files at a time.
+    fn with_auth_files(test: impl FnOnce(&Path)) {
+        static LOCK: Mutex<()> = Mutex::new(());
+        let _lock = LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+
+        let dir = TempDir::new().unwrap();
+        env::set_var("DOCKER_CONFIG", dir.path().join("docker"));
+        env::set_var("REGISTRY_AUTH_FILE", dir.path().join("auth.json"));
+        test(dir.path());
+        env::remove_var("DOCKER_CONFIG");
+        env::remove_var("REGISTRY_AUTH_FILE");
+    }
+
+    fn write_json(path: &Path, value: &serde_json::Value) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, value.to_string()).unwrap();
+    }
+
+    fn auth_file(auths: &[(&str, &str)]) -> AuthFile {
+        AuthFile {
+            auths: auths
+                .iter()
+                .map(|(key, auth)| {
+                    (
+                        (*key).to_owned(),
+                        AuthEntry {
+                            auth: Some((*auth).to_owned()),
+                        },
+                    )
+                })
+                .collect(),
+            ..AuthFile::default()
+        }
+    }
+
+    #[rstest]
+    #[case("ghcr.io", "ghcr.io")]
+    #[case("https://ghcr.io", "ghcr.io")]
+    #[case("http://localhost:5000/v2/", "localhost:5000")]
+    #[case("quay.io/org/image", "quay.io")]
+    #[case("https://index.docker.io/v1/", "docker.io")]
+    #[case("registry-1.docker.io", "docker.io")]
+    #[case("docker.io", "docker.io")]
+    fn normalizes_keys(#[case] key: &str, #[case] expected: &str) {
+        assert_eq!(normalize(key), expected);
+    }
+
+    #[rstest]
+    #[case(&["docker.io", "https://index.docker.io/v1/"], "docker.io", "docker.io")]
+    #[case(&["https://index.docker.io/v1/", "registry-1.docker.io"], "docker.io", "registry-1.docker.io")]
+    #[case(&["https://index.docker.io/v1/"], "docker.io", "https://index.docker.io/v1/")]
+    #[case(&["quay.io", "quay.io/org", "quay.io/other"], "quay.io/org/image", "quay.io/org")]
+    #[case(&["quay.io/organization", "quay.io"], "quay.io/org/image", "quay.io")]
+    #[case(&["https://ghcr.io", "http://ghcr.io", "ghcr.io/"], "ghcr.io", "ghcr.io/")]
+    #[case(&["https://ghcr.io", "http://ghcr.io"], "ghcr.io", "http://ghcr.io")]
+    #[case(&["ghcr.io"], "quay.io", "")]
+    fn looks_up_most_specific(
+        #[case] keys: &[&str],
+        #[case] registry: &str,
+        #[case] expected: &str,
+    ) {
+        // The order of a HashMap changes with every instance
+        for _ in 0..10 {
+            let map = keys
+                .iter()
+                .map(|key| ((*key).to_owned(), (*key).to_owned()))
+                .collect::<HashMap<_, _>>();
+            assert_eq!(
+                AuthFile::lookup(&map, registry).map_or("", String::as_str),
+                expected
+            );
+        }
+    }
+
+    #[rstest]
+    #[case("dXNlcjpwdw==")]
+    #[case("dXNlcjpwdw")]
+    fn decodes_auth_with_any_padding(#[case] auth: &str) {
+        let file = auth_file(&[("ghcr.io", auth)]);
+        assert_eq!(
+            file.auth("ghcr.io").unwrap(),
+            Some(("user".to_owned(), "pw".to_owned()))
+        );
+    }
+
+    #[test]
+    fn auth_without_password() {
+        let file = auth_file(&[("ghcr.io", &BASE64.encode("user"))]);
+        assert!(file.auth("ghcr.io").is_err());
+        assert_eq!(file.auth("quay.io").unwrap(), None);
+    }
+
+    #[test]
+    fn credential_from_auth_files() {
+        with_auth_files(|dir| {
+            write_json(
+                &dir.join("docker/config.json"),
+                &json!({
+                    "auths": {
+                        "https://index.docker.io/v1/": { "auth": BASE64.encode("docker:hub") },
+                    },
+                    // A helper that isn't installed is skipped
+                    "credHelpers": { "quay.io": "bluebuild-missing" },
+                }),
+            );
+            write_json(
+                &dir.join("auth.json"),
+                &json!({
+                    "auths": {
+                        "quay.io": { "auth": BASE64.encode("quay:pass") },
+                        "ghcr.io": { "auth": BASE64.encode(":") },
+                    },
+                }),
+            );
+
+            assert_eq!(
+                credential("docker.io"),
+                Some(("docker".to_owned(), "hub".to_owned()))
+            );
+            assert_eq!(
+                credential("quay.io"),
+                Some(("quay".to_owned(), "pass".to_owned()))
+            );
+            assert_eq!(credential("ghcr.io"), None);
+            assert_eq!(credential("registry.gitlab.com"), None);
+        });
+    }
+
+    #[test]
+    fn stores_of_registry() {
+        with_auth_files(|dir| {
+            let auth_file = CredentialStore::AuthFile(dir.join("auth.json"));
+            assert_eq!(stores("ghcr.io"), std::slice::from_ref(&auth_file));
+
+            write_json(
+                &dir.join("docker/config.json"),
+                &json!({
+                    "credsStore": "desktop",
+                    "credHelpers": { "quay.io": "pass", "ghcr.io": "" },
+                }),
+            );
+            assert_eq!(
+                stores("quay.io"),
+                [CredentialStore::Helper("pass".into()), auth_file.clone()]
+            );
+            assert_eq!(
+                stores("ghcr.io"),
+                [CredentialStore::Helper("desktop".into()), auth_file]
+            );
+        });
+    }
+
+    #[test]
+    fn saves_to_auth_file() {
+        with_auth_files(|dir| {
+            let path = dir.join("auth.json");
+            write_json(
+                &path,
+                &json!({
+                    "auths": { "quay.io": { "auth": BASE64.encode("quay:pass") } },
+                    "other": true,
+                }),
+            );
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+
+            let store = CredentialStore::AuthFile(path.clone());
+            save(&store, "ghcr.io", "user", "secret").unwrap();
+
+            let saved: serde_json::Value =
+                serde_json::from_slice(&fs::read(&path).unwrap()).unwrap();
+            assert_eq!(saved["other"], true);
+            assert_eq!(
+                fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+            assert_eq!(
+                credential("ghcr.io"),
+                Some(("user".to_owned(), "secret".to_owned()))
+            );
+            assert_eq!(
+                credential("quay.io"),
+                Some(("quay".to_owned(), "pass".to_owned()))
+            );
+            assert_eq!(fs::read_dir(dir).unwrap().count(), 1);
+        });
+    }
+
+    #[test]
+    fn saves_new_auth_file() {
+        with_auth_files(|dir| {
+            let path = dir.join("containers/auth.json");
+            save(
+                &CredentialStore::AuthFile(path.clone()),
+                "ghcr.io",
+                "user",
+                "secret",
+            )
+            .unwrap();
+
+            assert_eq!(
+                fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+                0o600
+            );
+        });
+    }
+
+    #[test]
+    fn keeps_symlinked_auth_file() {
+        with_auth_files(|dir| {
+            let target = dir.join("dotfiles/auth.json");
+            write_json(&target, &json!({}));
+            let path = dir.join("auth.json");
+            std::os::unix::fs::symlink(&target, &path).unwrap();
+
+            save(
+                &CredentialStore::AuthFile(path.clone()),
+                "ghcr.io",
+                "user",
+                "secret",
+            )
+            .unwrap();
+
+            assert!(fs::symlink_metadata(&path).unwrap().is_symlink());
+            assert_eq!(
+                credential("ghcr.io"),
+                Some(("user".to_owned(), "secret".to_owned()))
+            );
+        });
+    }
+}
@@ -0,0 +1,62 @@
+//! The images of the helpers that bluebuild runs or mounts,
+//! like the rechunk image or the build scripts.
+//!
+//! A helper image can be pinned, usually by digest, with the env var
+//! `BB_<HELPER>_IMAGE`, like `BB_RECHUNK_IMAGE`, or in the `images`
+//! table of the config files, so that builds don't change when the
+//! upstream tag is moved. The env var takes precedence over the
+//! config files.
+
+use std::{collections::BTreeMap, env, sync::RwLock};
+
+/// The helpers whose images can be pinned.
+pub const HELPER_IMAGES: [&str; 3] = ["build-scripts", "iso-installer", "rechunk"];
+
+static CONFIGURED: RwLock<BTreeMap<String, String>> = RwLock::new(BTreeMap::new());
+
+/// The env var that pins the image of `helper`.
+#[must_use]
+pub fn env_var(helper: &str) -> String {
+    format!("BB_{}_IMAGE", helper.to_uppercase().replace('-', "_"))
+}
+
+/// Sets the images of helpers from the config files.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn set_configured<I>(images: I)
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    *CONFIGURED.write().expect("Should lock CONFIGURED") = images.into_iter().collect();
+}
+
+/// The image that `helper` is pinned to, or
+/// `None` if it uses its default image.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+#[must_use]
+pub fn pinned(helper: &str) -> Option<String> {
+    if !HELPER_IMAGES.contains(&helper) {
+        return None;
+    }
+
+    env::var(env_var(helper))
+        .ok()
+        .filter(|image| !image.is_empty())
+        .or_else(|| {
+            CONFIGURED
+                .read()
+                .expect("Should lock CONFIGURED")
+                .get(helper)
+                .cloned()
+        })
+}
+
+/// The image to use for `helper`, which is the image
+/// it's pinned to or `default` if it isn't pinned.
+#[must_use]
+pub fn helper_image(helper: &str, default: &str) -> String {
+    pinned(helper).unwrap_or_else(|| default.to_owned())
+}
@@ -1,15 +1,18 @@
 pub mod command_output;
 pub mod constants;
 pub mod credentials;
+pub mod helper_images;
 mod macros;
+pub mod os_family;
 pub mod syntax_highlighting;
 #[cfg(feature = "test")]
 pub mod test_utils;
+pub mod tools;
 pub mod traits;
 
 use std::{
-    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
     thread,
     time::Duration,
 };
@@ -28,6 +31,8 @@ use crate::constants::CONTAINER_FILE;
 
 pub use command_output::*;
 
+static RETRY_COUNT: AtomicU64 = AtomicU64::new(0);
+
 /// Checks for the existance of a given command.
 ///
 /// # Errors
@@ -35,13 +40,7 @@ pub use command_output::*;
 pub fn check_command_exists(command: &str) -> Result<()> {
     trace!("check_command_exists({command})");
 
-    trace!("which {command}");
-    if cmd!("which", command)
-        .output()
-        .into_diagnostic()?
-        .status
-        .success()
-    {
+    if which::which(tools::tool_path(command)).is_ok() {
         trace!("Command {command} does exist");
         Ok(())
     } else {
@@ -82,18 +81,74 @@ where
             Err(e) if retries == 0 => return Err(e),
             Err(e) => {
                 retries -= 1;
+                RETRY_COUNT.fetch_add(1, Ordering::Relaxed);
                 warn!("Failed operation, will retry {retries} more time(s). Error:\n{e:?}");
                 thread::sleep(Duration::from_secs(delay_secs));
             }
-        };
+        }
     }
 }
 
+/// The total number of retries performed by [`retry`]
+/// over the lifetime of this process.
+#[must_use]
+pub fn retry_count() -> u64 {
+    RETRY_COUNT.load(Ordering::Relaxed)
+}
+
 #[must_use]
 pub fn home_dir() -> Option<PathBuf> {
     directories::BaseDirs::new().map(|base_dirs| base_dirs.home_dir().to_path_buf())
 }
 
+/// The user's config directory, usually `~/.config`.
+#[must_use]
+pub fn config_dir() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|base_dirs| base_dirs.config_dir().to_path_buf())
+}
+
+/// The directory the CLI caches data in, usually `~/.cache/bluebuild`.
+#[must_use]
+pub fn cache_dir() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|base_dirs| base_dirs.cache_dir().join("bluebuild"))
+}
+
+/// The directory the CLI keeps its lock files in, usually
+/// `$XDG_RUNTIME_DIR/bluebuild`, which only the user can write to.
+/// It's the cache dir on systems without a runtime dir.
+#[must_use]
+pub fn runtime_dir() -> Option<PathBuf> {
+    directories::BaseDirs::new().map(|base_dirs| {
+        base_dirs
+            .runtime_dir()
+            .unwrap_or_else(|| base_dirs.cache_dir())
+            .join("bluebuild")
+    })
+}
+
+/// Recursively copies the contents of `from` into `to`.
+///
+/// # Errors
+/// Will error if a file or directory can't be copied.
+pub fn copy_dir(from: &Path, to: &Path) -> Result<()> {
+    std::fs::create_dir_all(to).into_diagnostic()?;
+
+    for entry in std::fs::read_dir(from).into_diagnostic()? {
+        let entry = entry.into_diagnostic()?;
+        let dest = to.join(entry.file_name());
+
+        if entry.file_type().into_diagnostic()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            std::fs::copy(entry.path(), &dest)
+                .into_diagnostic()
+                .with_context(|| format!("Failed to copy {}", entry.path().display()))?;
+        }
+    }
+
+    Ok(())
+}
+
 /// Generates a 1-1 related Containerfile to a recipe.
 /// The file is in the format of `Containerfile.{path_hash}`.
 ///
@@ -104,7 +159,7 @@ pub fn generate_containerfile_path<T: AsRef<Path>>(path: T) -> Result<PathBuf> {
     let mut buf = [0u8; HASH_SIZE];
 
     let mut hasher = Blake2bVar::new(HASH_SIZE).into_diagnostic()?;
-    hasher.update(path.as_ref().as_os_str().as_bytes());
+    hasher.update(path.as_ref().as_os_str().as_encoded_bytes());
     hasher.finalize_variable(&mut buf).into_diagnostic()?;
 
     Ok(PathBuf::from(format!(
@@ -118,6 +173,25 @@ pub fn get_tag_timestamp() -> String {
     Local::now().format("%Y%m%d").to_string()
 }
 
+/// Escapes text to be used in the contents
+/// or attributes of an XML document.
+///
+/// Control characters like the escape of terminal colors
+/// can't be in an XML document at all, so they're removed.
+#[must_use]
+pub fn escape_xml(text: &str) -> String {
+    text.chars()
+        .filter(|&c| {
+            matches!(c, '\t' | '\n' | '\r')
+                || !(c.is_control() || c == '\u{FFFE}' || c == '\u{FFFF}')
+        })
+        .collect::<String>()
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
 /// Get's the env var wrapping it with a miette error
 ///
 /// # Errors
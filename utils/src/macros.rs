@@ -1,5 +1,8 @@
 /// Creates or modifies a `std::process::Command` adding args.
 ///
+/// Tools like `podman` are run from the path that
+/// they're set to, see [`tools`](crate::tools).
+///
 /// # Examples
 /// ```
 /// use blue_build_utils::cmd;
@@ -12,7 +15,7 @@
 #[macro_export]
 macro_rules! cmd {
     ($command:expr) => {
-        ::std::process::Command::new($command)
+        $crate::tools::command($command)
     };
     ($command:ident, $($tail:tt)*) => {
         $crate::cmd!(@ $command, $($tail)*)
@@ -0,0 +1,93 @@
+use serde::{Deserialize, Serialize};
+
+/// The family of the OS that an image is based on, which
+/// decides how packages are installed and how each
+/// step of the build is cleaned up.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OsFamily {
+    /// Fedora, CentOS, and the other rpm based images.
+    #[default]
+    Fedora,
+
+    /// Debian, Ubuntu, and the other apt based images.
+    Debian,
+}
+
+impl OsFamily {
+    /// Picks the family from the `ID` and `ID_LIKE` of an `os-release`
+    /// file. Anything that isn't like Debian is treated as Fedora.
+    #[must_use]
+    pub fn from_os_release(os_release: &str) -> Self {
+        let is_debian = os_release
+            .lines()
+            .filter_map(|line| {
+                line.trim()
+                    .strip_prefix("ID=")
+                    .or_else(|| line.trim().strip_prefix("ID_LIKE="))
+            })
+            .flat_map(|value| {
+                value
+                    .trim_matches(|c| c == '"' || c == '\'')
+                    .split_whitespace()
+            })
+            .any(|id| matches!(id, "debian" | "ubuntu"));
+
+        if is_debian {
+            Self::Debian
+        } else {
+            Self::Fedora
+        }
+    }
+
+    /// The command that cleans up after a `RUN` in the final image.
+    #[must_use]
+    pub const fn commit_command(self) -> &'static str {
+        match self {
+            Self::Fedora => "ostree container commit",
+            Self::Debian => "rm -rf /var/lib/apt/lists/* /var/log/apt/* /var/log/dpkg.log",
+        }
+    }
+
+    /// The command that installs the packages given after it.
+    #[must_use]
+    pub const fn install_command(self) -> &'static str {
+        match self {
+            Self::Fedora => "dnf -y install",
+            Self::Debian => {
+                "apt-get update && DEBIAN_FRONTEND=noninteractive apt-get install -y --no-install-recommends"
+            }
+        }
+    }
+
+    /// The command that removes the packages given after it.
+    #[must_use]
+    pub const fn remove_command(self) -> &'static str {
+        match self {
+            Self::Fedora => "dnf -y remove",
+            Self::Debian => "DEBIAN_FRONTEND=noninteractive apt-get purge -y",
+        }
+    }
+
+    /// The directories of the package manager that are cached between
+    /// builds, with the prefix of the ID of their cache mount.
+    #[must_use]
+    pub const fn package_caches(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            Self::Fedora => &[
+                ("/var/cache/rpm-ostree", "rpm-ostree-cache"),
+                ("/var/cache/libdnf5", "dnf-cache"),
+            ],
+            Self::Debian => &[("/var/cache/apt", "apt-cache")],
+        }
+    }
+}
+
+impl std::fmt::Display for OsFamily {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Fedora => "fedora",
+            Self::Debian => "debian",
+        })
+    }
+}
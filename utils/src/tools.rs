@@ -0,0 +1,144 @@
+//! The paths of the external tools that are run, like `podman` or `cosign`.
+//!
+//! A tool is found on `PATH` unless its path is set with the env var
+//! `BB_<TOOL>_BIN`, like `BB_COSIGN_BIN` or `BB_RPM_OSTREE_BIN`, or
+//! in the `tools` table of the config files. The env var takes
+//! precedence over the config files.
+
+use std::{
+    collections::BTreeMap,
+    env,
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+    process::Command,
+    sync::RwLock,
+};
+
+/// The tools whose paths can be set.
+pub const TOOLS: [&str; 7] = [
+    "bootc",
+    "buildah",
+    "cosign",
+    "docker",
+    "podman",
+    "rpm-ostree",
+    "skopeo",
+];
+
+static CONFIGURED: RwLock<BTreeMap<String, PathBuf>> = RwLock::new(BTreeMap::new());
+
+/// The env vars that are set for the tools, which are kept
+/// here instead of in the environment of this process since
+/// other threads can be reading it.
+static ENV: RwLock<BTreeMap<&'static str, String>> = RwLock::new(BTreeMap::new());
+
+/// The env var that sets the path of `tool`.
+#[must_use]
+pub fn env_var(tool: &str) -> String {
+    format!("BB_{}_BIN", tool.to_uppercase().replace('-', "_"))
+}
+
+/// Sets the paths of tools from the config files.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn set_configured<I>(paths: I)
+where
+    I: IntoIterator<Item = (String, PathBuf)>,
+{
+    *CONFIGURED.write().expect("Should lock CONFIGURED") = paths.into_iter().collect();
+}
+
+/// The path that `tool` is set to, or `None`
+/// if it's found on `PATH`.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+#[must_use]
+pub fn overridden(tool: &str) -> Option<PathBuf> {
+    if !TOOLS.contains(&tool) {
+        return None;
+    }
+
+    env::var_os(env_var(tool))
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            CONFIGURED
+                .read()
+                .expect("Should lock CONFIGURED")
+                .get(tool)
+                .cloned()
+        })
+}
+
+/// Every tool that has its path set.
+#[must_use]
+pub fn overrides() -> Vec<(&'static str, PathBuf)> {
+    TOOLS
+        .into_iter()
+        .filter_map(|tool| Some((tool, overridden(tool)?)))
+        .collect()
+}
+
+/// Sets an env var for the tools that are run, unless it's
+/// already set in the environment. Returns whether it was set.
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+pub fn set_env(key: &'static str, value: impl Into<String>) -> bool {
+    if env::var_os(key).is_some() {
+        return false;
+    }
+    ENV.write()
+        .expect("Should lock ENV")
+        .insert(key, value.into());
+    true
+}
+
+/// The value of an env var for the tools that are run, from
+/// the environment or set with [`set_env`].
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+#[must_use]
+pub fn env_value(key: &str) -> Option<OsString> {
+    env::var_os(key).or_else(|| {
+        ENV.read()
+            .expect("Should lock ENV")
+            .get(key)
+            .map(Into::into)
+    })
+}
+
+/// The env vars that were set with [`set_env`].
+///
+/// # Panics
+/// Will panic if the lock is poisoned.
+#[must_use]
+pub fn envs() -> Vec<(&'static str, String)> {
+    ENV.read()
+        .expect("Should lock ENV")
+        .iter()
+        .map(|(key, value)| (*key, value.clone()))
+        .collect()
+}
+
+/// A command that runs `program` at its [`tool_path`]
+/// with the env vars that were set with [`set_env`].
+pub fn command<S: AsRef<OsStr>>(program: S) -> Command {
+    let mut command = Command::new(tool_path(program));
+    command.envs(envs());
+    command
+}
+
+/// The program to run for `program`, which is the
+/// path that it's set to if it's one of the [`TOOLS`].
+pub fn tool_path<S: AsRef<OsStr>>(program: S) -> OsString {
+    let program = program.as_ref();
+
+    program
+        .to_str()
+        .and_then(overridden)
+        .map_or_else(|| program.to_os_string(), PathBuf::into_os_string)
+}